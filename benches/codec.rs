@@ -0,0 +1,210 @@
+//! Micro-benchmarks for the varint and instruction codec
+//!
+//! Run: cargo bench --bench codec --features unstable-format
+//! Compare: cargo bench --bench codec --features unstable-format -- --save-baseline main
+//!          cargo bench --bench codec --features unstable-format -- --baseline main
+
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use gdelta::format::{
+    BufferStream, DeltaUnit, read_delta_unit, read_varint, write_delta_unit, write_varint,
+};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::hint::black_box;
+
+/// Number of values encoded per benchmark iteration.
+const VALUES_PER_ITER: usize = 4096;
+
+// ============================================================================
+// Value Distributions
+// ============================================================================
+
+/// Values that fit the single-byte fast path (< 128).
+fn tiny_values() -> Vec<u64> {
+    let mut rng = StdRng::seed_from_u64(42);
+    (0..VALUES_PER_ITER)
+        .map(|_| rng.random_range(0..128))
+        .collect()
+}
+
+/// Values that fit the two-byte fast path (128..16384).
+fn small_values() -> Vec<u64> {
+    let mut rng = StdRng::seed_from_u64(42);
+    (0..VALUES_PER_ITER)
+        .map(|_| rng.random_range(128..16384))
+        .collect()
+}
+
+/// Offset-like values for bases up to 4 GiB.
+fn offset_values() -> Vec<u64> {
+    let mut rng = StdRng::seed_from_u64(42);
+    (0..VALUES_PER_ITER)
+        .map(|_| u64::from(rng.random::<u32>()))
+        .collect()
+}
+
+/// Full-width values that take the longest general path.
+fn wide_values() -> Vec<u64> {
+    let mut rng = StdRng::seed_from_u64(42);
+    (0..VALUES_PER_ITER).map(|_| rng.random()).collect()
+}
+
+/// A mix roughly resembling real deltas: mostly short lengths, some offsets.
+fn mixed_values() -> Vec<u64> {
+    let mut rng = StdRng::seed_from_u64(42);
+    (0..VALUES_PER_ITER)
+        .map(|_| match rng.random_range(0..10) {
+            0..=5 => rng.random_range(0..128),
+            6..=8 => rng.random_range(128..16384),
+            _ => u64::from(rng.random::<u32>()),
+        })
+        .collect()
+}
+
+/// Instruction stream with alternating literals and copies.
+fn delta_units() -> Vec<DeltaUnit> {
+    let mut rng = StdRng::seed_from_u64(42);
+    (0..VALUES_PER_ITER)
+        .map(|i| {
+            if i % 2 == 0 {
+                DeltaUnit::literal(rng.random_range(1..64))
+            } else {
+                DeltaUnit::copy(rng.random_range(0..1 << 20), rng.random_range(8..4096))
+            }
+        })
+        .collect()
+}
+
+fn distributions() -> Vec<(&'static str, Vec<u64>)> {
+    vec![
+        ("tiny", tiny_values()),
+        ("small", small_values()),
+        ("offset", offset_values()),
+        ("wide", wide_values()),
+        ("mixed", mixed_values()),
+    ]
+}
+
+fn encode_values(values: &[u64]) -> BufferStream {
+    let mut buffer = BufferStream::with_capacity(values.len() * 10);
+    for &value in values {
+        write_varint(&mut buffer, value);
+    }
+    buffer
+}
+
+// ============================================================================
+// Benchmarks
+// ============================================================================
+
+fn bench_write_varint(c: &mut Criterion) {
+    let mut group = c.benchmark_group("varint_write");
+
+    for (name, values) in distributions() {
+        group.throughput(Throughput::Elements(values.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), &values, |b, values| {
+            b.iter(|| {
+                let mut buffer = BufferStream::with_capacity(values.len() * 10);
+                for &value in values {
+                    write_varint(&mut buffer, black_box(value));
+                }
+                buffer
+            });
+        });
+    }
+
+    group.finish();
+}
+
+fn bench_read_varint(c: &mut Criterion) {
+    let mut group = c.benchmark_group("varint_read");
+
+    for (name, values) in distributions() {
+        let encoded = encode_values(&values).into_vec();
+
+        group.throughput(Throughput::Elements(values.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), &encoded, |b, encoded| {
+            b.iter(|| {
+                let mut buffer = BufferStream::from_slice(black_box(encoded));
+                let mut sum = 0u64;
+                for _ in 0..VALUES_PER_ITER {
+                    sum = sum.wrapping_add(read_varint(&mut buffer).unwrap());
+                }
+                sum
+            });
+        });
+    }
+
+    group.finish();
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn bench_delta_unit(c: &mut Criterion) {
+    let mut group = c.benchmark_group("delta_unit");
+    let units = delta_units();
+
+    let mut encoded = BufferStream::with_capacity(units.len() * 12);
+    for unit in &units {
+        write_delta_unit(&mut encoded, unit);
+    }
+    let encoded = encoded.into_vec();
+
+    println!(
+        "\ndelta_unit: {} instructions encode to {} bytes ({:.2} bytes/instruction)\n",
+        units.len(),
+        encoded.len(),
+        encoded.len() as f64 / units.len() as f64
+    );
+
+    group.throughput(Throughput::Elements(units.len() as u64));
+    group.bench_function("write", |b| {
+        b.iter(|| {
+            let mut buffer = BufferStream::with_capacity(units.len() * 12);
+            for unit in &units {
+                write_delta_unit(&mut buffer, black_box(unit));
+            }
+            buffer
+        });
+    });
+    group.bench_function("read", |b| {
+        b.iter(|| {
+            let mut buffer = BufferStream::from_slice(black_box(&encoded));
+            let mut total = 0u64;
+            for _ in 0..units.len() {
+                total = total.wrapping_add(read_delta_unit(&mut buffer).unwrap().length);
+            }
+            total
+        });
+    });
+
+    group.finish();
+}
+
+/// Prints the encoded size of each distribution, for comparing codec changes.
+#[allow(clippy::cast_precision_loss)]
+fn bench_encoded_size(c: &mut Criterion) {
+    println!("\n=== Varint Encoded Sizes ===\n");
+
+    for (name, values) in distributions() {
+        let encoded = encode_values(&values);
+        println!(
+            "{name:10} | Values: {:>5} | Encoded: {:>6} B | {:.2} bytes/value",
+            values.len(),
+            encoded.len(),
+            encoded.len() as f64 / values.len() as f64
+        );
+    }
+    println!();
+
+    // Run a minimal benchmark just to keep criterion happy
+    c.bench_function("encoded_size_check", |b| b.iter(|| {}));
+}
+
+criterion_group!(
+    benches,
+    bench_encoded_size,
+    bench_write_varint,
+    bench_read_varint,
+    bench_delta_unit
+);
+criterion_main!(benches);
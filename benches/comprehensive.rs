@@ -7,13 +7,16 @@
 //! - Verify reconstruction correctness
 //! - WAL-based metrics collection
 //! - generate Markdown and JSON reports
+//! - Optional Parquet report for analysis in pandas, polars or duckdb
 //! - Graceful Ctrl+C handling with partial results
 //!
 //! Run: cargo bench --bench comprehensive
 //! Quick mode: `BENCH_MODE=quick` cargo bench --bench comprehensive
 //! Full mode: `BENCH_MODE=full` cargo bench --bench comprehensive
 //! Custom: `BENCH_ALGOS=gdelta,xpatch` `BENCH_FORMATS=json,csv` cargo bench --bench comprehensive
+//! Secondary codecs: `BENCH_CODECS=zstd-3,lz4` cargo bench --bench comprehensive
 //! View report: cat `target/benchmark_report.md`
+//! Parquet report: cargo bench --bench comprehensive --features bench-parquet
 
 use criterion::{Criterion, Throughput, criterion_group, criterion_main};
 use fake::Fake;
@@ -53,6 +56,11 @@ fn get_report_json(timestamp: &str) -> String {
     format!("target/benchmark_report_{timestamp}.json")
 }
 
+#[cfg(feature = "bench-parquet")]
+fn get_report_parquet(timestamp: &str) -> String {
+    format!("target/benchmark_report_{timestamp}.parquet")
+}
+
 // Global flag for graceful shutdown
 static SHUTDOWN_FLAG: AtomicBool = AtomicBool::new(false);
 
@@ -80,6 +88,12 @@ trait DeltaAlgorithm: Send + Sync {
     fn name(&self) -> &str;
     fn encode(&self, new: &[u8], base: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>>;
     fn decode(&self, delta: &[u8], base: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>>;
+
+    /// Whether deltas are left uncompressed, so secondary codecs are
+    /// measured on them.
+    fn raw_deltas(&self) -> bool {
+        false
+    }
 }
 
 struct GdeltaAlgorithm;
@@ -96,67 +110,9 @@ impl DeltaAlgorithm for GdeltaAlgorithm {
     fn decode(&self, delta: &[u8], base: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
         decode(delta, base).map_err(std::convert::Into::into)
     }
-}
-
-// Gdelta with Zstd compression
-struct GdeltaZstdAlgorithm;
-
-impl DeltaAlgorithm for GdeltaZstdAlgorithm {
-    fn name(&self) -> &'static str {
-        "gdelta_zstd"
-    }
 
-    fn encode(&self, new: &[u8], base: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-        let delta = encode(new, base)?;
-        let compressed = zstd::encode_all(&delta[..], 3)?; // Level 3 for speed
-        Ok(compressed)
-    }
-
-    fn decode(&self, delta: &[u8], base: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-        let decompressed = zstd::decode_all(delta)?;
-        decode(&decompressed, base).map_err(std::convert::Into::into)
-    }
-}
-
-// Gdelta with LZ4 compression
-struct GdeltaLz4Algorithm;
-
-impl DeltaAlgorithm for GdeltaLz4Algorithm {
-    fn name(&self) -> &'static str {
-        "gdelta_lz4"
-    }
-
-    #[allow(clippy::cast_possible_truncation)]
-    fn encode(&self, new: &[u8], base: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-        let delta = encode(new, base)?;
-
-        // Compress with LZ4
-        let compressed = lz4::block::compress(&delta, None, false)?;
-
-        // Prepend the original size (needed for decompression)
-        let mut result = Vec::new();
-        result.extend_from_slice(&(delta.len() as u32).to_le_bytes());
-        result.extend_from_slice(&compressed);
-
-        Ok(result)
-    }
-
-    #[allow(clippy::cast_possible_truncation)]
-    #[allow(clippy::cast_possible_wrap)]
-    fn decode(&self, delta: &[u8], base: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-        // Extract the original size
-        if delta.len() < 4 {
-            return Err("Invalid LZ4 delta: too short".into());
-        }
-
-        let original_size = u32::from_le_bytes([delta[0], delta[1], delta[2], delta[3]]) as usize;
-        let compressed_data = &delta[4..];
-
-        // Decompress with size hint
-        let decompressed = lz4::block::decompress(compressed_data, Some(original_size as i32))?;
-
-        // Apply delta
-        decode(&decompressed, base).map_err(std::convert::Into::into)
+    fn raw_deltas(&self) -> bool {
+        true
     }
 }
 
@@ -196,6 +152,10 @@ impl DeltaAlgorithm for VCDiffAlgorithm {
     fn decode(&self, delta: &[u8], base: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
         Ok(vcdiff::decode(base, delta))
     }
+
+    fn raw_deltas(&self) -> bool {
+        true
+    }
 }
 
 // qbsdiff - industry standard
@@ -254,6 +214,63 @@ impl DeltaAlgorithm for ZstdDictAlgorithm {
     }
 }
 
+// ============================================================================
+// Secondary Compression
+// ============================================================================
+
+/// A codec applied to finished deltas, measured separately from the delta
+/// algorithm so the raw and compressed sizes and the codec's own time can
+/// be compared per level.
+#[derive(Clone, Copy, Debug)]
+enum SecondaryCodec {
+    Zstd(i32),
+    /// LZ4 block format; level 0 is the default fast mode, higher levels
+    /// use the high compression mode.
+    Lz4(i32),
+}
+
+impl SecondaryCodec {
+    fn name(self) -> String {
+        match self {
+            Self::Zstd(level) => format!("zstd-{level}"),
+            Self::Lz4(0) => "lz4".to_string(),
+            Self::Lz4(level) => format!("lz4hc-{level}"),
+        }
+    }
+
+    fn codec(self) -> &'static str {
+        match self {
+            Self::Zstd(_) => "zstd",
+            Self::Lz4(_) => "lz4",
+        }
+    }
+
+    fn level(self) -> i32 {
+        match self {
+            Self::Zstd(level) | Self::Lz4(level) => level,
+        }
+    }
+
+    fn compress(self, data: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        match self {
+            Self::Zstd(level) => Ok(zstd::encode_all(data, level)?),
+            Self::Lz4(0) => Ok(lz4::block::compress(data, None, true)?),
+            Self::Lz4(level) => Ok(lz4::block::compress(
+                data,
+                Some(lz4::block::CompressionMode::HIGHCOMPRESSION(level)),
+                true,
+            )?),
+        }
+    }
+
+    fn decompress(self, data: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        match self {
+            Self::Zstd(_) => Ok(zstd::decode_all(data)?),
+            Self::Lz4(_) => Ok(lz4::block::decompress(data, None)?),
+        }
+    }
+}
+
 // ============================================================================
 // Realistic Data generators
 // ============================================================================
@@ -765,6 +782,19 @@ struct BenchmarkMetric {
     decode_time_ns: u128,
     verification_passed: bool,
     cache_level: String,
+    /// Secondary codecs applied to the delta, if the algorithm leaves its
+    /// deltas uncompressed; `delta_size` is the raw size they start from.
+    #[serde(default)]
+    secondary: Vec<SecondaryMetric>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SecondaryMetric {
+    codec: String,
+    level: i32,
+    compressed_size: usize,
+    compress_time_ns: u128,
+    decompress_time_ns: u128,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -860,6 +890,7 @@ fn collect_hardware_info() -> HardwareInfo {
 // ============================================================================
 
 #[allow(clippy::cast_precision_loss)]
+#[allow(clippy::too_many_arguments)]
 fn run_benchmark(
     algo: &dyn DeltaAlgorithm,
     format: DataFormat,
@@ -868,6 +899,7 @@ fn run_benchmark(
     cache_level: &str,
     base: &[u8],
     new: &[u8],
+    codecs: &[SecondaryCodec],
 ) -> Option<BenchmarkMetric> {
     // Encode with timeout and error handling
     let encode_start = Instant::now();
@@ -917,6 +949,15 @@ fn run_benchmark(
         );
     }
 
+    let secondary = if algo.raw_deltas() && verification_passed {
+        codecs
+            .iter()
+            .filter_map(|codec| measure_secondary(*codec, &delta))
+            .collect()
+    } else {
+        Vec::new()
+    };
+
     Some(BenchmarkMetric {
         timestamp: SystemTime::now()
             .duration_since(UNIX_EPOCH)
@@ -934,6 +975,37 @@ fn run_benchmark(
         decode_time_ns: decode_time.as_nanos(),
         verification_passed,
         cache_level: cache_level.to_string(),
+        secondary,
+    })
+}
+
+/// Compresses `delta` with `codec` and times both directions.
+fn measure_secondary(codec: SecondaryCodec, delta: &[u8]) -> Option<SecondaryMetric> {
+    let compress_start = Instant::now();
+    let compressed = codec
+        .compress(delta)
+        .map_err(|e| eprintln!("\r⚠️  {} compression failed: {}", codec.name(), e))
+        .ok()?;
+    let compress_time = compress_start.elapsed();
+
+    let decompress_start = Instant::now();
+    let decompressed = codec
+        .decompress(&compressed)
+        .map_err(|e| eprintln!("\r⚠️  {} decompression failed: {}", codec.name(), e))
+        .ok()?;
+    let decompress_time = decompress_start.elapsed();
+
+    if decompressed != delta {
+        eprintln!("\r⚠️  {} round trip changed the delta", codec.name());
+        return None;
+    }
+
+    Some(SecondaryMetric {
+        codec: codec.codec().to_string(),
+        level: codec.level(),
+        compressed_size: compressed.len(),
+        compress_time_ns: compress_time.as_nanos(),
+        decompress_time_ns: decompress_time.as_nanos(),
     })
 }
 
@@ -988,16 +1060,17 @@ fn generate_markdown_report(
     report.push_str("3. [Overall Rankings](#-overall-rankings)\n");
     report.push_str("4. [Performance Scaling by Size](#-performance-scaling-by-size)\n");
     report.push_str("5. [Actual Delta Sizes](#-actual-delta-sizes)\n");
-    report.push_str("6. [Compression Consistency](#-compression-consistency)\n");
-    report.push_str("7. [Performance by Data Format](#-performance-by-data-format)\n");
-    report.push_str("8. [Performance by Change Pattern](#-performance-by-change-pattern)\n");
-    report.push_str("9. [Algorithm Deep Dive](#-algorithm-deep-dive)\n");
-    report.push_str("10. [Head-to-Head Comparison](#️-head-to-head-comparison)\n");
-    report.push_str("11. [Speed vs Compression Trade-offs](#️-speed-vs-compression-trade-offs)\n");
-    report.push_str("12. [Compression ROI Analysis](#-compression-roi-analysis)\n");
-    report.push_str("13. [Quick Decision Matrix](#-quick-decision-matrix)\n");
-    report.push_str("14. [Pattern-Specific Recommendations](#-pattern-specific-recommendations)\n");
-    report.push_str("15. [What NOT to Use](#-what-not-to-use)\n\n");
+    report.push_str("6. [Secondary Compression](#️-secondary-compression)\n");
+    report.push_str("7. [Compression Consistency](#-compression-consistency)\n");
+    report.push_str("8. [Performance by Data Format](#-performance-by-data-format)\n");
+    report.push_str("9. [Performance by Change Pattern](#-performance-by-change-pattern)\n");
+    report.push_str("10. [Algorithm Deep Dive](#-algorithm-deep-dive)\n");
+    report.push_str("11. [Head-to-Head Comparison](#️-head-to-head-comparison)\n");
+    report.push_str("12. [Speed vs Compression Trade-offs](#️-speed-vs-compression-trade-offs)\n");
+    report.push_str("13. [Compression ROI Analysis](#-compression-roi-analysis)\n");
+    report.push_str("14. [Quick Decision Matrix](#-quick-decision-matrix)\n");
+    report.push_str("15. [Pattern-Specific Recommendations](#-pattern-specific-recommendations)\n");
+    report.push_str("16. [What NOT to Use](#-what-not-to-use)\n\n");
 
     // Executive Summary
     report.push_str("## 📊 Executive Summary\n\n");
@@ -1409,6 +1482,10 @@ fn generate_markdown_report(
         }
     }
 
+    // SECONDARY COMPRESSION
+    report.push_str("## 🗜️ Secondary Compression\n\n");
+    report.push_str(&secondary_compression_table(metrics));
+
     // CONSISTENCY SCORE
     report.push_str("## 🎯 Compression Consistency\n\n");
     report.push_str("How predictable is each algorithm's compression ratio?\n\n");
@@ -2075,6 +2152,82 @@ fn generate_markdown_report(
     Ok(())
 }
 
+/// Sums of the secondary codec results for one algorithm, codec and level.
+#[derive(Default)]
+struct SecondaryTotals {
+    runs: usize,
+    raw_size: usize,
+    compressed_size: usize,
+    compress_time_ns: u128,
+    decompress_time_ns: u128,
+}
+
+/// Averages the secondary codec results per algorithm, codec and level.
+#[allow(clippy::cast_precision_loss)]
+fn secondary_compression_table(metrics: &[BenchmarkMetric]) -> String {
+    let mut totals: Vec<((&str, &str, i32), SecondaryTotals)> = Vec::new();
+    for metric in metrics.iter().filter(|m| m.verification_passed) {
+        for secondary in &metric.secondary {
+            let key = (
+                metric.algorithm.as_str(),
+                secondary.codec.as_str(),
+                secondary.level,
+            );
+            let index = totals
+                .iter()
+                .position(|(k, _)| *k == key)
+                .unwrap_or_else(|| {
+                    totals.push((key, SecondaryTotals::default()));
+                    totals.len() - 1
+                });
+            let total = &mut totals[index].1;
+            total.runs += 1;
+            total.raw_size += metric.delta_size;
+            total.compressed_size += secondary.compressed_size;
+            total.compress_time_ns += secondary.compress_time_ns;
+            total.decompress_time_ns += secondary.decompress_time_ns;
+        }
+    }
+
+    if totals.is_empty() {
+        return "No secondary compression was measured.\n\n".to_string();
+    }
+
+    let mut table = String::from(
+        "Codecs applied to uncompressed deltas, timed on their own (averages over all tests):\n\n",
+    );
+    table.push_str(
+        "| Algorithm | Codec | Level | Raw Delta | Compressed | Compressed / Raw | Compress Time | Decompress Time |\n",
+    );
+    table.push_str(
+        "|-----------|-------|-------|-----------|------------|------------------|---------------|-----------------|\n",
+    );
+    for ((algo, codec, level), total) in totals {
+        let runs = total.runs;
+        let ratio = if total.raw_size == 0 {
+            1.0
+        } else {
+            total.compressed_size as f64 / total.raw_size as f64
+        };
+        table.push_str(
+            format!(
+                "| {} | {} | {} | {} | {} | {:.1}% | {:.3}ms | {:.3}ms |\n",
+                algo,
+                codec,
+                level,
+                format_bytes(total.raw_size / runs),
+                format_bytes(total.compressed_size / runs),
+                ratio * 100.0,
+                total.compress_time_ns as f64 / runs as f64 / 1_000_000.0,
+                total.decompress_time_ns as f64 / runs as f64 / 1_000_000.0
+            )
+            .as_str(),
+        );
+    }
+    table.push('\n');
+    table
+}
+
 // Helper function for formatting bytes
 #[allow(clippy::cast_precision_loss)]
 fn format_bytes(bytes: usize) -> String {
@@ -2110,6 +2263,137 @@ fn generate_json_report(
     Ok(())
 }
 
+/// Writes one row per metric, with the hardware info and the early
+/// termination flag stored as key-value metadata in the schema.
+///
+/// Load with e.g. `SELECT * FROM 'target/benchmark_report_*.parquet'` in
+/// duckdb or `pandas.read_parquet`.
+#[cfg(feature = "bench-parquet")]
+fn generate_parquet_report(
+    metrics: &[BenchmarkMetric],
+    hardware: &HardwareInfo,
+    early_termination: bool,
+    output_path: &str,
+) -> std::io::Result<()> {
+    use arrow_array::{
+        ArrayRef, BooleanArray, Float64Array, RecordBatch, StringArray, UInt64Array,
+    };
+    use arrow_schema::{DataType, Field, Schema};
+    use parquet::arrow::ArrowWriter;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    fn strings(metrics: &[BenchmarkMetric], f: impl Fn(&BenchmarkMetric) -> &str) -> ArrayRef {
+        Arc::new(StringArray::from_iter_values(metrics.iter().map(f)))
+    }
+
+    fn numbers(metrics: &[BenchmarkMetric], f: impl Fn(&BenchmarkMetric) -> u64) -> ArrayRef {
+        Arc::new(UInt64Array::from_iter_values(metrics.iter().map(f)))
+    }
+
+    let saturate = |ns: u128| u64::try_from(ns).unwrap_or(u64::MAX);
+    let columns: Vec<(&str, DataType, ArrayRef)> = vec![
+        (
+            "timestamp",
+            DataType::UInt64,
+            numbers(metrics, |m| m.timestamp),
+        ),
+        (
+            "algorithm",
+            DataType::Utf8,
+            strings(metrics, |m| &m.algorithm),
+        ),
+        (
+            "data_format",
+            DataType::Utf8,
+            strings(metrics, |m| &m.data_format),
+        ),
+        (
+            "change_pattern",
+            DataType::Utf8,
+            strings(metrics, |m| &m.change_pattern),
+        ),
+        (
+            "data_source",
+            DataType::Utf8,
+            strings(metrics, |m| &m.data_source),
+        ),
+        (
+            "cache_level",
+            DataType::Utf8,
+            strings(metrics, |m| &m.cache_level),
+        ),
+        (
+            "base_size",
+            DataType::UInt64,
+            numbers(metrics, |m| m.base_size as u64),
+        ),
+        (
+            "new_size",
+            DataType::UInt64,
+            numbers(metrics, |m| m.new_size as u64),
+        ),
+        (
+            "delta_size",
+            DataType::UInt64,
+            numbers(metrics, |m| m.delta_size as u64),
+        ),
+        (
+            "compression_ratio",
+            DataType::Float64,
+            Arc::new(Float64Array::from_iter_values(
+                metrics.iter().map(|m| m.compression_ratio),
+            )),
+        ),
+        (
+            "encode_time_ns",
+            DataType::UInt64,
+            numbers(metrics, |m| saturate(m.encode_time_ns)),
+        ),
+        (
+            "decode_time_ns",
+            DataType::UInt64,
+            numbers(metrics, |m| saturate(m.decode_time_ns)),
+        ),
+        (
+            "verification_passed",
+            DataType::Boolean,
+            Arc::new(BooleanArray::from_iter(
+                metrics.iter().map(|m| Some(m.verification_passed)),
+            )),
+        ),
+    ];
+
+    let metadata = HashMap::from([
+        ("cpu_brand".to_string(), hardware.cpu_brand.clone()),
+        ("cpu_cores".to_string(), hardware.cpu_cores.to_string()),
+        (
+            "total_memory_mb".to_string(),
+            hardware.total_memory_mb.to_string(),
+        ),
+        ("os".to_string(), hardware.os.clone()),
+        (
+            "early_termination".to_string(),
+            early_termination.to_string(),
+        ),
+    ]);
+    let fields: Vec<Field> = columns
+        .iter()
+        .map(|(name, data_type, _)| Field::new(*name, data_type.clone(), false))
+        .collect();
+    let schema = Arc::new(Schema::new_with_metadata(fields, metadata));
+    let arrays = columns.into_iter().map(|(_, _, array)| array).collect();
+    let batch = RecordBatch::try_new(Arc::clone(&schema), arrays).map_err(std::io::Error::other)?;
+
+    let file = File::create(output_path)?;
+    let mut writer = ArrowWriter::try_new(file, schema, None).map_err(std::io::Error::other)?;
+    writer.write(&batch).map_err(std::io::Error::other)?;
+    writer.close().map_err(std::io::Error::other)?;
+    println!("✅ Parquet report generated: {output_path}");
+
+    Ok(())
+}
+
 // ============================================================================
 // Benchmark Configuration
 // ============================================================================
@@ -2125,6 +2409,7 @@ struct BenchmarkConfig {
     formats: Option<Vec<String>>,
     change_patterns: Option<Vec<String>>,
     sizes: Option<Vec<String>>,
+    codecs: Option<Vec<String>>,
 }
 
 impl BenchmarkConfig {
@@ -2137,6 +2422,7 @@ impl BenchmarkConfig {
             formats: None,
             change_patterns: None,
             sizes: None,
+            codecs: None,
         }
     }
 
@@ -2149,6 +2435,7 @@ impl BenchmarkConfig {
             formats: None,
             change_patterns: None,
             sizes: None,
+            codecs: None,
         }
     }
 
@@ -2178,6 +2465,10 @@ impl BenchmarkConfig {
             config.sizes = Some(sizes.split(',').map(|s| s.trim().to_string()).collect());
         }
 
+        if let Ok(codecs) = std::env::var("BENCH_CODECS") {
+            config.codecs = Some(codecs.split(',').map(|s| s.trim().to_string()).collect());
+        }
+
         config
     }
 
@@ -2205,6 +2496,12 @@ impl BenchmarkConfig {
             .is_none_or(|list| list.contains(&name.to_string()))
     }
 
+    fn should_run_codec(&self, name: &str) -> bool {
+        self.codecs
+            .as_ref()
+            .is_none_or(|list| list.contains(&name.to_string()))
+    }
+
     fn print_info(&self) {
         println!("📋 Benchmark Configuration:");
         println!(
@@ -2235,6 +2532,12 @@ impl BenchmarkConfig {
         } else {
             println!("   Sizes: all");
         }
+
+        if let Some(codecs) = &self.codecs {
+            println!("   Secondary codecs: {}", codecs.join(", "));
+        } else {
+            println!("   Secondary codecs: all");
+        }
         println!();
     }
 }
@@ -2251,6 +2554,8 @@ fn run_benchmarks_with_config(c: &mut Criterion, config: &BenchmarkConfig) {
     let wal_file = get_wal_file(timestamp.as_str());
     let report_md = get_report_md(timestamp.as_str());
     let report_json = get_report_json(timestamp.as_str());
+    #[cfg(feature = "bench-parquet")]
+    let report_parquet = get_report_parquet(timestamp.as_str());
 
     println!("📁 Results will be saved with timestamp: {timestamp}");
 
@@ -2263,8 +2568,6 @@ fn run_benchmarks_with_config(c: &mut Criterion, config: &BenchmarkConfig) {
 
     let all_algos: Vec<Box<dyn DeltaAlgorithm>> = vec![
         Box::new(GdeltaAlgorithm),
-        Box::new(GdeltaZstdAlgorithm),
-        Box::new(GdeltaLz4Algorithm),
         Box::new(XpatchAlgorithm),
         Box::new(VCDiffAlgorithm),
         Box::new(QbsdiffAlgorithm),
@@ -2305,6 +2608,15 @@ fn run_benchmarks_with_config(c: &mut Criterion, config: &BenchmarkConfig) {
         ChangePattern::LineChanges { pct: 0.1 },
     ];
 
+    let all_codecs = vec![
+        SecondaryCodec::Zstd(1),
+        SecondaryCodec::Zstd(3),
+        SecondaryCodec::Zstd(9),
+        SecondaryCodec::Zstd(19),
+        SecondaryCodec::Lz4(0),
+        SecondaryCodec::Lz4(9),
+    ];
+
     let all_sizes = vec![
         ("cache_friendly", 16 * 1024),
         ("memory", 256 * 1024),
@@ -2332,6 +2644,11 @@ fn run_benchmarks_with_config(c: &mut Criterion, config: &BenchmarkConfig) {
         .filter(|(name, _)| config.should_run_size(name))
         .collect();
 
+    let codecs: Vec<_> = all_codecs
+        .into_iter()
+        .filter(|codec| config.should_run_codec(&codec.name()))
+        .collect();
+
     let total_tests = algos.len() * formats.len() * changes.len() * sizes.len();
     println!("📊 Running {total_tests} test combinations\n");
 
@@ -2363,6 +2680,7 @@ fn run_benchmarks_with_config(c: &mut Criterion, config: &BenchmarkConfig) {
                         size_name,
                         &base,
                         &new,
+                        &codecs,
                     ) {
                         wal.append(&metric).ok();
 
@@ -2405,6 +2723,9 @@ fn run_benchmarks_with_config(c: &mut Criterion, config: &BenchmarkConfig) {
     let all_metrics = wal.read_all().unwrap();
     if !all_metrics.is_empty() {
         generate_markdown_report(&all_metrics, &hardware, early_termination, &report_md).unwrap();
+        #[cfg(feature = "bench-parquet")]
+        generate_parquet_report(&all_metrics, &hardware, early_termination, &report_parquet)
+            .unwrap();
         generate_json_report(
             all_metrics,
             hardware.clone(),
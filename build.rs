@@ -0,0 +1,65 @@
+//! Embeds build information for `gdelta --version --json`.
+//!
+//! Only inputs of the build are recorded, never the time or the machine it
+//! ran on, so building the same commit with the same toolchain and features
+//! gives the same binary.
+
+use std::env;
+use std::path::Path;
+use std::process::Command;
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=GDELTA_GIT_HASH");
+    // Missing files would rerun the script on every build
+    for file in [".git/HEAD", ".git/index"] {
+        if Path::new(file).exists() {
+            println!("cargo:rerun-if-changed={file}");
+        }
+    }
+
+    // Source archives have no repository, so packagers can pass the hash
+    let git_hash = env::var("GDELTA_GIT_HASH")
+        .ok()
+        .filter(|hash| !hash.is_empty())
+        .or_else(git_hash)
+        .unwrap_or_default();
+    println!("cargo:rustc-env=GDELTA_GIT_HASH={git_hash}");
+
+    let mut features: Vec<String> = env::vars()
+        .filter_map(|(name, _)| {
+            let feature = name.strip_prefix("CARGO_FEATURE_")?;
+            Some(feature.to_lowercase().replace('_', "-"))
+        })
+        .collect();
+    features.sort();
+    println!("cargo:rustc-env=GDELTA_FEATURES={}", features.join(","));
+
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let rustc_version = output(Command::new(rustc).arg("--version")).unwrap_or_default();
+    println!("cargo:rustc-env=GDELTA_RUSTC_VERSION={rustc_version}");
+    println!(
+        "cargo:rustc-env=GDELTA_TARGET={}",
+        env::var("TARGET").unwrap_or_default()
+    );
+}
+
+/// Returns the commit being built, with `-dirty` appended if the working
+/// tree has changes, or `None` outside a git checkout.
+fn git_hash() -> Option<String> {
+    let hash = output(Command::new("git").args(["rev-parse", "HEAD"]))?;
+    let status =
+        output(Command::new("git").args(["status", "--porcelain", "--untracked-files=no"]));
+    Some(match status {
+        Some(changes) if !changes.is_empty() => format!("{hash}-dirty"),
+        _ => hash,
+    })
+}
+
+/// Runs `command` and returns its trimmed output, if it succeeded.
+fn output(command: &mut Command) -> Option<String> {
+    let output = command.output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8(output.stdout).ok()?.trim().to_string())
+}
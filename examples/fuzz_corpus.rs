@@ -0,0 +1,30 @@
+//! Writes the generated seed corpus to a directory.
+//!
+//! Run with `cargo run --example fuzz_corpus --features unstable-format -- <dir> [base size]`.
+//!
+//! Every delta from [`gdelta::format::seed_corpus`] is written as
+//! `<name>.gdelta`, for a base of the given size (256 bytes by default).
+//! Point a fuzzer's corpus directory at the output to start it from
+//! well-formed deltas with extreme field values instead of random bytes.
+//! Entries that expose a bug belong in `tests/fixtures/robustness` once
+//! fixed.
+
+use std::fs;
+use std::path::PathBuf;
+
+fn main() -> std::io::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let Some(dir) = args.next().map(PathBuf::from) else {
+        eprintln!("usage: fuzz_corpus <dir> [base size]");
+        std::process::exit(2);
+    };
+    let base_len = args.next().and_then(|arg| arg.parse().ok()).unwrap_or(256);
+
+    fs::create_dir_all(&dir)?;
+    let corpus = gdelta::format::seed_corpus(base_len);
+    for (name, delta) in &corpus {
+        fs::write(dir.join(format!("{name}.gdelta")), delta)?;
+    }
+    println!("Wrote {} deltas to {}", corpus.len(), dir.display());
+    Ok(())
+}
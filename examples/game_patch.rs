@@ -0,0 +1,187 @@
+//! Game asset patching with signed bundles.
+//!
+//! Run with `cargo run --example game_patch`.
+//!
+//! Builds two versions of an asset directory, packs the difference into a
+//! `.gdpack` bundle, signs it, and applies it to an installed copy of the
+//! old version the way a game launcher would:
+//!
+//! 1. The build server diffs the release directories with
+//!    [`Bundle::diff_dirs`], using checksummed deltas, and appends an
+//!    HMAC-SHA256 tag so clients only apply bundles it produced. A shared
+//!    key keeps the example short; a real launcher would verify a
+//!    public-key signature instead.
+//! 2. The launcher checks the tag, parses the bundle (which verifies its own
+//!    CRC-32), and applies it with [`Bundle::apply_to_dir`]. The install is
+//!    swapped in only after every file has been patched and verified, so an
+//!    interrupted update never leaves a half-patched game behind.
+
+use gdelta::EncodeOptions;
+use gdelta::bundle::{Bundle, Progress};
+use hmac_sha256::HMAC;
+use std::fs;
+use std::path::Path;
+
+/// Key shared by the build server and the launcher.
+const SIGNING_KEY: &[u8] = b"example signing key, keep secret";
+
+/// Size of the HMAC-SHA256 tag appended to the bundle.
+const TAG_SIZE: usize = 32;
+
+fn write(root: &Path, path: &str, contents: &[u8]) {
+    let file = root.join(path);
+    fs::create_dir_all(file.parent().unwrap()).unwrap();
+    fs::write(file, contents).unwrap();
+}
+
+/// Produces a pseudo-random "texture" that compresses poorly, like real
+/// asset data.
+fn texture(len: usize, seed: u32) -> Vec<u8> {
+    let mut state = seed;
+    (0..len)
+        .map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (state >> 16) as u8
+        })
+        .collect()
+}
+
+/// Writes release 1.0 and 1.1 of the game assets.
+fn build_releases(old: &Path, new: &Path) {
+    let wall = texture(256 * 1024, 1);
+    let floor = texture(128 * 1024, 2);
+    let level: String = (0..2000)
+        .map(|i| {
+            format!(
+                "spawn enemy {} at {},{}\n",
+                i % 7,
+                i * 13 % 500,
+                i * 29 % 500
+            )
+        })
+        .collect();
+
+    write(old, "textures/wall.tex", &wall);
+    write(old, "textures/floor.tex", &floor);
+    write(old, "levels/level1.map", level.as_bytes());
+    write(old, "audio/intro.ogg", &texture(64 * 1024, 3));
+
+    // 1.1 repaints part of the wall, rebalances level 1, drops the intro
+    // music and adds a new level
+    let mut repainted = wall.clone();
+    repainted[10_000..14_000].copy_from_slice(&texture(4_000, 4));
+    write(new, "textures/wall.tex", &repainted);
+    write(new, "textures/floor.tex", &floor);
+    write(
+        new,
+        "levels/level1.map",
+        level.replace("enemy 3", "boss 3").as_bytes(),
+    );
+    write(new, "levels/level2.map", b"spawn player at 0,0\n");
+}
+
+/// Installs a release by copying its directory.
+fn copy_dir(from: &Path, to: &Path) {
+    fs::create_dir_all(to).unwrap();
+    for entry in fs::read_dir(from).unwrap() {
+        let entry = entry.unwrap();
+        let target = to.join(entry.file_name());
+        if entry.file_type().unwrap().is_dir() {
+            copy_dir(&entry.path(), &target);
+        } else {
+            fs::copy(entry.path(), target).unwrap();
+        }
+    }
+}
+
+fn print_progress(action: &str, progress: Progress<'_>) {
+    println!(
+        "  [{}/{}] {action} {}",
+        progress.done + 1,
+        progress.total,
+        progress.path
+    );
+}
+
+/// Build server: diff the releases and sign the bundle.
+fn make_signed_bundle(old: &Path, new: &Path) -> Vec<u8> {
+    let options = EncodeOptions::new().checksum(true);
+    let bundle = Bundle::diff_dirs(old, new, &options, |progress| {
+        print_progress("diffing", progress);
+    })
+    .unwrap();
+
+    let mut signed = bundle.to_bytes();
+    let tag = HMAC::mac(&signed, SIGNING_KEY);
+    signed.extend_from_slice(&tag);
+    signed
+}
+
+/// Launcher: verify the signature, then patch the install atomically.
+fn apply_signed_bundle(signed: &[u8], install: &Path) -> Result<(), String> {
+    let (bundle, tag) = signed
+        .len()
+        .checked_sub(TAG_SIZE)
+        .map(|split| signed.split_at(split))
+        .ok_or("bundle too short")?;
+    if HMAC::mac(bundle, SIGNING_KEY) != tag {
+        return Err("signature mismatch, refusing to apply".to_string());
+    }
+
+    let bundle = Bundle::from_bytes(bundle).map_err(|e| e.to_string())?;
+    bundle
+        .apply_to_dir(install, |progress| print_progress("patching", progress))
+        .map_err(|e| e.to_string())
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn main() {
+    let root = std::env::temp_dir().join(format!("gdelta-game-patch-{}", std::process::id()));
+    let (old, new, install) = (
+        root.join("release-1.0"),
+        root.join("release-1.1"),
+        root.join("install/assets"),
+    );
+    build_releases(&old, &new);
+    copy_dir(&old, &install);
+
+    println!("Building update bundle:");
+    let signed = make_signed_bundle(&old, &new);
+    let release_size: u64 = [
+        "textures/wall.tex",
+        "textures/floor.tex",
+        "levels/level1.map",
+        "levels/level2.map",
+    ]
+    .iter()
+    .map(|path| fs::metadata(new.join(path)).unwrap().len())
+    .sum();
+    println!(
+        "Bundle: {} bytes for a {release_size} byte release ({:.1}%)",
+        signed.len(),
+        signed.len() as f64 / release_size as f64 * 100.0
+    );
+
+    // A tampered bundle is rejected before anything is touched
+    let mut tampered = signed.clone();
+    tampered[40] ^= 1;
+    let error = apply_signed_bundle(&tampered, &install).unwrap_err();
+    println!("Tampered bundle: {error}");
+
+    println!("Applying update:");
+    apply_signed_bundle(&signed, &install).unwrap();
+    for path in [
+        "textures/wall.tex",
+        "levels/level1.map",
+        "levels/level2.map",
+    ] {
+        assert_eq!(
+            fs::read(install.join(path)).unwrap(),
+            fs::read(new.join(path)).unwrap()
+        );
+    }
+    assert!(!install.join("audio").exists());
+    println!("Install now matches release 1.1");
+
+    fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,106 @@
+//! Container layer deltas.
+//!
+//! Run with `cargo run --example oci_layer [old-layer.tar new-layer.tar]`.
+//!
+//! Registries store image layers as compressed tars, so a rebuilt image
+//! usually means downloading every changed layer in full. This example
+//! encodes a new layer against the previous version of it with
+//! [`gdelta::oci::encode`], which matches the tar members by path, and
+//! checks that the old layer plus the delta reproduce the new layer tar
+//! byte for byte, so its digest still verifies.
+//!
+//! Without arguments it builds two small layers in memory. Layers pulled
+//! from a registry have to be decompressed first (`gunzip` or `zstd -d`).
+
+use gdelta::bundle::Bundle;
+use gdelta::{decode, encode, oci};
+use std::path::Path;
+
+/// Appends a regular file to a ustar archive.
+fn append(archive: &mut Vec<u8>, name: &str, contents: &[u8]) {
+    let mut header = [0u8; 512];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    header[100..108].copy_from_slice(b"0000644\0");
+    header[108..116].copy_from_slice(b"0000000\0");
+    header[116..124].copy_from_slice(b"0000000\0");
+    header[124..136].copy_from_slice(format!("{:011o}\0", contents.len()).as_bytes());
+    header[136..148].copy_from_slice(b"14674662000\0");
+    header[156] = b'0';
+    header[257..265].copy_from_slice(b"ustar\x0000");
+    header[148..156].fill(b' ');
+    let checksum: u32 = header.iter().map(|&byte| u32::from(byte)).sum();
+    header[148..156].copy_from_slice(format!("{checksum:06o}\0 ").as_bytes());
+
+    archive.extend_from_slice(&header);
+    archive.extend_from_slice(contents);
+    archive.resize(archive.len().next_multiple_of(512), 0);
+}
+
+fn finish(mut archive: Vec<u8>) -> Vec<u8> {
+    archive.resize(archive.len() + 1024, 0);
+    archive
+}
+
+/// Builds an application layer and a rebuild with an updated binary, a
+/// changed config and a new file, written in a different order.
+fn sample_layers() -> (Vec<u8>, Vec<u8>) {
+    let binary: Vec<u8> = (0..300_000u32)
+        .map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8)
+        .collect();
+    let assets = b"<html><body>static asset</body></html>\n".repeat(2000);
+    let config = "port = 8080\nworkers = 4\nlog = info\n";
+
+    let mut old = Vec::new();
+    append(&mut old, "app/server", &binary);
+    append(&mut old, "app/static/index.html", &assets);
+    append(&mut old, "etc/app.toml", config.as_bytes());
+
+    let mut rebuilt = binary.clone();
+    rebuilt[150_000..150_064].fill(0x90);
+    let mut new = Vec::new();
+    append(
+        &mut new,
+        "etc/app.toml",
+        config.replace("4", "8").as_bytes(),
+    );
+    append(&mut new, "app/static/index.html", &assets);
+    append(
+        &mut new,
+        "app/migrations/0002.sql",
+        b"ALTER TABLE users ADD email TEXT;\n",
+    );
+    append(&mut new, "app/server", &rebuilt);
+
+    (finish(old), finish(new))
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (old, new) = match args.as_slice() {
+        [old, new] => (
+            std::fs::read(Path::new(old)).expect("cannot read old layer"),
+            std::fs::read(Path::new(new)).expect("cannot read new layer"),
+        ),
+        _ => sample_layers(),
+    };
+
+    match oci::members(&new) {
+        Some(members) => println!("New layer: {} bytes, {} members", new.len(), members.len()),
+        None => println!("New layer is not a tar archive, encoding it as a whole"),
+    }
+
+    let plain = encode(&new, &old).unwrap();
+    let delta = oci::encode(&new, &old).unwrap();
+    assert_eq!(decode(&delta, &old).unwrap(), new);
+    println!("Whole-file delta:   {:>8} bytes", plain.len());
+    println!(
+        "Member-wise delta:  {:>8} bytes (reproduces the tar exactly)",
+        delta.len()
+    );
+
+    // Ship it as a bundle that patches the cached layer file in place
+    let bundle = oci::bundle("layer.tar", &new, &old).unwrap();
+    let bytes = bundle.to_bytes();
+    assert_eq!(Bundle::from_bytes(&bytes).unwrap(), bundle);
+    println!("Bundle:             {:>8} bytes", bytes.len());
+}
@@ -0,0 +1,235 @@
+//! Patch server example: a reference architecture for serving deltas.
+//!
+//! Run with `cargo run --example patch_server --features patch-server`.
+//!
+//! The server keeps every published version of a file and answers
+//! `GET /patch/{version}` with a delta from whatever version the client
+//! already has to the requested one:
+//!
+//! 1. **Signature exchange**: the client sends a signature of its local copy
+//!    in the `x-base-signature` header. The server maps it to a known
+//!    version, or answers `404` so the client falls back to `GET /full/...`.
+//! 2. **Base index caching**: the GEAR index of each base is built once and
+//!    shared between requests, so a popular base is only indexed once.
+//! 3. **Streamed responses**: the target is encoded in windows on a blocking
+//!    thread and every window is sent as soon as it is ready, framed as
+//!    `[length: u32 LE][delta]`. The client decodes each frame on arrival.
+//!
+//! After starting the server, the example runs a small client against it
+//! that upgrades from `v1` to `v3` and verifies the result.
+
+use axum::Router;
+use axum::body::{Body, Bytes};
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use gdelta::matcher::{GearMatcher, Match, Matcher};
+use gdelta::{decode, encode_with_matcher};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+/// Target bytes encoded per streamed frame.
+const FRAME_WINDOW: usize = 64 * 1024;
+
+/// A published version of the file.
+struct Version {
+    name: String,
+    data: Arc<Vec<u8>>,
+}
+
+/// A base index built once and shared between requests.
+///
+/// The whole base is indexed up front, so [`Matcher::index`] has nothing
+/// left to do when the encoder calls it.
+#[derive(Clone)]
+struct PrebuiltIndex(GearMatcher);
+
+impl PrebuiltIndex {
+    fn build(base: &[u8]) -> Self {
+        let mut matcher = GearMatcher::new();
+        matcher.index(base, 0, base.len());
+        Self(matcher)
+    }
+}
+
+impl Matcher for PrebuiltIndex {
+    fn index(&mut self, _base: &[u8], _start: usize, _end: usize) {}
+
+    fn find_match(&mut self, target: &[u8], base: &[u8], pos: usize) -> Option<Match> {
+        self.0.find_match(target, base, pos)
+    }
+}
+
+struct AppState {
+    versions: Vec<Version>,
+    /// Base indexes by version name, built on first use.
+    indexes: Mutex<HashMap<String, PrebuiltIndex>>,
+}
+
+impl AppState {
+    fn version(&self, name: &str) -> Option<&Version> {
+        self.versions.iter().find(|version| version.name == name)
+    }
+
+    fn by_signature(&self, signature: &str) -> Option<&Version> {
+        self.versions
+            .iter()
+            .find(|version| content_signature(&version.data) == signature)
+    }
+
+    fn index(&self, version: &Version) -> PrebuiltIndex {
+        let mut indexes = self.indexes.lock().unwrap();
+        indexes
+            .entry(version.name.clone())
+            .or_insert_with(|| PrebuiltIndex::build(&version.data))
+            .clone()
+    }
+}
+
+/// Identifies a version by its length and a 64-bit FNV-1a hash.
+///
+/// A real deployment would use a cryptographic hash here.
+fn content_signature(data: &[u8]) -> String {
+    let hash = data.iter().fold(0xcbf2_9ce4_8422_2325u64, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+    format!("{}-{hash:016x}", data.len())
+}
+
+async fn full(State(state): State<Arc<AppState>>, Path(name): Path<String>) -> Response {
+    match state.version(&name) {
+        Some(version) => Bytes::from(version.data.to_vec()).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+async fn patch(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    let Some(target) = state.version(&name) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let signature = headers
+        .get("x-base-signature")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    let Some(base) = state.by_signature(signature) else {
+        // Unknown base: the client has to download the full version
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let index = state.index(base);
+    let base = Arc::clone(&base.data);
+    let target = Arc::clone(&target.data);
+    let (sender, receiver) = mpsc::channel::<Result<Bytes, std::io::Error>>(4);
+
+    // Encoding is CPU-bound, so keep it off the async workers
+    tokio::task::spawn_blocking(move || {
+        for window in target.chunks(FRAME_WINDOW) {
+            let frame = encode_with_matcher(window, &base, &mut index.clone())
+                .map(|delta| {
+                    let mut frame = Vec::with_capacity(delta.len() + 4);
+                    frame.extend_from_slice(&(delta.len() as u32).to_le_bytes());
+                    frame.extend_from_slice(&delta);
+                    Bytes::from(frame)
+                })
+                .map_err(std::io::Error::other);
+            // The client hung up
+            if sender.blocking_send(frame).is_err() {
+                break;
+            }
+        }
+    });
+
+    Body::from_stream(ReceiverStream::new(receiver)).into_response()
+}
+
+/// Sends a bare HTTP/1.0 request and returns the status and body.
+async fn request(address: &str, path: &str, signature: &str) -> (u16, Vec<u8>) {
+    let mut stream = TcpStream::connect(address).await.unwrap();
+    let request = format!("GET {path} HTTP/1.0\r\nx-base-signature: {signature}\r\n\r\n");
+    stream.write_all(request.as_bytes()).await.unwrap();
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await.unwrap();
+    let split = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .unwrap();
+    let status = String::from_utf8_lossy(&response[9..12]).parse().unwrap();
+    (status, response[split + 4..].to_vec())
+}
+
+/// Applies a streamed patch frame by frame.
+fn apply_frames(mut body: &[u8], base: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    while !body.is_empty() {
+        let (length, rest) = body.split_at(4);
+        let length = u32::from_le_bytes(length.try_into().unwrap()) as usize;
+        let (delta, rest) = rest.split_at(length);
+        output.extend_from_slice(&decode(delta, base).unwrap());
+        body = rest;
+    }
+    output
+}
+
+/// Builds a changelog-like file and two later revisions of it.
+fn sample_versions() -> Vec<Version> {
+    let mut text: String = (0..20_000)
+        .map(|i| format!("entry {i}: status ok, value {}\n", i * 31 % 977))
+        .collect();
+    let mut versions = Vec::new();
+    for name in ["v1", "v2", "v3"] {
+        versions.push(Version {
+            name: name.to_string(),
+            data: Arc::new(text.clone().into_bytes()),
+        });
+        text = text.replace("status ok, value 1", "status degraded, value 1");
+        text.push_str(&format!("release {name} published\n"));
+    }
+    versions
+}
+
+#[tokio::main]
+async fn main() {
+    let state = Arc::new(AppState {
+        versions: sample_versions(),
+        indexes: Mutex::new(HashMap::new()),
+    });
+    let local = state.version("v1").unwrap().data.clone();
+    let latest = state.version("v3").unwrap().data.clone();
+
+    let app = Router::new()
+        .route("/full/{version}", get(full))
+        .route("/patch/{version}", get(patch))
+        .with_state(state);
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    println!("Patch server listening on http://{address}");
+
+    // A client that has v1 upgrades to v3
+    let (status, body) = request(&address, "/patch/v3", &content_signature(&local)).await;
+    assert_eq!(status, 200);
+    let upgraded = apply_frames(&body, &local);
+    assert_eq!(upgraded, *latest);
+    println!(
+        "Upgraded v1 -> v3 with {} bytes of patch instead of {} bytes",
+        body.len(),
+        latest.len()
+    );
+
+    // A client with an unknown local copy is told to download in full
+    let (status, _) = request(&address, "/patch/v3", "0-0000000000000000").await;
+    assert_eq!(status, 404);
+    let (status, body) = request(&address, "/full/v3", "").await;
+    assert_eq!((status, body.len()), (200, latest.len()));
+    println!("Unknown base: fell back to a full download");
+}
@@ -3,25 +3,55 @@
 //! Usage:
 //!   gdelta encode <base> <new> -o <output> [OPTIONS]
 //!   gdelta decode <base> <delta> -o <output> [OPTIONS]
+//!   gdelta apply-set <dir> <base> -o <output> [OPTIONS]
+//!   gdelta encode-dir <base> <new> -o <bundle> [OPTIONS]
+//!   gdelta decode-dir <dir> <bundle> [--to-tar <tar> | --dry-run] [OPTIONS]
+//!   gdelta extract <bundle> <path> -o <output> [OPTIONS]
+//!   gdelta rollback <log> [OPTIONS]
+//!   gdelta debug <base> <delta> (--instruction <n> | --offset <n>) [OPTIONS]
+//!   gdelta bench-compare <old.json> <new.json> [OPTIONS]
+//!   gdelta serve --socket <path> [OPTIONS]
+//!   gdelta --version [--json]
 
 use anyhow::{Context, Result, bail};
-use clap::{Parser, Subcommand, ValueEnum};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use gdelta::compression::PostCompressor;
 use owo_colors::OwoColorize;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
-use std::io::{self, Write};
+use std::io::{self, BufRead, Read, Write};
+use std::ops::{Deref, Range};
 use std::path::{Path, PathBuf};
 use std::process;
-use std::time::Instant;
-use sysinfo::System;
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime};
+use sysinfo::{Disks, System};
 
 /// Fast delta compression tool
 #[derive(Parser)]
 #[command(name = "gdelta")]
 #[command(author, version, about, long_about = None)]
-#[command(propagate_version = true)]
+#[command(disable_version_flag = true)]
 struct Cli {
     #[command(subcommand)]
-    command: Commands,
+    command: Option<Commands>,
+
+    /// Print version
+    #[arg(short = 'V', long)]
+    version: bool,
+
+    /// With --version, print the build info as JSON: the crate version,
+    /// enabled features, supported delta format versions, git commit,
+    /// target and compiler
+    #[arg(long, requires = "version")]
+    json: bool,
+
+    /// Append a JSON line describing the operation to this file: the
+    /// inputs and outputs with their sizes and BLAKE3 hashes, the duration
+    /// and the result
+    #[arg(long, global = true, value_name = "FILE")]
+    journal: Option<PathBuf>,
 }
 
 #[derive(Subcommand)]
@@ -34,18 +64,50 @@ enum Commands {
         /// New file (target version)
         new: PathBuf,
 
-        /// Output delta file
-        #[arg(short, long)]
-        output: PathBuf,
+        /// Output delta file, or the directory to put it in with --auto-name
+        #[arg(short, long, required_unless_present = "auto_name")]
+        output: Option<PathBuf>,
+
+        /// Name the delta <basehash>-<targethash>.gd after the BLAKE3 hashes
+        /// of the inputs, in the -o directory or the current one
+        #[arg(long)]
+        auto_name: bool,
 
         /// Compression method
         #[arg(short, long, value_enum, default_value = "none")]
         compress: Compression,
 
+        /// Use the start of the base as a zstd dictionary (with -c zstd)
+        #[arg(long)]
+        zstd_dict: bool,
+
+        /// Compress instructions and literal data separately, storing either
+        /// uncompressed if compression does not shrink it
+        #[arg(long)]
+        split_streams: bool,
+
+        /// Embed CRC-32 checksums of the delta, the base and the target, and
+        /// the target size, so decoding verifies all of them
+        #[arg(long)]
+        checksum: bool,
+
+        /// Append Reed-Solomon parity so the delta survives damaged sections;
+        /// decoding detects and repairs it
+        #[arg(long)]
+        fec: bool,
+
+        /// Encoder tuning for the kind of data being diffed
+        #[arg(long, value_enum, default_value = "auto")]
+        preset: PresetArg,
+
         /// Verify delta after creation by decoding and comparing
         #[arg(short, long)]
         verify: bool,
 
+        /// Read inputs with direct I/O, bypassing the page cache (Linux)
+        #[arg(long)]
+        direct_io: bool,
+
         /// Skip memory warning prompt
         #[arg(short = 'y', long)]
         yes: bool,
@@ -74,6 +136,19 @@ enum Commands {
         #[arg(long, value_enum)]
         format: Option<Compression>,
 
+        /// Limit writing the output to this many bytes per second
+        #[arg(long, value_name = "BYTES_PER_SEC")]
+        max_rate: Option<u64>,
+
+        /// If the output exists, check it against the target checksum in the
+        /// delta and skip decoding when it matches
+        #[arg(long)]
+        verify_checksum_only: bool,
+
+        /// Read inputs with direct I/O, bypassing the page cache (Linux)
+        #[arg(long)]
+        direct_io: bool,
+
         /// Skip memory warning prompt
         #[arg(short = 'y', long)]
         yes: bool,
@@ -82,6 +157,197 @@ enum Commands {
         #[arg(short, long)]
         force: bool,
 
+        /// Suppress output except errors
+        #[arg(short, long)]
+        quiet: bool,
+    },
+    /// Apply a directory of deltas in the order their checksums chain
+    ApplySet {
+        /// Directory of deltas encoded with --checksum
+        dir: PathBuf,
+
+        /// File the first delta applies to
+        base: PathBuf,
+
+        /// Output file
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// Overwrite output file if it exists
+        #[arg(short, long)]
+        force: bool,
+
+        /// Suppress output except errors
+        #[arg(short, long)]
+        quiet: bool,
+    },
+    /// Create a bundle of the changes from one directory tree to another
+    EncodeDir {
+        /// Base directory (original version)
+        base: PathBuf,
+
+        /// New directory (target version)
+        new: PathBuf,
+
+        /// Output bundle file
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// Leave out files matching this .gitignore-style pattern, after the
+        /// rules of the new tree's .gdignore (repeatable)
+        #[arg(long, value_name = "PATTERN")]
+        exclude: Vec<String>,
+
+        /// Ignore the .gdignore file of the new tree
+        #[arg(long)]
+        no_gdignore: bool,
+
+        /// Record symbolic links, permissions, modification times and, on
+        /// Unix, extended attributes
+        #[arg(long)]
+        metadata: bool,
+
+        /// Compare files on all cores, holding at most about this many bytes
+        /// of file contents at once
+        #[arg(long, value_name = "BYTES")]
+        memory_budget: Option<u64>,
+
+        /// Overwrite output file if it exists
+        #[arg(short, long)]
+        force: bool,
+
+        /// Suppress output except errors
+        #[arg(short, long)]
+        quiet: bool,
+    },
+    /// Apply a bundle created by encode-dir to a directory tree in place, or
+    /// write the result as a tar archive
+    DecodeDir {
+        /// Directory to update (original version)
+        dir: PathBuf,
+
+        /// Bundle file
+        bundle: PathBuf,
+
+        /// Write the updated tree to this tar archive instead, leaving the
+        /// directory unchanged
+        #[arg(long, value_name = "TAR")]
+        to_tar: Option<PathBuf>,
+
+        /// Check that every entry applies, that nothing blocks the apply and
+        /// that there is enough disk space, without writing anything, and
+        /// print the findings as JSON
+        #[arg(long, conflicts_with = "to_tar")]
+        dry_run: bool,
+
+        /// Overwrite the tar archive if it exists
+        #[arg(short, long, requires = "to_tar")]
+        force: bool,
+
+        /// Suppress output except errors
+        #[arg(short, long)]
+        quiet: bool,
+    },
+    /// Reconstruct a single file of the new tree from a bundle, reading only
+    /// its part of the bundle
+    Extract {
+        /// Bundle file created by encode-dir
+        bundle: PathBuf,
+
+        /// Path of the file in the new tree, with / separators
+        path: String,
+
+        /// Base directory, needed unless the file was added whole
+        #[arg(long)]
+        base: Option<PathBuf>,
+
+        /// Output file
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// Overwrite output file if it exists
+        #[arg(short, long)]
+        force: bool,
+
+        /// Suppress output except errors
+        #[arg(short, long)]
+        quiet: bool,
+    },
+    /// Undo a decode-dir that did not finish, from the <dir>.gdpack-txn
+    /// transaction log it left next to the directory
+    Rollback {
+        /// Transaction log
+        log: PathBuf,
+
+        /// Suppress output except errors
+        #[arg(short, long)]
+        quiet: bool,
+    },
+    /// Decode up to an instruction or target offset and dump the decoder
+    /// state, for bug reports about wrong output
+    Debug {
+        /// Base file (original version)
+        base: PathBuf,
+
+        /// Delta patch file
+        delta: PathBuf,
+
+        /// Stop before the instruction with this index, counting from 0
+        #[arg(long, conflicts_with = "offset", required_unless_present = "offset")]
+        instruction: Option<usize>,
+
+        /// Stop before the instruction that writes this byte of the target
+        #[arg(long)]
+        offset: Option<u64>,
+
+        /// Write the target reconstructed up to the stop to this file
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Overwrite output file if it exists
+        #[arg(short, long)]
+        force: bool,
+    },
+    /// Compare two JSON reports of the comprehensive benchmark
+    BenchCompare {
+        /// Baseline report (target/benchmark_report_<timestamp>.json)
+        old: PathBuf,
+
+        /// Report to compare against the baseline
+        new: PathBuf,
+
+        /// Throughput change in percent that counts as significant
+        #[arg(long, default_value_t = 5.0)]
+        threshold: f64,
+
+        /// Compression ratio change in percent that counts as significant
+        #[arg(long, default_value_t = 1.0)]
+        ratio_threshold: f64,
+
+        /// Also list scenarios without significant changes
+        #[arg(short, long)]
+        all: bool,
+
+        /// Exit with an error if any scenario regressed
+        #[arg(long)]
+        fail_on_regression: bool,
+    },
+    /// Serve encode and decode requests on a Unix socket, keeping base
+    /// indexes in memory between requests (Windows named pipes are not
+    /// supported)
+    Serve {
+        /// Socket to listen on
+        #[arg(long)]
+        socket: PathBuf,
+
+        /// Number of base indexes to keep in memory
+        #[arg(long, default_value_t = 8, value_parser = clap::value_parser!(u64).range(1..))]
+        cache: u64,
+
+        /// Largest target a decode request may produce, in bytes
+        #[arg(long, default_value_t = 1 << 30)]
+        max_output_size: u64,
+
         /// Suppress output except errors
         #[arg(short, long)]
         quiet: bool,
@@ -98,6 +364,36 @@ enum Compression {
     Lz4,
 }
 
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+enum PresetArg {
+    /// Detect the kind of data from the start of the new file
+    Auto,
+    /// Balanced settings
+    Default,
+    /// Source code, logs and other text
+    Text,
+    /// JSON, XML, CSV and similar formats
+    Structured,
+    /// Executables and other uncompressed binaries
+    Binary,
+    /// Already compressed or encrypted data
+    Compressed,
+}
+
+impl PresetArg {
+    /// Returns the preset to encode `new_data` with.
+    fn resolve(self, new_data: &[u8]) -> gdelta::Preset {
+        match self {
+            PresetArg::Auto => gdelta::Preset::detect(new_data),
+            PresetArg::Default => gdelta::Preset::Default,
+            PresetArg::Text => gdelta::Preset::Text,
+            PresetArg::Structured => gdelta::Preset::Structured,
+            PresetArg::Binary => gdelta::Preset::Binary,
+            PresetArg::Compressed => gdelta::Preset::Compressed,
+        }
+    }
+}
+
 // Exit codes
 const EXIT_SUCCESS: i32 = 0;
 const EXIT_ERROR: i32 = 1;
@@ -108,28 +404,173 @@ const EXIT_USER_CANCELLED: i32 = 5;
 fn main() {
     let cli = Cli::parse();
 
-    let result = match cli.command {
+    if cli.version {
+        print_version(cli.json);
+        return;
+    }
+    let Some(command) = cli.command else {
+        Cli::command()
+            .error(
+                clap::error::ErrorKind::MissingSubcommand,
+                "a subcommand is required",
+            )
+            .exit();
+    };
+
+    // Open the journal first, so an operation is never left unrecorded
+    let journal = match cli.journal.as_deref().map(Journal::open).transpose() {
+        Ok(journal) => journal.map(|journal| journal.start(&command)),
+        Err(e) => {
+            eprintln!("{} {}", "Error:".bright_red().bold(), e);
+            process::exit(EXIT_ERROR);
+        }
+    };
+
+    let result = match command {
         Commands::Encode {
             base,
             new,
             output,
+            auto_name,
+            compress,
+            zstd_dict,
+            split_streams,
+            checksum,
+            fec,
+            preset,
+            verify,
+            direct_io,
+            yes,
+            force,
+            quiet,
+        } => handle_encode(
+            &base,
+            &new,
+            output.as_deref(),
+            auto_name,
             compress,
+            zstd_dict,
+            split_streams,
+            checksum,
+            fec,
+            preset,
             verify,
+            direct_io,
             yes,
             force,
             quiet,
-        } => handle_encode(&base, &new, &output, compress, verify, yes, force, quiet),
+        ),
         Commands::Decode {
             base,
             delta,
             output,
             format,
+            max_rate,
+            verify_checksum_only,
+            direct_io,
+            yes,
+            force,
+            quiet,
+        } => handle_decode(
+            &base,
+            &delta,
+            &output,
+            format,
+            max_rate,
+            verify_checksum_only,
+            direct_io,
             yes,
             force,
             quiet,
-        } => handle_decode(&base, &delta, &output, format, yes, force, quiet),
+        ),
+        Commands::ApplySet {
+            dir,
+            base,
+            output,
+            force,
+            quiet,
+        } => handle_apply_set(&dir, &base, &output, force, quiet),
+        Commands::EncodeDir {
+            base,
+            new,
+            output,
+            exclude,
+            no_gdignore,
+            metadata,
+            memory_budget,
+            force,
+            quiet,
+        } => handle_encode_dir(
+            &base,
+            &new,
+            &output,
+            &exclude,
+            no_gdignore,
+            metadata,
+            memory_budget,
+            force,
+            quiet,
+        ),
+        Commands::DecodeDir {
+            dir,
+            bundle,
+            to_tar,
+            dry_run,
+            force,
+            quiet,
+        } => {
+            if dry_run {
+                handle_check_dir(&dir, &bundle)
+            } else {
+                handle_decode_dir(&dir, &bundle, to_tar.as_deref(), force, quiet)
+            }
+        }
+        Commands::Extract {
+            bundle,
+            path,
+            base,
+            output,
+            force,
+            quiet,
+        } => handle_extract(&bundle, &path, base.as_deref(), &output, force, quiet),
+        Commands::Rollback { log, quiet } => handle_rollback(&log, quiet),
+        Commands::Debug {
+            base,
+            delta,
+            instruction,
+            offset,
+            output,
+            force,
+        } => handle_debug(&base, &delta, instruction, offset, output.as_deref(), force),
+        Commands::BenchCompare {
+            old,
+            new,
+            threshold,
+            ratio_threshold,
+            all,
+            fail_on_regression,
+        } => handle_bench_compare(
+            &old,
+            &new,
+            threshold,
+            ratio_threshold,
+            all,
+            fail_on_regression,
+        ),
+        Commands::Serve {
+            socket,
+            cache,
+            max_output_size,
+            quiet,
+        } => handle_serve(&socket, cache as usize, max_output_size, quiet),
     };
 
+    if let Some(journal) = journal
+        && let Err(e) = journal.finish(&result)
+    {
+        eprintln!("{} {}", "Warning:".bright_yellow().bold(), e);
+    }
+
     match result {
         Ok(()) => process::exit(EXIT_SUCCESS),
         Err(e) => {
@@ -154,16 +595,66 @@ fn main() {
     }
 }
 
+/// Prints the version, or with `json` everything needed to reproduce the
+/// build and tell which deltas it reads and writes.
+fn print_version(json: bool) {
+    let git_hash = env!("GDELTA_GIT_HASH");
+    if !json {
+        match git_hash {
+            "" => println!("gdelta {}", env!("CARGO_PKG_VERSION")),
+            hash => println!("gdelta {} ({hash})", env!("CARGO_PKG_VERSION")),
+        }
+        return;
+    }
+
+    let features: Vec<&str> = env!("GDELTA_FEATURES")
+        .split(',')
+        .filter(|feature| !feature.is_empty())
+        .collect();
+    let format_versions: Vec<u8> = (gdelta::MIN_FORMAT_VERSION..=gdelta::FORMAT_VERSION)
+        .filter(|&version| gdelta::supports(version))
+        .collect();
+    let info = serde_json::json!({
+        "name": env!("CARGO_PKG_NAME"),
+        "version": env!("CARGO_PKG_VERSION"),
+        "git_hash": (!git_hash.is_empty()).then_some(git_hash),
+        "features": features,
+        "format_version": gdelta::FORMAT_VERSION,
+        "format_versions": format_versions,
+        "target": env!("GDELTA_TARGET"),
+        "rustc": env!("GDELTA_RUSTC_VERSION"),
+    });
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&info).expect("build info serializes")
+    );
+}
+
+#[allow(clippy::too_many_arguments)]
 fn handle_encode(
     base_path: &Path,
     new_path: &Path,
-    output_path: &Path,
+    output: Option<&Path>,
+    auto_name: bool,
     compress: Compression,
+    zstd_dict: bool,
+    split_streams: bool,
+    checksum: bool,
+    fec: bool,
+    preset: PresetArg,
     verify: bool,
+    direct_io: bool,
     yes: bool,
     force: bool,
     quiet: bool,
 ) -> Result<()> {
+    if zstd_dict && compress != Compression::Zstd {
+        bail!("--zstd-dict requires --compress zstd");
+    }
+    if split_streams && compress == Compression::None {
+        bail!("--split-streams requires --compress");
+    }
+
     // Check if files exist
     if !base_path.exists() {
         bail!("File not found: {}", base_path.display());
@@ -172,12 +663,15 @@ fn handle_encode(
         bail!("File not found: {}", new_path.display());
     }
 
-    // Check if output exists
-    if output_path.exists() && !force {
-        bail!(
-            "Output file already exists: {}\n   Use --force to overwrite",
-            output_path.display()
-        );
+    // Check if output exists; with --auto-name its name is known only
+    // once the inputs are read
+    if auto_name {
+        let dir = output.unwrap_or(Path::new("."));
+        if !dir.is_dir() {
+            bail!("Output directory not found: {}", dir.display());
+        }
+    } else if let Some(output_path) = output {
+        check_output_absent(output_path, force)?;
     }
 
     // Get file sizes
@@ -210,10 +704,21 @@ fn handle_encode(
         );
     }
 
-    let base_data = fs::read(base_path)
-        .with_context(|| format!("Failed to read base file: {}", base_path.display()))?;
-    let new_data = fs::read(new_path)
-        .with_context(|| format!("Failed to read new file: {}", new_path.display()))?;
+    let (base_data, new_data) = read_inputs(
+        &open_input(base_path, "base", direct_io)?,
+        &open_input(new_path, "new", direct_io)?,
+        direct_io,
+    )?;
+
+    let output_path = match output {
+        Some(output_path) if !auto_name => output_path.to_path_buf(),
+        _ => {
+            let name = gdelta::naming::delta_name(&base_data, &new_data);
+            let output_path = output.unwrap_or(Path::new(".")).join(name);
+            check_output_absent(&output_path, force)?;
+            output_path
+        }
+    };
 
     // Encode
     if !quiet {
@@ -224,8 +729,19 @@ fn handle_encode(
         );
     }
 
+    let preset = preset.resolve(&new_data);
+    if !quiet {
+        println!("{} Using the {:?} preset", "Info:".bright_cyan(), preset);
+    }
+
     let start = Instant::now();
-    let delta = gdelta::encode(&new_data, &base_data)
+    let options = gdelta::EncodeOptions::new()
+        .preset(preset)
+        .checksum(checksum)
+        .store_target_size(checksum)
+        .target_checksum(checksum)
+        .base_checksum(checksum);
+    let delta = gdelta::encode_with_options(&new_data, &base_data, &options)
         .map_err(|e| anyhow::anyhow!("Encode failed: {}", e))?;
     let encode_time = start.elapsed();
 
@@ -241,16 +757,41 @@ fn handle_encode(
         }
 
         let start = Instant::now();
-        let compressed = match compress {
-            Compression::Zstd => compress_zstd(&delta)?,
-            Compression::Lz4 => compress_lz4(&delta)?,
-            Compression::None => unreachable!(),
+        let compressed = if zstd_dict || split_streams {
+            let dict_codec;
+            let codec: &dyn PostCompressor = match compress {
+                Compression::Zstd if zstd_dict => {
+                    dict_codec = ZstdDictCodec::for_base(&base_data, u64::MAX);
+                    &dict_codec
+                }
+                Compression::Zstd => &ZstdCodec { limit: u64::MAX },
+                Compression::Lz4 => &Lz4Codec { limit: u64::MAX },
+                Compression::None => unreachable!(),
+            };
+            if split_streams {
+                gdelta::compression::wrap_streams(&delta, codec)
+            } else {
+                gdelta::compression::wrap(&delta, codec)
+            }
+            .map_err(|e| anyhow::anyhow!("Compression failed: {}", e))?
+        } else {
+            match compress {
+                Compression::Zstd => compress_zstd(&delta)?,
+                Compression::Lz4 => compress_lz4(&delta)?,
+                Compression::None => unreachable!(),
+            }
         };
         let time = start.elapsed();
         (compressed, Some(time))
     } else {
         (delta, None)
     };
+    let final_delta = if fec {
+        gdelta::fec::protect(&final_delta, &gdelta::fec::FecOptions::new())
+            .map_err(|e| anyhow::anyhow!("Adding error correction failed: {}", e))?
+    } else {
+        final_delta
+    };
 
     // Write output
     if !quiet {
@@ -261,8 +802,14 @@ fn handle_encode(
         );
     }
 
-    fs::write(output_path, &final_delta)
+    let output_file = fs::File::create(&output_path)
+        .with_context(|| format!("Failed to create output file: {}", output_path.display()))?;
+    let mut writer = output_writer(output_file);
+    writer
+        .write_all(&final_delta)
+        .and_then(|()| writer.flush())
         .with_context(|| format!("Failed to write output file: {}", output_path.display()))?;
+    drop(writer);
 
     // Verify if requested
     let verify_result = if verify {
@@ -273,11 +820,8 @@ fn handle_encode(
         let verify_start = Instant::now();
 
         // Decompress if needed
-        let delta_for_verify = if compress != Compression::None {
-            decompress_if_needed(&final_delta, Some(compress), true)?.0
-        } else {
-            final_delta.clone()
-        };
+        let delta_for_verify =
+            decompress_if_needed(&final_delta, &base_data, Some(compress), true, u64::MAX)?.0;
 
         // Decode
         let reconstructed = gdelta::decode(&delta_for_verify, &base_data)
@@ -286,7 +830,7 @@ fn handle_encode(
         let verify_time = verify_start.elapsed();
 
         // Compare
-        if reconstructed != new_data {
+        if reconstructed != *new_data {
             bail!(
                 "Verification failed: reconstructed output does not match original new file\n   \
                  Expected {} bytes, got {} bytes",
@@ -323,11 +867,15 @@ fn handle_encode(
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 fn handle_decode(
     base_path: &Path,
     delta_path: &Path,
     output_path: &Path,
     format_override: Option<Compression>,
+    max_rate: Option<u64>,
+    verify_checksum_only: bool,
+    direct_io: bool,
     yes: bool,
     force: bool,
     quiet: bool,
@@ -340,8 +888,9 @@ fn handle_decode(
         bail!("File not found: {}", delta_path.display());
     }
 
-    // Check if output exists
-    if output_path.exists() && !force {
+    // Check if output exists; with --verify-checksum-only it may be up to date
+    let verify_existing = verify_checksum_only && output_path.exists();
+    if output_path.exists() && !force && !verify_existing {
         bail!(
             "Output file already exists: {}\n   Use --force to overwrite",
             output_path.display()
@@ -365,23 +914,24 @@ fn handle_decode(
         );
     }
 
-    // Memory check (estimate output size as ~base_size)
+    // Memory check
     let required = estimate_decode_memory(base_size, delta_size);
     check_memory(required, yes, quiet)?;
 
     // Read files
     if !quiet {
-        println!("{} Reading files...", "Step 1/3:".bright_cyan());
+        println!("{} Reading files...", "Step 1/2:".bright_cyan());
     }
 
-    let base_data = fs::read(base_path)
-        .with_context(|| format!("Failed to read base file: {}", base_path.display()))?;
-    let delta_data = fs::read(delta_path)
-        .with_context(|| format!("Failed to read delta file: {}", delta_path.display()))?;
+    let (base_data, delta_data) = read_inputs(
+        &open_input(base_path, "base", direct_io)?,
+        &open_input(delta_path, "delta", direct_io)?,
+        direct_io,
+    )?;
 
     // Detect or use specified compression
     let (delta_decompressed, detected_format, decompression_time) =
-        decompress_if_needed(&delta_data, format_override, quiet)?;
+        decompress_if_needed(&delta_data, &base_data, format_override, quiet, u64::MAX)?;
 
     if !quiet && detected_format != Compression::None {
         println!(
@@ -391,23 +941,76 @@ fn handle_decode(
         );
     }
 
-    // Decode
+    if verify_existing {
+        let expected = gdelta::target_checksum(&delta_decompressed)
+            .map_err(|e| anyhow::anyhow!("Invalid delta: {}", e))?;
+        let Some(expected) = expected else {
+            bail!(
+                "Delta has no target checksum to verify {} against\n   Encode with --checksum",
+                output_path.display()
+            );
+        };
+        if file_checksum(output_path)? == expected {
+            if !quiet {
+                println!();
+                println!(
+                    "{} {} already matches the target, skipped decoding",
+                    "Up to date:".bright_green().bold(),
+                    output_path.display()
+                );
+            }
+            return Ok(());
+        }
+        if !force {
+            bail!(
+                "Output file exists and does not match the target: {}\n   Use --force to overwrite",
+                output_path.display()
+            );
+        }
+        if !quiet {
+            println!(
+                "{} {} does not match the target, decoding",
+                "Info:".bright_cyan(),
+                output_path.display()
+            );
+        }
+    }
+
+    // Decode straight into a file next to the output, pre-sized to the
+    // target length, and move it into place once complete
     if !quiet {
-        println!("{} Decoding delta...", "Step 2/3:".bright_cyan());
+        println!("{} Decoding into output...", "Step 2/2:".bright_cyan());
     }
 
+    let (staged_path, output_file) = create_staged(output_path)?;
+
     let start = Instant::now();
-    let output_data = gdelta::decode(&delta_decompressed, &base_data)
-        .map_err(|e| anyhow::anyhow!("Decode failed: {}", e))?;
+    let result = match max_rate {
+        Some(rate) => gdelta::decode_to_writer(
+            &delta_decompressed,
+            &base_data,
+            gdelta::ThrottledWriter::new(output_writer(output_file), rate),
+        ),
+        None if cfg!(all(feature = "uring", target_os = "linux")) => {
+            presize_output(&output_file, &delta_decompressed, &base_data);
+            gdelta::decode_to_writer(&delta_decompressed, &base_data, output_writer(output_file))
+        }
+        None => {
+            let mut output_file = output_file;
+            gdelta::decode_to_file(&delta_decompressed, &base_data, &mut output_file)
+        }
+    };
     let decode_time = start.elapsed();
 
-    // Write output
-    if !quiet {
-        println!("{} Writing output...", "Step 3/3:".bright_cyan());
-    }
-
-    fs::write(output_path, &output_data)
-        .with_context(|| format!("Failed to write output file: {}", output_path.display()))?;
+    let output_size = match result {
+        Ok(size) => size,
+        Err(e) => {
+            // Don't leave a partially written target behind
+            let _ = fs::remove_file(&staged_path);
+            bail!("Decode failed: {}", e);
+        }
+    };
+    replace_output(&staged_path, output_path)?;
 
     // Success message
     if !quiet {
@@ -416,7 +1019,7 @@ fn handle_decode(
             "{} Created {} ({})",
             "Success:".bright_green().bold(),
             output_path.display(),
-            format_bytes(output_data.len() as u64)
+            format_bytes(output_size)
         );
         print!("   Decoding took {}", format_duration(decode_time));
         if let Some(decomp_time) = decompression_time {
@@ -429,37 +1032,1001 @@ fn handle_decode(
 }
 
 // ============================================================================
-// Memory Management
+// Delta Sets
 // ============================================================================
 
-fn estimate_encode_memory(base_size: u64, new_size: u64) -> u64 {
-    // base + new + delta (worst case = new) + 20% overhead
-    base_size + new_size + new_size + (base_size / 5)
+/// A delta of a set with the checksums of the file it applies to and the
+/// file it produces.
+struct SetDelta {
+    name: String,
+    delta: Vec<u8>,
+    base_checksum: u32,
+    target_checksum: u32,
 }
 
-fn estimate_decode_memory(base_size: u64, delta_size: u64) -> u64 {
-    // base + delta + output (estimate as base) + 20% overhead
-    base_size + delta_size + base_size + (base_size / 5)
+/// Reads every file in `dir` as a delta, in file name order.
+fn read_delta_set(dir: &Path) -> Result<Vec<SetDelta>> {
+    let mut paths = Vec::new();
+    for entry in
+        fs::read_dir(dir).with_context(|| format!("Failed to read directory: {}", dir.display()))?
+    {
+        let path = entry?.path();
+        if path.is_file() {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    let mut deltas = Vec::with_capacity(paths.len());
+    for path in paths {
+        let name = path.display().to_string();
+        let data = fs::read(&path).with_context(|| format!("Failed to read delta: {name}"))?;
+        if gdelta::compression::wrapped_id(&data) == Some(*b"zsdb") {
+            bail!(
+                "{name} is compressed with --zstd-dict, which needs its base to be read\n   \
+                 Encode deltas for a set without --zstd-dict"
+            );
+        }
+        let (delta, _, _) = decompress_if_needed(&data, &[], None, true, u64::MAX)?;
+        let invalid = |e: gdelta::GDeltaError| anyhow::anyhow!("Invalid delta {name}: {e}");
+        let base_checksum = gdelta::base_checksum(&delta).map_err(invalid)?;
+        let target_checksum = gdelta::target_checksum(&delta).map_err(invalid)?;
+        let (Some(base_checksum), Some(target_checksum)) = (base_checksum, target_checksum) else {
+            bail!("{name} does not declare its base and target\n   Encode with --checksum");
+        };
+        deltas.push(SetDelta {
+            name,
+            delta,
+            base_checksum,
+            target_checksum,
+        });
+    }
+    Ok(deltas)
 }
 
-fn check_memory(required: u64, skip_prompt: bool, quiet: bool) -> Result<()> {
-    let mut sys = System::new_all();
-    sys.refresh_memory();
+/// Orders `deltas` into the chain starting at a file with the CRC-32
+/// `start`, failing if any delta is left out or two apply to the same file.
+fn chain_deltas(mut deltas: Vec<SetDelta>, start: u32) -> Result<Vec<SetDelta>> {
+    let mut chain = Vec::with_capacity(deltas.len());
+    let mut current = start;
+    while !deltas.is_empty() {
+        let next: Vec<usize> = (0..deltas.len())
+            .filter(|&i| deltas[i].base_checksum == current)
+            .collect();
+        match next[..] {
+            [] => {
+                let left: Vec<&str> = deltas.iter().map(|d| d.name.as_str()).collect();
+                bail!(
+                    "Chain is broken: no delta applies to a file with checksum {current:08x}\n   \
+                     Not applied: {}",
+                    left.join(", ")
+                );
+            }
+            [i] => {
+                let delta = deltas.remove(i);
+                current = delta.target_checksum;
+                chain.push(delta);
+            }
+            [i, j, ..] => bail!(
+                "Chain is ambiguous: {} and {} both apply to a file with checksum {current:08x}",
+                deltas[i].name,
+                deltas[j].name
+            ),
+        }
+    }
+    Ok(chain)
+}
 
-    let available = sys.available_memory();
-    let total = sys.total_memory();
+#[allow(clippy::too_many_arguments)]
+fn handle_encode_dir(
+    base_dir: &Path,
+    new_dir: &Path,
+    output_path: &Path,
+    exclude: &[String],
+    no_gdignore: bool,
+    metadata: bool,
+    memory_budget: Option<u64>,
+    force: bool,
+    quiet: bool,
+) -> Result<()> {
+    for dir in [base_dir, new_dir] {
+        if !dir.is_dir() {
+            bail!("Directory not found: {}", dir.display());
+        }
+    }
+    check_output_absent(output_path, force)?;
 
-    // Check if totally insufficient (even if all apps closed)
-    if required > total {
-        bail!(
-            "Insufficient memory\n   Required: ~{}\n   Total RAM: {}\n\n   \
-             These files cannot be processed on this system.",
-            format_bytes(required),
-            format_bytes(total)
-        );
+    let mut diff = gdelta::bundle::DiffOptions::new().ignore_file(!no_gdignore);
+    for pattern in exclude {
+        diff = diff.exclude(pattern);
+    }
+    if metadata {
+        diff = diff.metadata(gdelta::bundle::MetadataOptions::all());
+    }
+    if let Some(bytes) = memory_budget {
+        diff = diff.memory_budget(bytes);
     }
 
-    // Calculate usage percentage
+    if !quiet {
+        println!("{} Comparing directories...", "Step 1/2:".bright_cyan());
+    }
+    let start = Instant::now();
+    let bundle = gdelta::bundle::Bundle::diff_dirs_with(
+        base_dir,
+        new_dir,
+        &gdelta::EncodeOptions::new(),
+        &diff,
+        |progress| {
+            if !quiet {
+                println!(
+                    "   {}/{} {}",
+                    progress.done + 1,
+                    progress.total,
+                    progress.path
+                );
+            }
+        },
+    )?;
+    let encode_time = start.elapsed();
+
+    if !quiet {
+        println!("{} Writing bundle...", "Step 2/2:".bright_cyan());
+    }
+    let bytes = bundle.to_indexed_bytes();
+    fs::write(output_path, &bytes)
+        .with_context(|| format!("Failed to write output file: {}", output_path.display()))?;
+
+    if !quiet {
+        println!();
+        println!(
+            "{} Created {} ({}, {} changed files)",
+            "Success:".bright_green().bold(),
+            output_path.display(),
+            format_bytes(bytes.len() as u64),
+            bundle.entries.len()
+        );
+        println!("   Comparing took {}", format_duration(encode_time));
+    }
+    Ok(())
+}
+
+fn handle_decode_dir(
+    dir: &Path,
+    bundle_path: &Path,
+    tar_path: Option<&Path>,
+    force: bool,
+    quiet: bool,
+) -> Result<()> {
+    if !dir.is_dir() {
+        bail!("Directory not found: {}", dir.display());
+    }
+    if let Some(tar_path) = tar_path {
+        check_output_absent(tar_path, force)?;
+    }
+    let bytes = fs::read(bundle_path)
+        .with_context(|| format!("Failed to read bundle file: {}", bundle_path.display()))?;
+    let bundle = gdelta::bundle::Bundle::from_bytes(&bytes)
+        .map_err(|e| anyhow::anyhow!("Invalid bundle {}: {}", bundle_path.display(), e))?;
+
+    let start = Instant::now();
+    let report = |progress: gdelta::bundle::Progress<'_>| {
+        if !quiet {
+            println!(
+                "   {}/{} {}",
+                progress.done + 1,
+                progress.total,
+                progress.path
+            );
+        }
+    };
+    if let Some(tar_path) = tar_path {
+        let (staged_path, tar_file) = create_staged(tar_path)?;
+        let result = bundle.write_tar(dir, io::BufWriter::new(tar_file), report);
+        if let Err(e) = result {
+            // Don't leave a partially written archive behind
+            let _ = fs::remove_file(&staged_path);
+            bail!("Failed to decode bundle: {}", e);
+        }
+        replace_output(&staged_path, tar_path)?;
+
+        if !quiet {
+            println!();
+            println!(
+                "{} Wrote the updated {} to {} ({})",
+                "Success:".bright_green().bold(),
+                dir.display(),
+                tar_path.display(),
+                format_bytes(fs::metadata(tar_path)?.len())
+            );
+            println!("   Applying took {}", format_duration(start.elapsed()));
+        }
+        return Ok(());
+    }
+    if let Err(e) = bundle.apply_to_dir(dir, report) {
+        let log = gdelta::bundle::transaction_log(dir)?;
+        if log.exists() {
+            bail!(
+                "Failed to decode bundle: {}\n   Run `gdelta rollback {}` to restore {}",
+                e,
+                log.display(),
+                dir.display()
+            );
+        }
+        bail!("Failed to decode bundle: {}", e);
+    }
+
+    if !quiet {
+        println!();
+        println!(
+            "{} Updated {} ({} changed files)",
+            "Success:".bright_green().bold(),
+            dir.display(),
+            bundle.entries.len()
+        );
+        println!("   Applying took {}", format_duration(start.elapsed()));
+    }
+    Ok(())
+}
+
+fn handle_check_dir(dir: &Path, bundle_path: &Path) -> Result<()> {
+    let bytes = fs::read(bundle_path)
+        .with_context(|| format!("Failed to read bundle file: {}", bundle_path.display()))?;
+    let bundle = gdelta::bundle::Bundle::from_bytes(&bytes)
+        .map_err(|e| anyhow::anyhow!("Invalid bundle {}: {}", bundle_path.display(), e))?;
+    let check = bundle
+        .check_dir(dir, |_| {})
+        .map_err(|e| anyhow::anyhow!("Failed to check {}: {}", dir.display(), e))?;
+
+    let mut problems: Vec<String> = check.problems.iter().map(ToString::to_string).collect();
+    let available = available_space(dir);
+    if let Some(available) = available
+        && available < check.bytes_needed
+    {
+        problems.push(format!(
+            "The apply needs {} but only {} are free",
+            format_bytes(check.bytes_needed),
+            format_bytes(available)
+        ));
+    }
+    let ready = check.is_ready() && problems.len() == check.problems.len();
+    let entries: Vec<Value> = bundle
+        .entries
+        .iter()
+        .zip(&check.entries)
+        .map(|(entry, entry_check)| {
+            let mut value = serde_json::json!({
+                "path": entry_check.path,
+                "kind": entry_kind(entry),
+                "ok": entry_check.problem.is_none(),
+            });
+            if let Some(problem) = &entry_check.problem {
+                value["error"] = Value::from(problem.to_string());
+            }
+            value
+        })
+        .collect();
+    let report = serde_json::json!({
+        "dir": dir.display().to_string(),
+        "bundle": bundle_path.display().to_string(),
+        "ready": ready,
+        "bytes_needed": check.bytes_needed,
+        "bytes_available": available,
+        "problems": problems,
+        "entries": entries,
+    });
+    println!("{}", serde_json::to_string_pretty(&report)?);
+
+    if !ready {
+        bail!("{} is not ready for the bundle", dir.display());
+    }
+    Ok(())
+}
+
+/// Names the kind of a bundle entry for reports.
+fn entry_kind(entry: &gdelta::bundle::Entry) -> &'static str {
+    match entry {
+        gdelta::bundle::Entry::Added { .. } => "added",
+        gdelta::bundle::Entry::Patched { .. } => "patched",
+        gdelta::bundle::Entry::Removed { .. } => "removed",
+        gdelta::bundle::Entry::Symlink { .. } => "symlink",
+        gdelta::bundle::Entry::Duplicate { .. } => "duplicate",
+        gdelta::bundle::Entry::Renamed { .. } => "renamed",
+    }
+}
+
+/// Returns the free space on the disk holding `path`, if it can be found.
+fn available_space(path: &Path) -> Option<u64> {
+    let path = fs::canonicalize(path).ok()?;
+    let disks = Disks::new_with_refreshed_list();
+    disks
+        .iter()
+        .filter(|disk| path.starts_with(disk.mount_point()))
+        .max_by_key(|disk| disk.mount_point().as_os_str().len())
+        .map(sysinfo::Disk::available_space)
+}
+
+fn handle_extract(
+    bundle_path: &Path,
+    path: &str,
+    base_dir: Option<&Path>,
+    output_path: &Path,
+    force: bool,
+    quiet: bool,
+) -> Result<()> {
+    check_output_absent(output_path, force)?;
+    let mut bundle = fs::File::open(bundle_path)
+        .with_context(|| format!("Failed to open bundle file: {}", bundle_path.display()))?;
+    let index = gdelta::bundle::BundleIndex::read(&mut bundle)
+        .map_err(|e| anyhow::anyhow!("Invalid bundle {}: {}", bundle_path.display(), e))?;
+    let contents = index
+        .extract(&mut bundle, base_dir, path)
+        .map_err(|e| anyhow::anyhow!("Failed to extract {}: {}", path, e))?;
+    fs::write(output_path, &contents)
+        .with_context(|| format!("Failed to write output file: {}", output_path.display()))?;
+
+    if !quiet {
+        println!(
+            "{} Extracted {} to {} ({})",
+            "Success:".bright_green().bold(),
+            path,
+            output_path.display(),
+            format_bytes(contents.len() as u64)
+        );
+    }
+    Ok(())
+}
+
+fn handle_rollback(log: &Path, quiet: bool) -> Result<()> {
+    let outcome = gdelta::bundle::rollback(log)
+        .map_err(|e| anyhow::anyhow!("Failed to roll back {}: {}", log.display(), e))?;
+
+    if !quiet {
+        let message = match outcome {
+            gdelta::bundle::Rollback::Discarded => {
+                "Discarded the unfinished apply; the directory was unchanged"
+            }
+            gdelta::bundle::Rollback::Restored => "Restored the directory from before the apply",
+            gdelta::bundle::Rollback::Completed => {
+                "The apply had already replaced the directory; finished removing the old tree"
+            }
+        };
+        println!("{} {}", "Success:".bright_green().bold(), message);
+    }
+    Ok(())
+}
+
+fn handle_debug(
+    base_path: &Path,
+    delta_path: &Path,
+    instruction: Option<usize>,
+    offset: Option<u64>,
+    output_path: Option<&Path>,
+    force: bool,
+) -> Result<()> {
+    if let Some(output_path) = output_path {
+        check_output_absent(output_path, force)?;
+    }
+    let base_data = fs::read(base_path)
+        .with_context(|| format!("Failed to read base file: {}", base_path.display()))?;
+    let delta_data = fs::read(delta_path)
+        .with_context(|| format!("Failed to read delta file: {}", delta_path.display()))?;
+    let (delta, _, _) = decompress_if_needed(&delta_data, &base_data, None, true, u64::MAX)?;
+
+    let breakpoint = match (instruction, offset) {
+        (Some(index), _) => gdelta::Breakpoint::Instruction(index),
+        (None, Some(offset)) => gdelta::Breakpoint::TargetOffset(offset),
+        (None, None) => unreachable!("clap requires --instruction or --offset"),
+    };
+    let state = gdelta::decode_until(&delta, &base_data, breakpoint);
+    print!("{state}");
+
+    if let Some(output_path) = output_path {
+        fs::write(output_path, &state.output)
+            .with_context(|| format!("Failed to write output file: {}", output_path.display()))?;
+    }
+    Ok(())
+}
+
+fn handle_apply_set(
+    dir: &Path,
+    base_path: &Path,
+    output_path: &Path,
+    force: bool,
+    quiet: bool,
+) -> Result<()> {
+    if !dir.is_dir() {
+        bail!("Directory not found: {}", dir.display());
+    }
+    if !base_path.exists() {
+        bail!("File not found: {}", base_path.display());
+    }
+    if output_path.exists() && !force {
+        bail!(
+            "Output file already exists: {}\n   Use --force to overwrite",
+            output_path.display()
+        );
+    }
+
+    // Order the whole set before applying anything
+    if !quiet {
+        println!("{} Reading deltas...", "Step 1/2:".bright_cyan());
+    }
+    let mut data = fs::read(base_path)
+        .with_context(|| format!("Failed to read base file: {}", base_path.display()))?;
+    let deltas = read_delta_set(dir)?;
+    if deltas.is_empty() {
+        bail!("No deltas found in {}", dir.display());
+    }
+    let mut checksum = gdelta::ChecksumWriter::new(io::sink());
+    checksum.write_all(&data)?;
+    let chain = chain_deltas(deltas, checksum.checksum())?;
+
+    if !quiet {
+        println!(
+            "{} Applying {} deltas...",
+            "Step 2/2:".bright_cyan(),
+            chain.len()
+        );
+    }
+    let start = Instant::now();
+    for (i, delta) in chain.iter().enumerate() {
+        if !quiet {
+            println!(
+                "   {}. {} ({:08x} -> {:08x})",
+                i + 1,
+                delta.name,
+                delta.base_checksum,
+                delta.target_checksum
+            );
+        }
+        data = gdelta::decode(&delta.delta, &data)
+            .map_err(|e| anyhow::anyhow!("Decode of {} failed: {}", delta.name, e))?;
+    }
+    let decode_time = start.elapsed();
+
+    let (staged_path, mut output_file) = create_staged(output_path)?;
+    if let Err(e) = output_file.write_all(&data) {
+        let _ = fs::remove_file(&staged_path);
+        return Err(e)
+            .with_context(|| format!("Failed to write output file: {}", output_path.display()));
+    }
+    drop(output_file);
+    replace_output(&staged_path, output_path)?;
+
+    if !quiet {
+        println!();
+        println!(
+            "{} Created {} ({})",
+            "Success:".bright_green().bold(),
+            output_path.display(),
+            format_bytes(data.len() as u64)
+        );
+        println!("   Decoding took {}", format_duration(decode_time));
+    }
+
+    Ok(())
+}
+
+// ============================================================================
+// Daemon
+// ============================================================================
+
+/// Listens on `socket` and answers requests until killed.
+///
+/// Each connection sends requests as JSON objects, one per line, and gets
+/// one JSON line back for each: `{"ok": true, ...}` with the results, or
+/// `{"ok": false, "error": "..."}`. Connections are served concurrently.
+#[cfg(unix)]
+fn handle_serve(socket: &Path, cache_size: usize, max_output_size: u64, quiet: bool) -> Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::net::UnixStream;
+
+    // A socket left behind by a server that was killed is replaced
+    if let Ok(metadata) = fs::symlink_metadata(socket) {
+        if !metadata.file_type().is_socket() {
+            bail!("{} exists and is not a socket", socket.display());
+        }
+        if UnixStream::connect(socket).is_ok() {
+            bail!("A server is already listening on {}", socket.display());
+        }
+        fs::remove_file(socket)
+            .with_context(|| format!("Failed to remove stale socket: {}", socket.display()))?;
+    }
+    let listener = bind_private(socket)?;
+    if !quiet {
+        println!(
+            "{} Listening on {}",
+            "Info:".bright_cyan(),
+            socket.display()
+        );
+    }
+
+    let cache = Arc::new(Mutex::new(IndexCache::new(cache_size)));
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("{} {}", "Warning:".bright_yellow().bold(), e);
+                continue;
+            }
+        };
+        let cache = Arc::clone(&cache);
+        std::thread::spawn(move || {
+            let reader = io::BufReader::new(&stream);
+            serve_connection(reader, &stream, &cache, max_output_size)
+        });
+    }
+    Ok(())
+}
+
+/// Listens on a socket at `path` that only the current user can connect
+/// to.
+///
+/// Requests make the server read and overwrite files with its rights, so
+/// the socket must not get the permissions of the umask. It is bound in a
+/// new directory only the user can enter, restricted, and then moved into
+/// place, so no one else can connect in between.
+#[cfg(unix)]
+fn bind_private(path: &Path) -> Result<std::os::unix::net::UnixListener> {
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+
+    let name = path
+        .file_name()
+        .with_context(|| format!("Invalid socket path: {}", path.display()))?;
+    let private = path.with_file_name(format!(".{}.{}.tmp", name.to_string_lossy(), process::id()));
+    fs::DirBuilder::new()
+        .mode(0o700)
+        .create(&private)
+        .with_context(|| format!("Failed to create {}", private.display()))?;
+    let staged = private.join("socket");
+    let listener = std::os::unix::net::UnixListener::bind(&staged)
+        .and_then(|listener| {
+            fs::set_permissions(&staged, fs::Permissions::from_mode(0o600))?;
+            fs::rename(&staged, path)?;
+            Ok(listener)
+        })
+        .with_context(|| format!("Failed to listen on {}", path.display()));
+    let _ = fs::remove_file(&staged);
+    let _ = fs::remove_dir(&private);
+    listener
+}
+
+#[cfg(not(unix))]
+fn handle_serve(
+    _socket: &Path,
+    _cache_size: usize,
+    _max_output_size: u64,
+    _quiet: bool,
+) -> Result<()> {
+    bail!("gdelta serve needs Unix domain sockets; Windows named pipes are not supported yet")
+}
+
+/// Longest request line the server reads, in bytes.
+const MAX_REQUEST_LINE: u64 = 64 * 1024;
+
+/// Longest decompressed delta the server accepts for targets of at most
+/// `max_output_size` bytes: the whole target as literals, with room for as
+/// many bytes of instructions and a header.
+fn max_delta_size(max_output_size: u64) -> u64 {
+    max_output_size.saturating_mul(2).saturating_add(64 * 1024)
+}
+
+/// Answers the requests read from `reader` until it is closed.
+///
+/// A request line longer than [`MAX_REQUEST_LINE`] is answered with an
+/// error and ends the connection, so a client cannot make the server
+/// buffer without bound.
+fn serve_connection(
+    mut reader: impl BufRead,
+    mut writer: impl Write,
+    cache: &Mutex<IndexCache>,
+    max_output_size: u64,
+) {
+    loop {
+        let mut line = String::new();
+        match (&mut reader).take(MAX_REQUEST_LINE).read_line(&mut line) {
+            Ok(0) | Err(_) => return,
+            Ok(_) if !line.ends_with('\n') && line.len() as u64 == MAX_REQUEST_LINE => {
+                let response = serde_json::json!({
+                    "ok": false,
+                    "error": format!("Request exceeds {MAX_REQUEST_LINE} bytes"),
+                });
+                let _ = writeln!(writer, "{response}").and_then(|()| writer.flush());
+                return;
+            }
+            Ok(_) => {}
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = serde_json::from_str(&line)
+            .context("Invalid request")
+            .and_then(|request| serve_request(&request, cache, max_output_size));
+        let response = match response {
+            Ok(mut value) => {
+                value["ok"] = Value::Bool(true);
+                value
+            }
+            Err(e) => serde_json::json!({ "ok": false, "error": format!("{e:#}") }),
+        };
+        if writeln!(writer, "{response}")
+            .and_then(|()| writer.flush())
+            .is_err()
+        {
+            return;
+        }
+    }
+}
+
+/// Carries out one request of the daemon protocol.
+///
+/// Deltas are validated before the output is created, and decoding fails
+/// if the target would exceed `max_output_size` bytes: a delta of a few
+/// bytes can describe a target of any size.
+fn serve_request(
+    request: &Value,
+    cache: &Mutex<IndexCache>,
+    max_output_size: u64,
+) -> Result<Value> {
+    let path = |name: &str| {
+        request
+            .get(name)
+            .and_then(Value::as_str)
+            .map(PathBuf::from)
+            .with_context(|| format!("Missing \"{name}\""))
+    };
+    let flag = |name: &str| request.get(name).and_then(Value::as_bool).unwrap_or(false);
+
+    match request.get("op").and_then(Value::as_str) {
+        Some("ping") => Ok(serde_json::json!({ "version": env!("CARGO_PKG_VERSION") })),
+        Some("encode") => {
+            let (base, new, output) = (path("base")?, path("new")?, path("output")?);
+            check_output_absent(&output, flag("force"))?;
+            let compress = match request.get("compress").and_then(Value::as_str) {
+                None | Some("none") => Compression::None,
+                Some("zstd") => Compression::Zstd,
+                Some("lz4") => Compression::Lz4,
+                Some(other) => bail!("Unknown compression: {other}"),
+            };
+
+            let (index, cached) = cached_index(cache, &base, flag("checksum"))?;
+            let new_data = fs::read(&new)
+                .with_context(|| format!("Failed to read new file: {}", new.display()))?;
+            let delta = index
+                .encode(&new_data)
+                .map_err(|e| anyhow::anyhow!("Encode failed: {}", e))?;
+            let delta = match compress {
+                Compression::None => delta,
+                Compression::Zstd => compress_zstd(&delta)?,
+                Compression::Lz4 => compress_lz4(&delta)?,
+            };
+
+            let (staged, mut file) = create_staged(&output)?;
+            if let Err(e) = file.write_all(&delta) {
+                let _ = fs::remove_file(&staged);
+                return Err(e)
+                    .with_context(|| format!("Failed to write output file: {}", output.display()));
+            }
+            replace_output(&staged, &output)?;
+            Ok(serde_json::json!({ "size": delta.len(), "cached": cached }))
+        }
+        Some("decode") => {
+            let (base, delta, output) = (path("base")?, path("delta")?, path("output")?);
+            check_output_absent(&output, flag("force"))?;
+            let base_data = fs::read(&base)
+                .with_context(|| format!("Failed to read base file: {}", base.display()))?;
+            let delta_data = fs::read(&delta)
+                .with_context(|| format!("Failed to read delta file: {}", delta.display()))?;
+            let limit = max_delta_size(max_output_size);
+            let (delta_data, _, _) =
+                decompress_if_needed(&delta_data, &base_data, None, true, limit)?;
+            let summary = gdelta::validate(&delta_data, base_data.len())
+                .map_err(|e| anyhow::anyhow!("Decode failed: {}", e))?;
+            if summary.target_size > max_output_size {
+                bail!(
+                    "Decode failed: target size {} exceeds the limit of {max_output_size}",
+                    summary.target_size
+                );
+            }
+
+            let (staged, mut file) = create_staged(&output)?;
+            let size = match gdelta::decode_to_file(&delta_data, &base_data, &mut file) {
+                Ok(size) => size,
+                Err(e) => {
+                    let _ = fs::remove_file(&staged);
+                    bail!("Decode failed: {}", e);
+                }
+            };
+            replace_output(&staged, &output)?;
+            Ok(serde_json::json!({ "size": size }))
+        }
+        Some(op) => bail!("Unknown op: {op}"),
+        None => bail!("Missing \"op\""),
+    }
+}
+
+/// Returns the index of the base at `path`, and whether it was cached,
+/// indexing the file if it is new or changed since it was indexed.
+fn cached_index(
+    cache: &Mutex<IndexCache>,
+    path: &Path,
+    checksum: bool,
+) -> Result<(Arc<gdelta::BaseIndex>, bool)> {
+    let metadata = fs::metadata(path)
+        .with_context(|| format!("Failed to read base file metadata: {}", path.display()))?;
+    let key = (path.to_path_buf(), checksum);
+    let stamp = (metadata.len(), metadata.modified().ok());
+
+    let lock = || cache.lock().unwrap_or_else(|poison| poison.into_inner());
+    if let Some(index) = lock().get(&key, stamp) {
+        return Ok((index, true));
+    }
+
+    // Index without holding the lock, so other requests go on meanwhile
+    let base =
+        fs::read(path).with_context(|| format!("Failed to read base file: {}", path.display()))?;
+    let options = gdelta::EncodeOptions::new()
+        .checksum(checksum)
+        .store_target_size(checksum)
+        .target_checksum(checksum)
+        .base_checksum(checksum);
+    let index = Arc::new(
+        gdelta::BaseIndex::new(base, &options)
+            .map_err(|e| anyhow::anyhow!("Encode failed: {}", e))?,
+    );
+    lock().insert(key, stamp, Arc::clone(&index));
+    Ok((index, false))
+}
+
+/// Size and modification time of a base file, to notice it changed.
+type FileStamp = (u64, Option<SystemTime>);
+
+/// Base indexes kept by `gdelta serve`, keyed by path and whether deltas
+/// carry checksums, evicting the least recently used.
+struct IndexCache {
+    capacity: usize,
+    /// Incremented on every use, to order the entries by recency.
+    clock: u64,
+    entries: HashMap<(PathBuf, bool), (FileStamp, Arc<gdelta::BaseIndex>, u64)>,
+}
+
+impl IndexCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            clock: 0,
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Returns the index for `key` if the file still has `stamp`.
+    fn get(&mut self, key: &(PathBuf, bool), stamp: FileStamp) -> Option<Arc<gdelta::BaseIndex>> {
+        self.clock += 1;
+        match self.entries.get_mut(key) {
+            Some((cached, index, used)) if *cached == stamp => {
+                *used = self.clock;
+                Some(Arc::clone(index))
+            }
+            _ => None,
+        }
+    }
+
+    fn insert(&mut self, key: (PathBuf, bool), stamp: FileStamp, index: Arc<gdelta::BaseIndex>) {
+        self.clock += 1;
+        if !self.entries.contains_key(&key) && self.entries.len() >= self.capacity {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, (_, _, used))| *used)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.entries.insert(key, (stamp, index, self.clock));
+    }
+}
+
+// ============================================================================
+// Benchmark Comparison
+// ============================================================================
+
+/// Averaged results of one benchmark scenario.
+#[derive(Default)]
+struct ScenarioStats {
+    /// Delta size divided by new size; lower is better.
+    ratio: f64,
+    /// Encode throughput in MB/s.
+    encode_mbps: f64,
+    /// Decode throughput in MB/s.
+    decode_mbps: f64,
+    samples: usize,
+}
+
+/// Reads a benchmark report and averages its metrics per scenario, keyed
+/// by algorithm, data format, change pattern and size.
+fn load_bench_report(path: &Path) -> Result<BTreeMap<String, ScenarioStats>> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read report: {}", path.display()))?;
+    let report: Value = serde_json::from_str(&text)
+        .with_context(|| format!("Failed to parse report: {}", path.display()))?;
+    let Some(metrics) = report["metrics"].as_array() else {
+        bail!("Not a benchmark report: {}", path.display());
+    };
+
+    let mut scenarios: BTreeMap<String, ScenarioStats> = BTreeMap::new();
+    for metric in metrics {
+        let text = |field: &str| metric[field].as_str().unwrap_or("?");
+        let number = |field: &str| metric[field].as_f64().unwrap_or(0.0);
+        // Times are in nanoseconds, so bytes per ns * 1000 is MB/s
+        let mbps = |field: &str| {
+            let ns = number(field);
+            if ns > 0.0 {
+                number("new_size") / ns * 1000.0
+            } else {
+                0.0
+            }
+        };
+
+        let key = format!(
+            "{} {} {} {}",
+            text("algorithm"),
+            text("data_format"),
+            text("change_pattern"),
+            text("cache_level")
+        );
+        let stats = scenarios.entry(key).or_default();
+        stats.ratio += number("compression_ratio");
+        stats.encode_mbps += mbps("encode_time_ns");
+        stats.decode_mbps += mbps("decode_time_ns");
+        stats.samples += 1;
+    }
+
+    for stats in scenarios.values_mut() {
+        let samples = stats.samples as f64;
+        stats.ratio /= samples;
+        stats.encode_mbps /= samples;
+        stats.decode_mbps /= samples;
+    }
+    Ok(scenarios)
+}
+
+/// Relative change from `old` to `new` in percent.
+fn percent_change(old: f64, new: f64) -> f64 {
+    if old == 0.0 {
+        if new == 0.0 { 0.0 } else { f64::INFINITY }
+    } else {
+        (new - old) / old * 100.0
+    }
+}
+
+/// Formats a change, colored green if it is a significant improvement and
+/// red if it is a significant regression.
+fn format_change(change: f64, threshold: f64, higher_is_better: bool) -> String {
+    let text = format!("{change:+.1}%");
+    if change.abs() < threshold {
+        text.dimmed().to_string()
+    } else if (change > 0.0) == higher_is_better {
+        text.bright_green().to_string()
+    } else {
+        text.bright_red().to_string()
+    }
+}
+
+fn handle_bench_compare(
+    old_path: &Path,
+    new_path: &Path,
+    threshold: f64,
+    ratio_threshold: f64,
+    all: bool,
+    fail_on_regression: bool,
+) -> Result<()> {
+    let old = load_bench_report(old_path)?;
+    let new = load_bench_report(new_path)?;
+
+    let (mut improved, mut regressed, mut unchanged) = (0, 0, 0);
+    for (key, after) in &new {
+        let Some(before) = old.get(key) else {
+            continue;
+        };
+
+        // A lower ratio and a higher throughput are improvements
+        let ratio = percent_change(before.ratio, after.ratio);
+        let encode = percent_change(before.encode_mbps, after.encode_mbps);
+        let decode = percent_change(before.decode_mbps, after.decode_mbps);
+        let changes = [
+            (-ratio, ratio_threshold),
+            (encode, threshold),
+            (decode, threshold),
+        ];
+
+        let is_regression = changes.iter().any(|&(c, t)| c <= -t);
+        let is_improvement = changes.iter().any(|&(c, t)| c >= t);
+        if is_regression {
+            regressed += 1;
+        } else if is_improvement {
+            improved += 1;
+        } else {
+            unchanged += 1;
+            if !all {
+                continue;
+            }
+        }
+
+        println!("{}", key.bold());
+        println!(
+            "   ratio   {:>10.4} -> {:>10.4}  {}",
+            before.ratio,
+            after.ratio,
+            format_change(ratio, ratio_threshold, false)
+        );
+        println!(
+            "   encode  {:>10.1} -> {:>10.1} MB/s  {}",
+            before.encode_mbps,
+            after.encode_mbps,
+            format_change(encode, threshold, true)
+        );
+        println!(
+            "   decode  {:>10.1} -> {:>10.1} MB/s  {}",
+            before.decode_mbps,
+            after.decode_mbps,
+            format_change(decode, threshold, true)
+        );
+    }
+
+    let only_old = old.keys().filter(|key| !new.contains_key(*key)).count();
+    let only_new = new.keys().filter(|key| !old.contains_key(*key)).count();
+
+    println!();
+    println!(
+        "{} {} improved, {} regressed, {} unchanged",
+        "Summary:".bright_cyan(),
+        improved.to_string().bright_green(),
+        regressed.to_string().bright_red(),
+        unchanged
+    );
+    if only_old > 0 || only_new > 0 {
+        println!("   {only_old} scenarios only in the old report, {only_new} only in the new one");
+    }
+
+    if fail_on_regression && regressed > 0 {
+        bail!("{} scenarios regressed", regressed);
+    }
+    Ok(())
+}
+
+// ============================================================================
+// Memory Management
+// ============================================================================
+
+fn estimate_encode_memory(base_size: u64, new_size: u64) -> u64 {
+    // base + new + delta (worst case = new) + 20% overhead
+    base_size + new_size + new_size + (base_size / 5)
+}
+
+fn estimate_decode_memory(base_size: u64, delta_size: u64) -> u64 {
+    // base + delta + 20% overhead; the output is written straight to disk
+    base_size + delta_size + (base_size / 5)
+}
+
+fn check_memory(required: u64, skip_prompt: bool, quiet: bool) -> Result<()> {
+    let mut sys = System::new_all();
+    sys.refresh_memory();
+
+    let available = sys.available_memory();
+    let total = sys.total_memory();
+
+    // Check if totally insufficient (even if all apps closed)
+    if required > total {
+        bail!(
+            "Insufficient memory\n   Required: ~{}\n   Total RAM: {}\n\n   \
+             These files cannot be processed on this system.",
+            format_bytes(required),
+            format_bytes(total)
+        );
+    }
+
+    // Calculate usage percentage
     let usage_pct = (required as f64 / available as f64) * 100.0;
 
     // Show status if not quiet
@@ -550,11 +2117,56 @@ fn compress_lz4(data: &[u8]) -> Result<Vec<u8>> {
     Ok(compressed)
 }
 
+/// Decompresses `data` if it is compressed, failing if the delta it holds
+/// is longer than `limit` bytes.
+///
+/// The limit is enforced while decompressing, so a small compressed delta
+/// cannot expand without bound in memory.
 fn decompress_if_needed(
     data: &[u8],
+    base: &[u8],
     format_override: Option<Compression>,
     quiet: bool,
+    limit: u64,
 ) -> Result<(Vec<u8>, Compression, Option<std::time::Duration>)> {
+    // Strip error correction first, repairing damage on the way
+    let recovered = gdelta::fec::recover(data)
+        .map_err(|e| anyhow::anyhow!("Error correction failed: {}", e))?;
+    let data = &*recovered;
+
+    // Deltas wrapped by the library record their codec
+    if let Some(id) = gdelta::compression::wrapped_id(data) {
+        let format = match &id {
+            b"zstd" | b"zsdb" => Compression::Zstd,
+            b"lz4f" => Compression::Lz4,
+            _ => bail!(
+                "Delta is compressed with an unknown codec: {}",
+                String::from_utf8_lossy(&id)
+            ),
+        };
+        if !quiet {
+            println!(
+                "{} Decompressing (detected wrapped {:?})...",
+                "Step 1.5/3:".bright_cyan(),
+                format
+            );
+        }
+        let start = Instant::now();
+        let codecs: [&dyn PostCompressor; 3] = [
+            &ZstdCodec { limit },
+            &ZstdDictCodec::for_base(base, limit),
+            &Lz4Codec { limit },
+        ];
+        let decompressed = gdelta::compression::unwrap(data, &codecs)
+            .map_err(|e| anyhow::anyhow!("Decompression failed: {}", e))?
+            .into_owned();
+        // Split streams are decompressed separately, so check their sum
+        if decompressed.len() as u64 > limit {
+            bail!("Decompression failed: delta exceeds {limit} bytes");
+        }
+        return Ok((decompressed, format, Some(start.elapsed())));
+    }
+
     // If format is explicitly specified, use it
     if let Some(format) = format_override {
         let start = Instant::now();
@@ -564,13 +2176,13 @@ fn decompress_if_needed(
                 if !quiet {
                     println!("{} Decompressing with Zstd...", "Step 1.5/3:".bright_cyan());
                 }
-                zstd::decode_all(data).context("Zstd decompression failed")?
+                decompress_zstd(data, limit)?
             }
             Compression::Lz4 => {
                 if !quiet {
                     println!("{} Decompressing with LZ4...", "Step 1.5/3:".bright_cyan());
                 }
-                decompress_lz4(data)?
+                decompress_lz4(data, limit)?
             }
         };
         let time = start.elapsed();
@@ -589,7 +2201,7 @@ fn decompress_if_needed(
             );
         }
         let start = Instant::now();
-        let decompressed = zstd::decode_all(data).context("Zstd decompression failed")?;
+        let decompressed = decompress_zstd(data, limit)?;
         let time = start.elapsed();
         Ok((decompressed, Compression::Zstd, Some(time)))
     } else if data.starts_with(LZ4_MAGIC) {
@@ -600,7 +2212,7 @@ fn decompress_if_needed(
             );
         }
         let start = Instant::now();
-        let decompressed = decompress_lz4(data)?;
+        let decompressed = decompress_lz4(data, limit)?;
         let time = start.elapsed();
         Ok((decompressed, Compression::Lz4, Some(time)))
     } else {
@@ -609,13 +2221,564 @@ fn decompress_if_needed(
     }
 }
 
-fn decompress_lz4(data: &[u8]) -> Result<Vec<u8>> {
-    let mut decoder = lz4::Decoder::new(data).context("Failed to create LZ4 decoder")?;
+/// The `--journal` file, which gets one JSON line per operation.
+struct Journal {
+    path: PathBuf,
+    file: fs::File,
+}
+
+/// Files named by a command, with their roles.
+type JournalFiles<'a> = Vec<(&'static str, &'a Path)>;
+
+/// An operation being recorded in the journal.
+struct JournalEntry {
+    journal: Journal,
+    command: &'static str,
+    started: Instant,
+    time: u64,
+    inputs: Vec<Value>,
+    outputs: Vec<(&'static str, PathBuf)>,
+}
+
+impl Journal {
+    /// Opens `path` for appending, creating it if needed.
+    fn open(path: &Path) -> Result<Self> {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open journal: {}", path.display()))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+        })
+    }
+
+    /// Records the inputs of `command` before it runs.
+    fn start(self, command: &Commands) -> JournalEntry {
+        let (name, inputs, outputs): (_, JournalFiles, JournalFiles) = match command {
+            Commands::Encode {
+                base, new, output, ..
+            } => (
+                "encode",
+                vec![("base", base), ("new", new)],
+                output
+                    .iter()
+                    .map(|output| ("output", output.as_path()))
+                    .collect(),
+            ),
+            Commands::Decode {
+                base,
+                delta,
+                output,
+                ..
+            } => (
+                "decode",
+                vec![("base", base), ("delta", delta)],
+                vec![("output", output)],
+            ),
+            Commands::ApplySet {
+                dir, base, output, ..
+            } => (
+                "apply-set",
+                vec![("deltas", dir), ("base", base)],
+                vec![("output", output)],
+            ),
+            Commands::EncodeDir {
+                base, new, output, ..
+            } => (
+                "encode-dir",
+                vec![("base", base), ("new", new)],
+                vec![("output", output)],
+            ),
+            Commands::DecodeDir {
+                dir,
+                bundle,
+                to_tar,
+                ..
+            } => (
+                "decode-dir",
+                vec![("dir", dir), ("bundle", bundle)],
+                to_tar
+                    .iter()
+                    .map(|to_tar| ("output", to_tar.as_path()))
+                    .collect(),
+            ),
+            Commands::Extract {
+                bundle,
+                base,
+                output,
+                ..
+            } => (
+                "extract",
+                std::iter::once(("bundle", bundle.as_path()))
+                    .chain(base.iter().map(|base| ("base", base.as_path())))
+                    .collect(),
+                vec![("output", output)],
+            ),
+            Commands::Rollback { log, .. } => ("rollback", vec![("log", log)], Vec::new()),
+            Commands::Debug {
+                base,
+                delta,
+                output,
+                ..
+            } => (
+                "debug",
+                vec![("base", base), ("delta", delta)],
+                output
+                    .iter()
+                    .map(|output| ("output", output.as_path()))
+                    .collect(),
+            ),
+            Commands::BenchCompare { old, new, .. } => (
+                "bench-compare",
+                vec![("old", old), ("new", new)],
+                Vec::new(),
+            ),
+            Commands::Serve { .. } => ("serve", Vec::new(), Vec::new()),
+        };
+        let time = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        JournalEntry {
+            journal: self,
+            command: name,
+            started: Instant::now(),
+            time,
+            inputs: inputs
+                .into_iter()
+                .map(|(role, path)| describe_file(role, path, true))
+                .collect(),
+            outputs: outputs
+                .into_iter()
+                .map(|(role, path)| (role, path.to_path_buf()))
+                .collect(),
+        }
+    }
+}
+
+impl JournalEntry {
+    /// Appends the entry with the outputs and `result` of the operation.
+    fn finish(mut self, result: &Result<()>) -> Result<()> {
+        let mut entry = serde_json::json!({
+            "time": self.time,
+            "version": env!("CARGO_PKG_VERSION"),
+            "command": self.command,
+            "inputs": self.inputs,
+            "outputs": self
+                .outputs
+                .iter()
+                .map(|(role, path)| describe_file(role, path, result.is_ok()))
+                .collect::<Vec<_>>(),
+            "duration_secs": self.started.elapsed().as_secs_f64(),
+            "result": if result.is_ok() { "ok" } else { "error" },
+        });
+        if let Err(e) = result {
+            entry["error"] = Value::from(e.to_string());
+        }
+        let mut line = entry.to_string();
+        line.push('\n');
+        self.journal
+            .file
+            .write_all(line.as_bytes())
+            .with_context(|| format!("Failed to write journal: {}", self.journal.path.display()))
+    }
+}
+
+/// Describes a file for the journal, with its size and hash if `contents`
+/// is set; directories and missing files only get their path.
+fn describe_file(role: &str, path: &Path, contents: bool) -> Value {
+    let mut file = serde_json::json!({
+        "role": role,
+        "path": path.display().to_string(),
+    });
+    if contents
+        && path.is_file()
+        && let Ok(size) = fs::metadata(path).map(|metadata| metadata.len())
+        && let Ok(hash) = fs::File::open(path).and_then(gdelta::naming::ContentHash::from_reader)
+    {
+        file["size"] = Value::from(size);
+        file["blake3"] = Value::from(hash.to_string());
+    }
+    file
+}
+
+/// Returns the hidden sibling `.<name>.<suffix>` of `path`.
+fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{name}.{suffix}"))
+}
+
+/// Creates the file an output is written to before [`replace_output`]
+/// moves it into place, so the output is never left half written.
+fn create_staged(path: &Path) -> Result<(PathBuf, fs::File)> {
+    let staged = sibling_path(path, &format!("gdelta-tmp-{}", process::id()));
+    let file = fs::File::create(&staged)
+        .with_context(|| format!("Failed to create output file: {}", staged.display()))?;
+    Ok((staged, file))
+}
+
+/// Moves the complete `staged` file over `path`, keeping the permissions of
+/// the file it replaces.
+///
+/// Windows refuses to replace a file that is open elsewhere or a running
+/// executable, but lets it be renamed. In that case the old file is moved
+/// aside to `.<name>.gdelta-old-<pid>` and deleted once nothing uses it,
+/// which may only happen on a later run.
+fn replace_output(staged: &Path, path: &Path) -> Result<()> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let old_prefix = format!(".{name}.gdelta-old-");
+    let result = (|| {
+        if let Ok(metadata) = fs::metadata(path) {
+            fs::set_permissions(staged, metadata.permissions())?;
+        }
+        match fs::rename(staged, path) {
+            Err(e) if cfg!(windows) && path.exists() => {
+                let old = sibling_path(path, &format!("gdelta-old-{}", process::id()));
+                fs::rename(path, &old).map_err(|_| e)?;
+                if let Err(e) = fs::rename(staged, path) {
+                    let _ = fs::rename(&old, path);
+                    return Err(e);
+                }
+                Ok(())
+            }
+            result => result,
+        }
+    })();
+    if let Err(e) = result {
+        let _ = fs::remove_file(staged);
+        return Err(e)
+            .with_context(|| format!("Failed to replace output file: {}", path.display()));
+    }
+
+    // Delete files moved aside by this and earlier runs, unless still in use
+    if !cfg!(windows) {
+        return Ok(());
+    }
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries.flatten() {
+            if entry.file_name().to_string_lossy().starts_with(&old_prefix) {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Fails if `path` exists, unless it may be overwritten.
+fn check_output_absent(path: &Path, force: bool) -> Result<()> {
+    if path.exists() && !force {
+        bail!(
+            "Output file already exists: {}\n   Use --force to overwrite",
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Returns the CRC-32 of the file at `path`, reading it in a single pass.
+fn file_checksum(path: &Path) -> Result<u32> {
+    let mut file = fs::File::open(path)
+        .with_context(|| format!("Failed to open output file: {}", path.display()))?;
+    let mut writer = gdelta::ChecksumWriter::new(io::sink());
+    io::copy(&mut file, &mut writer)
+        .with_context(|| format!("Failed to read output file: {}", path.display()))?;
+    Ok(writer.checksum())
+}
+
+fn decompress_zstd(data: &[u8], limit: u64) -> Result<Vec<u8>> {
+    let decoder = zstd::Decoder::new(data).context("Failed to create Zstd decoder")?;
+    read_limited(decoder, limit).context("Zstd decompression failed")
+}
+
+fn decompress_lz4(data: &[u8], limit: u64) -> Result<Vec<u8>> {
+    let decoder = lz4::Decoder::new(data).context("Failed to create LZ4 decoder")?;
+    read_limited(decoder, limit).context("Failed to decompress LZ4 data")
+}
+
+/// Reads `reader` to the end, failing once it produces more than `limit`
+/// bytes instead of buffering the rest.
+fn read_limited(reader: impl Read, limit: u64) -> io::Result<Vec<u8>> {
+    let mut output = Vec::new();
+    reader
+        .take(limit.saturating_add(1))
+        .read_to_end(&mut output)?;
+    if output.len() as u64 > limit {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Decompressed delta exceeds {limit} bytes"),
+        ));
+    }
+    Ok(output)
+}
+
+/// Zstd for deltas wrapped with [`gdelta::compression::wrap`].
+struct ZstdCodec {
+    /// Longest output `decompress` produces.
+    limit: u64,
+}
+
+impl PostCompressor for ZstdCodec {
+    fn id(&self) -> [u8; 4] {
+        *b"zstd"
+    }
+
+    fn compress(&self, data: &[u8]) -> gdelta::Result<Vec<u8>> {
+        compress_zstd(data).map_err(|e| gdelta::GDeltaError::Io(format!("{e:#}")))
+    }
+
+    fn decompress(&self, data: &[u8]) -> gdelta::Result<Vec<u8>> {
+        decompress_zstd(data, self.limit)
+            .map_err(|e| gdelta::GDeltaError::InvalidDelta(format!("{e:#}")))
+    }
+}
+
+/// Largest part of the base used as a zstd dictionary.
+const ZSTD_DICT_SIZE: usize = 128 * 1024;
+
+/// Zstd with the start of the base as a raw content dictionary.
+///
+/// Literal data is usually new text or records in the style of the base, so
+/// the base primes zstd for it. Deltas of small files, which are too short
+/// for zstd to learn much from themselves, benefit the most. The decoder has
+/// the base anyway, so the dictionary is not stored.
+struct ZstdDictCodec<'a> {
+    dictionary: &'a [u8],
+    /// Longest output `decompress` produces.
+    limit: u64,
+}
+
+impl<'a> ZstdDictCodec<'a> {
+    fn for_base(base: &'a [u8], limit: u64) -> Self {
+        Self {
+            dictionary: &base[..base.len().min(ZSTD_DICT_SIZE)],
+            limit,
+        }
+    }
+}
+
+impl PostCompressor for ZstdDictCodec<'_> {
+    fn id(&self) -> [u8; 4] {
+        *b"zsdb"
+    }
+
+    fn compress(&self, data: &[u8]) -> gdelta::Result<Vec<u8>> {
+        let mut encoder = zstd::Encoder::with_dictionary(Vec::new(), 3, self.dictionary)?;
+        encoder.write_all(data)?;
+        Ok(encoder.finish()?)
+    }
 
-    let mut decompressed = Vec::new();
-    io::copy(&mut decoder, &mut decompressed).context("Failed to decompress LZ4 data")?;
+    fn decompress(&self, data: &[u8]) -> gdelta::Result<Vec<u8>> {
+        zstd::Decoder::with_dictionary(data, self.dictionary)
+            .and_then(|decoder| read_limited(decoder, self.limit))
+            .map_err(|e| gdelta::GDeltaError::InvalidDelta(e.to_string()))
+    }
+}
 
-    Ok(decompressed)
+/// LZ4 frames for deltas wrapped with [`gdelta::compression::wrap`].
+struct Lz4Codec {
+    /// Longest output `decompress` produces.
+    limit: u64,
+}
+
+impl PostCompressor for Lz4Codec {
+    fn id(&self) -> [u8; 4] {
+        *b"lz4f"
+    }
+
+    fn compress(&self, data: &[u8]) -> gdelta::Result<Vec<u8>> {
+        compress_lz4(data).map_err(|e| gdelta::GDeltaError::Io(format!("{e:#}")))
+    }
+
+    fn decompress(&self, data: &[u8]) -> gdelta::Result<Vec<u8>> {
+        decompress_lz4(data, self.limit)
+            .map_err(|e| gdelta::GDeltaError::InvalidDelta(format!("{e:#}")))
+    }
+}
+
+// ============================================================================
+// File I/O
+// ============================================================================
+
+/// Alignment of buffers, file offsets and lengths for direct I/O.
+const DIRECT_IO_ALIGN: usize = 4096;
+
+/// Opens `path`, naming it as the `name` file in errors.
+///
+/// With `direct`, the file is opened with `O_DIRECT`, so reading it does not
+/// go through the page cache.
+fn open_input(path: &Path, name: &str, direct: bool) -> Result<fs::File> {
+    let mut options = fs::OpenOptions::new();
+    options.read(true);
+    if direct {
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.custom_flags(libc::O_DIRECT);
+        }
+        #[cfg(not(target_os = "linux"))]
+        bail!("--direct-io is only supported on Linux");
+    }
+    options
+        .open(path)
+        .with_context(|| format!("Failed to read {} file: {}", name, path.display()))
+}
+
+/// The contents of an input file.
+///
+/// Direct I/O reads into an aligned part of a larger buffer, padded to whole
+/// blocks, so the data does not always start at the beginning of it.
+#[derive(Default)]
+struct Input {
+    buffer: Vec<u8>,
+    data: Range<usize>,
+    /// Where reads go: `data`, extended to whole blocks for direct I/O.
+    target: Range<usize>,
+}
+
+impl Input {
+    fn with_len(len: usize, direct: bool) -> Self {
+        if !direct {
+            return Self::from(vec![0; len]);
+        }
+        let padded = len.next_multiple_of(DIRECT_IO_ALIGN);
+        let buffer = vec![0; padded + DIRECT_IO_ALIGN];
+        let start = buffer.as_ptr().align_offset(DIRECT_IO_ALIGN);
+        Self {
+            buffer,
+            data: start..start + len,
+            target: start..start + padded,
+        }
+    }
+
+    fn read_target(&mut self) -> &mut [u8] {
+        &mut self.buffer[self.target.clone()]
+    }
+}
+
+impl From<Vec<u8>> for Input {
+    fn from(buffer: Vec<u8>) -> Self {
+        Self {
+            data: 0..buffer.len(),
+            target: 0..buffer.len(),
+            buffer,
+        }
+    }
+}
+
+impl Deref for Input {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buffer[self.data.clone()]
+    }
+}
+
+/// Reads two input files completely.
+///
+/// With the `uring` feature on Linux both are read at once through io_uring,
+/// many chunks at a time; otherwise they are read one after the other.
+/// `direct` must match how the files were opened by [`open_input`].
+fn read_inputs(first: &fs::File, second: &fs::File, direct: bool) -> Result<(Input, Input)> {
+    let files = [first, second];
+    let mut inputs = [Input::default(), Input::default()];
+    let mut regular = true;
+    for (file, input) in files.iter().zip(&mut inputs) {
+        let metadata = file
+            .metadata()
+            .context("Failed to read input file metadata")?;
+        regular &= metadata.is_file();
+        let len = usize::try_from(metadata.len()).context("Input file does not fit in memory")?;
+        *input = Input::with_len(len, direct);
+    }
+
+    // Pipes and devices have no length to read up to
+    if !regular {
+        if direct {
+            bail!("--direct-io needs regular input files");
+        }
+        for (mut file, input) in files.into_iter().zip(&mut inputs) {
+            let mut buffer = Vec::new();
+            file.read_to_end(&mut buffer)
+                .context("Failed to read input file")?;
+            *input = Input::from(buffer);
+        }
+        let [first, second] = inputs;
+        return Ok((first, second));
+    }
+
+    let expected: usize = inputs.iter().map(|input| input.len()).sum();
+    let [first_input, second_input] = &mut inputs;
+    let mut targets = [first_input.read_target(), second_input.read_target()];
+    #[cfg(all(feature = "uring", target_os = "linux"))]
+    let read = uring::read_files(&files, &mut targets).context("Failed to read input files")?;
+    #[cfg(not(all(feature = "uring", target_os = "linux")))]
+    let read = None;
+    let read = match read {
+        Some(read) => read,
+        None => files
+            .into_iter()
+            .zip(targets.iter_mut())
+            .map(|(file, target)| read_full(file, target))
+            .sum::<io::Result<usize>>()
+            .context("Failed to read input files")?,
+    };
+    if read < expected {
+        bail!("Input files changed while being read");
+    }
+
+    let [first, second] = inputs;
+    Ok((first, second))
+}
+
+/// Reads from `file` until `buf` is full or the end of the file.
+fn read_full(mut file: &fs::File, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match file.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// Reserves the target length of `delta` in `file`, like
+/// `gdelta::decode_to_file` does, for output written through another writer.
+///
+/// Only done once the delta is valid and `base` matches its base checksum;
+/// otherwise the file is left alone and decoding reports the error.
+fn presize_output(file: &fs::File, delta: &[u8], base: &[u8]) {
+    if let Ok(Some(expected)) = gdelta::base_checksum(delta) {
+        let mut writer = gdelta::ChecksumWriter::new(io::sink());
+        if writer.write_all(base).is_err() || writer.checksum() != expected {
+            return;
+        }
+    }
+    if let Ok(summary) = gdelta::validate(delta, base.len()) {
+        // Only a hint for the filesystem, so a failure is not an error
+        let _ = file.set_len(summary.target_size);
+    }
+}
+
+/// Returns a writer for the output file.
+///
+/// With the `uring` feature on Linux, writes are handed to io_uring in the
+/// background, so the file is written while the caller produces more data.
+fn output_writer(file: fs::File) -> Box<dyn Write> {
+    #[cfg(all(feature = "uring", target_os = "linux"))]
+    let file = match uring::UringWriter::new(file) {
+        Ok(writer) => return Box::new(writer),
+        // The kernel is too old or io_uring is disabled
+        Err(file) => file,
+    };
+    Box::new(file)
 }
 
 // ============================================================================
@@ -651,3 +2814,377 @@ fn format_duration(duration: std::time::Duration) -> String {
         format!("{:.3}s", duration.as_secs_f64())
     }
 }
+
+// ============================================================================
+// io_uring
+// ============================================================================
+
+/// File reads and writes through io_uring, behind the `uring` feature.
+///
+/// Inputs are read in chunks with many reads of every file in flight at
+/// once, and output chunks are written while the next one is being filled,
+/// so large-file operations no longer wait for each read and write in turn.
+#[cfg(all(feature = "uring", target_os = "linux"))]
+mod uring {
+    use io_uring::{IoUring, opcode, types};
+    use std::collections::VecDeque;
+    use std::fs::File;
+    use std::io::{self, Write};
+    use std::mem;
+    use std::os::fd::{AsRawFd, RawFd};
+
+    /// Reads or writes in flight at once.
+    const QUEUE_DEPTH: usize = 16;
+
+    /// Bytes per read or write.
+    const CHUNK_SIZE: usize = 1024 * 1024;
+
+    /// A read into or a write from memory that outlives it.
+    #[derive(Clone, Copy)]
+    struct Op {
+        write: bool,
+        fd: RawFd,
+        offset: u64,
+        ptr: *mut u8,
+        len: usize,
+        /// Identifies the buffer once the operation has finished.
+        buffer: usize,
+    }
+
+    /// A ring and the operations waiting for a free slot in it.
+    struct Queue {
+        ring: IoUring,
+        slots: Vec<Option<Op>>,
+        waiting: VecDeque<Op>,
+        in_flight: usize,
+        /// Bytes read or written so far.
+        transferred: usize,
+    }
+
+    impl Queue {
+        fn new() -> io::Result<Self> {
+            Ok(Self {
+                ring: IoUring::new(QUEUE_DEPTH as u32)?,
+                slots: vec![None; QUEUE_DEPTH],
+                waiting: VecDeque::new(),
+                in_flight: 0,
+                transferred: 0,
+            })
+        }
+
+        /// Returns true if no operation is waiting or in flight.
+        fn is_idle(&self) -> bool {
+            self.in_flight == 0 && self.waiting.is_empty()
+        }
+
+        /// Starts waiting operations while slots are free.
+        fn start(&mut self) -> io::Result<()> {
+            while self.in_flight < QUEUE_DEPTH {
+                let Some(op) = self.waiting.pop_front() else {
+                    break;
+                };
+                let slot = self
+                    .slots
+                    .iter()
+                    .position(Option::is_none)
+                    .expect("a slot is free");
+                let len = op.len as u32;
+                let entry = if op.write {
+                    opcode::Write::new(types::Fd(op.fd), op.ptr.cast_const(), len)
+                        .offset(op.offset)
+                        .build()
+                } else {
+                    opcode::Read::new(types::Fd(op.fd), op.ptr, len)
+                        .offset(op.offset)
+                        .build()
+                };
+                // SAFETY: the memory of every operation stays allocated and
+                // untouched until its completion is reaped; `Drop` waits for
+                // all operations in flight.
+                unsafe { self.ring.submission().push(&entry.user_data(slot as u64)) }
+                    .expect("the submission queue has room for every slot");
+                self.slots[slot] = Some(op);
+                self.in_flight += 1;
+            }
+            self.ring.submit()?;
+            Ok(())
+        }
+
+        /// Waits for operations to complete, adding the buffers that are
+        /// done to `finished`. Short transfers are continued, except for
+        /// reads that reached the end of the file.
+        fn complete(&mut self, finished: &mut Vec<usize>) -> io::Result<()> {
+            self.start()?;
+            if self.in_flight == 0 {
+                return Ok(());
+            }
+            self.ring.submit_and_wait(1)?;
+
+            let mut errors = Errors::default();
+            let completions: Vec<(u64, i32)> = self
+                .ring
+                .completion()
+                .map(|entry| (entry.user_data(), entry.result()))
+                .collect();
+            for (slot, result) in completions {
+                let mut op = self.slots[slot as usize]
+                    .take()
+                    .expect("completed operations are in flight");
+                self.in_flight -= 1;
+                match result {
+                    ..0 => errors.add(io::Error::from_raw_os_error(-result)),
+                    0 if op.write => errors.add(io::Error::from(io::ErrorKind::WriteZero)),
+                    0 => finished.push(op.buffer),
+                    done => {
+                        let done = done as usize;
+                        self.transferred += done;
+                        if done < op.len {
+                            op.ptr = op.ptr.wrapping_add(done);
+                            op.offset += done as u64;
+                            op.len -= done;
+                            self.waiting.push_front(op);
+                        } else {
+                            finished.push(op.buffer);
+                        }
+                    }
+                }
+            }
+            errors.into_result()?;
+            self.start()
+        }
+
+        /// Drops the waiting operations and waits for those in flight.
+        ///
+        /// Returns an error if any operation in flight failed or, for a
+        /// write, was cut short, since its remainder is dropped.
+        fn drain(&mut self) -> io::Result<()> {
+            let mut errors = Errors::default();
+            if !self.waiting.is_empty() {
+                errors.add(io::Error::other(format!(
+                    "{} queued operations were dropped",
+                    self.waiting.len()
+                )));
+            }
+            self.waiting.clear();
+            while self.in_flight > 0 {
+                if let Err(error) = self.ring.submit_and_wait(1) {
+                    errors.add(error);
+                }
+                let completions: Vec<(u64, i32)> = self
+                    .ring
+                    .completion()
+                    .map(|entry| (entry.user_data(), entry.result()))
+                    .collect();
+                for (slot, result) in completions {
+                    let op = self.slots[slot as usize]
+                        .take()
+                        .expect("completed operations are in flight");
+                    self.in_flight -= 1;
+                    match result {
+                        ..0 => errors.add(io::Error::from_raw_os_error(-result)),
+                        done if op.write && (done as usize) < op.len => {
+                            errors.add(io::Error::from(io::ErrorKind::WriteZero));
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            errors.into_result()
+        }
+    }
+
+    impl Drop for Queue {
+        fn drop(&mut self) {
+            // The kernel may still access the memory of operations in flight.
+            // Writers report failures before this, from `flush`.
+            let _ = self.drain();
+        }
+    }
+
+    /// The errors of several operations, reported as one.
+    #[derive(Default)]
+    struct Errors {
+        first: Option<io::Error>,
+        count: usize,
+    }
+
+    impl Errors {
+        fn add(&mut self, error: io::Error) {
+            self.first.get_or_insert(error);
+            self.count += 1;
+        }
+
+        /// Merges `other` into these errors.
+        fn merge(&mut self, other: io::Result<()>) {
+            if let Err(error) = other {
+                self.add(error);
+            }
+        }
+
+        fn into_result(self) -> io::Result<()> {
+            match self.first {
+                None => Ok(()),
+                Some(first) if self.count == 1 => Err(first),
+                Some(first) => Err(io::Error::new(
+                    first.kind(),
+                    format!("{first} (and {} more failed operations)", self.count - 1),
+                )),
+            }
+        }
+    }
+
+    /// Fills each target from the start of its file, with reads of all of
+    /// them in flight at once, and returns the number of bytes read. Reads
+    /// stop early at the end of a file. Returns `None` if io_uring is
+    /// unavailable.
+    pub fn read_files(files: &[&File], targets: &mut [&mut [u8]]) -> io::Result<Option<usize>> {
+        let Ok(mut queue) = Queue::new() else {
+            return Ok(None);
+        };
+        for (file, target) in files.iter().zip(targets.iter_mut()) {
+            let ptr = target.as_mut_ptr();
+            for start in (0..target.len()).step_by(CHUNK_SIZE) {
+                queue.waiting.push_back(Op {
+                    write: false,
+                    fd: file.as_raw_fd(),
+                    offset: start as u64,
+                    ptr: ptr.wrapping_add(start),
+                    len: CHUNK_SIZE.min(target.len() - start),
+                    buffer: 0,
+                });
+            }
+        }
+
+        let mut finished = Vec::new();
+        while !queue.is_idle() {
+            queue.complete(&mut finished)?;
+        }
+        Ok(Some(queue.transferred))
+    }
+
+    /// Writes a file sequentially, handing each full chunk to the kernel
+    /// and filling the next one while it is written.
+    ///
+    /// Callers flush before dropping the writer to see errors. A writer
+    /// dropped with data still queued flushes it and reports a failure on
+    /// stderr, since `Drop` cannot return it.
+    pub struct UringWriter {
+        // Declared first, so it is dropped and drained before the buffers
+        queue: Queue,
+        file: File,
+        offset: u64,
+        pending: Vec<u8>,
+        /// Buffers being written, by id.
+        writing: Vec<Option<Vec<u8>>>,
+        spare: Vec<Vec<u8>>,
+        /// An error was returned, so the output is known to be incomplete.
+        failed: bool,
+    }
+
+    impl UringWriter {
+        /// Wraps `file`, or returns it if io_uring is unavailable.
+        pub fn new(file: File) -> Result<Self, File> {
+            match Queue::new() {
+                Ok(queue) => Ok(Self {
+                    queue,
+                    file,
+                    offset: 0,
+                    pending: Vec::with_capacity(CHUNK_SIZE),
+                    writing: Vec::new(),
+                    spare: Vec::new(),
+                    failed: false,
+                }),
+                Err(_) => Err(file),
+            }
+        }
+
+        /// Waits for a completion and recycles the written buffers.
+        fn complete(&mut self) -> io::Result<()> {
+            let mut finished = Vec::new();
+            let result = self.queue.complete(&mut finished);
+            for id in finished {
+                let mut buffer = self.writing[id].take().expect("buffer is being written");
+                buffer.clear();
+                self.spare.push(buffer);
+            }
+            self.check(result)
+        }
+
+        /// On error, stops the writes in flight and marks the writer as
+        /// failed, returning every error that occurred.
+        fn check(&mut self, result: io::Result<()>) -> io::Result<()> {
+            let Err(error) = result else {
+                return Ok(());
+            };
+            self.failed = true;
+            let mut errors = Errors::default();
+            errors.add(error);
+            errors.merge(self.queue.drain());
+            errors.into_result()
+        }
+
+        /// Hands the pending chunk to the kernel.
+        fn submit_pending(&mut self) -> io::Result<()> {
+            while self.queue.in_flight + self.queue.waiting.len() >= QUEUE_DEPTH {
+                self.complete()?;
+            }
+            let next = self
+                .spare
+                .pop()
+                .unwrap_or_else(|| Vec::with_capacity(CHUNK_SIZE));
+            let mut buffer = mem::replace(&mut self.pending, next);
+            let id = match self.writing.iter().position(Option::is_none) {
+                Some(id) => id,
+                None => {
+                    self.writing.push(None);
+                    self.writing.len() - 1
+                }
+            };
+            self.queue.waiting.push_back(Op {
+                write: true,
+                fd: self.file.as_raw_fd(),
+                offset: self.offset,
+                ptr: buffer.as_mut_ptr(),
+                len: buffer.len(),
+                buffer: id,
+            });
+            self.offset += buffer.len() as u64;
+            // Moving the buffer does not move the memory it owns
+            self.writing[id] = Some(buffer);
+            let result = self.queue.start();
+            self.check(result)
+        }
+    }
+
+    impl Drop for UringWriter {
+        fn drop(&mut self) {
+            if self.failed || (self.pending.is_empty() && self.queue.is_idle()) {
+                return;
+            }
+            if let Err(error) = self.flush() {
+                eprintln!("Warning: writing the output file failed: {error}");
+            }
+        }
+    }
+
+    impl Write for UringWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let n = buf.len().min(CHUNK_SIZE - self.pending.len());
+            self.pending.extend_from_slice(&buf[..n]);
+            if self.pending.len() == CHUNK_SIZE {
+                self.submit_pending()?;
+            }
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            if !self.pending.is_empty() {
+                self.submit_pending()?;
+            }
+            while !self.queue.is_idle() {
+                self.complete()?;
+            }
+            Ok(())
+        }
+    }
+}
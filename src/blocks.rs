@@ -0,0 +1,200 @@
+//! Fixed-block deltas for page-structured files.
+//!
+//! Databases, disk images and similar files are updated in place: a write
+//! rewrites a few pages and leaves every other page where it was.
+//! [`BlockDiff`] compares two versions of such a file block by block and
+//! encodes each changed block against the block at the same index of the old
+//! version only. Unchanged blocks cost nothing, blocks are independent of
+//! each other, and [`BlockDiff::apply`] patches the old version in place.
+//!
+//! Data that moves between blocks is not found; use [`crate::encode`] for
+//! files that are rewritten rather than updated.
+//!
+//! # Examples
+//!
+//! ```
+//! use gdelta::EncodeOptions;
+//! use gdelta::blocks::BlockDiff;
+//!
+//! let old = vec![0u8; 16 * 1024];
+//! let mut new = old.clone();
+//! new[5000..5004].copy_from_slice(b"page");
+//!
+//! let diff = BlockDiff::new(&new, &old, 4096, &EncodeOptions::new())?;
+//! assert_eq!(diff.blocks.len(), 1);
+//! assert_eq!(diff.blocks[0].index, 1);
+//!
+//! let mut restored = old.clone();
+//! diff.apply(&mut restored)?;
+//! assert_eq!(restored, new);
+//! # Ok::<(), gdelta::GDeltaError>(())
+//! ```
+
+use crate::error::{GDeltaError, Result};
+use crate::options::EncodeOptions;
+
+/// A changed block and the delta from its old contents.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockDelta {
+    /// Index of the block; it starts at `index * block_size`.
+    pub index: u64,
+    /// Delta from the old to the new contents of the block.
+    pub delta: Vec<u8>,
+}
+
+/// The changed blocks between two versions of a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockDiff {
+    /// Size of every block except possibly the last, in bytes.
+    pub block_size: usize,
+    /// Length of the new version.
+    pub target_len: u64,
+    /// Changed blocks, sorted by index.
+    pub blocks: Vec<BlockDelta>,
+}
+
+impl BlockDiff {
+    /// Compares `new` and `base` in blocks of `block_size` bytes and encodes
+    /// the blocks that differ with `options`.
+    ///
+    /// Blocks past the end of `base` are encoded against nothing.
+    ///
+    /// # Errors
+    ///
+    /// Returns `GDeltaError::InvalidOptions` if `block_size` is zero, and
+    /// any error from encoding a block.
+    pub fn new(
+        new: &[u8],
+        base: &[u8],
+        block_size: usize,
+        options: &EncodeOptions,
+    ) -> Result<Self> {
+        if block_size == 0 {
+            return Err(GDeltaError::InvalidOptions(
+                "Block size must not be zero".to_string(),
+            ));
+        }
+        let mut blocks = Vec::new();
+        for (index, block) in new.chunks(block_size).enumerate() {
+            let base_block =
+                block_range(base.len(), block_size, index).map_or(&[][..], |range| &base[range]);
+            if block != base_block {
+                blocks.push(BlockDelta {
+                    index: index as u64,
+                    delta: crate::encode_with_options(block, base_block, options)?,
+                });
+            }
+        }
+        Ok(Self {
+            block_size,
+            target_len: new.len() as u64,
+            blocks,
+        })
+    }
+
+    /// Returns the total size of the block deltas in bytes.
+    pub fn encoded_len(&self) -> usize {
+        self.blocks.iter().map(|block| block.delta.len()).sum()
+    }
+
+    /// Turns the old version in `data` into the new one.
+    ///
+    /// Every block is decoded before `data` is modified, so on error it is
+    /// left untouched.
+    ///
+    /// # Errors
+    ///
+    /// Returns `GDeltaError::InvalidDelta` if a block index lies outside the
+    /// new version, `GDeltaError::SizeMismatch` if a block decodes to the
+    /// wrong length, and any error from decoding a block.
+    pub fn apply(&self, data: &mut Vec<u8>) -> Result<()> {
+        let target_len = usize::try_from(self.target_len).map_err(|_| {
+            GDeltaError::InvalidDelta(format!("Target length {} is too large", self.target_len))
+        })?;
+
+        let mut decoded = Vec::with_capacity(self.blocks.len());
+        for block in &self.blocks {
+            let outside = || {
+                GDeltaError::InvalidDelta(format!(
+                    "Block {} lies outside the {target_len} byte target",
+                    block.index
+                ))
+            };
+            let index = usize::try_from(block.index).map_err(|_| outside())?;
+            let target = block_range(target_len, self.block_size, index).ok_or_else(outside)?;
+            let base = block_range(data.len(), self.block_size, index)
+                .map_or(&[][..], |range| &data[range]);
+            let contents = crate::decode(&block.delta, base)?;
+            if contents.len() != target.len() {
+                return Err(GDeltaError::SizeMismatch {
+                    expected: target.len(),
+                    actual: contents.len(),
+                });
+            }
+            decoded.push((target, contents));
+        }
+
+        data.resize(target_len, 0);
+        for (range, contents) in decoded {
+            data[range].copy_from_slice(&contents);
+        }
+        Ok(())
+    }
+}
+
+/// Returns the byte range of block `index` in data of length `len`, or
+/// `None` if the block starts at or past the end.
+fn block_range(len: usize, block_size: usize, index: usize) -> Option<std::ops::Range<usize>> {
+    let start = index.checked_mul(block_size).filter(|&start| start < len)?;
+    Some(start..len.min(start.saturating_add(block_size)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_changed_blocks_only() {
+        let old: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+        let mut new = old.clone();
+        new[1_500] ^= 0xFF;
+        new[9_999] = 0;
+        new.extend_from_slice(b"appended");
+
+        let diff = BlockDiff::new(&new, &old, 1024, &EncodeOptions::new()).unwrap();
+        let indexes: Vec<u64> = diff.blocks.iter().map(|block| block.index).collect();
+        assert_eq!(indexes, [1, 9]);
+
+        let mut data = old.clone();
+        diff.apply(&mut data).unwrap();
+        assert_eq!(data, new);
+
+        // Shrinking drops the tail and patches the new last block
+        let diff = BlockDiff::new(&old[..3_000], &old, 1024, &EncodeOptions::new()).unwrap();
+        assert_eq!(diff.blocks.len(), 1);
+        let mut data = old.clone();
+        diff.apply(&mut data).unwrap();
+        assert_eq!(data, &old[..3_000]);
+    }
+
+    #[test]
+    fn test_apply_leaves_data_on_error() {
+        let old = vec![7u8; 4096];
+        let mut new = old.clone();
+        new[100] = 0;
+        let mut diff = BlockDiff::new(&new, &old, 1024, &EncodeOptions::new()).unwrap();
+        diff.blocks[0].index = 10;
+
+        let mut data = old.clone();
+        assert!(matches!(
+            diff.apply(&mut data),
+            Err(GDeltaError::InvalidDelta(_))
+        ));
+        assert_eq!(data, old);
+
+        assert!(matches!(
+            BlockDiff::new(&new, &old, 0, &EncodeOptions::new()),
+            Err(GDeltaError::InvalidOptions(_))
+        ));
+    }
+}
@@ -0,0 +1,332 @@
+//! Reading and writing bsdiff patches.
+//!
+//! This module is only available with the `bsdiff` feature. It understands
+//! the classic `BSDIFF40` format produced by `bsdiff` and `qbsdiff`, so tools
+//! that must consume legacy patches can depend on gdelta alone:
+//!
+//! ```text
+//! ["BSDIFF40"][control len: i64][diff len: i64][new size: i64]
+//! [bzip2 control block][bzip2 diff block][bzip2 extra block]
+//! ```
+//!
+//! The control block is a sequence of `(add, extra, seek)` triples. Each adds
+//! `add` diff bytes to the old data at the current position, appends `extra`
+//! bytes verbatim, then moves the old position by `seek`.
+//!
+//! # Examples
+//!
+//! ```
+//! let old = b"The quick brown fox jumps over the lazy dog";
+//! let new = b"The quick brown cat jumps over the lazy dog";
+//!
+//! let patch = gdelta::bsdiff::encode(new, old).unwrap();
+//! assert_eq!(gdelta::bsdiff::apply(&patch, old).unwrap(), new);
+//! ```
+
+use crate::buffer::BufferStream;
+use crate::delta::DeltaWriter;
+use crate::error::{GDeltaError, Result};
+use crate::header;
+use crate::varint::{read_delta_unit, read_varint};
+use bzip2::Compression;
+use bzip2::read::BzDecoder;
+use bzip2::write::BzEncoder;
+use std::io::{Read, Write};
+
+/// Magic bytes at the start of every bsdiff patch.
+pub const MAGIC: &[u8; 8] = b"BSDIFF40";
+
+/// Size of the fixed patch header in bytes.
+const HEADER_SIZE: usize = 32;
+
+/// Applies a bsdiff patch to `old`, returning the new data.
+///
+/// # Errors
+///
+/// Returns `GDeltaError::InvalidDelta` if the patch is not a valid `BSDIFF40`
+/// patch, a block fails to decompress, or the control data points outside
+/// the old or new data.
+pub fn apply(patch: &[u8], old: &[u8]) -> Result<Vec<u8>> {
+    if patch.len() < HEADER_SIZE || &patch[..8] != MAGIC {
+        return Err(GDeltaError::InvalidDelta(
+            "Not a BSDIFF40 patch".to_string(),
+        ));
+    }
+
+    let ctrl_len = header_field(patch, 8)?;
+    let diff_len = header_field(patch, 16)?;
+    let new_size = header_field(patch, 24)?;
+
+    let ctrl_end = HEADER_SIZE
+        .checked_add(ctrl_len)
+        .filter(|&end| end <= patch.len())
+        .ok_or_else(|| invalid("Control block exceeds patch size"))?;
+    let diff_end = ctrl_end
+        .checked_add(diff_len)
+        .filter(|&end| end <= patch.len())
+        .ok_or_else(|| invalid("Diff block exceeds patch size"))?;
+
+    let ctrl = decompress(&patch[HEADER_SIZE..ctrl_end])?;
+    let diff = decompress(&patch[ctrl_end..diff_end])?;
+    let extra = decompress(&patch[diff_end..])?;
+
+    let mut new = Vec::with_capacity(new_size.min(diff.len() + extra.len()));
+    let mut ctrl_pos = 0;
+    let mut diff_pos = 0;
+    let mut extra_pos = 0;
+    let mut old_pos: i64 = 0;
+
+    while new.len() < new_size {
+        let triple = ctrl
+            .get(ctrl_pos..ctrl_pos + 24)
+            .ok_or_else(|| invalid("Control block ended early"))?;
+        ctrl_pos += 24;
+        let add = non_negative(offtin(&triple[0..8]))?;
+        let copy = non_negative(offtin(&triple[8..16]))?;
+        let seek = offtin(&triple[16..24]);
+
+        if new.len() + add > new_size {
+            return Err(invalid("Diff run exceeds new size"));
+        }
+        let diff_run = diff
+            .get(diff_pos..diff_pos + add)
+            .ok_or_else(|| invalid("Diff block ended early"))?;
+        for (i, &byte) in diff_run.iter().enumerate() {
+            let old_byte = usize::try_from(old_pos + i as i64)
+                .ok()
+                .and_then(|pos| old.get(pos))
+                .copied()
+                .unwrap_or(0);
+            new.push(byte.wrapping_add(old_byte));
+        }
+        diff_pos += add;
+        old_pos += add as i64;
+
+        if new.len() + copy > new_size {
+            return Err(invalid("Extra run exceeds new size"));
+        }
+        let extra_run = extra
+            .get(extra_pos..extra_pos + copy)
+            .ok_or_else(|| invalid("Extra block ended early"))?;
+        new.extend_from_slice(extra_run);
+        extra_pos += copy;
+        old_pos = old_pos
+            .checked_add(seek)
+            .ok_or_else(|| invalid("Seek overflows"))?;
+    }
+
+    Ok(new)
+}
+
+/// Encodes `new` against `old` and writes the result as a bsdiff patch.
+///
+/// The patch is produced by gdelta's matcher, so it is generally larger than
+/// one from `bsdiff` itself, but any bsdiff-compatible tool can apply it.
+///
+/// # Errors
+///
+/// Returns `GDeltaError::BufferError` if bzip2 fails to compress a block of
+/// the patch in [`from_delta`].
+pub fn encode(new: &[u8], old: &[u8]) -> Result<Vec<u8>> {
+    // The identity delta has no instructions, so spell out the copy
+    if new == old {
+        let mut writer = DeltaWriter::new();
+        if !old.is_empty() {
+            writer.copy(0, old.len() as u64);
+        }
+        return from_delta(&writer.finish());
+    }
+    from_delta(&crate::encode(new, old)?)
+}
+
+/// Converts a gdelta delta into an equivalent bsdiff patch.
+///
+/// Copies become diff runs of zero bytes and literals become extra runs.
+///
+/// # Errors
+///
+/// Returns `GDeltaError::InvalidDelta` if `delta` is malformed, or if it is
+/// an identity delta, whose output length is only known from the base, and
+/// `GDeltaError::BufferError` if bzip2 fails to compress a block.
+pub fn from_delta(delta: &[u8]) -> Result<Vec<u8>> {
+    let (header, payload) = header::split(delta)?;
+    if header.is_some_and(|header| header.is_identity()) {
+        return Err(invalid(
+            "Identity deltas need the base; use bsdiff::encode instead",
+        ));
+    }
+    if header.is_some_and(|header| header.has_target_copies()) {
+        return Err(invalid(
+            "Copies from the target cannot be expressed in bsdiff",
+        ));
+    }
+    let mut stream = BufferStream::from_slice(payload);
+    let inst_len = usize::try_from(read_varint(&mut stream)?).unwrap_or(usize::MAX);
+    let inst_start = stream.position();
+    let inst_end = inst_start
+        .checked_add(inst_len)
+        .filter(|&end| end <= payload.len())
+        .ok_or_else(|| invalid("Instruction length exceeds delta size"))?;
+    let mut instructions = BufferStream::from_slice(&payload[inst_start..inst_end]);
+    let mut literals = BufferStream::from_slice(&payload[inst_end..]);
+
+    let mut triples: Vec<[i64; 3]> = Vec::new();
+    let mut diff = Vec::new();
+    let mut extra = Vec::new();
+    let mut old_pos: i64 = 0;
+    let mut new_size: i64 = 0;
+
+    while instructions.remaining() > 0 {
+        let unit = read_delta_unit(&mut instructions)?;
+        let length = to_i64(unit.length)?;
+        if unit.is_copy {
+            // Seek to the copy source with the previous triple (or an empty one)
+            let offset = to_i64(unit.offset)?;
+            if offset != old_pos {
+                if triples.is_empty() {
+                    triples.push([0, 0, 0]);
+                }
+                triples.last_mut().unwrap()[2] = offset - old_pos;
+            }
+            triples.push([length, 0, 0]);
+            diff.resize(diff.len() + unit.length as usize, 0);
+            old_pos = offset + length;
+        } else {
+            extra.extend_from_slice(literals.read_bytes(unit.length as usize)?);
+            match triples.last_mut() {
+                Some(last) if last[2] == 0 => last[1] += length,
+                _ => triples.push([0, length, 0]),
+            }
+        }
+        new_size += length;
+    }
+
+    let mut ctrl = Vec::with_capacity(triples.len() * 24);
+    for triple in &triples {
+        for &value in triple {
+            ctrl.extend_from_slice(&offtout(value));
+        }
+    }
+
+    let ctrl = compress(&ctrl)?;
+    let diff = compress(&diff)?;
+    let extra = compress(&extra)?;
+
+    let mut patch = Vec::with_capacity(HEADER_SIZE + ctrl.len() + diff.len() + extra.len());
+    patch.extend_from_slice(MAGIC);
+    patch.extend_from_slice(&offtout(ctrl.len() as i64));
+    patch.extend_from_slice(&offtout(diff.len() as i64));
+    patch.extend_from_slice(&offtout(new_size));
+    patch.extend_from_slice(&ctrl);
+    patch.extend_from_slice(&diff);
+    patch.extend_from_slice(&extra);
+    Ok(patch)
+}
+
+fn invalid(msg: &str) -> GDeltaError {
+    GDeltaError::InvalidDelta(msg.to_string())
+}
+
+/// Decodes bsdiff's sign-magnitude little-endian integer.
+fn offtin(bytes: &[u8]) -> i64 {
+    let mut raw = [0u8; 8];
+    raw.copy_from_slice(bytes);
+    let magnitude = (u64::from_le_bytes(raw) & !(1 << 63)) as i64;
+    if raw[7] & 0x80 != 0 {
+        -magnitude
+    } else {
+        magnitude
+    }
+}
+
+/// Encodes an integer in bsdiff's sign-magnitude little-endian form.
+fn offtout(value: i64) -> [u8; 8] {
+    let mut bytes = value.unsigned_abs().to_le_bytes();
+    if value < 0 {
+        bytes[7] |= 0x80;
+    }
+    bytes
+}
+
+fn header_field(patch: &[u8], at: usize) -> Result<usize> {
+    non_negative(offtin(&patch[at..at + 8]))
+}
+
+fn non_negative(value: i64) -> Result<usize> {
+    usize::try_from(value).map_err(|_| invalid("Negative length in patch"))
+}
+
+fn to_i64(value: u64) -> Result<i64> {
+    i64::try_from(value).map_err(|_| invalid("Length exceeds bsdiff range"))
+}
+
+fn compress(data: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = BzEncoder::new(Vec::new(), Compression::best());
+    encoder
+        .write_all(data)
+        .and_then(|()| encoder.finish())
+        .map_err(|e| GDeltaError::BufferError(format!("bzip2 compression failed: {e}")))
+}
+
+fn decompress(data: &[u8]) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    BzDecoder::new(data)
+        .read_to_end(&mut out)
+        .map_err(|e| GDeltaError::InvalidDelta(format!("bzip2 decompression failed: {e}")))?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_offt_roundtrip() {
+        for value in [0, 1, -1, 255, -256, i64::MAX, -i64::MAX] {
+            assert_eq!(offtin(&offtout(value)), value);
+        }
+        assert_eq!(offtout(-1), [1, 0, 0, 0, 0, 0, 0, 0x80]);
+    }
+
+    #[test]
+    fn test_encode_apply_roundtrip() {
+        let old: Vec<u8> = (0..=255).cycle().take(10_000).collect();
+        let mut new = old[5000..].to_vec();
+        new.extend_from_slice(b"inserted literal bytes");
+        new.extend_from_slice(&old[..5000]);
+
+        let patch = encode(&new, &old).unwrap();
+        assert_eq!(&patch[..8], MAGIC);
+        assert_eq!(apply(&patch, &old).unwrap(), new);
+    }
+
+    #[test]
+    fn test_apply_adds_diff_bytes() {
+        // One triple: add 3 diff bytes, then 2 extra bytes
+        let mut ctrl = Vec::new();
+        for value in [3, 2, 0] {
+            ctrl.extend_from_slice(&offtout(value));
+        }
+        let ctrl = compress(&ctrl).unwrap();
+        let diff = compress(&[1, 1, 1]).unwrap();
+        let extra = compress(b"!!").unwrap();
+
+        let mut patch = MAGIC.to_vec();
+        patch.extend_from_slice(&offtout(ctrl.len() as i64));
+        patch.extend_from_slice(&offtout(diff.len() as i64));
+        patch.extend_from_slice(&offtout(5));
+        patch.extend_from_slice(&ctrl);
+        patch.extend_from_slice(&diff);
+        patch.extend_from_slice(&extra);
+
+        assert_eq!(apply(&patch, b"abc").unwrap(), b"bcd!!");
+    }
+
+    #[test]
+    fn test_apply_rejects_garbage() {
+        assert!(apply(b"not a patch", b"").is_err());
+        let mut patch = MAGIC.to_vec();
+        patch.extend_from_slice(&[0xFF; 24]);
+        assert!(apply(&patch, b"").is_err());
+    }
+}
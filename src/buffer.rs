@@ -126,6 +126,27 @@ impl BufferStream {
         Ok(())
     }
 
+    /// Appends a copy of bytes already written to this buffer.
+    ///
+    /// The copy may run past the current end, in which case it repeats the
+    /// bytes from `position` onwards, like an LZ77 overlapping match.
+    pub fn copy_within(&mut self, position: usize, len: usize) -> Result<()> {
+        if position >= self.buffer.len() && len > 0 {
+            return Err(GDeltaError::UnexpectedEndOfData);
+        }
+        // Bytes written by this copy become its source once reached, so
+        // each chunk can be as long as everything appended so far
+        let mut copied = 0;
+        while copied < len {
+            let start = position + copied;
+            let chunk = (len - copied).min(self.buffer.len() - start);
+            self.buffer.extend_from_within(start..start + chunk);
+            copied += chunk;
+        }
+        self.cursor += len;
+        Ok(())
+    }
+
     /// Appends the contents of another buffer from its current cursor position.
     pub fn append_from_cursor(&mut self, other: &mut BufferStream, len: usize) -> Result<()> {
         let data = other.read_bytes(len)?;
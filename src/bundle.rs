@@ -0,0 +1,3353 @@
+//! Directory patch bundles.
+//!
+//! A bundle (conventionally saved as a `.gdpack` file) turns one directory
+//! tree into another: files only present in the new tree are stored whole,
+//! changed files as deltas against their old version, and deleted files by
+//! name. Unchanged files are left out. Every delta records the CRC-32 of the
+//! file it applies to and of the result, and the bundle ends with a CRC-32
+//! of its own bytes, so a damaged bundle or a tree it was not made for is
+//! detected before anything is written.
+//!
+//! ```text
+//! [magic: "GDPK"][version: u8]
+//! versions 1 and 2: [entry count: varint]
+//! entries, sorted by path:
+//!   [kind: u8][path length: varint][path: UTF-8, '/'-separated]
+//!   kind 0, added:   [length: varint][contents]
+//!   kind 1, patched: [base crc32: u32 LE][target crc32: u32 LE][length: varint][delta]
+//!   kind 2, removed: nothing
+//!   kind 3, symlink: [length: varint][target: UTF-8]          (version 2)
+//!   kind 4, duplicate: [source path length: varint][source path]
+//!                      [target crc32: u32 LE][hard link: u8]   (version 2)
+//!   kind 5, renamed: [old path length: varint][old path]
+//!                    [base crc32: u32 LE][target crc32: u32 LE][length: varint][delta]
+//!                                                              (version 2)
+//! version 3: [end of entries: 0xFF]
+//! versions 2 and 3:
+//! [metadata count: varint]
+//! metadata, sorted by path:
+//!   [path length: varint][path][fields: u8, bit 0 mode, bit 1 mtime, bit 2 xattrs]
+//!   mode:   [permission bits: u32 LE]
+//!   mtime:  [seconds since the Unix epoch: varint][nanoseconds: varint]
+//!   xattrs: [count: varint] then per attribute
+//!           [name length: varint][name: UTF-8][value length: varint][value]
+//! version 3 only:
+//! [index entry count: varint]
+//! index entries, sorted by path:
+//!   [path length: varint][path][entry offset: varint][entry length: varint]
+//!   [entry crc32: u32 LE]
+//! [metadata crc32, from the end of entries marker: u32 LE]
+//! [index offset: u64 LE][index crc32: u32 LE]
+//! [crc32 of all preceding bytes: u32 LE]
+//! ```
+//!
+//! By default only the contents of regular files are tracked. With
+//! [`MetadataOptions`], [`Bundle::diff_dirs_with`] also records
+//! symbolic links and the permissions, modification times and extended
+//! attributes of files, for restoring faithful copies of a tree such as a
+//! backup. Extended attributes need the `xattr` feature and Unix. Empty
+//! directories are not preserved.
+//!
+//! Files matching the [`crate::ignore`] rules in a `.gdignore` file at the
+//! root of the new tree, or added with [`DiffOptions::exclude`], are left
+//! out of both trees, so build artifacts and caches do not end up in the
+//! bundle. Applying it leaves them alone.
+//!
+//! A new or changed file with the same contents as another file of the new
+//! tree is stored once: the others become references to it, restored as
+//! hard links if they were hard links in the new tree and as copies
+//! otherwise. A new file similar enough to a deleted one is stored as a
+//! delta against it, so moving files around costs little. Bundles without
+//! metadata, symbolic links, references or renames are written as version
+//! 1, which older readers understand.
+//!
+//! While a bundle is applied to a directory, a transaction log next to it
+//! records how far the apply got. If the process dies before the apply
+//! finishes, [`rollback`] reads the log and puts the original tree back.
+//!
+//! [`Bundle::to_indexed_bytes`] adds an index of the entries at the end,
+//! through which [`BundleIndex`] reads or reconstructs a single file without
+//! reading the rest of the bundle. Indexed bundles can also be written and
+//! read one entry at a time, without holding the bundle in memory, with
+//! [`BundleWriter`] and [`BundleReader`].
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use gdelta::EncodeOptions;
+//! use gdelta::bundle::Bundle;
+//! use std::path::Path;
+//!
+//! let bundle = Bundle::diff_dirs(
+//!     Path::new("assets-1.0"),
+//!     Path::new("assets-1.1"),
+//!     &EncodeOptions::new(),
+//!     |progress| println!("{}/{} {}", progress.done, progress.total, progress.path),
+//! )?;
+//! std::fs::write("update.gdpack", bundle.to_bytes())?;
+//!
+//! // On the client, turn its copy of assets-1.0 into assets-1.1
+//! let bundle = Bundle::from_bytes(&std::fs::read("update.gdpack")?)?;
+//! bundle.apply_to_dir(Path::new("game/assets"), |_| {})?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use crate::buffer::BufferStream;
+use crate::checksum::{ChecksumWriter, crc32};
+use crate::error::{GDeltaError, Result};
+use crate::ignore::{IGNORE_FILE, IgnoreRules};
+use crate::options::EncodeOptions;
+use crate::similarity::Sketch;
+use crate::tar::{Kind as TarKind, Member as TarMember, TarWriter};
+use crate::varint::{read_varint, write_varint};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Marker at the start of every bundle.
+pub const MAGIC: [u8; 4] = *b"GDPK";
+
+/// Current bundle format version, written by [`Bundle::to_indexed_bytes`].
+pub const VERSION: u8 = 3;
+
+/// Version of bundles without an index.
+const VERSION_METADATA: u8 = 2;
+
+/// Version of bundles without an index, metadata or symbolic links.
+const VERSION_CONTENTS: u8 = 1;
+
+const KIND_ADDED: u8 = 0;
+const KIND_PATCHED: u8 = 1;
+const KIND_REMOVED: u8 = 2;
+const KIND_SYMLINK: u8 = 3;
+const KIND_DUPLICATE: u8 = 4;
+const KIND_RENAMED: u8 = 5;
+/// Marks the end of the entries of an indexed bundle.
+const END_OF_ENTRIES: u8 = 0xFF;
+
+const FIELD_MODE: u8 = 1;
+const FIELD_MTIME: u8 = 2;
+const FIELD_XATTRS: u8 = 4;
+
+/// Size of the checksum trailer in bytes.
+const CHECKSUM_SIZE: usize = 4;
+
+/// Size of the index offset, index checksum and checksum at the end of an
+/// indexed bundle.
+const INDEX_TRAILER_SIZE: usize = 8 + 4 + CHECKSUM_SIZE;
+
+/// A change to a single file, identified by its `/`-separated path
+/// relative to the tree root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Entry {
+    /// The file is new, or a delta would not be smaller than its contents.
+    Added {
+        /// Path of the file.
+        path: String,
+        /// Full contents of the new file.
+        contents: Vec<u8>,
+    },
+    /// The file changed and is stored as a delta against its old version.
+    Patched {
+        /// Path of the file.
+        path: String,
+        /// CRC-32 of the old file the delta applies to.
+        base_checksum: u32,
+        /// CRC-32 of the new file.
+        target_checksum: u32,
+        /// Delta from the old to the new file.
+        delta: Vec<u8>,
+    },
+    /// The file was deleted.
+    Removed {
+        /// Path of the file.
+        path: String,
+    },
+    /// The path is a new or changed symbolic link.
+    Symlink {
+        /// Path of the link.
+        path: String,
+        /// Target of the link, as stored in the link.
+        target: String,
+    },
+    /// The file has the same contents as another file of the new tree.
+    ///
+    /// Duplicates are applied after all other entries, copies before hard
+    /// links, so `source` already holds its new contents. A hard link may
+    /// point to a copy, a copy never points to another duplicate.
+    Duplicate {
+        /// Path of the file.
+        path: String,
+        /// Path of the file with the same contents.
+        source: String,
+        /// CRC-32 of the contents.
+        checksum: u32,
+        /// Whether the file is a hard link to `source` rather than a copy.
+        hard_link: bool,
+    },
+    /// The file was moved from a deleted path, possibly with changes, and is
+    /// stored as a delta against the old file there.
+    ///
+    /// Renames are applied before all other entries, so the old file is
+    /// still present; its removal is a separate [`Entry::Removed`].
+    Renamed {
+        /// Path of the file.
+        path: String,
+        /// Path of the old file the delta applies to.
+        from: String,
+        /// CRC-32 of the old file.
+        base_checksum: u32,
+        /// CRC-32 of the new file.
+        target_checksum: u32,
+        /// Delta from the old to the new file.
+        delta: Vec<u8>,
+    },
+}
+
+impl Entry {
+    /// Returns the path of the file this entry changes.
+    pub fn path(&self) -> &str {
+        match self {
+            Entry::Added { path, .. }
+            | Entry::Patched { path, .. }
+            | Entry::Removed { path }
+            | Entry::Symlink { path, .. }
+            | Entry::Duplicate { path, .. }
+            | Entry::Renamed { path, .. } => path,
+        }
+    }
+}
+
+/// How [`Bundle::diff_dirs_with`] compares directory trees.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiffOptions {
+    metadata: MetadataOptions,
+    rename_threshold: Option<f64>,
+    excludes: Vec<String>,
+    ignore_file: bool,
+    memory_budget: Option<u64>,
+}
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        Self {
+            metadata: MetadataOptions::new(),
+            rename_threshold: Some(0.5),
+            excludes: Vec::new(),
+            ignore_file: true,
+            memory_budget: None,
+        }
+    }
+}
+
+impl DiffOptions {
+    /// Creates the default options: no metadata, renames detected at a
+    /// similarity of 0.5, and the rules of the `.gdignore` file applied.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the metadata to record.
+    #[must_use]
+    pub fn metadata(mut self, metadata: MetadataOptions) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    /// Sets how similar a new file must be to a deleted one to be stored as
+    /// a rename, from 0.0 to 1.0, as estimated by
+    /// [`crate::similarity::Sketch`]. `None` disables rename detection.
+    ///
+    /// A rename is only stored if its delta is smaller than the new file.
+    #[must_use]
+    pub fn rename_threshold(mut self, threshold: Option<f64>) -> Self {
+        self.rename_threshold = threshold;
+        self
+    }
+
+    /// Leaves out the files matching `pattern`, in the syntax of
+    /// [`crate::ignore`]. Patterns apply after the rules of the `.gdignore`
+    /// file, in the order they are added.
+    #[must_use]
+    pub fn exclude(mut self, pattern: impl Into<String>) -> Self {
+        self.excludes.push(pattern.into());
+        self
+    }
+
+    /// Sets whether to read ignore rules from the
+    /// [`IGNORE_FILE`](crate::ignore::IGNORE_FILE) at the root of the new
+    /// tree, if there is one.
+    #[must_use]
+    pub fn ignore_file(mut self, enabled: bool) -> Self {
+        self.ignore_file = enabled;
+        self
+    }
+
+    /// Limits the size of the files compared at once to about `bytes`,
+    /// counting both versions of each file.
+    ///
+    /// With the `rayon` feature, files are compared on all cores, and a file
+    /// only starts once the files in progress leave room for it; a file
+    /// larger than the budget is compared alone. Encoding needs some memory
+    /// beyond the files themselves, see [`EncodeOptions::memory_budget`].
+    /// The bundle does not depend on the budget or the number of cores.
+    #[must_use]
+    pub fn memory_budget(mut self, bytes: u64) -> Self {
+        self.memory_budget = Some(bytes);
+        self
+    }
+
+    /// Collects the ignore rules for diffing into `new`.
+    fn ignore_rules(&self, new: &Path) -> Result<IgnoreRules> {
+        let file = new.join(IGNORE_FILE);
+        let mut rules = if self.ignore_file && file.is_file() {
+            let text = fs::read_to_string(&file)?;
+            IgnoreRules::parse(&text).map_err(|error| match error {
+                GDeltaError::InvalidOptions(reason) => {
+                    GDeltaError::InvalidOptions(format!("{}: {reason}", file.display()))
+                }
+                error => error,
+            })?
+        } else {
+            IgnoreRules::new()
+        };
+        for pattern in &self.excludes {
+            rules.add(pattern)?;
+        }
+        Ok(rules)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if let Some(threshold) = self.rename_threshold
+            && !(0.0..=1.0).contains(&threshold)
+        {
+            return Err(GDeltaError::InvalidOptions(format!(
+                "Rename threshold {threshold} is not between 0 and 1"
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Which metadata [`Bundle::diff_dirs_with`] records and
+/// [`Bundle::apply_to_dir_with_metadata`] restores.
+///
+/// Everything is off by default, so bundles only carry file contents.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MetadataOptions {
+    permissions: bool,
+    mtimes: bool,
+    xattrs: bool,
+    symlinks: bool,
+}
+
+impl MetadataOptions {
+    /// Creates options that record no metadata.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates options that record all metadata.
+    pub fn all() -> Self {
+        Self {
+            permissions: true,
+            mtimes: true,
+            xattrs: true,
+            symlinks: true,
+        }
+    }
+
+    /// Records file permissions: the mode bits on Unix, only the read-only
+    /// flag elsewhere.
+    #[must_use]
+    pub fn permissions(mut self, enabled: bool) -> Self {
+        self.permissions = enabled;
+        self
+    }
+
+    /// Records file modification times.
+    #[must_use]
+    pub fn mtimes(mut self, enabled: bool) -> Self {
+        self.mtimes = enabled;
+        self
+    }
+
+    /// Records extended attributes. This needs the `xattr` feature and has
+    /// no effect on other platforms than Unix.
+    #[must_use]
+    pub fn xattrs(mut self, enabled: bool) -> Self {
+        self.xattrs = enabled;
+        self
+    }
+
+    /// Records symbolic links instead of skipping them. Links are stored
+    /// as they are, never followed. Applying a bundle always creates the
+    /// links it contains.
+    #[must_use]
+    pub fn symlinks(mut self, enabled: bool) -> Self {
+        self.symlinks = enabled;
+        self
+    }
+
+    fn records_files(&self) -> bool {
+        self.permissions || self.mtimes || self.xattrs
+    }
+}
+
+/// Metadata of a file in the new tree. Fields that were not recorded are
+/// `None` and left alone when applying.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileMetadata {
+    /// Permission bits. On other platforms than Unix only the write bits
+    /// are meaningful: a file without any is read-only.
+    pub mode: Option<u32>,
+    /// Modification time.
+    pub mtime: Option<SystemTime>,
+    /// Extended attributes as names and values, sorted by name.
+    pub xattrs: Option<Vec<(String, Vec<u8>)>>,
+}
+
+/// Progress report passed to the callbacks of [`Bundle::diff_dirs`] and
+/// [`Bundle::apply_to_dir`] before each file is processed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress<'a> {
+    /// Path of the file about to be processed.
+    pub path: &'a str,
+    /// Number of files already processed.
+    pub done: usize,
+    /// Total number of files to process.
+    pub total: usize,
+}
+
+/// Whether a bundle can be applied to a directory, as found by
+/// [`Bundle::check_dir`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DirCheck {
+    /// Problems with the directory as a whole, such as an unfinished
+    /// earlier apply.
+    pub problems: Vec<GDeltaError>,
+    /// The result for each entry, in bundle order.
+    pub entries: Vec<EntryCheck>,
+    /// Bytes the apply writes into the staging copy: the contents of added
+    /// and changed files and of copied references. Unchanged files are hard
+    /// links and take no space where the file system supports them.
+    pub bytes_needed: u64,
+}
+
+impl DirCheck {
+    /// Returns whether nothing was found that would make the apply fail.
+    pub fn is_ready(&self) -> bool {
+        self.problems.is_empty() && self.entries.iter().all(|check| check.problem.is_none())
+    }
+}
+
+/// The result of checking one entry of a bundle; see [`DirCheck`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryCheck {
+    /// Path of the entry.
+    pub path: String,
+    /// The error applying the entry would fail with, if any.
+    pub problem: Option<GDeltaError>,
+}
+
+/// The changes that turn one directory tree into another.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Bundle {
+    /// Changed files, sorted by path.
+    pub entries: Vec<Entry>,
+    /// Metadata to restore, by path, for files whose contents or metadata
+    /// changed.
+    pub metadata: BTreeMap<String, FileMetadata>,
+}
+
+impl Bundle {
+    /// Compares the trees under `old` and `new` and collects the changes,
+    /// with the default [`DiffOptions`].
+    ///
+    /// Changed files are encoded with `options`. `progress` is called for
+    /// every file in either tree.
+    ///
+    /// # Errors
+    ///
+    /// Returns `GDeltaError::Io` if a directory cannot be read or contains a
+    /// path that is not valid UTF-8, and any error from encoding a file.
+    pub fn diff_dirs(
+        old: &Path,
+        new: &Path,
+        options: &EncodeOptions,
+        progress: impl FnMut(Progress<'_>),
+    ) -> Result<Self> {
+        Self::diff_dirs_with(old, new, options, &DiffOptions::new(), progress)
+    }
+
+    /// Like [`Self::diff_dirs`], configured by `diff`.
+    ///
+    /// Metadata is recorded for every added or patched file and for
+    /// unchanged files whose recorded metadata differs between the trees.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of [`Self::diff_dirs`], `GDeltaError::Io` if
+    /// metadata cannot be read, or a link target or attribute name is not
+    /// valid UTF-8, and `GDeltaError::InvalidOptions` if `diff` or the
+    /// `.gdignore` file is invalid.
+    pub fn diff_dirs_with(
+        old: &Path,
+        new: &Path,
+        options: &EncodeOptions,
+        diff: &DiffOptions,
+        mut progress: impl FnMut(Progress<'_>),
+    ) -> Result<Self> {
+        diff.validate()?;
+        let metadata = &diff.metadata;
+        let ignore = diff.ignore_rules(new)?;
+        let old_files = list_files(old, metadata.symlinks, &ignore)?;
+        let new_files = list_files(new, metadata.symlinks, &ignore)?;
+        let mut paths: Vec<&String> = old_files.keys().chain(new_files.keys()).collect();
+        paths.sort();
+        paths.dedup();
+
+        let cost = |path: &String| {
+            [old_files.get(path), new_files.get(path)]
+                .into_iter()
+                .map(|node| match node {
+                    Some(Node::File(file)) => fs::metadata(file).map_or(0, |file| file.len()),
+                    _ => 0,
+                })
+                .sum::<u64>()
+        };
+        let diff_one = |path: &String| {
+            diff_path(
+                path,
+                old_files.get(path),
+                new_files.get(path),
+                options,
+                metadata,
+            )
+        };
+        let diffs = diff_all(&paths, diff.memory_budget, cost, diff_one, &mut progress)?;
+
+        let mut bundle = Self::default();
+        let mut contents = Vec::new();
+        for (&path, diff) in paths.iter().zip(diffs) {
+            let entry = diff.entry.as_ref().map(|_| bundle.entries.len());
+            if let Some((len, checksum)) = diff.content {
+                contents.push(Content {
+                    path: path.clone(),
+                    len,
+                    checksum,
+                    entry,
+                });
+            }
+            if let Some(recorded) = diff.metadata {
+                bundle.metadata.insert(path.clone(), recorded);
+            }
+            bundle.entries.extend(diff.entry);
+        }
+        bundle.dedup(&contents, &new_files)?;
+        if let Some(threshold) = diff.rename_threshold {
+            bundle.detect_renames(&old_files, threshold, options)?;
+        }
+        Ok(bundle)
+    }
+
+    /// Stores added files that are similar to removed ones as deltas
+    /// against them, pairing each removed file with at most one added file.
+    fn detect_renames(
+        &mut self,
+        old_files: &BTreeMap<String, Node>,
+        threshold: f64,
+        options: &EncodeOptions,
+    ) -> Result<()> {
+        let mut removed = Vec::new();
+        for entry in &self.entries {
+            if let Entry::Removed { path } = entry
+                && let Some(Node::File(old_path)) = old_files.get(path)
+            {
+                let base = fs::read(old_path)?;
+                removed.push((path.clone(), Sketch::new(&base), Some(base)));
+            }
+        }
+        if removed.is_empty() {
+            return Ok(());
+        }
+
+        for entry in &mut self.entries {
+            let Entry::Added { path, contents } = entry else {
+                continue;
+            };
+            let sketch = Sketch::new(contents);
+            let best = removed
+                .iter()
+                .enumerate()
+                .filter(|(_, (_, _, base))| base.is_some())
+                .map(|(index, (_, old_sketch, _))| (index, sketch.similarity(old_sketch)))
+                .filter(|&(_, similarity)| similarity >= threshold)
+                .max_by(|(_, a), (_, b)| a.total_cmp(b));
+            let Some((index, _)) = best else {
+                continue;
+            };
+            let (from, _, base) = &mut removed[index];
+            let Some(old) = base.as_deref() else {
+                continue;
+            };
+            let delta = match crate::encode_with_options(contents, old, options) {
+                Ok(delta) if delta.len() < contents.len() => delta,
+                Ok(_) | Err(GDeltaError::NotBeneficial) => continue,
+                Err(error) => return Err(error),
+            };
+            *entry = Entry::Renamed {
+                path: std::mem::take(path),
+                from: from.clone(),
+                base_checksum: crc32(old),
+                target_checksum: crc32(contents),
+                delta,
+            };
+            *base = None;
+        }
+        Ok(())
+    }
+
+    /// Replaces added and patched files that have the same contents as
+    /// another file of the new tree with references to it.
+    fn dedup(&mut self, contents: &[Content], files: &BTreeMap<String, Node>) -> Result<()> {
+        let mut groups: HashMap<(usize, u32), Vec<&Content>> = HashMap::new();
+        for content in contents.iter().filter(|content| content.len > 0) {
+            groups
+                .entry((content.len, content.checksum))
+                .or_default()
+                .push(content);
+        }
+
+        for group in groups.values().filter(|group| group.len() > 1) {
+            // Prefer an unchanged file, which the bundle need not carry
+            let source = group
+                .iter()
+                .find(|content| content.entry.is_none())
+                .unwrap_or(&group[0]);
+            let path_of = |content: &Content| match files.get(&content.path) {
+                Some(Node::File(path)) => path.clone(),
+                _ => unreachable!("contents are only listed for regular files"),
+            };
+            let source_path = path_of(source);
+            let source_data = fs::read(&source_path)?;
+            // One file per inode that others can be hard links to. Detaching
+            // an unchanged file to restore its metadata would break links.
+            let can_link = |content: &Content| {
+                content.entry.is_some() || !self.metadata.contains_key(&content.path)
+            };
+            let mut linkable: Vec<(PathBuf, &Content)> = Vec::new();
+            if can_link(source) {
+                linkable.push((source_path.clone(), source));
+            }
+
+            for &content in group {
+                let Some(index) = content.entry.filter(|_| content.path != source.path) else {
+                    continue;
+                };
+                let path = path_of(content);
+                // Rule out checksum collisions
+                if fs::read(&path)? != source_data {
+                    continue;
+                }
+                let mut link = None;
+                for (linked_path, linked) in &linkable {
+                    if same_file(&path, linked_path)? {
+                        link = Some(*linked);
+                        break;
+                    }
+                }
+                let (from, hard_link) = match link {
+                    // A hard link shares the metadata of its source
+                    Some(linked) => {
+                        self.metadata.remove(&content.path);
+                        (linked, true)
+                    }
+                    None => {
+                        linkable.push((path, content));
+                        (*source, false)
+                    }
+                };
+                self.entries[index] = Entry::Duplicate {
+                    path: content.path.clone(),
+                    source: from.path.clone(),
+                    checksum: content.checksum,
+                    hard_link,
+                };
+            }
+        }
+        Ok(())
+    }
+
+    /// Serializes the bundle.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut stream = BufferStream::with_capacity(64);
+        stream.write_bytes(&MAGIC);
+        let has_links = self.entries.iter().any(|entry| {
+            matches!(
+                entry,
+                Entry::Symlink { .. } | Entry::Duplicate { .. } | Entry::Renamed { .. }
+            )
+        });
+        let version = if has_links || !self.metadata.is_empty() {
+            VERSION_METADATA
+        } else {
+            VERSION_CONTENTS
+        };
+        stream.write_u8(version);
+        write_varint(&mut stream, self.entries.len() as u64);
+        for entry in &self.entries {
+            write_entry(&mut stream, entry);
+        }
+        if version == VERSION_METADATA {
+            write_metadata_section(&mut stream, &self.metadata);
+        }
+        let mut bytes = stream.into_vec();
+        let crc = crc32(&bytes);
+        bytes.extend_from_slice(&crc.to_le_bytes());
+        bytes
+    }
+
+    /// Serializes the bundle with an index of its entries at the end, in
+    /// format version 3, like [`BundleWriter`] does.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the entries are not sorted by path or have unsafe paths,
+    /// which bundles from [`Self::diff_dirs`] and [`Self::from_bytes`]
+    /// never do.
+    pub fn to_indexed_bytes(&self) -> Vec<u8> {
+        let mut writer = BundleWriter::new(Vec::new()).expect("writing to memory cannot fail");
+        for entry in &self.entries {
+            writer
+                .add(entry)
+                .expect("bundle entries are sorted and have safe paths");
+        }
+        for (path, metadata) in &self.metadata {
+            writer
+                .set_metadata(path, metadata.clone())
+                .expect("bundle metadata has safe paths");
+        }
+        writer.finish().expect("writing to memory cannot fail")
+    }
+
+    /// Parses a serialized bundle, verifying its checksum and paths.
+    ///
+    /// # Errors
+    ///
+    /// Returns `GDeltaError::ChecksumMismatch` if the bundle is damaged and
+    /// `GDeltaError::InvalidDelta` if it is malformed, has an unsupported
+    /// version, contains a path that could escape the target directory, or
+    /// has an index that does not match its entries.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if !bytes.starts_with(&MAGIC) {
+            return Err(invalid("Not a gdelta bundle"));
+        }
+        let end = bytes
+            .len()
+            .checked_sub(CHECKSUM_SIZE)
+            .filter(|&end| end > MAGIC.len())
+            .ok_or(GDeltaError::UnexpectedEndOfData)?;
+        let (body, trailer) = bytes.split_at(end);
+        let expected = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+        let actual = crc32(body);
+        if expected != actual {
+            return Err(GDeltaError::ChecksumMismatch { expected, actual });
+        }
+
+        let mut stream = BufferStream::from_slice(body);
+        stream.set_position(MAGIC.len());
+        let version = stream.read_u8()?;
+        if !(VERSION_CONTENTS..=VERSION).contains(&version) {
+            return Err(invalid(&format!("Unsupported bundle version {version}")));
+        }
+
+        let count = match version {
+            VERSION => None,
+            _ => Some(read_varint(&mut stream)?),
+        };
+        let mut entries = Vec::new();
+        let mut index = Vec::new();
+        loop {
+            let done = match count {
+                Some(count) => entries.len() as u64 == count,
+                None => stream.peek_at(stream.position(), 1)? == [END_OF_ENTRIES],
+            };
+            if done {
+                break;
+            }
+            let offset = stream.position();
+            let entry = read_entry(&mut stream, version)?;
+            index.push(IndexEntry {
+                path: entry.path().to_string(),
+                offset: offset as u64,
+                length: (stream.position() - offset) as u64,
+                checksum: crc32(&body[offset..stream.position()]),
+            });
+            entries.push(entry);
+        }
+        let metadata_offset = stream.position();
+        if version == VERSION {
+            stream.read_u8()?;
+        }
+        let metadata = match version {
+            VERSION_CONTENTS => BTreeMap::new(),
+            _ => read_metadata_section(&mut stream)?,
+        };
+        if version == VERSION {
+            let offset = stream.position();
+            let metadata_crc = crc32(&body[metadata_offset..offset]);
+            let stored = read_index(&mut stream)?;
+            let crc = crc32(&body[offset..stream.position()]);
+            let matches = read_u64(&mut stream)? == offset as u64
+                && read_u32(&mut stream)? == crc
+                && stored == (index, metadata_crc);
+            if !matches {
+                return Err(invalid("Bundle index does not match its entries"));
+            }
+        }
+        if stream.remaining() > 0 {
+            return Err(invalid("Trailing data after bundle entries"));
+        }
+        Ok(Self { entries, metadata })
+    }
+
+    /// Applies the bundle to the tree under `dir`, replacing it atomically.
+    ///
+    /// The changes are first made in a staging copy next to `dir`, in which
+    /// unchanged files are hard links where the file system allows it. Only
+    /// once every file has been written and verified is the staging copy
+    /// swapped in with two renames; on any error before that, `dir` is left
+    /// untouched. `progress` is called for every entry. All metadata in the
+    /// bundle is restored.
+    ///
+    /// The [`transaction_log`] of `dir` records each step and is removed
+    /// once the apply has finished. If the process dies in between,
+    /// [`rollback`] restores the original tree from it.
+    ///
+    /// # Errors
+    ///
+    /// Returns `GDeltaError::ChecksumMismatch` if a file to patch differs
+    /// from the one the bundle was made for or decodes to the wrong
+    /// contents, `GDeltaError::Io` if a file to remove is missing, an
+    /// earlier apply to `dir` did not finish or the file system operations
+    /// fail, `GDeltaError::InvalidDelta` if an entry would be written
+    /// through a symbolic link, and any error from decoding.
+    pub fn apply_to_dir(&self, dir: &Path, progress: impl FnMut(Progress<'_>)) -> Result<()> {
+        self.apply_to_dir_with_metadata(dir, &MetadataOptions::all(), progress)
+    }
+
+    /// Like [`Self::apply_to_dir`], only restoring the kinds of metadata
+    /// selected by `metadata`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of [`Self::apply_to_dir`].
+    pub fn apply_to_dir_with_metadata(
+        &self,
+        dir: &Path,
+        metadata: &MetadataOptions,
+        mut progress: impl FnMut(Progress<'_>),
+    ) -> Result<()> {
+        let staging = sibling(dir, "gdpack-staging")?;
+        let backup = sibling(dir, "gdpack-old")?;
+        let log = transaction_log(dir)?;
+        if log.exists() {
+            return Err(GDeltaError::Io(format!(
+                "An earlier apply to {} did not finish; roll it back with {} first",
+                dir.display(),
+                log.display()
+            )));
+        }
+        if staging.exists() {
+            fs::remove_dir_all(&staging)?;
+        }
+        write_transaction(&log, Phase::Staging)?;
+        if let Err(error) = link_tree(dir, &staging) {
+            if staging.exists() {
+                fs::remove_dir_all(&staging)?;
+            }
+            fs::remove_file(&log)?;
+            return Err(error);
+        }
+
+        // Renames read files that other entries remove, duplicates copy
+        // files that other entries may still change, and hard links may
+        // point to copies
+        let order = |entry: &&Entry| match entry {
+            Entry::Renamed { .. } => 0,
+            Entry::Duplicate {
+                hard_link: false, ..
+            } => 2,
+            Entry::Duplicate { .. } => 3,
+            _ => 1,
+        };
+        let result = (0..4)
+            .flat_map(|pass| {
+                self.entries
+                    .iter()
+                    .filter(move |entry| order(entry) == pass)
+            })
+            .enumerate()
+            .try_for_each(|(done, entry)| {
+                progress(Progress {
+                    path: entry.path(),
+                    done,
+                    total: self.entries.len(),
+                });
+                apply_entry(entry, &staging)
+            })
+            .and_then(|()| self.restore_metadata(&staging, metadata));
+        if let Err(error) = result {
+            fs::remove_dir_all(&staging)?;
+            fs::remove_file(&log)?;
+            return Err(error);
+        }
+
+        write_transaction(&log, Phase::Swapping)?;
+        fs::rename(dir, &backup)?;
+        if let Err(error) = fs::rename(&staging, dir) {
+            fs::rename(&backup, dir)?;
+            fs::remove_dir_all(&staging)?;
+            fs::remove_file(&log)?;
+            return Err(error.into());
+        }
+        // From here on the original tree is being deleted
+        write_transaction(&log, Phase::Committed)?;
+        fs::remove_dir_all(&backup)?;
+        fs::remove_file(&log)?;
+        Ok(())
+    }
+
+    /// Checks whether applying the bundle to the tree under `dir` would
+    /// succeed, without writing anything.
+    ///
+    /// Every entry is applied in memory: the files to patch are read and
+    /// their checksums and those of the results verified, references are
+    /// checked against their sources, and files to remove must exist.
+    /// `dir` must not be in the middle of another apply, and its parent
+    /// directory, where the staging copy is made, must not be read-only.
+    /// Space is not checked, but [`DirCheck::bytes_needed`] says how much
+    /// the apply writes.
+    ///
+    /// # Errors
+    ///
+    /// Returns `GDeltaError::Io` if `dir` is not a directory or cannot be
+    /// listed. Problems with the entries are reported in the result.
+    pub fn check_dir(
+        &self,
+        dir: &Path,
+        mut progress: impl FnMut(Progress<'_>),
+    ) -> Result<DirCheck> {
+        if !dir.is_dir() {
+            return Err(GDeltaError::Io(format!(
+                "{} is not a directory",
+                dir.display()
+            )));
+        }
+        let mut check = DirCheck::default();
+        let log = transaction_log(dir)?;
+        if log.exists() {
+            check.problems.push(GDeltaError::Io(format!(
+                "An earlier apply to {} did not finish; roll it back with {} first",
+                dir.display(),
+                log.display()
+            )));
+        }
+        let parent = dir
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        if fs::metadata(parent)?.permissions().readonly() {
+            check.problems.push(GDeltaError::Io(format!(
+                "{} is read-only, so the staging copy cannot be made",
+                parent.display()
+            )));
+        }
+
+        let base = list_files(dir, true, &IgnoreRules::new())?;
+        let changed: HashMap<&str, &Entry> = self
+            .entries
+            .iter()
+            .map(|entry| (entry.path(), entry))
+            .collect();
+        let read_base = |file: &str| {
+            check_no_symlinks(dir, file)?;
+            Ok(fs::read(dir.join(file))?)
+        };
+        for (done, entry) in self.entries.iter().enumerate() {
+            progress(Progress {
+                path: entry.path(),
+                done,
+                total: self.entries.len(),
+            });
+            let result = check_no_symlinks(dir, entry.path()).and_then(|()| match entry {
+                Entry::Removed { path } => match base.get(path) {
+                    Some(_) => Ok(0),
+                    None => Err(GDeltaError::Io(format!(
+                        "{path:?} to remove is not in {}",
+                        dir.display()
+                    ))),
+                },
+                Entry::Symlink { .. } => Ok(0),
+                Entry::Duplicate {
+                    source, hard_link, ..
+                } => {
+                    if matches!(base.get(source.as_str()), Some(Node::Symlink(_))) {
+                        return Err(invalid(&format!(
+                            "Bundle duplicate source {source:?} is a symbolic link"
+                        )));
+                    }
+                    let read_source = |source: &str| {
+                        reconstruct_file(
+                            source,
+                            changed.get(source).copied(),
+                            &read_base,
+                            nested_duplicate,
+                        )
+                    };
+                    let contents =
+                        reconstruct_file(entry.path(), Some(entry), &read_base, read_source)?;
+                    Ok(if *hard_link { 0 } else { contents.len() as u64 })
+                }
+                _ => reconstruct_file(entry.path(), Some(entry), &read_base, nested_duplicate)
+                    .map(|contents| contents.len() as u64),
+            });
+            let problem = match result {
+                Ok(written) => {
+                    check.bytes_needed += written;
+                    None
+                }
+                Err(error) => Some(error),
+            };
+            check.entries.push(EntryCheck {
+                path: entry.path().to_string(),
+                problem,
+            });
+        }
+        Ok(check)
+    }
+
+    /// Writes the tree that applying the bundle to `base_dir` produces as a
+    /// tar archive to `writer`, leaving `base_dir` unchanged, and returns
+    /// the writer.
+    ///
+    /// Files are reconstructed one at a time in path order, after their
+    /// parent directories. Symbolic links and hard-linked references stay
+    /// links. Files get the metadata recorded in the bundle, unchanged files
+    /// otherwise their metadata in `base_dir` and other files mode `644`
+    /// and time zero. Owners are always root.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of [`Self::apply_to_dir`], without changing
+    /// anything, and `GDeltaError::Io` if writing fails.
+    pub fn write_tar<W: Write>(
+        &self,
+        base_dir: &Path,
+        writer: W,
+        mut progress: impl FnMut(Progress<'_>),
+    ) -> Result<W> {
+        let base = list_files(base_dir, true, &IgnoreRules::new())?;
+        let mut tree: BTreeMap<&str, Option<&Entry>> =
+            base.keys().map(|path| (path.as_str(), None)).collect();
+        for entry in &self.entries {
+            if let Entry::Removed { path } = entry {
+                if tree.remove(path.as_str()).is_none() {
+                    return Err(GDeltaError::Io(format!(
+                        "{path:?} to remove is not in {}",
+                        base_dir.display()
+                    )));
+                }
+            } else {
+                tree.insert(entry.path(), Some(entry));
+            }
+        }
+
+        let read_base = |file: &str| {
+            check_no_symlinks(base_dir, file)?;
+            Ok(fs::read(base_dir.join(file))?)
+        };
+        let read_source = |source: &str| match tree.get(source) {
+            Some(&entry) => reconstruct_file(source, entry, &read_base, nested_duplicate),
+            None => Err(invalid(&format!(
+                "Bundle duplicate source {source:?} is not in the new tree"
+            ))),
+        };
+
+        let mut tar = TarWriter::new(writer);
+        let mut dirs = BTreeSet::new();
+        let mut checksums = HashMap::new();
+        for (done, (&path, &entry)) in tree.iter().enumerate() {
+            progress(Progress {
+                path,
+                done,
+                total: tree.len(),
+            });
+            for (index, _) in path.match_indices('/') {
+                if dirs.insert(&path[..index]) {
+                    let dir = TarMember {
+                        path: &path[..index],
+                        kind: TarKind::Directory,
+                        mode: 0o755,
+                        mtime: 0,
+                        xattrs: &[],
+                    };
+                    tar.append(&dir, &[])?;
+                }
+            }
+
+            let link = match (entry, base.get(path)) {
+                (Some(Entry::Symlink { target, .. }), _) | (None, Some(Node::Symlink(target))) => {
+                    Some(TarKind::Symlink(target))
+                }
+                (
+                    Some(Entry::Duplicate {
+                        source,
+                        checksum,
+                        hard_link: true,
+                        ..
+                    }),
+                    _,
+                ) if checksums.contains_key(source.as_str()) => {
+                    let actual = checksums[source.as_str()];
+                    if actual != *checksum {
+                        return Err(GDeltaError::ChecksumMismatch {
+                            expected: *checksum,
+                            actual,
+                        });
+                    }
+                    Some(TarKind::HardLink(source))
+                }
+                _ => None,
+            };
+            let metadata = match self.metadata.get(path) {
+                Some(metadata) => metadata.clone(),
+                None if entry.is_none() && link.is_none() => {
+                    read_metadata(&base_dir.join(path), &MetadataOptions::all())?
+                }
+                None => FileMetadata::default(),
+            };
+            let mut member = TarMember {
+                path,
+                kind: TarKind::File,
+                mode: metadata.mode.unwrap_or(0o644),
+                mtime: metadata
+                    .mtime
+                    .and_then(|time| time.duration_since(SystemTime::UNIX_EPOCH).ok())
+                    .map_or(0, |since_epoch| since_epoch.as_secs()),
+                xattrs: metadata.xattrs.as_deref().unwrap_or_default(),
+            };
+            if let Some(kind) = link {
+                member.kind = kind;
+                if let TarKind::Symlink(_) = kind {
+                    member.mode = 0o777;
+                }
+                tar.append(&member, &[])?;
+            } else {
+                let contents = reconstruct_file(path, entry, &read_base, read_source)?;
+                checksums.insert(path, crc32(&contents));
+                tar.append(&member, &contents)?;
+            }
+        }
+        Ok(tar.finish()?)
+    }
+
+    /// Restores the selected metadata in the staging tree, after all
+    /// contents are in place.
+    fn restore_metadata(&self, root: &Path, options: &MetadataOptions) -> Result<()> {
+        let written: BTreeSet<&str> = self
+            .entries
+            .iter()
+            .filter(|entry| {
+                matches!(
+                    entry,
+                    Entry::Added { .. }
+                        | Entry::Patched { .. }
+                        | Entry::Renamed { .. }
+                        | Entry::Duplicate {
+                            hard_link: false,
+                            ..
+                        }
+                )
+            })
+            .map(Entry::path)
+            .collect();
+        for (path, metadata) in &self.metadata {
+            check_no_symlinks(root, path)?;
+            let file = root.join(path);
+            if fs::symlink_metadata(&file)?.is_symlink() {
+                return Err(invalid(&format!(
+                    "Bundle metadata for symbolic link {path:?}"
+                )));
+            }
+            // Unchanged files are hard links to the tree being updated
+            if !written.contains(path.as_str()) {
+                let copy = sibling(&file, "gdpack-copy")?;
+                fs::copy(&file, &copy)?;
+                fs::rename(&copy, &file)?;
+            }
+            write_file_metadata(&file, metadata, options)?;
+        }
+        Ok(())
+    }
+}
+
+/// Where an entry of an indexed bundle is stored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexEntry {
+    /// Path of the file the entry changes.
+    pub path: String,
+    /// Offset of the entry from the start of the bundle.
+    pub offset: u64,
+    /// Length of the entry in bytes.
+    pub length: u64,
+    /// CRC-32 of the entry's bytes.
+    pub checksum: u32,
+}
+
+/// The index of a bundle written by [`Bundle::to_indexed_bytes`], for
+/// reading single entries without reading the whole bundle.
+///
+/// ```no_run
+/// use gdelta::bundle::BundleIndex;
+/// use std::fs::File;
+/// use std::path::Path;
+///
+/// let mut bundle = File::open("update.gdpack")?;
+/// let index = BundleIndex::read(&mut bundle)?;
+/// let config = index.extract(&mut bundle, Some(Path::new("assets-1.0")), "config/game.toml")?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BundleIndex {
+    entries: Vec<IndexEntry>,
+    /// Where the metadata section starts, at the end of entries marker, and
+    /// ends.
+    metadata: Range<u64>,
+    metadata_checksum: u32,
+}
+
+impl BundleIndex {
+    /// Reads the index from the end of a bundle, leaving `reader` at an
+    /// unspecified position.
+    ///
+    /// # Errors
+    ///
+    /// Returns `GDeltaError::InvalidDelta` if `reader` does not hold an
+    /// indexed bundle or its index is malformed,
+    /// `GDeltaError::ChecksumMismatch` if the index is damaged, and
+    /// `GDeltaError::Io` if reading fails.
+    pub fn read<R: Read + Seek>(reader: &mut R) -> Result<Self> {
+        let len = reader.seek(SeekFrom::End(0))?;
+        let mut header = [0; MAGIC.len() + 1];
+        reader.seek(SeekFrom::Start(0))?;
+        reader.read_exact(&mut header)?;
+        if header[..MAGIC.len()] != MAGIC {
+            return Err(invalid("Not a gdelta bundle"));
+        }
+        if header[MAGIC.len()] != VERSION {
+            return Err(invalid(&format!(
+                "Bundle version {} has no index",
+                header[MAGIC.len()]
+            )));
+        }
+
+        let index_end = len
+            .checked_sub(INDEX_TRAILER_SIZE as u64)
+            .filter(|&end| end >= header.len() as u64)
+            .ok_or(GDeltaError::UnexpectedEndOfData)?;
+        let mut trailer = [0; INDEX_TRAILER_SIZE];
+        reader.seek(SeekFrom::Start(index_end))?;
+        reader.read_exact(&mut trailer)?;
+        let mut stream = BufferStream::from_slice(&trailer);
+        let offset = read_u64(&mut stream)?;
+        let expected = read_u32(&mut stream)?;
+        if offset < header.len() as u64 || offset > index_end {
+            return Err(invalid("Bundle index offset out of range"));
+        }
+
+        let mut bytes = vec![0; usize::try_from(index_end - offset).unwrap_or(usize::MAX)];
+        reader.seek(SeekFrom::Start(offset))?;
+        reader.read_exact(&mut bytes)?;
+        let actual = crc32(&bytes);
+        if expected != actual {
+            return Err(GDeltaError::ChecksumMismatch { expected, actual });
+        }
+        let mut stream = BufferStream::from_slice(&bytes);
+        let (entries, metadata_checksum) = read_index(&mut stream)?;
+        let sorted = entries.windows(2).all(|pair| pair[0].path < pair[1].path);
+        // Entries end before the end of entries marker
+        let mut metadata_start = header.len() as u64;
+        for entry in &entries {
+            match entry.offset.checked_add(entry.length) {
+                Some(end) if end < offset => metadata_start = metadata_start.max(end),
+                _ => return Err(invalid("Malformed bundle index")),
+            }
+        }
+        if stream.remaining() > 0 || !sorted {
+            return Err(invalid("Malformed bundle index"));
+        }
+        Ok(Self {
+            entries,
+            metadata: metadata_start..offset,
+            metadata_checksum,
+        })
+    }
+
+    /// Returns the index entries, sorted by path.
+    pub fn entries(&self) -> &[IndexEntry] {
+        &self.entries
+    }
+
+    /// Returns the index entry for `path`, if the bundle changes it.
+    pub fn get(&self, path: &str) -> Option<&IndexEntry> {
+        self.entries
+            .binary_search_by(|entry| entry.path.as_str().cmp(path))
+            .ok()
+            .map(|found| &self.entries[found])
+    }
+
+    /// Reads the entry for `path` from the bundle, or returns `None` if the
+    /// bundle does not change it.
+    ///
+    /// # Errors
+    ///
+    /// Returns `GDeltaError::ChecksumMismatch` if the entry is damaged,
+    /// `GDeltaError::InvalidDelta` if it is malformed, and
+    /// `GDeltaError::Io` if reading fails.
+    pub fn read_entry<R: Read + Seek>(&self, reader: &mut R, path: &str) -> Result<Option<Entry>> {
+        self.get(path)
+            .map(|found| read_indexed(reader, found))
+            .transpose()
+    }
+
+    /// Reconstructs the file at `path` of the new tree, reading only its
+    /// entry from the bundle and, if it is patched, renamed, a reference or
+    /// unchanged, the file it is based on from `base_dir`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `GDeltaError::InvalidOptions` if the file needs `base_dir`
+    /// but none is given, `GDeltaError::Io` if the bundle removes the file,
+    /// replaces it with a symbolic link or a base file cannot be read,
+    /// `GDeltaError::ChecksumMismatch` if a base file differs from the one
+    /// the bundle was made for, and the errors of [`Self::read_entry`] and
+    /// decoding.
+    pub fn extract<R: Read + Seek>(
+        &self,
+        reader: &mut R,
+        base_dir: Option<&Path>,
+        path: &str,
+    ) -> Result<Vec<u8>> {
+        let read_base = |file: &str| {
+            let base_dir = base_dir.ok_or_else(|| {
+                GDeltaError::InvalidOptions(format!("Extracting {path:?} needs the base tree"))
+            })?;
+            check_no_symlinks(base_dir, file)?;
+            Ok(fs::read(base_dir.join(file))?)
+        };
+        let entry = self.read_entry(reader, path)?;
+        reconstruct_file(path, entry.as_ref(), &read_base, |source| {
+            let entry = self.read_entry(reader, source)?;
+            reconstruct_file(source, entry.as_ref(), &read_base, nested_duplicate)
+        })
+    }
+}
+
+/// Reads the entry `found` points to.
+fn read_indexed<R: Read + Seek>(reader: &mut R, found: &IndexEntry) -> Result<Entry> {
+    let bytes = read_range(reader, found.offset..found.offset + found.length)?;
+    verify(found.checksum, &bytes)?;
+    let mut stream = BufferStream::from_slice(&bytes);
+    let entry = read_entry(&mut stream, VERSION)?;
+    if stream.remaining() > 0 || entry.path() != found.path {
+        return Err(invalid(&format!(
+            "Bundle index entry for {:?} is wrong",
+            found.path
+        )));
+    }
+    Ok(entry)
+}
+
+fn read_range<R: Read + Seek>(reader: &mut R, range: Range<u64>) -> Result<Vec<u8>> {
+    let mut bytes = vec![0; usize::try_from(range.end - range.start).unwrap_or(usize::MAX)];
+    reader.seek(SeekFrom::Start(range.start))?;
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Writes an indexed bundle one entry at a time.
+///
+/// Entries must be added sorted by path. Only the index is kept in memory
+/// until [`Self::finish`] writes it after the entries, and
+/// [`Self::add_file`] copies the contents of a file without holding them
+/// at all. The output is the same as [`Bundle::to_indexed_bytes`] for the
+/// same entries. After an error the bundle is incomplete.
+///
+/// ```
+/// use gdelta::bundle::{BundleReader, BundleWriter, Entry};
+/// use std::io::Cursor;
+///
+/// let mut writer = BundleWriter::new(Vec::new())?;
+/// writer.add_file("docs/readme.txt", 5, &b"hello"[..])?;
+/// writer.add(&Entry::Removed {
+///     path: "old.txt".to_string(),
+/// })?;
+/// let bytes = writer.finish()?;
+///
+/// let mut reader = BundleReader::new(Cursor::new(bytes))?;
+/// assert_eq!(reader.extract(None, "docs/readme.txt")?, b"hello");
+/// # Ok::<(), gdelta::GDeltaError>(())
+/// ```
+pub struct BundleWriter<W: Write> {
+    writer: ChecksumWriter<W>,
+    index: Vec<IndexEntry>,
+    metadata: BTreeMap<String, FileMetadata>,
+}
+
+impl<W: Write> BundleWriter<W> {
+    /// Starts a bundle, writing its header to `writer`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `GDeltaError::Io` if writing fails.
+    pub fn new(writer: W) -> Result<Self> {
+        let mut writer = ChecksumWriter::new(writer);
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&[VERSION])?;
+        Ok(Self {
+            writer,
+            index: Vec::new(),
+            metadata: BTreeMap::new(),
+        })
+    }
+
+    /// Appends `entry`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `GDeltaError::InvalidOptions` if the path of `entry` could
+    /// escape the target directory or does not sort after the path of the
+    /// previous entry, and `GDeltaError::Io` if writing fails.
+    pub fn add(&mut self, entry: &Entry) -> Result<()> {
+        let mut stream = BufferStream::with_capacity(64);
+        write_entry(&mut stream, entry);
+        self.write_entry(entry.path(), stream.as_slice(), io::empty(), 0)
+    }
+
+    /// Appends an [`Entry::Added`] for `path`, copying its `len` bytes of
+    /// contents from `reader`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of [`Self::add`], and `GDeltaError::Io` if
+    /// reading fails or `reader` ends before `len` bytes.
+    pub fn add_file(&mut self, path: &str, len: u64, reader: impl Read) -> Result<()> {
+        let mut head = BufferStream::with_capacity(path.len() + 16);
+        head.write_u8(KIND_ADDED);
+        write_section(&mut head, path.as_bytes());
+        write_varint(&mut head, len);
+        self.write_entry(path, head.as_slice(), reader, len)
+    }
+
+    /// Records `metadata` for the file at `path`, written by
+    /// [`Self::finish`].
+    ///
+    /// # Errors
+    ///
+    /// Returns `GDeltaError::InvalidOptions` if `path` could escape the
+    /// target directory.
+    pub fn set_metadata(&mut self, path: &str, metadata: FileMetadata) -> Result<()> {
+        check_entry_path(path)?;
+        self.metadata.insert(path.to_string(), metadata);
+        Ok(())
+    }
+
+    /// Writes the metadata, the index and the checksum after the entries
+    /// and returns the underlying writer, flushed.
+    ///
+    /// # Errors
+    ///
+    /// Returns `GDeltaError::Io` if writing fails.
+    pub fn finish(self) -> Result<W> {
+        let Self {
+            mut writer,
+            index,
+            metadata,
+        } = self;
+        let mut stream = BufferStream::with_capacity(64);
+        stream.write_u8(END_OF_ENTRIES);
+        write_metadata_section(&mut stream, &metadata);
+        let metadata_checksum = crc32(stream.as_slice());
+        let index_start = stream.len();
+        write_index(&mut stream, &index, metadata_checksum);
+        let index_checksum = crc32(&stream.as_slice()[index_start..]);
+        let index_offset = writer.written() + index_start as u64;
+        stream.write_bytes(&index_offset.to_le_bytes());
+        stream.write_bytes(&index_checksum.to_le_bytes());
+        writer.write_all(stream.as_slice())?;
+
+        let checksum = writer.checksum();
+        let mut writer = writer.into_inner();
+        writer.write_all(&checksum.to_le_bytes())?;
+        writer.flush()?;
+        Ok(writer)
+    }
+
+    /// Writes an entry made of `head` followed by `len` bytes from `reader`.
+    fn write_entry(&mut self, path: &str, head: &[u8], reader: impl Read, len: u64) -> Result<()> {
+        check_entry_path(path)?;
+        if let Some(last) = self.index.last()
+            && last.path.as_str() >= path
+        {
+            return Err(GDeltaError::InvalidOptions(format!(
+                "Bundle entry {path:?} is not sorted after {:?}",
+                last.path
+            )));
+        }
+        let offset = self.writer.written();
+        let mut entry = ChecksumWriter::new(&mut self.writer);
+        entry.write_all(head)?;
+        let copied = io::copy(&mut reader.take(len), &mut entry)?;
+        if copied != len {
+            return Err(GDeltaError::Io(format!(
+                "Contents of {path:?} ended after {copied} of {len} bytes"
+            )));
+        }
+        let checksum = entry.checksum();
+        self.index.push(IndexEntry {
+            path: path.to_string(),
+            offset,
+            length: self.writer.written() - offset,
+            checksum,
+        });
+        Ok(())
+    }
+}
+
+/// Reads entries and files from an indexed bundle one at a time, only
+/// reading the parts of the bundle they are stored in.
+///
+/// See [`BundleWriter`] for an example.
+pub struct BundleReader<R> {
+    reader: R,
+    index: BundleIndex,
+}
+
+impl<R: Read + Seek> BundleReader<R> {
+    /// Reads the index at the end of the bundle in `reader`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of [`BundleIndex::read`].
+    pub fn new(mut reader: R) -> Result<Self> {
+        let index = BundleIndex::read(&mut reader)?;
+        Ok(Self { reader, index })
+    }
+
+    /// Returns the index of the bundle.
+    pub fn index(&self) -> &BundleIndex {
+        &self.index
+    }
+
+    /// Reads the entry for `path`; see [`BundleIndex::read_entry`].
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of [`BundleIndex::read_entry`].
+    pub fn read_entry(&mut self, path: &str) -> Result<Option<Entry>> {
+        self.index.read_entry(&mut self.reader, path)
+    }
+
+    /// Reads the entries one at a time, sorted by path.
+    pub fn entries(&mut self) -> impl Iterator<Item = Result<Entry>> + '_ {
+        let Self { reader, index } = self;
+        index
+            .entries
+            .iter()
+            .map(move |found| read_indexed(reader, found))
+    }
+
+    /// Reads the metadata recorded for files of the new tree.
+    ///
+    /// # Errors
+    ///
+    /// Returns `GDeltaError::ChecksumMismatch` if the metadata is damaged,
+    /// `GDeltaError::InvalidDelta` if it is malformed, and `GDeltaError::Io`
+    /// if reading fails.
+    pub fn metadata(&mut self) -> Result<BTreeMap<String, FileMetadata>> {
+        let bytes = read_range(&mut self.reader, self.index.metadata.clone())?;
+        verify(self.index.metadata_checksum, &bytes)?;
+        let mut stream = BufferStream::from_slice(&bytes);
+        if stream.read_u8()? != END_OF_ENTRIES {
+            return Err(invalid("Bundle metadata is not after the entries"));
+        }
+        let metadata = read_metadata_section(&mut stream)?;
+        if stream.remaining() > 0 {
+            return Err(invalid("Trailing data after bundle metadata"));
+        }
+        Ok(metadata)
+    }
+
+    /// Reconstructs the file at `path` of the new tree; see
+    /// [`BundleIndex::extract`].
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of [`BundleIndex::extract`].
+    pub fn extract(&mut self, base_dir: Option<&Path>, path: &str) -> Result<Vec<u8>> {
+        self.index.extract(&mut self.reader, base_dir, path)
+    }
+
+    /// Returns the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+/// What [`rollback`] did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rollback {
+    /// The apply had not changed the directory yet; its staging copy was
+    /// removed.
+    Discarded,
+    /// The directory had been swapped out and the original tree was moved
+    /// back.
+    Restored,
+    /// The apply had replaced the directory and was deleting the original
+    /// tree, so it could not be undone; the deletion was finished instead.
+    Completed,
+}
+
+/// How far an apply got, as recorded in its transaction log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    /// Building the staging copy.
+    Staging,
+    /// Swapping the staging copy and the directory.
+    Swapping,
+    /// Deleting the original tree.
+    Committed,
+}
+
+impl Phase {
+    fn name(self) -> &'static str {
+        match self {
+            Phase::Staging => "staging",
+            Phase::Swapping => "swapping",
+            Phase::Committed => "committed",
+        }
+    }
+}
+
+/// First line of a transaction log.
+const TRANSACTION_HEADER: &str = "gdpack transaction 1";
+
+/// Suffix of the transaction log name, after the directory name.
+const TRANSACTION_SUFFIX: &str = "gdpack-txn";
+
+/// Returns the path of the transaction log that applying a bundle to `dir`
+/// writes: `<dir>.gdpack-txn` next to it.
+///
+/// # Errors
+///
+/// Returns `GDeltaError::Io` if `dir` has no name, like `/`.
+pub fn transaction_log(dir: &Path) -> Result<PathBuf> {
+    sibling(dir, TRANSACTION_SUFFIX)
+}
+
+/// Undoes an apply to a directory that did not finish, from its
+/// [`transaction_log`], and removes the log.
+///
+/// Depending on how far the apply got, the staging copy is removed, the
+/// original tree is moved back into place, or, if the original tree was
+/// already being deleted, the deletion is finished. Rolling back never
+/// loses the original tree while it is still complete.
+///
+/// # Errors
+///
+/// Returns `GDeltaError::InvalidOptions` if `log` is not a transaction log,
+/// and `GDeltaError::Io` if it cannot be read or the file system operations
+/// fail. After an error the log is kept, so the rollback can be retried.
+pub fn rollback(log: &Path) -> Result<Rollback> {
+    let not_a_log =
+        || GDeltaError::InvalidOptions(format!("{} is not a transaction log", log.display()));
+    let text = fs::read_to_string(log)?;
+    let mut lines = text.lines();
+    if lines.next() != Some(TRANSACTION_HEADER) {
+        return Err(not_a_log());
+    }
+    let phase = match lines.next().and_then(|line| line.strip_prefix("phase ")) {
+        Some("staging") => Phase::Staging,
+        Some("swapping") => Phase::Swapping,
+        Some("committed") => Phase::Committed,
+        _ => return Err(not_a_log()),
+    };
+    let dir = log
+        .file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| name.strip_suffix(&format!(".{TRANSACTION_SUFFIX}")))
+        .map(|name| log.with_file_name(name))
+        .ok_or_else(not_a_log)?;
+    let staging = sibling(&dir, "gdpack-staging")?;
+    let backup = sibling(&dir, "gdpack-old")?;
+
+    let outcome = match phase {
+        Phase::Committed => {
+            if backup.exists() {
+                fs::remove_dir_all(&backup)?;
+            }
+            Rollback::Completed
+        }
+        Phase::Staging | Phase::Swapping if !backup.exists() => {
+            if staging.exists() {
+                fs::remove_dir_all(&staging)?;
+            }
+            Rollback::Discarded
+        }
+        Phase::Staging | Phase::Swapping => {
+            // The new tree is either still staged or already in place
+            if dir.exists() {
+                if staging.exists() {
+                    fs::remove_dir_all(&staging)?;
+                }
+                fs::rename(&dir, &staging)?;
+            }
+            fs::rename(&backup, &dir)?;
+            if staging.exists() {
+                fs::remove_dir_all(&staging)?;
+            }
+            Rollback::Restored
+        }
+    };
+    fs::remove_file(log)?;
+    Ok(outcome)
+}
+
+/// Records `phase` in the transaction log at `log`, replacing the file so
+/// it is never seen half written.
+fn write_transaction(log: &Path, phase: Phase) -> Result<()> {
+    let mut name = log.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    let temp = log.with_file_name(name);
+    let mut file = fs::File::create(&temp)?;
+    writeln!(file, "{TRANSACTION_HEADER}\nphase {}", phase.name())?;
+    file.sync_all()?;
+    fs::rename(&temp, log)?;
+    Ok(())
+}
+
+/// Fails if an entry for `path` could not be read back.
+fn check_entry_path(path: &str) -> Result<()> {
+    if is_safe_path(path) {
+        Ok(())
+    } else {
+        Err(GDeltaError::InvalidOptions(format!(
+            "Unsafe bundle path {path:?}"
+        )))
+    }
+}
+
+/// Writes one entry.
+fn write_entry(stream: &mut BufferStream, entry: &Entry) {
+    let kind = match entry {
+        Entry::Added { .. } => KIND_ADDED,
+        Entry::Patched { .. } => KIND_PATCHED,
+        Entry::Removed { .. } => KIND_REMOVED,
+        Entry::Symlink { .. } => KIND_SYMLINK,
+        Entry::Duplicate { .. } => KIND_DUPLICATE,
+        Entry::Renamed { .. } => KIND_RENAMED,
+    };
+    stream.write_u8(kind);
+    write_section(stream, entry.path().as_bytes());
+    match entry {
+        Entry::Added { contents, .. } => write_section(stream, contents),
+        Entry::Patched {
+            base_checksum,
+            target_checksum,
+            delta,
+            ..
+        } => {
+            stream.write_bytes(&base_checksum.to_le_bytes());
+            stream.write_bytes(&target_checksum.to_le_bytes());
+            write_section(stream, delta);
+        }
+        Entry::Removed { .. } => {}
+        Entry::Symlink { target, .. } => write_section(stream, target.as_bytes()),
+        Entry::Duplicate {
+            source,
+            checksum,
+            hard_link,
+            ..
+        } => {
+            write_section(stream, source.as_bytes());
+            stream.write_bytes(&checksum.to_le_bytes());
+            stream.write_u8(u8::from(*hard_link));
+        }
+        Entry::Renamed {
+            from,
+            base_checksum,
+            target_checksum,
+            delta,
+            ..
+        } => {
+            write_section(stream, from.as_bytes());
+            stream.write_bytes(&base_checksum.to_le_bytes());
+            stream.write_bytes(&target_checksum.to_le_bytes());
+            write_section(stream, delta);
+        }
+    }
+}
+
+/// Reads one entry of a bundle with format `version`.
+fn read_entry(stream: &mut BufferStream, version: u8) -> Result<Entry> {
+    let kind = stream.read_u8()?;
+    let path = read_path(stream)?;
+    let links = version != VERSION_CONTENTS;
+    Ok(match kind {
+        KIND_ADDED => Entry::Added {
+            path,
+            contents: read_section(stream)?,
+        },
+        KIND_PATCHED => Entry::Patched {
+            path,
+            base_checksum: read_u32(stream)?,
+            target_checksum: read_u32(stream)?,
+            delta: read_section(stream)?,
+        },
+        KIND_REMOVED => Entry::Removed { path },
+        KIND_SYMLINK if links => Entry::Symlink {
+            path,
+            target: String::from_utf8(read_section(stream)?)
+                .map_err(|_| invalid("Bundle link target is not valid UTF-8"))?,
+        },
+        KIND_DUPLICATE if links => Entry::Duplicate {
+            path,
+            source: read_path(stream)?,
+            checksum: read_u32(stream)?,
+            hard_link: match stream.read_u8()? {
+                0 => false,
+                1 => true,
+                flag => return Err(invalid(&format!("Invalid hard link flag {flag}"))),
+            },
+        },
+        KIND_RENAMED if links => Entry::Renamed {
+            path,
+            from: read_path(stream)?,
+            base_checksum: read_u32(stream)?,
+            target_checksum: read_u32(stream)?,
+            delta: read_section(stream)?,
+        },
+        _ => return Err(invalid(&format!("Unknown bundle entry kind {kind}"))),
+    })
+}
+
+/// Writes an index and the checksum of the metadata section.
+fn write_index(stream: &mut BufferStream, index: &[IndexEntry], metadata_checksum: u32) {
+    write_varint(stream, index.len() as u64);
+    for entry in index {
+        write_section(stream, entry.path.as_bytes());
+        write_varint(stream, entry.offset);
+        write_varint(stream, entry.length);
+        stream.write_bytes(&entry.checksum.to_le_bytes());
+    }
+    stream.write_bytes(&metadata_checksum.to_le_bytes());
+}
+
+/// Reads an index and the checksum of the metadata section.
+fn read_index(stream: &mut BufferStream) -> Result<(Vec<IndexEntry>, u32)> {
+    let mut index = Vec::new();
+    for _ in 0..read_varint(stream)? {
+        index.push(IndexEntry {
+            path: read_path(stream)?,
+            offset: read_varint(stream)?,
+            length: read_varint(stream)?,
+            checksum: read_u32(stream)?,
+        });
+    }
+    Ok((index, read_u32(stream)?))
+}
+
+/// Writes the metadata of a bundle of version 2 or 3.
+fn write_metadata_section(stream: &mut BufferStream, metadata: &BTreeMap<String, FileMetadata>) {
+    write_varint(stream, metadata.len() as u64);
+    for (path, metadata) in metadata {
+        write_section(stream, path.as_bytes());
+        write_metadata(stream, metadata);
+    }
+}
+
+/// Reads the metadata of a bundle of version 2 or 3.
+fn read_metadata_section(stream: &mut BufferStream) -> Result<BTreeMap<String, FileMetadata>> {
+    let mut metadata = BTreeMap::new();
+    for _ in 0..read_varint(stream)? {
+        let path = read_path(stream)?;
+        metadata.insert(path, read_metadata_fields(stream)?);
+    }
+    Ok(metadata)
+}
+
+/// What [`Bundle::diff_dirs_with`] found for one path of either tree.
+#[derive(Default)]
+struct PathDiff {
+    entry: Option<Entry>,
+    /// Length and CRC-32 of the file in the new tree, if it is a regular
+    /// file.
+    content: Option<(usize, u32)>,
+    /// Metadata to record for the path.
+    metadata: Option<FileMetadata>,
+}
+
+/// Compares the nodes at `path` in the old and new tree.
+fn diff_path(
+    path: &str,
+    old: Option<&Node>,
+    new: Option<&Node>,
+    options: &EncodeOptions,
+    metadata: &MetadataOptions,
+) -> Result<PathDiff> {
+    let record = |new_path: &Path| {
+        metadata
+            .records_files()
+            .then(|| read_metadata(new_path, metadata))
+            .transpose()
+    };
+    Ok(match (old, new) {
+        (Some(_), None) => PathDiff {
+            entry: Some(Entry::Removed {
+                path: path.to_string(),
+            }),
+            ..PathDiff::default()
+        },
+        (Some(Node::Symlink(old_target)), Some(Node::Symlink(target))) if old_target == target => {
+            PathDiff::default()
+        }
+        (_, Some(Node::Symlink(target))) => PathDiff {
+            entry: Some(Entry::Symlink {
+                path: path.to_string(),
+                target: target.clone(),
+            }),
+            ..PathDiff::default()
+        },
+        (None | Some(Node::Symlink(_)), Some(Node::File(new_path))) => {
+            let target = fs::read(new_path)?;
+            PathDiff {
+                content: Some((target.len(), crc32(&target))),
+                entry: Some(Entry::Added {
+                    path: path.to_string(),
+                    contents: target,
+                }),
+                metadata: record(new_path)?,
+            }
+        }
+        (Some(Node::File(old_path)), Some(Node::File(new_path))) => {
+            let base = fs::read(old_path)?;
+            let target = fs::read(new_path)?;
+            let content = Some((target.len(), crc32(&target)));
+            if base == target {
+                // Unchanged files only record metadata that changed
+                let recorded = record(new_path)?;
+                let changed = match &recorded {
+                    Some(recorded) => read_metadata(old_path, metadata)? != *recorded,
+                    None => false,
+                };
+                PathDiff {
+                    content,
+                    metadata: recorded.filter(|_| changed),
+                    ..PathDiff::default()
+                }
+            } else {
+                PathDiff {
+                    content,
+                    entry: Some(diff_file(path, &target, &base, options)?),
+                    metadata: record(new_path)?,
+                }
+            }
+        }
+        (None, None) => unreachable!("path comes from one of the trees"),
+    })
+}
+
+/// Runs `diff_one` for every path, on all cores with the `rayon` feature,
+/// starting a path only while the `cost` of the paths in progress stays
+/// within `budget`. A path costing more than the budget runs alone.
+///
+/// `progress` is called on the calling thread as each path starts. The
+/// results are in path order, whatever order they finish in.
+#[cfg(feature = "rayon")]
+fn diff_all(
+    paths: &[&String],
+    budget: Option<u64>,
+    cost: impl Fn(&String) -> u64,
+    diff_one: impl Fn(&String) -> Result<PathDiff> + Sync,
+    progress: &mut impl FnMut(Progress<'_>),
+) -> Result<Vec<PathDiff>> {
+    use std::sync::mpsc;
+
+    let budget = budget.unwrap_or(u64::MAX);
+    let mut results: Vec<Option<Result<PathDiff>>> = paths.iter().map(|_| None).collect();
+    let (sender, receiver) = mpsc::channel();
+    let (mut running, mut in_use, mut done) = (0, 0u64, 0);
+    rayon::in_place_scope(|scope| {
+        for (index, path) in paths.iter().enumerate() {
+            let cost = cost(path);
+            while running > 0 && in_use.saturating_add(cost) > budget {
+                let (finished, freed, result) = next_finished(&receiver);
+                (running, in_use, done) = (running - 1, in_use - freed, done + 1);
+                results[finished] = Some(result);
+            }
+            if results.iter().flatten().any(Result::is_err) {
+                break;
+            }
+            progress(Progress {
+                path,
+                done,
+                total: paths.len(),
+            });
+            (running, in_use) = (running + 1, in_use + cost);
+            let (sender, diff_one) = (sender.clone(), &diff_one);
+            scope.spawn(move |_| {
+                // The receiver lives until all paths finished
+                let _ = sender.send((index, cost, diff_one(path)));
+            });
+        }
+        for _ in 0..running {
+            let (finished, _, result) = next_finished(&receiver);
+            results[finished] = Some(result);
+        }
+    });
+    results.into_iter().flatten().collect()
+}
+
+/// Waits for the next path to finish. On a thread of the pool, runs other
+/// pending work meanwhile, so the spawned paths cannot starve.
+#[cfg(feature = "rayon")]
+fn next_finished<T>(receiver: &std::sync::mpsc::Receiver<T>) -> T {
+    loop {
+        if let Ok(finished) = receiver.try_recv() {
+            return finished;
+        }
+        match rayon::yield_now() {
+            Some(rayon::Yield::Executed) => {}
+            Some(rayon::Yield::Idle) => std::thread::yield_now(),
+            None => return receiver.recv().expect("a path in progress holds a sender"),
+        }
+    }
+}
+
+#[cfg(not(feature = "rayon"))]
+fn diff_all(
+    paths: &[&String],
+    _budget: Option<u64>,
+    _cost: impl Fn(&String) -> u64,
+    diff_one: impl Fn(&String) -> Result<PathDiff>,
+    progress: &mut impl FnMut(Progress<'_>),
+) -> Result<Vec<PathDiff>> {
+    paths
+        .iter()
+        .enumerate()
+        .map(|(done, path)| {
+            progress(Progress {
+                path,
+                done,
+                total: paths.len(),
+            });
+            diff_one(path)
+        })
+        .collect()
+}
+
+/// Encodes a changed file, storing it whole if the delta is not smaller.
+fn diff_file(path: &str, target: &[u8], base: &[u8], options: &EncodeOptions) -> Result<Entry> {
+    match crate::encode_with_options(target, base, options) {
+        Ok(delta) if delta.len() < target.len() => Ok(Entry::Patched {
+            path: path.to_string(),
+            base_checksum: crc32(base),
+            target_checksum: crc32(target),
+            delta,
+        }),
+        Ok(_) | Err(GDeltaError::NotBeneficial) => Ok(Entry::Added {
+            path: path.to_string(),
+            contents: target.to_vec(),
+        }),
+        Err(error) => Err(error),
+    }
+}
+
+/// Applies one entry to the staging tree.
+fn apply_entry(entry: &Entry, root: &Path) -> Result<()> {
+    check_no_symlinks(root, entry.path())?;
+    let file = root.join(entry.path());
+    match entry {
+        Entry::Added { contents, .. } => replace_file(&file, contents),
+        Entry::Patched {
+            base_checksum,
+            target_checksum,
+            delta,
+            ..
+        } => {
+            let base = fs::read(&file)?;
+            verify(*base_checksum, &base)?;
+            let target = crate::decode(delta, &base)?;
+            verify(*target_checksum, &target)?;
+            replace_file(&file, &target)
+        }
+        Entry::Removed { .. } => {
+            fs::remove_file(&file)?;
+            // Drop directories the removal left empty
+            let mut parent = file.parent();
+            while let Some(dir) = parent.filter(|&dir| dir != root) {
+                if fs::read_dir(dir)?.next().is_some() {
+                    break;
+                }
+                fs::remove_dir(dir)?;
+                parent = dir.parent();
+            }
+            Ok(())
+        }
+        Entry::Symlink { target, .. } => {
+            if fs::symlink_metadata(&file).is_ok() {
+                fs::remove_file(&file)?;
+            } else if let Some(parent) = file.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            symlink(Path::new(target), &file)
+        }
+        Entry::Renamed {
+            from,
+            base_checksum,
+            target_checksum,
+            delta,
+            ..
+        } => {
+            check_no_symlinks(root, from)?;
+            let base = fs::read(root.join(from))?;
+            verify(*base_checksum, &base)?;
+            let target = crate::decode(delta, &base)?;
+            verify(*target_checksum, &target)?;
+            replace_file(&file, &target)
+        }
+        Entry::Duplicate {
+            source,
+            checksum,
+            hard_link,
+            ..
+        } => {
+            check_no_symlinks(root, source)?;
+            let source = root.join(source);
+            if fs::symlink_metadata(&source)?.is_symlink() {
+                return Err(invalid(&format!(
+                    "Bundle duplicate source {} is a symbolic link",
+                    source.display()
+                )));
+            }
+            if fs::symlink_metadata(&file).is_ok() {
+                fs::remove_file(&file)?;
+            } else if let Some(parent) = file.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            if !*hard_link || fs::hard_link(&source, &file).is_err() {
+                fs::copy(&source, &file)?;
+            }
+            verify(*checksum, &fs::read(&file)?)
+        }
+    }
+}
+
+/// Reconstructs the file at `path` of the new tree from its entry, or from
+/// the base tree through `read_base` if the bundle does not change it.
+/// `read_source` reconstructs the source of a reference.
+fn reconstruct_file(
+    path: &str,
+    entry: Option<&Entry>,
+    read_base: &dyn Fn(&str) -> Result<Vec<u8>>,
+    read_source: impl FnOnce(&str) -> Result<Vec<u8>>,
+) -> Result<Vec<u8>> {
+    let patch = |file: &str, base_checksum: u32, target_checksum: u32, delta: &[u8]| {
+        let base = read_base(file)?;
+        verify(base_checksum, &base)?;
+        let target = crate::decode(delta, &base)?;
+        verify(target_checksum, &target)?;
+        Ok(target)
+    };
+    match entry {
+        None => read_base(path),
+        Some(Entry::Added { contents, .. }) => Ok(contents.clone()),
+        Some(Entry::Patched {
+            base_checksum,
+            target_checksum,
+            delta,
+            ..
+        }) => patch(path, *base_checksum, *target_checksum, delta),
+        Some(Entry::Renamed {
+            from,
+            base_checksum,
+            target_checksum,
+            delta,
+            ..
+        }) => patch(from, *base_checksum, *target_checksum, delta),
+        Some(Entry::Duplicate {
+            source, checksum, ..
+        }) => {
+            let contents = read_source(source)?;
+            verify(*checksum, &contents)?;
+            Ok(contents)
+        }
+        Some(Entry::Removed { .. }) => Err(GDeltaError::Io(format!(
+            "{path:?} is removed by the bundle"
+        ))),
+        Some(Entry::Symlink { target, .. }) => Err(GDeltaError::Io(format!(
+            "{path:?} is a symbolic link to {target:?}"
+        ))),
+    }
+}
+
+/// The `read_source` of [`reconstruct_file`] for the source of a reference,
+/// since sources are never references themselves.
+fn nested_duplicate(source: &str) -> Result<Vec<u8>> {
+    Err(invalid(&format!(
+        "Bundle duplicate source {source:?} is a duplicate"
+    )))
+}
+
+/// Fails if a directory on the way from `root` to `path` is a symbolic
+/// link, which would let a bundle write outside the tree.
+fn check_no_symlinks(root: &Path, path: &str) -> Result<()> {
+    let mut dir = root.to_path_buf();
+    let mut parts = path.split('/').peekable();
+    while let Some(part) = parts.next() {
+        if parts.peek().is_none() {
+            break;
+        }
+        dir.push(part);
+        if fs::symlink_metadata(&dir).is_ok_and(|metadata| metadata.is_symlink()) {
+            return Err(invalid(&format!(
+                "Bundle path {path:?} leads through a symbolic link"
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn verify(expected: u32, data: &[u8]) -> Result<()> {
+    let actual = crc32(data);
+    if expected == actual {
+        Ok(())
+    } else {
+        Err(GDeltaError::ChecksumMismatch { expected, actual })
+    }
+}
+
+/// Writes `contents` to a new file at `path`.
+///
+/// An existing file is unlinked first, since it may be a hard link to the
+/// tree being updated.
+fn replace_file(path: &Path, contents: &[u8]) -> Result<()> {
+    if fs::symlink_metadata(path).is_ok() {
+        fs::remove_file(path)?;
+    } else if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, contents)?;
+    Ok(())
+}
+
+/// Recreates the tree under `from` at `to`, hard-linking files where
+/// possible and copying them otherwise.
+fn link_tree(from: &Path, to: &Path) -> Result<()> {
+    fs::create_dir(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let target = to.join(entry.file_name());
+        if file_type.is_dir() {
+            link_tree(&entry.path(), &target)?;
+        } else if file_type.is_symlink() {
+            symlink(&fs::read_link(entry.path())?, &target)?;
+        } else if file_type.is_file() && fs::hard_link(entry.path(), &target).is_err() {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Creates a symbolic link at `link` pointing to `target`.
+fn symlink(target: &Path, link: &Path) -> Result<()> {
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(target, link)?;
+    #[cfg(windows)]
+    {
+        // Windows distinguishes links to directories, resolved from the link
+        let resolved = link
+            .parent()
+            .map_or(target.to_path_buf(), |dir| dir.join(target));
+        if resolved.is_dir() {
+            std::os::windows::fs::symlink_dir(target, link)?;
+        } else {
+            std::os::windows::fs::symlink_file(target, link)?;
+        }
+    }
+    #[cfg(not(any(unix, windows)))]
+    return Err(GDeltaError::Io(format!(
+        "Cannot create symbolic link {}: not supported on this platform",
+        link.display()
+    )));
+    #[cfg(any(unix, windows))]
+    Ok(())
+}
+
+/// Reads the metadata of the file at `path` selected by `options`.
+fn read_metadata(path: &Path, options: &MetadataOptions) -> Result<FileMetadata> {
+    let stat = fs::metadata(path)?;
+    let mode = options
+        .permissions
+        .then(|| permission_bits(&stat.permissions()));
+    let mtime = if options.mtimes {
+        // Times before the epoch cannot be stored
+        Some(stat.modified()?).filter(|time| *time >= SystemTime::UNIX_EPOCH)
+    } else {
+        None
+    };
+    let xattrs = if options.xattrs {
+        read_xattrs(path)?
+    } else {
+        None
+    };
+    Ok(FileMetadata {
+        mode,
+        mtime,
+        xattrs,
+    })
+}
+
+/// Applies the fields of `metadata` selected by `options` to `path`.
+fn write_file_metadata(
+    path: &Path,
+    metadata: &FileMetadata,
+    options: &MetadataOptions,
+) -> Result<()> {
+    if options.xattrs
+        && let Some(xattrs) = &metadata.xattrs
+    {
+        write_xattrs(path, xattrs)?;
+    }
+    // Before the permissions, which may make the file read-only
+    if options.mtimes
+        && let Some(mtime) = metadata.mtime
+    {
+        fs::OpenOptions::new()
+            .write(cfg!(windows))
+            .read(!cfg!(windows))
+            .open(path)?
+            .set_modified(mtime)?;
+    }
+    if options.permissions
+        && let Some(mode) = metadata.mode
+    {
+        let mut permissions = fs::metadata(path)?.permissions();
+        set_permission_bits(&mut permissions, mode);
+        fs::set_permissions(path, permissions)?;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn permission_bits(permissions: &fs::Permissions) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    permissions.mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+fn permission_bits(permissions: &fs::Permissions) -> u32 {
+    if permissions.readonly() { 0o444 } else { 0o666 }
+}
+
+#[cfg(unix)]
+fn set_permission_bits(permissions: &mut fs::Permissions, mode: u32) {
+    use std::os::unix::fs::PermissionsExt;
+    permissions.set_mode(mode & 0o7777);
+}
+
+#[cfg(not(unix))]
+fn set_permission_bits(permissions: &mut fs::Permissions, mode: u32) {
+    permissions.set_readonly(mode & 0o222 == 0);
+}
+
+/// Extended attribute names and values.
+type Xattrs = Vec<(String, Vec<u8>)>;
+
+/// Reads the extended attributes of `path`, sorted by name, or `None`
+/// where they are not supported.
+#[cfg(all(unix, feature = "xattr"))]
+fn read_xattrs(path: &Path) -> Result<Option<Xattrs>> {
+    let mut xattrs = Vec::new();
+    for name in xattr::list(path)? {
+        let value = xattr::get(path, &name)?.unwrap_or_default();
+        let name = name.into_string().map_err(|name| {
+            GDeltaError::Io(format!(
+                "Extended attribute {name:?} of {} is not valid UTF-8",
+                path.display()
+            ))
+        })?;
+        xattrs.push((name, value));
+    }
+    xattrs.sort();
+    Ok(Some(xattrs))
+}
+
+#[cfg(not(all(unix, feature = "xattr")))]
+fn read_xattrs(_path: &Path) -> Result<Option<Xattrs>> {
+    Ok(None)
+}
+
+/// Makes `xattrs` the extended attributes of `path`.
+#[cfg(all(unix, feature = "xattr"))]
+fn write_xattrs(path: &Path, xattrs: &[(String, Vec<u8>)]) -> Result<()> {
+    for name in xattr::list(path)? {
+        if !xattrs.iter().any(|(kept, _)| name == kept.as_str()) {
+            xattr::remove(path, &name)?;
+        }
+    }
+    for (name, value) in xattrs {
+        xattr::set(path, name, value)?;
+    }
+    Ok(())
+}
+
+#[cfg(not(all(unix, feature = "xattr")))]
+fn write_xattrs(_path: &Path, _xattrs: &[(String, Vec<u8>)]) -> Result<()> {
+    Ok(())
+}
+
+/// Returns the path next to `dir` with `suffix` appended to its name.
+fn sibling(dir: &Path, suffix: &str) -> Result<PathBuf> {
+    let name = dir
+        .file_name()
+        .ok_or_else(|| GDeltaError::Io(format!("{} has no directory name", dir.display())))?;
+    let mut name = name.to_os_string();
+    name.push(".");
+    name.push(suffix);
+    Ok(dir.with_file_name(name))
+}
+
+/// The contents of a regular file of the new tree, for finding duplicates.
+struct Content {
+    path: String,
+    len: usize,
+    checksum: u32,
+    /// Index of the entry storing the file, if it changed.
+    entry: Option<usize>,
+}
+
+/// Returns true if `a` and `b` are hard links to the same file.
+#[cfg(unix)]
+fn same_file(a: &Path, b: &Path) -> Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+    let (a, b) = (fs::metadata(a)?, fs::metadata(b)?);
+    Ok(a.dev() == b.dev() && a.ino() == b.ino())
+}
+
+#[cfg(not(unix))]
+fn same_file(_a: &Path, _b: &Path) -> Result<bool> {
+    Ok(false)
+}
+
+/// A tracked file in a tree.
+enum Node {
+    /// A regular file at this path.
+    File(PathBuf),
+    /// A symbolic link with this target.
+    Symlink(String),
+}
+
+/// Lists the regular files under `root`, and its symbolic links if
+/// `symlinks` is set, by their `/`-separated path, skipping what `ignore`
+/// excludes.
+fn list_files(root: &Path, symlinks: bool, ignore: &IgnoreRules) -> Result<BTreeMap<String, Node>> {
+    let mut files = BTreeMap::new();
+    let mut pending = vec![(root.to_path_buf(), String::new())];
+    while let Some((dir, prefix)) = pending.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let name = name.to_str().ok_or_else(|| {
+                GDeltaError::Io(format!("{} is not valid UTF-8", entry.path().display()))
+            })?;
+            let path = format!("{prefix}{name}");
+            let file_type = entry.file_type()?;
+            if ignore.is_ignored(&path, file_type.is_dir()) {
+                continue;
+            }
+            if file_type.is_dir() {
+                pending.push((entry.path(), format!("{path}/")));
+            } else if file_type.is_file() {
+                files.insert(path, Node::File(entry.path()));
+            } else if file_type.is_symlink() && symlinks {
+                let target = fs::read_link(entry.path())?;
+                let target = target.to_str().ok_or_else(|| {
+                    GDeltaError::Io(format!(
+                        "Target of {} is not valid UTF-8",
+                        entry.path().display()
+                    ))
+                })?;
+                files.insert(path, Node::Symlink(target.to_string()));
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Writes the recorded fields of `metadata`.
+fn write_metadata(stream: &mut BufferStream, metadata: &FileMetadata) {
+    let since_epoch = metadata
+        .mtime
+        .and_then(|mtime| mtime.duration_since(SystemTime::UNIX_EPOCH).ok());
+    let fields = metadata.mode.map_or(0, |_| FIELD_MODE)
+        | since_epoch.map_or(0, |_| FIELD_MTIME)
+        | metadata.xattrs.as_ref().map_or(0, |_| FIELD_XATTRS);
+    stream.write_u8(fields);
+    if let Some(mode) = metadata.mode {
+        stream.write_bytes(&mode.to_le_bytes());
+    }
+    if let Some(since_epoch) = since_epoch {
+        write_varint(stream, since_epoch.as_secs());
+        write_varint(stream, u64::from(since_epoch.subsec_nanos()));
+    }
+    if let Some(xattrs) = &metadata.xattrs {
+        write_varint(stream, xattrs.len() as u64);
+        for (name, value) in xattrs {
+            write_section(stream, name.as_bytes());
+            write_section(stream, value);
+        }
+    }
+}
+
+/// Reads metadata written by [`write_metadata`].
+fn read_metadata_fields(stream: &mut BufferStream) -> Result<FileMetadata> {
+    let fields = stream.read_u8()?;
+    if fields & !(FIELD_MODE | FIELD_MTIME | FIELD_XATTRS) != 0 {
+        return Err(invalid(&format!(
+            "Unknown bundle metadata fields {fields:#x}"
+        )));
+    }
+    let mut metadata = FileMetadata::default();
+    if fields & FIELD_MODE != 0 {
+        metadata.mode = Some(read_u32(stream)?);
+    }
+    if fields & FIELD_MTIME != 0 {
+        let seconds = read_varint(stream)?;
+        let nanos = u32::try_from(read_varint(stream)?)
+            .ok()
+            .filter(|&nanos| nanos < 1_000_000_000)
+            .ok_or_else(|| invalid("Invalid bundle modification time"))?;
+        metadata.mtime = SystemTime::UNIX_EPOCH.checked_add(Duration::new(seconds, nanos));
+        if metadata.mtime.is_none() {
+            return Err(invalid("Invalid bundle modification time"));
+        }
+    }
+    if fields & FIELD_XATTRS != 0 {
+        let mut xattrs = Vec::new();
+        for _ in 0..read_varint(stream)? {
+            let name = String::from_utf8(read_section(stream)?)
+                .map_err(|_| invalid("Bundle attribute name is not valid UTF-8"))?;
+            xattrs.push((name, read_section(stream)?));
+        }
+        metadata.xattrs = Some(xattrs);
+    }
+    Ok(metadata)
+}
+
+/// Writes a length-prefixed byte section.
+fn write_section(stream: &mut BufferStream, bytes: &[u8]) {
+    write_varint(stream, bytes.len() as u64);
+    stream.write_bytes(bytes);
+}
+
+fn read_u32(stream: &mut BufferStream) -> Result<u32> {
+    let bytes = stream.read_bytes(4)?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn read_u64(stream: &mut BufferStream) -> Result<u64> {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(stream.read_bytes(8)?);
+    Ok(u64::from_le_bytes(bytes))
+}
+
+/// Reads a length-prefixed byte section.
+fn read_section(stream: &mut BufferStream) -> Result<Vec<u8>> {
+    let len = usize::try_from(read_varint(stream)?).unwrap_or(usize::MAX);
+    if len > stream.remaining() {
+        return Err(GDeltaError::UnexpectedEndOfData);
+    }
+    Ok(stream.read_bytes(len)?.to_vec())
+}
+
+/// Reads a path and checks that it stays inside the tree.
+fn read_path(stream: &mut BufferStream) -> Result<String> {
+    let path = String::from_utf8(read_section(stream)?)
+        .map_err(|_| invalid("Bundle path is not valid UTF-8"))?;
+    if !is_safe_path(&path) {
+        return Err(invalid(&format!("Unsafe bundle path {path:?}")));
+    }
+    Ok(path)
+}
+
+/// Returns true if `path` is relative, `/`-separated and stays inside the
+/// tree.
+fn is_safe_path(path: &str) -> bool {
+    !path.contains('\\')
+        && path
+            .split('/')
+            .all(|part| !part.is_empty() && part != "." && part != ".." && !part.contains(':'))
+}
+
+fn invalid(message: &str) -> GDeltaError {
+    GDeltaError::InvalidDelta(message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("gdelta-bundle-{name}-{}", std::process::id()));
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write(root: &Path, path: &str, contents: &[u8]) {
+        let file = root.join(path);
+        fs::create_dir_all(file.parent().unwrap()).unwrap();
+        fs::write(file, contents).unwrap();
+    }
+
+    #[test]
+    fn test_diff_and_apply_dirs() {
+        let root = temp_dir("apply");
+        let (old, new) = (root.join("old"), root.join("new"));
+        let texture = b"texture data ".repeat(500);
+        let mut changed = texture.clone();
+        changed[100..110].copy_from_slice(b"repainted!");
+        write(&old, "textures/wall.tex", &texture);
+        write(&old, "sounds/old.wav", b"removed sound");
+        write(&old, "readme.txt", b"unchanged");
+        write(&new, "textures/wall.tex", &changed);
+        write(&new, "levels/new.map", b"added level");
+        write(&new, "readme.txt", b"unchanged");
+
+        let mut seen = Vec::new();
+        let bundle = Bundle::diff_dirs(&old, &new, &EncodeOptions::new(), |progress| {
+            seen.push(progress.path.to_string());
+        })
+        .unwrap();
+        assert_eq!(seen.len(), 4);
+        let paths: Vec<_> = bundle.entries.iter().map(Entry::path).collect();
+        assert_eq!(
+            paths,
+            ["levels/new.map", "sounds/old.wav", "textures/wall.tex"]
+        );
+        assert!(matches!(bundle.entries[2], Entry::Patched { .. }));
+
+        let bundle = Bundle::from_bytes(&bundle.to_bytes()).unwrap();
+        bundle.apply_to_dir(&old, |_| {}).unwrap();
+        assert_eq!(
+            list_files(&old, false, &IgnoreRules::new()).unwrap().len(),
+            3
+        );
+        assert_eq!(fs::read(old.join("textures/wall.tex")).unwrap(), changed);
+        assert_eq!(
+            fs::read(old.join("levels/new.map")).unwrap(),
+            b"added level"
+        );
+        assert!(!old.join("sounds").exists());
+
+        // Applying again fails and leaves the tree alone
+        assert!(bundle.apply_to_dir(&old, |_| {}).is_err());
+        assert_eq!(fs::read(old.join("textures/wall.tex")).unwrap(), changed);
+        assert!(!root.join("old.gdpack-staging").exists());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_checks_dir_without_writing() {
+        let root = temp_dir("check");
+        let (old, new) = (root.join("old"), root.join("new"));
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i * 13 % 251) as u8).collect();
+        let mut changed = data.clone();
+        changed[500..504].copy_from_slice(b"edit");
+        write(&old, "data.bin", &data);
+        write(&old, "notes.txt", b"some notes");
+        write(&old, "old.txt", b"removed");
+        write(&new, "data.bin", &changed);
+        write(&new, "notes.txt", b"some notes");
+        write(&new, "notes-copy.txt", b"some notes");
+        write(&new, "added.txt", b"added");
+
+        let bundle = Bundle::diff_dirs(&old, &new, &EncodeOptions::new(), |_| {}).unwrap();
+        let check = bundle.check_dir(&old, |_| {}).unwrap();
+        assert!(check.is_ready(), "{check:?}");
+        assert_eq!(check.entries.len(), 4);
+        assert_eq!(check.bytes_needed, 5 + changed.len() as u64 + 10);
+        assert_eq!(fs::read(old.join("data.bin")).unwrap(), data);
+
+        write(&old, "data.bin", &changed);
+        write(&old, "notes.txt", b"other notes");
+        fs::remove_file(old.join("old.txt")).unwrap();
+        write_transaction(&transaction_log(&old).unwrap(), Phase::Staging).unwrap();
+        let check = bundle.check_dir(&old, |_| {}).unwrap();
+        assert!(!check.is_ready());
+        assert_eq!(check.problems.len(), 1);
+        let problems: Vec<&str> = check
+            .entries
+            .iter()
+            .filter(|entry| entry.problem.is_some())
+            .map(|entry| entry.path.as_str())
+            .collect();
+        assert_eq!(problems, ["data.bin", "notes-copy.txt", "old.txt"]);
+        assert!(matches!(
+            check.entries[1].problem,
+            Some(GDeltaError::ChecksumMismatch { .. })
+        ));
+
+        assert!(bundle.check_dir(&root.join("missing"), |_| {}).is_err());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_rolls_back_unfinished_apply() {
+        let root = temp_dir("rollback");
+        let (old, new) = (root.join("old"), root.join("new"));
+        write(&old, "app.bin", b"version 1");
+        write(&new, "app.bin", b"version 2");
+        let bundle = Bundle::diff_dirs(&old, &new, &EncodeOptions::new(), |_| {}).unwrap();
+        let log = transaction_log(&old).unwrap();
+        assert_eq!(log, root.join("old.gdpack-txn"));
+        let (staging, backup) = (root.join("old.gdpack-staging"), root.join("old.gdpack-old"));
+
+        // Died while staging
+        link_tree(&old, &staging).unwrap();
+        write_transaction(&log, Phase::Staging).unwrap();
+        assert!(matches!(
+            bundle.apply_to_dir(&old, |_| {}),
+            Err(GDeltaError::Io(_))
+        ));
+        assert_eq!(rollback(&log).unwrap(), Rollback::Discarded);
+        assert!(!staging.exists() && !log.exists());
+
+        // Died between the two renames
+        link_tree(&new, &staging).unwrap();
+        write_transaction(&log, Phase::Swapping).unwrap();
+        fs::rename(&old, &backup).unwrap();
+        assert_eq!(rollback(&log).unwrap(), Rollback::Restored);
+        assert_eq!(fs::read(old.join("app.bin")).unwrap(), b"version 1");
+        assert!(!staging.exists() && !backup.exists());
+
+        // Died after swapping in the new tree
+        write_transaction(&log, Phase::Swapping).unwrap();
+        fs::rename(&old, &backup).unwrap();
+        link_tree(&new, &old).unwrap();
+        assert_eq!(rollback(&log).unwrap(), Rollback::Restored);
+        assert_eq!(fs::read(old.join("app.bin")).unwrap(), b"version 1");
+
+        // Died while deleting the original tree
+        bundle.apply_to_dir(&old, |_| {}).unwrap();
+        assert!(!log.exists());
+        link_tree(&new, &backup).unwrap();
+        write_transaction(&log, Phase::Committed).unwrap();
+        assert_eq!(rollback(&log).unwrap(), Rollback::Completed);
+        assert_eq!(fs::read(old.join("app.bin")).unwrap(), b"version 2");
+        assert!(!backup.exists());
+
+        fs::write(&log, "something else").unwrap();
+        assert!(matches!(
+            rollback(&log),
+            Err(GDeltaError::InvalidOptions(_))
+        ));
+        assert!(log.exists());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_writes_new_tree_as_tar() {
+        let root = temp_dir("tar");
+        let (old, new) = (root.join("old"), root.join("new"));
+        let model: Vec<u8> = (0..20_000u32).map(|i| (i * 13 % 251) as u8).collect();
+        let mut moved = model.clone();
+        moved[5_000..5_008].copy_from_slice(b"retopo!!");
+        write(&old, "models/ship.obj", &model);
+        write(&old, "config.ini", b"volume=3");
+        write(&old, "notes.txt", b"unchanged");
+        write(&old, "logs/old.log", b"removed");
+        write(&new, "models/v2/ship.obj", &moved);
+        write(&new, "config.ini", b"volume=7");
+        write(&new, "notes.txt", b"unchanged");
+        write(&new, "maps/a.map", b"same map");
+        write(&new, "maps/b.map", b"same map");
+
+        let bundle = Bundle::diff_dirs(&old, &new, &EncodeOptions::new(), |_| {}).unwrap();
+        assert!(
+            bundle
+                .entries
+                .iter()
+                .any(|entry| matches!(entry, Entry::Renamed { .. }))
+        );
+        let archive = bundle.write_tar(&old, Vec::new(), |_| {}).unwrap();
+
+        let mut files = BTreeMap::new();
+        let mut dirs = Vec::new();
+        for member in crate::oci::members(&archive).unwrap() {
+            let header = &archive[member.range.start..member.range.start + 512];
+            let size = std::str::from_utf8(&header[124..135]).unwrap();
+            let size = usize::from_str_radix(size, 8).unwrap();
+            let data = &archive[member.range.start + 512..][..size];
+            match header[156] {
+                b'0' => assert!(files.insert(member.path, data.to_vec()).is_none()),
+                b'5' => dirs.push(member.path),
+                kind => panic!("unexpected member kind {kind}"),
+            }
+        }
+        assert_eq!(dirs, ["maps/", "models/", "models/v2/"]);
+        let expected: BTreeMap<String, Vec<u8>> = list_files(&new, false, &IgnoreRules::new())
+            .unwrap()
+            .keys()
+            .map(|path| (path.clone(), fs::read(new.join(path)).unwrap()))
+            .collect();
+        assert_eq!(files, expected);
+        assert_eq!(fs::read(old.join("logs/old.log")).unwrap(), b"removed");
+
+        // A base tree the bundle was not made for is rejected
+        write(&old, "models/ship.obj", &model[1..]);
+        assert!(matches!(
+            bundle.write_tar(&old, Vec::new(), |_| {}),
+            Err(GDeltaError::ChecksumMismatch { .. })
+        ));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_budget_does_not_change_bundle() {
+        let root = temp_dir("budget");
+        let (old, new) = (root.join("old"), root.join("new"));
+        for i in 0..24u32 {
+            let data: Vec<u8> = (0..3_000 + i * 100)
+                .map(|j| (j * (i + 3) % 251) as u8)
+                .collect();
+            write(&old, &format!("dir{}/file{i}.bin", i % 3), &data);
+            let mut changed = data.clone();
+            changed[i as usize * 50] ^= 0xff;
+            write(&new, &format!("dir{}/file{i}.bin", i % 3), &changed);
+        }
+        write(&old, "gone.txt", b"removed");
+        write(&new, "new.txt", b"added");
+
+        let mut seen = Vec::new();
+        let unlimited = Bundle::diff_dirs(&old, &new, &EncodeOptions::new(), |progress| {
+            assert!(progress.done <= progress.total);
+            seen.push(progress.path.to_string());
+        })
+        .unwrap();
+        assert_eq!(seen.len(), 26);
+        assert!(seen.is_sorted());
+        for budget in [0, 10_000] {
+            let diff = DiffOptions::new().memory_budget(budget);
+            let bundle =
+                Bundle::diff_dirs_with(&old, &new, &EncodeOptions::new(), &diff, |_| {}).unwrap();
+            assert_eq!(bundle, unlimited);
+        }
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_contents_only_bundles_keep_version_1() {
+        let bundle = Bundle {
+            entries: vec![Entry::Removed {
+                path: "gone".to_string(),
+            }],
+            ..Bundle::default()
+        };
+        assert_eq!(bundle.to_bytes()[MAGIC.len()], VERSION_CONTENTS);
+
+        let mut with_metadata = bundle.clone();
+        with_metadata.metadata.insert(
+            "kept".to_string(),
+            FileMetadata {
+                mode: Some(0o640),
+                mtime: Some(SystemTime::UNIX_EPOCH + Duration::new(1_700_000_000, 5)),
+                xattrs: Some(vec![("user.tag".to_string(), b"value".to_vec())]),
+            },
+        );
+        let bytes = with_metadata.to_bytes();
+        assert_eq!(bytes[MAGIC.len()], VERSION_METADATA);
+        assert_eq!(Bundle::from_bytes(&bytes).unwrap(), with_metadata);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_restores_metadata_and_symlinks() {
+        use std::os::unix::fs::{PermissionsExt, symlink};
+
+        let root = temp_dir("metadata");
+        let (old, new) = (root.join("old"), root.join("new"));
+        write(&old, "bin/tool", b"#!/bin/sh\necho old\n");
+        write(&old, "etc/config", b"unchanged");
+        write(&old, "etc/link-me", b"replaced by a link");
+        write(&new, "bin/tool", b"#!/bin/sh\necho new\n");
+        write(&new, "etc/config", b"unchanged");
+        symlink("config", new.join("etc/link-me")).unwrap();
+        symlink("../bin/tool", new.join("etc/tool")).unwrap();
+        fs::set_permissions(new.join("bin/tool"), fs::Permissions::from_mode(0o755)).unwrap();
+        fs::set_permissions(new.join("etc/config"), fs::Permissions::from_mode(0o600)).unwrap();
+        let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        fs::File::open(new.join("etc/config"))
+            .unwrap()
+            .set_modified(mtime)
+            .unwrap();
+
+        // Without metadata options links are skipped like missing files
+        let plain = Bundle::diff_dirs(&old, &new, &EncodeOptions::new(), |_| {}).unwrap();
+        let paths: Vec<_> = plain.entries.iter().map(Entry::path).collect();
+        assert_eq!(paths, ["bin/tool", "etc/link-me"]);
+        assert!(matches!(plain.entries[1], Entry::Removed { .. }));
+        assert!(plain.metadata.is_empty());
+
+        let diff = DiffOptions::new().metadata(MetadataOptions::all());
+        let bundle =
+            Bundle::diff_dirs_with(&old, &new, &EncodeOptions::new(), &diff, |_| {}).unwrap();
+        let paths: Vec<_> = bundle.entries.iter().map(Entry::path).collect();
+        assert_eq!(paths, ["bin/tool", "etc/link-me", "etc/tool"]);
+        assert_eq!(
+            bundle.metadata.keys().collect::<Vec<_>>(),
+            ["bin/tool", "etc/config"]
+        );
+
+        // The unchanged config is hard-linked into staging, so restoring
+        // its metadata must not touch the original before the swap
+        let bundle = Bundle::from_bytes(&bundle.to_bytes()).unwrap();
+        let kept = root.join("kept-config");
+        fs::hard_link(old.join("etc/config"), &kept).unwrap();
+        bundle.apply_to_dir(&old, |_| {}).unwrap();
+
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&old.join("bin/tool")), 0o755);
+        assert_eq!(mode(&old.join("etc/config")), 0o600);
+        assert_ne!(mode(&kept), 0o600);
+        assert_eq!(
+            fs::metadata(old.join("etc/config"))
+                .unwrap()
+                .modified()
+                .unwrap(),
+            mtime
+        );
+        assert_eq!(
+            fs::read_link(old.join("etc/link-me")).unwrap(),
+            Path::new("config")
+        );
+        assert_eq!(
+            fs::read(old.join("etc/tool")).unwrap(),
+            b"#!/bin/sh\necho new\n"
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(all(unix, feature = "xattr"))]
+    #[test]
+    fn test_restores_xattrs() {
+        let root = temp_dir("xattrs");
+        let (old, new) = (root.join("old"), root.join("new"));
+        write(&old, "file", b"same contents");
+        write(&new, "file", b"same contents");
+        xattr::set(old.join("file"), "user.stale", b"old").unwrap();
+        if xattr::set(new.join("file"), "user.origin", b"scanner").is_err() {
+            // The file system does not support user attributes
+            fs::remove_dir_all(&root).unwrap();
+            return;
+        }
+
+        let diff = DiffOptions::new().metadata(MetadataOptions::new().xattrs(true));
+        let bundle =
+            Bundle::diff_dirs_with(&old, &new, &EncodeOptions::new(), &diff, |_| {}).unwrap();
+        assert!(bundle.entries.is_empty());
+        bundle.apply_to_dir(&old, |_| {}).unwrap();
+        assert_eq!(
+            read_xattrs(&old.join("file")).unwrap(),
+            Some(vec![("user.origin".to_string(), b"scanner".to_vec())])
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_stores_duplicates_once() {
+        let root = temp_dir("dedup");
+        let (old, new) = (root.join("old"), root.join("new"));
+        let sprite: Vec<u8> = (0..20_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let logo = b"shared logo ".repeat(100);
+        write(&old, "ui/logo.png", &logo);
+        write(&new, "ui/logo.png", &logo);
+        for path in ["a/sprite.bin", "b/sprite.bin", "c/sprite.bin"] {
+            write(&new, path, &sprite);
+        }
+        write(&new, "splash/logo.png", &logo);
+
+        let bundle = Bundle::diff_dirs(&old, &new, &EncodeOptions::new(), |_| {}).unwrap();
+        let sources: Vec<_> = bundle
+            .entries
+            .iter()
+            .map(|entry| match entry {
+                Entry::Duplicate { source, .. } => Some(source.as_str()),
+                _ => None,
+            })
+            .collect();
+        // The first copy is stored, the unchanged logo is referenced
+        assert_eq!(
+            sources,
+            [
+                None,
+                Some("a/sprite.bin"),
+                Some("a/sprite.bin"),
+                Some("ui/logo.png")
+            ]
+        );
+        let bytes = bundle.to_bytes();
+        assert!(bytes.len() < sprite.len() + 200);
+
+        Bundle::from_bytes(&bytes)
+            .unwrap()
+            .apply_to_dir(&old, |_| {})
+            .unwrap();
+        assert_eq!(fs::read(old.join("c/sprite.bin")).unwrap(), sprite);
+        assert_eq!(fs::read(old.join("splash/logo.png")).unwrap(), logo);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_restores_hard_links() {
+        use std::os::unix::fs::MetadataExt;
+
+        let root = temp_dir("hard-links");
+        let (old, new) = (root.join("old"), root.join("new"));
+        fs::create_dir_all(&old).unwrap();
+        write(&new, "lib/libfoo.so.1", b"shared object contents");
+        fs::hard_link(new.join("lib/libfoo.so.1"), new.join("lib/libfoo.so")).unwrap();
+        write(&new, "lib/copy.so", b"shared object contents");
+
+        let bundle = Bundle::diff_dirs(&old, &new, &EncodeOptions::new(), |_| {}).unwrap();
+        let links: Vec<_> = bundle
+            .entries
+            .iter()
+            .filter_map(|entry| match entry {
+                Entry::Duplicate {
+                    path, hard_link, ..
+                } => Some((path.as_str(), *hard_link)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(links, [("lib/libfoo.so", false), ("lib/libfoo.so.1", true)]);
+
+        bundle.apply_to_dir(&old, |_| {}).unwrap();
+        let inode = |path: &str| fs::metadata(old.join(path)).unwrap().ino();
+        assert_eq!(inode("lib/libfoo.so"), inode("lib/libfoo.so.1"));
+        assert_ne!(inode("lib/libfoo.so"), inode("lib/copy.so"));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_detects_renamed_files() {
+        let root = temp_dir("renames");
+        let (old, new) = (root.join("old"), root.join("new"));
+        let source: Vec<u8> = (0..2_000u32)
+            .flat_map(|i| format!("let value_{i} = {};\n", i * 31 % 97).into_bytes())
+            .collect();
+        let mut edited = source.clone();
+        edited[500..508].copy_from_slice(b"// moved");
+        let unrelated: Vec<u8> = (0..4_000u32).map(|i| (i * 131 % 256) as u8).collect();
+        write(&old, "src/old_name.rs", &source);
+        write(&old, "assets/blob.bin", &unrelated);
+        write(&new, "src/module/new_name.rs", &edited);
+        write(&new, "assets/other.bin", &source[..100]);
+
+        let bundle = Bundle::diff_dirs(&old, &new, &EncodeOptions::new(), |_| {}).unwrap();
+        let renames: Vec<_> = bundle
+            .entries
+            .iter()
+            .filter_map(|entry| match entry {
+                Entry::Renamed { path, from, .. } => Some((path.as_str(), from.as_str())),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(renames, [("src/module/new_name.rs", "src/old_name.rs")]);
+        let bytes = bundle.to_bytes();
+        assert!(bytes.len() < 400);
+
+        let disabled = DiffOptions::new().rename_threshold(None);
+        let plain =
+            Bundle::diff_dirs_with(&old, &new, &EncodeOptions::new(), &disabled, |_| {}).unwrap();
+        assert!(plain.to_bytes().len() > edited.len());
+        let invalid = DiffOptions::new().rename_threshold(Some(1.5));
+        assert!(matches!(
+            Bundle::diff_dirs_with(&old, &new, &EncodeOptions::new(), &invalid, |_| {}),
+            Err(GDeltaError::InvalidOptions(_))
+        ));
+
+        Bundle::from_bytes(&bytes)
+            .unwrap()
+            .apply_to_dir(&old, |_| {})
+            .unwrap();
+        assert_eq!(
+            fs::read(old.join("src/module/new_name.rs")).unwrap(),
+            edited
+        );
+        assert_eq!(
+            fs::read(old.join("assets/other.bin")).unwrap(),
+            &source[..100]
+        );
+        assert!(!old.join("src/old_name.rs").exists());
+        assert!(!old.join("assets/blob.bin").exists());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_ignores_excluded_files() {
+        let root = temp_dir("ignore");
+        let (old, new) = (root.join("old"), root.join("new"));
+        for tree in [&old, &new] {
+            write(tree, "src/main.rs", b"fn main() {}");
+            write(
+                tree,
+                "target/debug/app",
+                tree.to_str().unwrap().repeat(10).as_bytes(),
+            );
+            write(tree, "cache.tmp", tree.to_str().unwrap().as_bytes());
+        }
+        write(&new, ".gdignore", b"# build output\ntarget/\n");
+        write(&new, "notes.tmp", b"scratch");
+        write(&new, "keep.tmp", b"kept");
+
+        let diff = DiffOptions::new().exclude("*.tmp").exclude("!keep.tmp");
+        let bundle =
+            Bundle::diff_dirs_with(&old, &new, &EncodeOptions::new(), &diff, |_| {}).unwrap();
+        let paths: Vec<_> = bundle.entries.iter().map(Entry::path).collect();
+        assert_eq!(paths, [".gdignore", "keep.tmp"]);
+
+        let unfiltered = DiffOptions::new().ignore_file(false);
+        let bundle =
+            Bundle::diff_dirs_with(&old, &new, &EncodeOptions::new(), &unfiltered, |_| {}).unwrap();
+        let paths: Vec<_> = bundle.entries.iter().map(Entry::path).collect();
+        assert!(paths.contains(&"target/debug/app"));
+
+        // Ignored files of the tree being patched are kept
+        Bundle::diff_dirs_with(&old, &new, &EncodeOptions::new(), &diff, |_| {})
+            .unwrap()
+            .apply_to_dir(&old, |_| {})
+            .unwrap();
+        assert_eq!(
+            fs::read(old.join("cache.tmp")).unwrap(),
+            old.to_str().unwrap().as_bytes()
+        );
+        assert!(!old.join("notes.tmp").exists());
+        assert!(old.join("target/debug/app").exists());
+
+        write(&new, ".gdignore", b"[unterminated\n");
+        assert!(matches!(
+            Bundle::diff_dirs(&old, &new, &EncodeOptions::new(), |_| {}),
+            Err(GDeltaError::InvalidOptions(_))
+        ));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_rejects_writes_through_symlinks() {
+        let root = temp_dir("through-link");
+        let (tree, outside) = (root.join("tree"), root.join("outside"));
+        fs::create_dir_all(&outside).unwrap();
+        fs::create_dir_all(&tree).unwrap();
+        std::os::unix::fs::symlink(&outside, tree.join("escape")).unwrap();
+
+        let bundle = Bundle {
+            entries: vec![Entry::Added {
+                path: "escape/planted".to_string(),
+                contents: b"owned".to_vec(),
+            }],
+            ..Bundle::default()
+        };
+        assert!(matches!(
+            bundle.apply_to_dir(&tree, |_| {}),
+            Err(GDeltaError::InvalidDelta(_))
+        ));
+        assert!(!outside.join("planted").exists());
+        assert!(tree.join("escape").is_symlink());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_extracts_single_files_through_index() {
+        use std::io::Cursor;
+
+        let root = temp_dir("index");
+        let (old, new) = (root.join("old"), root.join("new"));
+        let level: Vec<u8> = (0..5_000u32).map(|i| (i * 13 % 251) as u8).collect();
+        let mut changed = level.clone();
+        changed[1000..1004].copy_from_slice(b"edit");
+        write(&old, "levels/one.map", &level);
+        write(&old, "levels/two.map", &level[..3000]);
+        write(&old, "readme.txt", b"unchanged");
+        write(&old, "old.txt", b"removed");
+        write(&new, "levels/one.map", &changed);
+        write(&new, "levels/moved/two.map", &level[..3001]);
+        write(&new, "levels/copy.map", &changed);
+        write(&new, "readme.txt", b"unchanged");
+        write(&new, "new.txt", b"added");
+
+        let bundle = Bundle::diff_dirs(&old, &new, &EncodeOptions::new(), |_| {}).unwrap();
+        let kinds =
+            |wanted: fn(&Entry) -> bool| bundle.entries.iter().filter(|e| wanted(e)).count();
+        assert_eq!(kinds(|entry| matches!(entry, Entry::Duplicate { .. })), 1);
+        assert_eq!(kinds(|entry| matches!(entry, Entry::Renamed { .. })), 1);
+        let bytes = bundle.to_indexed_bytes();
+        assert_eq!(bytes[MAGIC.len()], VERSION);
+        assert_eq!(Bundle::from_bytes(&bytes).unwrap(), bundle);
+
+        let mut reader = Cursor::new(bytes.clone());
+        let index = BundleIndex::read(&mut reader).unwrap();
+        assert_eq!(index.entries().len(), bundle.entries.len());
+        for path in [
+            "levels/one.map",
+            "levels/moved/two.map",
+            "levels/copy.map",
+            "readme.txt",
+            "new.txt",
+        ] {
+            assert_eq!(
+                index.extract(&mut reader, Some(&old), path).unwrap(),
+                fs::read(new.join(path)).unwrap(),
+                "{path}"
+            );
+        }
+        assert_eq!(
+            index.extract(&mut reader, None, "new.txt").unwrap(),
+            b"added"
+        );
+        assert!(matches!(
+            index.extract(&mut reader, None, "levels/one.map"),
+            Err(GDeltaError::InvalidOptions(_))
+        ));
+        assert!(index.extract(&mut reader, Some(&old), "old.txt").is_err());
+
+        // A damaged entry is detected on its own, and breaks the whole bundle
+        let entry = index.get("new.txt").unwrap();
+        let mut damaged = bytes.clone();
+        damaged[(entry.offset + entry.length) as usize - 1] ^= 1;
+        let mut reader = Cursor::new(damaged.clone());
+        let index = BundleIndex::read(&mut reader).unwrap();
+        assert!(matches!(
+            index.extract(&mut reader, Some(&old), "new.txt"),
+            Err(GDeltaError::ChecksumMismatch { .. })
+        ));
+        assert!(index.extract(&mut reader, Some(&old), "readme.txt").is_ok());
+        assert!(Bundle::from_bytes(&damaged).is_err());
+
+        assert!(matches!(
+            BundleIndex::read(&mut Cursor::new(bundle.to_bytes())),
+            Err(GDeltaError::InvalidDelta(_))
+        ));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_streams_bundles_entry_by_entry() {
+        use std::io::Cursor;
+
+        let contents = b"streamed file contents ".repeat(100);
+        let metadata = FileMetadata {
+            mode: Some(0o755),
+            mtime: None,
+            xattrs: None,
+        };
+        let mut writer = BundleWriter::new(Vec::new()).unwrap();
+        writer
+            .add_file("bin/tool", contents.len() as u64, contents.as_slice())
+            .unwrap();
+        writer.set_metadata("bin/tool", metadata.clone()).unwrap();
+        let removed = Entry::Removed {
+            path: "old/tool".to_string(),
+        };
+        writer.add(&removed).unwrap();
+        assert!(matches!(
+            writer.add(&removed),
+            Err(GDeltaError::InvalidOptions(_))
+        ));
+        assert!(matches!(
+            writer.add_file("../escape", 0, io::empty()),
+            Err(GDeltaError::InvalidOptions(_))
+        ));
+        let bytes = writer.finish().unwrap();
+        assert!(matches!(
+            BundleWriter::new(Vec::new())
+                .unwrap()
+                .add_file("short", 10, &b"abc"[..]),
+            Err(GDeltaError::Io(_))
+        ));
+
+        let expected = Bundle {
+            entries: vec![
+                Entry::Added {
+                    path: "bin/tool".to_string(),
+                    contents: contents.clone(),
+                },
+                removed,
+            ],
+            metadata: BTreeMap::from([("bin/tool".to_string(), metadata)]),
+        };
+        assert_eq!(expected.to_indexed_bytes(), bytes);
+        assert_eq!(Bundle::from_bytes(&bytes).unwrap(), expected);
+
+        let mut reader = BundleReader::new(Cursor::new(bytes)).unwrap();
+        let entries: Vec<_> = reader.entries().collect::<Result<_>>().unwrap();
+        assert_eq!(entries, expected.entries);
+        assert_eq!(reader.metadata().unwrap(), expected.metadata);
+        assert_eq!(reader.extract(None, "bin/tool").unwrap(), contents);
+        assert_eq!(reader.read_entry("missing").unwrap(), None);
+    }
+
+    #[test]
+    fn test_rejects_damaged_and_unsafe_bundles() {
+        let bundle = Bundle {
+            entries: vec![Entry::Removed {
+                path: "../outside".to_string(),
+            }],
+            ..Bundle::default()
+        };
+        let mut bytes = bundle.to_bytes();
+        assert!(matches!(
+            Bundle::from_bytes(&bytes),
+            Err(GDeltaError::InvalidDelta(_))
+        ));
+
+        bytes[MAGIC.len() + 3] ^= 1;
+        assert!(matches!(
+            Bundle::from_bytes(&bytes),
+            Err(GDeltaError::ChecksumMismatch { .. })
+        ));
+    }
+}
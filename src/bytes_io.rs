@@ -0,0 +1,84 @@
+//! Decoding with `bytes` buffers.
+
+use crate::buffer::BufferStream;
+use crate::delta::{decode_raw, for_each_slice, has_target_copies, prepare};
+use crate::error::Result;
+use crate::varint::read_delta_unit;
+use bytes::{Bytes, BytesMut};
+
+/// Largest output capacity reserved up front, before copies are bounds-checked.
+const MAX_RESERVE: u64 = 64 * 1024 * 1024;
+
+pub fn decode_bytes(delta: &Bytes, base_data: &Bytes) -> Result<Bytes> {
+    let (raw_deltas, _) = prepare(delta, base_data.len())?;
+
+    // A single instruction is served from the inputs without copying
+    if let [raw] = raw_deltas.as_slice()
+        && !raw.instructions.is_empty()
+    {
+        let mut instructions = BufferStream::from_slice(&raw.instructions);
+        let unit = read_delta_unit(&mut instructions)?;
+        if instructions.remaining() == 0 {
+            if !unit.is_copy {
+                return Ok(delta.slice_ref(raw.data));
+            }
+            let start = unit.offset as usize;
+            let end = start.saturating_add(unit.length as usize);
+            if end <= base_data.len() {
+                return Ok(base_data.slice(start..end));
+            }
+        }
+    }
+
+    let mut output = BytesMut::new();
+    decode_into(delta, base_data, &mut output)?;
+    Ok(output.freeze())
+}
+
+pub fn decode_into(delta: &[u8], base_data: &[u8], output: &mut BytesMut) -> Result<()> {
+    let (raw_deltas, output_size) = prepare(delta, base_data.len())?;
+    if has_target_copies(&raw_deltas) {
+        output.extend_from_slice(&decode_raw(&raw_deltas, base_data)?);
+        return Ok(());
+    }
+    output.reserve(output_size.min(MAX_RESERVE) as usize);
+
+    // Roll back on failure so nothing is appended
+    let start = output.len();
+    let result = for_each_slice(&raw_deltas, base_data, |slice| {
+        output.extend_from_slice(slice);
+        Ok(())
+    });
+    if result.is_err() {
+        output.truncate(start);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_single_copy_shares_base() {
+        let base = Bytes::from_static(b"Hello, World! Hello, World!");
+        let delta = Bytes::from(crate::encode(&base[..], &base[..]).unwrap());
+
+        let output = decode_bytes(&delta, &base).unwrap();
+        assert_eq!(output, base);
+        assert_eq!(output.as_ptr(), base.as_ptr());
+    }
+
+    #[test]
+    fn test_decode_bytes_matches_decode() {
+        let base = Bytes::from_static(b"The quick brown fox jumps over the lazy dog");
+        let new = b"The quick brown cat jumps over the lazy dog";
+        let delta = Bytes::from(crate::encode(new, &base[..]).unwrap());
+
+        assert_eq!(decode_bytes(&delta, &base).unwrap(), &new[..]);
+
+        let mut output = BytesMut::from(&b"prefix:"[..]);
+        decode_into(&delta, &base, &mut output).unwrap();
+        assert_eq!(&output[7..], new);
+    }
+}
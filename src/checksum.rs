@@ -0,0 +1,213 @@
+//! CRC-32 checksum used to protect encoded deltas and to verify decoded output.
+
+use std::io::{self, IoSlice, Write};
+
+/// Reflected polynomial of the IEEE 802.3 CRC-32.
+const POLYNOMIAL: u32 = 0xEDB8_8320;
+
+/// Lookup table for byte-at-a-time CRC computation.
+const CRC_TABLE: [u32; 256] = build_table();
+
+const fn build_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 0 {
+                crc >> 1
+            } else {
+                (crc >> 1) ^ POLYNOMIAL
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// Incremental CRC-32 (IEEE) computation.
+#[derive(Debug, Clone, Copy)]
+pub struct Crc32(u32);
+
+impl Crc32 {
+    /// Starts a new checksum.
+    pub fn new() -> Self {
+        Self(!0)
+    }
+
+    /// Continues a checksum from the value [`Crc32::finish`] returned.
+    pub fn resume(checksum: u32) -> Self {
+        Self(!checksum)
+    }
+
+    /// Feeds `data` into the checksum.
+    pub fn update(&mut self, data: &[u8]) {
+        self.0 = data.iter().fold(self.0, |crc, &byte| {
+            CRC_TABLE[((crc ^ u32::from(byte)) & 0xFF) as usize] ^ (crc >> 8)
+        });
+    }
+
+    /// Returns the checksum of all data fed so far.
+    pub fn finish(self) -> u32 {
+        !self.0
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Computes the CRC-32 (IEEE) of `data`.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(data);
+    crc.finish()
+}
+
+/// A writer that computes the CRC-32 (IEEE) of everything written through
+/// it.
+///
+/// Wrap the output of [`crate::decode_to_writer`] or
+/// [`crate::decode_from_source`] in it to checksum the target while it is
+/// written, instead of reading it back afterwards. The checksum is the
+/// common CRC-32 of zlib and gzip, so it can be compared against values
+/// computed by other tools.
+#[derive(Debug)]
+pub struct ChecksumWriter<W> {
+    inner: W,
+    crc: Crc32,
+    written: u64,
+}
+
+impl<W: Write> ChecksumWriter<W> {
+    /// Wraps `inner`.
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            crc: Crc32::new(),
+            written: 0,
+        }
+    }
+
+    /// Returns the CRC-32 of the bytes written so far.
+    pub fn checksum(&self) -> u32 {
+        self.crc.finish()
+    }
+
+    /// Returns the number of bytes written so far.
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+
+    /// Continues a checksum over `written` bytes that ended in `checksum`,
+    /// writing the rest to `inner`.
+    pub(crate) fn resume(inner: W, checksum: u32, written: u64) -> Self {
+        Self {
+            inner,
+            crc: Crc32::resume(checksum),
+            written,
+        }
+    }
+
+    /// Returns a reference to the inner writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the inner writer.
+    ///
+    /// Bytes written to it directly are not included in the checksum.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Returns the inner writer.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.crc.update(&buf[..n]);
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        let n = self.inner.write_vectored(bufs)?;
+        let mut left = n;
+        for buf in bufs {
+            let take = left.min(buf.len());
+            self.crc.update(&buf[..take]);
+            left -= take;
+        }
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Length and CRC-32 of a decoded target; see
+/// [`crate::decode_to_writer_checksummed`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TargetChecksum {
+    /// Number of bytes written.
+    pub len: u64,
+    /// CRC-32 (IEEE) of the bytes written.
+    pub crc32: u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32_known_values() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(
+            crc32(b"The quick brown fox jumps over the lazy dog"),
+            0x414F_A339
+        );
+    }
+
+    #[test]
+    fn test_resume_matches_one_shot() {
+        let mut crc = Crc32::resume(crc32(b"The quick brown fox "));
+        crc.update(b"jumps over the lazy dog");
+        assert_eq!(crc.finish(), 0x414F_A339);
+    }
+
+    #[test]
+    fn test_writer_checksums_vectored_writes() {
+        let mut writer = ChecksumWriter::new(Vec::new());
+        let slices = [
+            IoSlice::new(b"The quick brown fox "),
+            IoSlice::new(b"jumps over the lazy dog"),
+        ];
+        assert_eq!(writer.write_vectored(&slices).unwrap(), 43);
+        assert_eq!(writer.checksum(), 0x414F_A339);
+        assert_eq!(writer.written(), 43);
+        assert_eq!(writer.into_inner().len(), 43);
+    }
+
+    #[test]
+    fn test_incremental_matches_one_shot() {
+        let data = b"The quick brown fox jumps over the lazy dog";
+        let mut crc = Crc32::new();
+        for chunk in data.chunks(5) {
+            crc.update(chunk);
+        }
+        assert_eq!(crc.finish(), crc32(data));
+    }
+}
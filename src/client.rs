@@ -0,0 +1,371 @@
+//! Clients for the HTTP service of the [`crate::server`] module.
+//!
+//! Enabled by the `client` feature. [`Client`] is used from async code and
+//! [`BlockingClient`] from ordinary threads; both send the same requests
+//! and stream response bodies into a writer as they arrive.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use gdelta::client::BlockingClient;
+//!
+//! let client = BlockingClient::new("http://patches.example.com");
+//! let local = std::fs::read("app-v1.bin")?;
+//! if client.signature("app-v1.bin")?.matches(&local) {
+//!     let mut patched = Vec::new();
+//!     let new = std::fs::File::open("app-v2.bin")?;
+//!     let mut delta = Vec::new();
+//!     client.delta("app-v1.bin", new, &mut delta)?;
+//!     client.apply("app-v1.bin", delta, &mut patched)?;
+//! }
+//! # Ok::<(), gdelta::GDeltaError>(())
+//! ```
+
+use crate::error::{GDeltaError, Result};
+use crate::naming::ContentHash;
+use std::io::Write;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+/// Identity of a base on the server, as returned by `GET /signature`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    /// Name of the base.
+    pub base: String,
+    /// Size of the base in bytes.
+    pub size: u64,
+    /// BLAKE3 hash of the base.
+    pub hash: ContentHash,
+    /// Newest delta format version the server writes and decodes.
+    pub format_version: u8,
+    /// Oldest delta format version the server decodes.
+    pub min_format_version: u8,
+}
+
+impl Signature {
+    /// Returns whether `data` is the base the signature describes.
+    pub fn matches(&self, data: &[u8]) -> bool {
+        data.len() as u64 == self.size && ContentHash::of(data) == self.hash
+    }
+
+    /// Parses the JSON body of a `GET /signature` response.
+    fn parse(body: &[u8]) -> Result<Self> {
+        let invalid = || GDeltaError::Io("Invalid signature response".to_string());
+        let value: serde_json::Value = serde_json::from_slice(body).map_err(|_| invalid())?;
+        let version = |key| {
+            value[key]
+                .as_u64()
+                .and_then(|version| u8::try_from(version).ok())
+                .ok_or_else(invalid)
+        };
+        Ok(Self {
+            base: value["base"].as_str().ok_or_else(invalid)?.to_string(),
+            size: value["size"].as_u64().ok_or_else(invalid)?,
+            hash: value["blake3"]
+                .as_str()
+                .and_then(ContentHash::from_hex)
+                .ok_or_else(invalid)?,
+            format_version: version("format_version")?,
+            min_format_version: version("min_format_version")?,
+        })
+    }
+}
+
+/// A client of the delta service for async code.
+#[derive(Debug, Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    url: String,
+}
+
+impl Client {
+    /// Creates a client of the service at `url`, such as
+    /// `http://localhost:8080` or a URL the routes are nested under.
+    pub fn new(url: impl Into<String>) -> Self {
+        Self::with_http_client(reqwest::Client::new(), url)
+    }
+
+    /// Creates a client sending its requests with `http`, for example to
+    /// set timeouts or default headers.
+    pub fn with_http_client(http: reqwest::Client, url: impl Into<String>) -> Self {
+        Self {
+            http,
+            url: url.into().trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// Returns the signature of the base named `base`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `GDeltaError::Io` if the request fails, the server answers
+    /// with an error, such as for an unknown base, or the answer is invalid.
+    pub async fn signature(&self, base: &str) -> Result<Signature> {
+        let request = self.http.get(endpoint(&self.url, "signature"));
+        let response = checked(request.query(&[("base", base)]).send().await).await?;
+        Signature::parse(&response.bytes().await.map_err(http_error)?)
+    }
+
+    /// Sends `new` to be encoded against the base named `base` and writes
+    /// the delta to `output`. Returns the size of the delta.
+    ///
+    /// # Errors
+    ///
+    /// Returns `GDeltaError::Io` if the request fails, the server answers
+    /// with an error or writing fails.
+    pub async fn delta<W>(
+        &self,
+        base: &str,
+        new: impl Into<reqwest::Body>,
+        output: &mut W,
+    ) -> Result<u64>
+    where
+        W: AsyncWrite + Unpin + ?Sized,
+    {
+        self.post("delta", base, new.into(), output).await
+    }
+
+    /// Sends `delta` to be applied to the base named `base` and writes the
+    /// target to `output`. Returns the size of the target.
+    ///
+    /// # Errors
+    ///
+    /// Returns `GDeltaError::Io` if the request fails, the server answers
+    /// with an error, for example because the delta is invalid, or writing
+    /// fails.
+    pub async fn apply<W>(
+        &self,
+        base: &str,
+        delta: impl Into<reqwest::Body>,
+        output: &mut W,
+    ) -> Result<u64>
+    where
+        W: AsyncWrite + Unpin + ?Sized,
+    {
+        self.post("apply", base, delta.into(), output).await
+    }
+
+    async fn post<W>(
+        &self,
+        route: &str,
+        base: &str,
+        body: reqwest::Body,
+        output: &mut W,
+    ) -> Result<u64>
+    where
+        W: AsyncWrite + Unpin + ?Sized,
+    {
+        let request = self.http.post(endpoint(&self.url, route));
+        let request = request.query(&[("base", base)]).body(body);
+        let mut response = checked(request.send().await).await?;
+        let mut written = 0;
+        while let Some(chunk) = response.chunk().await.map_err(http_error)? {
+            output.write_all(&chunk).await?;
+            written += chunk.len() as u64;
+        }
+        output.flush().await?;
+        Ok(written)
+    }
+}
+
+/// A client of the delta service for blocking code.
+///
+/// Must not be used from within an async runtime; use [`Client`] there.
+#[derive(Debug, Clone)]
+pub struct BlockingClient {
+    http: reqwest::blocking::Client,
+    url: String,
+}
+
+impl BlockingClient {
+    /// Creates a client of the service at `url`, such as
+    /// `http://localhost:8080` or a URL the routes are nested under.
+    pub fn new(url: impl Into<String>) -> Self {
+        Self::with_http_client(reqwest::blocking::Client::new(), url)
+    }
+
+    /// Creates a client sending its requests with `http`, for example to
+    /// set timeouts or default headers.
+    pub fn with_http_client(http: reqwest::blocking::Client, url: impl Into<String>) -> Self {
+        Self {
+            http,
+            url: url.into().trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// Returns the signature of the base named `base`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `GDeltaError::Io` if the request fails, the server answers
+    /// with an error, such as for an unknown base, or the answer is invalid.
+    pub fn signature(&self, base: &str) -> Result<Signature> {
+        let request = self.http.get(endpoint(&self.url, "signature"));
+        let response = checked_blocking(request.query(&[("base", base)]).send())?;
+        Signature::parse(&response.bytes().map_err(http_error)?)
+    }
+
+    /// Sends `new`, for example a `Vec<u8>` or a `File`, to be encoded
+    /// against the base named `base` and writes the delta to `output`.
+    /// Returns the size of the delta.
+    ///
+    /// # Errors
+    ///
+    /// Returns `GDeltaError::Io` if the request fails, the server answers
+    /// with an error or writing fails.
+    pub fn delta<W: Write>(
+        &self,
+        base: &str,
+        new: impl Into<reqwest::blocking::Body>,
+        output: W,
+    ) -> Result<u64> {
+        self.post("delta", base, new.into(), output)
+    }
+
+    /// Sends `delta` to be applied to the base named `base` and writes the
+    /// target to `output`. Returns the size of the target.
+    ///
+    /// # Errors
+    ///
+    /// Returns `GDeltaError::Io` if the request fails, the server answers
+    /// with an error, for example because the delta is invalid, or writing
+    /// fails.
+    pub fn apply<W: Write>(
+        &self,
+        base: &str,
+        delta: impl Into<reqwest::blocking::Body>,
+        output: W,
+    ) -> Result<u64> {
+        self.post("apply", base, delta.into(), output)
+    }
+
+    fn post<W: Write>(
+        &self,
+        route: &str,
+        base: &str,
+        body: reqwest::blocking::Body,
+        mut output: W,
+    ) -> Result<u64> {
+        let request = self.http.post(endpoint(&self.url, route));
+        let request = request.query(&[("base", base)]).body(body);
+        let mut response = checked_blocking(request.send())?;
+        let written = response.copy_to(&mut output).map_err(http_error)?;
+        output.flush()?;
+        Ok(written)
+    }
+}
+
+/// Returns the URL of `route`; the base name is added as a query
+/// parameter, which percent-encodes it.
+fn endpoint(url: &str, route: &str) -> String {
+    format!("{url}/{route}")
+}
+
+fn http_error(error: reqwest::Error) -> GDeltaError {
+    GDeltaError::Io(error.to_string())
+}
+
+/// Turns an answer with an error status into an error with the message
+/// the server sent.
+async fn checked(response: reqwest::Result<reqwest::Response>) -> Result<reqwest::Response> {
+    let response = response.map_err(http_error)?;
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let message = response.text().await.unwrap_or_default();
+    Err(status_error(status, &message))
+}
+
+fn checked_blocking(
+    response: reqwest::Result<reqwest::blocking::Response>,
+) -> Result<reqwest::blocking::Response> {
+    let response = response.map_err(http_error)?;
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let message = response.text().unwrap_or_default();
+    Err(status_error(status, &message))
+}
+
+fn status_error(status: reqwest::StatusCode, message: &str) -> GDeltaError {
+    GDeltaError::Io(format!("Server answered {status}: {}", message.trim()))
+}
+
+#[cfg(all(test, feature = "server"))]
+mod tests {
+    use super::*;
+    use crate::server::PatchService;
+
+    /// Serves a directory holding `base` as `v1.bin` on a runtime of its
+    /// own, returning the runtime and the URL.
+    fn serve(name: &str, base: &[u8]) -> (tokio::runtime::Runtime, String) {
+        let dir = std::env::temp_dir().join(format!("gdelta-client-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("v1.bin"), base).unwrap();
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let listener = runtime
+            .block_on(tokio::net::TcpListener::bind("127.0.0.1:0"))
+            .unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        runtime.spawn(async move { axum::serve(listener, PatchService::new(dir).router()).await });
+        (runtime, url)
+    }
+
+    fn versions() -> (Vec<u8>, Vec<u8>) {
+        let base: Vec<u8> = (0..30_000u32)
+            .flat_map(|i| format!("entry {i}\n").into_bytes())
+            .collect();
+        let mut new = base.clone();
+        new.splice(100_000..100_000, b"inserted".iter().copied());
+        (base, new)
+    }
+
+    #[test]
+    fn test_blocking_client() {
+        let (base, new) = versions();
+        let (_runtime, url) = serve("blocking", &base);
+        let client = BlockingClient::new(url);
+
+        let signature = client.signature("v1.bin").unwrap();
+        assert!(signature.matches(&base));
+        assert!(!signature.matches(&new));
+        assert_eq!(signature.format_version, crate::FORMAT_VERSION);
+
+        let mut delta = Vec::new();
+        let size = client.delta("v1.bin", new.clone(), &mut delta).unwrap();
+        assert_eq!(size, delta.len() as u64);
+        let mut target = Vec::new();
+        client.apply("v1.bin", delta, &mut target).unwrap();
+        assert_eq!(target, new);
+
+        let error = client.signature("v2.bin").unwrap_err();
+        assert!(error.to_string().contains("404"), "{error}");
+        // The whole name is one parameter, not `v1.bin` and another one
+        let error = client.signature("v1.bin&format=json").unwrap_err();
+        assert!(error.to_string().contains("404"), "{error}");
+        assert!(client.apply("v1.bin", vec![0xff; 4], Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_async_client() {
+        let (base, new) = versions();
+        let (runtime, url) = serve("async", &base);
+        runtime.block_on(async {
+            let client = Client::new(url);
+            assert!(client.signature("v1.bin").await.unwrap().matches(&base));
+
+            let mut delta = Vec::new();
+            client
+                .delta("v1.bin", new.clone(), &mut delta)
+                .await
+                .unwrap();
+            let mut target = Vec::new();
+            client.apply("v1.bin", delta, &mut target).await.unwrap();
+            assert_eq!(target, new);
+
+            assert!(client.signature("../v1.bin").await.is_err());
+        });
+    }
+}
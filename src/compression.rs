@@ -0,0 +1,393 @@
+//! Secondary compression of deltas with pluggable codecs.
+//!
+//! Deltas are often compressed once more before they are stored or sent,
+//! since literal data usually still compresses well. The library does not
+//! depend on any codec: implement [`PostCompressor`] for brotli, zstd,
+//! snappy or an in-house codec, and [`wrap`] a delta with it. The wrapped
+//! delta records the codec's id, so [`unwrap`] can pick the right codec
+//! from a list of candidates and passes unwrapped deltas through unchanged:
+//!
+//! ```text
+//! [magic: 0x80 0x00 'G' 'Z'][codec id: 4][compressed delta...]
+//! ```
+//!
+//! Like the delta header, the magic begins with a non-canonical varint, so
+//! wrapped deltas cannot be mistaken for raw or headered ones.
+//!
+//! Literal data that is already compressed or random grows when it is
+//! compressed again. [`wrap_streams`] therefore compresses the instructions
+//! and the literal data of a delta separately, keeps each part only if it
+//! got smaller, and records which parts it kept compressed:
+//!
+//! ```text
+//! [magic: 0x80 0x00 'G' 'S'][codec id: 4][flags: u8]
+//! [instruction part length: varint][instruction part][literal part]
+//! ```
+//!
+//! # Examples
+//!
+//! ```
+//! use gdelta::compression::{PostCompressor, unwrap, wrap};
+//!
+//! /// Stores deltas as they are; a real codec would compress them.
+//! struct Store;
+//!
+//! impl PostCompressor for Store {
+//!     fn id(&self) -> [u8; 4] {
+//!         *b"stor"
+//!     }
+//!
+//!     fn compress(&self, data: &[u8]) -> gdelta::Result<Vec<u8>> {
+//!         Ok(data.to_vec())
+//!     }
+//!
+//!     fn decompress(&self, data: &[u8]) -> gdelta::Result<Vec<u8>> {
+//!         Ok(data.to_vec())
+//!     }
+//! }
+//!
+//! let base = b"Hello, World!";
+//! let delta = gdelta::encode(b"Hello, Rust!", base)?;
+//!
+//! let wrapped = wrap(&delta, &Store)?;
+//! let unwrapped = unwrap(&wrapped, &[&Store])?;
+//! assert_eq!(gdelta::decode(&unwrapped, base)?, b"Hello, Rust!");
+//! # Ok::<(), gdelta::GDeltaError>(())
+//! ```
+
+use crate::buffer::BufferStream;
+use crate::error::{GDeltaError, Result};
+use crate::header;
+use crate::varint::{read_varint, write_varint};
+use std::borrow::Cow;
+
+/// Marker identifying a wrapped delta.
+pub const MAGIC: [u8; 4] = [0x80, 0x00, b'G', b'Z'];
+
+/// Marker identifying a delta wrapped with [`wrap_streams`].
+pub const STREAMS_MAGIC: [u8; 4] = [0x80, 0x00, b'G', b'S'];
+
+/// Length of the magic and codec id preceding the compressed data.
+const PREFIX_LEN: usize = MAGIC.len() + 4;
+
+/// The instruction part is compressed.
+const FLAG_INSTRUCTIONS: u8 = 0x01;
+
+/// The literal part is compressed.
+const FLAG_LITERALS: u8 = 0x02;
+
+/// A codec for compressing whole deltas.
+pub trait PostCompressor {
+    /// Returns the id recorded in wrapped deltas.
+    ///
+    /// Ids must be unique among the compressors passed to [`unwrap`] and
+    /// must never change for a codec, or stored deltas become unreadable.
+    fn id(&self) -> [u8; 4];
+
+    /// Compresses `data`.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error of the codec, for instance as `GDeltaError::Io`.
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>>;
+
+    /// Reverses [`PostCompressor::compress`].
+    ///
+    /// # Errors
+    ///
+    /// Returns any error of the codec, typically as
+    /// `GDeltaError::InvalidDelta` for corrupted input.
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>>;
+}
+
+impl<C: PostCompressor + ?Sized> PostCompressor for &C {
+    fn id(&self) -> [u8; 4] {
+        (**self).id()
+    }
+
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        (**self).compress(data)
+    }
+
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        (**self).decompress(data)
+    }
+}
+
+/// Compresses `delta` with `compressor` and records the codec's id.
+///
+/// # Errors
+///
+/// Returns any error of the compressor.
+pub fn wrap(delta: &[u8], compressor: &dyn PostCompressor) -> Result<Vec<u8>> {
+    let compressed = compressor.compress(delta)?;
+    let mut wrapped = Vec::with_capacity(PREFIX_LEN + compressed.len());
+    wrapped.extend_from_slice(&MAGIC);
+    wrapped.extend_from_slice(&compressor.id());
+    wrapped.extend_from_slice(&compressed);
+    Ok(wrapped)
+}
+
+/// Compresses the instructions and the literal data of `delta` separately
+/// with `compressor`, keeping each part uncompressed if compressing does
+/// not make it smaller.
+///
+/// The instruction part runs from the start of the delta to its literal
+/// data and the literal part holds the rest, including any checksum
+/// trailer. Segmented and identity deltas are not split; they are treated
+/// as a single instruction part.
+///
+/// # Errors
+///
+/// Returns any error of the compressor.
+pub fn wrap_streams(delta: &[u8], compressor: &dyn PostCompressor) -> Result<Vec<u8>> {
+    let (instructions, literals) = delta.split_at(literal_offset(delta).unwrap_or(delta.len()));
+    let mut flags = 0;
+    let instructions =
+        compress_if_smaller(instructions, compressor, FLAG_INSTRUCTIONS, &mut flags)?;
+    let literals = compress_if_smaller(literals, compressor, FLAG_LITERALS, &mut flags)?;
+
+    let mut wrapped =
+        BufferStream::with_capacity(PREFIX_LEN + 11 + instructions.len() + literals.len());
+    wrapped.write_bytes(&STREAMS_MAGIC);
+    wrapped.write_bytes(&compressor.id());
+    wrapped.write_u8(flags);
+    write_varint(&mut wrapped, instructions.len() as u64);
+    wrapped.write_bytes(&instructions);
+    wrapped.write_bytes(&literals);
+    Ok(wrapped.into_vec())
+}
+
+/// Returns where the literal data of an unsegmented delta starts.
+fn literal_offset(delta: &[u8]) -> Option<usize> {
+    let framed = header::parse(delta).ok()?;
+    if framed
+        .header
+        .is_some_and(|header| header.is_segmented() || header.is_identity())
+    {
+        return None;
+    }
+    let mut stream = BufferStream::from_slice(&framed.payload[..framed.payload.len().min(10)]);
+    let instruction_len = usize::try_from(read_varint(&mut stream).ok()?).ok()?;
+    let offset = (framed.payload_offset + stream.position()).checked_add(instruction_len)?;
+    (offset <= framed.payload_offset + framed.payload.len()).then_some(offset)
+}
+
+/// Compresses `data` and sets `flag` if that makes it smaller.
+fn compress_if_smaller<'a>(
+    data: &'a [u8],
+    compressor: &dyn PostCompressor,
+    flag: u8,
+    flags: &mut u8,
+) -> Result<Cow<'a, [u8]>> {
+    if data.is_empty() {
+        return Ok(Cow::Borrowed(data));
+    }
+    let compressed = compressor.compress(data)?;
+    if compressed.len() < data.len() {
+        *flags |= flag;
+        Ok(Cow::Owned(compressed))
+    } else {
+        Ok(Cow::Borrowed(data))
+    }
+}
+
+/// Returns the codec id of a wrapped delta, or `None` if `data` is not
+/// wrapped.
+pub fn wrapped_id(data: &[u8]) -> Option<[u8; 4]> {
+    let id = data
+        .strip_prefix(&MAGIC)
+        .or_else(|| data.strip_prefix(&STREAMS_MAGIC))?
+        .get(..4)?;
+    Some([id[0], id[1], id[2], id[3]])
+}
+
+/// Decompresses a delta produced by [`wrap`] or [`wrap_streams`] with the
+/// compressor from `compressors` that has its id. Data that is not wrapped
+/// is returned unchanged, so plain deltas can be passed in as well.
+///
+/// # Errors
+///
+/// Returns `GDeltaError::InvalidDelta` if the delta is wrapped with a
+/// codec none of `compressors` has the id of, and any error of the
+/// matching compressor.
+pub fn unwrap<'a>(data: &'a [u8], compressors: &[&dyn PostCompressor]) -> Result<Cow<'a, [u8]>> {
+    let streams = data.starts_with(&STREAMS_MAGIC);
+    if !streams && !data.starts_with(&MAGIC) {
+        return Ok(Cow::Borrowed(data));
+    }
+    let id = wrapped_id(data).ok_or(GDeltaError::UnexpectedEndOfData)?;
+    let compressor = || {
+        compressors
+            .iter()
+            .find(|compressor| compressor.id() == id)
+            .ok_or_else(|| {
+                GDeltaError::InvalidDelta(format!(
+                    "No compressor with id {:?}",
+                    String::from_utf8_lossy(&id)
+                ))
+            })
+    };
+    if !streams {
+        return compressor()?
+            .decompress(&data[PREFIX_LEN..])
+            .map(Cow::Owned);
+    }
+
+    let fields = &data[PREFIX_LEN..data.len().min(PREFIX_LEN + 11)];
+    let mut stream = BufferStream::from_slice(fields);
+    let flags = stream.read_u8()?;
+    if flags & !(FLAG_INSTRUCTIONS | FLAG_LITERALS) != 0 {
+        return Err(GDeltaError::InvalidDelta(format!(
+            "Unknown stream flags {flags:#04x}"
+        )));
+    }
+    let instruction_len = usize::try_from(read_varint(&mut stream)?).unwrap_or(usize::MAX);
+    let rest = &data[PREFIX_LEN + stream.position()..];
+    if instruction_len > rest.len() {
+        return Err(GDeltaError::UnexpectedEndOfData);
+    }
+    let mut delta = Vec::with_capacity(rest.len());
+    for (part, flag) in [
+        (&rest[..instruction_len], FLAG_INSTRUCTIONS),
+        (&rest[instruction_len..], FLAG_LITERALS),
+    ] {
+        if flags & flag != 0 {
+            delta.extend_from_slice(&compressor()?.decompress(part)?);
+        } else {
+            delta.extend_from_slice(part);
+        }
+    }
+    Ok(Cow::Owned(delta))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run-length codec: pairs of (count, byte).
+    struct RunLength;
+
+    impl PostCompressor for RunLength {
+        fn id(&self) -> [u8; 4] {
+            *b"rle1"
+        }
+
+        fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+            let mut out = Vec::new();
+            for run in data.chunk_by(|a, b| a == b) {
+                for chunk in run.chunks(255) {
+                    out.extend_from_slice(&[chunk.len() as u8, chunk[0]]);
+                }
+            }
+            Ok(out)
+        }
+
+        fn decompress(&self, data: &[u8]) -> Result<Vec<u8>> {
+            if data.len() % 2 != 0 {
+                return Err(GDeltaError::InvalidDelta("odd run-length data".into()));
+            }
+            Ok(data
+                .chunks(2)
+                .flat_map(|pair| std::iter::repeat_n(pair[1], usize::from(pair[0])))
+                .collect())
+        }
+    }
+
+    /// Always fails to decompress.
+    struct Broken;
+
+    impl PostCompressor for Broken {
+        fn id(&self) -> [u8; 4] {
+            *b"brkn"
+        }
+
+        fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+            Ok(data.to_vec())
+        }
+
+        fn decompress(&self, _data: &[u8]) -> Result<Vec<u8>> {
+            Err(GDeltaError::InvalidDelta("broken".into()))
+        }
+    }
+
+    #[test]
+    fn test_wrap_roundtrip_picks_codec_by_id() {
+        let base = vec![0u8; 1000];
+        let new = vec![1u8; 2000];
+        let delta = crate::encode(&new, &base).unwrap();
+
+        let wrapped = wrap(&delta, &RunLength).unwrap();
+        assert!(wrapped.len() < delta.len());
+        assert_eq!(wrapped_id(&wrapped), Some(*b"rle1"));
+
+        let unwrapped = unwrap(&wrapped, &[&Broken, &RunLength]).unwrap();
+        assert_eq!(crate::decode(&unwrapped, &base).unwrap(), new);
+        assert!(matches!(
+            unwrap(&delta, &[]).unwrap(),
+            Cow::Borrowed(data) if data == delta
+        ));
+    }
+
+    #[test]
+    fn test_streams_are_only_compressed_if_smaller() {
+        let base: Vec<u8> = (0..4000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let mut state = 0x2545_F491_4F6C_DD1Du64;
+        let noise: Vec<u8> = (0..3000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect();
+
+        let mut runs = base.clone();
+        runs.extend_from_slice(&[0u8; 3000]);
+        let mut random = base.clone();
+        random.extend_from_slice(&noise);
+        for (new, flags) in [(&runs, FLAG_LITERALS), (&random, 0)] {
+            for options in [
+                crate::EncodeOptions::new(),
+                crate::EncodeOptions::new().checksum(true),
+                crate::EncodeOptions::new().rsyncable(true),
+            ] {
+                let delta = crate::encode_with_options(new, &base, &options).unwrap();
+                let wrapped = wrap_streams(&delta, &RunLength).unwrap();
+                assert!(wrapped.len() <= delta.len() + 11);
+                if !options.rsyncable {
+                    assert_eq!(wrapped[8], flags, "{options:?}");
+                }
+                assert_eq!(wrapped_id(&wrapped), Some(*b"rle1"));
+                let unwrapped = unwrap(&wrapped, &[&RunLength]).unwrap();
+                assert_eq!(unwrapped, delta);
+            }
+        }
+
+        // Whole-delta wrapping inflates the random literals
+        let delta = crate::encode(&random, &base).unwrap();
+        assert!(wrap(&delta, &RunLength).unwrap().len() > delta.len() * 3 / 2);
+    }
+
+    #[test]
+    fn test_unwrap_errors() {
+        let wrapped = wrap(b"data", &Broken).unwrap();
+        assert!(matches!(
+            unwrap(&wrapped, &[&RunLength]),
+            Err(GDeltaError::InvalidDelta(_))
+        ));
+        assert!(unwrap(&wrapped, &[&Broken]).is_err());
+        assert_eq!(
+            unwrap(&MAGIC, &[&RunLength]),
+            Err(GDeltaError::UnexpectedEndOfData)
+        );
+
+        let mut wrapped = wrap_streams(b"\x02abcd", &RunLength).unwrap();
+        assert_eq!(*unwrap(&wrapped, &[]).unwrap(), b"\x02abcd"[..]);
+        wrapped[8] = 0x04;
+        assert!(matches!(
+            unwrap(&wrapped, &[&RunLength]),
+            Err(GDeltaError::InvalidDelta(_))
+        ));
+    }
+}
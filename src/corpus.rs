@@ -0,0 +1,234 @@
+//! Seed corpus of well-formed but extreme deltas.
+
+use crate::buffer::BufferStream;
+use crate::delta::finalize_delta;
+use crate::header::{
+    DeltaHeader, FLAG_CHECKSUM, FLAG_IDENTITY, FLAG_SEGMENTED, FLAG_TARGET_CHECKSUM,
+    FLAG_TARGET_COPIES, FLAG_TARGET_SIZE, VERSION,
+};
+use crate::varint::{DeltaUnit, write_delta_unit, write_varint};
+
+/// Doublings of the target-copy chain, bounding its output to 1 MiB.
+const DOUBLINGS: u32 = 20;
+
+/// Copies of the whole base in the repeated-copy delta.
+const REPEATS: u64 = 4096;
+
+/// Builds a raw delta from instructions and literal data.
+fn raw(units: &[DeltaUnit], data: &[u8]) -> Vec<u8> {
+    let mut instructions = BufferStream::with_capacity(units.len() * 4);
+    for unit in units {
+        write_delta_unit(&mut instructions, unit);
+    }
+    finalize_delta(&instructions, &BufferStream::from_slice(data))
+}
+
+/// Wraps `payload` in a version 1 header with `flags`.
+fn framed(flags: u8, target_size: Option<u64>, payload: &[u8]) -> Vec<u8> {
+    let header = DeltaHeader {
+        version: VERSION,
+        flags: flags | target_size.map_or(0, |_| FLAG_TARGET_SIZE),
+        target_size,
+        target_checksum: None,
+        base_checksum: None,
+        metadata: &[],
+    };
+    header.wrap(payload)
+}
+
+/// Writes `value` as a varint padded to the maximum of ten bytes.
+fn padded_varint(stream: &mut BufferStream, value: u64) {
+    for i in 0..9 {
+        stream.write_u8(((value >> (7 * i)) & 0x7F) as u8 | 0x80);
+    }
+    stream.write_u8((value >> 63) as u8);
+}
+
+/// Returns deltas for a base of `base_len` bytes that parse as far as the
+/// format allows but push every field to an extreme: huge and maximal
+/// lengths and offsets, non-canonical ten-byte varints, copies ending
+/// exactly at or just past the end of the base, overlapping and doubling
+/// copies from the target, empty instructions and segments, a wrong target
+/// checksum, and unusual header flag combinations.
+///
+/// Each delta is returned with a short name suitable as a file name. Some
+/// decode successfully and some must be rejected; none may cause a panic or
+/// an allocation beyond a few MiB. Use them as a seed corpus for fuzzing
+/// and as regression inputs.
+///
+/// # Examples
+///
+/// ```
+/// let base = vec![7u8; 256];
+/// for (name, delta) in gdelta::format::seed_corpus(base.len()) {
+///     // Either result is fine, as long as decoding returns
+///     let _ = gdelta::decode(&delta, &base);
+///     assert!(!name.is_empty());
+/// }
+/// ```
+#[allow(clippy::cast_possible_truncation)]
+pub fn seed_corpus(base_len: usize) -> Vec<(&'static str, Vec<u8>)> {
+    let base = base_len as u64;
+    let copy = DeltaUnit::copy;
+    let literal = DeltaUnit::literal;
+    let mut corpus = vec![
+        ("empty", raw(&[], &[])),
+        (
+            "zero-length-units",
+            raw(
+                &[literal(0), copy(0, 0), copy(u64::MAX, 0), literal(0)],
+                &[],
+            ),
+        ),
+        ("copy-whole-base", raw(&[copy(0, base)], &[])),
+        (
+            "copy-ending-at-base-end",
+            raw(&[copy(base / 2, base - base / 2)], &[]),
+        ),
+        (
+            "copy-one-past-base-end",
+            raw(&[copy(base / 2, base - base / 2 + 1)], &[]),
+        ),
+        ("copy-max-length", raw(&[copy(0, u64::MAX)], &[])),
+        ("copy-max-offset", raw(&[copy(u64::MAX, 1)], &[])),
+        ("copy-wrapping-end", raw(&[copy(u64::MAX - 1, 2)], &[])),
+        ("literal-max-length", raw(&[literal(u64::MAX)], b"x")),
+        (
+            "literal-lengths-overflowing",
+            raw(&[literal(u64::MAX), literal(2)], b"x"),
+        ),
+        (
+            "repeated-whole-base-copies",
+            raw(&vec![copy(0, base); REPEATS as usize], &[]),
+        ),
+    ];
+
+    // Instruction length as the largest varint
+    let mut stream = BufferStream::with_capacity(10);
+    write_varint(&mut stream, u64::MAX);
+    corpus.push(("instruction-length-max", stream.into_vec()));
+
+    // A valid copy with every varint padded to ten bytes
+    let mut instructions = BufferStream::with_capacity(32);
+    instructions.write_u8(0xC0 | (base & 0x3F) as u8);
+    padded_varint(&mut instructions, base >> 6);
+    padded_varint(&mut instructions, 0);
+    let mut stream = BufferStream::with_capacity(64);
+    padded_varint(&mut stream, instructions.len() as u64);
+    stream.write_bytes(instructions.as_slice());
+    corpus.push(("padded-varints", stream.into_vec()));
+
+    // Copies from the target overlapping the output they extend
+    let overlapping = raw(&[literal(1), copy(base, 64)], b"a");
+    corpus.push((
+        "target-copy-overlapping",
+        framed(FLAG_TARGET_COPIES, Some(65), &overlapping),
+    ));
+    let unbounded = raw(&[literal(1), copy(base, u64::MAX >> 1)], b"a");
+    corpus.push((
+        "target-copy-without-size",
+        framed(FLAG_TARGET_COPIES, None, &unbounded),
+    ));
+
+    // Each copy doubles the target reconstructed so far
+    let mut units = vec![literal(1)];
+    units.extend((0..DOUBLINGS).map(|i| copy(base, 1 << i)));
+    let doubling = raw(&units, b"a");
+    corpus.push((
+        "target-copy-doubling",
+        framed(
+            FLAG_TARGET_COPIES | FLAG_CHECKSUM,
+            Some(1 << DOUBLINGS),
+            &doubling,
+        ),
+    ));
+
+    // Many empty segments, then one with a maximal length
+    let mut segments = BufferStream::with_capacity(4096);
+    for _ in 0..1000 {
+        write_varint(&mut segments, 1);
+        segments.write_u8(0);
+    }
+    corpus.push((
+        "segments-empty",
+        framed(FLAG_SEGMENTED, Some(0), segments.as_slice()),
+    ));
+    write_varint(&mut segments, u64::MAX);
+    corpus.push((
+        "segment-length-max",
+        framed(FLAG_SEGMENTED, None, segments.as_slice()),
+    ));
+
+    corpus.push(("target-size-max", framed(0, Some(u64::MAX), &raw(&[], &[]))));
+    corpus.push((
+        "identity-target-size-max",
+        framed(FLAG_IDENTITY, Some(u64::MAX), &[]),
+    ));
+    corpus.push((
+        "identity-with-payload",
+        framed(FLAG_IDENTITY, None, &raw(&[copy(0, base)], &[])),
+    ));
+    let mismatch = DeltaHeader {
+        version: VERSION,
+        flags: FLAG_TARGET_CHECKSUM,
+        target_size: None,
+        target_checksum: Some(0),
+        base_checksum: None,
+        metadata: &[],
+    };
+    corpus.push((
+        "target-checksum-mismatch",
+        mismatch.wrap(&raw(&[copy(0, base)], &[])),
+    ));
+    corpus.push((
+        "all-flags",
+        framed(
+            FLAG_CHECKSUM | FLAG_SEGMENTED | FLAG_TARGET_COPIES | FLAG_IDENTITY,
+            Some(base),
+            &[],
+        ),
+    ));
+    corpus
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_corpus_names_are_unique() {
+        let corpus = seed_corpus(100);
+        let names: HashSet<_> = corpus.iter().map(|(name, _)| name).collect();
+        assert_eq!(names.len(), corpus.len());
+    }
+
+    #[test]
+    fn test_valid_entries_decode() {
+        let base: Vec<u8> = (0..=255).collect();
+        let corpus: std::collections::HashMap<_, _> = seed_corpus(base.len()).into_iter().collect();
+        let decode = |name| crate::decode(&corpus[name], &base);
+
+        assert_eq!(decode("copy-whole-base").unwrap(), base);
+        assert_eq!(decode("padded-varints").unwrap(), base);
+        assert_eq!(decode("target-copy-overlapping").unwrap(), vec![b'a'; 65]);
+        assert_eq!(
+            decode("target-copy-doubling").unwrap(),
+            vec![b'a'; 1 << DOUBLINGS]
+        );
+        assert_eq!(
+            decode("repeated-whole-base-copies").unwrap().len(),
+            base.len() * REPEATS as usize
+        );
+        assert!(decode("segments-empty").unwrap().is_empty());
+        for name in [
+            "copy-one-past-base-end",
+            "copy-max-length",
+            "literal-max-length",
+            "target-checksum-mismatch",
+            "target-copy-without-size",
+        ] {
+            assert!(decode(name).is_err(), "{name}");
+        }
+    }
+}
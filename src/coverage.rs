@@ -0,0 +1,357 @@
+//! Base regions referenced by a delta.
+
+use crate::buffer::BufferStream;
+use crate::delta::prepare;
+use crate::error::{GDeltaError, Result};
+use crate::source::{BaseSource, PrefetchedBase};
+use crate::varint::read_delta_unit;
+use std::ops::Range;
+
+/// Which base bytes a delta copies, and how often.
+///
+/// Applying a delta against a remote or slow base only needs the base
+/// ranges its copy instructions read. The coverage lists them, so a cache
+/// can be warmed with exactly those bytes before decoding with
+/// [`crate::decode_from_source`], and shows which base regions are hot.
+///
+/// # Examples
+///
+/// ```
+/// use gdelta::{BaseCoverage, encode};
+///
+/// let base: Vec<u8> = (0..20_000u32).map(|i| (i * 7919 % 251) as u8).collect();
+/// let mut new = base[..5_000].to_vec();
+/// new.extend_from_slice(&base[15_000..]);
+///
+/// let delta = encode(&new, &base)?;
+/// let coverage = BaseCoverage::from_delta(&delta, base.len())?;
+/// assert!(coverage.covered_bytes() <= 10_000);
+/// for range in coverage.ranges() {
+///     assert!(range.offset + range.length <= 5_000 || range.offset >= 15_000);
+/// }
+/// # Ok::<(), gdelta::GDeltaError>(())
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BaseCoverage {
+    base_len: u64,
+    ranges: Vec<CoveredRange>,
+}
+
+/// A base range read by the same number of copy instructions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CoveredRange {
+    /// Offset of the range in the base.
+    pub offset: u64,
+    /// Length of the range.
+    pub length: u64,
+    /// Number of copy instructions that read the range.
+    pub references: u64,
+}
+
+impl CoveredRange {
+    /// Returns the range as base offsets.
+    pub fn range(&self) -> Range<u64> {
+        self.offset..self.offset + self.length
+    }
+}
+
+impl BaseCoverage {
+    /// Collects the base ranges copied by `delta`, which was encoded
+    /// against a base of `base_len` bytes. Copies from earlier in the
+    /// target are left out.
+    ///
+    /// # Errors
+    ///
+    /// Returns `GDeltaError::InvalidDelta` if the delta is malformed or
+    /// copies past the end of the base.
+    pub fn from_delta(delta: &[u8], base_len: usize) -> Result<Self> {
+        let (raw_deltas, _) = prepare(delta, base_len)?;
+
+        let base_len = base_len as u64;
+        // Each copy starts one reference at its offset and ends it past its end
+        let mut events: Vec<(u64, i64)> = Vec::new();
+        for raw in &raw_deltas {
+            let mut stream = BufferStream::from_slice(&raw.instructions);
+            while stream.remaining() > 0 {
+                let unit = read_delta_unit(&mut stream)?;
+                if !unit.is_copy || unit.length == 0 {
+                    continue;
+                }
+                if raw.target_copies && unit.offset >= base_len {
+                    continue;
+                }
+                let end = unit.offset.saturating_add(unit.length);
+                if end > base_len {
+                    return Err(GDeltaError::InvalidDelta(format!(
+                        "Copy offset {} + length {} exceeds base size {base_len}",
+                        unit.offset, unit.length
+                    )));
+                }
+                events.push((unit.offset, 1));
+                events.push((end, -1));
+            }
+        }
+        events.sort_unstable();
+
+        let mut ranges: Vec<CoveredRange> = Vec::new();
+        let mut references = 0i64;
+        let mut start = 0;
+        for (offset, change) in events {
+            if offset > start && references > 0 {
+                match ranges.last_mut() {
+                    Some(last)
+                        if last.offset + last.length == start
+                            && last.references == references as u64 =>
+                    {
+                        last.length += offset - start;
+                    }
+                    _ => ranges.push(CoveredRange {
+                        offset: start,
+                        length: offset - start,
+                        references: references as u64,
+                    }),
+                }
+            }
+            references += change;
+            start = offset;
+        }
+        Ok(Self { base_len, ranges })
+    }
+
+    /// Returns the length of the base the delta was checked against.
+    pub fn base_len(&self) -> u64 {
+        self.base_len
+    }
+
+    /// Returns the referenced base ranges in base order. They do not
+    /// overlap, and adjacent ranges differ in their reference counts.
+    pub fn ranges(&self) -> &[CoveredRange] {
+        &self.ranges
+    }
+
+    /// Returns the number of distinct base bytes the delta reads.
+    pub fn covered_bytes(&self) -> u64 {
+        self.ranges.iter().map(|range| range.length).sum()
+    }
+
+    /// Returns the number of base bytes copied, counting bytes read by
+    /// several copies once for each.
+    pub fn copied_bytes(&self) -> u64 {
+        self.ranges
+            .iter()
+            .map(|range| range.length.saturating_mul(range.references))
+            .fold(0, u64::saturating_add)
+    }
+
+    /// Returns the fraction of the base the delta reads, from 0.0 to 1.0,
+    /// or 0.0 for an empty base.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn fraction(&self) -> f64 {
+        if self.base_len == 0 {
+            0.0
+        } else {
+            self.covered_bytes() as f64 / self.base_len as f64
+        }
+    }
+
+    /// Plans the base reads for applying the delta as a few large ranged
+    /// requests instead of one per copy.
+    ///
+    /// Covered ranges at most `max_gap` bytes apart are fetched together,
+    /// reading the gap too, and no request is longer than `max_request`
+    /// bytes (at least 1).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use gdelta::{BaseCoverage, decode_from_source, encode};
+    ///
+    /// let base: Vec<u8> = (0..100_000u32).map(|i| (i * 7919 % 251) as u8).collect();
+    /// let mut new = base[60_000..70_000].to_vec();
+    /// new.extend_from_slice(&base[10_000..12_000]);
+    ///
+    /// let delta = encode(&new, &base)?;
+    /// let coverage = BaseCoverage::from_delta(&delta, base.len())?;
+    /// let plan = coverage.prefetch_plan(4096, 1 << 20);
+    /// assert!(plan.fetched_bytes() <= 12_000);
+    ///
+    /// // Issue the requests up front, then decode from the fetched ranges
+    /// let mut prefetched = plan.fetch(base.as_slice())?;
+    /// let mut output = Vec::new();
+    /// decode_from_source(&delta, &mut prefetched, &mut output)?;
+    /// assert_eq!(output, new);
+    /// # Ok::<(), gdelta::GDeltaError>(())
+    /// ```
+    pub fn prefetch_plan(&self, max_gap: u64, max_request: u64) -> PrefetchPlan {
+        let mut merged: Vec<Range<u64>> = Vec::new();
+        for range in &self.ranges {
+            match merged.last_mut() {
+                Some(last) if range.offset - last.end <= max_gap => last.end = range.range().end,
+                _ => merged.push(range.range()),
+            }
+        }
+
+        let max_request = max_request.max(1);
+        let mut ranges = Vec::new();
+        for range in merged {
+            let mut start = range.start;
+            while start < range.end {
+                let end = range.end.min(start.saturating_add(max_request));
+                ranges.push(start..end);
+                start = end;
+            }
+        }
+        PrefetchPlan { ranges }
+    }
+}
+
+/// Base ranges to fetch before applying a delta, in base order; built with
+/// [`BaseCoverage::prefetch_plan`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PrefetchPlan {
+    ranges: Vec<Range<u64>>,
+}
+
+impl PrefetchPlan {
+    /// Returns the ranges to request, in ascending order and without
+    /// overlaps.
+    pub fn ranges(&self) -> &[Range<u64>] {
+        &self.ranges
+    }
+
+    /// Returns the total number of bytes the plan fetches.
+    pub fn fetched_bytes(&self) -> u64 {
+        self.ranges
+            .iter()
+            .map(|range| range.end - range.start)
+            .sum()
+    }
+
+    /// Reads every range of the plan from `source` and keeps them in memory.
+    ///
+    /// The result is a [`BaseSource`] that serves reads within the plan
+    /// from memory and passes any others on to `source`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `GDeltaError::InvalidOptions` if a range lies past the end of
+    /// `source`, and any error from [`BaseSource::read_at`].
+    pub fn fetch<S: BaseSource>(&self, mut source: S) -> Result<PrefetchedBase<S>> {
+        let mut fetched = Vec::with_capacity(self.ranges.len());
+        for range in &self.ranges {
+            if range.end > source.len() {
+                return Err(GDeltaError::InvalidOptions(format!(
+                    "Prefetch range {range:?} is past the end of the {}-byte base",
+                    source.len()
+                )));
+            }
+            let length = usize::try_from(range.end - range.start).map_err(|_| {
+                GDeltaError::BufferError(format!("Prefetch range {range:?} is too large"))
+            })?;
+            let mut data = vec![0; length];
+            source.read_at(range.start, &mut data)?;
+            fetched.push((range.start, data));
+        }
+        Ok(PrefetchedBase::new(source, fetched))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EncodeOptions;
+
+    fn pseudo_random(len: usize, seed: u32) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (state >> 16) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_counts_references() {
+        let base = pseudo_random(40_000, 1);
+        let mut new = base[..10_000].to_vec();
+        new.extend_from_slice(&base[20_000..25_000]);
+        new.extend_from_slice(&pseudo_random(1_000, 2));
+        new.extend_from_slice(&base[22_000..30_000]);
+
+        let delta = crate::encode(&new, &base).unwrap();
+        let coverage = BaseCoverage::from_delta(&delta, base.len()).unwrap();
+        let references = |offset: u64| {
+            coverage
+                .ranges()
+                .iter()
+                .find(|range| range.range().contains(&offset))
+                .map_or(0, |range| range.references)
+        };
+        assert_eq!(references(5_000), 1);
+        assert_eq!(references(15_000), 0);
+        assert_eq!(references(21_000), 1);
+        assert_eq!(references(23_000), 2);
+        assert_eq!(references(27_000), 1);
+        assert_eq!(references(35_000), 0);
+        assert!(
+            coverage
+                .ranges()
+                .windows(2)
+                .all(|pair| pair[0].offset + pair[0].length <= pair[1].offset)
+        );
+        assert!((19_900..=20_000).contains(&coverage.covered_bytes()));
+        assert!((22_900..=23_000).contains(&coverage.copied_bytes()));
+        assert!((coverage.fraction() - 0.5).abs() < 0.01);
+
+        assert!(BaseCoverage::from_delta(&delta, 1_000).is_err());
+        let malformed = [128, 0, 71, 157, 76, 1, 17, 80, 225, 137, 173, 109];
+        assert!(BaseCoverage::from_delta(&malformed, base.len()).is_err());
+    }
+
+    #[test]
+    fn test_prefetch_plan() {
+        let range = |offset, length| CoveredRange {
+            offset,
+            length,
+            references: 1,
+        };
+        let coverage = BaseCoverage {
+            base_len: 100_000,
+            ranges: vec![range(0, 1_000), range(1_500, 500), range(10_000, 25_000)],
+        };
+        assert_eq!(
+            coverage.prefetch_plan(1_000, 10_000).ranges(),
+            [0..2_000, 10_000..20_000, 20_000..30_000, 30_000..35_000]
+        );
+        assert_eq!(
+            coverage.prefetch_plan(0, u64::MAX).ranges(),
+            [0..1_000, 1_500..2_000, 10_000..35_000]
+        );
+        assert_eq!(
+            coverage.prefetch_plan(10_000, u64::MAX).fetched_bytes(),
+            35_000
+        );
+
+        let base = pseudo_random(30_000, 5);
+        let plan = coverage.prefetch_plan(1_000, 10_000);
+        assert!(matches!(
+            plan.fetch(base.as_slice()),
+            Err(GDeltaError::InvalidOptions(_))
+        ));
+    }
+
+    #[test]
+    fn test_skips_target_copies() {
+        let base = pseudo_random(10_000, 3);
+        let block = pseudo_random(5_000, 4);
+        let new = [base.as_slice(), &block, &block].concat();
+
+        let options = EncodeOptions::new().target_matches(true);
+        let delta = crate::encode_with_options(&new, &base, &options).unwrap();
+        let coverage = BaseCoverage::from_delta(&delta, base.len()).unwrap();
+        assert_eq!(coverage.base_len(), 10_000);
+        assert!(coverage.ranges().iter().all(|range| range.references == 1));
+        assert!(coverage.covered_bytes() <= 10_000);
+    }
+}
@@ -0,0 +1,440 @@
+//! Step-by-step decoding for diagnosing wrong or damaged output.
+
+use crate::buffer::{BufferStream, INIT_BUFFER_SIZE};
+use crate::delta::{RawDelta, apply_instructions};
+use crate::error::GDeltaError;
+use crate::header;
+use crate::recovery::DecodeFailure;
+use crate::varint::{read_delta_unit, read_varint, write_varint};
+use std::collections::VecDeque;
+use std::fmt;
+
+/// Number of applied instructions kept in [`DecoderState::recent`].
+const HISTORY: usize = 8;
+
+/// Number of output bytes shown at the end of a state dump.
+const TAIL_BYTES: usize = 32;
+
+/// Where [`crate::decode_until`] stops.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Breakpoint {
+    /// Before the instruction with this index, counting from 0 across all
+    /// frames of the delta.
+    Instruction(usize),
+    /// Before the instruction that writes this byte of the target.
+    TargetOffset(u64),
+}
+
+/// What an instruction does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugOp {
+    /// Copies `length` bytes from `offset` in the base.
+    Copy {
+        /// Start of the source in the base.
+        offset: u64,
+        /// Number of bytes copied.
+        length: u64,
+    },
+    /// Copies `length` bytes from `offset` in the target written so far.
+    TargetCopy {
+        /// Start of the source in the target.
+        offset: u64,
+        /// Number of bytes copied.
+        length: u64,
+    },
+    /// Appends `length` bytes of literal data.
+    Literal {
+        /// Number of bytes appended.
+        length: u64,
+    },
+}
+
+impl DebugOp {
+    /// Returns the number of target bytes the instruction writes.
+    pub fn length(&self) -> u64 {
+        match *self {
+            Self::Copy { length, .. }
+            | Self::TargetCopy { length, .. }
+            | Self::Literal { length } => length,
+        }
+    }
+}
+
+/// An instruction and where it sits in the delta and the target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DebugInstruction {
+    /// Index of the instruction, counting from 0 across all frames.
+    pub index: usize,
+    /// Byte offset of the instruction in the delta.
+    pub delta_offset: usize,
+    /// Offset in the target of the first byte the instruction writes.
+    pub output_offset: u64,
+    /// What the instruction does.
+    pub op: DebugOp,
+}
+
+impl fmt::Display for DebugInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "#{} at delta offset {}, target offset {}: ",
+            self.index, self.delta_offset, self.output_offset
+        )?;
+        match self.op {
+            DebugOp::Copy { offset, length } => write!(f, "copy {length} bytes from base {offset}"),
+            DebugOp::TargetCopy { offset, length } => {
+                write!(f, "copy {length} bytes from target {offset}")
+            }
+            DebugOp::Literal { length } => write!(f, "literal of {length} bytes"),
+        }
+    }
+}
+
+/// Decoder state where [`crate::decode_until`] stopped.
+///
+/// The [`Display`](fmt::Display) output is a plain-text dump meant to be
+/// attached to bug reports.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecoderState {
+    /// Whether decoding stopped at the breakpoint, rather than at the end of
+    /// the delta or at a failure.
+    pub hit: bool,
+    /// Number of instructions applied.
+    pub instructions_applied: usize,
+    /// Index of the frame being decoded, for segmented deltas.
+    pub segment: Option<usize>,
+    /// The target reconstructed so far.
+    pub output: Vec<u8>,
+    /// The instruction at the breakpoint or the one that failed, which has
+    /// not been applied.
+    pub next: Option<DebugInstruction>,
+    /// The last instructions applied, oldest first.
+    pub recent: Vec<DebugInstruction>,
+    /// The first failure encountered before the breakpoint, if any.
+    pub failure: Option<DecodeFailure>,
+}
+
+impl fmt::Display for DecoderState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.hit {
+            writeln!(f, "Stopped at breakpoint")?;
+        } else if self.failure.is_some() {
+            writeln!(f, "Stopped at failure")?;
+        } else {
+            writeln!(f, "Decoding finished before the breakpoint")?;
+        }
+        writeln!(f, "Instructions applied: {}", self.instructions_applied)?;
+        if let Some(segment) = self.segment {
+            writeln!(f, "Segment: {segment}")?;
+        }
+        writeln!(f, "Output size: {}", self.output.len())?;
+        if !self.recent.is_empty() {
+            writeln!(f, "Recent instructions:")?;
+            for instruction in &self.recent {
+                writeln!(f, "  {instruction}")?;
+            }
+        }
+        if let Some(next) = &self.next {
+            writeln!(f, "Next instruction:")?;
+            writeln!(f, "  {next}")?;
+        }
+        if let Some(failure) = &self.failure {
+            writeln!(
+                f,
+                "Failure at delta offset {}: {}",
+                failure.delta_offset, failure.error
+            )?;
+        }
+        let tail = &self.output[self.output.len().saturating_sub(TAIL_BYTES)..];
+        write!(f, "Output tail:")?;
+        for byte in tail {
+            write!(f, " {byte:02x}")?;
+        }
+        writeln!(f)
+    }
+}
+
+/// Applies instructions one at a time, watching for the breakpoint.
+struct Stepper<'a> {
+    base_data: &'a [u8],
+    breakpoint: Breakpoint,
+    target_copies: bool,
+    output: BufferStream,
+    applied: usize,
+    recent: VecDeque<DebugInstruction>,
+    next: Option<DebugInstruction>,
+    failure: Option<DecodeFailure>,
+}
+
+impl Stepper<'_> {
+    /// Returns true if decoding should stop before `instruction`.
+    fn breaks_at(&self, instruction: &DebugInstruction) -> bool {
+        match self.breakpoint {
+            Breakpoint::Instruction(index) => instruction.index == index,
+            Breakpoint::TargetOffset(offset) => {
+                instruction
+                    .output_offset
+                    .saturating_add(instruction.op.length())
+                    > offset
+            }
+        }
+    }
+
+    fn fail(&mut self, delta_offset: usize, error: GDeltaError) {
+        self.failure = Some(DecodeFailure {
+            instruction_index: self.next.map(|next| next.index),
+            delta_offset,
+            output_offset: self.output.len(),
+            error,
+        });
+    }
+
+    /// Steps through the raw delta starting at `delta_offset`, returning
+    /// true if decoding stopped.
+    fn run(&mut self, raw: &[u8], delta_offset: usize) -> bool {
+        let mut stream = BufferStream::from_slice(raw);
+        let instruction_len = match read_varint(&mut stream) {
+            Ok(len) => usize::try_from(len).unwrap_or(usize::MAX),
+            Err(error) => {
+                self.fail(delta_offset, error);
+                return true;
+            }
+        };
+        let inst_start = stream.position();
+        let inst_end = inst_start.saturating_add(instruction_len).min(raw.len());
+        let instructions = &raw[inst_start..inst_end];
+        let data = &raw[inst_end..];
+
+        let mut stream = BufferStream::from_slice(instructions);
+        let mut literal_pos = 0usize;
+        while stream.remaining() > 0 {
+            let start = stream.position();
+            let offset = delta_offset + inst_start + start;
+            let unit = match read_delta_unit(&mut stream) {
+                Ok(unit) => unit,
+                Err(error) => {
+                    self.fail(offset, error);
+                    return true;
+                }
+            };
+            let base_len = self.base_data.len() as u64;
+            let op = if unit.is_copy && self.target_copies && unit.offset >= base_len {
+                DebugOp::TargetCopy {
+                    offset: unit.offset - base_len,
+                    length: unit.length,
+                }
+            } else if unit.is_copy {
+                DebugOp::Copy {
+                    offset: unit.offset,
+                    length: unit.length,
+                }
+            } else {
+                DebugOp::Literal {
+                    length: unit.length,
+                }
+            };
+            let instruction = DebugInstruction {
+                index: self.applied,
+                delta_offset: offset,
+                output_offset: self.output.len() as u64,
+                op,
+            };
+            self.next = Some(instruction);
+            if self.breaks_at(&instruction) {
+                return true;
+            }
+
+            // Apply just this instruction, against the literal data it uses
+            let applied = apply_instructions(
+                &instructions[start..stream.position()],
+                data.get(literal_pos..).unwrap_or_default(),
+                self.base_data,
+                self.target_copies,
+                &mut self.output,
+            );
+            if let Err(failure) = applied {
+                self.fail(offset, failure.error);
+                return true;
+            }
+            if !unit.is_copy {
+                literal_pos = literal_pos.saturating_add(unit.length as usize);
+            }
+            if self.recent.len() == HISTORY {
+                self.recent.pop_front();
+            }
+            self.recent.push_back(instruction);
+            self.applied += 1;
+            self.next = None;
+        }
+
+        if inst_end < inst_start.saturating_add(instruction_len) {
+            self.fail(delta_offset + raw.len(), GDeltaError::UnexpectedEndOfData);
+            return true;
+        }
+        false
+    }
+}
+
+pub fn decode_until(delta: &[u8], base_data: &[u8], breakpoint: Breakpoint) -> DecoderState {
+    let framed = header::parse(delta);
+    let header = framed.as_ref().ok().and_then(|framed| framed.header);
+    let mut stepper = Stepper {
+        base_data,
+        breakpoint,
+        target_copies: header.is_some_and(|header| header.has_target_copies()),
+        output: BufferStream::with_capacity(INIT_BUFFER_SIZE),
+        applied: 0,
+        recent: VecDeque::with_capacity(HISTORY),
+        next: None,
+        failure: None,
+    };
+    let mut segment = None;
+
+    match framed {
+        Err(error) => stepper.fail(0, error),
+        Ok(framed) if header.is_some_and(|header| header.is_identity()) => {
+            // The identity delta stands for a single copy of the whole base
+            let identity = RawDelta::identity(base_data.len());
+            let mut raw = BufferStream::with_capacity(identity.instructions.len() + 4);
+            write_varint(&mut raw, identity.instructions.len() as u64);
+            raw.write_bytes(&identity.instructions);
+            stepper.run(raw.as_slice(), framed.payload_offset);
+        }
+        Ok(framed) if header.is_some_and(|header| header.is_segmented()) => {
+            let payload = framed.payload;
+            let mut stream = BufferStream::from_slice(payload);
+            while stream.remaining() > 0 {
+                segment = Some(segment.map_or(0, |index| index + 1));
+                let offset = framed.payload_offset + stream.position();
+                let segment_len = match read_varint(&mut stream) {
+                    Ok(len) => usize::try_from(len).unwrap_or(usize::MAX),
+                    Err(error) => {
+                        stepper.fail(offset, error);
+                        break;
+                    }
+                };
+                let start = stream.position();
+                let end = start.saturating_add(segment_len);
+                if stepper.run(
+                    &payload[start..end.min(payload.len())],
+                    framed.payload_offset + start,
+                ) {
+                    break;
+                }
+                if end > payload.len() {
+                    stepper.fail(delta.len(), GDeltaError::UnexpectedEndOfData);
+                    break;
+                }
+                stream.set_position(end);
+            }
+        }
+        Ok(framed) => {
+            stepper.run(framed.payload, framed.payload_offset);
+        }
+    }
+
+    DecoderState {
+        hit: stepper.failure.is_none() && stepper.next.is_some(),
+        instructions_applied: stepper.applied,
+        segment,
+        output: stepper.output.into_vec(),
+        next: stepper.next,
+        recent: stepper.recent.into(),
+        failure: stepper.failure,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EncodeOptions, encode, encode_with_options};
+
+    #[test]
+    fn test_stops_at_instruction() {
+        let base = b"The quick brown fox jumps over the lazy dog";
+        let new = b"The quick brown cat jumps over the lazy dog";
+        let delta = encode(new, base).unwrap();
+
+        let state = decode_until(&delta, base, Breakpoint::Instruction(1));
+        assert!(state.hit);
+        assert_eq!(state.instructions_applied, 1);
+        assert_eq!(state.output, b"The quick brown ");
+        assert_eq!(state.recent.len(), 1);
+        assert_eq!(
+            state.next.unwrap().op,
+            DebugOp::Literal { length: 3 },
+            "{state}"
+        );
+    }
+
+    #[test]
+    fn test_stops_at_target_offset() {
+        let base: Vec<u8> = (0..=255).cycle().take(8192).collect();
+        let mut new = base.clone();
+        new[5000..5016].copy_from_slice(b"sixteen new byte");
+        let options = EncodeOptions::new().checksum(true).rsyncable(true);
+        let delta = encode_with_options(&new, &base, &options).unwrap();
+
+        for offset in [0, 4999, 5000, 5010, 8191] {
+            let state = decode_until(&delta, &base, Breakpoint::TargetOffset(offset));
+            assert!(state.hit);
+            let next = state.next.unwrap();
+            assert_eq!(next.output_offset, state.output.len() as u64);
+            assert!(next.output_offset <= offset);
+            assert!(next.output_offset + next.op.length() > offset);
+            assert_eq!(state.output, new[..state.output.len()]);
+        }
+
+        // Past the end, decoding runs to completion
+        let state = decode_until(&delta, &base, Breakpoint::TargetOffset(8192));
+        assert!(!state.hit);
+        assert!(state.next.is_none() && state.failure.is_none());
+        assert_eq!(state.output, new);
+        assert!(state.to_string().contains("finished before the breakpoint"));
+    }
+
+    #[test]
+    fn test_identity_delta() {
+        let base = b"unchanged";
+        let options = EncodeOptions::new().checksum(true);
+        let delta = encode_with_options(base, base, &options).unwrap();
+
+        let state = decode_until(&delta, base, Breakpoint::Instruction(0));
+        assert!(state.hit);
+        assert_eq!(
+            state.next.unwrap().op,
+            DebugOp::Copy {
+                offset: 0,
+                length: 9
+            }
+        );
+    }
+
+    #[test]
+    fn test_reports_failure() {
+        // A literal, then a copy beyond the end of the base
+        let delta = [3, 0x02, 0x84, 0x10, b'h', b'i'];
+
+        let state = decode_until(&delta, b"base", Breakpoint::Instruction(5));
+        assert!(!state.hit);
+        assert_eq!(state.output, b"hi");
+        let next = state.next.unwrap();
+        assert_eq!(next.index, 1);
+        assert_eq!(next.delta_offset, 2);
+        assert_eq!(
+            next.op,
+            DebugOp::Copy {
+                offset: 16,
+                length: 4
+            }
+        );
+        let failure = state.failure.as_ref().unwrap();
+        assert_eq!(failure.instruction_index, Some(1));
+        assert!(matches!(failure.error, GDeltaError::InvalidDelta(_)));
+
+        let dump = state.to_string();
+        assert!(dump.contains("Stopped at failure"), "{dump}");
+        assert!(dump.contains("#1 at delta offset 2"), "{dump}");
+        assert!(dump.contains("Output tail: 68 69"), "{dump}");
+    }
+}
@@ -1,20 +1,55 @@
 //! Core delta encoding and decoding implementation.
 
 use crate::buffer::{BufferStream, INIT_BUFFER_SIZE};
+use crate::checksum::crc32;
+use crate::entropy;
 use crate::error::{GDeltaError, Result};
-use crate::gear::{WORD_SIZE, build_hash_table, compute_fingerprint, roll_fingerprint};
-use crate::varint::{DeltaUnit, read_delta_unit, read_varint, write_delta_unit, write_varint};
+use crate::header::{self, DeltaHeader};
+use crate::matcher::{Match, Matcher, TargetMatcher};
+use crate::options::{EncodeOptions, check_deadline};
+use crate::segment;
+use crate::varint::{
+    DeltaUnit, delta_unit_len, read_delta_unit, read_varint, write_delta_unit, write_varint,
+};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::time::Instant;
 
 /// Minimum length for prefix/suffix optimization.
 const MIN_MATCH_LENGTH: usize = 16;
 
+/// Number of match searches between deadline checks.
+const DEADLINE_CHECK_INTERVAL: usize = 4096;
+
+/// Largest number of bytes skipped after a miss when degrading to meet the
+/// deadline.
+const MAX_DEGRADED_STRIDE: usize = 64;
+
+/// Shortest literal run remembered for replacing later repeats of it.
+const MIN_REPEATED_LITERAL: usize = 8;
+
 /// Chunk size for processing.
 #[allow(dead_code)]
 pub const CHUNK_SIZE: usize = 300 * 1024;
 
-/// Encodes the delta between new data and base data.
-#[allow(clippy::unnecessary_wraps)]
-pub fn encode(new_data: &[u8], base_data: &[u8]) -> Result<Vec<u8>> {
+/// Encodes the delta between new data and base data using `matcher`.
+pub fn encode<M: Matcher>(
+    new_data: &[u8],
+    base_data: &[u8],
+    matcher: &mut M,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>> {
+    options.validate()?;
+    // An unchanged target gets the canonical identity delta when a header
+    // is written anyway; raw output stays readable by releases up to 0.2.1
+    if new_data == base_data
+        && options.allows_header()
+        && DeltaHeader::for_options(options, new_data, base_data).is_some()
+    {
+        return Ok(DeltaHeader::identity(options, new_data).wrap(&[]));
+    }
+
     let new_size = new_data.len();
     let base_size = base_data.len();
 
@@ -36,56 +71,441 @@ pub fn encode(new_data: &[u8], base_data: &[u8]) -> Result<Vec<u8>> {
         suffix_size = new_size.saturating_sub(prefix_size);
     }
 
-    // Initialize streams
-    let mut instruction_stream = BufferStream::with_capacity(INIT_BUFFER_SIZE);
-    let mut data_stream = BufferStream::with_capacity(INIT_BUFFER_SIZE);
+    let mut encoder = Encoder::new(new_data, base_data.len(), options);
+    encoder.header = DeltaHeader::for_options(options, new_data, base_data);
 
     // Handle trivial case where prefix + suffix covers entire base
     if prefix_size + suffix_size >= base_size {
-        encode_trivial_case(
-            new_data,
-            base_data,
-            prefix_size,
-            suffix_size,
-            &mut instruction_stream,
-            &mut data_stream,
-        );
-
-        return Ok(finalize_delta(&instruction_stream, &data_stream));
+        encode_trivial_case(&mut encoder, base_data, prefix_size, suffix_size)?;
+        return encoder.finish();
     }
 
     // Write prefix instruction if present
-    if has_prefix {
-        let unit = DeltaUnit::copy(0, prefix_size as u64);
-        write_delta_unit(&mut instruction_stream, &unit);
+    encoder.copy_or_literal(0, 0, prefix_size);
+
+    let middle = &new_data[prefix_size..new_size - suffix_size];
+    let base_middle = &base_data[prefix_size..base_size - suffix_size];
+    options.check_deadline()?;
+
+    // Nothing to find, or no time left to look, so skip the byte-by-byte scan
+    if options.deadline_passed() || entropy::looks_incompressible(middle, base_middle) {
+        encoder.literal(prefix_size, new_size - suffix_size);
+    } else {
+        // Index the part of the base not covered by prefix/suffix
+        matcher.index(base_data, prefix_size, base_size - suffix_size);
+
+        // Encode the middle section
+        encode_middle_section(
+            &mut encoder,
+            base_data,
+            prefix_size,
+            new_size - suffix_size,
+            base_size - suffix_size,
+            matcher,
+        )?;
     }
 
-    // Build hash table for base data
-    let work_base_size = base_size - prefix_size - suffix_size;
-    let hash_bits = calculate_hash_bits(work_base_size);
-    let hash_table = build_hash_table(base_data, prefix_size, base_size - suffix_size, hash_bits);
-    let hash_shift = 64 - hash_bits;
+    // Write suffix instruction if present
+    encoder.copy_or_literal(new_size - suffix_size, base_size - suffix_size, suffix_size);
+
+    encoder.finish()
+}
 
-    // Encode the middle section
+/// Encodes `new_data[window]` against the whole base as a raw delta without
+/// a header, for stitching into a segmented delta.
+///
+/// `matcher` must already index the whole base. Copies from the target
+/// only reach back within the window, and are addressed as in a delta of
+/// all of `new_data`.
+pub fn encode_window<M: Matcher>(
+    new_data: &[u8],
+    base_data: &[u8],
+    window: Range<usize>,
+    matcher: &mut M,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>> {
+    let mut encoder = Encoder::new(new_data, base_data.len(), options);
+    // The caller frames the windows and checks the size of the whole delta
+    encoder.size_limit = None;
     encode_middle_section(
-        new_data,
+        &mut encoder,
         base_data,
-        prefix_size,
-        new_size - suffix_size,
-        base_size - suffix_size,
-        &hash_table[..],
-        hash_shift,
-        &mut instruction_stream,
-        &mut data_stream,
-    );
+        window.start,
+        window.end,
+        base_data.len(),
+        matcher,
+    )?;
+    encoder.flush_literal();
+    Ok(finalize_delta(
+        &encoder.instruction_stream,
+        &encoder.data_stream,
+    ))
+}
 
-    // Write suffix instruction if present
-    if suffix_size > 0 {
-        let unit = DeltaUnit::copy((base_size - suffix_size) as u64, suffix_size as u64);
-        write_delta_unit(&mut instruction_stream, &unit);
+/// Output streams and copy constraints shared by the encoding stages.
+///
+/// Literal bytes are accumulated as a pending range of the target and only
+/// written out when the next copy is emitted, so adjacent literals merge into
+/// a single instruction.
+struct Encoder<'a> {
+    new_data: &'a [u8],
+    header: Option<DeltaHeader<'a>>,
+    copy_window: Option<usize>,
+    instruction_stream: BufferStream,
+    data_stream: BufferStream,
+    literal_start: usize,
+    literal_end: usize,
+    /// End of the furthest base region referenced so far.
+    base_high_water: usize,
+    deadline: Option<Instant>,
+    /// Whether to lower the effort to meet the deadline instead of aborting.
+    degrade_on_deadline: bool,
+    /// When encoding started, for pacing against the deadline.
+    started: Instant,
+    /// Misses after which unmatched regions are skipped faster, as a power of two.
+    skip_shift: Option<u32>,
+    /// Largest acceptable delta size, from the options' maximum ratio.
+    size_limit: Option<usize>,
+    /// Whether copies may read from earlier in the target.
+    target_matches: bool,
+    /// Shortest copy the options allow.
+    min_match_len: usize,
+    /// Delimiter that segment boundaries are aligned to.
+    record_delimiter: Option<u8>,
+    /// Size of the base, past which copies address the target.
+    base_len: usize,
+    /// Multiple of which copies start at, in the target and their source.
+    alignment: usize,
+    /// Whether copies from the base are extended back over literal data.
+    extend_backward: bool,
+    /// Target offsets of the literal runs written so far, by content, if
+    /// copies may read from the target.
+    literal_runs: Option<HashMap<&'a [u8], usize>>,
+}
+
+impl<'a> Encoder<'a> {
+    fn new(new_data: &'a [u8], base_len: usize, options: &EncodeOptions) -> Self {
+        let target_matches = options.target_matches;
+        Self {
+            new_data,
+            header: None,
+            copy_window: options.copy_window,
+            instruction_stream: BufferStream::with_capacity(INIT_BUFFER_SIZE),
+            data_stream: BufferStream::with_capacity(INIT_BUFFER_SIZE),
+            literal_start: 0,
+            literal_end: 0,
+            base_high_water: 0,
+            deadline: options.deadline,
+            degrade_on_deadline: options.degrade_on_deadline,
+            started: Instant::now(),
+            skip_shift: options.preset.skip_shift(),
+            size_limit: options.size_limit(new_data.len()),
+            target_matches,
+            min_match_len: options.min_match_len.unwrap_or(0),
+            record_delimiter: options.record_delimiter_for_alignment(),
+            base_len,
+            alignment: options.match_alignment.unwrap_or(1),
+            extend_backward: options.extend_backward,
+            literal_runs: target_matches.then(HashMap::new),
+        }
+    }
+
+    /// Returns `NotBeneficial` once the delta has outgrown the size limit.
+    ///
+    /// The delta only grows as encoding proceeds, so there is no point in
+    /// continuing past this.
+    fn check_size(&self) -> Result<()> {
+        let pending = self.literal_end - self.literal_start;
+        match self.size_limit {
+            Some(limit)
+                if self.instruction_stream.len() + self.data_stream.len() + pending > limit =>
+            {
+                Err(GDeltaError::NotBeneficial)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns true if a copy starting at `base_offset` satisfies the options.
+    fn copy_allowed(&self, base_offset: usize) -> bool {
+        self.copy_window
+            .is_none_or(|window| base_offset.saturating_add(window) >= self.base_high_water)
+    }
+
+    /// Returns true if copies may start at `offset`, in the target or in the
+    /// source of the copy.
+    fn aligned(&self, offset: usize) -> bool {
+        offset % self.alignment == 0
+    }
+
+    /// Returns true if copying `length` bytes from `offset` is allowed by the
+    /// options and encodes smaller than storing the bytes as literal data.
+    fn copy_pays_off(&self, offset: usize, length: usize) -> bool {
+        if length < self.min_match_len {
+            return false;
+        }
+        let copy_len = delta_unit_len(&DeltaUnit::copy(offset as u64, length as u64));
+        // A copy inside literal data splits it, costing another literal head
+        let split_penalty = usize::from(self.literal_start < self.literal_end);
+        copy_len + split_penalty < length
+    }
+
+    /// Queues `new_data[start..end]` as literal data.
+    fn literal(&mut self, start: usize, end: usize) {
+        if start >= end {
+            return;
+        }
+        if start != self.literal_end {
+            self.flush_literal();
+            self.literal_start = start;
+        }
+        self.literal_end = end;
+    }
+
+    /// Writes a copy instruction, flushing any pending literal first.
+    fn copy(&mut self, base_offset: usize, length: usize) {
+        self.flush_literal();
+        let unit = DeltaUnit::copy(base_offset as u64, length as u64);
+        write_delta_unit(&mut self.instruction_stream, &unit);
+        self.base_high_water = self.base_high_water.max(base_offset + length);
+    }
+
+    /// Moves the start of a copy of `base[base_offset..]` to `pos` back over
+    /// the pending literal data as far as it matches the base, and returns
+    /// the number of bytes taken from the literal.
+    fn extend_backward(&mut self, base: &[u8], pos: usize, base_offset: usize) -> usize {
+        if !self.extend_backward || self.literal_end != pos {
+            return 0;
+        }
+        let pending = &self.new_data[self.literal_start..pos];
+        let mut back = pending
+            .iter()
+            .rev()
+            .zip(base[..base_offset].iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+        // Keep the copy aligned, and within the window
+        back -= back % self.alignment;
+        if back == 0 || !self.copy_allowed(base_offset - back) {
+            return 0;
+        }
+        self.literal_end -= back;
+        back
+    }
+
+    /// Writes a copy of earlier target bytes, which are addressed past the
+    /// end of the base.
+    fn target_copy(&mut self, base_len: usize, target_offset: usize, length: usize) {
+        self.flush_literal();
+        let unit = DeltaUnit::copy((base_len + target_offset) as u64, length as u64);
+        write_delta_unit(&mut self.instruction_stream, &unit);
+    }
+
+    /// Copies `length` bytes from the base if allowed, otherwise emits them
+    /// as literal data taken from the target at `new_offset`.
+    fn copy_or_literal(
+        &mut self,
+        mut new_offset: usize,
+        mut base_offset: usize,
+        mut length: usize,
+    ) {
+        // Store the bytes up to an aligned start as literal data
+        let skip = (new_offset.next_multiple_of(self.alignment) - new_offset).min(length);
+        self.literal(new_offset, new_offset + skip);
+        new_offset += skip;
+        base_offset += skip;
+        length -= skip;
+
+        if length == 0 {
+            return;
+        }
+        if self.copy_allowed(base_offset)
+            && self.aligned(base_offset)
+            && length >= self.min_match_len
+        {
+            self.copy(base_offset, length);
+        } else {
+            self.literal(new_offset, new_offset + length);
+        }
+    }
+
+    /// Writes the pending literal run, or a copy of an identical earlier
+    /// run if copies may read from the target and that is smaller.
+    fn flush_literal(&mut self) {
+        if self.literal_start < self.literal_end {
+            let new_data = self.new_data;
+            let run = &new_data[self.literal_start..self.literal_end];
+            let unit = match self.repeated_run(run) {
+                Some(offset) => DeltaUnit::copy(offset as u64, run.len() as u64),
+                None => {
+                    self.data_stream.write_bytes(run);
+                    DeltaUnit::literal(run.len() as u64)
+                }
+            };
+            write_delta_unit(&mut self.instruction_stream, &unit);
+        }
+        self.literal_start = self.literal_end;
+    }
+
+    /// Returns the copy offset of an earlier literal run equal to `run` if
+    /// copying it encodes smaller than storing `run` again, and otherwise
+    /// remembers `run`, which starts at `literal_start`.
+    fn repeated_run(&mut self, run: &'a [u8]) -> Option<usize> {
+        let runs = self.literal_runs.as_mut()?;
+        if run.len() < MIN_REPEATED_LITERAL || self.literal_start % self.alignment != 0 {
+            return None;
+        }
+        match runs.get(run) {
+            Some(&start) => {
+                let offset = self.base_len + start;
+                let copy_len = delta_unit_len(&DeltaUnit::copy(offset as u64, run.len() as u64));
+                let literal_len = delta_unit_len(&DeltaUnit::literal(run.len() as u64));
+                (copy_len < literal_len + run.len()).then_some(offset)
+            }
+            None => {
+                runs.insert(run, self.literal_start);
+                None
+            }
+        }
+    }
+
+    fn finish(mut self) -> Result<Vec<u8>> {
+        self.flush_literal();
+        let payload = match self.header {
+            Some(header) if header.is_segmented() => {
+                let mut cuts = segment::cut_points(self.new_data);
+                if let Some(delimiter) = self.record_delimiter {
+                    cuts = segment::align_cuts(self.new_data, &cuts, delimiter);
+                }
+                if self.alignment > 1 {
+                    // Keep the copies split at cuts aligned
+                    let (len, alignment) = (self.new_data.len(), self.alignment);
+                    cuts = cuts
+                        .iter()
+                        .map(|cut| cut.next_multiple_of(alignment))
+                        .collect();
+                    cuts.retain(|&cut| cut < len);
+                    cuts.dedup();
+                }
+                segment::segment(&self.instruction_stream, &self.data_stream, &cuts)?
+            }
+            _ => finalize_delta(&self.instruction_stream, &self.data_stream),
+        };
+        let delta = match self.header {
+            Some(header) => header.wrap(&payload),
+            None => payload,
+        };
+        if self.size_limit.is_some_and(|limit| delta.len() > limit) {
+            return Err(GDeltaError::NotBeneficial);
+        }
+        Ok(delta)
+    }
+}
+
+/// Builds a delta from a sequence of copy and literal instructions.
+///
+/// Instructions are written in the order they are added; the decoder replays
+/// them sequentially to produce the target.
+pub struct DeltaWriter {
+    instruction_stream: BufferStream,
+    data_stream: BufferStream,
+}
+
+impl DeltaWriter {
+    /// Creates an empty writer.
+    pub fn new() -> Self {
+        Self {
+            instruction_stream: BufferStream::with_capacity(INIT_BUFFER_SIZE),
+            data_stream: BufferStream::with_capacity(INIT_BUFFER_SIZE),
+        }
+    }
+
+    /// Appends an instruction copying `length` bytes from `offset` in the base.
+    ///
+    /// Zero-length copies are skipped.
+    pub fn copy(&mut self, offset: u64, length: u64) {
+        if length > 0 {
+            write_delta_unit(
+                &mut self.instruction_stream,
+                &DeltaUnit::copy(offset, length),
+            );
+        }
+    }
+
+    /// Appends an instruction inserting `data` verbatim.
+    ///
+    /// Empty literals are skipped.
+    pub fn literal(&mut self, data: &[u8]) {
+        if !data.is_empty() {
+            write_delta_unit(
+                &mut self.instruction_stream,
+                &DeltaUnit::literal(data.len() as u64),
+            );
+            self.data_stream.write_bytes(data);
+        }
+    }
+
+    /// Returns the size the finished delta would have, in bytes.
+    #[cfg_attr(not(feature = "unstable-format"), allow(dead_code))]
+    pub fn encoded_len(&self) -> usize {
+        let mut prefix = BufferStream::with_capacity(10);
+        write_varint(&mut prefix, self.instruction_stream.len() as u64);
+        prefix.len() + self.instruction_stream.len() + self.data_stream.len()
+    }
+
+    /// Finalizes the delta, combining instructions and literal data.
+    pub fn finish(self) -> Vec<u8> {
+        finalize_delta(&self.instruction_stream, &self.data_stream)
     }
+}
+
+impl Default for DeltaWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Encodes `target` against `base_window` and appends the instructions to
+/// `writer`, adding `base_offset` to every copy offset.
+///
+/// Returns the end of the last copy as `(target end, base end)`, relative to
+/// the start of `target` and the full base, or `None` if nothing was copied.
+pub fn encode_rebased<M: Matcher>(
+    writer: &mut DeltaWriter,
+    target: &[u8],
+    base_window: &[u8],
+    base_offset: u64,
+    matcher: &mut M,
+) -> Result<Option<(u64, u64)>> {
+    // The identity delta has no instructions to rebase
+    if target == base_window {
+        let length = target.len() as u64;
+        if length == 0 {
+            return Ok(None);
+        }
+        writer.copy(base_offset, length);
+        return Ok(Some((length, base_offset + length)));
+    }
+
+    let delta = encode(target, base_window, matcher, &EncodeOptions::default())?;
+    let raw = RawDelta::parse(&delta)?;
+    let mut instructions = BufferStream::from_slice(&raw.instructions);
+    let mut literals = BufferStream::from_slice(raw.data);
 
-    Ok(finalize_delta(&instruction_stream, &data_stream))
+    let mut target_pos = 0;
+    let mut last_copy = None;
+    while instructions.remaining() > 0 {
+        let unit = read_delta_unit(&mut instructions)?;
+        target_pos += unit.length;
+        if unit.is_copy {
+            let offset = unit.offset + base_offset;
+            writer.copy(offset, unit.length);
+            last_copy = Some((target_pos, offset + unit.length));
+        } else {
+            writer.literal(literals.read_bytes(unit.length as usize)?);
+        }
+    }
+    Ok(last_copy)
 }
 
 /// Finds the length of the common prefix between two byte slices.
@@ -173,196 +593,185 @@ fn find_common_suffix(a: &[u8], b: &[u8], prefix_len: usize) -> usize {
     len
 }
 
-/// Calculates the number of hash bits based on data size.
-fn calculate_hash_bits(size: usize) -> u32 {
-    let mut bits = 0u32;
-    let mut temp = size + 10;
-    while temp > 0 {
-        bits += 1;
-        temp >>= 1;
-    }
-    bits
-}
-
 /// Encodes the trivial case where prefix + suffix cover the entire base.
 fn encode_trivial_case(
-    new_data: &[u8],
+    encoder: &mut Encoder<'_>,
     base_data: &[u8],
     prefix_size: usize,
     suffix_size: usize,
-    instruction_stream: &mut BufferStream,
-    data_stream: &mut BufferStream,
-) {
-    let new_size = new_data.len();
+) -> Result<()> {
+    let new_size = encoder.new_data.len();
     let base_size = base_data.len();
 
     // Write prefix
-    if prefix_size > 0 {
-        let unit = DeltaUnit::copy(0, prefix_size as u64);
-        write_delta_unit(instruction_stream, &unit);
-    }
+    encoder.copy_or_literal(0, 0, prefix_size);
 
-    // Write middle as literal
-    let middle_size = new_size - prefix_size - suffix_size;
-    if middle_size > 0 {
-        let unit = DeltaUnit::literal(middle_size as u64);
-        write_delta_unit(instruction_stream, &unit);
-        data_stream.write_bytes(&new_data[prefix_size..new_size - suffix_size]);
+    // Write middle as literal, or search it for repeats of itself
+    if encoder.target_matches {
+        encode_middle_section(
+            encoder,
+            base_data,
+            prefix_size,
+            new_size - suffix_size,
+            0,
+            &mut NoMatches,
+        )?;
+    } else {
+        encoder.literal(prefix_size, new_size - suffix_size);
     }
 
     // Write suffix
-    if suffix_size > 0 {
-        let unit = DeltaUnit::copy((base_size - suffix_size) as u64, suffix_size as u64);
-        write_delta_unit(instruction_stream, &unit);
+    encoder.copy_or_literal(new_size - suffix_size, base_size - suffix_size, suffix_size);
+    Ok(())
+}
+
+/// Matcher for sections without base data to search.
+struct NoMatches;
+
+impl Matcher for NoMatches {
+    fn index(&mut self, _base: &[u8], _start: usize, _end: usize) {}
+
+    fn find_match(&mut self, _target: &[u8], _base: &[u8], _pos: usize) -> Option<Match> {
+        None
     }
 }
 
-/// Encodes the middle section of the data using hash table lookups.
-#[allow(clippy::too_many_arguments)]
-#[allow(clippy::cast_possible_truncation)]
-fn encode_middle_section(
-    new_data: &[u8],
+/// Encodes the middle section of the data using the matcher.
+fn encode_middle_section<M: Matcher>(
+    encoder: &mut Encoder<'_>,
     base_data: &[u8],
     start: usize,
     end: usize,
     base_end: usize,
-    hash_table: &[u32],
-    hash_shift: u32,
-    instruction_stream: &mut BufferStream,
-    data_stream: &mut BufferStream,
-) {
-    if start >= end || end - start < WORD_SIZE {
-        // Write remaining data as literal
-        if start < end {
-            let unit = DeltaUnit::literal((end - start) as u64);
-            write_delta_unit(instruction_stream, &unit);
-            data_stream.write_bytes(&new_data[start..end]);
-        }
-        return;
-    }
+    matcher: &mut M,
+) -> Result<()> {
+    let new_data = encoder.new_data;
+    let target = &new_data[..end];
+    let base = &base_data[..base_end];
 
     let mut pos = start;
-    let mut literal_start = start;
-    let mut fingerprint = compute_fingerprint(new_data, pos);
-
-    while pos + WORD_SIZE <= end {
-        // Look up in hash table
-        let hash_index = (fingerprint >> hash_shift) as usize;
-        let base_offset = hash_table[hash_index] as usize;
-
-        // Check if we have a match
-        if base_offset > 0
-            && base_offset + WORD_SIZE <= base_end
-            && new_data[pos..pos + WORD_SIZE] == base_data[base_offset..base_offset + WORD_SIZE]
-        {
-            // Found a match, extend it
-            let match_len = extend_match(new_data, base_data, pos, base_offset, end, base_end);
-
-            // Write pending literal if any
-            if pos > literal_start {
-                let lit_len = pos - literal_start;
-                let unit = DeltaUnit::literal(lit_len as u64);
-                write_delta_unit(instruction_stream, &unit);
-                data_stream.write_bytes(&new_data[literal_start..pos]);
+    let mut searches = 0;
+    let mut misses = 0usize;
+    // Bytes skipped at least after a miss, raised to meet the deadline
+    let mut stride = 1;
+    let mut target_matcher = encoder
+        .target_matches
+        .then(|| TargetMatcher::new(end - start));
+
+    while pos < end {
+        if !encoder.aligned(pos) {
+            // Copies only start at aligned offsets
+            let next = pos.next_multiple_of(encoder.alignment).min(end);
+            encoder.literal(pos, next);
+            pos = next;
+            continue;
+        }
+        searches += 1;
+        if searches % DEADLINE_CHECK_INTERVAL == 0 {
+            match encoder.deadline {
+                Some(deadline) if encoder.degrade_on_deadline => {
+                    match pace(encoder.started, deadline, pos - start, end - pos) {
+                        Pace::Expired => {
+                            encoder.literal(pos, end);
+                            return Ok(());
+                        }
+                        Pace::Behind => stride = (stride * 2).min(MAX_DEGRADED_STRIDE),
+                        Pace::OnTrack => {}
+                    }
+                }
+                _ => check_deadline(encoder.deadline)?,
+            }
+        }
+        encoder.check_size()?;
+
+        let Some(found) = matcher.find_match(target, base, pos).filter(|found| {
+            is_valid_match(found, pos, end, base_end)
+                && encoder.aligned(found.base_offset)
+                && encoder.copy_allowed(found.base_offset)
+                && encoder.copy_pays_off(found.base_offset, found.length)
+        }) else {
+            // Fall back to data repeated earlier in the target
+            if let Some(target_matcher) = &mut target_matcher {
+                if let Some(found) = target_matcher.find_match(target, pos, end).filter(|found| {
+                    encoder.aligned(found.base_offset)
+                        && encoder.copy_pays_off(base_data.len() + found.base_offset, found.length)
+                }) {
+                    encoder.target_copy(base_data.len(), found.base_offset, found.length);
+                    pos += found.length;
+                    misses = 0;
+                    continue;
+                }
+                target_matcher.insert(target, pos);
             }
 
-            // Write copy instruction
-            let unit = DeltaUnit::copy(base_offset as u64, match_len as u64);
-            write_delta_unit(instruction_stream, &unit);
+            // No match, advance by one byte or further after a long miss run
+            let step = encoder
+                .skip_shift
+                .map_or(1, |shift| 1 + (misses >> shift))
+                .max(stride)
+                .min(end - pos);
+            encoder.literal(pos, pos + step);
+            pos += step;
+            misses += 1;
+            continue;
+        };
+        misses = 0;
 
-            // Advance position
-            pos += match_len;
-            literal_start = pos;
+        debug_assert_eq!(
+            target[pos..pos + found.length],
+            base[found.base_offset..found.base_offset + found.length],
+            "matcher returned a match whose bytes differ"
+        );
 
-            // Recompute fingerprint
-            if pos + WORD_SIZE <= end {
-                fingerprint = compute_fingerprint(new_data, pos);
-            }
-            continue;
-        }
+        // Write copy instruction, anchored as far back as the data matches
+        let back = encoder.extend_backward(base, pos, found.base_offset);
+        encoder.copy(found.base_offset - back, back + found.length);
 
-        // No match, advance by one byte
-        pos += 1;
-        if pos + WORD_SIZE <= end {
-            fingerprint = roll_fingerprint(fingerprint, new_data[pos + WORD_SIZE - 1]);
-        }
+        // Advance position
+        pos += found.length;
     }
 
-    // Write final literal if any
-    if literal_start < end {
-        let lit_len = end - literal_start;
-        let unit = DeltaUnit::literal(lit_len as u64);
-        write_delta_unit(instruction_stream, &unit);
-        data_stream.write_bytes(&new_data[literal_start..end]);
-    }
+    Ok(())
 }
 
-/// Extends a match as far as possible.
-fn extend_match(
-    new_data: &[u8],
-    base_data: &[u8],
-    new_pos: usize,
-    base_pos: usize,
-    new_end: usize,
-    base_end: usize,
-) -> usize {
-    let mut len = WORD_SIZE;
-
-    #[cfg(feature = "simd")]
-    {
-        use wide::u8x16;
-
-        // Extend in 16-byte chunks with SIMD
-        while new_pos + len + 16 <= new_end && base_pos + len + 16 <= base_end {
-            let new_chunk = u8x16::new(
-                new_data[new_pos + len..new_pos + len + 16]
-                    .try_into()
-                    .unwrap(),
-            );
-            let base_chunk = u8x16::new(
-                base_data[base_pos + len..base_pos + len + 16]
-                    .try_into()
-                    .unwrap(),
-            );
-
-            if new_chunk != base_chunk {
-                break;
-            }
-            len += 16;
-        }
-    }
+/// Progress of an encoding against its deadline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Pace {
+    OnTrack,
+    /// At the pace so far, the encoding would finish after the deadline.
+    Behind,
+    Expired,
+}
 
-    // Extend in 8-byte chunks
-    while new_pos + len + 8 <= new_end && base_pos + len + 8 <= base_end {
-        let new_chunk = u64::from_le_bytes(
-            new_data[new_pos + len..new_pos + len + 8]
-                .try_into()
-                .unwrap(),
-        );
-        let base_chunk = u64::from_le_bytes(
-            base_data[base_pos + len..base_pos + len + 8]
-                .try_into()
-                .unwrap(),
-        );
-        if new_chunk != base_chunk {
-            break;
-        }
-        len += 8;
+/// Compares the time an encoding started at `started` needs for the
+/// `remaining` bytes, at the pace it took for the `done` bytes, with the
+/// time left until `deadline`.
+#[allow(clippy::cast_precision_loss)]
+fn pace(started: Instant, deadline: Instant, done: usize, remaining: usize) -> Pace {
+    let now = Instant::now();
+    if now >= deadline {
+        return Pace::Expired;
     }
-
-    // Extend byte by byte
-    while new_pos + len < new_end
-        && base_pos + len < base_end
-        && new_data[new_pos + len] == base_data[base_pos + len]
-    {
-        len += 1;
+    let needed = (now - started).as_secs_f64() * remaining as f64 / done.max(1) as f64;
+    if needed > (deadline - now).as_secs_f64() {
+        Pace::Behind
+    } else {
+        Pace::OnTrack
     }
+}
 
-    len
+/// Checks that a match reported by a matcher stays within both inputs.
+fn is_valid_match(found: &Match, pos: usize, end: usize, base_end: usize) -> bool {
+    found.length > 0
+        && found.length <= end - pos
+        && found
+            .base_offset
+            .checked_add(found.length)
+            .is_some_and(|match_end| match_end <= base_end)
 }
 
 /// Finalizes the delta by combining instruction and data streams.
-fn finalize_delta(instruction_stream: &BufferStream, data_stream: &BufferStream) -> Vec<u8> {
+pub fn finalize_delta(instruction_stream: &BufferStream, data_stream: &BufferStream) -> Vec<u8> {
     let mut result = BufferStream::with_capacity(instruction_stream.len() + data_stream.len() + 10);
 
     // Write instruction length as varint
@@ -380,59 +789,428 @@ fn finalize_delta(instruction_stream: &BufferStream, data_stream: &BufferStream)
 /// Decodes delta data using the base data.
 #[allow(clippy::cast_possible_truncation)]
 pub fn decode(delta: &[u8], base_data: &[u8]) -> Result<Vec<u8>> {
-    let mut delta_stream = BufferStream::from_slice(delta);
+    let (header, payload) = header::split(delta)?;
+    let (raw_deltas, _) = prepare_payload(header, payload, base_data.len())?;
+    check_base_checksum(header, base_data)?;
+    let output = decode_raw(&raw_deltas, base_data)?;
+    check_target_checksum(header, || crc32(&output))?;
+    Ok(output)
+}
 
-    // Read instruction length
-    let instruction_len = read_varint(&mut delta_stream)? as usize;
-    let inst_start = delta_stream.position();
-    let inst_end = inst_start + instruction_len;
+/// Applies already prepared deltas into a new buffer.
+pub fn decode_raw(raw_deltas: &[RawDelta<'_>], base_data: &[u8]) -> Result<Vec<u8>> {
+    let mut output = BufferStream::with_capacity(INIT_BUFFER_SIZE);
+    for raw in raw_deltas {
+        apply_instructions(
+            &raw.instructions,
+            raw.data,
+            base_data,
+            raw.target_copies,
+            &mut output,
+        )
+        .map_err(|failure| failure.error)?;
+    }
+
+    Ok(output.into_vec())
+}
+
+/// Returns true if any of the deltas copies from the reconstructed target.
+///
+/// Such deltas cannot be decoded slice by slice with [`for_each_slice`] and
+/// have to be assembled with [`decode_raw`] instead.
+pub fn has_target_copies(raw_deltas: &[RawDelta<'_>]) -> bool {
+    raw_deltas.iter().any(|raw| raw.target_copies)
+}
+
+/// Decodes `delta` into the start of `output`, returning the bytes written.
+pub fn decode_into_slice(delta: &[u8], base_data: &[u8], output: &mut [u8]) -> Result<usize> {
+    let (header, payload) = header::split(delta)?;
+    let (raw_deltas, output_size) = prepare_payload(header, payload, base_data.len())?;
+    if output_size > output.len() as u64 {
+        return Err(GDeltaError::BufferError(format!(
+            "Output buffer too small: need {output_size} bytes, have {}",
+            output.len()
+        )));
+    }
+    check_base_checksum(header, base_data)?;
+
+    let len = if has_target_copies(&raw_deltas) {
+        let decoded = decode_raw(&raw_deltas, base_data)?;
+        output[..decoded.len()].copy_from_slice(&decoded);
+        decoded.len()
+    } else {
+        let mut pos = 0;
+        for_each_slice(&raw_deltas, base_data, |slice| {
+            output[pos..pos + slice.len()].copy_from_slice(slice);
+            pos += slice.len();
+            Ok(())
+        })?;
+        pos
+    };
+    check_target_checksum(header, || crc32(&output[..len]))?;
+    Ok(len)
+}
+
+/// Parses the framing of `delta` and validates every raw delta it contains
+/// before any output is produced, returning them with the output size.
+///
+/// An identity delta is returned as a single copy of the whole base, which
+/// is `base_len` bytes long.
+pub fn prepare(delta: &[u8], base_len: usize) -> Result<(Vec<RawDelta<'_>>, u64)> {
+    let (header, payload) = header::split(delta)?;
+    prepare_payload(header, payload, base_len)
+}
+
+/// Like [`prepare`], for a payload whose framing was already parsed and
+/// verified.
+pub fn prepare_payload<'a>(
+    header: Option<DeltaHeader<'_>>,
+    payload: &'a [u8],
+    base_len: usize,
+) -> Result<(Vec<RawDelta<'a>>, u64)> {
+    if header.is_some_and(|header| header.is_identity()) {
+        let raw = RawDelta::identity(base_len);
+        let output_size = raw.validate()?;
+        check_target_size(header, output_size)?;
+        return Ok((vec![raw], output_size));
+    }
+
+    let segments = match header {
+        Some(header) if header.is_segmented() => segment::split_segments(payload)?,
+        _ => vec![payload],
+    };
+
+    check_target_copies(header)?;
+    let mut raw_deltas = Vec::with_capacity(segments.len());
+    let mut output_size = 0u64;
+    let target_copies = header.is_some_and(|header| header.has_target_copies());
+    for segment in segments {
+        let mut raw = RawDelta::parse(segment)?;
+        raw.target_copies = target_copies;
+        output_size = output_size.saturating_add(raw.validate()?);
+        raw_deltas.push(raw);
+    }
+
+    check_target_size(header, output_size)?;
+    Ok((raw_deltas, output_size))
+}
+
+/// Returns an error if `header` records a target checksum other than the
+/// one `actual` computes; `actual` is only called if there is one.
+pub fn check_target_checksum(
+    header: Option<DeltaHeader<'_>>,
+    actual: impl FnOnce() -> u32,
+) -> Result<()> {
+    check_checksum(header.and_then(|header| header.target_checksum), actual)
+}
+
+/// Returns an error if `header` records a base checksum that `base_data`
+/// does not match, so a delta applied to the wrong base fails before any
+/// output is produced.
+pub fn check_base_checksum(header: Option<DeltaHeader<'_>>, base_data: &[u8]) -> Result<()> {
+    check_checksum(header.and_then(|header| header.base_checksum), || {
+        crc32(base_data)
+    })
+}
+
+fn check_checksum(expected: Option<u32>, actual: impl FnOnce() -> u32) -> Result<()> {
+    match expected {
+        Some(expected) => {
+            let actual = actual();
+            if expected == actual {
+                Ok(())
+            } else {
+                Err(GDeltaError::ChecksumMismatch { expected, actual })
+            }
+        }
+        None => Ok(()),
+    }
+}
+
+/// Returns an error if `header` allows copies from the target without
+/// recording the target size.
+///
+/// An overlapping target copy repeats the bytes it writes, so a delta of a
+/// few bytes can describe a target of any size. The stored size, which the
+/// instruction lengths must add up to, lets callers bound the output
+/// before producing any of it.
+pub fn check_target_copies(header: Option<DeltaHeader<'_>>) -> Result<()> {
+    match header {
+        Some(header) if header.has_target_copies() && header.target_size.is_none() => {
+            Err(GDeltaError::InvalidDelta(
+                "Delta copies from the target but does not record the target size".to_string(),
+            ))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Checks the output size, the sum of all instruction lengths, against
+/// the target size recorded in the header.
+pub fn check_target_size(header: Option<DeltaHeader<'_>>, output_size: u64) -> Result<()> {
+    match header.and_then(|header| header.target_size) {
+        Some(expected) if expected != output_size => Err(GDeltaError::SizeMismatch {
+            expected: usize::try_from(expected).unwrap_or(usize::MAX),
+            actual: usize::try_from(output_size).unwrap_or(usize::MAX),
+        }),
+        _ => Ok(()),
+    }
+}
 
-    if inst_end > delta.len() {
+/// Base data that copy instructions read from.
+///
+/// Implemented for contiguous slices and for bases split across several
+/// buffers, so decoding does not require a concatenated copy of the base.
+pub trait BaseSlices<'a> {
+    /// Total base length in bytes.
+    fn len(&self) -> usize;
+
+    /// Passes the slices making up `base[offset..offset + length]` to `sink`.
+    ///
+    /// The range must lie within the base.
+    fn for_each_range(
+        &self,
+        offset: usize,
+        length: usize,
+        sink: &mut dyn FnMut(&'a [u8]) -> Result<()>,
+    ) -> Result<()>;
+}
+
+impl<'a> BaseSlices<'a> for &'a [u8] {
+    fn len(&self) -> usize {
+        <[u8]>::len(self)
+    }
+
+    fn for_each_range(
+        &self,
+        offset: usize,
+        length: usize,
+        sink: &mut dyn FnMut(&'a [u8]) -> Result<()>,
+    ) -> Result<()> {
+        sink(&self[offset..offset + length])
+    }
+}
+
+/// Passes the base or literal slice produced by each instruction to `sink`,
+/// in output order.
+///
+/// The raw deltas must have been validated by [`prepare`].
+pub fn for_each_slice<'a, B: BaseSlices<'a>>(
+    raw_deltas: &[RawDelta<'a>],
+    base_data: B,
+    mut sink: impl FnMut(&'a [u8]) -> Result<()>,
+) -> Result<()> {
+    if has_target_copies(raw_deltas) {
         return Err(GDeltaError::InvalidDelta(
-            "Instruction length exceeds delta size".to_string(),
+            "Copies from the target need the assembled output".to_string(),
         ));
     }
 
-    // Position data stream after instructions
-    let data_start = inst_end;
-    let mut data_stream = BufferStream::from_slice(&delta[data_start..]);
+    for raw in raw_deltas {
+        let mut instructions = BufferStream::from_slice(&raw.instructions);
+        let mut literal_pos = 0;
 
-    // Output buffer
-    let mut output = BufferStream::with_capacity(INIT_BUFFER_SIZE);
+        while instructions.remaining() > 0 {
+            let unit = read_delta_unit(&mut instructions)?;
+            let length = unit.length as usize;
+            if unit.is_copy {
+                let offset = unit.offset as usize;
+                if offset.saturating_add(length) > base_data.len() {
+                    return Err(GDeltaError::InvalidDelta(format!(
+                        "Copy offset {offset} + length {length} exceeds base size {}",
+                        base_data.len()
+                    )));
+                }
+                base_data.for_each_range(offset, length, &mut sink)?;
+            } else {
+                // Literal lengths were validated against the data section
+                sink(&raw.data[literal_pos..literal_pos + length])?;
+                literal_pos += length;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A headerless delta split into its instruction and literal sections.
+pub struct RawDelta<'a> {
+    /// Encoded instructions.
+    pub instructions: Cow<'a, [u8]>,
+    /// Literal data referenced by literal instructions.
+    pub data: &'a [u8],
+    /// Copies past the end of the base read from the reconstructed target.
+    pub target_copies: bool,
+}
+
+impl<'a> RawDelta<'a> {
+    /// Reads the instruction length prefix and splits the sections.
+    pub fn parse(delta: &'a [u8]) -> Result<Self> {
+        let mut stream = BufferStream::from_slice(delta);
+        let instruction_len = usize::try_from(read_varint(&mut stream)?).unwrap_or(usize::MAX);
+        let inst_start = stream.position();
+        let inst_end = inst_start.saturating_add(instruction_len);
+
+        if inst_end > delta.len() {
+            return Err(GDeltaError::InvalidDelta(
+                "Instruction length exceeds delta size".to_string(),
+            ));
+        }
+
+        Ok(Self {
+            instructions: Cow::Borrowed(&delta[inst_start..inst_end]),
+            data: &delta[inst_end..],
+            target_copies: false,
+        })
+    }
+
+    /// Returns the instructions reproducing a base of `base_len` bytes.
+    pub fn identity(base_len: usize) -> Self {
+        let mut instructions = BufferStream::with_capacity(12);
+        if base_len > 0 {
+            write_delta_unit(&mut instructions, &DeltaUnit::copy(0, base_len as u64));
+        }
+        Self {
+            instructions: Cow::Owned(instructions.into_vec()),
+            data: &[],
+            target_copies: false,
+        }
+    }
+
+    /// Checks the literal data length, returning the total output size.
+    pub fn validate(&self) -> Result<u64> {
+        validate_instructions(&self.instructions, self.data.len())
+    }
+}
+
+/// Where and why applying an instruction stream stopped.
+pub struct ApplyFailure {
+    /// Index of the failing instruction.
+    pub instruction: usize,
+    /// Offset of the failing instruction within the instruction stream.
+    pub offset: usize,
+    /// The error that stopped decoding.
+    pub error: GDeltaError,
+}
+
+/// Whether a target copy of `length` bytes from `offset` can be applied to
+/// an output of `output_len` bytes.
+///
+/// The source must start within the output, but may overlap the bytes the
+/// copy itself writes: a copy from `output_len - 1` of any length repeats
+/// the last byte. Empty copies may start at the end of the output.
+pub(crate) fn target_copy_in_bounds(offset: u64, length: u64, output_len: u64) -> bool {
+    offset < output_len || (length == 0 && offset == output_len)
+}
+
+/// Replays `instructions` against `base_data`, appending to `output`, and
+/// returns the number of instructions applied.
+///
+/// On failure, `output` holds everything reconstructed before the failing
+/// instruction, plus whatever part of a truncated literal was available.
+pub fn apply_instructions(
+    instructions: &[u8],
+    data: &[u8],
+    base_data: &[u8],
+    target_copies: bool,
+    output: &mut BufferStream,
+) -> std::result::Result<usize, ApplyFailure> {
+    let mut delta_stream = BufferStream::from_slice(instructions);
+    let mut data_stream = BufferStream::from_slice(data);
     let base_stream = BufferStream::from_slice(base_data);
+    let mut instruction = 0;
 
     // Process instructions
-    while delta_stream.position() < inst_end {
-        let unit = read_delta_unit(&mut delta_stream)?;
+    while delta_stream.remaining() > 0 {
+        let offset = delta_stream.position();
+        let fail = |error| ApplyFailure {
+            instruction,
+            offset,
+            error,
+        };
+        let unit = read_delta_unit(&mut delta_stream).map_err(fail)?;
+
+        if unit.is_copy && target_copies && unit.offset >= base_data.len() as u64 {
+            // Copy from the part of the target reconstructed so far
+            let offset = (unit.offset - base_data.len() as u64) as usize;
+            let length = unit.length as usize;
 
-        if unit.is_copy {
+            if !target_copy_in_bounds(offset as u64, length as u64, output.len() as u64) {
+                return Err(fail(GDeltaError::InvalidDelta(format!(
+                    "Target copy offset {} is past output size {}",
+                    offset,
+                    output.len()
+                ))));
+            }
+
+            output.copy_within(offset, length).map_err(fail)?;
+        } else if unit.is_copy {
             // Copy from base data
             let offset = unit.offset as usize;
             let length = unit.length as usize;
 
-            if offset + length > base_data.len() {
-                return Err(GDeltaError::InvalidDelta(format!(
+            if offset.saturating_add(length) > base_data.len() {
+                return Err(fail(GDeltaError::InvalidDelta(format!(
                     "Copy offset {} + length {} exceeds base size {}",
                     offset,
                     length,
                     base_data.len()
-                )));
+                ))));
             }
 
-            output.copy_from(&base_stream, offset, length)?;
+            output
+                .copy_from(&base_stream, offset, length)
+                .map_err(fail)?;
         } else {
             // Copy literal data
             let length = unit.length as usize;
-            output.append_from_cursor(&mut data_stream, length)?;
+            if length > data_stream.remaining() {
+                let available = data_stream.remaining();
+                output
+                    .append_from_cursor(&mut data_stream, available)
+                    .map_err(fail)?;
+                return Err(fail(GDeltaError::UnexpectedEndOfData));
+            }
+            output
+                .append_from_cursor(&mut data_stream, length)
+                .map_err(fail)?;
         }
+        instruction += 1;
     }
 
-    Ok(output.into_vec())
+    Ok(instruction)
+}
+
+/// Checks that the literal data referenced by `instructions` exactly fills
+/// the data section, returning the total output size.
+///
+/// Running this before decoding turns a truncated or padded delta into a
+/// single clear error instead of a failure partway through reconstruction.
+fn validate_instructions(instructions: &[u8], data_len: usize) -> Result<u64> {
+    let mut stream = BufferStream::from_slice(instructions);
+    let mut literal_len = 0u64;
+    let mut output_len = 0u64;
+
+    while stream.remaining() > 0 {
+        let unit = read_delta_unit(&mut stream)?;
+        if !unit.is_copy {
+            literal_len = literal_len.saturating_add(unit.length);
+        }
+        output_len = output_len.saturating_add(unit.length);
+    }
+
+    if literal_len != data_len as u64 {
+        return Err(GDeltaError::InvalidDelta(format!(
+            "Instructions reference {literal_len} bytes of literal data, but the data section has {data_len} bytes"
+        )));
+    }
+
+    Ok(output_len)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::matcher::GearMatcher;
 
     #[test]
     fn test_find_common_prefix() {
@@ -449,6 +1227,15 @@ mod tests {
         assert_eq!(find_common_suffix(a, b, 0), 8);
     }
 
+    fn encode(new_data: &[u8], base_data: &[u8]) -> Result<Vec<u8>> {
+        super::encode(
+            new_data,
+            base_data,
+            &mut GearMatcher::new(),
+            &EncodeOptions::default(),
+        )
+    }
+
     #[test]
     fn test_encode_decode_simple() {
         let base = b"The quick brown fox jumps over the lazy dog";
@@ -482,4 +1269,305 @@ mod tests {
 
         assert_eq!(decoded, new);
     }
+
+    #[test]
+    fn test_copy_window_limits_look_behind() {
+        // Target revisits the start of the base after reading its end
+        let mut state = 0x2545_f491_u32;
+        let base: Vec<u8> = (0..20_000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect();
+        let mut new = base[15_000..].to_vec();
+        new.extend_from_slice(&base[..5_000]);
+
+        let window = 4096;
+        let options = EncodeOptions::new().copy_window(window);
+        let delta = super::encode(&new, &base, &mut GearMatcher::new(), &options).unwrap();
+        assert_eq!(decode(&delta, &base).unwrap(), new);
+
+        let mut stream = BufferStream::from_slice(&delta);
+        let inst_len = read_varint(&mut stream).unwrap() as usize;
+        let mut instructions = BufferStream::from_slice(&delta[stream.position()..][..inst_len]);
+        let mut high_water = 0;
+        while instructions.position() < inst_len {
+            let unit = read_delta_unit(&mut instructions).unwrap();
+            if unit.is_copy {
+                let offset = unit.offset as usize;
+                assert!(offset + window >= high_water);
+                high_water = high_water.max(offset + unit.length as usize);
+            }
+        }
+
+        // Without the window the wrap-around is encoded as a copy
+        let unbounded = encode(&new, &base).unwrap();
+        assert!(unbounded.len() < delta.len());
+    }
+
+    /// Returns the lengths of all copy instructions in a raw delta.
+    fn copy_lengths(delta: &[u8]) -> Vec<u64> {
+        let raw = RawDelta::parse(delta).unwrap();
+        let mut instructions = BufferStream::from_slice(&raw.instructions);
+        let mut lengths = Vec::new();
+        while instructions.remaining() > 0 {
+            let unit = read_delta_unit(&mut instructions).unwrap();
+            if unit.is_copy {
+                lengths.push(unit.length);
+            }
+        }
+        lengths
+    }
+
+    #[test]
+    fn test_copies_must_beat_literals() {
+        /// Reports a 3-byte match at offset 200 wherever one exists.
+        struct Tiny;
+
+        impl Matcher for Tiny {
+            fn index(&mut self, _base: &[u8], _start: usize, _end: usize) {}
+
+            fn find_match(&mut self, target: &[u8], base: &[u8], pos: usize) -> Option<Match> {
+                (target.get(pos..pos + 3)? == base.get(200..203)?).then_some(Match {
+                    base_offset: 200,
+                    length: 3,
+                })
+            }
+        }
+
+        let base: Vec<u8> = (0..=255).collect();
+        let new: Vec<u8> = (0..50).flat_map(|i| [i, 200, 201, 202]).collect();
+        let delta = super::encode(&new, &base, &mut Tiny, &EncodeOptions::default()).unwrap();
+
+        // A 3-byte copy from offset 200 takes 3 bytes, no better than a literal
+        assert!(copy_lengths(&delta).is_empty());
+        assert_eq!(decode(&delta, &base).unwrap(), new);
+    }
+
+    #[test]
+    fn test_min_match_len() {
+        let base: Vec<u8> = (0..=255).cycle().take(10_000).collect();
+        let mut new = base.clone();
+        for i in (0..new.len()).step_by(40) {
+            new[i] ^= 0xFF;
+        }
+
+        let options = EncodeOptions::new().min_match_len(64);
+        let delta = super::encode(&new, &base, &mut GearMatcher::new(), &options).unwrap();
+        assert_eq!(decode(&delta, &base).unwrap(), new);
+        assert!(copy_lengths(&delta).iter().all(|&len| len >= 64));
+        assert!(!copy_lengths(&encode(&new, &base).unwrap()).is_empty());
+    }
+
+    #[test]
+    fn test_repeated_literal_runs_become_target_copies() {
+        // The inserted line is too short for the target matcher on its own
+        let base: Vec<u8> = (0..2048u32)
+            .flat_map(|i| i.wrapping_mul(0x9E37_79B9).to_le_bytes())
+            .collect();
+        let token = b"#[inline]\n";
+        let mut new = Vec::new();
+        for chunk in base.chunks(512) {
+            new.extend_from_slice(chunk);
+            new.extend_from_slice(token);
+        }
+
+        let plain = encode(&new, &base).unwrap();
+        let plain_literals = crate::validate(&plain, base.len()).unwrap().literal_bytes;
+        let options = EncodeOptions::new().target_matches(true);
+        let delta = crate::encode_with_options(&new, &base, &options).unwrap();
+        let literals = crate::validate(&delta, base.len()).unwrap().literal_bytes;
+        // Some runs include a few unmatched base bytes and differ, the rest
+        // are copies of the first
+        assert!(plain_literals - literals >= 6 * token.len() as u64);
+        assert_eq!(decode(&delta, &base).unwrap(), new);
+    }
+
+    #[test]
+    fn test_runs_become_overlapping_target_copies() {
+        let base = b"unrelated base data";
+        let mut new = b"header ".to_vec();
+        new.extend_from_slice(&[0u8; 100_000]);
+        new.extend_from_slice(&b"ab".repeat(50_000));
+
+        let options = EncodeOptions::new().target_matches(true);
+        let delta = crate::encode_with_options(&new, base, &options).unwrap();
+        assert!(delta.len() < 64, "{} bytes", delta.len());
+        assert_eq!(decode(&delta, base).unwrap(), new);
+
+        let mut output = Vec::new();
+        crate::decode_from_source(&delta, &mut &base[..], &mut output).unwrap();
+        assert_eq!(output, new);
+        assert_eq!(
+            crate::validate(&delta, base.len()).unwrap().target_size,
+            new.len() as u64
+        );
+    }
+
+    #[test]
+    fn test_target_copies_need_target_size() {
+        let base = b"unrelated base data";
+        let new = vec![0u8; 100_000];
+        let options = EncodeOptions::new().target_matches(true);
+        let delta = crate::encode_with_options(&new, base, &options).unwrap();
+        let (header, payload) = header::split(&delta).unwrap();
+        assert_eq!(header.unwrap().target_size, Some(new.len() as u64));
+
+        // The same copies claiming a smaller target, or no size at all
+        let with_size = |target_size: Option<u64>| {
+            let flags =
+                header::FLAG_TARGET_COPIES | target_size.map_or(0, |_| header::FLAG_TARGET_SIZE);
+            DeltaHeader {
+                version: header::VERSION,
+                flags,
+                target_size,
+                target_checksum: None,
+                base_checksum: None,
+                metadata: &[],
+            }
+            .wrap(payload)
+        };
+        assert_eq!(decode(&with_size(Some(100_000)), base).unwrap(), new);
+        assert!(matches!(
+            decode(&with_size(Some(1000)), base),
+            Err(GDeltaError::SizeMismatch { .. })
+        ));
+        let unbounded = with_size(None);
+        assert!(matches!(
+            decode(&unbounded, base),
+            Err(GDeltaError::InvalidDelta(_))
+        ));
+        assert!(crate::decode_into_slice(&unbounded, base, &mut [0; 16]).is_err());
+        let recovered = crate::decode_lossy(&unbounded, base);
+        assert!(recovered.output.is_empty() && recovered.failure.is_some());
+
+        let mut decoder = crate::ProgressiveDecoder::new(base, Vec::new());
+        assert!(decoder.feed(&unbounded).is_err());
+    }
+
+    /// Returns the target position and source offset of each copy.
+    fn copy_starts(delta: &[u8]) -> Vec<(u64, u64)> {
+        let raw = RawDelta::parse(delta).unwrap();
+        let mut instructions = BufferStream::from_slice(&raw.instructions);
+        let mut starts = Vec::new();
+        let mut pos = 0;
+        while instructions.remaining() > 0 {
+            let unit = read_delta_unit(&mut instructions).unwrap();
+            if unit.is_copy {
+                starts.push((pos, unit.offset));
+            }
+            pos += unit.length;
+        }
+        starts
+    }
+
+    #[test]
+    fn test_match_alignment() {
+        let base: Vec<u8> = (0..4096u64)
+            .flat_map(|i| i.wrapping_mul(0x9E37_79B9_7F4A_7C15).to_le_bytes())
+            .collect();
+        let mut new = base.clone();
+        new[803] ^= 0xFF;
+        new.splice(8000..8000, *b"RECORD!!");
+
+        let unaligned = encode(&new, &base).unwrap();
+        assert!(copy_starts(&unaligned).iter().any(|&(pos, _)| pos % 8 != 0));
+
+        let options = EncodeOptions::new().match_alignment(8);
+        let delta = crate::encode_with_options(&new, &base, &options).unwrap();
+        assert_eq!(decode(&delta, &base).unwrap(), new);
+        let starts = copy_starts(&delta);
+        assert!(starts.len() >= 3);
+        assert!(
+            starts
+                .iter()
+                .all(|&(pos, offset)| pos % 8 == 0 && offset % 8 == 0)
+        );
+        assert!(delta.len() < unaligned.len() + 32);
+
+        assert!(matches!(
+            crate::encode_with_options(&new, &base, &EncodeOptions::new().match_alignment(0)),
+            Err(GDeltaError::InvalidOptions(_))
+        ));
+    }
+
+    #[test]
+    fn test_extend_matches_backward() {
+        let noise: Vec<u8> = (0..40_000u32)
+            .flat_map(|i| i.wrapping_mul(0x9E37_79B9).to_le_bytes())
+            .collect();
+        let (base, edits) = noise.split_at(100_000);
+        // Unchanged blocks framed by edits
+        let mut new = Vec::new();
+        for (i, block) in base.chunks(10_000).enumerate() {
+            new.extend_from_slice(&edits[i * 1000..i * 1000 + 500]);
+            new.extend_from_slice(block);
+        }
+        let inserted = 10 * 500;
+
+        // A sparsely sampled base starts most copies late
+        let options = EncodeOptions::new().memory_budget(4096);
+        let delta = crate::encode_with_options(&new, base, &options).unwrap();
+        let literals = crate::validate(&delta, base.len()).unwrap().literal_bytes;
+
+        let options = options.extend_matches_backward(true);
+        let extended = crate::encode_with_options(&new, base, &options).unwrap();
+        assert_eq!(decode(&extended, base).unwrap(), new);
+        let summary = crate::validate(&extended, base.len()).unwrap();
+        assert!(
+            summary.literal_bytes <= inserted,
+            "{}",
+            summary.literal_bytes
+        );
+        assert!(literals > summary.literal_bytes + 100, "{literals}");
+        assert!(extended.len() < delta.len());
+    }
+
+    #[test]
+    fn test_decode_rejects_literal_length_mismatch() {
+        // One literal instruction of 3 bytes
+        let delta = [1, 0x03, b'a', b'b', b'c'];
+        assert_eq!(decode(&delta, b"").unwrap(), b"abc");
+
+        for bad in [&delta[..4], &[1, 0x03, b'a', b'b', b'c', b'd'][..]] {
+            let err = decode(bad, b"").unwrap_err();
+            assert!(
+                matches!(err, GDeltaError::InvalidDelta(ref msg) if msg.contains("literal data"))
+            );
+        }
+    }
+
+    #[test]
+    fn test_decode_into_slice() {
+        let base = b"The quick brown fox jumps over the lazy dog";
+        let new = b"The quick brown cat jumps over the lazy dog";
+        let delta = encode(new, base).unwrap();
+
+        let mut output = [0u8; 64];
+        let written = decode_into_slice(&delta, base, &mut output).unwrap();
+        assert_eq!(&output[..written], new);
+
+        let mut small = [0u8; 10];
+        assert!(matches!(
+            decode_into_slice(&delta, base, &mut small),
+            Err(GDeltaError::BufferError(_))
+        ));
+    }
+
+    #[test]
+    fn test_pace() {
+        use std::time::Duration;
+
+        let now = Instant::now();
+        let started = now - Duration::from_secs(1);
+        let deadline = now + Duration::from_secs(10);
+        // A quarter done in a second leaves three seconds of work
+        assert_eq!(pace(started, deadline, 250, 750), Pace::OnTrack);
+        // A hundredth done in a second leaves 99 seconds of work
+        assert_eq!(pace(started, deadline, 10, 990), Pace::Behind);
+        assert_eq!(pace(started, now, 10, 990), Pace::Expired);
+    }
 }
@@ -0,0 +1,105 @@
+//! Cheap detection of targets that cannot be delta-compressed.
+//!
+//! Scanning a high-entropy target against a base it shares nothing with is
+//! the encoder's worst case: every position is looked up and misses. Before
+//! doing that, the target is sampled for its byte entropy, and if it looks
+//! compressed or encrypted, a handful of target windows are searched for in
+//! the base. If none occur, the target is emitted as a single literal.
+
+use crate::gear::{WORD_SIZE, compute_fingerprint, fingerprints};
+use std::collections::HashSet;
+
+/// Targets shorter than this are always scanned normally.
+const MIN_CHECK_SIZE: usize = 64 * 1024;
+
+/// Number of evenly spaced chunks sampled for the entropy estimate.
+const SAMPLE_CHUNKS: usize = 16;
+
+/// Size of each sampled chunk.
+const SAMPLE_CHUNK_SIZE: usize = 4096;
+
+/// Entropy in bits per byte above which data is treated as incompressible.
+const ENTROPY_THRESHOLD: f64 = 7.9;
+
+/// Number of target windows searched for in the base.
+const PROBES: usize = 256;
+
+/// Returns true if `target` looks incompressible and shares none of the
+/// probed windows with `base`.
+pub(crate) fn looks_incompressible(target: &[u8], base: &[u8]) -> bool {
+    target.len() >= MIN_CHECK_SIZE
+        && sampled_entropy(target) >= ENTROPY_THRESHOLD
+        && !shares_probe(target, base)
+}
+
+/// Estimates the Shannon entropy of `data` from evenly spaced chunks.
+#[allow(clippy::cast_precision_loss)]
+fn sampled_entropy(data: &[u8]) -> f64 {
+    let mut counts = [0u64; 256];
+    let stride = data.len() / SAMPLE_CHUNKS;
+    for chunk in 0..SAMPLE_CHUNKS {
+        let start = chunk * stride;
+        let end = (start + SAMPLE_CHUNK_SIZE).min(data.len());
+        for &byte in &data[start..end] {
+            counts[byte as usize] += 1;
+        }
+    }
+
+    let total = counts.iter().sum::<u64>() as f64;
+    counts
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f64 / total;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// Returns true if any probed window of `target` occurs in `base`.
+fn shares_probe(target: &[u8], base: &[u8]) -> bool {
+    let stride = (target.len() - WORD_SIZE) / PROBES;
+    let probes: HashSet<u64> = (0..PROBES)
+        .map(|probe| compute_fingerprint(target, probe * stride))
+        .collect();
+
+    fingerprints(base).any(|(_, fingerprint)| probes.contains(&fingerprint))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pseudo_random(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                (state >> 32) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_random_target_unrelated_base() {
+        let target = pseudo_random(200_000, 1);
+        let base = pseudo_random(200_000, 2);
+        assert!(looks_incompressible(&target, &base));
+    }
+
+    #[test]
+    fn test_random_target_shared_with_base() {
+        let base = pseudo_random(200_000, 3);
+        let mut target = pseudo_random(100_000, 4);
+        target.extend_from_slice(&base[..100_000]);
+        assert!(!looks_incompressible(&target, &base));
+    }
+
+    #[test]
+    fn test_text_target_is_scanned() {
+        let target = b"lorem ipsum dolor sit amet ".repeat(10_000);
+        assert!(!looks_incompressible(&target, b""));
+    }
+}
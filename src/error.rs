@@ -24,6 +24,33 @@ pub enum GDeltaError {
 
     /// Buffer operation failed.
     BufferError(String),
+
+    /// The checksum stored in the delta does not match its contents.
+    ChecksumMismatch {
+        /// Checksum stored in the delta
+        expected: u32,
+        /// Checksum computed over the received data
+        actual: u32,
+    },
+
+    /// Reading or writing a stream failed.
+    Io(String),
+
+    /// The requested options cannot be combined or are not supported by
+    /// the operation.
+    InvalidOptions(String),
+
+    /// Encoding did not finish before the deadline set in the options.
+    DeadlineExceeded,
+
+    /// The delta would exceed the maximum ratio set in the options.
+    NotBeneficial,
+
+    /// The inputs are less similar than required, so no delta was encoded.
+    NotSimilar,
+
+    /// The delta exceeds a limit set in the [`crate::DecodeLimits`].
+    LimitExceeded(String),
 }
 
 impl fmt::Display for GDeltaError {
@@ -38,8 +65,28 @@ impl fmt::Display for GDeltaError {
                 )
             }
             GDeltaError::BufferError(msg) => write!(f, "Buffer error: {msg}"),
+            GDeltaError::ChecksumMismatch { expected, actual } => {
+                write!(
+                    f,
+                    "Checksum mismatch: expected {expected:#010x}, got {actual:#010x}"
+                )
+            }
+            GDeltaError::Io(msg) => write!(f, "I/O error: {msg}"),
+            GDeltaError::InvalidOptions(msg) => write!(f, "Invalid options: {msg}"),
+            GDeltaError::DeadlineExceeded => write!(f, "Encoding deadline exceeded"),
+            GDeltaError::NotBeneficial => {
+                write!(f, "Delta would exceed the maximum size ratio")
+            }
+            GDeltaError::NotSimilar => write!(f, "Inputs are not similar enough to encode"),
+            GDeltaError::LimitExceeded(msg) => write!(f, "Limit exceeded: {msg}"),
         }
     }
 }
 
 impl std::error::Error for GDeltaError {}
+
+impl From<std::io::Error> for GDeltaError {
+    fn from(error: std::io::Error) -> Self {
+        GDeltaError::Io(error.to_string())
+    }
+}
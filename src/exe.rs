@@ -0,0 +1,316 @@
+//! Section-aware preprocessing for executable files.
+//!
+//! Compiled binaries change in many small places at once: code moves shift
+//! every later address, and unrelated sections often contain similar byte
+//! patterns. Matching the whole target against the whole base wastes hash
+//! table slots on those false matches. [`encode`] instead splits ELF and PE
+//! files into their sections and encodes each section against the base
+//! section of the same name, falling back to the whole base for headers,
+//! gaps and sections that are new.
+//!
+//! The result is a regular delta that [`crate::decode`] applies; no
+//! preprocessing is needed on the decoding side.
+//!
+//! # Examples
+//!
+//! ```
+//! let base = b"not an executable, so the plain encoder is used";
+//! let new = b"still not an executable, so the plain encoder is used";
+//!
+//! let delta = gdelta::exe::encode(new, base).unwrap();
+//! assert_eq!(gdelta::decode(&delta, base).unwrap(), new);
+//! ```
+
+use crate::delta::{DeltaWriter, encode_rebased};
+use crate::error::Result;
+use crate::matcher::GearMatcher;
+use std::ops::Range;
+
+/// Executable container formats recognized by [`sections`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecutableFormat {
+    /// Executable and Linkable Format (Linux, BSD, most embedded targets).
+    Elf,
+    /// Portable Executable (Windows).
+    Pe,
+}
+
+/// A named region of an executable file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Section {
+    /// Section name, e.g. `.text`.
+    pub name: String,
+    /// Byte range of the section within the file.
+    pub range: Range<usize>,
+}
+
+/// Detects the executable format of `data` and lists its sections.
+///
+/// Only sections with contents in the file are returned, sorted by offset.
+/// Returns `None` if `data` is not a well-formed ELF or PE file.
+pub fn sections(data: &[u8]) -> Option<(ExecutableFormat, Vec<Section>)> {
+    let (format, mut sections) = if data.starts_with(b"\x7fELF") {
+        (ExecutableFormat::Elf, elf_sections(data)?)
+    } else if data.starts_with(b"MZ") {
+        (ExecutableFormat::Pe, pe_sections(data)?)
+    } else {
+        return None;
+    };
+
+    sections.retain(|section| !section.range.is_empty() && section.range.end <= data.len());
+    sections.sort_by_key(|section| section.range.start);
+    Some((format, sections))
+}
+
+/// Encodes `new` against `base`, matching executables section by section.
+///
+/// If either input is not a recognized executable, this is equivalent to
+/// [`crate::encode`].
+///
+/// # Errors
+///
+/// Sections are encoded with the default options, which never fail, so an
+/// error is a bug: `GDeltaError::InvalidDelta` if the delta of a section
+/// cannot be parsed again to rebase its copies.
+pub fn encode(new: &[u8], base: &[u8]) -> Result<Vec<u8>> {
+    let (Some((new_format, new_sections)), Some((base_format, base_sections))) =
+        (sections(new), sections(base))
+    else {
+        return crate::encode(new, base);
+    };
+    if new_format != base_format {
+        return crate::encode(new, base);
+    }
+
+    let mut writer = DeltaWriter::new();
+    let mut pos = 0;
+    for section in &new_sections {
+        // Overlapping sections are covered by the earlier one
+        if section.range.start < pos {
+            continue;
+        }
+        if section.range.start > pos {
+            encode_region(
+                &mut writer,
+                &new[pos..section.range.start],
+                base,
+                0..base.len(),
+            )?;
+        }
+        let base_range = base_sections
+            .iter()
+            .find(|candidate| candidate.name == section.name)
+            .map_or(0..base.len(), |candidate| candidate.range.clone());
+        encode_region(&mut writer, &new[section.range.clone()], base, base_range)?;
+        pos = section.range.end;
+    }
+    if pos < new.len() {
+        encode_region(&mut writer, &new[pos..], base, 0..base.len())?;
+    }
+
+    Ok(writer.finish())
+}
+
+/// Encodes `target` against `base[base_range]` and appends the instructions
+/// to `writer`, rebasing copy offsets onto the full base.
+fn encode_region(
+    writer: &mut DeltaWriter,
+    target: &[u8],
+    base: &[u8],
+    base_range: Range<usize>,
+) -> Result<()> {
+    encode_rebased(
+        writer,
+        target,
+        &base[base_range.clone()],
+        base_range.start as u64,
+        &mut GearMatcher::new(),
+    )?;
+    Ok(())
+}
+
+/// Reads an unsigned integer of `size` bytes at `at`.
+fn read_uint(data: &[u8], at: usize, size: usize, big_endian: bool) -> Option<u64> {
+    let bytes = data.get(at..at.checked_add(size)?)?;
+    let fold = |acc: u64, &byte: &u8| (acc << 8) | u64::from(byte);
+    Some(if big_endian {
+        bytes.iter().fold(0, fold)
+    } else {
+        bytes.iter().rev().fold(0, fold)
+    })
+}
+
+/// Reads a NUL-terminated name starting at `at`.
+fn read_name(data: &[u8], at: usize) -> Option<String> {
+    let bytes = data.get(at..)?;
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    Some(String::from_utf8_lossy(&bytes[..end]).into_owned())
+}
+
+fn to_range(offset: u64, size: u64) -> Option<Range<usize>> {
+    let start = usize::try_from(offset).ok()?;
+    let end = start.checked_add(usize::try_from(size).ok()?)?;
+    Some(start..end)
+}
+
+fn elf_sections(data: &[u8]) -> Option<Vec<Section>> {
+    /// Section type occupying no file space (e.g. `.bss`).
+    const SHT_NOBITS: u64 = 8;
+
+    let is_64 = match data.get(4)? {
+        1 => false,
+        2 => true,
+        _ => return None,
+    };
+    let be = match data.get(5)? {
+        1 => false,
+        2 => true,
+        _ => return None,
+    };
+    let word = if is_64 { 8 } else { 4 };
+
+    let (shoff, shentsize, shnum, shstrndx) = if is_64 {
+        (
+            read_uint(data, 0x28, 8, be)?,
+            read_uint(data, 0x3A, 2, be)?,
+            read_uint(data, 0x3C, 2, be)?,
+            read_uint(data, 0x3E, 2, be)?,
+        )
+    } else {
+        (
+            read_uint(data, 0x20, 4, be)?,
+            read_uint(data, 0x2E, 2, be)?,
+            read_uint(data, 0x30, 2, be)?,
+            read_uint(data, 0x32, 2, be)?,
+        )
+    };
+
+    let header = |index: u64| -> Option<(u64, u64, Range<usize>)> {
+        let at = usize::try_from(shoff.checked_add(index.checked_mul(shentsize)?)?).ok()?;
+        let (offset_at, size_at) = if is_64 { (0x18, 0x20) } else { (0x10, 0x14) };
+        let name = read_uint(data, at, 4, be)?;
+        let kind = read_uint(data, at + 4, 4, be)?;
+        let offset = read_uint(data, at + offset_at, word, be)?;
+        let size = read_uint(data, at + size_at, word, be)?;
+        Some((name, kind, to_range(offset, size)?))
+    };
+
+    let (_, _, strtab) = header(shstrndx)?;
+    let mut sections = Vec::with_capacity(shnum as usize);
+    for index in 0..shnum {
+        let (name, kind, range) = header(index)?;
+        if kind == SHT_NOBITS {
+            continue;
+        }
+        let name_at = strtab.start.checked_add(usize::try_from(name).ok()?)?;
+        sections.push(Section {
+            name: read_name(data.get(..strtab.end)?, name_at)?,
+            range,
+        });
+    }
+    Some(sections)
+}
+
+fn pe_sections(data: &[u8]) -> Option<Vec<Section>> {
+    let pe = usize::try_from(read_uint(data, 0x3C, 4, false)?).ok()?;
+    if data.get(pe..pe.checked_add(4)?)? != b"PE\0\0" {
+        return None;
+    }
+    let count = read_uint(data, pe + 6, 2, false)? as usize;
+    let optional_size = read_uint(data, pe + 20, 2, false)? as usize;
+    let table = pe + 24 + optional_size;
+
+    let mut sections = Vec::with_capacity(count);
+    for index in 0..count {
+        let at = table + index * 40;
+        let name = data.get(at..at + 8)?;
+        let end = name.iter().position(|&b| b == 0).unwrap_or(8);
+        let size = read_uint(data, at + 16, 4, false)?;
+        let offset = read_uint(data, at + 20, 4, false)?;
+        sections.push(Section {
+            name: String::from_utf8_lossy(&name[..end]).into_owned(),
+            range: to_range(offset, size)?,
+        });
+    }
+    Some(sections)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a little-endian ELF64 file with the given sections.
+    fn build_elf(sections: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut data = vec![0u8; 64];
+        data[..6].copy_from_slice(b"\x7fELF\x02\x01");
+
+        let mut strtab = vec![0u8];
+        let mut headers = vec![[0u64; 4]]; // null section
+        for (name, contents) in sections {
+            headers.push([
+                strtab.len() as u64,
+                1,
+                data.len() as u64,
+                contents.len() as u64,
+            ]);
+            strtab.extend_from_slice(name.as_bytes());
+            strtab.push(0);
+            data.extend_from_slice(contents);
+        }
+        headers.push([strtab.len() as u64, 3, data.len() as u64, 0]);
+        strtab.extend_from_slice(b".shstrtab\0");
+        let last = headers.len() - 1;
+        headers[last][3] = strtab.len() as u64;
+        data.extend_from_slice(&strtab);
+
+        let shoff = data.len() as u64;
+        for [name, kind, offset, size] in &headers {
+            let mut header = [0u8; 64];
+            header[0..4].copy_from_slice(&(*name as u32).to_le_bytes());
+            header[4..8].copy_from_slice(&(*kind as u32).to_le_bytes());
+            header[0x18..0x20].copy_from_slice(&offset.to_le_bytes());
+            header[0x20..0x28].copy_from_slice(&size.to_le_bytes());
+            data.extend_from_slice(&header);
+        }
+        data[0x28..0x30].copy_from_slice(&shoff.to_le_bytes());
+        data[0x3A..0x3C].copy_from_slice(&64u16.to_le_bytes());
+        data[0x3C..0x3E].copy_from_slice(&(headers.len() as u16).to_le_bytes());
+        data[0x3E..0x40].copy_from_slice(&(last as u16).to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn test_elf_sections() {
+        let elf = build_elf(&[(".text", b"code code code"), (".data", b"data")]);
+        let (format, sections) = sections(&elf).unwrap();
+
+        assert_eq!(format, ExecutableFormat::Elf);
+        let names: Vec<_> = sections.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, [".text", ".data", ".shstrtab"]);
+        assert_eq!(&elf[sections[0].range.clone()], b"code code code");
+    }
+
+    #[test]
+    fn test_section_aware_roundtrip() {
+        let text: Vec<u8> = (0..4000u32).map(|i| (i * 31 % 251) as u8).collect();
+        let data: Vec<u8> = (0..2000u32).map(|i| (i * 17 % 241) as u8).collect();
+        let base = build_elf(&[(".text", &text), (".data", &data)]);
+
+        let mut new_text = text.clone();
+        new_text.splice(100..100, [0x90; 12]);
+        let mut new_data = data.clone();
+        new_data[500] ^= 0xFF;
+        let new = build_elf(&[(".text", &new_text), (".data", &new_data)]);
+
+        let delta = encode(&new, &base).unwrap();
+        assert_eq!(crate::decode(&delta, &base).unwrap(), new);
+        assert!(delta.len() < new.len() / 10);
+    }
+
+    #[test]
+    fn test_rejects_non_executables() {
+        assert!(sections(b"plain text").is_none());
+        assert!(sections(b"\x7fELF\x02\x01 truncated").is_none());
+        assert!(sections(b"MZ truncated").is_none());
+    }
+}
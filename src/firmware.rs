@@ -0,0 +1,476 @@
+//! Firmware patches that are applied in place.
+//!
+//! Microcontrollers rarely have room for a second copy of their firmware, so
+//! an update has to be written over the old image in the same flash slot.
+//! [`encode`] produces deltas that allow this: every copy reads from at or
+//! ahead of the position it writes to, at most [`MemoryMap::window`] bytes
+//! ahead, so bytes that were already overwritten are never read again and
+//! [`apply_in_place`] needs no memory beyond the slot itself. The output
+//! always fills the whole slot: the new image is followed by erased flash.
+//!
+//! The patches are plain headerless deltas. [`crate::decode`] applies them
+//! to the old image padded to the slot size and returns the new image.
+//! [`c_header`] embeds a patch in a C header together with a small decoder
+//! for the device side.
+//!
+//! Forward-only copies cannot move data to higher addresses; bytes that
+//! moved up are stored as literals. Images whose code grows at the end
+//! patch best.
+//!
+//! # Examples
+//!
+//! ```
+//! use gdelta::firmware::{self, MemoryMap};
+//!
+//! let map = MemoryMap::new(64);
+//! let old = b"bootloader v1 | app code app code app code";
+//! let new = b"bootloader v2 | app code app code";
+//!
+//! let patch = firmware::encode(new, old, &map).unwrap();
+//!
+//! let mut slot = old.to_vec();
+//! slot.resize(map.slot_size, map.fill);
+//! firmware::apply_in_place(&patch, &mut slot, &map).unwrap();
+//! assert_eq!(&slot[..new.len()], new);
+//! assert!(slot[new.len()..].iter().all(|&byte| byte == map.fill));
+//! ```
+
+use crate::buffer::BufferStream;
+use crate::delta::{self, DeltaWriter, RawDelta};
+use crate::error::{GDeltaError, Result};
+use crate::header;
+use crate::matcher::{GearMatcher, Match, Matcher};
+use crate::options::EncodeOptions;
+use crate::varint::read_delta_unit;
+use std::fmt::Write;
+
+/// Layout of the flash slot a firmware image is stored in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryMap {
+    /// Size of the slot in bytes.
+    pub slot_size: usize,
+    /// How far ahead of the write position a copy may read, in bytes.
+    ///
+    /// Decoders that buffer the slot page by page only need this much
+    /// look-ahead.
+    pub window: usize,
+    /// Value of erased flash, used to pad images to the slot size.
+    pub fill: u8,
+}
+
+impl MemoryMap {
+    /// Describes a slot of `slot_size` bytes whose erased bytes read as
+    /// `0xFF`, with copies allowed to read anywhere ahead.
+    pub fn new(slot_size: usize) -> Self {
+        Self {
+            slot_size,
+            window: slot_size,
+            fill: 0xFF,
+        }
+    }
+
+    /// Pads `image` to the slot size.
+    fn pad(&self, image: &[u8]) -> Result<Vec<u8>> {
+        if image.len() > self.slot_size {
+            return Err(GDeltaError::InvalidOptions(format!(
+                "Image of {} bytes does not fit the {} byte slot",
+                image.len(),
+                self.slot_size
+            )));
+        }
+        let mut padded = image.to_vec();
+        padded.resize(self.slot_size, self.fill);
+        Ok(padded)
+    }
+}
+
+/// Encodes the image `new` against `base` for in-place application.
+///
+/// # Errors
+///
+/// Returns `GDeltaError::InvalidOptions` if either image is larger than the
+/// slot.
+pub fn encode(new: &[u8], base: &[u8], map: &MemoryMap) -> Result<Vec<u8>> {
+    map.pad(new)?;
+    // Erased flash after the old image can be copied into the new one
+    let base = map.pad(base)?;
+    if base.starts_with(new) {
+        // The regular encoder would emit a headed identity delta
+        let mut writer = DeltaWriter::new();
+        writer.copy(0, new.len() as u64);
+        return Ok(writer.finish());
+    }
+
+    let mut matcher = ForwardOnly {
+        inner: GearMatcher::new(),
+        window: map.window,
+    };
+    let delta = delta::encode(new, &base, &mut matcher, &EncodeOptions::default())?;
+    legalize(&delta, new, map.window)
+}
+
+/// Applies a patch from [`encode`] to `slot`, which holds the old image,
+/// leaving the new image followed by erased flash in it.
+///
+/// The whole patch is checked before the first byte is written, so a
+/// malformed patch leaves the slot untouched.
+///
+/// # Errors
+///
+/// Returns `GDeltaError::SizeMismatch` if `slot` is not as large as the
+/// memory map says, and `GDeltaError::InvalidDelta` if the patch is
+/// malformed, has a header, writes past the slot, or contains a copy that
+/// would read bytes already overwritten.
+pub fn apply_in_place(delta: &[u8], slot: &mut [u8], map: &MemoryMap) -> Result<()> {
+    if slot.len() != map.slot_size {
+        return Err(GDeltaError::SizeMismatch {
+            expected: map.slot_size,
+            actual: slot.len(),
+        });
+    }
+    let len = run(delta, slot.len(), None)?;
+    run(delta, slot.len(), Some(slot))?;
+    slot[len..].fill(map.fill);
+    Ok(())
+}
+
+/// Generates a C header that embeds `delta` as `<name>_patch` along with
+/// `gdelta_apply_in_place`, a dependency-free decoder equivalent to
+/// [`apply_in_place`].
+///
+/// Characters of `name` that are not valid in a C identifier are replaced
+/// by underscores. Several generated headers can be included in the same
+/// translation unit; the decoder is only defined once.
+pub fn c_header(delta: &[u8], name: &str, map: &MemoryMap) -> String {
+    let ident: String = name
+        .chars()
+        .enumerate()
+        .map(|(i, c)| {
+            if c.is_ascii_alphanumeric() && !(i == 0 && c.is_ascii_digit()) || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let upper = ident.to_ascii_uppercase();
+
+    let mut out = String::new();
+    let _ = writeln!(out, "/* Firmware patch `{name}`, generated by gdelta. */");
+    let _ = writeln!(out, "#ifndef {upper}_PATCH_H");
+    let _ = writeln!(out, "#define {upper}_PATCH_H\n");
+    let _ = writeln!(out, "#include <stddef.h>\n#include <stdint.h>\n");
+    let _ = writeln!(out, "#define {upper}_SLOT_SIZE {}u", map.slot_size);
+    let _ = writeln!(out, "#define {upper}_FILL 0x{:02x}u", map.fill);
+    let _ = writeln!(out, "#define {upper}_PATCH_SIZE {}u\n", delta.len());
+    let _ = writeln!(
+        out,
+        "static const uint8_t {ident}_patch[{upper}_PATCH_SIZE] = {{"
+    );
+    for line in delta.chunks(12) {
+        let bytes: Vec<String> = line.iter().map(|byte| format!("0x{byte:02x}")).collect();
+        let _ = writeln!(out, "    {},", bytes.join(", "));
+    }
+    let _ = writeln!(out, "}};\n");
+    out.push_str(C_DECODER);
+    let _ = writeln!(out, "\n#endif /* {upper}_PATCH_H */");
+    out
+}
+
+/// In-place decoder emitted by [`c_header`], mirroring [`run`].
+const C_DECODER: &str = r"#ifndef GDELTA_APPLY_IN_PLACE
+#define GDELTA_APPLY_IN_PLACE
+
+static int gdelta_read_varint(const uint8_t **p, const uint8_t *end, uint64_t *value)
+{
+    uint64_t result = 0;
+    unsigned shift = 0;
+    for (;;) {
+        uint8_t byte;
+        if (*p == end || shift > 63) {
+            return 0;
+        }
+        byte = *(*p)++;
+        result |= (uint64_t)(byte & 0x7f) << shift;
+        if (!(byte & 0x80)) {
+            break;
+        }
+        shift += 7;
+    }
+    *value = result;
+    return 1;
+}
+
+/* Turns the old image in `slot` into the new one and sets the rest of the
+ * slot to `fill`. The patch is checked in a first pass and only applied in
+ * the second, so a damaged patch leaves the slot untouched. Returns 0 on
+ * success and -1 for an invalid patch. */
+static int gdelta_apply_in_place(const uint8_t *patch, size_t patch_size,
+                                 uint8_t *slot, size_t slot_size, uint8_t fill)
+{
+    int pass;
+    for (pass = 0; pass < 2; pass++) {
+        const uint8_t *p = patch;
+        const uint8_t *end = patch + patch_size;
+        const uint8_t *instructions_end;
+        const uint8_t *data;
+        uint64_t instructions_size;
+        uint64_t pos = 0;
+
+        if (!gdelta_read_varint(&p, end, &instructions_size)
+            || instructions_size > (uint64_t)(end - p)) {
+            return -1;
+        }
+        instructions_end = p + instructions_size;
+        data = instructions_end;
+
+        while (p < instructions_end) {
+            uint8_t head = *p++;
+            uint64_t length = head & 0x3f;
+            uint64_t i;
+            if (head & 0x40) {
+                uint64_t more;
+                if (!gdelta_read_varint(&p, instructions_end, &more) || more >> 58) {
+                    return -1;
+                }
+                length |= more << 6;
+            }
+            if (length > slot_size - pos) {
+                return -1;
+            }
+            if (head & 0x80) {
+                /* Copies read at or ahead of the write position */
+                uint64_t offset;
+                if (!gdelta_read_varint(&p, instructions_end, &offset)
+                    || offset < pos || offset > slot_size - length) {
+                    return -1;
+                }
+                for (i = 0; pass && i < length; i++) {
+                    slot[pos + i] = slot[offset + i];
+                }
+            } else {
+                if (length > (uint64_t)(end - data)) {
+                    return -1;
+                }
+                for (i = 0; pass && i < length; i++) {
+                    slot[pos + i] = data[i];
+                }
+                data += length;
+            }
+            pos += length;
+        }
+        if (data != end) {
+            return -1;
+        }
+        for (; pass && pos < slot_size; pos++) {
+            slot[pos] = fill;
+        }
+    }
+    return 0;
+}
+
+#endif /* GDELTA_APPLY_IN_PLACE */
+";
+
+/// Restricts matches to copies that read at most `window` bytes ahead of
+/// the target position.
+struct ForwardOnly<M> {
+    inner: M,
+    window: usize,
+}
+
+impl<M: Matcher> Matcher for ForwardOnly<M> {
+    fn index(&mut self, base: &[u8], start: usize, end: usize) {
+        self.inner.index(base, start, end);
+    }
+
+    fn find_match(&mut self, target: &[u8], base: &[u8], pos: usize) -> Option<Match> {
+        // Extending a match keeps the distance between its offsets, so the
+        // encoder cannot turn an accepted match into a backward copy
+        self.inner
+            .find_match(target, base, pos)
+            .filter(|found| found.base_offset >= pos && found.base_offset - pos <= self.window)
+    }
+}
+
+/// Turns copies in `delta` that read behind the write position or further
+/// ahead than `window` into literals.
+///
+/// The common suffix handling of the encoder bypasses the matcher and can
+/// produce such copies.
+fn legalize(delta: &[u8], new: &[u8], window: usize) -> Result<Vec<u8>> {
+    let raw = RawDelta::parse(delta)?;
+    let mut instructions = BufferStream::from_slice(&raw.instructions);
+    let mut data = raw.data;
+    let mut writer = DeltaWriter::new();
+    let mut pos = 0;
+    while instructions.remaining() > 0 {
+        let unit = read_delta_unit(&mut instructions)?;
+        let length = unit.length as usize;
+        let offset = unit.offset as usize;
+        if !unit.is_copy {
+            let (literal, rest) = data.split_at(length);
+            writer.literal(literal);
+            data = rest;
+        } else if offset >= pos && offset - pos <= window {
+            writer.copy(unit.offset, unit.length);
+        } else {
+            writer.literal(&new[pos..pos + length]);
+        }
+        pos += length;
+    }
+    Ok(writer.finish())
+}
+
+/// Checks `delta` against a slot of `slot_len` bytes and, if `slot` is
+/// given, applies it. Returns the length of the new image.
+fn run(delta: &[u8], slot_len: usize, mut slot: Option<&mut [u8]>) -> Result<usize> {
+    let (header, payload) = header::split(delta)?;
+    if header.is_some() {
+        return Err(GDeltaError::InvalidDelta(
+            "Firmware patches must not have a header".to_string(),
+        ));
+    }
+    let raw = RawDelta::parse(payload)?;
+    let mut instructions = BufferStream::from_slice(&raw.instructions);
+    let mut data = raw.data;
+    let mut pos = 0usize;
+
+    while instructions.remaining() > 0 {
+        let unit = read_delta_unit(&mut instructions)?;
+        let length = usize::try_from(unit.length).unwrap_or(usize::MAX);
+        let end = pos
+            .checked_add(length)
+            .filter(|&end| end <= slot_len)
+            .ok_or_else(|| {
+                GDeltaError::InvalidDelta(format!(
+                    "Patch writes past the end of the {slot_len} byte slot"
+                ))
+            })?;
+
+        if unit.is_copy {
+            let offset = usize::try_from(unit.offset).unwrap_or(usize::MAX);
+            if offset < pos {
+                return Err(GDeltaError::InvalidDelta(format!(
+                    "Copy to {pos} reads from {offset}, which is already overwritten"
+                )));
+            }
+            if offset.saturating_add(length) > slot_len {
+                return Err(GDeltaError::InvalidDelta(format!(
+                    "Copy offset {offset} + length {length} exceeds slot size {slot_len}"
+                )));
+            }
+            if let Some(slot) = slot.as_deref_mut() {
+                slot.copy_within(offset..offset + length, pos);
+            }
+        } else {
+            if length > data.len() {
+                return Err(GDeltaError::UnexpectedEndOfData);
+            }
+            let (literal, rest) = data.split_at(length);
+            if let Some(slot) = slot.as_deref_mut() {
+                slot[pos..end].copy_from_slice(literal);
+            }
+            data = rest;
+        }
+        pos = end;
+    }
+
+    if !data.is_empty() {
+        return Err(GDeltaError::InvalidDelta(
+            "Trailing literal data after the last instruction".to_string(),
+        ));
+    }
+    Ok(pos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A pseudo-random image of `len` bytes, like compiled code.
+    fn image(len: usize, seed: u32) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect()
+    }
+
+    fn apply(patch: &[u8], old: &[u8], map: &MemoryMap) -> Vec<u8> {
+        let mut slot = map.pad(old).unwrap();
+        apply_in_place(patch, &mut slot, map).unwrap();
+        slot
+    }
+
+    #[test]
+    fn test_in_place_round_trip() {
+        let map = MemoryMap::new(64 * 1024);
+        let old = image(40_000, 1);
+
+        // A function removed from the middle shifts the rest down
+        let mut new = old[..10_000].to_vec();
+        new.extend_from_slice(&old[12_000..]);
+        new[30_000..30_016].copy_from_slice(&image(16, 2));
+
+        let patch = encode(&new, &old, &map).unwrap();
+        assert!(patch.len() < 200);
+        let slot = apply(&patch, &old, &map);
+        assert_eq!(slot, map.pad(&new).unwrap());
+        assert_eq!(crate::decode(&patch, &map.pad(&old).unwrap()).unwrap(), new);
+
+        let unchanged = encode(&old, &old, &map).unwrap();
+        assert_eq!(apply(&unchanged, &old, &map), map.pad(&old).unwrap());
+    }
+
+    #[test]
+    fn test_window_bounds_copies() {
+        let old = image(8_000, 3);
+        let new = &old[4_000..];
+
+        // The moved code is too far ahead for a small window
+        let unbounded = encode(new, &old, &MemoryMap::new(8_000)).unwrap();
+        let map = MemoryMap {
+            window: 1_000,
+            ..MemoryMap::new(8_000)
+        };
+        let bounded = encode(new, &old, &map).unwrap();
+        assert!(unbounded.len() < 100);
+        assert!(bounded.len() > 4_000);
+        assert_eq!(apply(&bounded, &old, &map), map.pad(new).unwrap());
+
+        assert!(matches!(
+            encode(&image(9_000, 4), &old, &map),
+            Err(GDeltaError::InvalidOptions(_))
+        ));
+    }
+
+    #[test]
+    fn test_rejects_backward_copies() {
+        let old = image(4_000, 5);
+        let mut new = image(1_000, 6);
+        new.extend_from_slice(&old[..3_000]);
+
+        // The regular encoder copies the shifted code from behind
+        let delta = crate::encode(&new, &old).unwrap();
+        let mut slot = old.clone();
+        assert!(matches!(
+            apply_in_place(&delta, &mut slot, &MemoryMap::new(4_000)),
+            Err(GDeltaError::InvalidDelta(_))
+        ));
+        assert_eq!(slot, old);
+    }
+
+    #[test]
+    fn test_c_header() {
+        let map = MemoryMap::new(16);
+        let patch = encode(b"new firmware", b"old firmware", &map).unwrap();
+        let header = c_header(&patch, "app-v2", &map);
+        assert!(header.contains("#define APP_V2_SLOT_SIZE 16u"));
+        assert!(header.contains("static const uint8_t app_v2_patch[APP_V2_PATCH_SIZE] = {"));
+        assert!(header.contains(&format!("0x{:02x}", patch[0])));
+        assert!(header.contains("static int gdelta_apply_in_place("));
+    }
+}
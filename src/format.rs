@@ -0,0 +1,70 @@
+//! Low-level access to the delta wire format.
+//!
+//! This module is only available with the `unstable-format` feature. It mirrors
+//! the on-disk encoding closely, so its items are **not** covered by semver:
+//! they may change in any minor release together with the format itself.
+//!
+//! It lets advanced users emit gdelta-compatible patches from their own
+//! matchers. A delta is a varint-prefixed instruction stream followed by the
+//! literal data referenced by literal instructions:
+//!
+//! ```text
+//! [instruction length: varint][instructions...][literal data...]
+//! ```
+//!
+//! [`seed_corpus`] builds well-formed deltas with extreme field values, for
+//! seeding fuzzers and as regression inputs.
+//!
+//! # Examples
+//!
+//! ```
+//! use gdelta::format::DeltaWriter;
+//!
+//! let base = b"Hello, World!";
+//!
+//! let mut writer = DeltaWriter::new();
+//! writer.copy(0, 7);
+//! writer.literal(b"Rust!");
+//! let delta = writer.finish();
+//!
+//! assert_eq!(gdelta::decode(&delta, base).unwrap(), b"Hello, Rust!");
+//! ```
+
+pub use crate::buffer::BufferStream;
+pub use crate::corpus::seed_corpus;
+pub use crate::delta::{DeltaWriter, finalize_delta};
+pub use crate::varint::{DeltaUnit, read_delta_unit, read_varint, write_delta_unit, write_varint};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_writer_roundtrip() {
+        let base = b"The quick brown fox jumps over the lazy dog";
+
+        let mut writer = DeltaWriter::new();
+        writer.copy(0, 16);
+        writer.literal(b"cat");
+        writer.copy(19, 24);
+        let expected_len = writer.encoded_len();
+        let delta = writer.finish();
+
+        assert_eq!(delta.len(), expected_len);
+        assert_eq!(
+            crate::decode(&delta, base).unwrap(),
+            b"The quick brown cat jumps over the lazy dog"
+        );
+    }
+
+    #[test]
+    fn test_writer_skips_empty_instructions() {
+        let mut writer = DeltaWriter::new();
+        writer.copy(5, 0);
+        writer.literal(b"");
+        let delta = writer.finish();
+
+        assert_eq!(delta, vec![0]);
+        assert!(crate::decode(&delta, b"base").unwrap().is_empty());
+    }
+}
@@ -0,0 +1,355 @@
+//! Length-prefixed framing for sending deltas over sockets.
+//!
+//! A delta sent over a connection needs its end marked, and damage in
+//! transit should be caught before the delta is applied. [`FrameWriter`]
+//! cuts a byte stream into frames, each protected by its own CRC-32, and
+//! [`FrameReader`] checks and joins them again:
+//!
+//! ```text
+//! [magic: 0x80 0x00 'G' 'L']
+//! [payload length: u32 LE][CRC-32 of payload: u32 LE][payload]...
+//! [0: u32 LE][0: u32 LE]
+//! ```
+//!
+//! All integers are little-endian. The empty frame at the end marks the end
+//! of the stream, so a connection closed early is told apart from a
+//! complete delta. Frames carry at most [`MAX_FRAME_SIZE`] bytes, which
+//! bounds what a reader buffers. The reader never reads past the end
+//! marker, so further messages can follow on the same connection.
+//!
+//! # Examples
+//!
+//! ```
+//! use gdelta::framing::{FrameReader, FrameWriter};
+//! use std::io::{Read, Write};
+//!
+//! let delta = gdelta::encode(b"Hello, Rust!", b"Hello, World!")?;
+//!
+//! let mut writer = FrameWriter::new(Vec::new());
+//! writer.write_all(&delta)?;
+//! let sent = writer.finish()?;
+//!
+//! let mut received = Vec::new();
+//! FrameReader::new(&sent[..]).read_to_end(&mut received)?;
+//! assert_eq!(received, delta);
+//! # Ok::<(), gdelta::GDeltaError>(())
+//! ```
+
+use crate::checksum::crc32;
+use crate::error::{GDeltaError, Result};
+use std::io::{self, BufRead, Read, Write};
+
+/// Marker at the start of a framed stream.
+pub const MAGIC: [u8; 4] = [0x80, 0x00, b'G', b'L'];
+
+/// Largest payload a frame may carry, in bytes.
+pub const MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+/// Payload size of the frames written by [`FrameWriter::new`], in bytes.
+pub const DEFAULT_FRAME_SIZE: usize = 64 * 1024;
+
+/// Size of the length and CRC-32 preceding each payload.
+const FRAME_HEADER_SIZE: usize = 8;
+
+/// A writer that sends everything written to it as a framed stream.
+///
+/// Data is collected until a frame is full or the writer is flushed, so
+/// flushing sends a short frame with what has been written so far.
+/// [`FrameWriter::finish`] writes the end marker; a writer dropped without
+/// it leaves a stream that readers report as truncated.
+#[derive(Debug)]
+pub struct FrameWriter<W: Write> {
+    inner: W,
+    frame_size: usize,
+    pending: Vec<u8>,
+    started: bool,
+}
+
+impl<W: Write> FrameWriter<W> {
+    /// Creates a writer sending frames of [`DEFAULT_FRAME_SIZE`] bytes.
+    pub fn new(inner: W) -> Self {
+        Self::with_frame_size(inner, DEFAULT_FRAME_SIZE)
+    }
+
+    /// Creates a writer sending frames of up to `frame_size` bytes.
+    ///
+    /// The size is clamped to between 1 and [`MAX_FRAME_SIZE`] bytes.
+    pub fn with_frame_size(inner: W, frame_size: usize) -> Self {
+        let frame_size = frame_size.clamp(1, MAX_FRAME_SIZE);
+        Self {
+            inner,
+            frame_size,
+            pending: Vec::with_capacity(frame_size),
+            started: false,
+        }
+    }
+
+    /// Returns a reference to the inner writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Sends the pending data and the end marker, and returns the inner
+    /// writer.
+    ///
+    /// # Errors
+    ///
+    /// Returns `GDeltaError::Io` if writing fails.
+    pub fn finish(mut self) -> Result<W> {
+        self.send_pending()?;
+        self.send_frame(&[])?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    fn send_pending(&mut self) -> io::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let pending = std::mem::take(&mut self.pending);
+        let result = self.send_frame(&pending);
+        self.pending = pending;
+        if result.is_ok() {
+            self.pending.clear();
+        }
+        result
+    }
+
+    fn send_frame(&mut self, payload: &[u8]) -> io::Result<()> {
+        if !self.started {
+            self.inner.write_all(&MAGIC)?;
+            self.started = true;
+        }
+        let mut header = [0u8; FRAME_HEADER_SIZE];
+        header[..4].copy_from_slice(&(payload.len() as u32).to_le_bytes());
+        header[4..].copy_from_slice(&crc32(payload).to_le_bytes());
+        self.inner.write_all(&header)?;
+        self.inner.write_all(payload)
+    }
+}
+
+impl<W: Write> Write for FrameWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = buf.len().min(self.frame_size - self.pending.len());
+        self.pending.extend_from_slice(&buf[..n]);
+        if self.pending.len() == self.frame_size {
+            self.send_pending()?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send_pending()?;
+        self.inner.flush()
+    }
+}
+
+/// A reader returning the payload of a framed stream.
+///
+/// Each frame is checked against its CRC-32 before any of it is returned.
+/// Reading ends at the end marker, leaving the inner reader positioned
+/// just after it.
+///
+/// Errors are returned as `std::io::Error`s wrapping a [`GDeltaError`]:
+/// `GDeltaError::InvalidDelta` for a stream without the magic or with an
+/// oversized frame, and `GDeltaError::ChecksumMismatch` for a damaged
+/// frame. A stream that ends before its end marker gives an error of kind
+/// `UnexpectedEof`.
+#[derive(Debug)]
+pub struct FrameReader<R: Read> {
+    inner: R,
+    frame: Vec<u8>,
+    position: usize,
+    started: bool,
+    finished: bool,
+}
+
+impl<R: Read> FrameReader<R> {
+    /// Creates a reader of the framed stream in `inner`.
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            frame: Vec::new(),
+            position: 0,
+            started: false,
+            finished: false,
+        }
+    }
+
+    /// Returns whether the end marker has been read.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Returns the inner reader.
+    ///
+    /// Once the reader is finished, the inner reader is positioned just
+    /// after the end marker.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Reads frames until one has data or the end marker is reached.
+    fn next_frame(&mut self) -> Result<()> {
+        if !self.started {
+            let mut magic = [0u8; 4];
+            self.inner.read_exact(&mut magic).map_err(truncated)?;
+            if magic != MAGIC {
+                return Err(GDeltaError::InvalidDelta(
+                    "Not a framed gdelta stream".to_string(),
+                ));
+            }
+            self.started = true;
+        }
+        while self.position == self.frame.len() && !self.finished {
+            let mut header = [0u8; FRAME_HEADER_SIZE];
+            self.inner.read_exact(&mut header).map_err(truncated)?;
+            let len = u32::from_le_bytes(header[..4].try_into().expect("4 bytes")) as usize;
+            let expected = u32::from_le_bytes(header[4..].try_into().expect("4 bytes"));
+            if len > MAX_FRAME_SIZE {
+                return Err(GDeltaError::InvalidDelta(format!(
+                    "Frame of {len} bytes exceeds the maximum of {MAX_FRAME_SIZE}"
+                )));
+            }
+
+            self.frame.resize(len, 0);
+            self.position = 0;
+            if let Err(error) = self.inner.read_exact(&mut self.frame) {
+                self.frame.clear();
+                return Err(truncated(error));
+            }
+            let actual = crc32(&self.frame);
+            if expected != actual {
+                self.frame.clear();
+                return Err(GDeltaError::ChecksumMismatch { expected, actual });
+            }
+            self.finished = len == 0;
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for FrameReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: Read> BufRead for FrameReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.next_frame().map_err(|error| match error {
+            GDeltaError::UnexpectedEndOfData => io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Framed stream ended before its end marker",
+            ),
+            error => io::Error::other(error),
+        })?;
+        Ok(&self.frame[self.position..])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.position = (self.position + amount).min(self.frame.len());
+    }
+}
+
+/// Maps a stream that ends early to `GDeltaError::UnexpectedEndOfData`.
+fn truncated(error: io::Error) -> GDeltaError {
+    if error.kind() == io::ErrorKind::UnexpectedEof {
+        GDeltaError::UnexpectedEndOfData
+    } else {
+        error.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn framed(data: &[u8], frame_size: usize) -> Vec<u8> {
+        let mut writer = FrameWriter::with_frame_size(Vec::new(), frame_size);
+        writer.write_all(data).unwrap();
+        writer.finish().unwrap()
+    }
+
+    fn unframed(stream: &[u8]) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        FrameReader::new(stream).read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    #[test]
+    fn test_frame_roundtrip() {
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        for frame_size in [1, 100, 4096, DEFAULT_FRAME_SIZE] {
+            let stream = framed(&data, frame_size);
+            let frames = data.len().div_ceil(frame_size) + 1;
+            assert_eq!(stream.len(), 4 + data.len() + frames * FRAME_HEADER_SIZE);
+            assert_eq!(unframed(&stream).unwrap(), data);
+        }
+        assert!(unframed(&framed(b"", 16)).unwrap().is_empty());
+
+        // Flushing sends what was written so far as a short frame
+        let mut writer = FrameWriter::with_frame_size(Vec::new(), 1000);
+        writer.write_all(b"first").unwrap();
+        writer.flush().unwrap();
+        assert_eq!(writer.get_ref().len(), 4 + FRAME_HEADER_SIZE + 5);
+        writer.write_all(b" second").unwrap();
+        assert_eq!(
+            unframed(&writer.finish().unwrap()).unwrap(),
+            b"first second"
+        );
+    }
+
+    #[test]
+    fn test_reader_stops_at_end_marker() {
+        let mut connection = framed(b"first delta", 4);
+        connection.extend_from_slice(&framed(b"second delta", 4));
+
+        let mut reader = FrameReader::new(&connection[..]);
+        let mut first = Vec::new();
+        reader.read_to_end(&mut first).unwrap();
+        assert!(reader.is_finished());
+        let mut second = Vec::new();
+        FrameReader::new(reader.into_inner())
+            .read_to_end(&mut second)
+            .unwrap();
+        assert_eq!(first, b"first delta");
+        assert_eq!(second, b"second delta");
+    }
+
+    #[test]
+    fn test_reader_detects_damage() {
+        let stream = framed(b"some delta bytes", 8);
+
+        let mut damaged = stream.clone();
+        damaged[4 + FRAME_HEADER_SIZE + 2] ^= 0x01;
+        let error = unframed(&damaged).unwrap_err();
+        let inner = error.get_ref().unwrap().downcast_ref::<GDeltaError>();
+        assert!(matches!(inner, Some(GDeltaError::ChecksumMismatch { .. })));
+
+        // Cut at a frame boundary, before the end marker
+        let cut = &stream[..stream.len() - FRAME_HEADER_SIZE];
+        assert_eq!(
+            unframed(cut).unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+
+        let mut oversized = MAGIC.to_vec();
+        oversized.extend_from_slice(&(MAX_FRAME_SIZE as u32 + 1).to_le_bytes());
+        oversized.extend_from_slice(&[0; 4]);
+        assert!(unframed(&oversized).is_err());
+        assert!(unframed(b"GDFR\0\0\0\0\0\0\0\0").is_err());
+    }
+
+    #[test]
+    fn test_not_taken_for_other_wrappers() {
+        let stream = framed(b"some delta bytes", 8);
+        assert_eq!(crate::compression::wrapped_id(&stream), None);
+        #[cfg(feature = "fec")]
+        assert!(!crate::fec::is_protected(&stream));
+    }
+}
@@ -2,12 +2,41 @@
 //!
 //! The GEAR hash uses precomputed random values to create a rolling
 //! fingerprint of data windows, enabling efficient similarity detection.
+//!
+//! This is the exact hash the encoder uses to index the base data, exposed so
+//! that chunkers and similarity tools can produce fingerprints compatible with
+//! it. Each step shifts the fingerprint left by [`SHIFT_BITS`] and adds the
+//! [`GEAR_MX`] entry of the incoming byte, so bytes older than [`WORD_SIZE`]
+//! positions fall out of the 64-bit state on their own.
+//!
+//! ```
+//! use gdelta::gear::{WORD_SIZE, compute_fingerprint, roll_fingerprint};
+//!
+//! let data = b"rolling hashes make sliding windows cheap";
+//!
+//! // Rolling one byte forward equals recomputing the next window
+//! let fp = compute_fingerprint(data, 0);
+//! let rolled = roll_fingerprint(fp, data[WORD_SIZE]);
+//! assert_eq!(rolled, compute_fingerprint(data, 1));
+//! ```
+
+use std::iter;
+use std::sync::Arc;
 
 /// Word size for rolling hash window.
 pub const WORD_SIZE: usize = 8;
 
+/// Longest match anchor accepted by [`crate::EncodeOptions::word_size`].
+pub const MAX_WORD_SIZE: usize = 4096;
+
+/// Number of bits the fingerprint is shifted per input byte.
+///
+/// Chosen so that exactly [`WORD_SIZE`] bytes contribute to a fingerprint.
+#[allow(clippy::cast_possible_truncation)]
+pub const SHIFT_BITS: u32 = ((64 / WORD_SIZE) + (64 % WORD_SIZE != 0) as usize) as u32;
+
 /// Base sample rate for hash table insertion.
-pub const BASE_SAMPLE_RATE: usize = 3;
+pub(crate) const BASE_SAMPLE_RATE: usize = 3;
 
 /// GEAR hash matrix mapping 256 ASCII characters to random 64-bit values.
 pub const GEAR_MX: [u64; 256] = [
@@ -271,7 +300,7 @@ pub const GEAR_MX: [u64; 256] = [
 
 /// GEAR hash matrix left-shifted by 1 bit (for optimization).
 #[allow(dead_code)]
-pub const GEAR_MX_L: [u64; 256] = [
+pub(crate) const GEAR_MX_L: [u64; 256] = [
     0x6111_a753_d081_eab2,
     0xaca5_8fee_73da_41ac,
     0x8b65_12d3_1312_e556,
@@ -530,49 +559,138 @@ pub const GEAR_MX_L: [u64; 256] = [
     0xc78f_520d_83ba_30f6,
 ];
 
+/// Size of a huge page, to which large hash tables can be aligned.
+pub(crate) const HUGE_PAGE_SIZE: usize = 2 * 1024 * 1024;
+
+/// A hash table built by [`build_hash_table`].
+///
+/// The entries are shared between clones. An aligned table starts at an
+/// offset into its allocation.
+#[derive(Debug, Clone)]
+pub(crate) struct HashTable {
+    entries: Arc<[u32]>,
+    start: usize,
+    len: usize,
+    /// The [`tag`] of the fingerprint stored with each entry, if enabled.
+    tags: Option<Arc<[u16]>>,
+    /// Number of base positions inserted, including overwritten ones.
+    inserts: usize,
+}
+
+impl HashTable {
+    /// Returns the entry at `index`.
+    #[inline]
+    pub(crate) fn get(&self, index: usize) -> Option<u32> {
+        if index < self.len {
+            Some(self.entries[self.start + index])
+        } else {
+            None
+        }
+    }
+
+    /// Returns the tag stored with the entry at `index`, if tags are enabled.
+    #[inline]
+    pub(crate) fn tag(&self, index: usize) -> Option<u16> {
+        self.tags.as_ref()?.get(index).copied()
+    }
+
+    /// Returns the number of entries.
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns the number of entries holding a base position.
+    pub(crate) fn occupied(&self) -> usize {
+        self.entries[self.start..self.start + self.len]
+            .iter()
+            .filter(|&&entry| entry != 0)
+            .count()
+    }
+
+    /// Returns the number of base positions inserted while building.
+    pub(crate) fn inserts(&self) -> usize {
+        self.inserts
+    }
+
+    /// Returns the address of the first entry.
+    #[cfg(test)]
+    pub(crate) fn address(&self) -> usize {
+        self.entries[self.start..].as_ptr() as usize
+    }
+}
+
 /// Builds a hash table for the base data using GEAR rolling hash.
 ///
 /// The hash table maps fingerprints to positions in the base data,
-/// enabling fast lookup of potential matches during encoding.
+/// enabling fast lookup of potential matches during encoding. Every
+/// `sample_rate`-th position is inserted. The table is built in place in
+/// its shared allocation, so sharing it costs no copy.
+///
+/// With `huge_pages`, a table of at least [`HUGE_PAGE_SIZE`] bytes is placed
+/// on a huge page boundary inside an allocation that is that much larger.
+/// With `tags`, the [`tag`] of each inserted fingerprint is kept alongside.
 #[allow(clippy::cast_possible_truncation)]
-#[allow(clippy::cast_lossless)]
-pub fn build_hash_table(base_data: &[u8], start: usize, end: usize, hash_bits: u32) -> Vec<u32> {
+pub(crate) fn build_hash_table(
+    base_data: &[u8],
+    start: usize,
+    end: usize,
+    hash_bits: u32,
+    sample_rate: usize,
+    huge_pages: bool,
+    tags: bool,
+) -> HashTable {
     let hash_size = 1usize << hash_bits;
-    let mut hash_table = vec![0u32; hash_size];
+    let padding = if huge_pages && hash_size * size_of::<u32>() >= HUGE_PAGE_SIZE {
+        HUGE_PAGE_SIZE / size_of::<u32>()
+    } else {
+        0
+    };
+    let mut entries: Arc<[u32]> = iter::repeat_n(0, hash_size + padding).collect();
+    let table_start = if padding > 0 {
+        entries.as_ptr().align_offset(HUGE_PAGE_SIZE).min(padding)
+    } else {
+        0
+    };
+    let hash_table = &mut Arc::get_mut(&mut entries).expect("a new table is not shared")
+        [table_start..table_start + hash_size];
+    let mut tag_table: Option<Arc<[u16]>> = tags.then(|| iter::repeat_n(0, hash_size).collect());
+    let table = |entries, tags, inserts| HashTable {
+        entries,
+        start: table_start,
+        len: hash_size,
+        tags,
+        inserts,
+    };
 
     if end - start < WORD_SIZE {
-        return hash_table;
+        return table(entries, tag_table, 0);
     }
 
-    let shift_bits = (64 / WORD_SIZE) + (64 % WORD_SIZE != 0) as usize;
     let index_shift = 64 - hash_bits;
+    let mut tag_entries = tag_table
+        .as_mut()
+        .map(|tags| Arc::get_mut(tags).expect("a new table is not shared"));
 
     // Initialize fingerprint with first WORD_SIZE bytes
-    let mut fingerprint = 0u64;
-    for i in 0..WORD_SIZE {
-        if start + i < end {
-            // Use wrapping operations - overflow is intentional
-            fingerprint = fingerprint
-                .wrapping_shl(shift_bits as u32)
-                .wrapping_add(GEAR_MX[base_data[start + i] as usize]);
-        }
-    }
+    let mut fingerprint = compute_fingerprint(&base_data[..end], start);
 
     // Build hash table with sampling
     let mut pos = start;
     let num_chunks = end - start - WORD_SIZE;
+    let mut inserts = 0;
 
     while pos < start + num_chunks {
         let index = (fingerprint >> index_shift) as usize;
         hash_table[index] = pos as u32;
+        inserts += 1;
+        if let Some(tags) = tag_entries.as_mut() {
+            tags[index] = tag(fingerprint, index_shift);
+        }
 
-        // Advance by BASE_SAMPLE_RATE positions
-        for _ in 0..BASE_SAMPLE_RATE {
+        // Advance by sample_rate positions
+        for _ in 0..sample_rate {
             if pos + WORD_SIZE < end {
-                // Use wrapping operations - overflow is intentional
-                fingerprint = fingerprint
-                    .wrapping_shl(shift_bits as u32)
-                    .wrapping_add(GEAR_MX[base_data[pos + WORD_SIZE] as usize]);
+                fingerprint = roll_fingerprint(fingerprint, base_data[pos + WORD_SIZE]);
                 pos += 1;
             } else {
                 break;
@@ -580,37 +698,130 @@ pub fn build_hash_table(base_data: &[u8], start: usize, end: usize, hash_bits: u
         }
     }
 
-    hash_table
+    table(entries, tag_table, inserts)
 }
 
-/// Computes a GEAR rolling hash fingerprint for a data window.
+/// Returns the 16 fingerprint bits just below the ones that select the
+/// hash table entry, given the shift that selects it.
+///
+/// Windows whose tags differ cannot be equal, so comparing the tag stored
+/// with an entry rules out most false candidates without touching the base.
 #[inline]
 #[allow(clippy::cast_possible_truncation)]
-#[allow(clippy::cast_lossless)]
+pub(crate) fn tag(fingerprint: u64, index_shift: u32) -> u16 {
+    (fingerprint >> index_shift.saturating_sub(16)) as u16
+}
+
+/// Computes a GEAR rolling hash fingerprint for a data window.
+///
+/// The window covers `data[start..start + WORD_SIZE]`. If fewer than
+/// [`WORD_SIZE`] bytes remain, only the available bytes are hashed.
+#[inline]
 pub fn compute_fingerprint(data: &[u8], start: usize) -> u64 {
-    let shift_bits = (64 / WORD_SIZE) + (64 % WORD_SIZE != 0) as usize;
-    let mut fingerprint = 0u64;
-
-    for i in 0..WORD_SIZE {
-        if start + i < data.len() {
-            // Use wrapping operations - overflow is intentional in hash computation
-            fingerprint = fingerprint
-                .wrapping_shl(shift_bits as u32)
-                .wrapping_add(GEAR_MX[data[start + i] as usize]);
-        }
-    }
+    let end = data.len().min(start.saturating_add(WORD_SIZE));
+    let window = data.get(start..end).unwrap_or_default();
 
-    fingerprint
+    window.iter().fold(0u64, |fingerprint, &byte| {
+        roll_fingerprint(fingerprint, byte)
+    })
 }
 
 /// Updates a rolling fingerprint by removing one byte and adding another.
+///
+/// The byte leaving the window does not need to be passed in: its
+/// contribution is shifted out of the 64-bit state after [`WORD_SIZE`] steps.
 #[inline]
-#[allow(clippy::cast_possible_truncation)]
-#[allow(clippy::cast_lossless)]
 pub fn roll_fingerprint(fingerprint: u64, new_byte: u8) -> u64 {
-    let shift_bits = (64 / WORD_SIZE) + (64 % WORD_SIZE != 0) as usize;
     // Use wrapping operations - overflow is intentional in hash computation
     fingerprint
-        .wrapping_shl(shift_bits as u32)
+        .wrapping_shl(SHIFT_BITS)
         .wrapping_add(GEAR_MX[new_byte as usize])
 }
+
+/// Returns an iterator over the fingerprint of every full window in `data`.
+///
+/// Yields `(position, fingerprint)` pairs where `fingerprint` covers
+/// `data[position..position + WORD_SIZE]`.
+///
+/// # Examples
+///
+/// ```
+/// use gdelta::gear::{compute_fingerprint, fingerprints};
+///
+/// let data = b"0123456789abcdef";
+/// for (pos, fp) in fingerprints(data) {
+///     assert_eq!(fp, compute_fingerprint(data, pos));
+/// }
+/// assert_eq!(fingerprints(data).count(), data.len() - 7);
+/// ```
+pub fn fingerprints(data: &[u8]) -> Fingerprints<'_> {
+    Fingerprints {
+        data,
+        pos: 0,
+        fingerprint: compute_fingerprint(data, 0),
+    }
+}
+
+/// Iterator returned by [`fingerprints`].
+#[derive(Debug, Clone)]
+pub struct Fingerprints<'a> {
+    data: &'a [u8],
+    pos: usize,
+    fingerprint: u64,
+}
+
+impl Iterator for Fingerprints<'_> {
+    type Item = (usize, u64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos + WORD_SIZE > self.data.len() {
+            return None;
+        }
+
+        let item = (self.pos, self.fingerprint);
+        self.pos += 1;
+        if self.pos + WORD_SIZE <= self.data.len() {
+            self.fingerprint =
+                roll_fingerprint(self.fingerprint, self.data[self.pos + WORD_SIZE - 1]);
+        }
+        Some(item)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = (self.data.len() + 1).saturating_sub(self.pos + WORD_SIZE);
+        (remaining, Some(remaining))
+    }
+}
+
+impl ExactSizeIterator for Fingerprints<'_> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roll_matches_compute() {
+        let data: Vec<u8> = (0..=255).cycle().take(1000).collect();
+        let mut fingerprint = compute_fingerprint(&data, 0);
+
+        for pos in 1..=data.len() - WORD_SIZE {
+            fingerprint = roll_fingerprint(fingerprint, data[pos + WORD_SIZE - 1]);
+            assert_eq!(fingerprint, compute_fingerprint(&data, pos));
+        }
+    }
+
+    #[test]
+    fn test_fingerprint_ignores_bytes_outside_window() {
+        let a = b"XXXXXXXXsame-win";
+        let b = b"YYYYYYYYsame-win";
+        assert_eq!(compute_fingerprint(a, 8), compute_fingerprint(b, 8));
+        assert_ne!(compute_fingerprint(a, 0), compute_fingerprint(b, 0));
+    }
+
+    #[test]
+    fn test_fingerprints_short_input() {
+        assert_eq!(fingerprints(b"").count(), 0);
+        assert_eq!(fingerprints(b"1234567").count(), 0);
+        assert_eq!(fingerprints(b"12345678").len(), 1);
+    }
+}
@@ -0,0 +1,547 @@
+//! Optional header and trailer framing the delta payload.
+//!
+//! Plain [`crate::encode`] output starts directly with the instruction length
+//! varint. When an option needs extra metadata, the payload is wrapped:
+//!
+//! ```text
+//! [magic: 4][version: u8][flags: u8]
+//! [target size: varint, if FLAG_TARGET_SIZE]
+//! [target crc32: u32 LE, if FLAG_TARGET_CHECKSUM]
+//! [base crc32: u32 LE, if FLAG_BASE_CHECKSUM]
+//! [metadata length: varint, if FLAG_METADATA]
+//! [metadata entries...]   ([tag: varint][length: varint][value] each)
+//! [payload...]            (segments if FLAG_SEGMENTED)
+//!                          (copies may address the target if FLAG_TARGET_COPIES)
+//!                          (empty if FLAG_IDENTITY)
+//! [crc32: u32 LE, if FLAG_CHECKSUM]
+//! ```
+//!
+//! The magic is an eight-byte non-canonical varint, which the encoder never
+//! produces for the instruction length, so headered and raw deltas can be
+//! told apart unambiguously. Releases up to 0.2.1 read it as an instruction
+//! length of about 2^49 bytes (just under 2 GiB on 32-bit targets) and
+//! reject every delta with a header as longer than its data instead of
+//! misreading it. Its two low groups spell `G` and `D`.
+
+use crate::buffer::BufferStream;
+use crate::checksum::crc32;
+use crate::error::{GDeltaError, Result};
+use crate::options::EncodeOptions;
+use crate::varint::{read_varint, write_varint};
+
+/// Marker identifying a delta with a header.
+pub const MAGIC: [u8; 8] = [0xC7, 0xC4, 0xFF, 0xFF, 0xF7, 0xFF, 0xFF, 0x00];
+
+/// Current header format version.
+pub const VERSION: u8 = 1;
+
+/// Oldest format version this crate still decodes.
+///
+/// Version 0 is the raw format without a header, written by every release
+/// up to 0.2.1 and still by plain [`crate::encode`].
+pub const MIN_VERSION: u8 = 0;
+
+/// Returns true if deltas of format `version` can be decoded.
+pub fn is_supported(version: u8) -> bool {
+    (MIN_VERSION..=VERSION).contains(&version)
+}
+
+/// The delta ends with a CRC-32 of all preceding bytes.
+pub const FLAG_CHECKSUM: u8 = 0x01;
+
+/// The header records the size of the reconstructed target.
+pub const FLAG_TARGET_SIZE: u8 = 0x02;
+
+/// The payload is a sequence of length-prefixed, independent segments.
+pub const FLAG_SEGMENTED: u8 = 0x04;
+
+/// Copy offsets at or past the end of the base address the already
+/// reconstructed target, starting at offset `base.len()`.
+///
+/// Such a copy must start within the target produced so far but may extend
+/// past its end, reading back the bytes it writes as it goes (LZ77-style
+/// overlapping copies), so a copy one byte back of length `n` repeats the
+/// last byte `n` times.
+pub const FLAG_TARGET_COPIES: u8 = 0x08;
+
+/// The target is identical to the base and the payload is empty.
+pub const FLAG_IDENTITY: u8 = 0x10;
+
+/// The header records a CRC-32 of the reconstructed target.
+pub const FLAG_TARGET_CHECKSUM: u8 = 0x20;
+
+/// The header records a CRC-32 of the base the delta applies to.
+pub const FLAG_BASE_CHECKSUM: u8 = 0x40;
+
+/// The header carries application metadata as tag-length-value entries.
+///
+/// This is the last flag bit, so every flags byte is valid in version 1;
+/// later header fields need a new version or a metadata entry.
+pub const FLAG_METADATA: u8 = 0x80;
+
+/// Size of the checksum trailer in bytes.
+pub(crate) const CHECKSUM_SIZE: usize = 4;
+
+/// Longest header before the metadata: magic, version, flags, a ten-byte
+/// target size varint, both checksums and the metadata length varint.
+const MAX_FIXED_SIZE: usize = MAGIC.len() + 2 + 10 + 4 + 4 + 10;
+
+/// Metadata carried in a delta header.
+///
+/// Returned by [`DeltaHeader::parse`], which reads only the header bytes,
+/// so indices over many deltas can be built without touching their
+/// instructions. Raw deltas without a header report version 0, no flags
+/// and no recorded values.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DeltaHeader<'a> {
+    /// Format version the delta was written with.
+    pub version: u8,
+    /// Feature flags; see the accessor methods for their meaning.
+    pub flags: u8,
+    /// Size of the reconstructed target, if recorded.
+    pub target_size: Option<u64>,
+    /// CRC-32 of the reconstructed target, if recorded.
+    pub target_checksum: Option<u32>,
+    /// CRC-32 of the base, if recorded.
+    pub base_checksum: Option<u32>,
+    /// Encoded metadata entries, validated when parsed.
+    pub(crate) metadata: &'a [u8],
+}
+
+impl<'a> DeltaHeader<'a> {
+    /// Parses the header of `delta` without reading its payload or
+    /// verifying its checksum.
+    ///
+    /// # Errors
+    ///
+    /// Returns `GDeltaError::InvalidDelta` for an unsupported version or
+    /// malformed metadata, and
+    /// `GDeltaError::UnexpectedEndOfData` if the header is truncated.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use gdelta::{DeltaHeader, EncodeOptions, encode, encode_with_options};
+    ///
+    /// let base = b"Hello, World!";
+    /// let new = b"Hello, Rust!";
+    ///
+    /// let options = EncodeOptions::new()
+    ///     .store_target_size(true)
+    ///     .metadata(1, b"greeting.txt");
+    /// let delta = encode_with_options(new, base, &options).unwrap();
+    ///
+    /// let header = DeltaHeader::parse(&delta).unwrap();
+    /// assert_eq!(header.version, gdelta::FORMAT_VERSION);
+    /// assert_eq!(header.target_size, Some(new.len() as u64));
+    /// assert_eq!(header.metadata().collect::<Vec<_>>(), [(1, &b"greeting.txt"[..])]);
+    ///
+    /// let raw = encode(new, base).unwrap();
+    /// assert_eq!(DeltaHeader::parse(&raw).unwrap().version, 0);
+    /// ```
+    pub fn parse(delta: &'a [u8]) -> Result<Self> {
+        Ok(parse_header(delta)?.0.unwrap_or_default())
+    }
+
+    /// Returns the header required by `options` for a delta from `base` to
+    /// `target`, or `None` if the raw format suffices.
+    pub(crate) fn for_options(
+        options: &'a EncodeOptions,
+        target: &[u8],
+        base: &[u8],
+    ) -> Option<Self> {
+        let mut header = Self {
+            version: VERSION,
+            flags: 0,
+            target_size: None,
+            target_checksum: None,
+            base_checksum: None,
+            metadata: &[],
+        };
+        if options.checksum {
+            header.flags |= FLAG_CHECKSUM;
+        }
+        // Overlapping target copies let a few bytes expand to any size, so
+        // decoders only accept them with the target size to check against
+        if options.store_target_size || options.target_matches {
+            header.flags |= FLAG_TARGET_SIZE;
+            header.target_size = Some(target.len() as u64);
+        }
+        if options.target_checksum {
+            header.flags |= FLAG_TARGET_CHECKSUM;
+            header.target_checksum = Some(crc32(target));
+        }
+        if options.base_checksum {
+            header.flags |= FLAG_BASE_CHECKSUM;
+            header.base_checksum = Some(crc32(base));
+        }
+        if !options.metadata.is_empty() {
+            header.flags |= FLAG_METADATA;
+            header.metadata = &options.metadata;
+        }
+        if options.rsyncable || options.parallel {
+            header.flags |= FLAG_SEGMENTED;
+        }
+        if options.target_matches {
+            header.flags |= FLAG_TARGET_COPIES;
+        }
+        (header.flags != 0).then_some(header)
+    }
+
+    /// Returns the header of the identity delta for `target`, keeping the
+    /// checksum, target size, target checksum, base checksum and metadata
+    /// options.
+    pub(crate) fn identity(options: &'a EncodeOptions, target: &[u8]) -> Self {
+        let mut header = Self::for_options(options, target, target).unwrap_or_default();
+        header.version = VERSION;
+        header.flags = (header.flags
+            & (FLAG_CHECKSUM
+                | FLAG_TARGET_SIZE
+                | FLAG_TARGET_CHECKSUM
+                | FLAG_BASE_CHECKSUM
+                | FLAG_METADATA))
+            | FLAG_IDENTITY;
+        header
+    }
+
+    /// Returns true if the delta carries a checksum trailer.
+    pub fn has_checksum(&self) -> bool {
+        self.flags & FLAG_CHECKSUM != 0
+    }
+
+    /// Returns true if the payload is split into segments.
+    pub fn is_segmented(&self) -> bool {
+        self.flags & FLAG_SEGMENTED != 0
+    }
+
+    /// Returns true if copies may read from the reconstructed target.
+    pub fn has_target_copies(&self) -> bool {
+        self.flags & FLAG_TARGET_COPIES != 0
+    }
+
+    /// Returns true if the target is the unchanged base.
+    pub fn is_identity(&self) -> bool {
+        self.flags & FLAG_IDENTITY != 0
+    }
+
+    /// Iterates over the metadata entries as tag and value, in the order
+    /// they were added with [`EncodeOptions::metadata`].
+    pub fn metadata(&self) -> impl Iterator<Item = (u64, &'a [u8])> + use<'a> {
+        let metadata = self.metadata;
+        let mut stream = BufferStream::from_slice(metadata);
+        std::iter::from_fn(move || read_entry(&mut stream, metadata).ok().flatten())
+    }
+
+    /// Returns the encoded header bytes that precede the payload.
+    pub(crate) fn to_bytes(self) -> Vec<u8> {
+        let mut stream = BufferStream::with_capacity(MAGIC.len() + 22 + self.metadata.len());
+        stream.write_bytes(&MAGIC);
+        stream.write_u8(self.version);
+        stream.write_u8(self.flags);
+        if let Some(target_size) = self.target_size {
+            write_varint(&mut stream, target_size);
+        }
+        if let Some(target_checksum) = self.target_checksum {
+            stream.write_bytes(&target_checksum.to_le_bytes());
+        }
+        if let Some(base_checksum) = self.base_checksum {
+            stream.write_bytes(&base_checksum.to_le_bytes());
+        }
+        if self.flags & FLAG_METADATA != 0 {
+            write_varint(&mut stream, self.metadata.len() as u64);
+            stream.write_bytes(self.metadata);
+        }
+        stream.into_vec()
+    }
+
+    /// Wraps a raw delta payload with this header and any trailer.
+    pub(crate) fn wrap(&self, payload: &[u8]) -> Vec<u8> {
+        let mut delta = self.to_bytes();
+        delta.reserve(payload.len() + CHECKSUM_SIZE);
+        delta.extend_from_slice(payload);
+        if self.has_checksum() {
+            let crc = crc32(&delta);
+            delta.extend_from_slice(&crc.to_le_bytes());
+        }
+        delta
+    }
+}
+
+/// A delta separated into its header, payload and trailer.
+#[derive(Debug, Clone, Copy)]
+pub struct Framed<'a> {
+    /// Parsed header, or `None` for a raw delta.
+    pub header: Option<DeltaHeader<'a>>,
+    /// Raw delta payload (instruction length, instructions, literal data).
+    pub payload: &'a [u8],
+    /// Offset of the payload within the full delta.
+    pub payload_offset: usize,
+    /// Stored and computed checksums, if the delta has a trailer.
+    pub checksum: Option<(u32, u32)>,
+}
+
+impl Framed<'_> {
+    /// Returns an error if the stored checksum does not match the contents.
+    pub fn verify(&self) -> Result<()> {
+        match self.checksum {
+            Some((expected, actual)) if expected != actual => {
+                Err(GDeltaError::ChecksumMismatch { expected, actual })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Parses the framing of a delta without verifying its checksum.
+pub fn parse(delta: &[u8]) -> Result<Framed<'_>> {
+    let (Some(header), start) = parse_header(delta)? else {
+        return Ok(Framed {
+            header: None,
+            payload: delta,
+            payload_offset: 0,
+            checksum: None,
+        });
+    };
+
+    let mut end = delta.len();
+    let mut checksum = None;
+    if header.has_checksum() {
+        end = end
+            .checked_sub(CHECKSUM_SIZE)
+            .filter(|&end| end >= start)
+            .ok_or(GDeltaError::UnexpectedEndOfData)?;
+        let (body, trailer) = delta.split_at(end);
+        let expected = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+        checksum = Some((expected, crc32(body)));
+    }
+    if header.is_identity() && start != end {
+        return Err(GDeltaError::InvalidDelta(
+            "Identity delta has a payload".to_string(),
+        ));
+    }
+
+    Ok(Framed {
+        header: Some(header),
+        payload: &delta[start..end],
+        payload_offset: start,
+        checksum,
+    })
+}
+
+/// Parses only the header of a delta, returning it with the offset of the
+/// payload; raw deltas have no header and start their payload at 0.
+#[allow(clippy::cast_possible_truncation)]
+pub(crate) fn parse_header(delta: &[u8]) -> Result<(Option<DeltaHeader<'_>>, usize)> {
+    if !delta.starts_with(&MAGIC) {
+        return Ok((None, 0));
+    }
+
+    let mut stream = BufferStream::from_slice(&delta[..delta.len().min(MAX_FIXED_SIZE)]);
+    stream.set_position(MAGIC.len());
+
+    let version = stream.read_u8()?;
+    // A header is only written from version 1 on
+    if version == 0 || !is_supported(version) {
+        return Err(GDeltaError::InvalidDelta(format!(
+            "Unsupported format version {version}"
+        )));
+    }
+    let flags = stream.read_u8()?;
+
+    let mut header = DeltaHeader {
+        version,
+        flags,
+        target_size: None,
+        target_checksum: None,
+        base_checksum: None,
+        metadata: &[],
+    };
+    if flags & FLAG_TARGET_SIZE != 0 {
+        header.target_size = Some(read_varint(&mut stream)?);
+    }
+    if flags & FLAG_TARGET_CHECKSUM != 0 {
+        header.target_checksum = Some(read_u32(&mut stream)?);
+    }
+    if flags & FLAG_BASE_CHECKSUM != 0 {
+        header.base_checksum = Some(read_u32(&mut stream)?);
+    }
+    let mut end = stream.position();
+    if flags & FLAG_METADATA != 0 {
+        let len = read_varint(&mut stream)?;
+        let start = stream.position();
+        if len > (delta.len() - start) as u64 {
+            return Err(GDeltaError::UnexpectedEndOfData);
+        }
+        let metadata = &delta[start..start + len as usize];
+        let mut entries = BufferStream::from_slice(metadata);
+        while read_entry(&mut entries, metadata)?.is_some() {}
+        header.metadata = metadata;
+        end = start + metadata.len();
+    }
+    Ok((Some(header), end))
+}
+
+/// Reads the metadata entry at the position of `stream`, which reads
+/// `metadata`, or returns `None` at its end.
+#[allow(clippy::cast_possible_truncation)]
+fn read_entry<'a>(
+    stream: &mut BufferStream,
+    metadata: &'a [u8],
+) -> Result<Option<(u64, &'a [u8])>> {
+    if stream.remaining() == 0 {
+        return Ok(None);
+    }
+    let tag = read_varint(stream)?;
+    let len = read_varint(stream)?;
+    if len > stream.remaining() as u64 {
+        return Err(GDeltaError::InvalidDelta(format!(
+            "Metadata entry {tag} of {len} bytes exceeds the header"
+        )));
+    }
+    let start = stream.position();
+    stream.set_position(start + len as usize);
+    Ok(Some((tag, &metadata[start..start + len as usize])))
+}
+
+fn read_u32(stream: &mut BufferStream) -> Result<u32> {
+    let bytes = stream.read_bytes(4)?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Splits a delta into its header (if any) and the raw payload.
+///
+/// Validates the version, flags and checksum trailer, so corruption is
+/// reported before any instruction is decoded.
+pub fn split(delta: &[u8]) -> Result<(Option<DeltaHeader<'_>>, &[u8])> {
+    let framed = parse(delta)?;
+    framed.verify()?;
+    Ok((framed.header, framed.payload))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_raw_delta_has_no_header() {
+        let (header, payload) = split(&[3, 1, 2, 3]).unwrap();
+        assert!(header.is_none());
+        assert_eq!(payload, &[3, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_checksum_roundtrip() {
+        let options = EncodeOptions::new().checksum(true);
+        let header = DeltaHeader::for_options(&options, &[], &[]).unwrap();
+        let delta = header.wrap(&[0]);
+
+        let (parsed, payload) = split(&delta).unwrap();
+        assert_eq!(parsed, Some(header));
+        assert_eq!(payload, &[0]);
+    }
+
+    #[test]
+    fn test_target_size_roundtrip() {
+        let options = EncodeOptions::new().store_target_size(true).checksum(true);
+        let header = DeltaHeader::for_options(&options, &[0; 300], &[]).unwrap();
+        let delta = header.wrap(&[0]);
+
+        let (parsed, payload) = split(&delta).unwrap();
+        assert_eq!(parsed.unwrap().target_size, Some(300));
+        assert_eq!(payload, &[0]);
+    }
+
+    #[test]
+    fn test_target_checksum_roundtrip() {
+        let options = EncodeOptions::new()
+            .store_target_size(true)
+            .target_checksum(true)
+            .base_checksum(true);
+        let header = DeltaHeader::for_options(&options, b"target", b"base").unwrap();
+        assert_eq!(header.target_checksum, Some(crc32(b"target")));
+        assert_eq!(header.base_checksum, Some(crc32(b"base")));
+        let delta = header.wrap(&[0]);
+
+        let (parsed, payload) = split(&delta).unwrap();
+        assert_eq!(parsed, Some(header));
+        assert_eq!(payload, &[0]);
+
+        let identity = DeltaHeader::identity(&options, b"target");
+        assert_eq!(
+            identity.flags,
+            FLAG_IDENTITY | FLAG_TARGET_SIZE | FLAG_TARGET_CHECKSUM | FLAG_BASE_CHECKSUM
+        );
+        assert_eq!(identity.base_checksum, identity.target_checksum);
+        assert!(matches!(
+            split(&delta[..MAGIC.len() + 4]),
+            Err(GDeltaError::UnexpectedEndOfData)
+        ));
+    }
+
+    #[test]
+    fn test_checksum_detects_corruption() {
+        let options = EncodeOptions::new().checksum(true);
+        let header = DeltaHeader::for_options(&options, &[0], &[]).unwrap();
+        let mut delta = header.wrap(&[1, 0x01, b'x']);
+        delta[MAGIC.len() + 3] ^= 0x20;
+
+        assert!(matches!(
+            split(&delta),
+            Err(GDeltaError::ChecksumMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_identity_has_no_payload() {
+        let options = EncodeOptions::new().rsyncable(true).checksum(true);
+        let header = DeltaHeader::identity(&options, &[0; 10]);
+        assert_eq!(header.flags, FLAG_IDENTITY | FLAG_CHECKSUM);
+
+        let delta = header.wrap(&[]);
+        assert!(split(&delta).unwrap().0.unwrap().is_identity());
+
+        let delta = DeltaHeader::identity(&EncodeOptions::new(), &[0; 10]).wrap(&[0]);
+        assert!(matches!(split(&delta), Err(GDeltaError::InvalidDelta(_))));
+    }
+
+    #[test]
+    fn test_rejects_unknown_version_and_malformed_metadata() {
+        let mut delta = MAGIC.to_vec();
+        delta.extend_from_slice(&[VERSION + 1, 0, 0]);
+        assert!(matches!(split(&delta), Err(GDeltaError::InvalidDelta(_))));
+
+        // An entry of 9 bytes in 2 bytes of metadata, then metadata of 10
+        // bytes in a 3 byte header
+        delta[MAGIC.len()] = VERSION;
+        delta[MAGIC.len() + 1] = FLAG_METADATA;
+        delta.truncate(MAGIC.len() + 2);
+        delta.extend_from_slice(&[2, 5, 9, 0]);
+        assert!(matches!(split(&delta), Err(GDeltaError::InvalidDelta(_))));
+        delta[MAGIC.len() + 2] = 10;
+        assert!(matches!(
+            split(&delta),
+            Err(GDeltaError::UnexpectedEndOfData)
+        ));
+    }
+
+    #[test]
+    fn test_metadata_roundtrip() {
+        let options = EncodeOptions::new()
+            .metadata(1, b"file.txt")
+            .metadata(300, &[])
+            .metadata(1, b"again");
+        let header = DeltaHeader::for_options(&options, &[], &[]).unwrap();
+        assert_eq!(header.flags, FLAG_METADATA);
+        let delta = header.wrap(&[0]);
+
+        let (parsed, payload) = split(&delta).unwrap();
+        assert_eq!(payload, &[0]);
+        assert_eq!(
+            parsed.unwrap().metadata().collect::<Vec<_>>(),
+            [(1, &b"file.txt"[..]), (300, &[][..]), (1, &b"again"[..])]
+        );
+        assert_eq!(DeltaHeader::parse(&delta).unwrap(), header);
+        assert_eq!(DeltaHeader::identity(&options, &[]).metadata().count(), 3);
+        assert_eq!(DeltaHeader::parse(&[0]).unwrap().metadata().count(), 0);
+    }
+}
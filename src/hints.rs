@@ -0,0 +1,166 @@
+//! Copy regions of earlier deltas, for seeding the matcher.
+
+use crate::buffer::BufferStream;
+use crate::delta::prepare;
+use crate::error::Result;
+use crate::varint::read_delta_unit;
+use std::sync::Arc;
+
+/// The copies from the base of an earlier delta, used to encode a related
+/// target against the same base faster.
+///
+/// In a release pipeline that encodes every version against the same base
+/// (v1→v2, then v1→v3), consecutive targets mostly copy the same base
+/// regions at about the same offsets. [`crate::matcher::HintedMatcher`], or
+/// [`crate::EncodeOptions::hints`], tries those regions first and only
+/// searches the base where they do not match.
+///
+/// Hints never change what a delta decodes to, only which copies the
+/// encoder finds first, so stale or unrelated hints are safe.
+///
+/// # Examples
+///
+/// ```
+/// use gdelta::{EncodeHints, EncodeOptions, decode, encode, encode_with_options};
+///
+/// let v1: Vec<u8> = (0..50_000u32).map(|i| (i * 31 % 251) as u8).collect();
+/// let mut v2 = v1.clone();
+/// v2[10_000..10_004].copy_from_slice(b"v2!!");
+/// let mut v3 = v2.clone();
+/// v3[30_000..30_004].copy_from_slice(b"v3!!");
+///
+/// let hints = EncodeHints::from_delta(&encode(&v2, &v1)?, v1.len())?;
+/// let delta = encode_with_options(&v3, &v1, &EncodeOptions::new().hints(hints))?;
+/// assert_eq!(decode(&delta, &v1)?, v3);
+/// # Ok::<(), gdelta::GDeltaError>(())
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EncodeHints {
+    /// Copies from the base, sorted by target offset.
+    pub(crate) copies: Arc<[HintedCopy]>,
+    /// Indexes into `copies`, sorted by base offset.
+    pub(crate) by_base: Arc<[usize]>,
+}
+
+/// A copy of `base..base + length` to `target..target + length`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct HintedCopy {
+    pub target: usize,
+    pub base: usize,
+    pub length: usize,
+}
+
+impl HintedCopy {
+    /// Returns whether the copy writes the target byte at `pos`.
+    pub fn covers_target(&self, pos: usize) -> bool {
+        (self.target..self.target + self.length).contains(&pos)
+    }
+
+    /// Returns whether the copy reads the base byte at `offset`.
+    pub fn covers_base(&self, offset: usize) -> bool {
+        (self.base..self.base + self.length).contains(&offset)
+    }
+}
+
+impl EncodeHints {
+    /// Collects the copies from the base of `delta`, which was encoded
+    /// against a base of `base_len` bytes. Adjacent copies are merged and
+    /// copies from earlier in the target are left out.
+    ///
+    /// # Errors
+    ///
+    /// Returns `GDeltaError::InvalidDelta` if the delta is malformed.
+    pub fn from_delta(delta: &[u8], base_len: usize) -> Result<Self> {
+        let (raw_deltas, _) = prepare(delta, base_len)?;
+
+        let mut copies: Vec<HintedCopy> = Vec::new();
+        let mut pos = 0usize;
+        for raw in &raw_deltas {
+            let mut stream = BufferStream::from_slice(&raw.instructions);
+            while stream.remaining() > 0 {
+                let unit = read_delta_unit(&mut stream)?;
+                let (offset, length) = (unit.offset as usize, unit.length as usize);
+                if unit.is_copy && offset < base_len {
+                    match copies.last_mut() {
+                        Some(last)
+                            if last.target + last.length == pos
+                                && last.base + last.length == offset =>
+                        {
+                            last.length += length;
+                        }
+                        _ => copies.push(HintedCopy {
+                            target: pos,
+                            base: offset,
+                            length,
+                        }),
+                    }
+                }
+                pos = pos.saturating_add(length);
+            }
+        }
+
+        let mut by_base: Vec<usize> = (0..copies.len()).collect();
+        by_base.sort_by_key(|&index| copies[index].base);
+        Ok(Self {
+            copies: copies.into(),
+            by_base: by_base.into(),
+        })
+    }
+
+    /// Returns the number of copies.
+    pub fn len(&self) -> usize {
+        self.copies.len()
+    }
+
+    /// Returns whether there are no copies to try.
+    pub fn is_empty(&self) -> bool {
+        self.copies.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collects_base_copies() {
+        let mut seed = 1u32;
+        let base: Vec<u8> = (0..20_000)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (seed >> 16) as u8
+            })
+            .collect();
+        let mut new = base[10_000..].to_vec();
+        new.extend_from_slice(b"some inserted literal bytes");
+        new.extend_from_slice(&base[..5_000]);
+        new.extend_from_slice(&base[..5_000]);
+
+        let delta = crate::encode(&new, &base).unwrap();
+        let hints = EncodeHints::from_delta(&delta, base.len()).unwrap();
+        let moved = hints
+            .copies
+            .iter()
+            .find(|copy| copy.covers_target(100))
+            .unwrap();
+        assert_eq!(moved.base - moved.target, 10_000);
+        assert!(moved.length > 9_000, "adjacent copies are merged");
+        for copy in hints.copies.iter() {
+            assert!(copy.base + copy.length <= base.len());
+            assert_eq!(
+                new[copy.target..copy.target + copy.length],
+                base[copy.base..copy.base + copy.length]
+            );
+        }
+        let bases: Vec<usize> = hints
+            .by_base
+            .iter()
+            .map(|&i| hints.copies[i].base)
+            .collect();
+        assert!(bases.is_sorted());
+
+        assert!(EncodeHints::from_delta(b"\xff\xff", base.len()).is_err());
+        let malformed = [128, 0, 71, 157, 76, 1, 17, 80, 225, 137, 173, 109];
+        assert!(EncodeHints::from_delta(&malformed, base.len()).is_err());
+    }
+}
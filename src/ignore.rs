@@ -0,0 +1,301 @@
+//! Ignore rules for directory diffs.
+//!
+//! [`IgnoreRules`] follow the syntax of `.gitignore` files, so build
+//! artifacts and caches can be kept out of directory bundles with the rules
+//! a project already has:
+//!
+//! - blank lines and lines starting with `#` are skipped
+//! - `*` matches anything but `/`, `?` one character other than `/`, and
+//!   `[a-z]` or `[!a-z]` one character in or not in a set
+//! - `**/` matches any number of directories, and a trailing `/**`
+//!   everything inside a directory
+//! - a pattern with a `/` at its start or in the middle is matched from the
+//!   root of the tree, others against names at any depth
+//! - a trailing `/` only matches directories
+//! - a leading `!` includes again what an earlier rule excluded; the last
+//!   matching rule decides
+//! - `\` escapes the next character
+//!
+//! Everything inside an ignored directory is ignored too.
+//!
+//! ```
+//! use gdelta::ignore::IgnoreRules;
+//!
+//! let rules = IgnoreRules::parse("target/\n*.log\n!keep.log\n").unwrap();
+//! assert!(rules.is_ignored("target/debug/app", false));
+//! assert!(rules.is_ignored("logs/build.log", false));
+//! assert!(!rules.is_ignored("logs/keep.log", false));
+//! assert!(!rules.is_ignored("src/main.rs", false));
+//! ```
+
+use crate::error::{GDeltaError, Result};
+
+/// Name of the file directory diffs read ignore rules from, at the root of
+/// the new tree.
+pub const IGNORE_FILE: &str = ".gdignore";
+
+/// An ordered list of ignore rules.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IgnoreRules {
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Rule {
+    tokens: Vec<Token>,
+    negated: bool,
+    dirs_only: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Char(char),
+    /// `?`
+    One,
+    /// `*`
+    Star,
+    /// Trailing `**`, matching anything including `/`.
+    All,
+    /// `**/`, matching nothing or any directories.
+    Dirs,
+    /// `[...]`
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+}
+
+impl IgnoreRules {
+    /// Creates an empty list that ignores nothing.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses the rules of an ignore file, one per line.
+    ///
+    /// # Errors
+    ///
+    /// Returns `GDeltaError::InvalidOptions` if a pattern is invalid.
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut rules = Self::new();
+        for line in text.lines() {
+            let line = line.trim_end();
+            if !line.is_empty() && !line.starts_with('#') {
+                rules.add(line)?;
+            }
+        }
+        Ok(rules)
+    }
+
+    /// Appends one rule, which takes precedence over the earlier ones.
+    ///
+    /// # Errors
+    ///
+    /// Returns `GDeltaError::InvalidOptions` if the pattern is empty or has
+    /// an unterminated `[` or a trailing `\`.
+    pub fn add(&mut self, pattern: &str) -> Result<()> {
+        let invalid = |reason: &str| {
+            Err(GDeltaError::InvalidOptions(format!(
+                "Invalid ignore pattern {pattern:?}: {reason}"
+            )))
+        };
+        let (negated, rest) = match pattern.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, pattern),
+        };
+        let (dirs_only, rest) = match rest.strip_suffix('/') {
+            Some(rest) if !rest.ends_with('\\') => (true, rest),
+            _ => (false, rest),
+        };
+        let anchored = rest.trim_end_matches('/').contains('/');
+        let rest = rest.strip_prefix('/').unwrap_or(rest);
+        if rest.is_empty() {
+            return invalid("it matches nothing");
+        }
+
+        let mut tokens = if anchored {
+            Vec::new()
+        } else {
+            vec![Token::Dirs]
+        };
+        let chars: Vec<char> = rest.chars().collect();
+        let mut i = 0;
+        while i < chars.len() {
+            match chars[i] {
+                '\\' => {
+                    let Some(&c) = chars.get(i + 1) else {
+                        return invalid("it ends with an escape");
+                    };
+                    tokens.push(Token::Char(c));
+                    i += 1;
+                }
+                '?' => tokens.push(Token::One),
+                '*' if chars.get(i + 1) == Some(&'*') => {
+                    let at_start = i == 0 || chars[i - 1] == '/';
+                    match chars.get(i + 2) {
+                        None if at_start => tokens.push(Token::All),
+                        Some('/') if at_start => {
+                            tokens.push(Token::Dirs);
+                            i += 1;
+                        }
+                        // Elsewhere `**` is an ordinary `*`
+                        _ => tokens.push(Token::Star),
+                    }
+                    i += 1;
+                }
+                '*' => tokens.push(Token::Star),
+                '[' => {
+                    let Some((class, end)) = parse_class(&chars, i + 1) else {
+                        return invalid("it has an unterminated `[`");
+                    };
+                    tokens.push(class);
+                    i = end;
+                }
+                c => tokens.push(Token::Char(c)),
+            }
+            i += 1;
+        }
+        self.rules.push(Rule {
+            tokens,
+            negated,
+            dirs_only,
+        });
+        Ok(())
+    }
+
+    /// Returns whether there are no rules.
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Returns whether `path`, relative to the root of the tree with `/`
+    /// separators, is ignored, either itself or through one of its parent
+    /// directories.
+    pub fn is_ignored(&self, path: &str, is_dir: bool) -> bool {
+        path.match_indices('/')
+            .map(|(index, _)| &path[..index])
+            .filter(|parent| !parent.is_empty())
+            .any(|parent| self.matches(parent, true))
+            || self.matches(path, is_dir)
+    }
+
+    /// Returns whether the last rule matching `path` itself excludes it.
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        let path: Vec<char> = path.chars().collect();
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| (is_dir || !rule.dirs_only) && glob_match(&rule.tokens, &path))
+            .is_some_and(|rule| !rule.negated)
+    }
+}
+
+/// Parses a character class starting after its `[`, returning it and the
+/// index of its `]`.
+fn parse_class(chars: &[char], start: usize) -> Option<(Token, usize)> {
+    let mut i = start;
+    let negated = matches!(chars.get(i), Some('!' | '^'));
+    if negated {
+        i += 1;
+    }
+    let mut ranges = Vec::new();
+    let first = i;
+    loop {
+        let mut c = *chars.get(i)?;
+        if c == ']' && i > first {
+            return Some((Token::Class { negated, ranges }, i));
+        }
+        if c == '\\' {
+            i += 1;
+            c = *chars.get(i)?;
+        }
+        if chars.get(i + 1) == Some(&'-') && chars.get(i + 2).is_some_and(|&end| end != ']') {
+            ranges.push((c, chars[i + 2]));
+            i += 3;
+        } else {
+            ranges.push((c, c));
+            i += 1;
+        }
+    }
+}
+
+/// Matches `path` against the whole of `tokens`.
+fn glob_match(tokens: &[Token], path: &[char]) -> bool {
+    let Some((token, rest)) = tokens.split_first() else {
+        return path.is_empty();
+    };
+    match token {
+        Token::Char(c) => path.first() == Some(c) && glob_match(rest, &path[1..]),
+        Token::One => path.first().is_some_and(|&c| c != '/') && glob_match(rest, &path[1..]),
+        Token::Class { negated, ranges } => path.first().is_some_and(|&c| {
+            c != '/'
+                && ranges.iter().any(|&(low, high)| (low..=high).contains(&c)) != *negated
+                && glob_match(rest, &path[1..])
+        }),
+        Token::Star => {
+            let run = path.iter().take_while(|&&c| c != '/').count();
+            (0..=run).any(|skip| glob_match(rest, &path[skip..]))
+        }
+        Token::All => true,
+        Token::Dirs => {
+            glob_match(rest, path)
+                || path
+                    .iter()
+                    .enumerate()
+                    .filter(|&(_, &c)| c == '/')
+                    .any(|(index, _)| glob_match(rest, &path[index + 1..]))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_patterns() {
+        let rules = |text: &str| IgnoreRules::parse(text).unwrap();
+
+        let names = rules("*.o\ncache?\n[Tt]emp*\n");
+        assert!(names.is_ignored("main.o", false));
+        assert!(names.is_ignored("src/deep/util.o", false));
+        assert!(!names.is_ignored("main.obj", false));
+        assert!(names.is_ignored("cache1", true));
+        assert!(!names.is_ignored("cache", true));
+        assert!(names.is_ignored("a/Temporary/file", false));
+        assert!(names.is_ignored("a/temp", false));
+        assert!(!names.is_ignored("a/xemp", false));
+
+        let anchored = rules("/build\ndocs/*.html\n");
+        assert!(anchored.is_ignored("build/app", false));
+        assert!(!anchored.is_ignored("src/build", false));
+        assert!(anchored.is_ignored("docs/index.html", false));
+        assert!(!anchored.is_ignored("docs/api/index.html", false));
+
+        let stars = rules("**/node_modules/\nassets/**/*.psd\nlogs/**\n");
+        assert!(stars.is_ignored("web/node_modules/pkg/index.js", false));
+        assert!(!stars.is_ignored("node_modules", false));
+        assert!(stars.is_ignored("assets/logo.psd", false));
+        assert!(stars.is_ignored("assets/ui/icons/logo.psd", false));
+        assert!(stars.is_ignored("logs/2024/run.txt", false));
+        assert!(!stars.is_ignored("logs", true));
+
+        let negated = rules("# build output\n*.bin\n!firmware.bin\n\n\\#notes\n");
+        assert!(negated.is_ignored("out/app.bin", false));
+        assert!(!negated.is_ignored("out/firmware.bin", false));
+        assert!(negated.is_ignored("#notes", false));
+    }
+
+    #[test]
+    fn test_rejects_invalid_patterns() {
+        for pattern in ["", "/", "!", "[abc", "trailing\\"] {
+            assert!(
+                matches!(
+                    IgnoreRules::new().add(pattern),
+                    Err(GDeltaError::InvalidOptions(_))
+                ),
+                "{pattern:?}"
+            );
+        }
+    }
+}
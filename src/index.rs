@@ -0,0 +1,139 @@
+//! A base indexed once for encoding many targets.
+
+use crate::delta;
+use crate::error::Result;
+use crate::matcher::{ConfiguredMatcher, Match, Matcher};
+use crate::options::EncodeOptions;
+use crate::parallel;
+use crate::preset::Preset;
+
+/// A base together with its hash table, for encoding many targets against
+/// it without indexing it again each time.
+///
+/// Indexing is a large part of the cost of encoding a small change against
+/// a large base. A server or daemon that keeps producing deltas from the
+/// same base builds the index once; encoding a target then only scans the
+/// target. The index covers the whole base, so it may find copies that an
+/// encoding from scratch, which only indexes what lies between the common
+/// prefix and suffix, would not; the deltas decode the same either way.
+///
+/// The index is shared between threads, which can encode concurrently.
+///
+/// # Examples
+///
+/// ```
+/// use gdelta::{BaseIndex, EncodeOptions, decode};
+///
+/// let base: Vec<u8> = (0..50_000u32).map(|i| (i * 31 % 251) as u8).collect();
+/// let index = BaseIndex::new(base.clone(), &EncodeOptions::new().checksum(true))?;
+///
+/// for edit in [1_000, 20_000, 40_000] {
+///     let mut new = base.clone();
+///     new[edit..edit + 4].copy_from_slice(b"edit");
+///     let delta = index.encode(&new)?;
+///     assert_eq!(decode(&delta, &base)?, new);
+/// }
+/// # Ok::<(), gdelta::GDeltaError>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct BaseIndex {
+    base: Vec<u8>,
+    options: EncodeOptions,
+    matcher: ConfiguredMatcher,
+}
+
+impl BaseIndex {
+    /// Indexes `base` for encoding with `options`.
+    ///
+    /// Unless set with [`EncodeOptions::preset`], the preset is picked from
+    /// the start of the base rather than of each target, since the index
+    /// depends on it.
+    ///
+    /// # Errors
+    ///
+    /// Returns `GDeltaError::InvalidOptions` if the options are invalid.
+    pub fn new(base: Vec<u8>, options: &EncodeOptions) -> Result<Self> {
+        options.validate()?;
+        let options = if options.auto_preset {
+            options.clone().preset(Preset::detect(&base))
+        } else {
+            options.clone()
+        };
+        let mut matcher = options.matcher();
+        matcher.index(&base, 0, base.len());
+        Ok(Self {
+            base,
+            options,
+            matcher,
+        })
+    }
+
+    /// Returns the indexed base.
+    pub fn base(&self) -> &[u8] {
+        &self.base
+    }
+
+    /// Returns the options targets are encoded with.
+    pub fn options(&self) -> &EncodeOptions {
+        &self.options
+    }
+
+    /// Encodes the delta from the base to `new_data`, like
+    /// [`crate::encode_with_options`] with the options of the index.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`crate::encode_with_options`].
+    pub fn encode(&self, new_data: &[u8]) -> Result<Vec<u8>> {
+        let mut matcher = Indexed(self.matcher.clone());
+        if self.options.parallel {
+            return parallel::encode(new_data, &self.base, matcher, &self.options);
+        }
+        delta::encode(new_data, &self.base, &mut matcher, &self.options)
+    }
+}
+
+/// A matcher whose index is already built over the whole base.
+#[derive(Debug, Clone)]
+struct Indexed(ConfiguredMatcher);
+
+impl Matcher for Indexed {
+    fn index(&mut self, _base: &[u8], _start: usize, _end: usize) {}
+
+    fn find_match(&mut self, target: &[u8], base: &[u8], pos: usize) -> Option<Match> {
+        self.0.find_match(target, base, pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reuses_index() {
+        let base: Vec<u8> = (0..20_000u32)
+            .flat_map(|i| format!("line {i}\n").into_bytes())
+            .collect();
+        let mut moved = base[100_000..].to_vec();
+        moved.extend_from_slice(&base[..100_000]);
+        let mut edited = base.clone();
+        edited[50_000..50_010].copy_from_slice(b"0123456789");
+
+        for options in [
+            EncodeOptions::new(),
+            EncodeOptions::new().text_mode(true),
+            EncodeOptions::new().parallel(true).checksum(true),
+        ] {
+            let index = BaseIndex::new(base.clone(), &options).unwrap();
+            for new in [&moved, &edited, &base] {
+                let delta = index.encode(new).unwrap();
+                assert_eq!(crate::decode(&delta, &base).unwrap(), *new, "{options:?}");
+            }
+            // Matches outside the middle only help
+            let from_scratch = crate::encode_with_options(&edited, &base, &options).unwrap();
+            assert!(index.encode(&edited).unwrap().len() <= from_scratch.len() + 16);
+        }
+
+        assert!(BaseIndex::new(base, &EncodeOptions::new().format_version(200)).is_err());
+    }
+}
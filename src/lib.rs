@@ -39,18 +39,163 @@
 //!
 //! For maximum compression, combine `GDelta` with a general-purpose compressor
 //! like ZSTD or LZ4.
+//!
+//! ## Rolling Hash
+//!
+//! The GEAR rolling hash used for matching is available in the [`gear`]
+//! module, so chunkers and similarity tools can compute fingerprints that are
+//! identical to the ones the encoder uses.
+//!
+//! ## Feature Flags
+//!
+//! - `simd` (default): SIMD-accelerated prefix, suffix and match scanning
+//! - `bsdiff`: enables the [`bsdiff`] module for applying and emitting
+//!   `BSDIFF40` patches
+//! - `bytes`: [`decode_bytes`] and [`decode_into_bytes_mut`] for decoding
+//!   with `bytes::Bytes` buffers
+//! - `rayon`: encodes the windows of [`EncodeOptions::parallel`] and the files
+//!   of directory bundles on all cores; the output is the same as without the
+//!   feature
+//! - `client`: the [`client`] module, blocking and async clients of the
+//!   `server` service
+//! - `server`: the [`server`] module, an HTTP service creating and applying
+//!   deltas against the files of a directory
+//! - `unstable-format`: exposes the [`format`] module for emitting deltas from
+//!   custom matchers; not covered by semver guarantees
 
 #![forbid(unsafe_code)]
 #![warn(missing_docs)]
 #![warn(clippy::all)]
 
+pub mod blocks;
+#[cfg(feature = "bsdiff")]
+pub mod bsdiff;
 mod buffer;
+pub mod bundle;
+#[cfg(feature = "bytes")]
+mod bytes_io;
+mod checksum;
+#[cfg(feature = "client")]
+pub mod client;
+pub mod compression;
+#[cfg(feature = "unstable-format")]
+mod corpus;
+mod coverage;
+mod debug;
 mod delta;
+mod entropy;
 mod error;
-mod gear;
+pub mod exe;
+#[cfg(feature = "fec")]
+pub mod fec;
+pub mod firmware;
+#[cfg(feature = "unstable-format")]
+pub mod format;
+pub mod framing;
+pub mod gear;
+mod header;
+mod hints;
+pub mod ignore;
+mod index;
+pub mod matcher;
+#[cfg(feature = "naming")]
+pub mod naming;
+pub mod oci;
+mod options;
+pub mod ota;
+mod parallel;
+mod preset;
+mod progressive;
+mod recovery;
+mod scatter;
+mod segment;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod similarity;
+mod source;
+mod stats;
+mod stream;
+mod summary;
+mod tar;
+mod throttle;
+mod untrusted;
 mod varint;
 
+pub use checksum::{ChecksumWriter, TargetChecksum};
+pub use coverage::{BaseCoverage, CoveredRange, PrefetchPlan};
+pub use debug::{Breakpoint, DebugInstruction, DebugOp, DecoderState};
 pub use error::{GDeltaError, Result};
+pub use header::DeltaHeader;
+pub use hints::EncodeHints;
+pub use index::BaseIndex;
+pub use options::EncodeOptions;
+pub use preset::Preset;
+pub use progressive::ProgressiveDecoder;
+pub use recovery::{DecodeFailure, LossyDecode};
+pub use source::{BaseSource, PrefetchedBase, SeekSource};
+pub use stats::{EncodeStats, LengthHistogram, MovedBlock, TableStats};
+pub use stream::{EncodeCheckpoint, EncodeReader};
+pub use summary::DeltaSummary;
+pub use throttle::ThrottledWriter;
+pub use untrusted::DecodeLimits;
+
+use matcher::{ConfiguredMatcher, GearMatcher, Matcher};
+use stats::TableCounters;
+use std::sync::Arc;
+
+/// Newest delta format version this crate writes and decodes.
+pub const FORMAT_VERSION: u8 = header::VERSION;
+
+/// Oldest delta format version this crate decodes.
+///
+/// Version 0 is the raw format without a header; see [`format_version`].
+pub const MIN_FORMAT_VERSION: u8 = header::MIN_VERSION;
+
+/// Returns true if this release decodes deltas of format `version`, that
+/// is, if it lies in [`MIN_FORMAT_VERSION`]`..=`[`FORMAT_VERSION`].
+///
+/// # Examples
+///
+/// ```
+/// assert!(gdelta::supports(0));
+/// assert!(gdelta::supports(gdelta::FORMAT_VERSION));
+/// assert!(!gdelta::supports(gdelta::FORMAT_VERSION + 1));
+/// ```
+pub fn supports(version: u8) -> bool {
+    header::is_supported(version)
+}
+
+/// Picks the newest format version that both this release and a peer
+/// reading versions `peer_min..=peer_max` support, or `None` if the ranges
+/// do not overlap.
+///
+/// A client announces the range it decodes, usually its own
+/// [`MIN_FORMAT_VERSION`] and [`FORMAT_VERSION`], and the server encodes
+/// with [`EncodeOptions::format_version`] set to the result, so old clients
+/// keep working while newer ones get the newer format.
+///
+/// # Examples
+///
+/// ```
+/// use gdelta::{EncodeOptions, decode, encode_with_options, format_version, negotiate_format};
+///
+/// // A client from before delta headers only reads the raw format
+/// let version = negotiate_format(0, 0).unwrap();
+/// let options = EncodeOptions::new().format_version(version);
+///
+/// let base = b"Hello, World!";
+/// let delta = encode_with_options(b"Hello, Rust!", base, &options)?;
+/// assert_eq!(format_version(&delta)?, 0);
+/// assert_eq!(decode(&delta, base)?, b"Hello, Rust!");
+///
+/// assert_eq!(negotiate_format(0, 200), Some(gdelta::FORMAT_VERSION));
+/// assert_eq!(negotiate_format(200, 255), None);
+/// # Ok::<(), gdelta::GDeltaError>(())
+/// ```
+pub fn negotiate_format(peer_min: u8, peer_max: u8) -> Option<u8> {
+    let version = peer_max.min(FORMAT_VERSION);
+    (version >= peer_min && supports(version)).then_some(version)
+}
 
 /// Encodes the delta between new data and base data.
 ///
@@ -69,9 +214,9 @@ pub use error::{GDeltaError, Result};
 ///
 /// # Errors
 ///
-/// Currently, encoding does not fail under normal circumstances. The `Result` type
-/// is used for API consistency with `decode` and to allow for future validation
-/// or error conditions without breaking the API.
+/// Encoding with the default options used here does not fail. The errors of
+/// [`encode_with_options`] only come from options that limit the encoding,
+/// such as a deadline or a maximum ratio, or that are invalid.
 ///
 /// # Examples
 ///
@@ -91,7 +236,369 @@ pub use error::{GDeltaError, Result};
 /// with additional overhead for building the hash table of the base data.
 /// Typical throughput is several hundred MB/s on modern hardware.
 pub fn encode(new_data: &[u8], base_data: &[u8]) -> Result<Vec<u8>> {
-    delta::encode(new_data, base_data)
+    delta::encode(
+        new_data,
+        base_data,
+        &mut GearMatcher::new(),
+        &EncodeOptions::default(),
+    )
+}
+
+/// Encodes the delta between new data and base data with custom options.
+///
+/// Works like [`encode`], with behavior adjusted by `options`. The output is
+/// decoded with [`decode`].
+///
+/// # Errors
+///
+/// - `GDeltaError::InvalidOptions` if `options` are invalid or conflict,
+///   such as a feature that [`EncodeOptions::format_version`] cannot
+///   represent
+/// - `GDeltaError::NotBeneficial` if the delta would exceed
+///   [`EncodeOptions::max_ratio`]
+/// - `GDeltaError::DeadlineExceeded` if [`EncodeOptions::deadline`] or
+///   [`EncodeOptions::timeout`] passes before encoding finishes, unless
+///   [`EncodeOptions::degrade_on_deadline`] is set
+///
+/// # Examples
+///
+/// ```
+/// use gdelta::{EncodeOptions, decode, encode_with_options};
+///
+/// let base = b"Hello, World! This is some base data.";
+/// let new = b"Hello, Rust! This is some modified data.";
+///
+/// let options = EncodeOptions::new().copy_window(64 * 1024);
+/// let delta = encode_with_options(new, base, &options).unwrap();
+/// assert_eq!(decode(&delta, base).unwrap(), new);
+/// ```
+pub fn encode_with_options(
+    new_data: &[u8],
+    base_data: &[u8],
+    options: &EncodeOptions,
+) -> Result<Vec<u8>> {
+    if options.auto_preset {
+        let options = options.clone().preset(Preset::detect(new_data));
+        return encode_with_options(new_data, base_data, &options);
+    }
+    encode_with_configured(new_data, base_data, options.matcher(), options)
+}
+
+/// Encodes with `matcher`, which `options` configured.
+fn encode_with_configured(
+    new_data: &[u8],
+    base_data: &[u8],
+    mut matcher: ConfiguredMatcher,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>> {
+    if options.parallel {
+        return parallel::encode(new_data, base_data, matcher, options);
+    }
+    delta::encode(new_data, base_data, &mut matcher, options)
+}
+
+/// Encodes like [`encode_with_options`] and also returns the distribution
+/// of match and gap lengths in the delta.
+///
+/// Useful for tuning chunk sizes and matcher settings against real data.
+/// The statistics describe the delta that was produced, so they reflect
+/// every option, including parallel and segmented encoding. They also
+/// report how full the hash table of the base was and how many of its
+/// lookups found a match, in [`EncodeStats::table`], to guide the choice
+/// of [`EncodeOptions::memory_budget`] and [`Preset`].
+///
+/// # Errors
+///
+/// Returns the same errors as [`encode_with_options`].
+///
+/// # Examples
+///
+/// ```
+/// use gdelta::{EncodeOptions, encode_with_stats};
+///
+/// let base = b"The quick brown fox jumps over the lazy dog. ".repeat(10);
+/// let mut new = base.clone();
+/// new.extend_from_slice(b"A new sentence.");
+///
+/// let (delta, stats) = encode_with_stats(&new, &base, &EncodeOptions::new()).unwrap();
+/// assert_eq!(stats.matches.count(), 1);
+/// for (lengths, count) in stats.gaps.iter() {
+///     println!("{count} gaps of {lengths:?} bytes");
+/// }
+/// # assert_eq!(gdelta::decode(&delta, &base).unwrap(), new);
+/// ```
+pub fn encode_with_stats(
+    new_data: &[u8],
+    base_data: &[u8],
+    options: &EncodeOptions,
+) -> Result<(Vec<u8>, EncodeStats)> {
+    if options.auto_preset {
+        let options = options.clone().preset(Preset::detect(new_data));
+        return encode_with_stats(new_data, base_data, &options);
+    }
+    let counters = Arc::new(TableCounters::default());
+    let mut matcher = options.matcher();
+    matcher.collect_table_stats(&counters);
+    let delta = encode_with_configured(new_data, base_data, matcher, options)?;
+    let mut stats = EncodeStats::from_delta(&delta, base_data.len())?;
+    stats.table = counters.stats();
+    Ok((delta, stats))
+}
+
+/// Encodes the delta between new data and base data if they are similar
+/// enough for a delta to pay off.
+///
+/// First estimates the similarity of the inputs from their
+/// [`similarity::Sketch`]es, which takes a fraction of the encoding time,
+/// and only encodes if it reaches `min_similarity` (between 0.0 and 1.0).
+/// Callers comparing one object against many candidate bases can build the
+/// sketches themselves and keep them.
+///
+/// # Errors
+///
+/// Returns `GDeltaError::NotSimilar` if the estimated similarity is below
+/// `min_similarity`.
+///
+/// # Examples
+///
+/// ```
+/// use gdelta::{GDeltaError, decode, encode_if_similar};
+///
+/// let base = b"The quick brown fox jumps over the lazy dog. ".repeat(20);
+/// let mut new = base.clone();
+/// new.extend_from_slice(b"And then it ran away.");
+///
+/// let delta = encode_if_similar(&new, &base, 0.5).unwrap();
+/// assert_eq!(decode(&delta, &base).unwrap(), new);
+///
+/// let unrelated = b"Lorem ipsum dolor sit amet, consectetur adipiscing elit.";
+/// assert_eq!(
+///     encode_if_similar(unrelated, &base, 0.5),
+///     Err(GDeltaError::NotSimilar)
+/// );
+/// ```
+pub fn encode_if_similar(
+    new_data: &[u8],
+    base_data: &[u8],
+    min_similarity: f64,
+) -> Result<Vec<u8>> {
+    let similarity =
+        similarity::Sketch::new(new_data).similarity(&similarity::Sketch::new(base_data));
+    if similarity < min_similarity {
+        return Err(GDeltaError::NotSimilar);
+    }
+    encode(new_data, base_data)
+}
+
+/// Number of the most similar candidates [`choose_base`] trial-encodes.
+const TRIAL_CANDIDATES: usize = 3;
+
+/// Picks the candidate base that new data encodes against best.
+///
+/// Ranks the candidates by [`similarity::Sketch`] similarity, then encodes
+/// against the most similar few, giving up on each trial as soon as its
+/// delta grows past the smallest one so far. Returns the index of the best
+/// candidate and the ratio of its delta size to the size of `new_data`,
+/// which is below 1.0 when the delta is smaller than the data itself.
+///
+/// Pass the chosen base to [`encode`] or [`encode_with_options`] to build
+/// the delta; the trial deltas are not kept.
+///
+/// # Errors
+///
+/// Returns `GDeltaError::InvalidOptions` if `candidates` is empty.
+///
+/// # Examples
+///
+/// ```
+/// use gdelta::choose_base;
+///
+/// let v1: String = (0..2000).map(|i| format!("record {i}\n")).collect();
+/// let v2 = v1.replace("record 500\n", "record five hundred\n");
+/// let unrelated: String = (0..2000).map(|i| format!("{i:x} other\n")).collect();
+/// let new = v2.replace("record 1500\n", "record 1,500\n");
+///
+/// let candidates = [unrelated.as_bytes(), v1.as_bytes(), v2.as_bytes()];
+/// let (index, ratio) = choose_base(new.as_bytes(), &candidates)?;
+/// assert_eq!(index, 2);
+/// assert!(ratio < 0.01);
+/// # Ok::<(), gdelta::GDeltaError>(())
+/// ```
+#[allow(clippy::cast_precision_loss)]
+pub fn choose_base(new_data: &[u8], candidates: &[&[u8]]) -> Result<(usize, f64)> {
+    if candidates.is_empty() {
+        return Err(GDeltaError::InvalidOptions(
+            "No candidate bases to choose from".to_string(),
+        ));
+    }
+
+    let sketch = similarity::Sketch::new(new_data);
+    let mut ranked: Vec<(usize, f64)> = candidates
+        .iter()
+        .enumerate()
+        .map(|(index, base)| (index, sketch.similarity(&similarity::Sketch::new(base))))
+        .collect();
+    ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    let len = new_data.len().max(1) as f64;
+    let mut best: Option<(usize, f64)> = None;
+    for &(index, _) in ranked.iter().take(TRIAL_CANDIDATES) {
+        let mut options = EncodeOptions::new();
+        if let Some((_, ratio)) = best {
+            options = options.max_ratio(ratio);
+        }
+        match encode_with_options(new_data, candidates[index], &options) {
+            Ok(delta) => best = Some((index, delta.len() as f64 / len)),
+            Err(GDeltaError::NotBeneficial) => {}
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(best.unwrap_or((ranked[0].0, f64::INFINITY)))
+}
+
+/// Encodes the delta between two streams with bounded memory.
+///
+/// Reads `new` sequentially and `base` with seeks, so neither input has to
+/// be loaded fully; memory use stays around three target windows of a few
+/// MiB each regardless of input size. The delta is written to `output` and
+/// decoded with [`decode`]. Returns the number of bytes written.
+///
+/// Each target window is matched against the region of the base around
+/// where the previous window's matches ended, so content that moved further
+/// than a window is stored as literal data. For inputs that fit in memory,
+/// [`encode_with_options`] finds more matches.
+///
+/// # Errors
+///
+/// Returns `GDeltaError::Io` if reading, seeking or writing fails, and
+/// `GDeltaError::InvalidOptions` if `options` request what windows cannot
+/// honor, such as a stored target size, which needs the whole target up
+/// front.
+///
+/// # Examples
+///
+/// ```
+/// use gdelta::{EncodeOptions, decode, encode_streams};
+/// use std::io::Cursor;
+///
+/// let base = b"The quick brown fox jumps over the lazy dog";
+/// let new = b"The quick brown cat jumps over the lazy dog";
+///
+/// let mut delta = Vec::new();
+/// encode_streams(&new[..], Cursor::new(base), &mut delta, &EncodeOptions::new()).unwrap();
+/// assert_eq!(decode(&delta, base).unwrap(), new);
+/// ```
+pub fn encode_streams<N, B, W>(new: N, base: B, output: W, options: &EncodeOptions) -> Result<u64>
+where
+    N: std::io::Read,
+    B: std::io::Read + std::io::Seek,
+    W: std::io::Write,
+{
+    stream::encode_streams(new, base, output, options)
+}
+
+/// Encodes the delta between two streams like [`encode_streams`], so that
+/// an interrupted encode can be resumed.
+///
+/// After every target window has been written and `output` flushed,
+/// `checkpoint` is called with an [`EncodeCheckpoint`] to persist, for
+/// example with [`EncodeCheckpoint::to_bytes`]. To resume, pass the last
+/// persisted checkpoint as `resume_from` together with:
+///
+/// - `new` positioned at [`EncodeCheckpoint::target_position`],
+/// - `output` positioned just after the first
+///   [`EncodeCheckpoint::output_position`] bytes of the earlier output, with
+///   anything after them discarded,
+/// - the same base and options.
+///
+/// The finished delta is byte-identical to one encoded without interruption.
+/// Returns the total size of the delta, including the bytes written before
+/// the checkpoint.
+///
+/// # Errors
+///
+/// Returns the errors of [`encode_streams`], any error from `checkpoint`,
+/// `GDeltaError::InvalidOptions` if `options` differ from the ones the
+/// checkpoint was taken with, and `GDeltaError::SizeMismatch` if the base
+/// has a different length.
+///
+/// # Examples
+///
+/// ```
+/// use gdelta::{EncodeCheckpoint, EncodeOptions, decode, encode_streams_resumable};
+/// use std::io::Cursor;
+///
+/// let base = b"The quick brown fox jumps over the lazy dog";
+/// let new = b"The quick brown cat jumps over the lazy dog";
+/// let options = EncodeOptions::new();
+///
+/// let mut delta = Vec::new();
+/// let mut saved = Vec::new();
+/// encode_streams_resumable(&new[..], Cursor::new(base), &mut delta, &options, None, |c| {
+///     saved = c.to_bytes();
+///     Ok(())
+/// })
+/// .unwrap();
+///
+/// // The whole target fit in one window, so resuming only finishes the delta
+/// let checkpoint = EncodeCheckpoint::from_bytes(&saved).unwrap();
+/// delta.truncate(checkpoint.output_position() as usize);
+/// let position = checkpoint.target_position() as usize;
+/// encode_streams_resumable(
+///     &new[position..],
+///     Cursor::new(base),
+///     &mut delta,
+///     &options,
+///     Some(&checkpoint),
+///     |_| Ok(()),
+/// )
+/// .unwrap();
+/// assert_eq!(decode(&delta, base).unwrap(), new);
+/// ```
+pub fn encode_streams_resumable<N, B, W>(
+    new: N,
+    base: B,
+    output: W,
+    options: &EncodeOptions,
+    resume_from: Option<&EncodeCheckpoint>,
+    checkpoint: impl FnMut(&EncodeCheckpoint) -> Result<()>,
+) -> Result<u64>
+where
+    N: std::io::Read,
+    B: std::io::Read + std::io::Seek,
+    W: std::io::Write,
+{
+    stream::encode_streams_resumable(new, base, output, options, resume_from, checkpoint)
+}
+
+/// Encodes the delta between new data and base data using a custom matcher.
+///
+/// Works like [`encode`], but delegates the search for copies to `matcher`
+/// instead of the default [`GearMatcher`]. The output uses the regular delta
+/// format and is decoded with [`decode`].
+///
+/// # Errors
+///
+/// Encoding with the default options used here does not fail; see [`encode`].
+///
+/// # Examples
+///
+/// ```
+/// use gdelta::matcher::GearMatcher;
+/// use gdelta::{decode, encode_with_matcher};
+///
+/// let base = b"The quick brown fox jumps over the lazy dog";
+/// let new = b"The quick brown cat jumps over the lazy dog";
+///
+/// let delta = encode_with_matcher(new, base, &mut GearMatcher::new()).unwrap();
+/// assert_eq!(decode(&delta, base).unwrap(), new);
+/// ```
+pub fn encode_with_matcher<M: Matcher>(
+    new_data: &[u8],
+    base_data: &[u8],
+    matcher: &mut M,
+) -> Result<Vec<u8>> {
+    delta::encode(new_data, base_data, matcher, &EncodeOptions::default())
 }
 
 /// Decodes delta data using the base data to reconstruct the original.
@@ -114,7 +621,20 @@ pub fn encode(new_data: &[u8], base_data: &[u8]) -> Result<Vec<u8>> {
 /// Returns `GDeltaError::InvalidDelta` if:
 /// - The delta data is corrupted or malformed
 /// - The instruction length exceeds the delta size
+/// - The literal data section is shorter or longer than the instructions require
 /// - A copy instruction references data beyond the base data bounds
+/// - The delta copies from the target but does not record the target size
+///
+/// Returns `GDeltaError::ChecksumMismatch` if the delta was encoded with
+/// [`EncodeOptions::checksum`] and its contents were altered.
+///
+/// Returns `GDeltaError::SizeMismatch` if the delta was encoded with
+/// [`EncodeOptions::store_target_size`] and the output length differs.
+///
+/// A few bytes of delta can describe a much larger target, most of all with
+/// overlapping copies from the target, so the output is only bounded by the
+/// target size the delta declares. Decode deltas from untrusted sources
+/// with [`decode_untrusted`].
 ///
 /// # Examples
 ///
@@ -138,6 +658,524 @@ pub fn decode(delta: &[u8], base_data: &[u8]) -> Result<Vec<u8>> {
     delta::decode(delta, base_data)
 }
 
+/// Decodes a delta against a base split into several buffers.
+///
+/// `base_parts` are addressed as if they were concatenated, and copies that
+/// cross a boundary between parts are assembled from each of them. This
+/// avoids concatenating the base first, e.g. when it is reassembled from
+/// chunks of a deduplicating store.
+///
+/// # Errors
+///
+/// Returns the same errors as [`decode`].
+///
+/// # Examples
+///
+/// ```
+/// use gdelta::{decode_scattered, encode};
+///
+/// let base = b"Hello, World!";
+/// let delta = encode(b"Hello, Rust!", base).unwrap();
+///
+/// let parts: [&[u8]; 2] = [b"Hello, ", b"World!"];
+/// assert_eq!(decode_scattered(&delta, &parts).unwrap(), b"Hello, Rust!");
+/// ```
+pub fn decode_scattered(delta: &[u8], base_parts: &[&[u8]]) -> Result<Vec<u8>> {
+    scatter::decode(delta, base_parts)
+}
+
+/// Decodes a delta into a caller-provided buffer.
+///
+/// Writes the reconstructed data to the start of `output` and returns its
+/// length, so callers with pre-allocated arenas (e.g. page caches) avoid a
+/// fresh allocation per call. The delta is fully validated first; on error,
+/// `output` may have been partially written.
+///
+/// # Errors
+///
+/// Returns `GDeltaError::BufferError` if `output` is shorter than the
+/// reconstructed data, and otherwise the same errors as [`decode`].
+///
+/// # Examples
+///
+/// ```
+/// use gdelta::{decode_into_slice, encode};
+///
+/// let base = b"Hello, World!";
+/// let delta = encode(b"Hello, Rust!", base).unwrap();
+///
+/// let mut page = [0u8; 4096];
+/// let len = decode_into_slice(&delta, base, &mut page).unwrap();
+/// assert_eq!(&page[..len], b"Hello, Rust!");
+/// ```
+pub fn decode_into_slice(delta: &[u8], base_data: &[u8], output: &mut [u8]) -> Result<usize> {
+    delta::decode_into_slice(delta, base_data, output)
+}
+
+/// Decodes a delta directly into an [`std::io::Write`] sink.
+///
+/// Works like [`decode`], but instead of assembling the output in memory it
+/// passes slices of the base and of the delta's literal data straight to
+/// [`std::io::Write::write_vectored`], avoiding an intermediate copy of every
+/// instruction's payload. Returns the number of bytes written.
+///
+/// The delta is fully validated before anything is written.
+///
+/// # Errors
+///
+/// Returns the same errors as [`decode`], and `GDeltaError::Io` if writing
+/// fails.
+///
+/// # Examples
+///
+/// ```
+/// use gdelta::{decode_to_writer, encode};
+///
+/// let base = b"Hello, World!";
+/// let new = b"Hello, Rust!";
+///
+/// let delta = encode(new, base).unwrap();
+/// let mut output = Vec::new();
+/// decode_to_writer(&delta, base, &mut output).unwrap();
+/// assert_eq!(output, new);
+/// ```
+pub fn decode_to_writer<W: std::io::Write>(
+    delta: &[u8],
+    base_data: &[u8],
+    output: W,
+) -> Result<u64> {
+    stream::decode_to_writer(delta, base_data, output)
+}
+
+/// Decodes a delta into an [`std::io::Write`] sink and returns the length
+/// and CRC-32 of the output.
+///
+/// Works like [`decode_to_writer`], computing the checksum of the target
+/// as it is written through a [`ChecksumWriter`], so verifying a large
+/// output against a known checksum does not need a second pass over it.
+///
+/// # Errors
+///
+/// Returns the same errors as [`decode_to_writer`].
+///
+/// # Examples
+///
+/// ```
+/// use gdelta::{decode_to_writer_checksummed, encode};
+///
+/// let base = b"The quick brown fox jumps over the cat";
+/// let new = b"The quick brown fox jumps over the lazy dog";
+///
+/// let delta = encode(new, base).unwrap();
+/// let mut output = Vec::new();
+/// let target = decode_to_writer_checksummed(&delta, base, &mut output).unwrap();
+/// assert_eq!(target.len, 43);
+/// assert_eq!(target.crc32, 0x414F_A339);
+/// ```
+pub fn decode_to_writer_checksummed<W: std::io::Write>(
+    delta: &[u8],
+    base_data: &[u8],
+    output: W,
+) -> Result<TargetChecksum> {
+    let mut writer = ChecksumWriter::new(output);
+    stream::decode_to_writer(delta, base_data, &mut writer)?;
+    Ok(TargetChecksum {
+        len: writer.written(),
+        crc32: writer.checksum(),
+    })
+}
+
+/// Decodes a delta directly into a file.
+///
+/// The file is resized to the exact output length before writing, so the
+/// filesystem can allocate the whole target at once and stale trailing
+/// contents of an existing file are dropped. Output is then written from the
+/// start of the file the same way as [`decode_to_writer`], without assembling
+/// it in memory first. Returns the number of bytes written.
+///
+/// # Errors
+///
+/// Returns the same errors as [`decode`], and `GDeltaError::Io` if resizing
+/// or writing the file fails. The file is left untouched if the delta is
+/// invalid or its base checksum does not match; after other errors its
+/// contents are unspecified.
+///
+/// # Examples
+///
+/// ```no_run
+/// use gdelta::{decode_to_file, encode};
+/// use std::fs::File;
+///
+/// let base = b"Hello, World!";
+/// let delta = encode(b"Hello, Rust!", base).unwrap();
+///
+/// let mut file = File::create("output.bin").unwrap();
+/// decode_to_file(&delta, base, &mut file).unwrap();
+/// ```
+pub fn decode_to_file(delta: &[u8], base_data: &[u8], file: &mut std::fs::File) -> Result<u64> {
+    stream::decode_to_file(delta, base_data, file)
+}
+
+/// Decodes a delta against a base that is read on demand.
+///
+/// Works like [`decode_to_writer`], but the base is a [`BaseSource`] that is
+/// only asked for the ranges copy instructions reference, so bases on disk
+/// or in remote storage never have to be loaded in full. Copies of adjacent
+/// base ranges are merged into a single read. Returns the number of bytes
+/// written.
+///
+/// The delta is fully validated before the base is read or anything is
+/// written. Deltas with copies from the target keep the output in memory.
+///
+/// # Errors
+///
+/// Returns the same errors as [`decode`], any error from
+/// [`BaseSource::read_at`], and `GDeltaError::Io` if writing fails.
+///
+/// # Examples
+///
+/// ```
+/// use gdelta::{SeekSource, decode_from_source, encode};
+/// use std::io::Cursor;
+///
+/// let base = b"Hello, World!";
+/// let delta = encode(b"Hello, Rust!", base).unwrap();
+///
+/// let mut source = SeekSource::new(Cursor::new(base)).unwrap();
+/// let mut output = Vec::new();
+/// decode_from_source(&delta, &mut source, &mut output).unwrap();
+/// assert_eq!(output, b"Hello, Rust!");
+/// ```
+pub fn decode_from_source<S, W>(delta: &[u8], base: &mut S, output: W) -> Result<u64>
+where
+    S: BaseSource + ?Sized,
+    W: std::io::Write,
+{
+    source::decode_from_source(delta, base, output)
+}
+
+/// Decodes a delta held in [`bytes::Bytes`] buffers.
+///
+/// If the delta consists of a single copy or a single literal, the result
+/// shares memory with `base_data` or `delta` instead of copying it; this is
+/// common for unchanged or completely replaced chunks. Otherwise the output
+/// is assembled in one [`bytes::BytesMut`] allocation.
+///
+/// # Errors
+///
+/// Returns the same errors as [`decode`].
+///
+/// # Examples
+///
+/// ```
+/// use bytes::Bytes;
+/// use gdelta::{decode_bytes, encode};
+///
+/// let base = Bytes::from_static(b"Hello, World!");
+/// let delta = Bytes::from(encode(b"Hello, Rust!", &base).unwrap());
+///
+/// assert_eq!(decode_bytes(&delta, &base).unwrap(), &b"Hello, Rust!"[..]);
+/// ```
+#[cfg(feature = "bytes")]
+pub fn decode_bytes(delta: &bytes::Bytes, base_data: &bytes::Bytes) -> Result<bytes::Bytes> {
+    bytes_io::decode_bytes(delta, base_data)
+}
+
+/// Decodes a delta, appending the output to a [`bytes::BytesMut`].
+///
+/// Lets network code reuse one buffer across many decodes. Nothing is
+/// appended if the delta fails validation.
+///
+/// # Errors
+///
+/// Returns the same errors as [`decode`].
+#[cfg(feature = "bytes")]
+pub fn decode_into_bytes_mut(
+    delta: &[u8],
+    base_data: &[u8],
+    output: &mut bytes::BytesMut,
+) -> Result<()> {
+    bytes_io::decode_into(delta, base_data, output)
+}
+
+/// Decodes as much of a possibly damaged delta as possible.
+///
+/// Unlike [`decode`], this never fails outright: it applies instructions
+/// until the first one that cannot be decoded and returns the output
+/// reconstructed so far, together with a report of where and why decoding
+/// stopped. A checksum mismatch is reported but does not stop decoding.
+///
+/// Useful for forensic recovery of partially corrupted or truncated patch
+/// files; for normal use prefer [`decode`], which rejects damaged input.
+///
+/// # Examples
+///
+/// ```
+/// use gdelta::{decode_lossy, encode};
+///
+/// let base = b"Hello, World!";
+/// let new = b"Hello, Rust!";
+///
+/// let delta = encode(new, base).unwrap();
+/// let result = decode_lossy(&delta[..delta.len() - 2], base);
+///
+/// assert!(!result.is_complete());
+/// assert!(new.starts_with(&result.output));
+/// ```
+pub fn decode_lossy(delta: &[u8], base_data: &[u8]) -> LossyDecode {
+    recovery::decode_lossy(delta, base_data)
+}
+
+/// Decodes a delta up to a breakpoint and returns the decoder state there.
+///
+/// Instructions are applied one at a time until the one selected by
+/// `breakpoint`, which is left unapplied, or until the delta ends or an
+/// instruction fails. The returned [`DecoderState`] holds the output so
+/// far, the instruction at the breakpoint and the ones applied just before
+/// it; its `Display` output is a dump to attach to a bug report about
+/// wrong or corrupted output. Checksums are not verified.
+///
+/// # Examples
+///
+/// ```
+/// use gdelta::{Breakpoint, decode_until, encode};
+///
+/// let base = b"The quick brown fox jumps over the lazy dog";
+/// let new = b"The quick brown cat jumps over the lazy dog";
+///
+/// let delta = encode(new, base).unwrap();
+/// let state = decode_until(&delta, base, Breakpoint::TargetOffset(17));
+///
+/// assert!(state.hit);
+/// assert_eq!(state.output, b"The quick brown ");
+/// println!("{state}");
+/// ```
+pub fn decode_until(delta: &[u8], base_data: &[u8], breakpoint: Breakpoint) -> DecoderState {
+    debug::decode_until(delta, base_data, breakpoint)
+}
+
+/// Decodes a delta from an untrusted source within `limits`.
+///
+/// Combines the checks a service needs before applying a patch it did not
+/// create: the delta size, a required checksum, the structure and copy
+/// bounds as checked by [`validate`], the target size and the instruction
+/// count are all checked before any output is allocated.
+///
+/// # Errors
+///
+/// Returns `GDeltaError::LimitExceeded` if the delta exceeds one of the
+/// `limits`, `GDeltaError::InvalidDelta` if a checksum is required but
+/// missing, and otherwise the same errors as [`decode`].
+///
+/// # Examples
+///
+/// ```
+/// use gdelta::{DecodeLimits, EncodeOptions, decode_untrusted, encode_with_options};
+///
+/// let base = b"Hello, World!";
+/// let options = EncodeOptions::new().checksum(true);
+/// let delta = encode_with_options(b"Hello, Rust!", base, &options).unwrap();
+///
+/// let output = decode_untrusted(&delta, base, &DecodeLimits::new()).unwrap();
+/// assert_eq!(output, b"Hello, Rust!");
+/// ```
+pub fn decode_untrusted(delta: &[u8], base_data: &[u8], limits: &DecodeLimits) -> Result<Vec<u8>> {
+    untrusted::decode_untrusted(delta, base_data, limits)
+}
+
+/// Checks that a delta can be applied to a base of `base_len` bytes,
+/// without the base data, and summarizes it.
+///
+/// Parses the framing and every instruction, verifies the checksum if the
+/// delta has one, and checks all copies against the base size and the
+/// target reconstructed so far, as well as the literal data and the stored
+/// target size. No output is produced, so servers can use it for cheap
+/// admission control before committing to a decode. A delta that passes
+/// decodes against any base of that length, unless it records a target or
+/// base checksum (see [`EncodeOptions::target_checksum`] and
+/// [`EncodeOptions::base_checksum`]) that only the right base matches.
+///
+/// # Errors
+///
+/// Returns the same errors as [`decode`] for a malformed delta.
+///
+/// # Examples
+///
+/// ```
+/// use gdelta::{encode, validate};
+///
+/// let base = b"The quick brown fox jumps over the lazy dog";
+/// let delta = encode(b"The quick brown fox jumps over the lazy cat", base).unwrap();
+///
+/// let summary = validate(&delta, base.len()).unwrap();
+/// assert_eq!(summary.target_size, 43);
+/// assert!(validate(&delta, 10).is_err());
+/// ```
+pub fn validate(delta: &[u8], base_len: usize) -> Result<DeltaSummary> {
+    summary::validate(delta, base_len)
+}
+
+/// Returns the format version a delta was written with.
+///
+/// Raw deltas, as produced by plain [`encode`] and by all releases before
+/// headers were introduced, report version 0. Storage layers can use this
+/// to check that a delta is readable before shipping it to older readers.
+///
+/// # Errors
+///
+/// Returns `GDeltaError::InvalidDelta` if the delta has a header with a
+/// version outside [`MIN_FORMAT_VERSION`]`..=`[`FORMAT_VERSION`] or malformed
+/// metadata. The payload and checksum are not examined.
+///
+/// # Examples
+///
+/// ```
+/// use gdelta::{EncodeOptions, encode, encode_with_options, format_version};
+///
+/// let base = b"Hello, World!";
+/// let new = b"Hello, Rust!";
+///
+/// assert_eq!(format_version(&encode(new, base).unwrap()).unwrap(), 0);
+///
+/// let options = EncodeOptions::new().checksum(true);
+/// let delta = encode_with_options(new, base, &options).unwrap();
+/// assert_eq!(format_version(&delta).unwrap(), gdelta::FORMAT_VERSION);
+/// ```
+pub fn format_version(delta: &[u8]) -> Result<u8> {
+    Ok(DeltaHeader::parse(delta)?.version)
+}
+
+/// Returns true if `delta` is the identity delta, which reproduces its base
+/// unchanged.
+///
+/// The encoders emit this canonical ten-byte delta (plus any checksum or
+/// target size that was requested) whenever the new data equals the base
+/// and the options call for a header, such as [`EncodeOptions::checksum`],
+/// so storage layers can recognize unchanged objects without decoding.
+/// Without a header, as from plain [`encode`], identical inputs become a
+/// single copy in the raw format that every release decodes. Malformed
+/// deltas and raw deltas are never identity deltas.
+///
+/// # Examples
+///
+/// ```
+/// use gdelta::{EncodeOptions, decode, encode, encode_with_options, is_identity};
+///
+/// let base = b"Hello, World!";
+/// let options = EncodeOptions::new().checksum(true);
+///
+/// let delta = encode_with_options(base, base, &options).unwrap();
+/// assert!(is_identity(&delta));
+/// assert_eq!(decode(&delta, base).unwrap(), base);
+///
+/// assert!(!is_identity(&encode(base, base).unwrap()));
+/// let changed = encode_with_options(b"Hello, Rust!", base, &options).unwrap();
+/// assert!(!is_identity(&changed));
+/// ```
+pub fn is_identity(delta: &[u8]) -> bool {
+    header::parse(delta)
+        .is_ok_and(|framed| framed.header.is_some_and(|header| header.is_identity()))
+}
+
+/// Returns the CRC-32 of the target recorded in `delta`, or `None` if it
+/// was encoded without [`EncodeOptions::target_checksum`].
+///
+/// Comparing it with the CRC-32 of an existing file, for instance computed
+/// through a [`ChecksumWriter`], tells whether the file already is the
+/// target without reading the base or decoding.
+///
+/// # Errors
+///
+/// Returns the same errors as [`format_version`].
+///
+/// # Examples
+///
+/// ```
+/// use gdelta::{ChecksumWriter, EncodeOptions, encode_with_options, target_checksum};
+/// use std::io::Write;
+///
+/// let base = b"Hello, World!";
+/// let new = b"Hello, Rust!";
+///
+/// let options = EncodeOptions::new().target_checksum(true);
+/// let delta = encode_with_options(new, base, &options).unwrap();
+///
+/// let mut writer = ChecksumWriter::new(std::io::sink());
+/// writer.write_all(new).unwrap();
+/// assert_eq!(target_checksum(&delta).unwrap(), Some(writer.checksum()));
+/// ```
+pub fn target_checksum(delta: &[u8]) -> Result<Option<u32>> {
+    Ok(DeltaHeader::parse(delta)?.target_checksum)
+}
+
+/// Returns the CRC-32 of the base recorded in `delta`, or `None` if it was
+/// encoded without [`EncodeOptions::base_checksum`].
+///
+/// With [`target_checksum`] it tells which file a delta applies to and
+/// which file it produces, so a set of deltas can be chained in order
+/// without relying on file names.
+///
+/// # Errors
+///
+/// Returns the same errors as [`format_version`].
+///
+/// # Examples
+///
+/// ```
+/// use gdelta::{EncodeOptions, base_checksum, encode_with_options, target_checksum};
+///
+/// let v1 = b"version = 1";
+/// let v2 = b"version = 2";
+/// let v3 = b"version = 3";
+///
+/// let options = EncodeOptions::new().base_checksum(true).target_checksum(true);
+/// let first = encode_with_options(v2, v1, &options).unwrap();
+/// let second = encode_with_options(v3, v2, &options).unwrap();
+/// assert_eq!(base_checksum(&second).unwrap(), target_checksum(&first).unwrap());
+/// ```
+pub fn base_checksum(delta: &[u8]) -> Result<Option<u32>> {
+    Ok(DeltaHeader::parse(delta)?.base_checksum)
+}
+
+/// Returns true if `candidate` already is the target `delta` reconstructs.
+///
+/// Judged by the target checksum and, if recorded, the target size in the
+/// delta header (see [`EncodeOptions::target_checksum`] and
+/// [`EncodeOptions::store_target_size`]), so orchestrators can detect an
+/// already patched file without keeping the base around. A size mismatch
+/// is detected without reading `candidate`.
+///
+/// Returns false if the delta records no target checksum or is malformed,
+/// since then nothing can be said about the candidate.
+///
+/// # Examples
+///
+/// ```
+/// use gdelta::{EncodeOptions, encode_with_options, is_applied};
+///
+/// let base = b"version = 1";
+/// let new = b"version = 2";
+///
+/// let options = EncodeOptions::new().target_checksum(true);
+/// let delta = encode_with_options(new, base, &options).unwrap();
+/// assert!(is_applied(&delta, new));
+/// assert!(!is_applied(&delta, base));
+/// ```
+pub fn is_applied(delta: &[u8], candidate: &[u8]) -> bool {
+    let Ok(header) = DeltaHeader::parse(delta) else {
+        return false;
+    };
+    match header.target_checksum {
+        Some(expected) => {
+            header
+                .target_size
+                .is_none_or(|size| size == candidate.len() as u64)
+                && checksum::crc32(candidate) == expected
+        }
+        None => false,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
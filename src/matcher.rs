@@ -0,0 +1,943 @@
+//! Match finding strategies used by the encoder.
+//!
+//! The encoder handles the common prefix and suffix itself and delegates the
+//! search for copies in the remaining middle section to a [`Matcher`]. The
+//! default [`GearMatcher`] indexes the base with the GEAR rolling hash;
+//! alternative strategies can be plugged in through [`crate::encode_with_matcher`]
+//! without touching the instruction encoding.
+//!
+//! # Examples
+//!
+//! A matcher that only looks for unchanged bytes at the same offset:
+//!
+//! ```
+//! use gdelta::matcher::{Match, Matcher};
+//!
+//! struct SameOffset;
+//!
+//! impl Matcher for SameOffset {
+//!     fn index(&mut self, _base: &[u8], _start: usize, _end: usize) {}
+//!
+//!     fn find_match(&mut self, target: &[u8], base: &[u8], pos: usize) -> Option<Match> {
+//!         let length = target[pos..]
+//!             .iter()
+//!             .zip(base.get(pos..)?)
+//!             .take_while(|(a, b)| a == b)
+//!             .count();
+//!         (length >= 8).then_some(Match { base_offset: pos, length })
+//!     }
+//! }
+//!
+//! let base = b"0123456789abcdefghij0123456789";
+//! let new = b"0123456789ABCDEFGHIJ0123456789";
+//!
+//! let delta = gdelta::encode_with_matcher(new, base, &mut SameOffset).unwrap();
+//! assert_eq!(gdelta::decode(&delta, base).unwrap(), new);
+//! ```
+
+use crate::gear::{
+    BASE_SAMPLE_RATE, HashTable, WORD_SIZE, build_hash_table, compute_fingerprint,
+    roll_fingerprint, tag,
+};
+use crate::hints::EncodeHints;
+use crate::stats::TableCounters;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Smallest hash table a memory budget can shrink the index to, in bits.
+const MIN_HASH_BITS: u32 = 8;
+
+/// Largest sampling stride a memory budget can raise the index to.
+const MAX_SAMPLE_RATE: usize = 64;
+
+/// Sampling stride of the base index behind hints, which find most copies.
+pub(crate) const HINTED_SAMPLE_RATE: usize = 16;
+
+/// A region of the target that can be copied from the base.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Match {
+    /// Offset of the matching bytes in the base data.
+    pub base_offset: usize,
+    /// Number of matching bytes.
+    pub length: usize,
+}
+
+/// Finds copies of target data in the base data.
+///
+/// The encoder calls [`Matcher::index`] once per encode, then
+/// [`Matcher::find_match`] for target positions in increasing order. Positions
+/// advance by one after a miss and jump past the match after a hit, which
+/// allows rolling state such as hashes to be carried between calls. Some
+/// [`crate::Preset`]s skip further ahead after long runs of misses.
+pub trait Matcher {
+    /// Prepares the matcher to search `base[start..end]`.
+    ///
+    /// Bytes outside this range are already covered by the encoder's prefix
+    /// and suffix handling.
+    fn index(&mut self, base: &[u8], start: usize, end: usize);
+
+    /// Returns a match for the target bytes starting at `pos`, if any.
+    ///
+    /// `target` and `base` are truncated to the region being encoded, so any
+    /// match must satisfy `target[pos..pos + length] == base[base_offset..base_offset + length]`.
+    /// Matches that fall outside either slice are ignored by the encoder.
+    fn find_match(&mut self, target: &[u8], base: &[u8], pos: usize) -> Option<Match>;
+}
+
+impl<M: Matcher + ?Sized> Matcher for &mut M {
+    fn index(&mut self, base: &[u8], start: usize, end: usize) {
+        (**self).index(base, start, end);
+    }
+
+    fn find_match(&mut self, target: &[u8], base: &[u8], pos: usize) -> Option<Match> {
+        (**self).find_match(target, base, pos)
+    }
+}
+
+/// The default matcher, based on a sampled GEAR hash table of the base.
+///
+/// Every few positions of the base are inserted into a hash table keyed by
+/// the fingerprint of the following [`WORD_SIZE`] bytes. Target windows are
+/// looked up with a rolling fingerprint and verified before being extended.
+///
+/// The index is shared between clones, so an indexed matcher can be cloned
+/// cheaply to search several parts of a target at once.
+#[derive(Debug, Clone)]
+pub struct GearMatcher {
+    memory_budget: Option<usize>,
+    sample_rate: usize,
+    word_size: usize,
+    huge_pages: bool,
+    hash_tags: bool,
+    alignment: usize,
+    /// Shared with clones; `None` until the base is indexed.
+    hash_table: Option<HashTable>,
+    hash_shift: u32,
+    fingerprint: u64,
+    next_pos: usize,
+    /// Where the table diagnostics go, shared with clones, if collected.
+    counters: Option<Arc<TableCounters>>,
+}
+
+impl GearMatcher {
+    /// Creates a matcher with an empty index.
+    pub fn new() -> Self {
+        Self {
+            memory_budget: None,
+            sample_rate: BASE_SAMPLE_RATE,
+            word_size: WORD_SIZE,
+            huge_pages: false,
+            hash_tags: false,
+            alignment: 1,
+            hash_table: None,
+            hash_shift: 0,
+            fingerprint: 0,
+            next_pos: usize::MAX,
+            counters: None,
+        }
+    }
+
+    /// Creates a matcher whose index stays within `bytes` of memory.
+    ///
+    /// When the base is too large for the budget, the hash table is shrunk
+    /// and base positions are sampled more sparsely instead. Short matches
+    /// may then be missed, so deltas get larger, but encoding still succeeds.
+    /// The table never shrinks below 1 KiB.
+    pub fn with_memory_budget(bytes: usize) -> Self {
+        Self {
+            memory_budget: Some(bytes),
+            ..Self::new()
+        }
+    }
+
+    /// Requires matches to start with at least `size` equal bytes.
+    ///
+    /// The fingerprint always covers [`WORD_SIZE`] bytes; longer anchors are
+    /// verified before a match is reported. The base is then sampled at
+    /// intervals of at least `size - 7` bytes, which keeps the index small, but a
+    /// match is only found where the target lines up with a sampled base
+    /// position, so some matches of `size` bytes are missed.
+    pub(crate) fn word_size(mut self, size: usize) -> Self {
+        self.word_size = size.max(WORD_SIZE);
+        self.sample_rate = self.sample_rate.max(self.word_size - WORD_SIZE + 1);
+        self
+    }
+
+    /// Inserts every `rate`-th base position into the index.
+    pub(crate) fn sample_rate(mut self, rate: usize) -> Self {
+        self.sample_rate = rate.max(1);
+        self
+    }
+
+    /// Aligns large hash tables to huge pages; see
+    /// [`crate::EncodeOptions::huge_pages`].
+    pub(crate) fn huge_pages(mut self, enabled: bool) -> Self {
+        self.huge_pages = enabled;
+        self
+    }
+
+    /// Checks a 16-bit tag of the fingerprint before comparing bytes; see
+    /// [`crate::EncodeOptions::hash_tags`].
+    pub(crate) fn hash_tags(mut self, enabled: bool) -> Self {
+        self.hash_tags = enabled;
+        self
+    }
+
+    /// Only indexes base positions that are multiples of `bytes`; see
+    /// [`crate::EncodeOptions::match_alignment`].
+    pub(crate) fn alignment(mut self, bytes: usize) -> Self {
+        self.alignment = bytes.max(1);
+        self
+    }
+
+    /// Adds one to a diagnostics counter, if they are collected.
+    #[inline]
+    fn count(&self, counter: fn(&TableCounters) -> &AtomicU64) {
+        if let Some(counters) = &self.counters {
+            counter(counters).fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Default for GearMatcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Matcher for GearMatcher {
+    fn index(&mut self, base: &[u8], start: usize, end: usize) {
+        let mut hash_bits = calculate_hash_bits(end - start);
+        let mut sample_rate = self.sample_rate;
+        let entry_size = if self.hash_tags {
+            std::mem::size_of::<u32>() + std::mem::size_of::<u16>()
+        } else {
+            std::mem::size_of::<u32>()
+        };
+        if let Some(budget) = self.memory_budget {
+            // Halve the table and double the stride until it fits
+            while hash_bits > MIN_HASH_BITS && (entry_size << hash_bits) > budget {
+                hash_bits -= 1;
+                sample_rate = (sample_rate * 2).min(MAX_SAMPLE_RATE);
+            }
+        }
+        // Sample aligned positions only
+        let start = start.next_multiple_of(self.alignment).min(end);
+        let sample_rate = sample_rate.next_multiple_of(self.alignment);
+        self.hash_table = Some(build_hash_table(
+            base,
+            start,
+            end,
+            hash_bits,
+            sample_rate,
+            self.huge_pages,
+            self.hash_tags,
+        ));
+        self.hash_shift = 64 - hash_bits;
+        self.next_pos = usize::MAX;
+        if let (Some(counters), Some(table)) = (&self.counters, &self.hash_table) {
+            counters.record_index(hash_bits, sample_rate, table);
+        }
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn find_match(&mut self, target: &[u8], base: &[u8], pos: usize) -> Option<Match> {
+        if pos + WORD_SIZE > target.len() {
+            return None;
+        }
+
+        // Roll the fingerprint after a miss, recompute after a jump
+        self.fingerprint = if pos == self.next_pos {
+            roll_fingerprint(self.fingerprint, target[pos + WORD_SIZE - 1])
+        } else {
+            compute_fingerprint(target, pos)
+        };
+        self.next_pos = pos + 1;
+
+        // Look up in hash table
+        let hash_index = (self.fingerprint >> self.hash_shift) as usize;
+        let hash_table = self.hash_table.as_ref()?;
+        let base_offset = hash_table.get(hash_index)? as usize;
+        self.count(|counters| &counters.lookups);
+
+        // A differing tag rules the entry out without reading the base
+        let tag_differs = hash_table
+            .tag(hash_index)
+            .is_some_and(|stored| stored != tag(self.fingerprint, self.hash_shift));
+
+        // Check if we have a match of at least one anchor
+        let word = self.word_size;
+        if base_offset > 0
+            && !tag_differs
+            && base_offset
+                .checked_add(word)
+                .is_some_and(|end| end <= base.len())
+            && pos.checked_add(word).is_some_and(|end| end <= target.len())
+            && target[pos..pos + word] == base[base_offset..base_offset + word]
+        {
+            self.count(|counters| &counters.hits);
+            let length = extend_match(target, base, pos, base_offset, target.len(), base.len());
+            return Some(Match {
+                base_offset,
+                length,
+            });
+        }
+
+        if base_offset > 0 {
+            self.count(|counters| &counters.false_candidates);
+        }
+        None
+    }
+}
+
+/// A matcher for text that matches whole lines before falling back to bytes.
+///
+/// Every line of the base is indexed by a hash of its contents. At the start
+/// of each target line, the matcher first tries to continue the previous
+/// match, then looks the line up in the index; only if neither works does it
+/// search byte-wise with a [`GearMatcher`]. Since edits to source code and
+/// logs are usually line-aligned, this keeps copies in step with the lines
+/// and finds short lines the byte-wise search would miss.
+///
+/// Lines end after the delimiter byte, which is `\n` unless set with
+/// [`LineMatcher::with_delimiter`].
+///
+/// # Examples
+///
+/// ```
+/// use gdelta::matcher::LineMatcher;
+///
+/// let base = b"fn main() {\n    let x = 1;\n    println!(\"{x}\");\n}\n";
+/// let new = b"fn main() {\n    let x = 2;\n    println!(\"{x}\");\n}\n";
+///
+/// let delta = gdelta::encode_with_matcher(new, base, &mut LineMatcher::new()).unwrap();
+/// assert_eq!(gdelta::decode(&delta, base).unwrap(), new);
+/// ```
+#[derive(Debug, Clone)]
+pub struct LineMatcher {
+    delimiter: u8,
+    /// First base offset of each line, keyed by the hash of its contents.
+    lines: Arc<HashMap<u64, usize>>,
+    /// Base offset just past the previous match.
+    next_base: usize,
+    /// Whether matches are cut back to end at a record boundary.
+    align_matches: bool,
+    fallback: GearMatcher,
+}
+
+impl LineMatcher {
+    /// Creates a matcher for newline-terminated lines.
+    pub fn new() -> Self {
+        Self::with_delimiter(b'\n')
+    }
+
+    /// Creates a matcher for records terminated by `delimiter`.
+    pub fn with_delimiter(delimiter: u8) -> Self {
+        Self::with_fallback(delimiter, GearMatcher::new())
+    }
+
+    /// Creates a matcher that searches byte-wise with `fallback`.
+    pub(crate) fn with_fallback(delimiter: u8, fallback: GearMatcher) -> Self {
+        Self {
+            delimiter,
+            lines: Arc::default(),
+            next_base: 0,
+            align_matches: false,
+            fallback,
+        }
+    }
+
+    /// Ends matches at the last record boundary they contain.
+    ///
+    /// The next search then starts at a record, where the whole-record
+    /// lookup can pick up the right alignment again, so an inserted record
+    /// does not leave the following copies out of step.
+    pub(crate) fn align_matches(mut self, enabled: bool) -> Self {
+        self.align_matches = enabled;
+        self
+    }
+
+    /// Applies record alignment to a match found at `pos`.
+    fn aligned(&self, target: &[u8], pos: usize, mut found: Match) -> Match {
+        let end = pos + found.length;
+        if self.align_matches
+            && end < target.len()
+            && let Some(last) = target[pos..end]
+                .iter()
+                .rposition(|&byte| byte == self.delimiter)
+        {
+            found.length = last + 1;
+        }
+        found
+    }
+
+    /// Returns the end of the line starting at `start`, including its delimiter.
+    fn line_end(&self, data: &[u8], start: usize) -> usize {
+        data[start..]
+            .iter()
+            .position(|&byte| byte == self.delimiter)
+            .map_or(data.len(), |at| start + at + 1)
+    }
+
+    /// Returns a base offset where `line` occurs, preferring the
+    /// continuation of the previous match.
+    fn find_line(&self, base: &[u8], line: &[u8]) -> Option<usize> {
+        if base
+            .get(self.next_base..)
+            .is_some_and(|rest| rest.starts_with(line))
+        {
+            return Some(self.next_base);
+        }
+        let offset = *self.lines.get(&hash_line(line))?;
+        base[offset..].starts_with(line).then_some(offset)
+    }
+}
+
+impl Default for LineMatcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Matcher for LineMatcher {
+    fn index(&mut self, base: &[u8], start: usize, end: usize) {
+        self.fallback.index(base, start, end);
+        self.next_base = start;
+        let mut lines = HashMap::new();
+
+        let base = &base[..end];
+        let mut line_start = start;
+        // Skip the partial line the range starts in
+        if start > 0 && base[start - 1] != self.delimiter {
+            line_start = self.line_end(base, start);
+        }
+        while line_start < end {
+            let line_end = self.line_end(base, line_start);
+            lines
+                .entry(hash_line(&base[line_start..line_end]))
+                .or_insert(line_start);
+            line_start = line_end;
+        }
+        self.lines = Arc::new(lines);
+    }
+
+    fn find_match(&mut self, target: &[u8], base: &[u8], pos: usize) -> Option<Match> {
+        if pos == 0 || target[pos - 1] == self.delimiter {
+            let line = &target[pos..self.line_end(target, pos)];
+            if let Some(base_offset) = self.find_line(base, line) {
+                // Keep going past the line for as long as the data agrees
+                let length = target[pos..]
+                    .iter()
+                    .zip(&base[base_offset..])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                let found = self.aligned(
+                    target,
+                    pos,
+                    Match {
+                        base_offset,
+                        length,
+                    },
+                );
+                self.next_base = found.base_offset + found.length;
+                return Some(found);
+            }
+        }
+
+        let found = self.fallback.find_match(target, base, pos)?;
+        let found = self.aligned(target, pos, found);
+        self.next_base = found.base_offset + found.length;
+        Some(found)
+    }
+}
+
+/// Hashes the contents of a line with FNV-1a.
+fn hash_line(line: &[u8]) -> u64 {
+    line.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// A matcher that tries the copies of an earlier delta before searching.
+///
+/// At each position, the copy of the [`EncodeHints`] covering the same
+/// offset of the earlier target is tried first. Only where its base bytes
+/// differ is the fallback matcher asked, and every match the fallback finds
+/// inside a hinted base region moves the hints into step with the new
+/// target, so insertions and deletions between the two targets do not
+/// throw them off.
+///
+/// Since the fallback only has to find what changed, the default one is a
+/// [`GearMatcher`] indexing every 16th base position instead of every 3rd,
+/// which makes encoding about twice as fast.
+///
+/// # Examples
+///
+/// ```
+/// use gdelta::EncodeHints;
+/// use gdelta::matcher::HintedMatcher;
+///
+/// let base = b"header|".repeat(200);
+/// let mut v2 = base.clone();
+/// v2.splice(700..700, *b"new record|");
+/// let mut v3 = v2.clone();
+/// v3.splice(0..0, *b"v3|");
+///
+/// let hints = EncodeHints::from_delta(&gdelta::encode(&v2, &base)?, base.len())?;
+/// let delta = gdelta::encode_with_matcher(&v3, &base, &mut HintedMatcher::new(hints))?;
+/// assert_eq!(gdelta::decode(&delta, &base)?, v3);
+/// # Ok::<(), gdelta::GDeltaError>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct HintedMatcher<M = GearMatcher> {
+    hints: EncodeHints,
+    /// Offset of the new target relative to the hinted one.
+    shift: isize,
+    /// Index of the hinted copy tried last.
+    cursor: usize,
+    fallback: M,
+}
+
+impl HintedMatcher {
+    /// Creates a matcher trying `hints` before a sparsely sampled
+    /// [`GearMatcher`].
+    pub fn new(hints: EncodeHints) -> Self {
+        Self::with_fallback(hints, GearMatcher::new().sample_rate(HINTED_SAMPLE_RATE))
+    }
+}
+
+impl<M: Matcher> HintedMatcher<M> {
+    /// Creates a matcher trying `hints` before `fallback`.
+    pub fn with_fallback(hints: EncodeHints, fallback: M) -> Self {
+        Self {
+            hints,
+            shift: 0,
+            cursor: 0,
+            fallback,
+        }
+    }
+
+    /// Returns the match the hinted copy covering `pos` suggests, if its
+    /// bytes agree for at least one word.
+    fn hinted(&mut self, target: &[u8], base: &[u8], pos: usize) -> Option<Match> {
+        let hinted_pos = pos.checked_add_signed(-self.shift)?;
+        let copies = &self.hints.copies;
+        if !copies
+            .get(self.cursor)
+            .is_some_and(|copy| copy.covers_target(hinted_pos))
+        {
+            self.cursor = copies.partition_point(|copy| copy.target + copy.length <= hinted_pos);
+        }
+        let copy = copies
+            .get(self.cursor)
+            .filter(|copy| copy.covers_target(hinted_pos))?;
+        let base_offset = copy.base + (hinted_pos - copy.target);
+        if pos + WORD_SIZE > target.len()
+            || base_offset + WORD_SIZE > base.len()
+            || target[pos..pos + WORD_SIZE] != base[base_offset..base_offset + WORD_SIZE]
+        {
+            return None;
+        }
+        let length = extend_match(target, base, pos, base_offset, target.len(), base.len());
+        Some(Match {
+            base_offset,
+            length,
+        })
+    }
+
+    /// Lines the hints up with a match at `pos` found by the fallback, if a
+    /// hinted copy read the same base bytes.
+    fn resync(&mut self, pos: usize, base_offset: usize) {
+        let copies = &self.hints.copies;
+        let by_base = &self.hints.by_base;
+        let after = by_base.partition_point(|&index| copies[index].base <= base_offset);
+        if let Some(&index) = after.checked_sub(1).map(|last| &by_base[last])
+            && copies[index].covers_base(base_offset)
+        {
+            let hinted_pos = copies[index].target + (base_offset - copies[index].base);
+            self.shift = pos as isize - hinted_pos as isize;
+            self.cursor = index;
+        }
+    }
+}
+
+impl<M: Matcher> Matcher for HintedMatcher<M> {
+    fn index(&mut self, base: &[u8], start: usize, end: usize) {
+        self.fallback.index(base, start, end);
+        self.shift = 0;
+        self.cursor = 0;
+    }
+
+    fn find_match(&mut self, target: &[u8], base: &[u8], pos: usize) -> Option<Match> {
+        if let Some(found) = self.hinted(target, base, pos) {
+            return Some(found);
+        }
+        let found = self.fallback.find_match(target, base, pos)?;
+        self.resync(pos, found.base_offset);
+        Some(found)
+    }
+}
+
+/// The matcher selected by [`crate::EncodeOptions`].
+#[derive(Debug, Clone)]
+pub(crate) enum ConfiguredMatcher {
+    Gear(GearMatcher),
+    Line(LineMatcher),
+    Hinted(Box<HintedMatcher<ConfiguredMatcher>>),
+}
+
+impl ConfiguredMatcher {
+    /// Collects the diagnostics of the hash table searching the base into
+    /// `counters`.
+    pub(crate) fn collect_table_stats(&mut self, counters: &Arc<TableCounters>) {
+        match self {
+            ConfiguredMatcher::Gear(matcher) => matcher.counters = Some(Arc::clone(counters)),
+            ConfiguredMatcher::Line(matcher) => {
+                matcher.fallback.counters = Some(Arc::clone(counters));
+            }
+            ConfiguredMatcher::Hinted(matcher) => matcher.fallback.collect_table_stats(counters),
+        }
+    }
+}
+
+impl Matcher for ConfiguredMatcher {
+    fn index(&mut self, base: &[u8], start: usize, end: usize) {
+        match self {
+            ConfiguredMatcher::Gear(matcher) => matcher.index(base, start, end),
+            ConfiguredMatcher::Line(matcher) => matcher.index(base, start, end),
+            ConfiguredMatcher::Hinted(matcher) => matcher.index(base, start, end),
+        }
+    }
+
+    fn find_match(&mut self, target: &[u8], base: &[u8], pos: usize) -> Option<Match> {
+        match self {
+            ConfiguredMatcher::Gear(matcher) => matcher.find_match(target, base, pos),
+            ConfiguredMatcher::Line(matcher) => matcher.find_match(target, base, pos),
+            ConfiguredMatcher::Hinted(matcher) => matcher.find_match(target, base, pos),
+        }
+    }
+}
+
+/// Minimum length of a copy from earlier in the target.
+///
+/// Target copies are addressed past the end of the base, so their offsets
+/// are large; shorter ones rarely beat a literal.
+const MIN_TARGET_MATCH: usize = 2 * WORD_SIZE;
+
+/// Index of the already scanned part of the target, used to find data that
+/// repeats within the target itself.
+///
+/// Positions are inserted as the encoder passes them, so every match found
+/// lies entirely before the position being encoded.
+#[derive(Debug, Clone)]
+pub(crate) struct TargetMatcher {
+    /// Target position plus one, or zero for an empty slot.
+    hash_table: Vec<u32>,
+    hash_shift: u32,
+}
+
+impl TargetMatcher {
+    /// Creates an empty index sized for a target of `target_len` bytes.
+    pub(crate) fn new(target_len: usize) -> Self {
+        let hash_bits = calculate_hash_bits(target_len);
+        Self {
+            hash_table: vec![0; 1 << hash_bits],
+            hash_shift: 64 - hash_bits,
+        }
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn slot(&self, target: &[u8], pos: usize) -> usize {
+        (compute_fingerprint(target, pos) >> self.hash_shift) as usize
+    }
+
+    /// Records the window starting at `pos`.
+    #[allow(clippy::cast_possible_truncation)]
+    pub(crate) fn insert(&mut self, target: &[u8], pos: usize) {
+        if pos + WORD_SIZE <= target.len() && pos < u32::MAX as usize {
+            let slot = self.slot(target, pos);
+            self.hash_table[slot] = pos as u32 + 1;
+        }
+    }
+
+    /// Returns an earlier occurrence of the bytes at `pos`, extended up to
+    /// `end`. The match may run past `pos` into the bytes it reconstructs,
+    /// so a run of a repeated pattern is a single match one period back.
+    /// The match's `base_offset` is the target position it was found at.
+    pub(crate) fn find_match(&self, target: &[u8], pos: usize, end: usize) -> Option<Match> {
+        if pos + MIN_TARGET_MATCH > end {
+            return None;
+        }
+        let source = (*self.hash_table.get(self.slot(target, pos))? as usize)
+            .checked_sub(1)
+            .filter(|&source| source < pos)?;
+
+        let length = target[source..end]
+            .iter()
+            .zip(&target[pos..end])
+            .take_while(|(a, b)| a == b)
+            .count();
+        (length >= MIN_TARGET_MATCH).then_some(Match {
+            base_offset: source,
+            length,
+        })
+    }
+}
+
+/// Calculates the number of hash bits based on data size.
+fn calculate_hash_bits(size: usize) -> u32 {
+    let mut bits = 0u32;
+    let mut temp = size + 10;
+    while temp > 0 {
+        bits += 1;
+        temp >>= 1;
+    }
+    bits
+}
+
+/// Extends a match as far as possible.
+fn extend_match(
+    new_data: &[u8],
+    base_data: &[u8],
+    new_pos: usize,
+    base_pos: usize,
+    new_end: usize,
+    base_end: usize,
+) -> usize {
+    let mut len = WORD_SIZE;
+
+    #[cfg(feature = "simd")]
+    {
+        use wide::u8x16;
+
+        // Extend in 16-byte chunks with SIMD
+        while new_pos + len + 16 <= new_end && base_pos + len + 16 <= base_end {
+            let new_chunk = u8x16::new(
+                new_data[new_pos + len..new_pos + len + 16]
+                    .try_into()
+                    .unwrap(),
+            );
+            let base_chunk = u8x16::new(
+                base_data[base_pos + len..base_pos + len + 16]
+                    .try_into()
+                    .unwrap(),
+            );
+
+            if new_chunk != base_chunk {
+                break;
+            }
+            len += 16;
+        }
+    }
+
+    // Extend in 8-byte chunks
+    while new_pos + len + 8 <= new_end && base_pos + len + 8 <= base_end {
+        let new_chunk = u64::from_le_bytes(
+            new_data[new_pos + len..new_pos + len + 8]
+                .try_into()
+                .unwrap(),
+        );
+        let base_chunk = u64::from_le_bytes(
+            base_data[base_pos + len..base_pos + len + 8]
+                .try_into()
+                .unwrap(),
+        );
+        if new_chunk != base_chunk {
+            break;
+        }
+        len += 8;
+    }
+
+    // Extend byte by byte
+    while new_pos + len < new_end
+        && base_pos + len < base_end
+        && new_data[new_pos + len] == base_data[base_pos + len]
+    {
+        len += 1;
+    }
+
+    len
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gear::HUGE_PAGE_SIZE;
+
+    #[test]
+    fn test_gear_matcher_finds_shifted_block() {
+        let base: Vec<u8> = (0..=255).cycle().take(4096).collect();
+        let mut target = b"inserted".to_vec();
+        target.extend_from_slice(&base[1000..2000]);
+
+        let mut matcher = GearMatcher::new();
+        matcher.index(&base, 0, base.len());
+
+        let found = (0..target.len())
+            .find_map(|pos| matcher.find_match(&target, &base, pos).map(|m| (pos, m)))
+            .unwrap();
+
+        let (pos, m) = found;
+        assert_eq!(
+            target[pos..pos + m.length],
+            base[m.base_offset..m.base_offset + m.length]
+        );
+        assert!(m.length >= WORD_SIZE);
+    }
+
+    #[test]
+    fn test_hinted_matcher_follows_hints() {
+        struct Counting(GearMatcher, usize);
+
+        impl Matcher for Counting {
+            fn index(&mut self, base: &[u8], start: usize, end: usize) {
+                self.0.index(base, start, end);
+            }
+
+            fn find_match(&mut self, target: &[u8], base: &[u8], pos: usize) -> Option<Match> {
+                self.1 += 1;
+                self.0.find_match(target, base, pos)
+            }
+        }
+
+        let mut seed = 3u32;
+        let base: Vec<u8> = (0..100_000)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (seed >> 16) as u8
+            })
+            .collect();
+        let mut v2 = base.clone();
+        let mut v3 = base.clone();
+        for i in 1..6 {
+            v2[i * 15_000..i * 15_000 + 10].fill(2);
+            v3[i * 15_000..i * 15_000 + 10].fill(2);
+            v3[i * 16_500..i * 16_500 + 10].fill(3);
+        }
+        v3.splice(0..0, *b"v3|");
+
+        let hints =
+            EncodeHints::from_delta(&crate::encode(&v2, &base).unwrap(), base.len()).unwrap();
+        let mut fallback = Counting(GearMatcher::new(), 0);
+        let mut matcher = HintedMatcher::with_fallback(hints, &mut fallback);
+        let delta = crate::encode_with_matcher(&v3, &base, &mut matcher).unwrap();
+        assert_eq!(crate::decode(&delta, &base).unwrap(), v3);
+        assert!(fallback.1 < 500, "{} fallback searches", fallback.1);
+
+        // Unrelated hints only cost time
+        let unrelated =
+            EncodeHints::from_delta(&crate::encode(&base[50_000..], &base).unwrap(), base.len())
+                .unwrap();
+        let delta =
+            crate::encode_with_matcher(&v3, &base, &mut HintedMatcher::new(unrelated)).unwrap();
+        assert_eq!(crate::decode(&delta, &base).unwrap(), v3);
+    }
+
+    #[test]
+    fn test_gear_matcher_without_index() {
+        let mut matcher = GearMatcher::new();
+        assert_eq!(matcher.find_match(b"0123456789", b"0123456789", 0), None);
+    }
+
+    #[test]
+    fn test_huge_pages_align_large_tables() {
+        let base: Vec<u8> = (0..600_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let mut matcher = GearMatcher::new().huge_pages(true);
+        matcher.index(&base, 0, base.len());
+        let table = matcher.hash_table.as_ref().unwrap();
+        assert!(table.len() * 4 >= HUGE_PAGE_SIZE);
+        assert_eq!(table.address() % HUGE_PAGE_SIZE, 0);
+
+        let mut new = base.clone();
+        new[300_000..300_010].copy_from_slice(b"0123456789");
+        let options = crate::EncodeOptions::new().huge_pages(true);
+        assert_eq!(
+            crate::encode_with_options(&new, &base, &options).unwrap(),
+            crate::encode(&new, &base).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_hash_tags_keep_output() {
+        let noise: Vec<u8> = (0..200_000u64)
+            .map(|i| (i.wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 56) as u8)
+            .collect();
+        let text: Vec<u8> = (0..20_000u32)
+            .flat_map(|i| format!("entry {i} = {}\n", i % 97).into_bytes())
+            .collect();
+        for base in [noise, text] {
+            let mut new = base.clone();
+            new[1_000..1_010].copy_from_slice(b"0123456789");
+            new.rotate_left(base.len() / 3);
+            for options in [
+                crate::EncodeOptions::new(),
+                crate::EncodeOptions::new().word_size(32),
+            ] {
+                let tagged = options.clone().hash_tags(true);
+                assert_eq!(
+                    crate::encode_with_options(&new, &base, &tagged).unwrap(),
+                    crate::encode_with_options(&new, &base, &options).unwrap()
+                );
+            }
+        }
+
+        // The tags count against the memory budget
+        let base: Vec<u8> = (0..=255).cycle().take(100_000).collect();
+        let mut matcher = GearMatcher::with_memory_budget(16 * 1024).hash_tags(true);
+        matcher.index(&base, 0, base.len());
+        assert!(matcher.hash_table.as_ref().unwrap().len() * 6 <= 16 * 1024);
+    }
+
+    #[test]
+    fn test_memory_budget_limits_table() {
+        let base: Vec<u8> = (0..=255).cycle().take(100_000).collect();
+
+        let mut matcher = GearMatcher::with_memory_budget(16 * 1024);
+        matcher.index(&base, 0, base.len());
+        assert!(matcher.hash_table.as_ref().unwrap().len() * 4 <= 16 * 1024);
+
+        let mut matcher = GearMatcher::with_memory_budget(0);
+        matcher.index(&base, 0, base.len());
+        assert_eq!(
+            matcher.hash_table.as_ref().unwrap().len(),
+            1 << MIN_HASH_BITS
+        );
+    }
+
+    #[test]
+    fn test_target_matcher_finds_repeat() {
+        let mut target = b"a block that is inserted twice, ".to_vec();
+        target.extend_from_slice(b"then other bytes, then ");
+        target.extend_from_slice(b"a block that is inserted twice, ");
+
+        let mut matcher = TargetMatcher::new(target.len());
+        let found = (0..target.len()).find_map(|pos| {
+            let found = matcher.find_match(&target, pos, target.len());
+            matcher.insert(&target, pos);
+            found.map(|m| (pos, m))
+        });
+
+        let (pos, m) = found.unwrap();
+        assert_eq!(pos, 55);
+        assert_eq!(m.base_offset, 0);
+        assert_eq!(m.length, 32);
+    }
+
+    #[test]
+    fn test_word_size_requires_longer_anchor() {
+        let base: Vec<u8> = (0..=255).collect();
+        let mut target = base[10..22].to_vec();
+        target.extend_from_slice(b"----");
+        target.extend_from_slice(&base[100..140]);
+
+        let mut matcher = GearMatcher::new().word_size(16);
+        matcher.index(&base, 0, base.len());
+        let found: Vec<_> = (0..target.len())
+            .filter_map(|pos| matcher.find_match(&target, &base, pos).map(|m| (pos, m)))
+            .collect();
+
+        assert!(!found.is_empty());
+        assert!(found.iter().all(|(pos, m)| *pos >= 16 && m.length >= 16));
+    }
+}
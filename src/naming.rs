@@ -0,0 +1,135 @@
+//! Content-addressed names for stored deltas.
+//!
+//! A delta is identified by the content it connects: the BLAKE3 hashes of
+//! its base and target. [`delta_name`] turns them into a file name of the
+//! form `<basehash>-<targethash>.gd`, so every producer names the same delta
+//! the same way, re-encoding never creates duplicates, and a store can find
+//! the delta from a given file to a given file without an index.
+//!
+//! # Examples
+//!
+//! ```
+//! use gdelta::naming::{ContentHash, delta_name, parse_delta_name};
+//!
+//! let base = b"version = 1";
+//! let new = b"version = 2";
+//!
+//! let name = delta_name(base, new);
+//! assert!(name.ends_with(".gd"));
+//! assert_eq!(
+//!     parse_delta_name(&name),
+//!     Some((ContentHash::of(base), ContentHash::of(new)))
+//! );
+//! ```
+
+use std::fmt;
+use std::io::{self, Read};
+
+/// File extension of content-addressed delta names, without the dot.
+pub const EXTENSION: &str = "gd";
+
+/// BLAKE3 hash of some content, shown as 64 lowercase hex digits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ContentHash([u8; 32]);
+
+impl ContentHash {
+    /// Hashes `data`.
+    pub fn of(data: &[u8]) -> Self {
+        Self(*blake3::hash(data).as_bytes())
+    }
+
+    /// Hashes everything `reader` yields, without holding it in memory.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error from reading.
+    pub fn from_reader<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut hasher = blake3::Hasher::new();
+        io::copy(&mut reader, &mut hasher)?;
+        Ok(Self(*hasher.finalize().as_bytes()))
+    }
+
+    /// Parses a hash from 64 hex digits in either case.
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        blake3::Hash::from_hex(hex)
+            .ok()
+            .map(|hash| Self(*hash.as_bytes()))
+    }
+
+    /// Returns the raw hash bytes.
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl fmt::Display for ContentHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the content-addressed file name of the delta from `base` to
+/// `target`, `<basehash>-<targethash>.gd`.
+pub fn delta_name(base: &[u8], target: &[u8]) -> String {
+    delta_name_for(&ContentHash::of(base), &ContentHash::of(target))
+}
+
+/// Like [`delta_name`], for hashes computed beforehand, for instance with
+/// [`ContentHash::from_reader`].
+pub fn delta_name_for(base: &ContentHash, target: &ContentHash) -> String {
+    format!("{base}-{target}.{EXTENSION}")
+}
+
+/// Splits a name produced by [`delta_name`] back into the base and target
+/// hashes, or returns `None` if it does not have that form.
+pub fn parse_delta_name(name: &str) -> Option<(ContentHash, ContentHash)> {
+    let stem = name.strip_suffix(EXTENSION)?.strip_suffix('.')?;
+    let (base, target) = stem.split_once('-')?;
+    Some((ContentHash::from_hex(base)?, ContentHash::from_hex(target)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_names_are_stable() {
+        // BLAKE3 of the empty input
+        let empty = "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262";
+        assert_eq!(ContentHash::of(&[]).to_string(), empty);
+        assert_eq!(delta_name(&[], &[]), format!("{empty}-{empty}.{EXTENSION}"));
+
+        let data = b"The quick brown fox jumps over the lazy dog".repeat(1000);
+        assert_eq!(
+            ContentHash::from_reader(&data[..]).unwrap(),
+            ContentHash::of(&data)
+        );
+        assert_ne!(delta_name(&data, &[]), delta_name(&[], &data));
+    }
+
+    #[test]
+    fn test_parse_delta_name() {
+        let base = ContentHash::of(b"base");
+        let target = ContentHash::of(b"target");
+        let name = delta_name_for(&base, &target);
+        assert_eq!(parse_delta_name(&name), Some((base, target)));
+        assert_eq!(
+            parse_delta_name(&name.to_uppercase().replace(".GD", ".gd")),
+            Some((base, target))
+        );
+
+        for name in [
+            "",
+            ".gd",
+            "abc-def.gd",
+            &name.replace(".gd", ".delta"),
+            &name.replace('-', "_"),
+            &format!("{base}-{target}-{target}.gd"),
+        ] {
+            assert_eq!(parse_delta_name(name), None, "{name}");
+        }
+    }
+}
@@ -0,0 +1,271 @@
+//! Member-aware encoding of container image layers.
+//!
+//! An OCI or Docker image layer is a tar archive. Between two builds of an
+//! image, most files in a layer are unchanged or slightly changed, but
+//! members may be reordered and a changed file shifts everything after it.
+//! [`encode`] splits both (uncompressed) layer tars into their members and
+//! encodes every member of the new layer against the member of the old layer
+//! with the same path. Members without a counterpart are encoded against the
+//! whole old layer.
+//!
+//! The result is a regular delta that [`crate::decode`] applies to the old
+//! layer tar, reproducing the new tar bit for bit, headers and padding
+//! included. [`bundle`] wraps it in a [`Bundle`] for distribution.
+//!
+//! # Examples
+//!
+//! ```
+//! let base = b"not a tar archive, so the plain encoder is used";
+//! let new = b"still not a tar archive, so the plain encoder is used";
+//!
+//! let delta = gdelta::oci::encode(new, base).unwrap();
+//! assert_eq!(gdelta::decode(&delta, base).unwrap(), new);
+//! ```
+
+use crate::bundle::{Bundle, Entry};
+use crate::checksum::crc32;
+use crate::delta::{DeltaWriter, encode_rebased};
+use crate::error::Result;
+use crate::matcher::GearMatcher;
+use crate::tar::{BLOCK_SIZE, header_checksum};
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// A file, directory or extended header record in a tar archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Member {
+    /// Path recorded in the header, including the ustar prefix.
+    pub path: String,
+    /// Byte range of the header and padded data within the archive.
+    pub range: Range<usize>,
+}
+
+/// Lists the members of a tar archive in archive order.
+///
+/// Returns `None` if `data` does not start with a valid tar header or a
+/// member extends past the end of the data. The end-of-archive marker and
+/// anything after it are not part of any member.
+pub fn members(data: &[u8]) -> Option<Vec<Member>> {
+    let mut members = Vec::new();
+    let mut pos = 0;
+    while let Some(header) = data.get(pos..pos + BLOCK_SIZE) {
+        if header.iter().all(|&byte| byte == 0) {
+            break;
+        }
+        if parse_octal(&header[148..156])? != header_checksum(header) {
+            return None;
+        }
+        let size = usize::try_from(parse_size(&header[124..136])?).ok()?;
+        let end = size
+            .checked_next_multiple_of(BLOCK_SIZE)
+            .and_then(|padded| (pos + BLOCK_SIZE).checked_add(padded))
+            .filter(|&end| end <= data.len())?;
+        members.push(Member {
+            path: header_path(header),
+            range: pos..end,
+        });
+        pos = end;
+    }
+    (!members.is_empty()).then_some(members)
+}
+
+/// Encodes the layer tar `new` against `base`, matching members by path.
+///
+/// If either input is not a tar archive, this is equivalent to
+/// [`crate::encode`].
+///
+/// # Errors
+///
+/// Returns `GDeltaError::InvalidDelta` only if the delta of a member cannot
+/// be read back to rebase its copies onto the whole base, which would be a
+/// bug: members are encoded with the default options, which do not fail.
+pub fn encode(new: &[u8], base: &[u8]) -> Result<Vec<u8>> {
+    let (Some(new_members), Some(base_members)) = (members(new), members(base)) else {
+        return crate::encode(new, base);
+    };
+
+    // Members with the same path are paired up in archive order
+    let mut by_path: HashMap<&str, Vec<&Member>> = HashMap::new();
+    for member in base_members.iter().rev() {
+        by_path.entry(&member.path).or_default().push(member);
+    }
+
+    let mut writer = DeltaWriter::new();
+    let mut unmatched_start = 0;
+    for member in &new_members {
+        let Some(base_member) = by_path.get_mut(member.path.as_str()).and_then(Vec::pop) else {
+            continue;
+        };
+        // New members in between are encoded together against the whole base
+        if member.range.start > unmatched_start {
+            encode_region(
+                &mut writer,
+                &new[unmatched_start..member.range.start],
+                base,
+                0..base.len(),
+            )?;
+        }
+        encode_region(
+            &mut writer,
+            &new[member.range.clone()],
+            base,
+            base_member.range.clone(),
+        )?;
+        unmatched_start = member.range.end;
+    }
+    if unmatched_start < new.len() {
+        encode_region(&mut writer, &new[unmatched_start..], base, 0..base.len())?;
+    }
+
+    Ok(writer.finish())
+}
+
+/// Encodes `new` against `base` with [`encode`] and wraps the delta in a
+/// bundle that patches the file `name`.
+///
+/// Applying the bundle with [`Bundle::apply_to_dir`] to a directory that
+/// holds the old layer tar as `name` replaces it with the new layer tar.
+///
+/// # Errors
+///
+/// Returns the errors of [`encode`], which produces the delta of `name`.
+pub fn bundle(name: &str, new: &[u8], base: &[u8]) -> Result<Bundle> {
+    let entry = Entry::Patched {
+        path: name.to_string(),
+        base_checksum: crc32(base),
+        target_checksum: crc32(new),
+        delta: encode(new, base)?,
+    };
+    Ok(Bundle {
+        entries: vec![entry],
+        ..Bundle::default()
+    })
+}
+
+/// Encodes `target` against `base[base_range]` and appends the instructions
+/// to `writer`, rebasing copy offsets onto the full base.
+fn encode_region(
+    writer: &mut DeltaWriter,
+    target: &[u8],
+    base: &[u8],
+    base_range: Range<usize>,
+) -> Result<()> {
+    encode_rebased(
+        writer,
+        target,
+        &base[base_range.clone()],
+        base_range.start as u64,
+        &mut GearMatcher::new(),
+    )?;
+    Ok(())
+}
+
+/// Parses a NUL- or space-terminated octal field.
+fn parse_octal(field: &[u8]) -> Option<u64> {
+    let digits = field
+        .iter()
+        .skip_while(|&&byte| byte == b' ')
+        .take_while(|&&byte| byte != 0 && byte != b' ');
+    let mut value = 0u64;
+    let mut any = false;
+    for &digit in digits {
+        if !(b'0'..=b'7').contains(&digit) {
+            return None;
+        }
+        value = value.checked_mul(8)? + u64::from(digit - b'0');
+        any = true;
+    }
+    any.then_some(value)
+}
+
+/// Parses the size field, which GNU tar writes in base-256 for large files.
+fn parse_size(field: &[u8]) -> Option<u64> {
+    if field[0] & 0x80 == 0 {
+        return parse_octal(field);
+    }
+    field[1..]
+        .iter()
+        .try_fold(u64::from(field[0] & 0x7F), |acc, &byte| {
+            acc.checked_mul(256).map(|acc| acc | u64::from(byte))
+        })
+}
+
+/// Returns the member path, joining the ustar prefix and name fields.
+fn header_path(header: &[u8]) -> String {
+    let field = |range: Range<usize>| {
+        let bytes = &header[range];
+        let end = bytes
+            .iter()
+            .position(|&byte| byte == 0)
+            .unwrap_or(bytes.len());
+        String::from_utf8_lossy(&bytes[..end]).into_owned()
+    };
+    let name = field(0..100);
+    if &header[257..262] == b"ustar" {
+        let prefix = field(345..500);
+        if !prefix.is_empty() {
+            return format!("{prefix}/{name}");
+        }
+    }
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a ustar archive of regular files.
+    fn tar(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut archive = Vec::new();
+        for (name, contents) in files {
+            let mut header = [0u8; BLOCK_SIZE];
+            header[..name.len()].copy_from_slice(name.as_bytes());
+            header[100..108].copy_from_slice(b"0000644\0");
+            header[124..136].copy_from_slice(format!("{:011o}\0", contents.len()).as_bytes());
+            header[156] = b'0';
+            header[257..265].copy_from_slice(b"ustar\x0000");
+            let checksum = header_checksum(&header);
+            header[148..156].copy_from_slice(format!("{checksum:06o}\0 ").as_bytes());
+            archive.extend_from_slice(&header);
+            archive.extend_from_slice(contents);
+            archive.resize(archive.len().next_multiple_of(BLOCK_SIZE), 0);
+        }
+        archive.resize(archive.len() + 2 * BLOCK_SIZE, 0);
+        archive
+    }
+
+    #[test]
+    fn test_members() {
+        let archive = tar(&[("etc/hostname", b"box\n"), ("bin/app", &[7; 600])]);
+        let members = members(&archive).unwrap();
+        assert_eq!(members.len(), 2);
+        assert_eq!(members[0].path, "etc/hostname");
+        assert_eq!(members[0].range, 0..1024);
+        assert_eq!(members[1].range, 1024..2560);
+
+        assert!(super::members(b"plain text").is_none());
+    }
+
+    #[test]
+    fn test_reordered_members() {
+        let library: Vec<u8> = (0..40_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let config = b"listen 80\n".repeat(200);
+        let base = tar(&[("usr/lib/libfoo.so", &library), ("etc/app.conf", &config)]);
+
+        let mut updated = library.clone();
+        updated[20_000..20_010].copy_from_slice(b"patched!!!");
+        let new = tar(&[
+            ("etc/app.conf", &config),
+            ("usr/bin/tool", b"#!/bin/sh\necho new tool\n"),
+            ("usr/lib/libfoo.so", &updated),
+        ]);
+
+        let delta = encode(&new, &base).unwrap();
+        assert_eq!(crate::decode(&delta, &base).unwrap(), new);
+        assert!(delta.len() < 500);
+
+        let bundle = bundle("layer.tar", &new, &base).unwrap();
+        let bytes = bundle.to_bytes();
+        assert_eq!(Bundle::from_bytes(&bytes).unwrap(), bundle);
+    }
+}
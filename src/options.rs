@@ -0,0 +1,613 @@
+//! Configuration for delta encoding.
+
+use crate::buffer::BufferStream;
+use crate::error::{GDeltaError, Result};
+use crate::gear::{MAX_WORD_SIZE, WORD_SIZE};
+use crate::hints::EncodeHints;
+use crate::matcher::{
+    ConfiguredMatcher, GearMatcher, HINTED_SAMPLE_RATE, HintedMatcher, LineMatcher,
+};
+use crate::preset::Preset;
+use crate::varint::write_varint;
+use std::time::{Duration, Instant};
+
+/// Options controlling how a delta is encoded.
+///
+/// The defaults reproduce [`crate::encode`]. Options are set with chained
+/// builder methods and passed to [`crate::encode_with_options`].
+///
+/// # Examples
+///
+/// ```
+/// use gdelta::{EncodeOptions, decode, encode_with_options};
+///
+/// let base = b"The quick brown fox jumps over the lazy dog";
+/// let new = b"The quick brown cat jumps over the lazy dog";
+///
+/// let options = EncodeOptions::new().copy_window(1024);
+/// let delta = encode_with_options(new, base, &options).unwrap();
+/// assert_eq!(decode(&delta, base).unwrap(), new);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EncodeOptions {
+    pub(crate) copy_window: Option<usize>,
+    pub(crate) checksum: bool,
+    pub(crate) store_target_size: bool,
+    pub(crate) target_checksum: bool,
+    pub(crate) base_checksum: bool,
+    pub(crate) metadata: Vec<u8>,
+    pub(crate) format_version: Option<u8>,
+    pub(crate) rsyncable: bool,
+    pub(crate) parallel: bool,
+    pub(crate) deadline: Option<Instant>,
+    pub(crate) degrade_on_deadline: bool,
+    pub(crate) memory_budget: Option<usize>,
+    pub(crate) huge_pages: bool,
+    pub(crate) hash_tags: bool,
+    pub(crate) preset: Preset,
+    /// Whether the preset is picked from the new data at encode time.
+    pub(crate) auto_preset: bool,
+    /// Maximum delta to target size ratio, in millionths.
+    pub(crate) max_ratio: Option<u64>,
+    pub(crate) target_matches: bool,
+    pub(crate) min_match_len: Option<usize>,
+    pub(crate) word_size: Option<usize>,
+    pub(crate) line_delimiter: Option<u8>,
+    pub(crate) align_records: bool,
+    pub(crate) match_alignment: Option<usize>,
+    pub(crate) extend_backward: bool,
+    pub(crate) hints: Option<EncodeHints>,
+}
+
+impl EncodeOptions {
+    /// Creates options with default settings.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates options that pick the preset from the new data.
+    ///
+    /// [`Preset::detect`] classifies the start of the new data when encoding
+    /// with [`crate::encode_with_options`]. Streaming encodes cannot look
+    /// ahead and use [`Preset::Default`]. Calling [`Self::preset`] turns the
+    /// detection off again.
+    ///
+    /// ```
+    /// use gdelta::{EncodeOptions, decode, encode_with_options};
+    ///
+    /// let base = b"timestamp,level,message\n".repeat(100);
+    /// let mut new = base.clone();
+    /// new.extend_from_slice(b"2024-05-01,INFO,started\n");
+    ///
+    /// let delta = encode_with_options(&new, &base, &EncodeOptions::auto()).unwrap();
+    /// assert_eq!(decode(&delta, &base).unwrap(), new);
+    /// ```
+    pub fn auto() -> Self {
+        Self {
+            auto_preset: true,
+            ..Self::default()
+        }
+    }
+
+    /// Restricts copies to a sliding window of the base.
+    ///
+    /// A copy may start at most `window` bytes before the end of the furthest
+    /// base region referenced by any earlier copy. A decoder that reads the
+    /// base sequentially therefore only needs to keep the last `window` bytes
+    /// of it resident, which allows streaming application of huge files.
+    ///
+    /// Matches that would violate the window are emitted as literals instead.
+    /// The windows of [`crate::encode_streams`] do not track the furthest
+    /// base region, so it rejects this option with
+    /// [`GDeltaError::InvalidOptions`].
+    #[must_use]
+    pub fn copy_window(mut self, window: usize) -> Self {
+        self.copy_window = Some(window);
+        self
+    }
+
+    /// Appends a CRC-32 of the delta so corruption is detected before decoding.
+    ///
+    /// Enabling this wraps the delta in a small header; [`crate::decode`]
+    /// verifies the checksum and returns [`crate::GDeltaError::ChecksumMismatch`]
+    /// if the delta was truncated or altered.
+    #[must_use]
+    pub fn checksum(mut self, enabled: bool) -> Self {
+        self.checksum = enabled;
+        self
+    }
+
+    /// Records the size of the new data in the delta header.
+    ///
+    /// [`crate::decode`] checks the reconstructed length against it and
+    /// returns [`crate::GDeltaError::SizeMismatch`] if they differ, so a
+    /// damaged delta cannot silently produce a short output.
+    #[must_use]
+    pub fn store_target_size(mut self, enabled: bool) -> Self {
+        self.store_target_size = enabled;
+        self
+    }
+
+    /// Records a CRC-32 of the new data in the delta header.
+    ///
+    /// [`crate::decode`], [`crate::decode_into_slice`],
+    /// [`crate::decode_to_writer`] and [`crate::decode_to_file`] verify the
+    /// reconstructed target against it and return
+    /// [`crate::GDeltaError::ChecksumMismatch`] if they differ. It also lets
+    /// a file be checked against the delta without the base; see
+    /// [`crate::target_checksum`].
+    #[must_use]
+    pub fn target_checksum(mut self, enabled: bool) -> Self {
+        self.target_checksum = enabled;
+        self
+    }
+
+    /// Records a CRC-32 of the base data in the delta header.
+    ///
+    /// The decoders check the base against it before producing any output
+    /// and return [`crate::GDeltaError::ChecksumMismatch`] if it is not the
+    /// base the delta was encoded against. Together with
+    /// [`Self::target_checksum`] it declares which file a delta applies to
+    /// and which file it produces, so a set of deltas can be put in order;
+    /// see [`crate::base_checksum`].
+    #[must_use]
+    pub fn base_checksum(mut self, enabled: bool) -> Self {
+        self.base_checksum = enabled;
+        self
+    }
+
+    /// Adds a metadata entry with `tag` and `value` to the delta header.
+    ///
+    /// Entries are opaque to gdelta and kept in the order they were added;
+    /// the same tag may appear more than once. They are read back without
+    /// decoding with [`crate::DeltaHeader::metadata`], for instance to index
+    /// stored deltas by file name or build id.
+    #[must_use]
+    pub fn metadata(mut self, tag: u64, value: &[u8]) -> Self {
+        let mut entry = BufferStream::with_capacity(value.len() + 20);
+        write_varint(&mut entry, tag);
+        write_varint(&mut entry, value.len() as u64);
+        entry.write_bytes(value);
+        self.metadata.extend_from_slice(entry.as_slice());
+        self
+    }
+
+    /// Writes deltas in format `version` or older, for readers of an older
+    /// release; see [`crate::negotiate_format`].
+    ///
+    /// Version 0 is the raw format without a header, which every release
+    /// decodes. It has no room for checksums, a stored target size,
+    /// metadata, segments or copies from the target, so encoding fails with
+    /// `GDeltaError::InvalidOptions` if any of those are requested, and
+    /// identical inputs become a single copy instead of the identity delta.
+    #[must_use]
+    pub fn format_version(mut self, version: u8) -> Self {
+        self.format_version = Some(version);
+        self
+    }
+
+    /// Returns whether deltas may start with a header.
+    pub(crate) fn allows_header(&self) -> bool {
+        self.format_version != Some(0)
+    }
+
+    /// Splits the delta into segments at content-defined points of the target.
+    ///
+    /// When a delta is regenerated after a small change to the target, only
+    /// the segments around the change differ, so rsync or a deduplicating
+    /// store can transfer or store the new delta file incrementally. This
+    /// costs a few bytes per segment (one segment per ~64 KiB of target).
+    #[must_use]
+    pub fn rsyncable(mut self, enabled: bool) -> Self {
+        self.rsyncable = enabled;
+        self
+    }
+
+    /// Encodes the new data in fixed 1 MiB windows, on all cores when the
+    /// `rayon` feature is enabled.
+    ///
+    /// Each window is encoded independently against the whole base and
+    /// stored as a segment of the delta, in order. The window boundaries do
+    /// not depend on the number of threads, so the delta is byte-identical
+    /// whether the `rayon` feature is enabled or not and on any machine.
+    /// Windows cannot continue a copy started in the previous window, which
+    /// costs a few bytes per window.
+    ///
+    /// Only used by [`crate::encode_with_options`]. Cannot be combined with
+    /// [`Self::rsyncable`] or [`Self::copy_window`]; encoding then fails
+    /// with [`GDeltaError::InvalidOptions`].
+    #[must_use]
+    pub fn parallel(mut self, enabled: bool) -> Self {
+        self.parallel = enabled;
+        self
+    }
+
+    /// Aborts encoding with [`GDeltaError::DeadlineExceeded`] once `deadline`
+    /// has passed.
+    ///
+    /// Useful for latency-sensitive services that would rather send the full
+    /// object than wait for a slow diff. The deadline is checked periodically
+    /// while searching for matches, so encoding may overrun it slightly.
+    /// With [`Self::degrade_on_deadline`], a lower-quality delta is returned
+    /// instead.
+    #[must_use]
+    pub fn deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Lowers the encoding effort to meet the deadline instead of aborting.
+    ///
+    /// While searching for matches, the encoder compares its progress with
+    /// the time left. If it would not finish in time, it searches fewer
+    /// positions in unmatched regions, doubling the stride each time it
+    /// falls behind, up to 64 bytes; long matches are still found, short
+    /// ones are missed. Once the deadline has passed, the rest of the
+    /// target is stored as literal data. The delta is always valid, just
+    /// larger, so interactive tools get a usable patch within their latency
+    /// budget. Without a deadline this has no effect.
+    ///
+    /// ```
+    /// use gdelta::{EncodeOptions, decode, encode_with_options};
+    /// use std::time::Duration;
+    ///
+    /// let base = b"The quick brown fox jumps over the lazy dog. ".repeat(1000);
+    /// let mut new = base.clone();
+    /// new[20_000..20_010].copy_from_slice(b"0123456789");
+    ///
+    /// let options = EncodeOptions::new()
+    ///     .timeout(Duration::from_millis(50))
+    ///     .degrade_on_deadline(true);
+    /// let delta = encode_with_options(&new, &base, &options).unwrap();
+    /// assert_eq!(decode(&delta, &base).unwrap(), new);
+    /// ```
+    #[must_use]
+    pub fn degrade_on_deadline(mut self, enabled: bool) -> Self {
+        self.degrade_on_deadline = enabled;
+        self
+    }
+
+    /// Sets a deadline `timeout` from now; see [`Self::deadline`].
+    #[must_use]
+    pub fn timeout(self, timeout: Duration) -> Self {
+        match Instant::now().checked_add(timeout) {
+            Some(deadline) => self.deadline(deadline),
+            None => self,
+        }
+    }
+
+    /// Limits the memory used to index the base to about `bytes`.
+    ///
+    /// Instead of allocating an index proportional to the base, the encoder
+    /// shrinks its hash table and samples the base more sparsely to stay
+    /// within the budget. This keeps memory use predictable, for example
+    /// under container memory limits, at the cost of larger deltas. The
+    /// input and output buffers are not counted.
+    #[must_use]
+    pub fn memory_budget(mut self, bytes: usize) -> Self {
+        self.memory_budget = Some(bytes);
+        self
+    }
+
+    /// Places large hash tables on 2 MiB boundaries.
+    ///
+    /// Lookups in the index of a large base miss the TLB often. Where the
+    /// kernel backs aligned memory with huge pages, as Linux does when
+    /// transparent huge pages are enabled, an aligned table needs far fewer
+    /// TLB entries. Tables under 2 MiB are not affected, and larger ones use
+    /// up to 2 MiB more memory. The encoded output does not change.
+    #[must_use]
+    pub fn huge_pages(mut self, enabled: bool) -> Self {
+        self.huge_pages = enabled;
+        self
+    }
+
+    /// Stores a 16-bit tag of each indexed fingerprint and checks it before
+    /// comparing target and base bytes.
+    ///
+    /// On noisy data most hash table hits point at unrelated base positions,
+    /// and each of them costs a read of the base at a random offset. The tag
+    /// rules out nearly all of them from a table that is more likely to be
+    /// in cache. The index grows by half and the encoded output does not
+    /// change; with a [`EncodeOptions::memory_budget`], the tags count
+    /// against it.
+    #[must_use]
+    pub fn hash_tags(mut self, enabled: bool) -> Self {
+        self.hash_tags = enabled;
+        self
+    }
+
+    /// Tunes the encoder for a kind of data; see [`Preset`].
+    #[must_use]
+    pub fn preset(mut self, preset: Preset) -> Self {
+        self.preset = preset;
+        self.auto_preset = false;
+        self
+    }
+
+    /// Gives up with [`GDeltaError::NotBeneficial`] once the delta is bound
+    /// to exceed `ratio` times the size of the new data.
+    ///
+    /// Pipelines that store the raw data whenever the delta is not small
+    /// enough, such as deduplicating stores, can set this (e.g. `0.9`) to stop
+    /// the encoder as soon as the delta grows past that point instead of
+    /// finishing it. The ratio is applied with a precision of one millionth.
+    ///
+    /// Streaming encodes have written most of the delta by the time the
+    /// target size is known, so [`crate::encode_streams`] rejects this option
+    /// with [`GDeltaError::InvalidOptions`].
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn max_ratio(mut self, ratio: f64) -> Self {
+        // Saturating conversion; NaN and negative ratios become 0
+        self.max_ratio = Some((ratio * 1_000_000.0).round() as u64);
+        self
+    }
+
+    /// Also copies data that repeats within the new data itself.
+    ///
+    /// Blocks that were inserted more than once compress even when they do
+    /// not occur in the base, and a literal run identical to an earlier one
+    /// becomes a copy of it however short, as long as the copy is smaller,
+    /// which helps templated inserts. A copy may overlap the bytes it
+    /// produces, so long runs of a byte or a short pattern take only a few
+    /// bytes. Such copies are addressed past the end of the base, which is
+    /// flagged in the delta header. Releases up to 0.2.1 fail on these
+    /// deltas with an error, as on any delta with a header.
+    ///
+    /// Because a short overlapping copy can expand to any length, the
+    /// target size is always stored with these deltas, as with
+    /// [`Self::store_target_size`], and decoders reject target copies
+    /// without it.
+    #[must_use]
+    pub fn target_matches(mut self, enabled: bool) -> Self {
+        self.target_matches = enabled;
+        self
+    }
+
+    /// Only emits copies of at least `len` bytes.
+    ///
+    /// Independently of this, a copy is only emitted when its instruction is
+    /// smaller than the literal bytes it replaces, including the cost of
+    /// splitting a literal run in two. Raising the minimum trades delta size
+    /// for fewer, longer instructions, which speeds up decoding.
+    ///
+    /// Not applied per window: [`crate::encode_streams`] rejects it with
+    /// [`GDeltaError::InvalidOptions`].
+    #[must_use]
+    pub fn min_match_len(mut self, len: usize) -> Self {
+        self.min_match_len = Some(len);
+        self
+    }
+
+    /// Sets the length of the anchor a match must start with, in bytes.
+    ///
+    /// The default of [`crate::gear::WORD_SIZE`] (8) suits small text edits. Longer
+    /// anchors such as 16, 32 or 48 bytes skip the many short, accidental
+    /// matches in structured binary data and index the base more sparsely,
+    /// which makes encoding faster. Sizes below 8 or above
+    /// [`crate::gear::MAX_WORD_SIZE`] are rejected with
+    /// [`GDeltaError::InvalidOptions`] when encoding.
+    #[must_use]
+    pub fn word_size(mut self, size: usize) -> Self {
+        self.word_size = Some(size);
+        self
+    }
+
+    /// Extends each copy from the base backwards over the literal data
+    /// before it, as far as the bytes match.
+    ///
+    /// Only the common prefix and suffix of the whole inputs are found by
+    /// comparing bytes; inside them, copies are found by sampled fingerprints
+    /// and often start some bytes into an unchanged block, or far into it
+    /// after a long run of changed data the encoder skipped through quickly.
+    /// Scanning back from each copy anchors both ends of every unchanged
+    /// block framed by edits, so they are copied whole. This costs a
+    /// comparison per recovered byte and changes the encoded bytes, so it is
+    /// off by default.
+    ///
+    /// Streaming encodes, including [`crate::EncodeReader`], do not extend
+    /// matches and reject this option with [`GDeltaError::InvalidOptions`].
+    #[must_use]
+    pub fn extend_matches_backward(mut self, enabled: bool) -> Self {
+        self.extend_backward = enabled;
+        self
+    }
+
+    /// Only starts copies at multiples of `bytes` in both the new data and
+    /// the base.
+    ///
+    /// For formats made of fixed-size records, such as arrays of 4- or 8-byte
+    /// values or columnar pages, matches that start inside a record are
+    /// accidental, and copies that keep records whole are more useful to
+    /// tools that process the delta per record. The base is only indexed and
+    /// the new data only searched at aligned offsets, which also makes
+    /// encoding faster. Unaligned differences are stored as literal data.
+    /// An alignment of 0 is rejected with [`GDeltaError::InvalidOptions`]
+    /// when encoding; 1 is the default and has no effect.
+    #[must_use]
+    pub fn match_alignment(mut self, bytes: usize) -> Self {
+        self.match_alignment = Some(bytes);
+        self
+    }
+
+    /// Returns the largest acceptable delta size for a target of `target_len` bytes.
+    pub(crate) fn size_limit(&self, target_len: usize) -> Option<usize> {
+        self.max_ratio.map(|ratio| {
+            let limit = u128::from(ratio) * target_len as u128 / 1_000_000;
+            usize::try_from(limit).unwrap_or(usize::MAX)
+        })
+    }
+
+    /// Matches whole lines before falling back to byte-wise matching.
+    ///
+    /// Produces much smaller deltas for source code, logs and other text
+    /// where edits are line-aligned; see [`LineMatcher`].
+    #[must_use]
+    pub fn text_mode(mut self, enabled: bool) -> Self {
+        self.line_delimiter = enabled.then_some(b'\n');
+        self
+    }
+
+    /// Treats the data as records ending in `delimiter`, such as rows of a
+    /// CSV or JSON Lines file (`b'\n'`).
+    ///
+    /// Records are matched whole as in [`Self::text_mode`], copies end at
+    /// record boundaries, and so do the segments of [`Self::rsyncable`] and
+    /// the windows of [`crate::encode_streams`]. A single inserted or
+    /// deleted row then only changes the delta around it instead of
+    /// desynchronizing the copies for the rest of the file.
+    #[must_use]
+    pub fn record_delimiter(mut self, delimiter: u8) -> Self {
+        self.line_delimiter = Some(delimiter);
+        self.align_records = true;
+        self
+    }
+
+    /// Tries the copies of an earlier delta against the same base before
+    /// searching, as [`HintedMatcher`] does.
+    ///
+    /// Encoding a new version against the base of an earlier delta (v1→v3
+    /// after v1→v2) then mostly verifies the copies it already found. The
+    /// delta still decodes to the new data whatever the hints are. Streaming
+    /// encodes do not use hints.
+    #[must_use]
+    pub fn hints(mut self, hints: EncodeHints) -> Self {
+        self.hints = Some(hints);
+        self
+    }
+
+    /// Returns the delimiter that boundaries are aligned to, if any.
+    pub(crate) fn record_delimiter_for_alignment(&self) -> Option<u8> {
+        self.line_delimiter.filter(|_| self.align_records)
+    }
+
+    /// Returns the matcher configured by these options.
+    pub(crate) fn matcher(&self) -> ConfiguredMatcher {
+        match &self.hints {
+            Some(hints) => {
+                // The hints find most copies, so the base is sampled sparsely
+                let sample_rate = self.preset.sample_rate().max(HINTED_SAMPLE_RATE);
+                ConfiguredMatcher::Hinted(Box::new(HintedMatcher::with_fallback(
+                    hints.clone(),
+                    self.search_matcher(sample_rate),
+                )))
+            }
+            None => self.window_matcher(),
+        }
+    }
+
+    /// Returns the matcher configured by these options without the hints,
+    /// whose offsets are meaningless within a window of the target.
+    pub(crate) fn window_matcher(&self) -> ConfiguredMatcher {
+        self.search_matcher(self.preset.sample_rate())
+    }
+
+    /// Returns the matcher searching the base, sampled every `sample_rate`
+    /// positions.
+    fn search_matcher(&self, sample_rate: usize) -> ConfiguredMatcher {
+        let gear = match self.memory_budget {
+            Some(budget) => GearMatcher::with_memory_budget(budget),
+            None => GearMatcher::new(),
+        };
+        let gear = gear
+            .sample_rate(sample_rate)
+            .word_size(self.word_size.unwrap_or(WORD_SIZE))
+            .huge_pages(self.huge_pages)
+            .hash_tags(self.hash_tags)
+            .alignment(self.match_alignment.unwrap_or(1));
+        match self.line_delimiter {
+            Some(delimiter) => ConfiguredMatcher::Line(
+                LineMatcher::with_fallback(delimiter, gear).align_matches(self.align_records),
+            ),
+            None => ConfiguredMatcher::Gear(gear),
+        }
+    }
+
+    /// Checks that the options are consistent.
+    pub(crate) fn validate(&self) -> Result<()> {
+        if let Some(size) = self.word_size
+            && size < WORD_SIZE
+        {
+            return Err(GDeltaError::InvalidOptions(format!(
+                "word size {size} is below the minimum of {WORD_SIZE} bytes"
+            )));
+        }
+        if let Some(size) = self.word_size
+            && size > MAX_WORD_SIZE
+        {
+            return Err(GDeltaError::InvalidOptions(format!(
+                "word size {size} is above the maximum of {MAX_WORD_SIZE} bytes"
+            )));
+        }
+        if self.match_alignment == Some(0) {
+            return Err(GDeltaError::InvalidOptions(
+                "match alignment must be at least 1 byte".to_string(),
+            ));
+        }
+        let needs_header = [
+            (self.checksum, "a checksum"),
+            (self.store_target_size, "a stored target size"),
+            (self.target_checksum, "a target checksum"),
+            (self.base_checksum, "a base checksum"),
+            (!self.metadata.is_empty(), "metadata"),
+            (self.rsyncable || self.parallel, "segments"),
+            (self.target_matches, "matches within the target"),
+        ];
+        let needed = needs_header.iter().find(|(needed, _)| *needed);
+        if let Some(version) = self.format_version {
+            if !crate::supports(version) {
+                return Err(GDeltaError::InvalidOptions(format!(
+                    "format version {version} is not supported; this release writes versions {} to {}",
+                    crate::MIN_FORMAT_VERSION,
+                    crate::FORMAT_VERSION
+                )));
+            }
+            if version == 0
+                && let Some((_, feature)) = needed
+            {
+                return Err(GDeltaError::InvalidOptions(format!(
+                    "{feature} cannot be written in format version 0"
+                )));
+            }
+        }
+        if self.parallel {
+            let conflict = if self.rsyncable {
+                Some("rsyncable segments")
+            } else if self.copy_window.is_some() {
+                Some("a copy window")
+            } else {
+                None
+            };
+            if let Some(conflict) = conflict {
+                return Err(GDeltaError::InvalidOptions(format!(
+                    "parallel encoding cannot be combined with {conflict}"
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns an error if the deadline has passed, unless the encoding is
+    /// to degrade instead.
+    pub(crate) fn check_deadline(&self) -> Result<()> {
+        if self.degrade_on_deadline {
+            return Ok(());
+        }
+        check_deadline(self.deadline)
+    }
+
+    /// Returns whether the deadline is set and has passed.
+    pub(crate) fn deadline_passed(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+}
+
+/// Returns an error if `deadline` is set and has passed.
+pub(crate) fn check_deadline(deadline: Option<Instant>) -> Result<()> {
+    match deadline {
+        Some(deadline) if Instant::now() >= deadline => Err(GDeltaError::DeadlineExceeded),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,520 @@
+//! A/B over-the-air updates.
+//!
+//! Devices with two firmware slots run from one while the other is updated,
+//! and only switch once the new image is complete and verified. If the new
+//! image fails to boot a few times in a row, the bootloader falls back to
+//! the old slot. This module provides both halves of that scheme:
+//!
+//! - [`Payload`] is the update shipped to the device: a delta from the image
+//!   in the active slot, together with the size and CRC-32 of the image it
+//!   applies to and of the image it produces.
+//! - [`BootControl`] is the rollback metadata kept in a small separate
+//!   partition, modelled on the Android boot control HAL: the active slot and,
+//!   per slot, whether it is bootable, whether it has booted successfully and
+//!   how many boot attempts it has left.
+//!
+//! [`install`] streams the decoded image into the inactive slot while
+//! checksumming it, and only marks the slot bootable and active if the
+//! result verifies. The bootloader calls [`BootControl::boot`] on every
+//! start, and the system calls [`BootControl::mark_successful`] once the new
+//! image is known to work.
+//!
+//! # Examples
+//!
+//! ```
+//! use gdelta::ota::{self, BootControl, Payload, Slot};
+//!
+//! let v1 = b"firmware v1: sensor loop, radio driver".to_vec();
+//! let v2 = b"firmware v2: sensor loop, radio driver, ota".to_vec();
+//! let mut control = BootControl::new(&v1);
+//!
+//! // Build server
+//! let payload = Payload::new(&v2, &v1)?.to_bytes();
+//!
+//! // Device: write slot B while running from slot A
+//! let mut slot_b = Vec::new();
+//! let payload = Payload::from_bytes(&payload)?;
+//! ota::install(&payload, &v1, &mut slot_b, &mut control, |_| Ok(()))?;
+//! assert_eq!(slot_b, v2);
+//!
+//! // Bootloader and system after the reboot
+//! assert_eq!(control.boot(), Some(Slot::B));
+//! control.mark_successful();
+//! # Ok::<(), gdelta::GDeltaError>(())
+//! ```
+
+use crate::buffer::BufferStream;
+use crate::checksum::{ChecksumWriter, crc32};
+use crate::error::{GDeltaError, Result};
+use crate::varint::{read_varint, write_varint};
+use std::io::Write;
+
+/// Marker at the start of every payload.
+pub const PAYLOAD_MAGIC: [u8; 4] = *b"GDOT";
+
+/// Marker at the start of serialized boot control metadata.
+pub const BOOT_CONTROL_MAGIC: [u8; 4] = *b"GDBC";
+
+/// Current version of the payload and boot control formats.
+pub const VERSION: u8 = 1;
+
+/// Boot attempts a newly installed slot gets before the bootloader falls
+/// back to the other slot.
+pub const BOOT_TRIES: u8 = 3;
+
+/// Size of a serialized [`BootControl`] in bytes.
+pub const BOOT_CONTROL_SIZE: usize = 4 + 2 + 2 * SLOT_INFO_SIZE + 4;
+
+const SLOT_INFO_SIZE: usize = 2 + 8 + 4;
+
+const FLAG_BOOTABLE: u8 = 0x01;
+const FLAG_SUCCESSFUL: u8 = 0x02;
+
+/// One of the two firmware slots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Slot {
+    /// The first slot.
+    A,
+    /// The second slot.
+    B,
+}
+
+impl Slot {
+    /// Returns the other slot.
+    pub fn other(self) -> Self {
+        match self {
+            Slot::A => Slot::B,
+            Slot::B => Slot::A,
+        }
+    }
+
+    fn index(self) -> usize {
+        match self {
+            Slot::A => 0,
+            Slot::B => 1,
+        }
+    }
+}
+
+/// Boot state of a single slot.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SlotInfo {
+    /// The slot holds a complete, verified image.
+    pub bootable: bool,
+    /// The image has booted and was marked as working.
+    pub successful: bool,
+    /// Boot attempts left before the slot is given up, unless it is
+    /// successful.
+    pub tries_remaining: u8,
+    /// Length of the image in the slot.
+    pub image_size: u64,
+    /// CRC-32 of the image in the slot.
+    pub image_checksum: u32,
+}
+
+impl SlotInfo {
+    /// Returns true if `slot` starts with the image this slot records, so a
+    /// bootloader can check the slot before jumping to it.
+    pub fn verify(&self, slot: &[u8]) -> bool {
+        usize::try_from(self.image_size)
+            .ok()
+            .and_then(|size| slot.get(..size))
+            .is_some_and(|image| crc32(image) == self.image_checksum)
+    }
+
+    fn can_boot(&self) -> bool {
+        self.bootable && (self.successful || self.tries_remaining > 0)
+    }
+}
+
+/// Rollback metadata for a pair of slots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BootControl {
+    /// Slot the bootloader tries first.
+    pub active: Slot,
+    /// State of slots A and B, in that order.
+    pub slots: [SlotInfo; 2],
+}
+
+impl BootControl {
+    /// Creates the metadata of a freshly provisioned device running `image`
+    /// from slot A, with slot B empty.
+    pub fn new(image: &[u8]) -> Self {
+        let installed = SlotInfo {
+            bootable: true,
+            successful: true,
+            tries_remaining: 0,
+            image_size: image.len() as u64,
+            image_checksum: crc32(image),
+        };
+        Self {
+            active: Slot::A,
+            slots: [installed, SlotInfo::default()],
+        }
+    }
+
+    /// Returns the state of `slot`.
+    pub fn slot(&self, slot: Slot) -> &SlotInfo {
+        &self.slots[slot.index()]
+    }
+
+    fn slot_mut(&mut self, slot: Slot) -> &mut SlotInfo {
+        &mut self.slots[slot.index()]
+    }
+
+    /// Picks the slot to boot, as the bootloader does on every start.
+    ///
+    /// An active slot that has not been marked successful uses up one of
+    /// its tries. Once it has none left, it is marked unbootable and the
+    /// other slot becomes active again. Returns `None` if neither slot can
+    /// boot. The metadata must be persisted before jumping to the slot.
+    pub fn boot(&mut self) -> Option<Slot> {
+        for _ in 0..2 {
+            let active = self.active;
+            let info = self.slot_mut(active);
+            if info.can_boot() {
+                if !info.successful {
+                    info.tries_remaining -= 1;
+                }
+                return Some(active);
+            }
+            info.bootable = false;
+            self.active = active.other();
+        }
+        None
+    }
+
+    /// Marks the active slot as working, so it is kept even if it is
+    /// restarted many times.
+    pub fn mark_successful(&mut self) {
+        let active = self.active;
+        self.slot_mut(active).successful = true;
+    }
+
+    /// Serializes the metadata into [`BOOT_CONTROL_SIZE`] bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut stream = BufferStream::with_capacity(BOOT_CONTROL_SIZE);
+        stream.write_bytes(&BOOT_CONTROL_MAGIC);
+        stream.write_u8(VERSION);
+        stream.write_u8(self.active.index() as u8);
+        for info in &self.slots {
+            let mut flags = 0;
+            if info.bootable {
+                flags |= FLAG_BOOTABLE;
+            }
+            if info.successful {
+                flags |= FLAG_SUCCESSFUL;
+            }
+            stream.write_u8(flags);
+            stream.write_u8(info.tries_remaining);
+            stream.write_bytes(&info.image_size.to_le_bytes());
+            stream.write_bytes(&info.image_checksum.to_le_bytes());
+        }
+        let mut bytes = stream.into_vec();
+        let crc = crc32(&bytes);
+        bytes.extend_from_slice(&crc.to_le_bytes());
+        bytes
+    }
+
+    /// Parses metadata written by [`BootControl::to_bytes`].
+    ///
+    /// Trailing bytes are ignored, so the whole metadata partition can be
+    /// passed in.
+    ///
+    /// # Errors
+    ///
+    /// Returns `GDeltaError::ChecksumMismatch` if the metadata is damaged
+    /// and `GDeltaError::InvalidDelta` if it is malformed or has an
+    /// unsupported version.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let bytes = bytes
+            .get(..BOOT_CONTROL_SIZE)
+            .ok_or(GDeltaError::UnexpectedEndOfData)?;
+        if !bytes.starts_with(&BOOT_CONTROL_MAGIC) {
+            return Err(invalid("Not gdelta boot control metadata"));
+        }
+        let (body, trailer) = bytes.split_at(BOOT_CONTROL_SIZE - 4);
+        verify(read_u32(trailer), body)?;
+
+        let mut stream = BufferStream::from_slice(body);
+        stream.set_position(BOOT_CONTROL_MAGIC.len());
+        read_version(&mut stream)?;
+        let active = match stream.read_u8()? {
+            0 => Slot::A,
+            1 => Slot::B,
+            slot => return Err(invalid(&format!("Unknown slot {slot}"))),
+        };
+        let mut slots = [SlotInfo::default(); 2];
+        for info in &mut slots {
+            let flags = stream.read_u8()?;
+            info.bootable = flags & FLAG_BOOTABLE != 0;
+            info.successful = flags & FLAG_SUCCESSFUL != 0;
+            info.tries_remaining = stream.read_u8()?;
+            let size = stream.read_bytes(8)?;
+            info.image_size = u64::from_le_bytes(size.try_into().expect("read 8 bytes"));
+            info.image_checksum = read_u32(stream.read_bytes(4)?);
+        }
+        Ok(Self { active, slots })
+    }
+}
+
+/// An update from one image to another.
+///
+/// ```text
+/// [magic: "GDOT"][version: u8]
+/// [source size: varint][source crc32: u32 LE]
+/// [target size: varint][target crc32: u32 LE]
+/// [delta]
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Payload {
+    /// Length of the image the delta applies to.
+    pub source_size: u64,
+    /// CRC-32 of the image the delta applies to.
+    pub source_checksum: u32,
+    /// Length of the image the delta produces.
+    pub target_size: u64,
+    /// CRC-32 of the image the delta produces.
+    pub target_checksum: u32,
+    /// Delta from the source to the target image.
+    pub delta: Vec<u8>,
+}
+
+impl Payload {
+    /// Encodes an update from `source` to `target` with [`crate::encode`].
+    pub fn new(target: &[u8], source: &[u8]) -> Result<Self> {
+        Ok(Self {
+            source_size: source.len() as u64,
+            source_checksum: crc32(source),
+            target_size: target.len() as u64,
+            target_checksum: crc32(target),
+            delta: crate::encode(target, source)?,
+        })
+    }
+
+    /// Serializes the payload.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut stream = BufferStream::with_capacity(self.delta.len() + 32);
+        stream.write_bytes(&PAYLOAD_MAGIC);
+        stream.write_u8(VERSION);
+        write_varint(&mut stream, self.source_size);
+        stream.write_bytes(&self.source_checksum.to_le_bytes());
+        write_varint(&mut stream, self.target_size);
+        stream.write_bytes(&self.target_checksum.to_le_bytes());
+        stream.write_bytes(&self.delta);
+        stream.into_vec()
+    }
+
+    /// Parses a serialized payload.
+    ///
+    /// # Errors
+    ///
+    /// Returns `GDeltaError::InvalidDelta` if the payload is malformed or has
+    /// an unsupported version.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if !bytes.starts_with(&PAYLOAD_MAGIC) {
+            return Err(invalid("Not a gdelta OTA payload"));
+        }
+        let mut stream = BufferStream::from_slice(bytes);
+        stream.set_position(PAYLOAD_MAGIC.len());
+        read_version(&mut stream)?;
+        let source_size = read_varint(&mut stream)?;
+        let source_checksum = read_u32(stream.read_bytes(4)?);
+        let target_size = read_varint(&mut stream)?;
+        let target_checksum = read_u32(stream.read_bytes(4)?);
+        Ok(Self {
+            source_size,
+            source_checksum,
+            target_size,
+            target_checksum,
+            delta: bytes[stream.position()..].to_vec(),
+        })
+    }
+}
+
+/// Installs `payload` into the inactive slot.
+///
+/// `source_slot` is the contents of the active slot; it may be longer than
+/// the image in it. The new image is streamed to `target_slot` and
+/// checksummed on the way. `persist` is called whenever `control` has to
+/// reach storage before the next step: once after the inactive slot has
+/// been marked unbootable, so an interrupted install is never booted, and
+/// once after the verified slot has been made active with [`BOOT_TRIES`]
+/// boot attempts. Returns the slot that was written.
+///
+/// # Errors
+///
+/// Returns `GDeltaError::ChecksumMismatch` if the active slot does not hold
+/// the image the payload applies to or the new image does not verify,
+/// `GDeltaError::SizeMismatch` if the new image has the wrong length,
+/// `GDeltaError::Io` if writing fails, and any error from decoding or from
+/// `persist`. The active slot stays active on every error.
+pub fn install<W: Write>(
+    payload: &Payload,
+    source_slot: &[u8],
+    target_slot: W,
+    control: &mut BootControl,
+    mut persist: impl FnMut(&BootControl) -> Result<()>,
+) -> Result<Slot> {
+    let source = usize::try_from(payload.source_size)
+        .ok()
+        .and_then(|size| source_slot.get(..size))
+        .ok_or(GDeltaError::SizeMismatch {
+            expected: usize::try_from(payload.source_size).unwrap_or(usize::MAX),
+            actual: source_slot.len(),
+        })?;
+    verify(payload.source_checksum, source)?;
+
+    let slot = control.active.other();
+    *control.slot_mut(slot) = SlotInfo::default();
+    persist(control)?;
+
+    let mut writer = ChecksumWriter::new(target_slot);
+    crate::decode_to_writer(&payload.delta, source, &mut writer)?;
+    if writer.written() != payload.target_size {
+        return Err(GDeltaError::SizeMismatch {
+            expected: usize::try_from(payload.target_size).unwrap_or(usize::MAX),
+            actual: usize::try_from(writer.written()).unwrap_or(usize::MAX),
+        });
+    }
+    verify_checksum(payload.target_checksum, writer.checksum())?;
+
+    *control.slot_mut(slot) = SlotInfo {
+        bootable: true,
+        successful: false,
+        tries_remaining: BOOT_TRIES,
+        image_size: payload.target_size,
+        image_checksum: payload.target_checksum,
+    };
+    control.active = slot;
+    persist(control)?;
+    Ok(slot)
+}
+
+fn read_version(stream: &mut BufferStream) -> Result<()> {
+    let version = stream.read_u8()?;
+    if version == VERSION {
+        Ok(())
+    } else {
+        Err(invalid(&format!(
+            "Unsupported OTA format version {version}"
+        )))
+    }
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn verify(expected: u32, data: &[u8]) -> Result<()> {
+    verify_checksum(expected, crc32(data))
+}
+
+fn verify_checksum(expected: u32, actual: u32) -> Result<()> {
+    if expected == actual {
+        Ok(())
+    } else {
+        Err(GDeltaError::ChecksumMismatch { expected, actual })
+    }
+}
+
+fn invalid(message: &str) -> GDeltaError {
+    GDeltaError::InvalidDelta(message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn images() -> (Vec<u8>, Vec<u8>) {
+        let v1: Vec<u8> = (0..50_000u32).map(|i| (i * 13 % 251) as u8).collect();
+        let mut v2 = v1.clone();
+        v2[25_000..25_100].fill(0x42);
+        v2.extend_from_slice(b"new feature");
+        (v1, v2)
+    }
+
+    #[test]
+    fn test_install_boot_and_rollback() {
+        let (v1, v2) = images();
+        let mut control = BootControl::new(&v1);
+        let payload = Payload::from_bytes(&Payload::new(&v2, &v1).unwrap().to_bytes()).unwrap();
+
+        // The active slot is a partition, longer than the image
+        let mut slot_a = v1.clone();
+        slot_a.resize(64 * 1024, 0xFF);
+        let mut slot_b = Vec::new();
+        let mut persisted = Vec::new();
+        let slot = install(&payload, &slot_a, &mut slot_b, &mut control, |control| {
+            persisted.push(control.to_bytes());
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(slot, Slot::B);
+        assert_eq!(slot_b, v2);
+        assert!(control.slot(Slot::B).verify(&slot_b));
+
+        // The first write hits storage with slot B unbootable
+        let before = BootControl::from_bytes(&persisted[0]).unwrap();
+        assert_eq!(before.active, Slot::A);
+        assert!(!before.slot(Slot::B).bootable);
+        assert_eq!(BootControl::from_bytes(&persisted[1]).unwrap(), control);
+
+        // The new image never reports success, so A takes over again
+        for _ in 0..BOOT_TRIES {
+            assert_eq!(control.boot(), Some(Slot::B));
+        }
+        assert_eq!(control.boot(), Some(Slot::A));
+        assert!(!control.slot(Slot::B).bootable);
+    }
+
+    #[test]
+    fn test_successful_slot_stays_active() {
+        let (v1, v2) = images();
+        let mut control = BootControl::new(&v1);
+        let payload = Payload::new(&v2, &v1).unwrap();
+        install(&payload, &v1, Vec::new(), &mut control, |_| Ok(())).unwrap();
+
+        assert_eq!(control.boot(), Some(Slot::B));
+        control.mark_successful();
+        for _ in 0..10 {
+            assert_eq!(control.boot(), Some(Slot::B));
+        }
+    }
+
+    #[test]
+    fn test_failed_install_keeps_active_slot() {
+        let (v1, v2) = images();
+        let mut control = BootControl::new(&v1);
+        let mut payload = Payload::new(&v2, &v1).unwrap();
+
+        // Not the image the payload was made for
+        let mut other = v1.clone();
+        other[0] ^= 1;
+        assert!(matches!(
+            install(&payload, &other, Vec::new(), &mut control, |_| Ok(())),
+            Err(GDeltaError::ChecksumMismatch { .. })
+        ));
+
+        // The decoded image does not verify
+        payload.target_checksum ^= 1;
+        assert!(matches!(
+            install(&payload, &v1, Vec::new(), &mut control, |_| Ok(())),
+            Err(GDeltaError::ChecksumMismatch { .. })
+        ));
+        assert_eq!(control.active, Slot::A);
+        assert!(!control.slot(Slot::B).bootable);
+        assert_eq!(control.boot(), Some(Slot::A));
+    }
+
+    #[test]
+    fn test_rejects_damaged_boot_control() {
+        let mut bytes = BootControl::new(b"image").to_bytes();
+        assert_eq!(bytes.len(), BOOT_CONTROL_SIZE);
+        bytes[8] ^= 1;
+        assert!(matches!(
+            BootControl::from_bytes(&bytes),
+            Err(GDeltaError::ChecksumMismatch { .. })
+        ));
+    }
+}
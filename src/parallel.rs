@@ -0,0 +1,153 @@
+//! Encoding fixed windows of the target independently.
+//!
+//! The target is cut every [`WINDOW_SIZE`] bytes and each window is encoded
+//! against the whole base with its own clone of one indexed matcher. The
+//! raw deltas are stored in window order as the segments of a segmented
+//! delta, so the output only depends on the inputs and options: windows are
+//! spread over threads with the `rayon` feature, and encoded one after the
+//! other without it.
+
+use crate::buffer::BufferStream;
+use crate::delta::encode_window;
+use crate::error::{GDeltaError, Result};
+use crate::header::DeltaHeader;
+use crate::matcher::Matcher;
+use crate::options::EncodeOptions;
+use crate::varint::write_varint;
+use std::ops::Range;
+
+/// Target bytes per window.
+const WINDOW_SIZE: usize = 1024 * 1024;
+
+/// Encodes `new_data` window by window; see [`EncodeOptions::parallel`].
+pub fn encode<M>(
+    new_data: &[u8],
+    base_data: &[u8],
+    mut matcher: M,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>>
+where
+    M: Matcher + Clone + Sync,
+{
+    options.validate()?;
+    if new_data == base_data {
+        return Ok(DeltaHeader::identity(options, new_data).wrap(&[]));
+    }
+
+    matcher.index(base_data, 0, base_data.len());
+    options.check_deadline()?;
+
+    // An empty target still gets one (empty) segment
+    let windows: Vec<Range<usize>> = (0..new_data.len().max(1))
+        .step_by(WINDOW_SIZE)
+        .map(|start| start..new_data.len().min(start + WINDOW_SIZE))
+        .collect();
+    let encode_one = |window: Range<usize>| {
+        // A fresh clone per window, so no matcher state carries over
+        encode_window(new_data, base_data, window, &mut matcher.clone(), options)
+    };
+
+    #[cfg(feature = "rayon")]
+    let segments: Vec<Vec<u8>> = {
+        use rayon::prelude::*;
+        windows
+            .into_par_iter()
+            .map(encode_one)
+            .collect::<Result<_>>()?
+    };
+    #[cfg(not(feature = "rayon"))]
+    let segments: Vec<Vec<u8>> = windows.into_iter().map(encode_one).collect::<Result<_>>()?;
+
+    let mut payload = BufferStream::with_capacity(segments.iter().map(|s| s.len() + 4).sum());
+    for segment in &segments {
+        write_varint(&mut payload, segment.len() as u64);
+        payload.write_bytes(segment);
+    }
+
+    let header = DeltaHeader::for_options(options, new_data, base_data)
+        .expect("parallel encoding always writes a segmented header");
+    let delta = header.wrap(payload.as_slice());
+    if options
+        .size_limit(new_data.len())
+        .is_some_and(|limit| delta.len() > limit)
+    {
+        return Err(GDeltaError::NotBeneficial);
+    }
+    Ok(delta)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A base of numbered lines and a target spanning several windows with
+    /// edits, moved blocks and repeats.
+    fn sample() -> (Vec<u8>, Vec<u8>) {
+        let base: Vec<u8> = (0..120_000u32)
+            .flat_map(|i| format!("line {i} of the base\n").into_bytes())
+            .collect();
+        let mut new = base.clone();
+        new[500_000..500_010].copy_from_slice(b"0123456789");
+        let moved = base[100_000..300_000].to_vec();
+        new.splice(2_000_000..2_000_000, moved.iter().copied());
+        new.extend_from_slice(&moved);
+        (base, new)
+    }
+
+    #[test]
+    fn test_windows_roundtrip() {
+        let (base, new) = sample();
+        assert!(new.len() > 2 * WINDOW_SIZE);
+        for options in [
+            EncodeOptions::new().parallel(true),
+            EncodeOptions::new()
+                .parallel(true)
+                .checksum(true)
+                .store_target_size(true)
+                .target_matches(true),
+            EncodeOptions::new().parallel(true).text_mode(true),
+        ] {
+            let delta = crate::encode_with_options(&new, &base, &options).unwrap();
+            assert_eq!(crate::decode(&delta, &base).unwrap(), new);
+            assert!(delta.len() < new.len() / 50);
+        }
+
+        let options = EncodeOptions::new().parallel(true);
+        for (new, base) in [(&b""[..], &b"base"[..]), (b"new", b""), (b"same", b"same")] {
+            let delta = crate::encode_with_options(new, base, &options).unwrap();
+            assert_eq!(crate::decode(&delta, base).unwrap(), new);
+        }
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_output_does_not_depend_on_threads() {
+        let (base, new) = sample();
+        let options = EncodeOptions::new().parallel(true).text_mode(true);
+        let encode_on = |threads| {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap()
+                .install(|| crate::encode_with_options(&new, &base, &options).unwrap())
+        };
+
+        let serial = encode_on(1);
+        for threads in [2, 3, 8] {
+            assert_eq!(encode_on(threads), serial, "{threads} threads");
+        }
+    }
+
+    #[test]
+    fn test_rejects_conflicting_options() {
+        for options in [
+            EncodeOptions::new().parallel(true).rsyncable(true),
+            EncodeOptions::new().parallel(true).copy_window(4096),
+        ] {
+            assert!(matches!(
+                crate::encode_with_options(b"new", b"base", &options),
+                Err(GDeltaError::InvalidOptions(_))
+            ));
+        }
+    }
+}
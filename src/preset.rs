@@ -0,0 +1,167 @@
+//! Encoder tuning presets for common kinds of data.
+
+use crate::gear::BASE_SAMPLE_RATE;
+
+/// Bytes at the start of the data that [`Preset::detect`] looks at.
+const DETECT_SAMPLE_SIZE: usize = 64 * 1024;
+
+/// Samples shorter than this are too small to classify.
+const MIN_DETECT_SIZE: usize = 1024;
+
+/// Entropy in bits per byte above which data looks compressed.
+const COMPRESSED_ENTROPY: f64 = 7.5;
+
+/// Share of printable bytes above which data looks like text.
+const TEXT_RATIO: f64 = 0.95;
+
+/// Share of delimiters such as `{`, `"` or `,` above which text looks
+/// structured.
+const STRUCTURE_RATIO: f64 = 0.1;
+
+/// Encoder tuning for a kind of data, selected with
+/// [`crate::EncodeOptions::preset`].
+///
+/// Presets trade encoding speed against delta size by choosing how densely
+/// the base is indexed and how quickly the encoder skips ahead through
+/// regions where it finds no matches. All presets produce regular deltas
+/// that decode with [`crate::decode`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Preset {
+    /// Balanced settings, identical to [`crate::encode`].
+    #[default]
+    Default,
+    /// Source code, logs and other text, where edits leave many short
+    /// matches. Indexes every base position.
+    Text,
+    /// Structured formats such as JSON, XML or CSV with repetitive fields.
+    /// Indexes every other base position.
+    Structured,
+    /// Executables and other uncompressed binary data. Starts skipping
+    /// ahead after long runs without a match.
+    Binary,
+    /// Compressed or encrypted data, where matches are rare and long.
+    /// Samples the base sparsely and skips ahead quickly.
+    Compressed,
+}
+
+impl Preset {
+    /// Picks a preset for data that starts with `sample`.
+    ///
+    /// Only the first 64 KiB are looked at. Their byte histogram decides:
+    /// high entropy means [`Preset::Compressed`], mostly printable bytes
+    /// mean [`Preset::Structured`] if delimiters such as `{`, `"` and `,` are
+    /// frequent and [`Preset::Text`] otherwise, and anything else is
+    /// [`Preset::Binary`]. Samples under 1 KiB get [`Preset::Default`].
+    ///
+    /// ```
+    /// use gdelta::Preset;
+    ///
+    /// let json = br#"{"id": 1, "name": "gdelta", "tags": ["delta", "diff"]}"#.repeat(40);
+    /// assert_eq!(Preset::detect(&json), Preset::Structured);
+    ///
+    /// let prose = b"Delta compression stores only what changed. ".repeat(40);
+    /// assert_eq!(Preset::detect(&prose), Preset::Text);
+    /// ```
+    #[allow(clippy::cast_precision_loss)]
+    pub fn detect(sample: &[u8]) -> Preset {
+        let sample = &sample[..sample.len().min(DETECT_SAMPLE_SIZE)];
+        if sample.len() < MIN_DETECT_SIZE {
+            return Preset::Default;
+        }
+
+        let mut counts = [0usize; 256];
+        for &byte in sample {
+            counts[usize::from(byte)] += 1;
+        }
+        let total = sample.len() as f64;
+        let share = |bytes: &[u8]| {
+            bytes.iter().map(|&b| counts[usize::from(b)]).sum::<usize>() as f64 / total
+        };
+
+        let entropy: f64 = counts
+            .iter()
+            .filter(|&&count| count > 0)
+            .map(|&count| {
+                let p = count as f64 / total;
+                -p * p.log2()
+            })
+            .sum();
+        if entropy >= COMPRESSED_ENTROPY {
+            return Preset::Compressed;
+        }
+
+        // UTF-8 continuation and lead bytes count as text
+        let printable: usize = counts[0x20..=0x7E].iter().sum::<usize>()
+            + counts[0x80..].iter().sum::<usize>()
+            + counts[usize::from(b'\t')]
+            + counts[usize::from(b'\n')]
+            + counts[usize::from(b'\r')];
+        if counts[0] > 0 || (printable as f64 / total) < TEXT_RATIO {
+            return Preset::Binary;
+        }
+        if share(b"{}[]<>\":,") >= STRUCTURE_RATIO {
+            Preset::Structured
+        } else {
+            Preset::Text
+        }
+    }
+
+    /// Distance between base positions inserted into the index.
+    pub(crate) fn sample_rate(self) -> usize {
+        match self {
+            Preset::Default | Preset::Binary => BASE_SAMPLE_RATE,
+            Preset::Text => 1,
+            Preset::Structured => 2,
+            Preset::Compressed => 8,
+        }
+    }
+
+    /// Number of consecutive misses after which the encoder starts
+    /// advancing by more than one byte, as a power of two.
+    ///
+    /// The step grows by one for every further `1 << shift` misses.
+    pub(crate) fn skip_shift(self) -> Option<u32> {
+        match self {
+            Preset::Default | Preset::Text | Preset::Structured => None,
+            Preset::Binary => Some(8),
+            Preset::Compressed => Some(4),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_presets() {
+        let csv: Vec<u8> = (0..2000)
+            .flat_map(|i| format!("{i},user{i},{},active\n", i * 37 % 1000).into_bytes())
+            .collect();
+        assert_eq!(Preset::detect(&csv), Preset::Structured);
+
+        let log: Vec<u8> = (0..500)
+            .flat_map(|i| {
+                format!("2024-05-01 12:00:{:02} INFO request {i} served\n", i % 60).into_bytes()
+            })
+            .collect();
+        assert_eq!(Preset::detect(&log), Preset::Text);
+
+        let mut binary: Vec<u8> = (0..20_000u32).map(|i| (i % 64) as u8).collect();
+        binary[100] = 0;
+        assert_eq!(Preset::detect(&binary), Preset::Binary);
+
+        let mut state = 0x2545_F491_4F6C_DD1Du64;
+        let random: Vec<u8> = (0..100_000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                (state >> 24) as u8
+            })
+            .collect();
+        assert_eq!(Preset::detect(&random), Preset::Compressed);
+
+        assert_eq!(Preset::detect(b"short"), Preset::Default);
+    }
+}
@@ -0,0 +1,439 @@
+//! Applying deltas while they are still being received.
+
+use crate::buffer::BufferStream;
+use crate::checksum::Crc32;
+use crate::delta::{
+    RawDelta, apply_instructions, check_base_checksum, check_target_checksum, check_target_copies,
+    check_target_size, prepare_payload,
+};
+use crate::error::{GDeltaError, Result};
+use crate::header::{self, CHECKSUM_SIZE, DeltaHeader, MAGIC};
+use crate::varint::read_varint;
+use std::io::{self, Write};
+
+/// Applies a delta to a base while the delta is still arriving, writing
+/// each frame of the target as soon as it is complete.
+///
+/// Segmented deltas, as written by [`crate::encode_streams`] or with
+/// [`crate::EncodeOptions::rsyncable`] or [`crate::EncodeOptions::parallel`],
+/// are a sequence of independent frames. Each frame is validated against
+/// the base and the target written so far before any of it is written, so
+/// a frame damaged in transit is never applied halfway. Other deltas are a
+/// single frame, which is applied by [`Self::finish`].
+///
+/// The checksum trailer and the target checksum cover the whole delta and
+/// target, so only [`Self::finish`] can verify them. An updater should
+/// write to an inactive slot or a temporary file and only switch over once
+/// `finish` succeeds. After an error the decoder rejects further input.
+///
+/// Received bytes are passed to [`Self::feed`], or written to the decoder
+/// as a [`Write`], for instance with [`std::io::copy`] from a socket.
+///
+/// # Examples
+///
+/// ```
+/// use gdelta::{EncodeOptions, ProgressiveDecoder, encode_streams};
+/// use std::io::Cursor;
+///
+/// let base = b"The quick brown fox jumps over the lazy dog. ".repeat(1000);
+/// let mut new = base.clone();
+/// new[100..110].copy_from_slice(b"0123456789");
+///
+/// let mut delta = Vec::new();
+/// let options = EncodeOptions::new().checksum(true);
+/// encode_streams(&new[..], Cursor::new(&base), &mut delta, &options).unwrap();
+///
+/// let mut decoder = ProgressiveDecoder::new(&base, Vec::new());
+/// for packet in delta.chunks(1500) {
+///     decoder.feed(packet).unwrap();
+/// }
+/// assert_eq!(decoder.finish().unwrap(), new);
+/// ```
+pub struct ProgressiveDecoder<'b, W: Write> {
+    base: &'b [u8],
+    output: W,
+    /// Header bytes once they are complete; empty for a raw delta.
+    head: Option<Vec<u8>>,
+    /// Bytes received but not applied yet.
+    pending: Vec<u8>,
+    /// CRC-32 of the delta bytes applied so far, for the trailer.
+    crc: Crc32,
+    /// CRC-32 of the target written so far, if the header records one.
+    target_crc: Option<Crc32>,
+    /// The target written so far, if frames may copy from it.
+    history: Option<BufferStream>,
+    /// Target size recorded in the header, which no frame may exceed.
+    target_size: Option<u64>,
+    written: u64,
+    failed: bool,
+}
+
+impl<'b, W: Write> ProgressiveDecoder<'b, W> {
+    /// Creates a decoder that applies a delta to `base` and writes the
+    /// target to `output`.
+    pub fn new(base: &'b [u8], output: W) -> Self {
+        Self {
+            base,
+            output,
+            head: None,
+            pending: Vec::new(),
+            crc: Crc32::new(),
+            target_crc: None,
+            history: None,
+            target_size: None,
+            written: 0,
+            failed: false,
+        }
+    }
+
+    /// Number of target bytes written to the output so far.
+    pub fn target_written(&self) -> u64 {
+        self.written
+    }
+
+    /// Takes the next received bytes of the delta and writes every frame
+    /// they complete.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors [`crate::decode`] reports for a malformed frame
+    /// or header, or a wrong base, and any error from writing the output.
+    pub fn feed(&mut self, data: &[u8]) -> Result<()> {
+        self.check_failed()?;
+        self.pending.extend_from_slice(data);
+        let result = self.advance(false);
+        self.failed = result.is_err();
+        result
+    }
+
+    /// Applies the rest of the delta, verifies the checksums and target
+    /// size, and returns the output.
+    ///
+    /// # Errors
+    ///
+    /// Returns `GDeltaError::UnexpectedEndOfData` if the delta ends inside
+    /// a frame, and otherwise the errors [`crate::decode`] reports.
+    pub fn finish(mut self) -> Result<W> {
+        self.check_failed()?;
+        self.advance(true)?;
+        let head = self.head.take().unwrap_or_default();
+        let header = header_of(&head);
+        let has_checksum = header.is_some_and(|header| header.has_checksum());
+
+        let body_len = if has_checksum {
+            self.pending
+                .len()
+                .checked_sub(CHECKSUM_SIZE)
+                .ok_or(GDeltaError::UnexpectedEndOfData)?
+        } else {
+            self.pending.len()
+        };
+        let pending = std::mem::take(&mut self.pending);
+        let (body, trailer) = pending.split_at(body_len);
+
+        if header.is_some_and(|header| header.is_segmented()) {
+            // Every complete frame was applied already
+            if !body.is_empty() {
+                return Err(GDeltaError::UnexpectedEndOfData);
+            }
+        } else {
+            if header.is_some_and(|header| header.is_identity()) && !body.is_empty() {
+                return Err(GDeltaError::InvalidDelta(
+                    "Identity delta has a payload".to_string(),
+                ));
+            }
+            self.crc.update(body);
+            let (raw_deltas, _) = prepare_payload(header, body, self.base.len())?;
+            self.apply(&raw_deltas)?;
+        }
+
+        if has_checksum {
+            let expected = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+            let actual = self.crc.finish();
+            if expected != actual {
+                return Err(GDeltaError::ChecksumMismatch { expected, actual });
+            }
+        }
+        check_target_size(header, self.written)?;
+        if let Some(crc) = self.target_crc {
+            check_target_checksum(header, || crc.finish())?;
+        }
+        self.output.flush()?;
+        Ok(self.output)
+    }
+
+    fn check_failed(&self) -> Result<()> {
+        if self.failed {
+            return Err(GDeltaError::InvalidDelta(
+                "Decoding already failed".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Reads the header once it is complete, then applies the complete
+    /// frames of a segmented delta.
+    fn advance(&mut self, at_end: bool) -> Result<()> {
+        if self.head.is_none() && !self.read_header(at_end)? {
+            return Ok(());
+        }
+        let head = self.head.take().unwrap_or_default();
+        let result = match header_of(&head) {
+            Some(header) if header.is_segmented() => {
+                let trailer = if header.has_checksum() {
+                    CHECKSUM_SIZE
+                } else {
+                    0
+                };
+                self.apply_frames(trailer)
+            }
+            _ => Ok(()),
+        };
+        self.head = Some(head);
+        result
+    }
+
+    /// Parses the header if enough of the delta has arrived, returning
+    /// false to wait for more.
+    fn read_header(&mut self, at_end: bool) -> Result<bool> {
+        // A raw delta cannot be told apart from a header until the magic
+        // has arrived
+        if !at_end && self.pending.len() < MAGIC.len() && MAGIC.starts_with(&self.pending) {
+            return Ok(false);
+        }
+        let len = match header::parse_header(&self.pending) {
+            Ok((None, _)) => 0,
+            Ok((Some(header), len)) => {
+                check_base_checksum(Some(header), self.base)?;
+                check_target_copies(Some(header))?;
+                self.target_size = header.target_size;
+                if header.target_checksum.is_some() {
+                    self.target_crc = Some(Crc32::new());
+                }
+                if header.has_target_copies() {
+                    self.history = Some(BufferStream::with_capacity(0));
+                }
+                len
+            }
+            Err(GDeltaError::UnexpectedEndOfData) if !at_end => return Ok(false),
+            Err(error) => return Err(error),
+        };
+        let head: Vec<u8> = self.pending.drain(..len).collect();
+        self.crc.update(&head);
+        self.head = Some(head);
+        Ok(true)
+    }
+
+    /// Applies every complete frame, leaving the last `trailer` bytes
+    /// received in case they are the checksum trailer.
+    fn apply_frames(&mut self, trailer: usize) -> Result<()> {
+        let pending = std::mem::take(&mut self.pending);
+        let available = pending.len().saturating_sub(trailer);
+        let mut consumed = 0;
+        let result = loop {
+            let mut stream = BufferStream::from_slice(&pending[consumed..available]);
+            let len = match read_varint(&mut stream) {
+                Ok(len) => len,
+                Err(GDeltaError::UnexpectedEndOfData) => break Ok(()),
+                Err(error) => break Err(error),
+            };
+            let start = consumed + stream.position();
+            let Some(end) = usize::try_from(len)
+                .ok()
+                .and_then(|len| start.checked_add(len))
+                .filter(|&end| end <= available)
+            else {
+                break Ok(());
+            };
+
+            let target_copies = self.history.is_some();
+            let applied = prepare_payload(None, &pending[start..end], self.base.len()).and_then(
+                |(mut raw_deltas, _)| {
+                    for raw in &mut raw_deltas {
+                        raw.target_copies = target_copies;
+                    }
+                    self.apply(&raw_deltas)
+                },
+            );
+            if let Err(error) = applied {
+                break Err(error);
+            }
+            self.crc.update(&pending[consumed..end]);
+            consumed = end;
+        };
+        self.pending = pending;
+        self.pending.drain(..consumed);
+        result
+    }
+
+    /// Applies validated deltas and writes what they produce.
+    fn apply(&mut self, raw_deltas: &[RawDelta<'_>]) -> Result<()> {
+        for raw in raw_deltas {
+            let size = raw.validate()?;
+            if let Some(expected) = self.target_size
+                && self.written.saturating_add(size) > expected
+            {
+                return Err(GDeltaError::SizeMismatch {
+                    expected: usize::try_from(expected).unwrap_or(usize::MAX),
+                    actual: usize::try_from(self.written.saturating_add(size))
+                        .unwrap_or(usize::MAX),
+                });
+            }
+            let size = usize::try_from(size).unwrap_or(usize::MAX);
+            let mut scratch;
+            let (output, start) = match &mut self.history {
+                Some(history) => {
+                    let start = history.len();
+                    (history, start)
+                }
+                None => {
+                    scratch =
+                        BufferStream::with_capacity(size.min(self.base.len() + raw.data.len()));
+                    (&mut scratch, 0)
+                }
+            };
+            apply_instructions(
+                &raw.instructions,
+                raw.data,
+                self.base,
+                raw.target_copies,
+                output,
+            )
+            .map_err(|failure| failure.error)?;
+
+            let produced = &output.as_slice()[start..];
+            self.output.write_all(produced)?;
+            if let Some(crc) = &mut self.target_crc {
+                crc.update(produced);
+            }
+            self.written += produced.len() as u64;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for ProgressiveDecoder<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.feed(buf).map_err(io::Error::other)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+/// Returns the header stored by [`ProgressiveDecoder::read_header`], which
+/// was already parsed once.
+fn header_of(head: &[u8]) -> Option<DeltaHeader<'_>> {
+    header::parse_header(head)
+        .ok()
+        .and_then(|(header, _)| header)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EncodeOptions;
+
+    fn sample() -> (Vec<u8>, Vec<u8>) {
+        let mut state = 0x9E37_79B9_7F4A_7C15u64;
+        let base: Vec<u8> = (0..600_000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                (state >> 56) as u8
+            })
+            .collect();
+        let mut new = base.clone();
+        new[1_000..1_010].copy_from_slice(b"0123456789");
+        new.drain(300_000..300_100);
+        new.extend_from_slice(b"appended");
+        (base, new)
+    }
+
+    fn decode_in_chunks(delta: &[u8], base: &[u8], chunk: usize) -> Result<Vec<u8>> {
+        let mut decoder = ProgressiveDecoder::new(base, Vec::new());
+        for packet in delta.chunks(chunk) {
+            decoder.feed(packet)?;
+        }
+        decoder.finish()
+    }
+
+    #[test]
+    fn test_decodes_all_formats_in_chunks() {
+        let (base, new) = sample();
+        for options in [
+            EncodeOptions::new(),
+            EncodeOptions::new().checksum(true).store_target_size(true),
+            EncodeOptions::new().rsyncable(true).target_checksum(true),
+            EncodeOptions::new()
+                .rsyncable(true)
+                .checksum(true)
+                .target_matches(true)
+                .base_checksum(true)
+                .metadata(1, b"image.bin"),
+        ] {
+            let delta = crate::encode_with_options(&new, &base, &options).unwrap();
+            for chunk in [1, 7, 1500, delta.len()] {
+                assert_eq!(
+                    decode_in_chunks(&delta, &base, chunk).unwrap(),
+                    new,
+                    "{options:?} {chunk}"
+                );
+            }
+        }
+
+        let identity = crate::encode_with_options(&base, &base, &EncodeOptions::new()).unwrap();
+        assert_eq!(decode_in_chunks(&identity, &base, 3).unwrap(), base);
+    }
+
+    #[test]
+    fn test_writes_frames_as_they_arrive() {
+        let (base, new) = sample();
+        let options = EncodeOptions::new().rsyncable(true).checksum(true);
+        let delta = crate::encode_with_options(&new, &base, &options).unwrap();
+
+        let mut decoder = ProgressiveDecoder::new(&base, Vec::new());
+        let (head, tail) = delta.split_at(delta.len() * 2 / 3);
+        decoder.feed(head).unwrap();
+        let early = decoder.target_written();
+        assert!(early > 0 && early < new.len() as u64);
+        io::copy(&mut &tail[..], &mut decoder).unwrap();
+        assert_eq!(decoder.finish().unwrap(), new);
+    }
+
+    #[test]
+    fn test_rejects_damaged_frames_and_trailers() {
+        let (base, new) = sample();
+        let options = EncodeOptions::new().rsyncable(true).checksum(true);
+        let delta = crate::encode_with_options(&new, &base, &options).unwrap();
+
+        // A frame pointing outside the base fails before it is written
+        let mut decoder = ProgressiveDecoder::new(&base[..1000], Vec::new());
+        assert!(decoder.feed(&delta).is_err());
+        assert!(decoder.feed(&[]).is_err());
+
+        assert!(matches!(
+            decode_in_chunks(&delta[..delta.len() - 1], &base, 100),
+            Err(GDeltaError::UnexpectedEndOfData)
+        ));
+        let mut corrupted = delta.clone();
+        let last = corrupted.len() - 1;
+        corrupted[last] ^= 1;
+        assert!(matches!(
+            decode_in_chunks(&corrupted, &base, 100),
+            Err(GDeltaError::ChecksumMismatch { .. })
+        ));
+
+        let options = EncodeOptions::new()
+            .store_target_size(true)
+            .base_checksum(true);
+        let delta = crate::encode_with_options(&new, &base, &options).unwrap();
+        assert!(matches!(
+            decode_in_chunks(&delta, &base[1..], 100),
+            Err(GDeltaError::ChecksumMismatch { .. })
+        ));
+    }
+}
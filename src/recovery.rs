@@ -0,0 +1,276 @@
+//! Best-effort decoding of damaged deltas.
+
+use crate::buffer::{BufferStream, INIT_BUFFER_SIZE};
+use crate::delta::{apply_instructions, check_target_copies};
+use crate::error::GDeltaError;
+use crate::header;
+use crate::varint::{read_delta_unit, read_varint};
+
+/// Location and cause of the first problem found while decoding.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodeFailure {
+    /// Index of the instruction that could not be applied, if decoding got
+    /// as far as the instruction stream.
+    pub instruction_index: Option<usize>,
+    /// Byte offset in the delta where the problem was detected.
+    pub delta_offset: usize,
+    /// Number of output bytes recovered before the failure.
+    pub output_offset: usize,
+    /// The underlying error.
+    pub error: GDeltaError,
+}
+
+/// Result of [`crate::decode_lossy`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LossyDecode {
+    /// The longest prefix of the target that could be reconstructed.
+    pub output: Vec<u8>,
+    /// Whether the checksum trailer matched, or `None` if the delta has none.
+    pub checksum_valid: Option<bool>,
+    /// The first failure encountered, or `None` if every instruction applied.
+    pub failure: Option<DecodeFailure>,
+}
+
+impl LossyDecode {
+    /// Returns true if the delta decoded fully and passed all checks.
+    pub fn is_complete(&self) -> bool {
+        self.failure.is_none() && self.checksum_valid != Some(false)
+    }
+}
+
+pub fn decode_lossy(delta: &[u8], base_data: &[u8]) -> LossyDecode {
+    let mut result = LossyDecode {
+        output: Vec::new(),
+        checksum_valid: None,
+        failure: None,
+    };
+    let fail = |delta_offset, output_offset, error| DecodeFailure {
+        instruction_index: None,
+        delta_offset,
+        output_offset,
+        error,
+    };
+
+    let framed = match header::parse(delta) {
+        Ok(framed) => framed,
+        Err(error) => {
+            result.failure = Some(fail(0, 0, error));
+            return result;
+        }
+    };
+    result.checksum_valid = framed.checksum.map(|(expected, actual)| expected == actual);
+
+    // Keep going on a checksum mismatch: the damage may lie past the part
+    // of the delta we can still use.
+    let mut output = BufferStream::with_capacity(INIT_BUFFER_SIZE);
+    let mut instructions = 0;
+    let segmented = framed.header.is_some_and(|header| header.is_segmented());
+    let target_copies = framed
+        .header
+        .is_some_and(|header| header.has_target_copies());
+    if let Err(error) = check_target_copies(framed.header) {
+        result.failure = Some(fail(0, 0, error));
+        return result;
+    }
+    // Target copies can expand without bound, so their output is capped at
+    // the recorded size
+    let limit = framed
+        .header
+        .and_then(|header| header.target_size)
+        .filter(|_| target_copies);
+    let payload = framed.payload;
+    let mut stream = BufferStream::from_slice(payload);
+
+    // The identity delta has no instructions and stands for the whole base
+    let identity = framed.header.is_some_and(|header| header.is_identity());
+    if identity {
+        output.write_bytes(base_data);
+    }
+
+    while !identity && result.failure.is_none() && (stream.remaining() > 0 || !segmented) {
+        let (start, end) = if segmented {
+            let segment_len = match read_varint(&mut stream) {
+                Ok(len) => usize::try_from(len).unwrap_or(usize::MAX),
+                Err(error) => {
+                    let offset = framed.payload_offset + stream.position();
+                    result.failure = Some(fail(offset, output.len(), error));
+                    break;
+                }
+            };
+            let start = stream.position();
+            (start, start.saturating_add(segment_len))
+        } else {
+            (0, payload.len())
+        };
+
+        // A truncated delta still yields the instructions that are present
+        let available = &payload[start..end.min(payload.len())];
+        result.failure = recover_raw(
+            available,
+            base_data,
+            target_copies,
+            limit,
+            &mut output,
+            &mut instructions,
+            framed.payload_offset + start,
+        );
+        if result.failure.is_none() && end > payload.len() {
+            result.failure = Some(fail(
+                delta.len(),
+                output.len(),
+                GDeltaError::UnexpectedEndOfData,
+            ));
+        }
+        if !segmented {
+            break;
+        }
+        stream.set_position(end.min(payload.len()));
+    }
+
+    if result.failure.is_none() {
+        result.failure = framed
+            .header
+            .and_then(|header| header.target_size)
+            .filter(|&expected| expected != output.len() as u64)
+            .map(|expected| {
+                fail(
+                    delta.len(),
+                    output.len(),
+                    GDeltaError::SizeMismatch {
+                        expected: usize::try_from(expected).unwrap_or(usize::MAX),
+                        actual: output.len(),
+                    },
+                )
+            });
+    }
+    result.output = output.into_vec();
+    result
+}
+
+/// Applies as much of a raw delta starting at `delta_offset` as possible.
+///
+/// Fails without applying anything if the instructions would take the
+/// output past `limit` bytes.
+fn recover_raw(
+    raw: &[u8],
+    base_data: &[u8],
+    target_copies: bool,
+    limit: Option<u64>,
+    output: &mut BufferStream,
+    instructions: &mut usize,
+    delta_offset: usize,
+) -> Option<DecodeFailure> {
+    let mut stream = BufferStream::from_slice(raw);
+    let instruction_len = match read_varint(&mut stream) {
+        Ok(len) => usize::try_from(len).unwrap_or(usize::MAX),
+        Err(error) => {
+            return Some(DecodeFailure {
+                instruction_index: None,
+                delta_offset,
+                output_offset: output.len(),
+                error,
+            });
+        }
+    };
+    let inst_start = stream.position();
+    let inst_end = inst_start.saturating_add(instruction_len).min(raw.len());
+
+    if let Some(limit) = limit {
+        let mut units = BufferStream::from_slice(&raw[inst_start..inst_end]);
+        let mut size = output.len() as u64;
+        while let Ok(unit) = read_delta_unit(&mut units) {
+            size = size.saturating_add(unit.length);
+        }
+        if size > limit {
+            return Some(DecodeFailure {
+                instruction_index: None,
+                delta_offset: delta_offset + inst_start,
+                output_offset: output.len(),
+                error: GDeltaError::SizeMismatch {
+                    expected: usize::try_from(limit).unwrap_or(usize::MAX),
+                    actual: usize::try_from(size).unwrap_or(usize::MAX),
+                },
+            });
+        }
+    }
+
+    let applied = apply_instructions(
+        &raw[inst_start..inst_end],
+        &raw[inst_end..],
+        base_data,
+        target_copies,
+        output,
+    );
+    match applied {
+        Err(failure) => Some(DecodeFailure {
+            instruction_index: Some(*instructions + failure.instruction),
+            delta_offset: delta_offset + inst_start + failure.offset,
+            output_offset: output.len(),
+            error: failure.error,
+        }),
+        Ok(count) if inst_end < inst_start.saturating_add(instruction_len) => {
+            *instructions += count;
+            Some(DecodeFailure {
+                instruction_index: None,
+                delta_offset: delta_offset + raw.len(),
+                output_offset: output.len(),
+                error: GDeltaError::UnexpectedEndOfData,
+            })
+        }
+        Ok(count) => {
+            *instructions += count;
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EncodeOptions, encode, encode_with_options};
+
+    #[test]
+    fn test_lossy_decode_of_valid_delta() {
+        let base = b"The quick brown fox jumps over the lazy dog";
+        let new = b"The quick brown cat jumps over the lazy dog";
+        let delta = encode(new, base).unwrap();
+
+        let result = decode_lossy(&delta, base);
+        assert!(result.is_complete());
+        assert_eq!(result.output, new);
+    }
+
+    #[test]
+    fn test_lossy_decode_recovers_prefix() {
+        let base: Vec<u8> = (0..=255).cycle().take(4096).collect();
+        let mut new = base.clone();
+        new[2000..2016].copy_from_slice(b"sixteen new byte");
+
+        let options = EncodeOptions::new().checksum(true);
+        let delta = encode_with_options(&new, &base, &options).unwrap();
+
+        // Cut off the tail: the suffix copy and trailer are lost
+        let truncated = &delta[..delta.len() - 8];
+        let result = decode_lossy(truncated, &base);
+
+        assert!(!result.is_complete());
+        assert_eq!(result.checksum_valid, Some(false));
+        let failure = result.failure.unwrap();
+        assert_eq!(failure.output_offset, result.output.len());
+        assert!(result.output.len() >= 2000);
+        assert_eq!(result.output, new[..result.output.len()]);
+    }
+
+    #[test]
+    fn test_lossy_decode_reports_bad_copy() {
+        // A literal, then a copy beyond the end of the base
+        let delta = [3, 0x02, 0x84, 0x10, b'h', b'i'];
+
+        let result = decode_lossy(&delta, b"base");
+        assert_eq!(result.output, b"hi");
+        let failure = result.failure.unwrap();
+        assert_eq!(failure.instruction_index, Some(1));
+        assert_eq!(failure.delta_offset, 2);
+        assert!(matches!(failure.error, GDeltaError::InvalidDelta(_)));
+    }
+}
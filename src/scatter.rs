@@ -0,0 +1,109 @@
+//! Base data split across multiple buffers.
+
+use crate::delta::{BaseSlices, decode_raw, for_each_slice, has_target_copies, prepare};
+use crate::error::Result;
+
+/// A base made of non-contiguous segments, addressed as if concatenated.
+pub struct ScatteredBase<'a> {
+    parts: &'a [&'a [u8]],
+    /// Offset of each part within the concatenated base.
+    starts: Vec<usize>,
+    len: usize,
+}
+
+impl<'a> ScatteredBase<'a> {
+    pub fn new(parts: &'a [&'a [u8]]) -> Self {
+        let mut starts = Vec::with_capacity(parts.len());
+        let mut len = 0;
+        for part in parts {
+            starts.push(len);
+            len += part.len();
+        }
+        Self { parts, starts, len }
+    }
+}
+
+impl<'a> BaseSlices<'a> for &ScatteredBase<'a> {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn for_each_range(
+        &self,
+        offset: usize,
+        length: usize,
+        sink: &mut dyn FnMut(&'a [u8]) -> Result<()>,
+    ) -> Result<()> {
+        if length == 0 {
+            return Ok(());
+        }
+
+        // Last part starting at or before `offset`; empty parts are skipped
+        // naturally since they contribute no bytes
+        let mut index = self.starts.partition_point(|&start| start <= offset) - 1;
+        let mut offset = offset - self.starts[index];
+        let mut remaining = length;
+
+        while remaining > 0 {
+            let part = self.parts[index];
+            let take = remaining.min(part.len() - offset);
+            if take > 0 {
+                sink(&part[offset..offset + take])?;
+            }
+            remaining -= take;
+            offset = 0;
+            index += 1;
+        }
+        Ok(())
+    }
+}
+
+pub fn decode(delta: &[u8], base_parts: &[&[u8]]) -> Result<Vec<u8>> {
+    let base = ScatteredBase::new(base_parts);
+    let (raw_deltas, _) = prepare(delta, base.len)?;
+
+    // Copies from the target address a contiguous base, so join the parts
+    if has_target_copies(&raw_deltas) {
+        return decode_raw(&raw_deltas, &base_parts.concat());
+    }
+
+    let mut output = Vec::new();
+    for_each_slice(&raw_deltas, &base, |slice| {
+        output.extend_from_slice(slice);
+        Ok(())
+    })?;
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ranges_span_parts() {
+        let parts: [&[u8]; 4] = [b"abc", b"", b"defg", b"h"];
+        let base = ScatteredBase::new(&parts);
+        assert_eq!((&base).len(), 8);
+
+        let mut collected = Vec::new();
+        (&base)
+            .for_each_range(2, 5, &mut |slice| {
+                collected.push(slice.to_vec());
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(collected, [b"c".to_vec(), b"defg".to_vec()]);
+    }
+
+    #[test]
+    fn test_decode_scattered_matches_decode() {
+        let base: Vec<u8> = (0..=255).cycle().take(10_000).collect();
+        let mut new = base.clone();
+        new.splice(5000..5000, b"inserted".iter().copied());
+        new.extend_from_slice(&base[..3000]);
+        let delta = crate::encode(&new, &base).unwrap();
+
+        let parts: Vec<&[u8]> = base.chunks(999).collect();
+        assert_eq!(crate::decode_scattered(&delta, &parts).unwrap(), new);
+    }
+}
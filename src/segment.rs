@@ -0,0 +1,188 @@
+//! Content-defined segmentation of the delta payload.
+//!
+//! A plain delta stores all instructions before all literal data, so a
+//! single edit shifts every byte that follows it. In rsyncable mode the
+//! payload is instead split into independent segments at content-defined cut
+//! points of the target:
+//!
+//! ```text
+//! [segment length: varint][raw delta]...[segment length: varint][raw delta]
+//! ```
+//!
+//! Each segment only depends on the part of the target it reconstructs, so
+//! regenerating a delta after a small change leaves most segments, and thus
+//! most bytes of the delta file, unchanged for rsync or deduplication.
+
+use crate::buffer::{BufferStream, INIT_BUFFER_SIZE};
+use crate::delta::finalize_delta;
+use crate::error::{GDeltaError, Result};
+use crate::gear::{WORD_SIZE, fingerprints};
+use crate::varint::{DeltaUnit, read_delta_unit, read_varint, write_delta_unit, write_varint};
+
+/// Segments are never cut shorter than this, in target bytes.
+const MIN_SEGMENT_SIZE: usize = 16 * 1024;
+
+/// Segments are always cut once they reach this size, in target bytes.
+const MAX_SEGMENT_SIZE: usize = 256 * 1024;
+
+/// A cut is made where these fingerprint bits are all zero (64 KiB average).
+///
+/// The high bits are used because they depend on every byte in the window.
+const CUT_MASK: u64 = 0xFFFF << 48;
+
+/// Returns the content-defined cut points of `data`, in ascending order.
+pub fn cut_points(data: &[u8]) -> Vec<usize> {
+    let mut cuts = Vec::new();
+    let mut last = 0;
+    for (pos, fingerprint) in fingerprints(data) {
+        let end = pos + WORD_SIZE;
+        let len = end - last;
+        if len >= MAX_SEGMENT_SIZE || (len >= MIN_SEGMENT_SIZE && fingerprint & CUT_MASK == 0) {
+            cuts.push(end);
+            last = end;
+        }
+    }
+    cuts.retain(|&cut| cut < data.len());
+    cuts
+}
+
+/// Moves each cut forward to just after the next `delimiter` in `data`, so
+/// segments start at record boundaries.
+pub fn align_cuts(data: &[u8], cuts: &[usize], delimiter: u8) -> Vec<usize> {
+    let mut aligned: Vec<usize> = Vec::with_capacity(cuts.len());
+    for &cut in cuts {
+        let Some(at) = data[cut - 1..].iter().position(|&byte| byte == delimiter) else {
+            break;
+        };
+        let cut = cut + at;
+        if cut >= data.len() {
+            break;
+        }
+        if aligned.last() != Some(&cut) {
+            aligned.push(cut);
+        }
+    }
+    aligned
+}
+
+/// Re-encodes a raw instruction and data stream as segments split at `cuts`.
+///
+/// Instructions crossing a cut are divided into two.
+pub fn segment(
+    instructions: &BufferStream,
+    data: &BufferStream,
+    cuts: &[usize],
+) -> Result<Vec<u8>> {
+    let mut reader = BufferStream::from_slice(instructions.as_slice());
+    let mut literals = BufferStream::from_slice(data.as_slice());
+    let mut output = BufferStream::with_capacity(instructions.len() + data.len() + 16);
+
+    let mut segment_inst = BufferStream::with_capacity(INIT_BUFFER_SIZE);
+    let mut segment_data = BufferStream::with_capacity(INIT_BUFFER_SIZE);
+    let mut cuts = cuts.iter().copied().peekable();
+    let mut pos = 0u64;
+
+    while reader.remaining() > 0 {
+        let mut unit = read_delta_unit(&mut reader)?;
+        while unit.length > 0 {
+            let cut = cuts.peek().map_or(u64::MAX, |&cut| cut as u64);
+            let take = unit.length.min(cut - pos);
+            let part = if unit.is_copy {
+                DeltaUnit::copy(unit.offset, take)
+            } else {
+                segment_data.write_bytes(literals.read_bytes(take as usize)?);
+                DeltaUnit::literal(take)
+            };
+            write_delta_unit(&mut segment_inst, &part);
+
+            pos += take;
+            unit.length -= take;
+            unit.offset += take;
+            if pos == cut {
+                cuts.next();
+                flush(&mut output, &mut segment_inst, &mut segment_data);
+            }
+        }
+    }
+    if !segment_inst.is_empty() || output.is_empty() {
+        flush(&mut output, &mut segment_inst, &mut segment_data);
+    }
+
+    Ok(output.into_vec())
+}
+
+/// Appends the pending segment to `output` and starts a new one.
+fn flush(output: &mut BufferStream, inst: &mut BufferStream, data: &mut BufferStream) {
+    let raw = finalize_delta(inst, data);
+    write_varint(output, raw.len() as u64);
+    output.write_bytes(&raw);
+    *inst = BufferStream::with_capacity(INIT_BUFFER_SIZE);
+    *data = BufferStream::with_capacity(INIT_BUFFER_SIZE);
+}
+
+/// Splits a segmented payload into its raw deltas.
+pub fn split_segments(payload: &[u8]) -> Result<Vec<&[u8]>> {
+    let mut stream = BufferStream::from_slice(payload);
+    let mut segments = Vec::new();
+    while stream.remaining() > 0 {
+        let len = usize::try_from(read_varint(&mut stream)?).unwrap_or(usize::MAX);
+        let start = stream.position();
+        let end = start
+            .checked_add(len)
+            .filter(|&end| end <= payload.len())
+            .ok_or_else(|| GDeltaError::InvalidDelta("Segment exceeds delta size".to_string()))?;
+        segments.push(&payload[start..end]);
+        stream.set_position(end);
+    }
+    Ok(segments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_align_cuts_to_records() {
+        let data = b"aaaa\nbbbb\ncccc\ndddd";
+        assert_eq!(align_cuts(data, &[2, 5, 7, 12, 18], b'\n'), vec![5, 10, 15]);
+    }
+
+    #[test]
+    fn test_cut_points_are_bounded() {
+        let mut state = 1u32;
+        let data: Vec<u8> = (0..2_000_000)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect();
+
+        let cuts = cut_points(&data);
+        assert!(!cuts.is_empty());
+        let mut last = 0;
+        for &cut in &cuts {
+            assert!(cut - last >= MIN_SEGMENT_SIZE && cut - last <= MAX_SEGMENT_SIZE);
+            last = cut;
+        }
+    }
+
+    #[test]
+    fn test_segment_splits_instructions() {
+        let mut inst = BufferStream::with_capacity(16);
+        write_delta_unit(&mut inst, &DeltaUnit::copy(10, 6));
+        write_delta_unit(&mut inst, &DeltaUnit::literal(4));
+        let mut data = BufferStream::with_capacity(4);
+        data.write_bytes(b"wxyz");
+
+        let payload = segment(&inst, &data, &[4, 8]).unwrap();
+        let segments = split_segments(&payload).unwrap();
+        assert_eq!(segments.len(), 3);
+
+        let base = b"0123456789abcdefghij";
+        let output: Vec<u8> = segments
+            .iter()
+            .flat_map(|raw| crate::delta::decode(raw, base).unwrap())
+            .collect();
+        assert_eq!(output, b"abcdefwxyz");
+    }
+}
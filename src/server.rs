@@ -0,0 +1,448 @@
+//! An HTTP service that creates and applies deltas against stored bases.
+//!
+//! Enabled by the `server` feature. [`PatchService`] serves the files of
+//! one directory as bases, each named by its file name:
+//!
+//! - `GET /signature?base=<name>` answers with a JSON object identifying
+//!   the base, so a client can check that its copy is the same before
+//!   asking for or applying a delta:
+//!   `{"base": "...", "size": ..., "blake3": "...", "format_version": ...}`
+//! - `POST /delta?base=<name>` takes the new version as the request body
+//!   and answers with the delta from the base to it
+//! - `POST /apply?base=<name>` takes a delta as the request body and
+//!   answers with the target it produces from the base
+//!
+//! Both `POST` endpoints are built on the streaming APIs: the new version
+//! is encoded with [`crate::encode_streams`] while it is still being
+//! received, deltas are applied with [`crate::decode_from_source`] reading
+//! the base from disk, and responses are sent as they are produced, so the
+//! base is never held in memory whole. A delta to apply is buffered, up to
+//! [`PatchService::delta_limit`] bytes, and checked against
+//! [`PatchService::decode_limits`] before any of the target is produced.
+//! The target is only held in memory whole for deltas that copy from it
+//! (see [`EncodeOptions::target_matches`]), within the output size limit.
+//!
+//! Errors found before any output is sent are answered with `400 Bad
+//! Request`, or `404 Not Found` for an unknown base; an error found later
+//! aborts the response, which clients notice as a truncated body.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use gdelta::server::PatchService;
+//!
+//! # async fn run() -> std::io::Result<()> {
+//! let router = PatchService::new("/srv/releases").router();
+//! let listener = tokio::net::TcpListener::bind("0.0.0.0:8080").await?;
+//! axum::serve(listener, router).await
+//! # }
+//! ```
+
+use crate::error::GDeltaError;
+use crate::naming::ContentHash;
+use crate::options::EncodeOptions;
+use crate::source::SeekSource;
+use crate::untrusted::{DecodeLimits, check_limits};
+use axum::Router;
+use axum::body::{Body, Bytes};
+use axum::extract::{RawQuery, State};
+use axum::http::{StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio_stream::StreamExt;
+use tokio_stream::wrappers::ReceiverStream;
+
+/// Size of the chunks responses are sent in.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Default largest delta `POST /apply` accepts.
+pub const DEFAULT_DELTA_LIMIT: usize = 256 * 1024 * 1024;
+
+/// The configuration of the delta service; see the [module
+/// documentation](self).
+#[derive(Debug, Clone)]
+pub struct PatchService {
+    bases: PathBuf,
+    options: EncodeOptions,
+    delta_limit: usize,
+    decode_limits: DecodeLimits,
+}
+
+impl PatchService {
+    /// Serves the files in `bases` as bases.
+    pub fn new(bases: impl Into<PathBuf>) -> Self {
+        Self {
+            bases: bases.into(),
+            options: EncodeOptions::new(),
+            delta_limit: DEFAULT_DELTA_LIMIT,
+            decode_limits: DecodeLimits::new().require_checksum(false),
+        }
+    }
+
+    /// Encodes deltas with `options`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`GDeltaError::InvalidOptions`] if `options` are rejected by
+    /// [`crate::encode_streams`], so the service never starts with options
+    /// every `POST /delta` would fail on.
+    pub fn encode_options(mut self, options: EncodeOptions) -> crate::Result<Self> {
+        crate::stream::check_options(&options)?;
+        self.options = options;
+        Ok(self)
+    }
+
+    /// Rejects deltas larger than `bytes` sent to `POST /apply` with
+    /// `413 Payload Too Large`. Defaults to [`DEFAULT_DELTA_LIMIT`].
+    #[must_use]
+    pub fn delta_limit(mut self, bytes: usize) -> Self {
+        self.delta_limit = bytes;
+        self
+    }
+
+    /// Rejects deltas sent to `POST /apply` that exceed `limits` with
+    /// `400 Bad Request`, before any of the target is produced. Defaults to
+    /// [`DecodeLimits::new`] without a required checksum, since deltas
+    /// from `POST /delta` only have one if [`Self::encode_options`] asks
+    /// for it.
+    #[must_use]
+    pub fn decode_limits(mut self, limits: DecodeLimits) -> Self {
+        self.decode_limits = limits;
+        self
+    }
+
+    /// Returns the routes of the service, to serve or to nest in a larger
+    /// application.
+    pub fn router(self) -> Router {
+        Router::new()
+            .route("/signature", get(signature))
+            .route("/delta", post(delta))
+            .route("/apply", post(apply))
+            .with_state(Arc::new(self))
+    }
+
+    /// Returns the name and path of the base named in `query`.
+    #[allow(clippy::result_large_err)]
+    fn base(&self, query: Option<&str>) -> Result<(String, PathBuf), Response> {
+        let name = query
+            .unwrap_or_default()
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("base="))
+            .ok_or_else(|| error(StatusCode::BAD_REQUEST, "Missing base parameter"))?;
+        // Plain file names only, which also need no percent-decoding
+        let valid = !name.is_empty()
+            && !name.starts_with('.')
+            && name
+                .bytes()
+                .all(|byte| byte.is_ascii_alphanumeric() || b"._-".contains(&byte));
+        let path = self.bases.join(name);
+        if !valid || !path.is_file() {
+            return Err(error(
+                StatusCode::NOT_FOUND,
+                &format!("Unknown base: {name}"),
+            ));
+        }
+        Ok((name.to_string(), path))
+    }
+}
+
+async fn signature(
+    State(service): State<Arc<PatchService>>,
+    RawQuery(query): RawQuery,
+) -> Response {
+    let (name, path) = match service.base(query.as_deref()) {
+        Ok(base) => base,
+        Err(response) => return response,
+    };
+    let hashed = tokio::task::spawn_blocking(move || -> io::Result<(u64, ContentHash)> {
+        let file = File::open(path)?;
+        let size = file.metadata()?.len();
+        Ok((size, ContentHash::from_reader(BufReader::new(file))?))
+    })
+    .await;
+    match hashed {
+        Ok(Ok((size, hash))) => {
+            // The name is checked to need no escaping
+            let body = format!(
+                "{{\"base\":\"{name}\",\"size\":{size},\"blake3\":\"{hash}\",\
+                 \"format_version\":{},\"min_format_version\":{}}}",
+                crate::FORMAT_VERSION,
+                crate::MIN_FORMAT_VERSION,
+            );
+            ([(header::CONTENT_TYPE, "application/json")], body).into_response()
+        }
+        Ok(Err(e)) => error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+        Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
+
+async fn delta(
+    State(service): State<Arc<PatchService>>,
+    RawQuery(query): RawQuery,
+    body: Body,
+) -> Response {
+    let (_, path) = match service.base(query.as_deref()) {
+        Ok(base) => base,
+        Err(response) => return response,
+    };
+    let new = BodyReader::new(body);
+    stream_response(move |output| {
+        let base = BufReader::new(File::open(path)?);
+        crate::encode_streams(new, base, output, &service.options)
+    })
+    .await
+}
+
+async fn apply(
+    State(service): State<Arc<PatchService>>,
+    RawQuery(query): RawQuery,
+    body: Body,
+) -> Response {
+    let (_, path) = match service.base(query.as_deref()) {
+        Ok(base) => base,
+        Err(response) => return response,
+    };
+    let delta = match axum::body::to_bytes(body, service.delta_limit).await {
+        Ok(delta) => delta,
+        Err(e) => {
+            // Other errors come from the connection, not an oversized delta
+            let e = e.into_inner();
+            if e.is::<http_body_util::LengthLimitError>() {
+                return error(StatusCode::PAYLOAD_TOO_LARGE, "Delta is too large");
+            }
+            return error(
+                StatusCode::BAD_REQUEST,
+                &format!("Failed to receive the delta: {e}"),
+            );
+        }
+    };
+    stream_response(move |output| {
+        let file = File::open(path)?;
+        let base_len = usize::try_from(file.metadata()?.len()).unwrap_or(usize::MAX);
+        check_limits(&delta, base_len, &service.decode_limits)?;
+        let mut base = SeekSource::new(BufReader::new(file))?;
+        crate::decode_from_source(&delta, &mut base, output)
+    })
+    .await
+}
+
+/// Runs `work` on a blocking thread and streams what it writes as the
+/// response body.
+async fn stream_response<F>(work: F) -> Response
+where
+    F: FnOnce(&mut ChannelWriter) -> crate::Result<u64> + Send + 'static,
+{
+    let (sender, mut receiver) = mpsc::channel(4);
+    tokio::task::spawn_blocking(move || {
+        let mut writer = ChannelWriter {
+            sender: sender.clone(),
+            buffer: Vec::with_capacity(CHUNK_SIZE),
+        };
+        let result = work(&mut writer).and_then(|_| Ok(writer.flush()?));
+        if let Err(e) = result {
+            let _ = sender.blocking_send(Err(e));
+        }
+    });
+
+    // Errors before any output get a status of their own
+    match receiver.recv().await {
+        Some(Err(e)) => {
+            let status = match e {
+                GDeltaError::Io(_) | GDeltaError::InvalidOptions(_) => {
+                    StatusCode::INTERNAL_SERVER_ERROR
+                }
+                _ => StatusCode::BAD_REQUEST,
+            };
+            error(status, &e.to_string())
+        }
+        first => {
+            let rest = ReceiverStream::new(receiver);
+            Body::from_stream(tokio_stream::iter(first).chain(rest)).into_response()
+        }
+    }
+}
+
+fn error(status: StatusCode, message: &str) -> Response {
+    (status, format!("{message}\n")).into_response()
+}
+
+/// Sends what is written to it to a response body in chunks.
+struct ChannelWriter {
+    sender: mpsc::Sender<crate::Result<Bytes>>,
+    buffer: Vec<u8>,
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= CHUNK_SIZE {
+            self.flush()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let chunk = Bytes::from(std::mem::replace(
+            &mut self.buffer,
+            Vec::with_capacity(CHUNK_SIZE),
+        ));
+        self.sender
+            .blocking_send(Ok(chunk))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "Client disconnected"))
+    }
+}
+
+/// Reads a request body on a blocking thread while it is received.
+struct BodyReader {
+    receiver: mpsc::Receiver<io::Result<Bytes>>,
+    chunk: Bytes,
+}
+
+impl BodyReader {
+    fn new(body: Body) -> Self {
+        let (sender, receiver) = mpsc::channel(4);
+        tokio::spawn(async move {
+            let mut chunks = body.into_data_stream();
+            while let Some(chunk) = chunks.next().await {
+                if sender.send(chunk.map_err(io::Error::other)).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Self {
+            receiver,
+            chunk: Bytes::new(),
+        }
+    }
+}
+
+impl Read for BodyReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.chunk.is_empty() {
+            match self.receiver.blocking_recv() {
+                Some(chunk) => self.chunk = chunk?,
+                None => return Ok(0),
+            }
+        }
+        let length = buf.len().min(self.chunk.len());
+        buf[..length].copy_from_slice(&self.chunk.split_to(length));
+        Ok(length)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::BufferStream;
+    use crate::delta::finalize_delta;
+    use crate::header::{self, DeltaHeader};
+    use crate::varint::{DeltaUnit, write_delta_unit};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    /// Sends a bare HTTP/1.0 request and returns the status and body.
+    async fn request(address: &str, method: &str, path: &str, body: &[u8]) -> (u16, Vec<u8>) {
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        let head = format!(
+            "{method} {path} HTTP/1.0\r\ncontent-length: {}\r\n\r\n",
+            body.len()
+        );
+        stream.write_all(head.as_bytes()).await.unwrap();
+        stream.write_all(body).await.unwrap();
+
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await.unwrap();
+        let split = response
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .unwrap();
+        let status = String::from_utf8_lossy(&response[9..12]).parse().unwrap();
+        (status, response[split + 4..].to_vec())
+    }
+
+    #[tokio::test]
+    async fn test_service() {
+        let dir = std::env::temp_dir().join(format!("gdelta-server-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let base: Vec<u8> = (0..50_000u32)
+            .flat_map(|i| format!("record {i}\n").into_bytes())
+            .collect();
+        std::fs::write(dir.join("v1.txt"), &base).unwrap();
+        let mut new = base.clone();
+        new[200_000..200_010].copy_from_slice(b"0123456789");
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let router = PatchService::new(&dir).delta_limit(1024 * 1024).router();
+        tokio::spawn(async move { axum::serve(listener, router).await });
+
+        let (status, signature) = request(&address, "GET", "/signature?base=v1.txt", b"").await;
+        assert_eq!(status, 200);
+        let signature = String::from_utf8(signature).unwrap();
+        assert!(signature.contains(&format!("\"size\":{}", base.len())));
+        assert!(signature.contains(&ContentHash::of(&base).to_string()));
+
+        let (status, delta) = request(&address, "POST", "/delta?base=v1.txt", &new).await;
+        assert_eq!(status, 200);
+        assert!(delta.len() < 1000);
+        assert_eq!(crate::decode(&delta, &base).unwrap(), new);
+
+        let (status, target) = request(&address, "POST", "/apply?base=v1.txt", &delta).await;
+        assert_eq!(status, 200);
+        assert_eq!(target, new);
+
+        let (status, _) = request(&address, "POST", "/apply?base=v1.txt", b"\xff\xff").await;
+        assert_eq!(status, 400);
+        for path in ["/signature?base=v2.txt", "/signature?base=../v1.txt"] {
+            assert_eq!(request(&address, "GET", path, b"").await.0, 404, "{path}");
+        }
+        let (status, _) = request(&address, "GET", "/signature", b"").await;
+        assert_eq!(status, 400);
+        let large = vec![0; 2 * 1024 * 1024];
+        let (status, _) = request(&address, "POST", "/apply?base=v1.txt", &large).await;
+        assert_eq!(status, 413);
+
+        // A few bytes repeating one literal into a terabyte target
+        let mut instructions = BufferStream::with_capacity(16);
+        let target_size = 1u64 << 40;
+        write_delta_unit(&mut instructions, &DeltaUnit::literal(1));
+        write_delta_unit(
+            &mut instructions,
+            &DeltaUnit::copy(base.len() as u64, target_size - 1),
+        );
+        let payload = finalize_delta(&instructions, &BufferStream::from_slice(b"a"));
+        let bomb = DeltaHeader {
+            version: header::VERSION,
+            flags: header::FLAG_TARGET_COPIES | header::FLAG_TARGET_SIZE,
+            target_size: Some(target_size),
+            target_checksum: None,
+            base_checksum: None,
+            metadata: &[],
+        }
+        .wrap(&payload);
+        let (status, message) = request(&address, "POST", "/apply?base=v1.txt", &bomb).await;
+        assert_eq!(status, 400);
+        assert!(String::from_utf8_lossy(&message).contains("target size"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_encode_options_rejected_at_startup() {
+        let result = PatchService::new(".").encode_options(EncodeOptions::new().max_ratio(0.9));
+        assert!(matches!(result, Err(GDeltaError::InvalidOptions(_))));
+        assert!(
+            PatchService::new(".")
+                .encode_options(EncodeOptions::new().checksum(true))
+                .is_ok()
+        );
+    }
+}
@@ -0,0 +1,293 @@
+//! Fast similarity estimates between inputs.
+//!
+//! A [`Sketch`] keeps the smallest hashes of all GEAR fingerprints in a
+//! buffer (a bottom-k `MinHash` sketch). Comparing two sketches estimates the
+//! Jaccard similarity of the sets of 8-byte windows in both inputs, which is
+//! a good predictor of how much of one can be copied from the other. Building
+//! a sketch is a single rolling-hash pass, much cheaper than encoding, and a
+//! sketch of a base can be kept and compared against many candidates.
+//!
+//! ```
+//! use gdelta::similarity::Sketch;
+//!
+//! let base = b"The quick brown fox jumps over the lazy dog. ".repeat(20);
+//! let mut new = base.clone();
+//! new[100..110].copy_from_slice(b"0123456789");
+//!
+//! let similarity = Sketch::new(&base).similarity(&Sketch::new(&new));
+//! assert!(similarity > 0.5);
+//! ```
+//!
+//! A [`SimilarityMap`] instead scores each fixed-size region of the new
+//! input by how much of it also occurs in the base, which shows where the
+//! inputs differ without encoding a delta.
+
+use crate::gear::fingerprints;
+use std::collections::{BTreeSet, HashSet};
+use std::ops::Range;
+
+/// Number of hashes kept in a sketch.
+pub const SKETCH_SIZE: usize = 128;
+
+/// Region size of a [`SimilarityMap`] unless another one is chosen.
+pub const DEFAULT_REGION_SIZE: usize = 4096;
+
+/// A [`SimilarityMap`] samples one in this many windows.
+const MAP_SAMPLE_RATE: u64 = 8;
+
+/// A bottom-k `MinHash` sketch of the 8-byte windows of a buffer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sketch {
+    /// Smallest distinct window hashes, in ascending order.
+    hashes: Vec<u64>,
+}
+
+impl Sketch {
+    /// Builds the sketch of `data`.
+    pub fn new(data: &[u8]) -> Self {
+        let mut smallest = BTreeSet::new();
+        let mut threshold = u64::MAX;
+        for (_, fingerprint) in fingerprints(data) {
+            let hash = mix(fingerprint);
+            // Once the sketch is full, almost every window is rejected here
+            if hash >= threshold || !smallest.insert(hash) {
+                continue;
+            }
+            if smallest.len() > SKETCH_SIZE {
+                smallest.pop_last();
+            }
+            if smallest.len() == SKETCH_SIZE {
+                threshold = *smallest.last().unwrap_or(&u64::MAX);
+            }
+        }
+        Self {
+            hashes: smallest.into_iter().collect(),
+        }
+    }
+
+    /// Returns true if the input was too short to contain a full window.
+    pub fn is_empty(&self) -> bool {
+        self.hashes.is_empty()
+    }
+
+    /// Estimates the fraction of windows the two inputs share, from 0.0
+    /// (nothing in common) to 1.0 (the same set of windows).
+    ///
+    /// Two inputs too short to sketch count as identical; one such input
+    /// shares nothing with a longer one.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn similarity(&self, other: &Sketch) -> f64 {
+        if self.is_empty() || other.is_empty() {
+            return if self.is_empty() && other.is_empty() {
+                1.0
+            } else {
+                0.0
+            };
+        }
+
+        // Walk the smallest hashes of the union, counting those in both
+        let (mut a, mut b) = (0, 0);
+        let (mut union, mut shared) = (0, 0);
+        while union < SKETCH_SIZE && (a < self.hashes.len() || b < other.hashes.len()) {
+            match (self.hashes.get(a), other.hashes.get(b)) {
+                (Some(x), Some(y)) if x == y => {
+                    shared += 1;
+                    a += 1;
+                    b += 1;
+                }
+                (Some(x), Some(y)) if x < y => a += 1,
+                (Some(_), None) => a += 1,
+                _ => b += 1,
+            }
+            union += 1;
+        }
+        shared as f64 / union as f64
+    }
+}
+
+/// The similarity of each region of a new input to a base.
+///
+/// Each score is the fraction of the region's 8-byte windows that occur
+/// anywhere in the base, estimated from a sample of one window in eight:
+/// 1.0 for a region a delta can copy entirely, 0.0 for new content. Unlike a
+/// [`Sketch`] comparison it says where the inputs differ, for visualizing
+/// changes or placing chunk boundaries, and it does not depend on how large
+/// the base is.
+///
+/// Building a map takes one rolling-hash pass over each input and keeps the
+/// sampled hashes of the base, about two bytes per base byte.
+///
+/// ```
+/// use gdelta::similarity::{DEFAULT_REGION_SIZE, SimilarityMap};
+///
+/// let base: Vec<u8> = (0..32_768u32).map(|i| (i * 7919 % 251) as u8).collect();
+/// let mut new = base.clone();
+/// new[8192..12_288].fill(b'x');
+///
+/// let map = SimilarityMap::new(&new, &base, DEFAULT_REGION_SIZE);
+/// assert_eq!(map.len(), 8);
+/// assert!(map.scores()[0] > 0.9);
+/// assert!(map.scores()[2] < 0.1);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimilarityMap {
+    region_size: usize,
+    len: usize,
+    scores: Vec<f64>,
+}
+
+impl SimilarityMap {
+    /// Scores `new_data` against `base_data` in regions of `region_size`
+    /// bytes, the last of which may be shorter. A region size of 0 is
+    /// treated as 1.
+    ///
+    /// Regions too short to contain a sampled window score 0.0.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn new(new_data: &[u8], base_data: &[u8], region_size: usize) -> Self {
+        let region_size = region_size.max(1);
+        let base: HashSet<u64> = fingerprints(base_data)
+            .map(|(_, fingerprint)| mix(fingerprint))
+            .filter(|&hash| is_sampled(hash))
+            .collect();
+
+        let regions = new_data.len().div_ceil(region_size);
+        let mut counts = vec![(0usize, 0usize); regions];
+        for (pos, fingerprint) in fingerprints(new_data) {
+            let hash = mix(fingerprint);
+            if is_sampled(hash) {
+                let (sampled, shared) = &mut counts[pos / region_size];
+                *sampled += 1;
+                *shared += usize::from(base.contains(&hash));
+            }
+        }
+
+        let scores = counts
+            .into_iter()
+            .map(|(sampled, shared)| {
+                if sampled == 0 {
+                    0.0
+                } else {
+                    shared as f64 / sampled as f64
+                }
+            })
+            .collect();
+        Self {
+            region_size,
+            len: new_data.len(),
+            scores,
+        }
+    }
+
+    /// Returns the size of the regions.
+    pub fn region_size(&self) -> usize {
+        self.region_size
+    }
+
+    /// Returns the number of regions.
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    /// Returns whether the new input was empty.
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    /// Returns the score of each region, in order.
+    pub fn scores(&self) -> &[f64] {
+        &self.scores
+    }
+
+    /// Iterates over the byte range of each region in the new input with
+    /// its score.
+    pub fn iter(&self) -> impl Iterator<Item = (Range<usize>, f64)> + '_ {
+        self.scores.iter().enumerate().map(|(index, &score)| {
+            let start = index * self.region_size;
+            (start..(start + self.region_size).min(self.len), score)
+        })
+    }
+}
+
+/// Returns whether a mixed window hash is in the sample of a
+/// [`SimilarityMap`].
+fn is_sampled(hash: u64) -> bool {
+    hash % MAP_SAMPLE_RATE == 0
+}
+
+/// Spreads the bits of a fingerprint, whose low bits only depend on the
+/// last bytes of the window.
+fn mix(fingerprint: u64) -> u64 {
+    let hash = fingerprint.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    hash ^ (hash >> 29)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pseudo_random(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                (state >> 32) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_identical_and_unrelated() {
+        let a = pseudo_random(50_000, 1);
+        let b = pseudo_random(50_000, 2);
+        assert_eq!(Sketch::new(&a).similarity(&Sketch::new(&a)), 1.0);
+        assert!(Sketch::new(&a).similarity(&Sketch::new(&b)) < 0.05);
+    }
+
+    #[test]
+    fn test_partial_overlap() {
+        let shared = pseudo_random(30_000, 3);
+        let a = [shared.as_slice(), &pseudo_random(30_000, 4)].concat();
+        let b = [shared.as_slice(), &pseudo_random(30_000, 5)].concat();
+
+        // Half of each input is shared, so a third of the union is
+        let similarity = Sketch::new(&a).similarity(&Sketch::new(&b));
+        assert!((0.2..0.5).contains(&similarity), "{similarity}");
+    }
+
+    #[test]
+    fn test_similarity_map() {
+        let base = pseudo_random(64_000, 6);
+        let new = [
+            &base[..16_384],
+            pseudo_random(8192, 7).as_slice(),
+            &base[40_000..52_000],
+        ]
+        .concat();
+
+        let map = SimilarityMap::new(&new, &base, 4096);
+        assert_eq!(map.len(), 9);
+        for (index, &score) in map.scores().iter().enumerate() {
+            if (4..6).contains(&index) {
+                assert!(score < 0.05, "{index}: {score}");
+            } else {
+                assert!(score > 0.95, "{index}: {score}");
+            }
+        }
+        let ranges: Vec<Range<usize>> = map.iter().map(|(range, _)| range).collect();
+        assert_eq!(ranges[0], 0..4096);
+        assert_eq!(ranges[8], 32_768..new.len());
+
+        assert!(SimilarityMap::new(b"", &base, 4096).is_empty());
+        assert_eq!(SimilarityMap::new(&new, &base, 0).region_size(), 1);
+    }
+
+    #[test]
+    fn test_short_inputs() {
+        let empty = Sketch::new(b"");
+        assert!(empty.is_empty());
+        assert_eq!(empty.similarity(&Sketch::new(b"short")), 1.0);
+        assert_eq!(empty.similarity(&Sketch::new(b"long enough input")), 0.0);
+    }
+}
@@ -0,0 +1,352 @@
+//! Decoding against a base that is read on demand.
+
+use crate::buffer::BufferStream;
+use crate::delta::{has_target_copies, prepare, target_copy_in_bounds};
+use crate::error::{GDeltaError, Result};
+use crate::varint::read_delta_unit;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+
+/// Largest base range read at once, in bytes.
+const MAX_READ_SIZE: u64 = 4 * 1024 * 1024;
+
+/// Base data that is fetched by range instead of being held in memory.
+///
+/// Implement this over a file, a block device or a remote object to decode
+/// with [`crate::decode_from_source`], which only reads the base ranges that
+/// copy instructions reference.
+pub trait BaseSource {
+    /// Total base length in bytes.
+    fn len(&self) -> u64;
+
+    /// Returns true if the base is empty.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Fills `buf` with the base bytes starting at `offset`.
+    ///
+    /// The range always lies within the base.
+    ///
+    /// # Errors
+    ///
+    /// Errors are passed on to the caller of the decode function;
+    /// `GDeltaError::Io` is the natural choice for failed reads.
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<()>;
+}
+
+impl BaseSource for &[u8] {
+    fn len(&self) -> u64 {
+        <[u8]>::len(self) as u64
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        let start = offset as usize;
+        buf.copy_from_slice(&self[start..start + buf.len()]);
+        Ok(())
+    }
+}
+
+/// A [`BaseSource`] over any seekable reader, such as a [`std::fs::File`].
+#[derive(Debug)]
+pub struct SeekSource<R> {
+    reader: R,
+    len: u64,
+}
+
+impl<R: Read + Seek> SeekSource<R> {
+    /// Wraps `reader`, seeking to its end to find the base length.
+    ///
+    /// # Errors
+    ///
+    /// Returns `GDeltaError::Io` if seeking fails.
+    pub fn new(mut reader: R) -> Result<Self> {
+        let len = reader.seek(SeekFrom::End(0))?;
+        Ok(Self { reader, len })
+    }
+
+    /// Returns the wrapped reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R: Read + Seek> BaseSource for SeekSource<R> {
+    fn len(&self) -> u64 {
+        self.len
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        self.reader.seek(SeekFrom::Start(offset))?;
+        self.reader.read_exact(buf)?;
+        Ok(())
+    }
+}
+
+/// A [`BaseSource`] with some base ranges already in memory; built with
+/// [`crate::PrefetchPlan::fetch`].
+///
+/// Reads within the fetched ranges are served from memory, even when they
+/// span several adjacent ranges, and everything else is read from the
+/// wrapped source.
+#[derive(Debug)]
+pub struct PrefetchedBase<S> {
+    source: S,
+    /// Fetched data by base offset, in ascending order.
+    fetched: Vec<(u64, Vec<u8>)>,
+}
+
+impl<S: BaseSource> PrefetchedBase<S> {
+    /// Wraps `source` with the `fetched` ranges, which are sorted and do not
+    /// overlap.
+    pub(crate) fn new(source: S, fetched: Vec<(u64, Vec<u8>)>) -> Self {
+        Self { source, fetched }
+    }
+
+    /// Returns the wrapped source.
+    pub fn into_inner(self) -> S {
+        self.source
+    }
+}
+
+impl<S: BaseSource> BaseSource for PrefetchedBase<S> {
+    fn len(&self) -> u64 {
+        self.source.len()
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset + done as u64;
+            // The last fetched range starting at or before `pos`
+            let index = self.fetched.partition_point(|(start, _)| *start <= pos);
+            let cached = index
+                .checked_sub(1)
+                .map(|index| &self.fetched[index])
+                .filter(|(start, data)| pos < start + data.len() as u64);
+            let length = match cached {
+                Some((start, data)) => {
+                    let data = &data[(pos - start) as usize..];
+                    let length = data.len().min(buf.len() - done);
+                    buf[done..done + length].copy_from_slice(&data[..length]);
+                    length
+                }
+                None => {
+                    // Read up to the next fetched range
+                    let next = self
+                        .fetched
+                        .get(index)
+                        .map_or(u64::MAX, |(start, _)| *start);
+                    let length = (buf.len() - done).min((next - pos) as usize);
+                    self.source.read_at(pos, &mut buf[done..done + length])?;
+                    length
+                }
+            };
+            done += length;
+        }
+        Ok(())
+    }
+}
+
+pub fn decode_from_source<S, W>(delta: &[u8], base: &mut S, output: W) -> Result<u64>
+where
+    S: BaseSource + ?Sized,
+    W: Write,
+{
+    let base_len = usize::try_from(base.len()).map_err(|_| {
+        GDeltaError::BufferError(format!("Base of {} bytes is too large", base.len()))
+    })?;
+    let (raw_deltas, _) = prepare(delta, base_len)?;
+
+    let mut decoder = SourceDecoder {
+        base,
+        output,
+        // Copies from the target need everything written so far
+        history: has_target_copies(&raw_deltas).then(Vec::new),
+        pending: 0..0,
+        buffer: Vec::new(),
+        written: 0,
+    };
+    for raw in &raw_deltas {
+        let mut instructions = BufferStream::from_slice(&raw.instructions);
+        let mut literal_pos = 0;
+
+        while instructions.remaining() > 0 {
+            let unit = read_delta_unit(&mut instructions)?;
+            if unit.is_copy && raw.target_copies && unit.offset >= base_len as u64 {
+                decoder.copy_from_target(unit.offset - base_len as u64, unit.length)?;
+            } else if unit.is_copy {
+                if unit.offset.saturating_add(unit.length) > base_len as u64 {
+                    return Err(GDeltaError::InvalidDelta(format!(
+                        "Copy offset {} + length {} exceeds base size {base_len}",
+                        unit.offset, unit.length
+                    )));
+                }
+                decoder.copy(unit.offset, unit.length)?;
+            } else {
+                // Literal lengths were validated against the data section
+                let length = unit.length as usize;
+                decoder.flush_copies()?;
+                decoder.write(&raw.data[literal_pos..literal_pos + length])?;
+                literal_pos += length;
+            }
+        }
+    }
+    decoder.flush_copies()?;
+    decoder.output.flush()?;
+    Ok(decoder.written)
+}
+
+/// Output state of [`decode_from_source`].
+struct SourceDecoder<'s, S: ?Sized, W> {
+    base: &'s mut S,
+    output: W,
+    history: Option<Vec<u8>>,
+    /// Base range of consecutive copies not read yet.
+    pending: Range<u64>,
+    buffer: Vec<u8>,
+    written: u64,
+}
+
+impl<S: BaseSource + ?Sized, W: Write> SourceDecoder<'_, S, W> {
+    /// Queues a base copy, merging it with the previous one if the ranges
+    /// are adjacent so they are fetched with a single read.
+    fn copy(&mut self, offset: u64, length: u64) -> Result<()> {
+        if self.pending.end != offset || self.pending.is_empty() {
+            self.flush_copies()?;
+            self.pending = offset..offset;
+        }
+        self.pending.end += length;
+        Ok(())
+    }
+
+    /// Reads and writes the queued base range.
+    fn flush_copies(&mut self) -> Result<()> {
+        while !self.pending.is_empty() {
+            let length = (self.pending.end - self.pending.start).min(MAX_READ_SIZE);
+            let mut buffer = std::mem::take(&mut self.buffer);
+            buffer.resize(length as usize, 0);
+            self.base.read_at(self.pending.start, &mut buffer)?;
+            self.write(&buffer)?;
+            self.buffer = buffer;
+            self.pending.start += length;
+        }
+        Ok(())
+    }
+
+    fn copy_from_target(&mut self, offset: u64, length: u64) -> Result<()> {
+        self.flush_copies()?;
+        let history = self.history.get_or_insert_with(Vec::new);
+        let history_len = history.len() as u64;
+        if !target_copy_in_bounds(offset, length, history_len) {
+            return Err(GDeltaError::InvalidDelta(format!(
+                "Target copy offset {offset} is past output size {history_len}"
+            )));
+        }
+        // An overlapping copy reads back what it has just written
+        let mut copied = 0;
+        while copied < length {
+            let start = (offset + copied) as usize;
+            let chunk = (length - copied).min(history.len() as u64 - start as u64) as usize;
+            let range = start..start + chunk;
+            self.output.write_all(&history[range.clone()])?;
+            history.extend_from_within(range);
+            copied += chunk as u64;
+        }
+        self.written += length;
+        Ok(())
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        self.output.write_all(data)?;
+        if let Some(history) = &mut self.history {
+            history.extend_from_slice(data);
+        }
+        self.written += data.len() as u64;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EncodeOptions;
+
+    /// Records the ranges read from a slice.
+    struct Recording<'a> {
+        base: &'a [u8],
+        reads: Vec<Range<u64>>,
+    }
+
+    impl BaseSource for Recording<'_> {
+        fn len(&self) -> u64 {
+            self.base.len() as u64
+        }
+
+        fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<()> {
+            self.reads.push(offset..offset + buf.len() as u64);
+            (&mut self.base).read_at(offset, buf)
+        }
+    }
+
+    #[test]
+    fn test_reads_only_referenced_ranges() {
+        let base: Vec<u8> = (0..100_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let mut new = base[60_000..70_000].to_vec();
+        new.extend_from_slice(b"inserted text");
+        new.extend_from_slice(&base[10_000..12_000]);
+
+        let delta = crate::encode(&new, &base).unwrap();
+        let mut source = Recording {
+            base: &base,
+            reads: Vec::new(),
+        };
+        let mut output = Vec::new();
+        assert_eq!(
+            decode_from_source(&delta, &mut source, &mut output).unwrap(),
+            new.len() as u64
+        );
+        assert_eq!(output, new);
+        let fetched: u64 = source
+            .reads
+            .iter()
+            .map(|range| range.end - range.start)
+            .sum();
+        assert!(fetched <= 12_000);
+    }
+
+    #[test]
+    fn test_prefetched_base() {
+        let base: Vec<u8> = (0..10_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let source = Recording {
+            base: &base,
+            reads: Vec::new(),
+        };
+        let fetched = vec![
+            (1_000, base[1_000..2_000].to_vec()),
+            (2_000, base[2_000..3_000].to_vec()),
+            (5_000, base[5_000..6_000].to_vec()),
+        ];
+        let mut prefetched = PrefetchedBase::new(source, fetched);
+
+        let mut buf = vec![0; 1_500];
+        prefetched.read_at(1_200, &mut buf).unwrap();
+        assert_eq!(buf, base[1_200..2_700]);
+        let mut buf = vec![0; 4_000];
+        prefetched.read_at(2_500, &mut buf).unwrap();
+        assert_eq!(buf, base[2_500..6_500]);
+        assert_eq!(prefetched.into_inner().reads, [3_000..5_000, 6_000..6_500]);
+    }
+
+    #[test]
+    fn test_target_copies() {
+        let base = b"unrelated base data".repeat(10);
+        let new = b"abcdefghijklmnopqrstuvwxyz0123456789".repeat(20);
+        let options = EncodeOptions::new().target_matches(true);
+        let delta = crate::encode_with_options(&new, &base, &options).unwrap();
+
+        let mut output = Vec::new();
+        decode_from_source(&delta, &mut base.as_slice(), &mut output).unwrap();
+        assert_eq!(output, new);
+    }
+}
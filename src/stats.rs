@@ -0,0 +1,573 @@
+//! Match and gap length distributions of encoded deltas.
+
+use crate::buffer::BufferStream;
+use crate::delta::prepare;
+use crate::error::Result;
+use crate::gear::HashTable;
+use crate::varint::read_delta_unit;
+use std::ops::Range;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Number of power-of-two buckets, enough for any `u64` length.
+const BUCKETS: usize = 64;
+
+/// Shortest match considered a moved block. The matcher resynchronizes
+/// after an edit with short, often accidental matches from anywhere in the
+/// base, which say nothing about the order of the data.
+const MIN_MOVED_BLOCK: u64 = 64;
+
+/// Counts of lengths in power-of-two buckets.
+///
+/// Bucket `i` counts the lengths in `2^i..2^(i+1)`, so bucket 0 holds
+/// lengths of 1, bucket 1 lengths of 2 and 3, and so on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LengthHistogram {
+    buckets: [u64; BUCKETS],
+    count: u64,
+    total: u64,
+    max: u64,
+}
+
+impl LengthHistogram {
+    /// Creates an empty histogram.
+    pub fn new() -> Self {
+        Self {
+            buckets: [0; BUCKETS],
+            count: 0,
+            total: 0,
+            max: 0,
+        }
+    }
+
+    /// Records one length. Lengths of 0 are ignored.
+    pub fn record(&mut self, length: u64) {
+        if length == 0 {
+            return;
+        }
+        self.buckets[length.ilog2() as usize] += 1;
+        self.count += 1;
+        self.total = self.total.saturating_add(length);
+        self.max = self.max.max(length);
+    }
+
+    /// Returns the number of recorded lengths.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Returns the sum of all recorded lengths.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Returns the longest recorded length, or 0 if there is none.
+    pub fn max(&self) -> u64 {
+        self.max
+    }
+
+    /// Returns the average length, or `None` if nothing was recorded.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn mean(&self) -> Option<f64> {
+        (self.count > 0).then(|| self.total as f64 / self.count as f64)
+    }
+
+    /// Returns the raw bucket counts; see the type documentation.
+    pub fn buckets(&self) -> &[u64; BUCKETS] {
+        &self.buckets
+    }
+
+    /// Iterates over the non-empty buckets as the range of lengths each one
+    /// covers and its count.
+    pub fn iter(&self) -> impl Iterator<Item = (Range<u64>, u64)> + '_ {
+        self.buckets
+            .iter()
+            .enumerate()
+            .filter(|&(_, &count)| count > 0)
+            .map(|(i, &count)| {
+                let end = 1u64.checked_shl(i as u32 + 1).unwrap_or(u64::MAX);
+                (1 << i..end, count)
+            })
+    }
+}
+
+impl Default for LengthHistogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Distribution of matched and unmatched runs in a delta.
+///
+/// Returned by [`crate::encode_with_stats`], or built from any existing
+/// delta with [`EncodeStats::from_delta`]. A match is a run of target bytes
+/// copied from the base or from earlier in the target; copies that continue
+/// exactly where the previous one ended count as one match, so segment
+/// boundaries do not split matches. A gap is a run of literal bytes between
+/// two matches, or before the first or after the last one.
+///
+/// Matches from the base that are out of order with it are also reported
+/// as moves, which tells reordered data apart from edited data. Of the
+/// matches from the base of at least 64 bytes, those in base order that
+/// cover the most target bytes are taken to be in place, and every other
+/// one is a moved block.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EncodeStats {
+    /// Lengths of matched runs.
+    pub matches: LengthHistogram,
+    /// Lengths of literal runs.
+    pub gaps: LengthHistogram,
+    /// Lengths of the matches that are moved blocks.
+    pub moved: LengthHistogram,
+    /// The moved blocks, in target order.
+    pub moves: Vec<MovedBlock>,
+    /// How full the hash table of the base was and how its lookups went.
+    ///
+    /// Only collected by [`crate::encode_with_stats`], and `None` if the
+    /// base was not indexed, for example because the inputs are equal.
+    pub table: Option<TableStats>,
+}
+
+/// Occupancy and lookup counts of the hash table indexing the base.
+///
+/// Every few base positions are inserted into a table of `2^hash_bits`
+/// buckets, each holding one position, so a later position with the same
+/// bucket replaces an earlier one. Many collisions mean the table is too
+/// small for the base and matches are lost; a low occupancy means it is
+/// larger than needed. Each target window that is looked up either finds
+/// an empty bucket, a position whose bytes match (a hit), or a position
+/// whose bytes differ (a false candidate), which costs a comparison with
+/// the base for nothing.
+///
+/// With parallel encoding, the lookups of all windows are added up.
+///
+/// # Examples
+///
+/// ```
+/// use gdelta::{EncodeOptions, encode_with_stats};
+///
+/// let base: Vec<u8> = (0..100_000u32).map(|i| (i * 7 % 251) as u8).collect();
+/// let mut new = base.clone();
+/// new[50_000..50_010].copy_from_slice(b"0123456789");
+///
+/// let (_, stats) = encode_with_stats(&new, &base, &EncodeOptions::new()).unwrap();
+/// let table = stats.table.unwrap();
+/// assert!(table.occupancy() <= 1.0);
+/// println!(
+///     "{} buckets, {:.0}% full, {:.0}% of inserts overwritten, {:.0}% of lookups hit",
+///     table.buckets,
+///     table.occupancy() * 100.0,
+///     table.overwrite_rate() * 100.0,
+///     table.probe_success_rate() * 100.0,
+/// );
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TableStats {
+    /// Fingerprint bits selecting a bucket.
+    pub hash_bits: u32,
+    /// Distance between inserted base positions.
+    pub sample_rate: usize,
+    /// Number of buckets.
+    pub buckets: u64,
+    /// Buckets holding a base position.
+    pub occupied: u64,
+    /// Base positions inserted.
+    pub inserts: u64,
+    /// Inserts into a bucket that already held a position, which was lost.
+    pub collisions: u64,
+    /// Target windows looked up.
+    pub lookups: u64,
+    /// Lookups that found a matching base position.
+    pub hits: u64,
+    /// Lookups that found a base position whose bytes differ.
+    pub false_candidates: u64,
+}
+
+impl TableStats {
+    /// Returns the fraction of buckets holding a position.
+    pub fn occupancy(&self) -> f64 {
+        ratio(self.occupied, self.buckets)
+    }
+
+    /// Returns the fraction of inserted positions that were overwritten.
+    pub fn overwrite_rate(&self) -> f64 {
+        ratio(self.collisions, self.inserts)
+    }
+
+    /// Returns the fraction of lookups that found a match.
+    pub fn probe_success_rate(&self) -> f64 {
+        ratio(self.hits, self.lookups)
+    }
+}
+
+/// Divides `part` by `whole`, or returns 0 if `whole` is 0.
+#[allow(clippy::cast_precision_loss)]
+fn ratio(part: u64, whole: u64) -> f64 {
+    if whole == 0 {
+        0.0
+    } else {
+        part as f64 / whole as f64
+    }
+}
+
+/// Collects [`TableStats`] from a matcher and its clones.
+#[derive(Debug, Default)]
+pub(crate) struct TableCounters {
+    /// The counts taken when the base was indexed, if it was.
+    index: Mutex<Option<TableStats>>,
+    pub lookups: AtomicU64,
+    pub hits: AtomicU64,
+    pub false_candidates: AtomicU64,
+}
+
+impl TableCounters {
+    /// Records the counts of a freshly built `table`, added to those of any
+    /// earlier one.
+    pub fn record_index(&self, hash_bits: u32, sample_rate: usize, table: &HashTable) {
+        let occupied = table.occupied() as u64;
+        let inserts = table.inserts() as u64;
+        let mut index = self
+            .index
+            .lock()
+            .unwrap_or_else(|poison| poison.into_inner());
+        let stats = index.get_or_insert_with(TableStats::default);
+        stats.hash_bits = hash_bits;
+        stats.sample_rate = sample_rate;
+        stats.buckets += table.len() as u64;
+        stats.occupied += occupied;
+        stats.inserts += inserts;
+        // Position 0 leaves its bucket looking empty, so it counts as a collision
+        stats.collisions += inserts.saturating_sub(occupied);
+    }
+
+    /// Returns the counts so far, or `None` if no base was indexed.
+    pub fn stats(&self) -> Option<TableStats> {
+        let index = *self
+            .index
+            .lock()
+            .unwrap_or_else(|poison| poison.into_inner());
+        index.map(|stats| TableStats {
+            lookups: self.lookups.load(Ordering::Relaxed),
+            hits: self.hits.load(Ordering::Relaxed),
+            false_candidates: self.false_candidates.load(Ordering::Relaxed),
+            ..stats
+        })
+    }
+}
+
+/// A block of the base that appears at a different place in the target;
+/// see [`EncodeStats`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MovedBlock {
+    /// Offset of the block in the target.
+    pub target_offset: u64,
+    /// Offset of the block in the base.
+    pub base_offset: u64,
+    /// Length of the block.
+    pub length: u64,
+}
+
+impl EncodeStats {
+    /// Collects the statistics of `delta`, which was encoded against a base
+    /// of `base_len` bytes.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors [`crate::decode`] reports for a malformed delta,
+    /// except that the checksum is not verified.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use gdelta::{EncodeStats, encode};
+    ///
+    /// let base = b"The quick brown fox jumps over the lazy dog. ".repeat(10);
+    /// let mut new = base.clone();
+    /// new[100..110].copy_from_slice(b"0123456789");
+    ///
+    /// let delta = encode(&new, &base).unwrap();
+    /// let stats = EncodeStats::from_delta(&delta, base.len()).unwrap();
+    /// assert_eq!(stats.gaps.total(), 10);
+    /// assert_eq!(stats.matches.total() + stats.gaps.total(), new.len() as u64);
+    /// ```
+    pub fn from_delta(delta: &[u8], base_len: usize) -> Result<Self> {
+        let (raw_deltas, _) = prepare(delta, base_len)?;
+        let mut collector = Collector {
+            base_len: base_len as u64,
+            ..Collector::default()
+        };
+        for raw in &raw_deltas {
+            collector.add_instructions(&raw.instructions)?;
+        }
+        Ok(collector.finish())
+    }
+}
+
+/// Merges runs across instructions before recording them.
+#[derive(Default)]
+struct Collector {
+    stats: EncodeStats,
+    /// Source offset just past the pending match, and its length.
+    copy: Option<(u64, u64)>,
+    gap: u64,
+    base_len: u64,
+    /// Target bytes produced by the instructions added so far.
+    pos: u64,
+    /// Matches from the base, in target order.
+    base_matches: Vec<MovedBlock>,
+}
+
+impl Collector {
+    fn add_instructions(&mut self, instructions: &[u8]) -> Result<()> {
+        let mut stream = BufferStream::from_slice(instructions);
+        while stream.remaining() > 0 {
+            let unit = read_delta_unit(&mut stream)?;
+            if !unit.is_copy {
+                self.flush_copy();
+                self.gap += unit.length;
+                self.pos = self.pos.saturating_add(unit.length);
+                continue;
+            }
+            self.stats.gaps.record(std::mem::take(&mut self.gap));
+            match &mut self.copy {
+                Some((end, length)) if *end == unit.offset => {
+                    *end = end.saturating_add(unit.length);
+                    *length += unit.length;
+                }
+                _ => {
+                    self.flush_copy();
+                    self.copy = Some((unit.offset.saturating_add(unit.length), unit.length));
+                }
+            }
+            self.pos = self.pos.saturating_add(unit.length);
+        }
+        Ok(())
+    }
+
+    fn flush_copy(&mut self) {
+        if let Some((end, length)) = self.copy.take() {
+            self.stats.matches.record(length);
+            let base_offset = end - length;
+            if base_offset < self.base_len && length >= MIN_MOVED_BLOCK {
+                self.base_matches.push(MovedBlock {
+                    target_offset: self.pos - length,
+                    base_offset,
+                    length,
+                });
+            }
+        }
+    }
+
+    fn finish(mut self) -> EncodeStats {
+        self.flush_copy();
+        self.stats.gaps.record(self.gap);
+        let in_place = in_base_order(&self.base_matches);
+        for (block, in_place) in self.base_matches.into_iter().zip(in_place) {
+            if !in_place {
+                self.stats.moved.record(block.length);
+                self.stats.moves.push(block);
+            }
+        }
+        self.stats
+    }
+}
+
+/// Marks the matches, given in target order, that form the longest run in
+/// base order by total length.
+///
+/// This is a heaviest increasing subsequence by base offset, found with a
+/// Fenwick tree of the best run ending below each offset.
+fn in_base_order(matches: &[MovedBlock]) -> Vec<bool> {
+    let mut offsets: Vec<u64> = matches.iter().map(|block| block.base_offset).collect();
+    offsets.sort_unstable();
+    offsets.dedup();
+
+    // Best (total length, last match) over runs ending at each offset rank
+    let mut tree = vec![(0u64, usize::MAX); offsets.len() + 1];
+    let mut previous = vec![usize::MAX; matches.len()];
+    let mut best = (0u64, usize::MAX);
+    for (i, block) in matches.iter().enumerate() {
+        let rank = offsets.partition_point(|&offset| offset < block.base_offset);
+
+        let mut before = (0u64, usize::MAX);
+        let mut node = rank;
+        while node > 0 {
+            before = before.max(tree[node]);
+            node &= node - 1;
+        }
+        previous[i] = before.1;
+        let run = (before.0 + block.length, i);
+        best = best.max(run);
+
+        let mut node = rank + 1;
+        while node < tree.len() {
+            tree[node] = tree[node].max(run);
+            node += node & node.wrapping_neg();
+        }
+    }
+
+    let mut in_place = vec![false; matches.len()];
+    let mut i = best.1;
+    while i != usize::MAX {
+        in_place[i] = true;
+        i = previous[i];
+    }
+    in_place
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EncodeOptions;
+
+    #[test]
+    fn test_histogram_buckets() {
+        let mut histogram = LengthHistogram::new();
+        for length in [0, 1, 2, 3, 4, 1000, u64::MAX] {
+            histogram.record(length);
+        }
+        assert_eq!(histogram.count(), 6);
+        assert_eq!(histogram.max(), u64::MAX);
+        assert_eq!(histogram.buckets()[1], 2);
+        assert_eq!(
+            histogram.iter().collect::<Vec<_>>(),
+            [
+                (1..2, 1),
+                (2..4, 2),
+                (4..8, 1),
+                (512..1024, 1),
+                (1 << 63..u64::MAX, 1)
+            ]
+        );
+        assert_eq!(LengthHistogram::new().mean(), None);
+    }
+
+    #[test]
+    fn test_stats_cover_target_for_all_formats() {
+        let base: Vec<u8> = (0..5_000u32)
+            .flat_map(|i| format!("line {i}\n").into_bytes())
+            .collect();
+        let mut new = base.clone();
+        new[2_000..2_020].copy_from_slice(&[b'#'; 20]);
+        new[20_000..20_005].copy_from_slice(b"12345");
+        new.extend_from_slice(b"appended tail");
+
+        let plain = crate::encode(&new, &base).unwrap();
+        let expected = EncodeStats::from_delta(&plain, base.len()).unwrap();
+        // The matcher may leave a few unchanged bytes around each edit
+        assert!((38..100).contains(&expected.gaps.total()));
+        assert_eq!(
+            expected.matches.total() + expected.gaps.total(),
+            new.len() as u64
+        );
+        assert!(expected.gaps.max() >= 20);
+
+        for options in [
+            EncodeOptions::new().checksum(true),
+            EncodeOptions::new().rsyncable(true),
+        ] {
+            let delta = crate::encode_with_options(&new, &base, &options).unwrap();
+            let stats = EncodeStats::from_delta(&delta, base.len()).unwrap();
+            assert_eq!(stats, expected, "{options:?}");
+        }
+
+        assert!(expected.moves.is_empty());
+
+        let identity = crate::encode_with_options(&base, &base, &EncodeOptions::new()).unwrap();
+        let stats = EncodeStats::from_delta(&identity, base.len()).unwrap();
+        assert_eq!(
+            (stats.matches.total(), stats.gaps.count()),
+            (base.len() as u64, 0)
+        );
+    }
+
+    #[test]
+    fn test_malformed_delta_is_rejected() {
+        let delta = [128, 0, 71, 157, 76, 1, 17, 80, 225, 137, 173, 109];
+        assert!(EncodeStats::from_delta(&delta, 100).is_err());
+    }
+
+    #[test]
+    fn test_moved_blocks() {
+        let base: Vec<u8> = (0..5_000u32)
+            .flat_map(|i| format!("line {i}\n").into_bytes())
+            .collect();
+        // Move a block forward and edit elsewhere
+        let mut new = base[..10_000].to_vec();
+        new.extend_from_slice(&base[30_000..35_000]);
+        new.extend_from_slice(&base[10_000..30_000]);
+        new.extend_from_slice(&base[35_000..]);
+        new[2_000..2_010].copy_from_slice(b"0123456789");
+
+        let delta = crate::encode(&new, &base).unwrap();
+        let stats = EncodeStats::from_delta(&delta, base.len()).unwrap();
+        assert_eq!(stats.moves.len(), 1, "{:?}", stats.moves);
+        let block = stats.moves[0];
+        assert!(block.length > 4_900 && block.length <= 5_000);
+        assert_eq!(block.target_offset - 10_000, block.base_offset - 30_000);
+        assert_eq!(stats.moved.total(), block.length);
+        assert_eq!(stats.matches.total() + stats.gaps.total(), new.len() as u64);
+    }
+
+    #[test]
+    fn test_table_stats() {
+        let mut seed = 1u32;
+        let base: Vec<u8> = (0..200_000)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (seed >> 16) as u8
+            })
+            .collect();
+        let mut new = base.clone();
+        for offset in (1_000..190_000).step_by(10_000) {
+            new[offset..offset + 16].fill(0);
+        }
+
+        let table = |options: &EncodeOptions| {
+            let (delta, stats) = crate::encode_with_stats(&new, &base, options).unwrap();
+            assert_eq!(crate::decode(&delta, &base).unwrap(), new);
+            stats.table.unwrap()
+        };
+        let full = table(&EncodeOptions::new());
+        assert_eq!(full.buckets, 1 << full.hash_bits);
+        assert_eq!(full.inserts, full.occupied + full.collisions);
+        assert!(
+            full.hits >= 18 && full.hits + full.false_candidates <= full.lookups,
+            "{full:?}"
+        );
+        assert!(full.probe_success_rate() > 0.0);
+
+        // A smaller table loses more of the inserted positions
+        let small = table(&EncodeOptions::new().memory_budget(4096));
+        assert!(small.buckets < full.buckets);
+        assert!(small.occupancy() > full.occupancy());
+        assert!(small.overwrite_rate() > full.overwrite_rate());
+
+        let parallel = table(&EncodeOptions::new().parallel(true));
+        // Parallel encoding indexes the whole base, not just the middle
+        assert!(parallel.inserts > full.inserts);
+        assert!(parallel.hits >= 18);
+
+        let (_, identity) = crate::encode_with_stats(&base, &base, &EncodeOptions::new()).unwrap();
+        assert_eq!(identity.table, None);
+        assert_eq!(TableStats::default().probe_success_rate(), 0.0);
+    }
+
+    #[test]
+    fn test_in_base_order_prefers_longest_run() {
+        let block = |target_offset, base_offset, length| MovedBlock {
+            target_offset,
+            base_offset,
+            length,
+        };
+        // Two short blocks in order against one long block before them
+        let matches = [
+            block(0, 100, 10),
+            block(10, 110, 10),
+            block(20, 0, 50),
+            block(70, 120, 10),
+        ];
+        assert_eq!(in_base_order(&matches), [false, false, true, true]);
+        assert!(in_base_order(&[]).is_empty());
+    }
+}
@@ -0,0 +1,937 @@
+//! Streaming encode and decode over `std::io` types.
+//!
+//! For encoding, the target is processed in fixed-size windows. Each window
+//! is encoded against a window of the base around the position where the
+//! previous window's matches ended, and written out as one segment of a
+//! segmented delta. Only one target window, one base window and the current
+//! segment are held in memory at a time.
+//!
+//! Nothing is carried over from one window to the next except where the
+//! base cursor stands: no literal is pending and the matcher re-indexes its
+//! base window. An [`EncodeCheckpoint`] taken between two windows therefore
+//! holds everything needed to continue the encode later, and a resumed
+//! encode writes the same bytes the uninterrupted one would have.
+//!
+//! [`EncodeReader`] runs the same window loop on demand, encoding the next
+//! window only when the consumer has read the previous segment.
+
+use crate::buffer::BufferStream;
+use crate::checksum::{ChecksumWriter, crc32};
+use crate::delta::{
+    DeltaWriter, RawDelta, check_base_checksum, check_target_checksum, decode_raw, encode_rebased,
+    for_each_slice, has_target_copies, prepare_payload,
+};
+use crate::error::{GDeltaError, Result};
+use crate::header::{self, DeltaHeader, FLAG_CHECKSUM, FLAG_METADATA, FLAG_SEGMENTED, VERSION};
+use crate::matcher::ConfiguredMatcher;
+use crate::options::EncodeOptions;
+use crate::varint::write_varint;
+use std::fs::File;
+use std::io::{BufRead, IoSlice, Read, Seek, SeekFrom, Write};
+
+/// Size of each target window, in bytes.
+pub const STREAM_WINDOW_SIZE: usize = 4 * 1024 * 1024;
+
+/// Base bytes searched before and after the expected match position.
+const BASE_MARGIN: u64 = STREAM_WINDOW_SIZE as u64;
+
+/// Marker at the start of a serialized [`EncodeCheckpoint`].
+const CHECKPOINT_MAGIC: [u8; 4] = *b"GDCK";
+
+/// Version of the checkpoint format.
+const CHECKPOINT_VERSION: u8 = 1;
+
+/// Size of a serialized [`EncodeCheckpoint`] in bytes.
+const CHECKPOINT_SIZE: usize = 4 + 2 + 4 * 8 + 4 + 4;
+
+/// State of an interrupted [`encode_streams`] between two target windows.
+///
+/// Passed to the callback of [`crate::encode_streams_resumable`] after each
+/// window has been written and flushed, and passed back in to continue from
+/// there. The checkpoint is only valid together with the output written so
+/// far, the same base and the same options.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EncodeCheckpoint {
+    flags: u8,
+    base_len: u64,
+    target_pos: u64,
+    base_cursor: u64,
+    written: u64,
+    crc: u32,
+}
+
+impl EncodeCheckpoint {
+    /// Returns the number of target bytes already encoded.
+    ///
+    /// A resumed encode reads the target from this position on.
+    pub fn target_position(&self) -> u64 {
+        self.target_pos
+    }
+
+    /// Returns the number of delta bytes already written.
+    ///
+    /// Output past this length belongs to a window that was not finished and
+    /// must be discarded before resuming.
+    pub fn output_position(&self) -> u64 {
+        self.written
+    }
+
+    /// Serializes the checkpoint, protected by a CRC-32.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut stream = BufferStream::with_capacity(CHECKPOINT_SIZE);
+        stream.write_bytes(&CHECKPOINT_MAGIC);
+        stream.write_u8(CHECKPOINT_VERSION);
+        stream.write_u8(self.flags);
+        for value in [
+            self.base_len,
+            self.target_pos,
+            self.base_cursor,
+            self.written,
+        ] {
+            stream.write_bytes(&value.to_le_bytes());
+        }
+        stream.write_bytes(&self.crc.to_le_bytes());
+        let mut bytes = stream.into_vec();
+        let crc = crc32(&bytes);
+        bytes.extend_from_slice(&crc.to_le_bytes());
+        bytes
+    }
+
+    /// Parses a checkpoint written by [`EncodeCheckpoint::to_bytes`].
+    ///
+    /// # Errors
+    ///
+    /// Returns `GDeltaError::ChecksumMismatch` if the checkpoint is damaged
+    /// and `GDeltaError::InvalidDelta` if it is malformed or has an
+    /// unsupported version.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < CHECKPOINT_SIZE {
+            return Err(GDeltaError::UnexpectedEndOfData);
+        }
+        if bytes.len() > CHECKPOINT_SIZE || !bytes.starts_with(&CHECKPOINT_MAGIC) {
+            return Err(invalid("Not a gdelta encode checkpoint"));
+        }
+        let (body, trailer) = bytes.split_at(CHECKPOINT_SIZE - 4);
+        let expected = read_u32(trailer);
+        let actual = crc32(body);
+        if expected != actual {
+            return Err(GDeltaError::ChecksumMismatch { expected, actual });
+        }
+
+        let mut stream = BufferStream::from_slice(body);
+        stream.set_position(CHECKPOINT_MAGIC.len());
+        let version = stream.read_u8()?;
+        if version != CHECKPOINT_VERSION {
+            return Err(invalid(&format!(
+                "Unsupported checkpoint version {version}"
+            )));
+        }
+        let flags = stream.read_u8()?;
+        let mut values = [0u64; 4];
+        for value in &mut values {
+            let bytes = stream.read_bytes(8)?;
+            *value = u64::from_le_bytes(bytes.try_into().expect("read 8 bytes"));
+        }
+        let [base_len, target_pos, base_cursor, written] = values;
+        Ok(Self {
+            flags,
+            base_len,
+            target_pos,
+            base_cursor,
+            written,
+            crc: read_u32(stream.read_bytes(4)?),
+        })
+    }
+}
+
+pub fn encode_streams<N, B, W>(new: N, base: B, output: W, options: &EncodeOptions) -> Result<u64>
+where
+    N: Read,
+    B: Read + Seek,
+    W: Write,
+{
+    encode_streams_resumable(new, base, output, options, None, |_| Ok(()))
+}
+
+pub fn encode_streams_resumable<N, B, W>(
+    new: N,
+    base: B,
+    output: W,
+    options: &EncodeOptions,
+    resume_from: Option<&EncodeCheckpoint>,
+    mut checkpoint: impl FnMut(&EncodeCheckpoint) -> Result<()>,
+) -> Result<u64>
+where
+    N: Read,
+    B: Read + Seek,
+    W: Write,
+{
+    let mut encoder = WindowEncoder::new(new, base, options, resume_from)?;
+    let mut output = match resume_from {
+        Some(resumed) => ChecksumWriter::resume(output, resumed.crc, resumed.written),
+        None => {
+            let mut output = ChecksumWriter::new(output);
+            output.write_all(&encoder.header())?;
+            output
+        }
+    };
+
+    while !encoder.done {
+        if encoder.encode_window(&mut output)? {
+            output.flush()?;
+            checkpoint(&encoder.checkpoint(&output))?;
+        }
+    }
+    encoder.finish(&mut output)?;
+    output.flush()?;
+    Ok(output.written())
+}
+
+/// Encodes the target of a streaming encode one window at a time.
+struct WindowEncoder<N, B> {
+    new: N,
+    base: B,
+    options: EncodeOptions,
+    flags: u8,
+    base_len: u64,
+    target_pos: u64,
+    // Where in the base the next target byte most likely comes from
+    base_cursor: u64,
+    matcher: ConfiguredMatcher,
+    target: Vec<u8>,
+    base_window: Vec<u8>,
+    // Bytes after the last record boundary, held over to the next window
+    carry: usize,
+    /// The whole target has been encoded.
+    done: bool,
+}
+
+/// Rejects the options that encoding from streams cannot honor.
+pub(crate) fn check_options(options: &EncodeOptions) -> Result<()> {
+    options.validate()?;
+    if options.format_version == Some(0) {
+        return Err(GDeltaError::InvalidOptions(
+            "deltas encoded from streams need format version 1 or later".to_string(),
+        ));
+    }
+    if options.store_target_size {
+        return Err(GDeltaError::InvalidOptions(
+            "the target size is unknown when encoding from streams".to_string(),
+        ));
+    }
+    if options.target_checksum {
+        return Err(GDeltaError::InvalidOptions(
+            "the target checksum is unknown when encoding from streams".to_string(),
+        ));
+    }
+    if options.base_checksum {
+        return Err(GDeltaError::InvalidOptions(
+            "the base checksum is not supported when encoding from streams".to_string(),
+        ));
+    }
+    if options
+        .match_alignment
+        .is_some_and(|alignment| alignment > 1)
+    {
+        return Err(GDeltaError::InvalidOptions(
+            "match alignment is not supported when encoding from streams".to_string(),
+        ));
+    }
+    if options.target_matches {
+        return Err(GDeltaError::InvalidOptions(
+            "matches within the target are not supported when encoding from streams".to_string(),
+        ));
+    }
+    if options.copy_window.is_some() {
+        return Err(GDeltaError::InvalidOptions(
+            "a copy window is not supported when encoding from streams".to_string(),
+        ));
+    }
+    if options.max_ratio.is_some() {
+        return Err(GDeltaError::InvalidOptions(
+            "a maximum ratio cannot be checked before the target stream ends".to_string(),
+        ));
+    }
+    if options.min_match_len.is_some() {
+        return Err(GDeltaError::InvalidOptions(
+            "a minimum match length is not supported when encoding from streams".to_string(),
+        ));
+    }
+    if options.extend_backward {
+        return Err(GDeltaError::InvalidOptions(
+            "backward match extension is not supported when encoding from streams".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+impl<N: Read, B: Read + Seek> WindowEncoder<N, B> {
+    fn new(
+        new: N,
+        mut base: B,
+        options: &EncodeOptions,
+        resume_from: Option<&EncodeCheckpoint>,
+    ) -> Result<Self> {
+        check_options(options)?;
+
+        let mut flags = FLAG_SEGMENTED;
+        if options.checksum {
+            flags |= FLAG_CHECKSUM;
+        }
+        if !options.metadata.is_empty() {
+            flags |= FLAG_METADATA;
+        }
+        let base_len = base.seek(SeekFrom::End(0))?;
+        let mut target_pos = 0u64;
+        let mut base_cursor = 0u64;
+        if let Some(resumed) = resume_from {
+            if resumed.flags != flags {
+                return Err(GDeltaError::InvalidOptions(
+                    "the checkpoint was taken with different options".to_string(),
+                ));
+            }
+            if resumed.base_len != base_len {
+                return Err(GDeltaError::SizeMismatch {
+                    expected: usize::try_from(resumed.base_len).unwrap_or(usize::MAX),
+                    actual: usize::try_from(base_len).unwrap_or(usize::MAX),
+                });
+            }
+            target_pos = resumed.target_pos;
+            base_cursor = resumed.base_cursor;
+        }
+
+        Ok(Self {
+            new,
+            base,
+            options: options.clone(),
+            flags,
+            base_len,
+            target_pos,
+            base_cursor,
+            matcher: options.window_matcher(),
+            target: vec![0u8; STREAM_WINDOW_SIZE],
+            base_window: Vec::new(),
+            carry: 0,
+            done: false,
+        })
+    }
+
+    /// Returns the header the delta starts with.
+    fn header(&self) -> Vec<u8> {
+        DeltaHeader {
+            version: VERSION,
+            flags: self.flags,
+            target_size: None,
+            target_checksum: None,
+            base_checksum: None,
+            metadata: &self.options.metadata,
+        }
+        .to_bytes()
+    }
+
+    /// Reads the next target window and writes its segment to `output`.
+    ///
+    /// Returns whether a segment was written; the target may turn out to
+    /// be exhausted instead. Sets `done` after the last window.
+    fn encode_window<W: Write>(&mut self, output: &mut ChecksumWriter<W>) -> Result<bool> {
+        self.options.check_deadline()?;
+        let carry = self.carry;
+        let read = read_full(&mut self.new, &mut self.target[carry..])?;
+        let available = carry + read;
+        if available == 0 {
+            self.done = true;
+            return Ok(false);
+        }
+        let at_end = read < self.target.len() - carry;
+
+        // End the window after its last complete record, so the next one
+        // starts on a record boundary
+        let len = match self.options.record_delimiter_for_alignment() {
+            Some(delimiter) if !at_end => self.target[..available]
+                .iter()
+                .rposition(|&byte| byte == delimiter)
+                .map_or(available, |last| last + 1),
+            _ => available,
+        };
+
+        let window_start = self
+            .base_cursor
+            .saturating_sub(BASE_MARGIN)
+            .min(self.base_len);
+        let window_end = self
+            .base_cursor
+            .saturating_add(len as u64 + BASE_MARGIN)
+            .min(self.base_len);
+        self.base_window
+            .resize((window_end - window_start) as usize, 0);
+        self.base.seek(SeekFrom::Start(window_start))?;
+        self.base.read_exact(&mut self.base_window)?;
+
+        let mut writer = DeltaWriter::new();
+        let last_copy = encode_rebased(
+            &mut writer,
+            &self.target[..len],
+            &self.base_window,
+            window_start,
+            &mut self.matcher,
+        )?;
+        let segment = writer.finish();
+
+        let mut prefix = BufferStream::with_capacity(10);
+        write_varint(&mut prefix, segment.len() as u64);
+        output.write_all(prefix.as_slice())?;
+        output.write_all(&segment)?;
+
+        // Follow the alignment of the last copy, or assume no shift
+        self.base_cursor = match last_copy {
+            Some((target_end, base_end)) => base_end + (len as u64 - target_end),
+            None => self.base_cursor + len as u64,
+        };
+        self.target_pos += len as u64;
+
+        if at_end {
+            self.done = true;
+        } else {
+            self.target.copy_within(len..available, 0);
+            self.carry = available - len;
+        }
+        Ok(true)
+    }
+
+    /// Returns the checkpoint after the last window written to `output`.
+    fn checkpoint<W: Write>(&self, output: &ChecksumWriter<W>) -> EncodeCheckpoint {
+        EncodeCheckpoint {
+            flags: self.flags,
+            base_len: self.base_len,
+            target_pos: self.target_pos,
+            base_cursor: self.base_cursor,
+            written: output.written(),
+            crc: output.checksum(),
+        }
+    }
+
+    /// Writes what follows the last window.
+    fn finish<W: Write>(&self, output: &mut ChecksumWriter<W>) -> Result<()> {
+        // An empty target still needs one (empty) segment
+        if self.target_pos == 0 {
+            output.write_all(&[1, 0])?;
+        }
+        if self.flags & FLAG_CHECKSUM != 0 {
+            let crc = output.checksum();
+            output.write_all(&crc.to_le_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+/// A reader producing the delta between two streams as it is read.
+///
+/// Encodes like [`crate::encode_streams`], but is pulled by the consumer
+/// instead of pushing into a writer: the next target window is read and
+/// encoded only once the previous segment has been read completely. Memory
+/// use therefore stays at one target window, one base window and one
+/// encoded segment however slowly the output is consumed, for example by a
+/// network sink.
+///
+/// Stacked under a compressor that reads its input, such as the reader
+/// side of the `zstd` crate, the whole pipeline runs at the pace of the
+/// consumer. The reader also implements [`BufRead`], so such compressors
+/// can take segments without copying them into a buffer of their own.
+///
+/// # Examples
+///
+/// ```
+/// use gdelta::{EncodeOptions, EncodeReader, decode};
+/// use std::io::{Cursor, Read};
+///
+/// let base = b"The quick brown fox jumps over the lazy dog";
+/// let new = b"The quick brown cat jumps over the lazy dog";
+///
+/// let mut reader = EncodeReader::new(&new[..], Cursor::new(base), &EncodeOptions::new())?;
+/// let mut delta = Vec::new();
+/// reader.read_to_end(&mut delta)?;
+/// assert_eq!(decode(&delta, base)?, new);
+/// # Ok::<(), gdelta::GDeltaError>(())
+/// ```
+pub struct EncodeReader<N, B> {
+    encoder: WindowEncoder<N, B>,
+    pending: ChecksumWriter<Vec<u8>>,
+    /// Bytes of `pending` already read.
+    position: usize,
+    finished: bool,
+}
+
+impl<N: Read, B: Read + Seek> EncodeReader<N, B> {
+    /// Creates a reader producing the delta from `base` to `new`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of [`crate::encode_streams`] for invalid options,
+    /// and `GDeltaError::Io` if the length of the base cannot be found.
+    pub fn new(new: N, base: B, options: &EncodeOptions) -> Result<Self> {
+        let encoder = WindowEncoder::new(new, base, options, None)?;
+        let mut pending = ChecksumWriter::new(Vec::new());
+        pending.write_all(&encoder.header())?;
+        Ok(Self {
+            encoder,
+            pending,
+            position: 0,
+            finished: false,
+        })
+    }
+
+    /// Returns the number of target bytes encoded so far.
+    pub fn target_position(&self) -> u64 {
+        self.encoder.target_pos
+    }
+
+    /// Returns the number of delta bytes produced so far, including those
+    /// not read yet.
+    pub fn produced(&self) -> u64 {
+        self.pending.written()
+    }
+
+    /// Encodes until there is unread output or the delta is complete.
+    fn refill(&mut self) -> Result<()> {
+        while self.position == self.pending.get_ref().len() && !self.finished {
+            self.pending.get_mut().clear();
+            self.position = 0;
+            if self.encoder.done {
+                self.encoder.finish(&mut self.pending)?;
+                self.finished = true;
+            } else {
+                self.encoder.encode_window(&mut self.pending)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<N, B> std::fmt::Debug for EncodeReader<N, B> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EncodeReader")
+            .field("target_position", &self.encoder.target_pos)
+            .field("produced", &self.pending.written())
+            .field("finished", &self.finished)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<N: Read, B: Read + Seek> Read for EncodeReader<N, B> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<N: Read, B: Read + Seek> BufRead for EncodeReader<N, B> {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        self.refill().map_err(std::io::Error::other)?;
+        Ok(&self.pending.get_ref()[self.position..])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.position = (self.position + amount).min(self.pending.get_ref().len());
+    }
+}
+
+/// Maximum number of slices gathered into one vectored write.
+const MAX_IO_SLICES: usize = 64;
+
+pub fn decode_to_writer<W: Write>(delta: &[u8], base_data: &[u8], output: W) -> Result<u64> {
+    let (header, payload) = header::split(delta)?;
+    let (raw_deltas, _) = prepare_payload(header, payload, base_data.len())?;
+    write_verified(header, &raw_deltas, base_data, output)
+}
+
+pub fn decode_to_file(delta: &[u8], base_data: &[u8], file: &mut File) -> Result<u64> {
+    let (header, payload) = header::split(delta)?;
+    let (raw_deltas, output_size) = prepare_payload(header, payload, base_data.len())?;
+    check_base_checksum(header, base_data)?;
+
+    // Reserve the full length up front so the filesystem can allocate it
+    // in one go, and drop any longer previous contents. Only done for the
+    // right base, so a mismatch leaves the file alone.
+    file.set_len(output_size)?;
+    file.seek(SeekFrom::Start(0))?;
+    write_checked(header, &raw_deltas, base_data, file)
+}
+
+/// Writes the output like [`write_output`], checking the base and the
+/// output against the checksums in `header` if there are any.
+fn write_verified<W: Write>(
+    header: Option<DeltaHeader<'_>>,
+    raw_deltas: &[RawDelta<'_>],
+    base_data: &[u8],
+    output: W,
+) -> Result<u64> {
+    check_base_checksum(header, base_data)?;
+    write_checked(header, raw_deltas, base_data, output)
+}
+
+/// Writes the output like [`write_output`], checking it against the target
+/// checksum in `header` if there is one.
+fn write_checked<W: Write>(
+    header: Option<DeltaHeader<'_>>,
+    raw_deltas: &[RawDelta<'_>],
+    base_data: &[u8],
+    output: W,
+) -> Result<u64> {
+    if header.is_none_or(|header| header.target_checksum.is_none()) {
+        return write_output(raw_deltas, base_data, output);
+    }
+    let mut writer = ChecksumWriter::new(output);
+    let written = write_output(raw_deltas, base_data, &mut writer)?;
+    check_target_checksum(header, || writer.checksum())?;
+    Ok(written)
+}
+
+/// Writes the output of already prepared deltas to `output`.
+fn write_output<W: Write>(
+    raw_deltas: &[RawDelta<'_>],
+    base_data: &[u8],
+    mut output: W,
+) -> Result<u64> {
+    // Copies from the target need the assembled output
+    if has_target_copies(raw_deltas) {
+        let decoded = decode_raw(raw_deltas, base_data)?;
+        output.write_all(&decoded)?;
+        output.flush()?;
+        return Ok(decoded.len() as u64);
+    }
+
+    let mut slices: Vec<IoSlice<'_>> = Vec::with_capacity(MAX_IO_SLICES);
+    let mut written = 0u64;
+
+    for_each_slice(raw_deltas, base_data, |slice| {
+        written += slice.len() as u64;
+        slices.push(IoSlice::new(slice));
+        if slices.len() == MAX_IO_SLICES {
+            write_all_vectored(&mut output, &mut slices)?;
+        }
+        Ok(())
+    })?;
+
+    write_all_vectored(&mut output, &mut slices)?;
+    output.flush()?;
+    Ok(written)
+}
+
+/// Writes every slice in `slices` and clears it.
+fn write_all_vectored<W: Write>(output: &mut W, slices: &mut Vec<IoSlice<'_>>) -> Result<()> {
+    let mut remaining = &mut slices[..];
+    while !remaining.is_empty() {
+        match output.write_vectored(remaining) {
+            Ok(0) => {
+                return Err(GDeltaError::Io("failed to write whole buffer".to_string()));
+            }
+            Ok(n) => IoSlice::advance_slices(&mut remaining, n),
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    slices.clear();
+    Ok(())
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn invalid(message: &str) -> GDeltaError {
+    GDeltaError::InvalidDelta(message.to_string())
+}
+
+/// Reads until `buf` is full or the reader is exhausted.
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn pseudo_random(len: usize, seed: u32) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_stream_roundtrip_across_windows() {
+        let base = pseudo_random(STREAM_WINDOW_SIZE * 2 + 1000, 1);
+        let mut new = base.clone();
+        // Shift everything after the insertion so windows must realign
+        new.splice(1000..1000, pseudo_random(50_000, 2));
+        new[STREAM_WINDOW_SIZE + 10] ^= 0xFF;
+
+        let options = EncodeOptions::new().checksum(true);
+        let mut delta = Vec::new();
+        let written =
+            encode_streams(Cursor::new(&new), Cursor::new(&base), &mut delta, &options).unwrap();
+
+        assert_eq!(written, delta.len() as u64);
+        assert_eq!(crate::decode(&delta, &base).unwrap(), new);
+        assert!(delta.len() < 60_000);
+    }
+
+    #[test]
+    fn test_resumed_encode_matches_uninterrupted() {
+        let base = pseudo_random(STREAM_WINDOW_SIZE * 3, 6);
+        let mut new = base.clone();
+        new.splice(5000..5000, pseudo_random(20_000, 7));
+        new[STREAM_WINDOW_SIZE * 2] ^= 0xFF;
+        new.truncate(STREAM_WINDOW_SIZE * 3 - 100);
+
+        let options = EncodeOptions::new().checksum(true);
+        let mut expected = Vec::new();
+        encode_streams(
+            Cursor::new(&new),
+            Cursor::new(&base),
+            &mut expected,
+            &options,
+        )
+        .unwrap();
+
+        for windows_done in 1..=3 {
+            // Fail after `windows_done` checkpoints, as if the process died
+            let mut delta = Vec::new();
+            let mut saved = Vec::new();
+            let result = encode_streams_resumable(
+                Cursor::new(&new),
+                Cursor::new(&base),
+                &mut delta,
+                &options,
+                None,
+                |checkpoint| {
+                    saved.push(checkpoint.to_bytes());
+                    if saved.len() == windows_done {
+                        return Err(GDeltaError::Io("interrupted".to_string()));
+                    }
+                    Ok(())
+                },
+            );
+            assert!(result.is_err());
+
+            let checkpoint = EncodeCheckpoint::from_bytes(saved.last().unwrap()).unwrap();
+            delta.truncate(checkpoint.output_position() as usize);
+            let target_pos = checkpoint.target_position() as usize;
+            let written = encode_streams_resumable(
+                Cursor::new(&new[target_pos..]),
+                Cursor::new(&base),
+                &mut delta,
+                &options,
+                Some(&checkpoint),
+                |_| Ok(()),
+            )
+            .unwrap();
+
+            assert_eq!(written, delta.len() as u64);
+            assert_eq!(delta, expected, "resumed after {windows_done} windows");
+        }
+    }
+
+    #[test]
+    fn test_resume_rejects_mismatched_inputs() {
+        let base = pseudo_random(10_000, 8);
+        let mut saved = None;
+        encode_streams_resumable(
+            &base[..5_000],
+            Cursor::new(&base),
+            Vec::new(),
+            &EncodeOptions::new(),
+            None,
+            |checkpoint| {
+                saved = Some(*checkpoint);
+                Ok(())
+            },
+        )
+        .unwrap();
+        let checkpoint = saved.unwrap();
+
+        let resume = |base: &[u8], options: &EncodeOptions| {
+            encode_streams_resumable(
+                &b""[..],
+                Cursor::new(base),
+                Vec::new(),
+                options,
+                Some(&checkpoint),
+                |_| Ok(()),
+            )
+        };
+        assert!(resume(&base, &EncodeOptions::new()).is_ok());
+        assert!(matches!(
+            resume(&base, &EncodeOptions::new().checksum(true)),
+            Err(GDeltaError::InvalidOptions(_))
+        ));
+        assert!(matches!(
+            resume(&base[1..], &EncodeOptions::new()),
+            Err(GDeltaError::SizeMismatch { .. })
+        ));
+
+        let mut bytes = checkpoint.to_bytes();
+        assert_eq!(EncodeCheckpoint::from_bytes(&bytes).unwrap(), checkpoint);
+        bytes[10] ^= 1;
+        assert!(matches!(
+            EncodeCheckpoint::from_bytes(&bytes),
+            Err(GDeltaError::ChecksumMismatch { .. })
+        ));
+        assert!(EncodeCheckpoint::from_bytes(&bytes[..20]).is_err());
+    }
+
+    #[test]
+    fn test_encode_reader_matches_encode_streams() {
+        let base = pseudo_random(STREAM_WINDOW_SIZE * 2, 8);
+        let mut new = base.clone();
+        new.splice(3000..3000, pseudo_random(10_000, 9));
+        new[STREAM_WINDOW_SIZE + 500] ^= 0xFF;
+
+        for options in [EncodeOptions::new(), EncodeOptions::new().checksum(true)] {
+            for new in [&new[..], &[]] {
+                let mut expected = Vec::new();
+                encode_streams(new, Cursor::new(&base), &mut expected, &options).unwrap();
+
+                let mut reader = EncodeReader::new(new, Cursor::new(&base), &options).unwrap();
+                let mut delta = Vec::new();
+                reader.read_to_end(&mut delta).unwrap();
+                assert_eq!(delta, expected);
+                assert_eq!(reader.produced(), delta.len() as u64);
+            }
+        }
+    }
+
+    #[test]
+    fn test_encode_reader_is_pulled_through_compression() {
+        /// Counts the target bytes the encoder has taken.
+        struct Counting<'a>(&'a [u8], std::rc::Rc<std::cell::Cell<usize>>);
+
+        impl Read for Counting<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                let n = self.0.read(buf)?;
+                self.1.set(self.1.get() + n);
+                Ok(n)
+            }
+        }
+
+        let base = pseudo_random(STREAM_WINDOW_SIZE * 2, 10);
+        let mut new = base.clone();
+        // Random literal data, so the compressor has output after one window
+        new.splice(100..100, pseudo_random(1024 * 1024, 11));
+        let taken = std::rc::Rc::new(std::cell::Cell::new(0));
+
+        let reader = EncodeReader::new(
+            Counting(&new, taken.clone()),
+            Cursor::new(&base),
+            &EncodeOptions::new(),
+        )
+        .unwrap();
+        let mut compressed = zstd::stream::read::Encoder::with_buffer(reader, 3).unwrap();
+        // A slow consumer reading a little only makes the first window encoded
+        let mut start = [0u8; 16];
+        compressed.read_exact(&mut start).unwrap();
+        assert_eq!(taken.get(), STREAM_WINDOW_SIZE);
+        assert_eq!(
+            compressed.get_ref().target_position(),
+            STREAM_WINDOW_SIZE as u64
+        );
+
+        let mut rest = start.to_vec();
+        compressed.read_to_end(&mut rest).unwrap();
+        assert_eq!(taken.get(), new.len());
+        let delta = zstd::decode_all(&rest[..]).unwrap();
+        assert_eq!(crate::decode(&delta, &base).unwrap(), new);
+    }
+
+    #[test]
+    fn test_stream_empty_target() {
+        let mut delta = Vec::new();
+        let options = EncodeOptions::new();
+        encode_streams(&b""[..], Cursor::new(b"base"), &mut delta, &options).unwrap();
+        assert!(crate::decode(&delta, b"base").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_decode_to_writer_matches_decode() {
+        let base = pseudo_random(100_000, 3);
+        let mut new = base.clone();
+        new.splice(50_000..50_000, pseudo_random(500, 4));
+
+        for options in [EncodeOptions::new(), EncodeOptions::new().rsyncable(true)] {
+            let delta = crate::encode_with_options(&new, &base, &options).unwrap();
+            let mut output = Vec::new();
+            let written = decode_to_writer(&delta, &base, &mut output).unwrap();
+
+            assert_eq!(written, new.len() as u64);
+            assert_eq!(output, new);
+        }
+    }
+
+    #[test]
+    fn test_decode_to_file_truncates_previous_contents() {
+        let base = pseudo_random(10_000, 5);
+        let mut new = base[..5_000].to_vec();
+        new.extend_from_slice(b"tail");
+        let delta = crate::encode(&new, &base).unwrap();
+
+        let path = std::env::temp_dir().join(format!("gdelta-decode-{}", std::process::id()));
+        std::fs::write(&path, vec![0xAA; 20_000]).unwrap();
+        let mut file = File::options().read(true).write(true).open(&path).unwrap();
+
+        // The wrong base is rejected before the file is touched
+        let checked = EncodeOptions::new().base_checksum(true);
+        let checked = crate::encode_with_options(&new, &base, &checked).unwrap();
+        let result = decode_to_file(&checked, &new, &mut file);
+        assert!(matches!(result, Err(GDeltaError::ChecksumMismatch { .. })));
+        assert_eq!(file.metadata().unwrap().len(), 20_000);
+
+        let written = decode_to_file(&delta, &base, &mut file).unwrap();
+        drop(file);
+
+        let output = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(written, new.len() as u64);
+        assert_eq!(output, new);
+    }
+
+    #[test]
+    fn test_stream_rejects_target_size() {
+        let options = EncodeOptions::new().store_target_size(true);
+        let result = encode_streams(&b"new"[..], Cursor::new(b"base"), Vec::new(), &options);
+        assert!(matches!(result, Err(GDeltaError::InvalidOptions(_))));
+    }
+
+    #[test]
+    fn test_stream_rejects_options_windows_cannot_honor() {
+        let options = [
+            EncodeOptions::new().copy_window(1024),
+            EncodeOptions::new().max_ratio(0.9),
+            EncodeOptions::new().min_match_len(64),
+            EncodeOptions::new().extend_matches_backward(true),
+        ];
+        for options in &options {
+            let result = encode_streams(&b"new"[..], Cursor::new(b"base"), Vec::new(), options);
+            assert!(
+                matches!(result, Err(GDeltaError::InvalidOptions(_))),
+                "{options:?}"
+            );
+            assert!(EncodeReader::new(&b"new"[..], Cursor::new(b"base"), options).is_err());
+        }
+    }
+}
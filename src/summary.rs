@@ -0,0 +1,153 @@
+//! Structural validation of deltas without the base data.
+
+use crate::buffer::BufferStream;
+use crate::delta::{RawDelta, prepare_payload, target_copy_in_bounds};
+use crate::error::{GDeltaError, Result};
+use crate::header::{self, DeltaHeader};
+use crate::varint::read_delta_unit;
+
+/// What a delta does, as reported by [`crate::validate`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeltaSummary {
+    /// Format version the delta was written with; 0 for raw deltas.
+    pub format_version: u8,
+    /// Size of the target the delta reconstructs.
+    pub target_size: u64,
+    /// Number of instructions, over all segments.
+    pub instructions: u64,
+    /// Target bytes copied from the base or from earlier in the target.
+    pub copied_bytes: u64,
+    /// Target bytes stored as literal data in the delta.
+    pub literal_bytes: u64,
+    /// Whether the delta carries a checksum, which was verified.
+    pub has_checksum: bool,
+}
+
+pub fn validate(delta: &[u8], base_len: usize) -> Result<DeltaSummary> {
+    let framed = header::parse(delta)?;
+    framed.verify()?;
+    let (raw_deltas, target_size) = prepare_payload(framed.header, framed.payload, base_len)?;
+    summarize(framed.header, &raw_deltas, target_size, base_len)
+}
+
+/// Checks the copies of prepared deltas against the base size and the
+/// target reconstructed so far, and summarizes them.
+pub(crate) fn summarize(
+    header: Option<DeltaHeader<'_>>,
+    raw_deltas: &[RawDelta<'_>],
+    target_size: u64,
+    base_len: usize,
+) -> Result<DeltaSummary> {
+    let mut summary = DeltaSummary {
+        format_version: header.map_or(0, |header| header.version),
+        target_size,
+        instructions: 0,
+        copied_bytes: 0,
+        literal_bytes: 0,
+        has_checksum: header.is_some_and(|header| header.has_checksum()),
+    };
+    let base_len = base_len as u64;
+    // Bytes of the target produced by the instructions checked so far
+    let mut output_len = 0u64;
+    for raw in raw_deltas {
+        let mut stream = BufferStream::from_slice(&raw.instructions);
+        while stream.remaining() > 0 {
+            let unit = read_delta_unit(&mut stream)?;
+            if unit.is_copy && raw.target_copies && unit.offset >= base_len {
+                let offset = unit.offset - base_len;
+                if !target_copy_in_bounds(offset, unit.length, output_len) {
+                    return Err(GDeltaError::InvalidDelta(format!(
+                        "Target copy offset {offset} is past output size {output_len}"
+                    )));
+                }
+            } else if unit.is_copy && unit.offset.saturating_add(unit.length) > base_len {
+                return Err(GDeltaError::InvalidDelta(format!(
+                    "Copy offset {} + length {} exceeds base size {base_len}",
+                    unit.offset, unit.length
+                )));
+            }
+
+            if unit.is_copy {
+                summary.copied_bytes += unit.length;
+            } else {
+                summary.literal_bytes += unit.length;
+            }
+            summary.instructions += 1;
+            output_len += unit.length;
+        }
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EncodeOptions;
+
+    #[test]
+    fn test_summary_matches_delta() {
+        let base = b"The quick brown fox jumps over the lazy dog. ".repeat(20);
+        let mut new = base.clone();
+        new[100..110].copy_from_slice(b"0123456789");
+        new.extend_from_slice(&base[..200]);
+
+        let options = EncodeOptions::new()
+            .checksum(true)
+            .store_target_size(true)
+            .target_matches(true);
+        let delta = crate::encode_with_options(&new, &base, &options).unwrap();
+        let summary = validate(&delta, base.len()).unwrap();
+        assert_eq!(summary.target_size, new.len() as u64);
+        assert_eq!(
+            summary.copied_bytes + summary.literal_bytes,
+            new.len() as u64
+        );
+        assert!(summary.literal_bytes >= 10);
+        assert!(summary.has_checksum);
+        assert_eq!(summary.format_version, crate::FORMAT_VERSION);
+
+        let identity = crate::encode_with_options(&base, &base, &options).unwrap();
+        let summary = validate(&identity, base.len()).unwrap();
+        assert_eq!(
+            (summary.copied_bytes, summary.instructions),
+            (base.len() as u64, 1)
+        );
+    }
+
+    #[test]
+    fn test_rejects_copies_out_of_bounds() {
+        let base = b"0123456789".repeat(10);
+        let delta = crate::encode(&base[..90], &base).unwrap();
+        assert!(validate(&delta, base.len()).is_ok());
+        assert!(matches!(
+            validate(&delta, 50),
+            Err(GDeltaError::InvalidDelta(_))
+        ));
+
+        // A copy from target bytes that have not been produced yet
+        let mut new = b"abcdefghijklmnopqrstuvwxyz".to_vec();
+        new.extend_from_slice(&new.clone());
+        let options = EncodeOptions::new().target_matches(true);
+        let delta = crate::encode_with_options(&new, b"0123456789", &options).unwrap();
+        let summary = validate(&delta, 10).unwrap();
+        assert_eq!(summary.copied_bytes, 26);
+        // Read from further on, the copy overlaps the bytes it writes
+        assert!(validate(&delta, 5).is_ok());
+    }
+
+    #[cfg(feature = "unstable-format")]
+    #[test]
+    fn test_rejects_overlapping_copy_out_of_bounds() {
+        // One literal byte, then a copy from the target starting at its end
+        let corpus = crate::format::seed_corpus(16);
+        let (_, overlapping) = corpus
+            .iter()
+            .find(|(name, _)| *name == "target-copy-overlapping")
+            .unwrap();
+        assert_eq!(validate(overlapping, 16).unwrap().target_size, 65);
+        assert!(matches!(
+            validate(overlapping, 15),
+            Err(GDeltaError::InvalidDelta(_))
+        ));
+    }
+}
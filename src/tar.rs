@@ -0,0 +1,259 @@
+//! Writing tar archives.
+//!
+//! Members get ustar headers, preceded by a PAX extended header when a
+//! path, link target, size or time does not fit them or extended
+//! attributes are recorded, so GNU tar, bsdtar and container tooling all
+//! read the archives the same way.
+
+use std::io::{self, Write};
+
+/// Size of a tar header and of the blocks member data is padded to.
+pub(crate) const BLOCK_SIZE: usize = 512;
+
+/// Largest value of the 12-byte octal size and time fields.
+const MAX_OCTAL: u64 = 0o777_7777_7777;
+
+/// Kind of a tar member.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Kind<'a> {
+    File,
+    /// A hard link to an earlier member.
+    HardLink(&'a str),
+    Symlink(&'a str),
+    Directory,
+}
+
+/// The header fields of a member.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Member<'a> {
+    /// `/`-separated path, without a trailing `/` for directories.
+    pub path: &'a str,
+    pub kind: Kind<'a>,
+    /// Permission bits.
+    pub mode: u32,
+    /// Modification time in seconds since the Unix epoch.
+    pub mtime: u64,
+    /// Extended attributes as names and values.
+    pub xattrs: &'a [(String, Vec<u8>)],
+}
+
+/// Writes members to a tar archive.
+pub(crate) struct TarWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> TarWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    /// Appends `member` with `contents`, which must be empty unless it is a
+    /// file.
+    pub fn append(&mut self, member: &Member<'_>, contents: &[u8]) -> io::Result<()> {
+        let mut path = member.path.to_string();
+        if member.kind == Kind::Directory {
+            path.push('/');
+        }
+        let link = match member.kind {
+            Kind::HardLink(target) | Kind::Symlink(target) => target,
+            Kind::File | Kind::Directory => "",
+        };
+        let size = contents.len() as u64;
+
+        let mut records = Vec::new();
+        let split = split_path(&path);
+        if split.is_none() {
+            pax_record(&mut records, "path", path.as_bytes());
+        }
+        if link.len() > 100 {
+            pax_record(&mut records, "linkpath", link.as_bytes());
+        }
+        if size > MAX_OCTAL {
+            pax_record(&mut records, "size", size.to_string().as_bytes());
+        }
+        if member.mtime > MAX_OCTAL {
+            pax_record(&mut records, "mtime", member.mtime.to_string().as_bytes());
+        }
+        for (name, value) in member.xattrs {
+            pax_record(&mut records, &format!("SCHILY.xattr.{name}"), value);
+        }
+        if !records.is_empty() {
+            let mut header = header(b"././@PaxHeader", b'x', 0o644, records.len() as u64, 0);
+            self.write_member(&mut header, &records)?;
+        }
+
+        let typeflag = match member.kind {
+            Kind::File => b'0',
+            Kind::HardLink(_) => b'1',
+            Kind::Symlink(_) => b'2',
+            Kind::Directory => b'5',
+        };
+        let (prefix, name) = split.unwrap_or(("", truncate(&path, 100)));
+        let mut header = header(
+            name.as_bytes(),
+            typeflag,
+            member.mode & 0o7777,
+            size.min(MAX_OCTAL),
+            member.mtime.min(MAX_OCTAL),
+        );
+        let link = truncate(link, 100);
+        header[157..157 + link.len()].copy_from_slice(link.as_bytes());
+        header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+        self.write_member(&mut header, contents)
+    }
+
+    /// Writes the end-of-archive marker and returns the underlying writer,
+    /// flushed.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.write_all(&[0; 2 * BLOCK_SIZE])?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    /// Checksums `header` and writes it followed by the padded `data`.
+    fn write_member(&mut self, header: &mut [u8; BLOCK_SIZE], data: &[u8]) -> io::Result<()> {
+        let checksum = header_checksum(header);
+        header[148..156].copy_from_slice(format!("{checksum:06o}\0 ").as_bytes());
+        self.writer.write_all(header)?;
+        self.writer.write_all(data)?;
+        let padding = data.len().next_multiple_of(BLOCK_SIZE) - data.len();
+        self.writer.write_all(&[0; BLOCK_SIZE][..padding])
+    }
+}
+
+/// Builds a ustar header without its checksum, link target and prefix.
+fn header(name: &[u8], typeflag: u8, mode: u32, size: u64, mtime: u64) -> [u8; BLOCK_SIZE] {
+    let mut header = [0; BLOCK_SIZE];
+    header[..name.len()].copy_from_slice(name);
+    header[100..108].copy_from_slice(format!("{mode:07o}\0").as_bytes());
+    header[108..116].copy_from_slice(b"0000000\0");
+    header[116..124].copy_from_slice(b"0000000\0");
+    header[124..136].copy_from_slice(format!("{size:011o}\0").as_bytes());
+    header[136..148].copy_from_slice(format!("{mtime:011o}\0").as_bytes());
+    header[156] = typeflag;
+    header[257..265].copy_from_slice(b"ustar\x0000");
+    header
+}
+
+/// Sums the header bytes with the checksum field counted as spaces.
+pub(crate) fn header_checksum(header: &[u8]) -> u64 {
+    header
+        .iter()
+        .enumerate()
+        .map(|(i, &byte)| {
+            if (148..156).contains(&i) {
+                u64::from(b' ')
+            } else {
+                u64::from(byte)
+            }
+        })
+        .sum()
+}
+
+/// Splits `path` into the ustar prefix and name fields, if it fits them.
+fn split_path(path: &str) -> Option<(&str, &str)> {
+    if path.len() <= 100 {
+        return Some(("", path));
+    }
+    // The prefix ends at a `/`, which is not stored
+    let trimmed = path.strip_suffix('/').unwrap_or(path);
+    trimmed
+        .match_indices('/')
+        .map(|(index, _)| index)
+        .find(|&index| index <= 155 && path.len() - index - 1 <= 100)
+        .map(|index| (&path[..index], &path[index + 1..]))
+}
+
+/// Cuts `text` to at most `len` bytes at a character boundary.
+fn truncate(text: &str, len: usize) -> &str {
+    let mut end = text.len().min(len);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
+
+/// Appends a `"<length> <key>=<value>\n"` PAX record, whose length counts
+/// its own digits.
+fn pax_record(records: &mut Vec<u8>, key: &str, value: &[u8]) {
+    let rest = key.len() + value.len() + 3;
+    let mut len = rest + 1;
+    while len != rest + len.to_string().len() {
+        len = rest + len.to_string().len();
+    }
+    records.extend_from_slice(format!("{len} {key}=").as_bytes());
+    records.extend_from_slice(value);
+    records.push(b'\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_headers() {
+        let long = format!("{}/{}", "d".repeat(120), "f".repeat(90));
+        let mut tar = TarWriter::new(Vec::new());
+        let member = |path, kind| Member {
+            path,
+            kind,
+            mode: 0o755,
+            mtime: 1_700_000_000,
+            xattrs: &[],
+        };
+        tar.append(&member("bin", Kind::Directory), b"").unwrap();
+        tar.append(&member("bin/app", Kind::File), &[7; 600])
+            .unwrap();
+        tar.append(&member("bin/run", Kind::Symlink("app")), b"")
+            .unwrap();
+        tar.append(&member(&long, Kind::File), b"x").unwrap();
+        let xattrs = [("user.note".to_string(), b"hi".to_vec())];
+        tar.append(
+            &Member {
+                xattrs: &xattrs,
+                ..member(&"n".repeat(300), Kind::File)
+            },
+            b"",
+        )
+        .unwrap();
+        let archive = tar.finish().unwrap();
+
+        let members = crate::oci::members(&archive).unwrap();
+        let paths: Vec<&str> = members.iter().map(|m| m.path.as_str()).collect();
+        let truncated = "n".repeat(100);
+        assert_eq!(
+            paths,
+            [
+                "bin/",
+                "bin/app",
+                "bin/run",
+                long.as_str(),
+                "././@PaxHeader",
+                truncated.as_str()
+            ]
+        );
+        assert_eq!(members[1].range, 512..2048);
+        assert_eq!(archive[members[2].range.start + 156], b'2');
+        assert_eq!(&archive[members[2].range.start + 157..][..4], b"app\0");
+
+        let pax = &archive[members[4].range.start + BLOCK_SIZE..];
+        let path_record = format!("310 path={}\n", "n".repeat(300));
+        assert!(pax.starts_with(path_record.as_bytes()));
+        assert!(pax[path_record.len()..].starts_with(b"29 SCHILY.xattr.user.note=hi\n"));
+        assert!(archive.ends_with(&[0; 2 * BLOCK_SIZE]));
+    }
+
+    #[test]
+    fn test_pax_record_lengths() {
+        for value_len in [0, 1, 2, 5, 6, 90, 95, 96, 990, 996, 997] {
+            let mut records = Vec::new();
+            pax_record(&mut records, "path", &vec![b'a'; value_len]);
+            let len: usize =
+                std::str::from_utf8(&records[..records.iter().position(|&b| b == b' ').unwrap()])
+                    .unwrap()
+                    .parse()
+                    .unwrap();
+            assert_eq!(len, records.len(), "{value_len}");
+        }
+    }
+}
@@ -0,0 +1,99 @@
+//! Rate limiting for streaming output.
+
+use std::io::{self, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A writer that limits the rate at which data reaches the inner writer.
+///
+/// Wrap the output of [`crate::decode_to_writer`] or
+/// [`crate::decode_from_source`] in it so that a background update does not
+/// saturate the disk. Data is passed on in chunks of a tenth of a second's
+/// worth of bytes, and after each chunk the writer sleeps until the average
+/// rate since the first write is back at the limit.
+#[derive(Debug)]
+pub struct ThrottledWriter<W> {
+    inner: W,
+    bytes_per_second: u64,
+    written: u64,
+    start: Option<Instant>,
+}
+
+impl<W: Write> ThrottledWriter<W> {
+    /// Wraps `inner`, limiting writes to `bytes_per_second` on average.
+    ///
+    /// A limit of 0 is treated as 1 byte per second.
+    pub fn new(inner: W, bytes_per_second: u64) -> Self {
+        Self {
+            inner,
+            bytes_per_second: bytes_per_second.max(1),
+            written: 0,
+            start: None,
+        }
+    }
+
+    /// Returns the number of bytes written so far.
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+
+    /// Returns a reference to the inner writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Returns the inner writer.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for ThrottledWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let start = *self.start.get_or_insert_with(Instant::now);
+        let chunk = usize::try_from(self.bytes_per_second / 10)
+            .unwrap_or(usize::MAX)
+            .max(1);
+        let n = self.inner.write(&buf[..buf.len().min(chunk)])?;
+        self.written += n as u64;
+
+        let due = Duration::from_secs_f64(self.written as f64 / self.bytes_per_second as f64);
+        if let Some(wait) = due.checked_sub(start.elapsed()) {
+            thread::sleep(wait);
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_throttled_decode_takes_expected_time() {
+        let base = vec![7u8; 100_000];
+        let mut new = base.clone();
+        new.extend_from_slice(&[1u8; 100_000]);
+        let delta = crate::encode(&new, &base).unwrap();
+
+        let start = Instant::now();
+        let mut writer = ThrottledWriter::new(Vec::new(), 1_000_000);
+        crate::decode_to_writer(&delta, &base, &mut writer).unwrap();
+        let elapsed = start.elapsed();
+
+        assert_eq!(writer.written(), new.len() as u64);
+        assert_eq!(writer.into_inner(), new);
+        assert!(elapsed >= Duration::from_millis(190), "took {elapsed:?}");
+    }
+
+    #[test]
+    fn test_writes_are_split_into_chunks() {
+        let mut writer = ThrottledWriter::new(Vec::new(), 100);
+        assert_eq!(writer.write(b"0123456789abcdef").unwrap(), 10);
+        assert_eq!(writer.get_ref().as_slice(), b"0123456789");
+    }
+}
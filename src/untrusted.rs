@@ -0,0 +1,180 @@
+//! Decoding deltas from untrusted sources within fixed limits.
+
+use crate::checksum::crc32;
+use crate::delta::{
+    RawDelta, check_base_checksum, check_target_checksum, decode_raw, prepare_payload,
+};
+use crate::error::{GDeltaError, Result};
+use crate::header::{self, Framed};
+use crate::summary::summarize;
+
+/// Limits enforced by [`crate::decode_untrusted`].
+///
+/// The defaults are conservative: deltas and targets of at most 256 MiB,
+/// at most 16 million instructions, and a required checksum. Raise them as
+/// far as the service can afford.
+///
+/// # Examples
+///
+/// ```
+/// use gdelta::DecodeLimits;
+///
+/// let limits = DecodeLimits::new()
+///     .max_output_size(16 * 1024 * 1024)
+///     .require_checksum(false);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodeLimits {
+    pub(crate) max_delta_size: u64,
+    pub(crate) max_output_size: u64,
+    pub(crate) max_instructions: u64,
+    pub(crate) require_checksum: bool,
+}
+
+impl DecodeLimits {
+    /// Creates the default limits.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Rejects deltas larger than `bytes`.
+    #[must_use]
+    pub fn max_delta_size(mut self, bytes: u64) -> Self {
+        self.max_delta_size = bytes;
+        self
+    }
+
+    /// Rejects deltas that reconstruct more than `bytes`.
+    ///
+    /// The output is the only allocation that grows with the delta, so this
+    /// also caps the memory a decode can take beyond the delta and base.
+    #[must_use]
+    pub fn max_output_size(mut self, bytes: u64) -> Self {
+        self.max_output_size = bytes;
+        self
+    }
+
+    /// Rejects deltas with more than `count` instructions, which bounds the
+    /// decoding time independently of the output size.
+    #[must_use]
+    pub fn max_instructions(mut self, count: u64) -> Self {
+        self.max_instructions = count;
+        self
+    }
+
+    /// Rejects deltas without a checksum trailer (see
+    /// [`crate::EncodeOptions::checksum`]), so corruption in transit is
+    /// always detected.
+    #[must_use]
+    pub fn require_checksum(mut self, required: bool) -> Self {
+        self.require_checksum = required;
+        self
+    }
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        Self {
+            max_delta_size: 256 * 1024 * 1024,
+            max_output_size: 256 * 1024 * 1024,
+            max_instructions: 16 * 1024 * 1024,
+            require_checksum: true,
+        }
+    }
+}
+
+pub fn decode_untrusted(delta: &[u8], base_data: &[u8], limits: &DecodeLimits) -> Result<Vec<u8>> {
+    let (framed, raw_deltas) = check_limits(delta, base_data.len(), limits)?;
+    check_base_checksum(framed.header, base_data)?;
+    let output = decode_raw(&raw_deltas, base_data)?;
+    check_target_checksum(framed.header, || crc32(&output))?;
+    Ok(output)
+}
+
+/// Checks `delta` against `limits` and a base of `base_len` bytes without
+/// the base data, returning its framing and validated raw deltas.
+pub(crate) fn check_limits<'a>(
+    delta: &'a [u8],
+    base_len: usize,
+    limits: &DecodeLimits,
+) -> Result<(Framed<'a>, Vec<RawDelta<'a>>)> {
+    check_limit("delta size", delta.len() as u64, limits.max_delta_size)?;
+
+    let framed = header::parse(delta)?;
+    if limits.require_checksum && framed.checksum.is_none() {
+        return Err(GDeltaError::InvalidDelta(
+            "Delta has no checksum".to_string(),
+        ));
+    }
+    framed.verify()?;
+
+    let (raw_deltas, target_size) = prepare_payload(framed.header, framed.payload, base_len)?;
+    check_limit("target size", target_size, limits.max_output_size)?;
+    let summary = summarize(framed.header, &raw_deltas, target_size, base_len)?;
+    check_limit(
+        "instruction count",
+        summary.instructions,
+        limits.max_instructions,
+    )?;
+    Ok((framed, raw_deltas))
+}
+
+fn check_limit(what: &str, value: u64, limit: u64) -> Result<()> {
+    if value > limit {
+        return Err(GDeltaError::LimitExceeded(format!(
+            "{what} {value} exceeds the limit of {limit}"
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EncodeOptions;
+
+    fn sample() -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+        let base = b"The quick brown fox jumps over the lazy dog. ".repeat(100);
+        let mut new = base.clone();
+        new[1_000..1_010].copy_from_slice(b"0123456789");
+        new.extend_from_slice(&base);
+        let options = EncodeOptions::new().checksum(true);
+        let delta = crate::encode_with_options(&new, &base, &options).unwrap();
+        (base, new, delta)
+    }
+
+    #[test]
+    fn test_decodes_within_limits() {
+        let (base, new, delta) = sample();
+        let limits = DecodeLimits::new();
+        assert_eq!(decode_untrusted(&delta, &base, &limits).unwrap(), new);
+
+        let plain = crate::encode(&new, &base).unwrap();
+        assert!(matches!(
+            decode_untrusted(&plain, &base, &limits),
+            Err(GDeltaError::InvalidDelta(_))
+        ));
+        let limits = limits.require_checksum(false);
+        assert_eq!(decode_untrusted(&plain, &base, &limits).unwrap(), new);
+    }
+
+    #[test]
+    fn test_rejects_deltas_over_limits() {
+        let (base, new, delta) = sample();
+        for limits in [
+            DecodeLimits::new().max_delta_size(delta.len() as u64 - 1),
+            DecodeLimits::new().max_output_size(new.len() as u64 - 1),
+            DecodeLimits::new().max_instructions(1),
+        ] {
+            assert!(matches!(
+                decode_untrusted(&delta, &base, &limits),
+                Err(GDeltaError::LimitExceeded(_))
+            ));
+        }
+
+        let mut corrupted = delta.clone();
+        corrupted[6] ^= 1;
+        assert!(decode_untrusted(&corrupted, &base, &DecodeLimits::new()).is_err());
+        assert!(decode_untrusted(&delta, &base[..100], &DecodeLimits::new()).is_err());
+    }
+}
@@ -4,7 +4,7 @@
 //! stores 7 bits of the value and 1 bit indicating if more bytes follow.
 
 use crate::buffer::BufferStream;
-use crate::error::Result;
+use crate::error::{GDeltaError, Result};
 
 /// Number of value bits per byte in varint encoding.
 const VARINT_BITS: u8 = 7;
@@ -50,6 +50,11 @@ pub fn write_varint(buffer: &mut BufferStream, value: u64) {
     }
 }
 
+/// Returns the number of bytes [`write_varint`] uses for `value`.
+pub fn varint_len(value: u64) -> usize {
+    (64 - (value | 1).leading_zeros()).div_ceil(u32::from(VARINT_BITS)) as usize
+}
+
 /// Reads a variable-length integer from the buffer.
 #[allow(clippy::cast_lossless)]
 pub fn read_varint(buffer: &mut BufferStream) -> Result<u64> {
@@ -73,6 +78,12 @@ pub fn read_varint(buffer: &mut BufferStream) -> Result<u64> {
     loop {
         let byte = buffer.read_u8()?;
         let more = (byte & 0x80) != 0;
+        // The tenth byte holds the top bit; anything past it cannot fit
+        if shift > 63 || (shift == 63 && (byte & 0x7F) > 1) {
+            return Err(GDeltaError::InvalidDelta(
+                "Varint exceeds 64 bits".to_string(),
+            ));
+        }
         value |= ((byte & 0x7F) as u64) << shift;
         shift += 7;
         if !more {
@@ -165,10 +176,38 @@ pub fn read_delta_unit(buffer: &mut BufferStream) -> Result<DeltaUnit> {
     })
 }
 
+/// Returns the number of bytes [`write_delta_unit`] uses for `unit`.
+pub fn delta_unit_len(unit: &DeltaUnit) -> usize {
+    let remaining_length = unit.length >> HEAD_VARINT_BITS;
+    let mut len = 1;
+    if remaining_length > 0 {
+        len += varint_len(remaining_length);
+    }
+    if unit.is_copy {
+        len += varint_len(unit.offset);
+    }
+    len
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_encoded_lengths() {
+        for value in [0, 1, 63, 64, 127, 128, 16383, 16384, u64::MAX] {
+            let mut buffer = BufferStream::with_capacity(10);
+            write_varint(&mut buffer, value);
+            assert_eq!(varint_len(value), buffer.len());
+
+            for unit in [DeltaUnit::copy(value, value), DeltaUnit::literal(value)] {
+                let mut buffer = BufferStream::with_capacity(20);
+                write_delta_unit(&mut buffer, &unit);
+                assert_eq!(delta_unit_len(&unit), buffer.len());
+            }
+        }
+    }
+
     #[test]
     fn test_varint_encoding() {
         let mut buffer = BufferStream::with_capacity(10);
@@ -184,6 +223,25 @@ mod tests {
         assert_eq!(read_varint(&mut buffer).unwrap(), 16383);
     }
 
+    #[test]
+    fn test_varint_overflow() {
+        let mut buffer = BufferStream::with_capacity(10);
+        write_varint(&mut buffer, u64::MAX);
+        buffer.set_position(0);
+        assert_eq!(read_varint(&mut buffer).unwrap(), u64::MAX);
+
+        for bytes in [
+            &[0xFF; 11][..],
+            &[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x02],
+        ] {
+            let mut buffer = BufferStream::from_slice(bytes);
+            assert!(matches!(
+                read_varint(&mut buffer),
+                Err(GDeltaError::InvalidDelta(_))
+            ));
+        }
+    }
+
     #[test]
     fn test_delta_unit_copy() {
         let mut buffer = BufferStream::with_capacity(20);
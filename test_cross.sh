@@ -0,0 +1,57 @@
+#!/bin/bash
+# gdelta cross-target test runner
+#
+# Runs the library tests and the portability, compatibility and robustness
+# test targets on foreign architectures under QEMU user-mode emulation, so
+# that byte-identical output can be checked on a big-endian and a 32-bit
+# target without dedicated hardware:
+# - s390x-unknown-linux-gnu (64-bit, big-endian)
+# - armv7-unknown-linux-gnueabihf (32-bit, little-endian)
+#
+# Requires `cross` (cargo install cross), which runs each target in a
+# container with the matching toolchain and QEMU. Pass target triples as
+# arguments to test other targets, e.g.:
+#   ./test_cross.sh powerpc-unknown-linux-gnu
+
+set -e  # Exit on any error
+
+# Colors for output
+GREEN='\033[0;32m'
+RED='\033[0;31m'
+BLUE='\033[0;34m'
+NC='\033[0m' # No Color
+
+if ! command -v cross > /dev/null; then
+    echo -e "${RED}cross is not installed${NC}: cargo install cross"
+    exit 1
+fi
+
+TARGETS=("$@")
+if [ ${#TARGETS[@]} -eq 0 ]; then
+    TARGETS=(s390x-unknown-linux-gnu armv7-unknown-linux-gnueabihf)
+fi
+
+cd "$(dirname "$0")"
+
+FAILED=()
+for target in "${TARGETS[@]}"; do
+    echo -e "${BLUE}=== $target ===${NC}"
+    # SIMD code paths only exist on some targets; test the scalar build too
+    for features in "" "--no-default-features"; do
+        if cross test --target "$target" $features \
+            --lib --test portability --test compat --test robustness; then
+            echo -e "${GREEN}✓ PASS${NC}: $target $features"
+        else
+            echo -e "${RED}✗ FAIL${NC}: $target $features"
+            FAILED+=("$target $features")
+        fi
+    done
+    echo ""
+done
+
+if [ ${#FAILED[@]} -eq 0 ]; then
+    echo -e "${GREEN}🎉 All targets passed!${NC}"
+else
+    echo -e "${RED}❌ Failed: ${FAILED[*]}${NC}"
+    exit 1
+fi
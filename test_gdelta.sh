@@ -10,6 +10,7 @@
 # - Error handling
 # - Memory warnings
 # - Output formatting
+# - Benchmark report comparison
 
 set -e  # Exit on any error
 
@@ -56,7 +57,7 @@ test_fail() {
 # 1. Create test files
 # ============================================================================
 
-echo -e "${BLUE}[1/9] Creating test files...${NC}"
+echo -e "${BLUE}[1/10] Creating test files...${NC}"
 
 # Small text file
 echo "Hello, World!" > small.txt
@@ -104,7 +105,7 @@ echo -e "${GREEN}✓ Test files created${NC}\n"
 # 2. Basic encode/decode (no compression)
 # ============================================================================
 
-echo -e "${BLUE}[2/9] Testing basic encode/decode...${NC}"
+echo -e "${BLUE}[2/10] Testing basic encode/decode...${NC}"
 
 if gdelta encode small.txt small_modified.txt -o test1.delta -q; then
     if [ -f test1.delta ]; then
@@ -126,13 +127,171 @@ else
     test_fail "Basic decode" "Command failed"
 fi
 
+if gdelta decode small.txt test1.delta -o test1_throttled.txt --max-rate 1000000 -q; then
+    if diff -q small_modified.txt test1_throttled.txt > /dev/null; then
+        test_pass "Throttled decode"
+    else
+        test_fail "Throttled decode" "Output doesn't match original"
+    fi
+else
+    test_fail "Throttled decode" "Command failed"
+fi
+
+mkdir -p test_named
+if gdelta encode small.txt small_modified.txt -o test_named --auto-name -q; then
+    named=$(ls test_named)
+    if [[ "$named" =~ ^[0-9a-f]{64}-[0-9a-f]{64}\.gd$ ]] \
+        && ! gdelta encode small.txt small_modified.txt -o test_named --auto-name -q 2>/dev/null; then
+        test_pass "Encode with a content-addressed name"
+    else
+        test_fail "Encode with a content-addressed name" "Unexpected name: $named"
+    fi
+else
+    test_fail "Encode with a content-addressed name" "Command failed"
+fi
+
+if dump=$(gdelta debug small.txt test1.delta --offset 5 -o test1_debug.txt); then
+    if [[ "$dump" == *"Stopped at breakpoint"* ]] && [ "$(wc -c < test1_debug.txt)" -le 5 ] \
+        && cmp -s test1_debug.txt <(head -c "$(wc -c < test1_debug.txt)" small_modified.txt); then
+        test_pass "Debug decode to a target offset"
+    else
+        test_fail "Debug decode to a target offset" "Unexpected state: $dump"
+    fi
+else
+    test_fail "Debug decode to a target offset" "Command failed"
+fi
+
+cp small.txt test1_inplace.txt
+chmod 755 test1_inplace.txt
+if gdelta decode test1_inplace.txt test1.delta -o test1_inplace.txt --force -q \
+    && diff -q small_modified.txt test1_inplace.txt > /dev/null \
+    && [ -x test1_inplace.txt ] && [ -z "$(ls -A | grep gdelta-tmp)" ]; then
+    test_pass "Decode in place"
+else
+    test_fail "Decode in place" "Output or permissions don't match"
+fi
+
+if gdelta decode small.txt test1.delta -o test1_journal.txt --journal test.journal -q \
+    && ! gdelta decode small.txt missing.delta -o test1_journal.txt --journal test.journal -q 2>/dev/null \
+    && [ "$(wc -l < test.journal)" -eq 2 ] \
+    && head -n 1 test.journal | grep -q '"command":"decode".*"result":"ok"' \
+    && tail -n 1 test.journal | grep -q '"result":"error"'; then
+    test_pass "Journal of operations"
+else
+    test_fail "Journal of operations" "Unexpected journal: $(cat test.journal)"
+fi
+
+mkdir -p tree_old/src tree_new/src tree_new/target
+cp small.txt tree_old/src/notes.txt
+cp small_modified.txt tree_new/src/notes.txt
+cp medium.json tree_new/target/build.json
+cp medium.json tree_new/scratch.tmp
+echo "target/" > tree_new/.gdignore
+if gdelta encode-dir tree_old tree_new -o tree.gdpack --exclude '*.tmp' -q \
+    && gdelta decode-dir tree_old tree.gdpack -q \
+    && diff -q tree_new/src/notes.txt tree_old/src/notes.txt > /dev/null \
+    && [ ! -e tree_old/target ] && [ ! -e tree_old/scratch.tmp ]; then
+    test_pass "Directory bundle with ignore rules"
+else
+    test_fail "Directory bundle with ignore rules" "Tree doesn't match"
+fi
+
+cp small.txt tree_base.txt
+if gdelta extract tree.gdpack src/notes.txt --base tree_old -o tree_notes.txt -q 2>/dev/null; then
+    test_fail "Extract a file from a bundle" "Extracted against the patched tree"
+elif mkdir -p tree_base/src && cp tree_base.txt tree_base/src/notes.txt \
+    && gdelta extract tree.gdpack src/notes.txt --base tree_base -o tree_notes.txt -q \
+    && diff -q tree_new/src/notes.txt tree_notes.txt > /dev/null; then
+    test_pass "Extract a file from a bundle"
+else
+    test_fail "Extract a file from a bundle" "Output doesn't match"
+fi
+
+mkdir -p tree_untar
+if gdelta decode-dir tree_base tree.gdpack --to-tar tree.tar -q \
+    && tar -xf tree.tar -C tree_untar \
+    && diff -q tree_new/src/notes.txt tree_untar/src/notes.txt > /dev/null \
+    && diff -q tree_base.txt tree_base/src/notes.txt > /dev/null; then
+    test_pass "Decode a bundle to a tar archive"
+else
+    test_fail "Decode a bundle to a tar archive" "Archive doesn't match"
+fi
+
+if gdelta decode-dir tree_base tree.gdpack --dry-run > tree_check.json \
+    && grep -q '"ready": true' tree_check.json \
+    && diff -q tree_base.txt tree_base/src/notes.txt > /dev/null \
+    && ! gdelta decode-dir tree_old tree.gdpack --dry-run > tree_check.json 2>/dev/null \
+    && grep -q '"ready": false' tree_check.json \
+    && grep -q '"error": "Checksum mismatch' tree_check.json; then
+    test_pass "Dry-run a bundle apply"
+else
+    test_fail "Dry-run a bundle apply" "Unexpected report: $(cat tree_check.json)"
+fi
+
+# Simulate a decode-dir killed between moving the tree aside and swapping in the new one
+mkdir -p tree_rollback/src tree_rollback.gdpack-staging/src
+cp small.txt tree_rollback/src/notes.txt
+cp small_modified.txt tree_rollback.gdpack-staging/src/notes.txt
+mv tree_rollback tree_rollback.gdpack-old
+printf 'gdpack transaction 1\nphase swapping\n' > tree_rollback.gdpack-txn
+if gdelta rollback tree_rollback.gdpack-txn -q \
+    && diff -q small.txt tree_rollback/src/notes.txt > /dev/null \
+    && [ ! -e tree_rollback.gdpack-old ] && [ ! -e tree_rollback.gdpack-staging ] \
+    && [ ! -e tree_rollback.gdpack-txn ]; then
+    test_pass "Roll back an unfinished directory apply"
+else
+    test_fail "Roll back an unfinished directory apply" "Tree wasn't restored"
+fi
+
+head -c 1048576 /dev/zero > zeros.bin
+gdelta encode small.txt zeros.bin -o zeros.delta -c zstd -q
+gdelta serve --socket gdelta.sock --max-output-size 64 -q &
+SERVER_PID=$!
+sleep 1
+SERVE_OUTPUT=$(python3 - <<'PY'
+import json, socket
+
+client = socket.socket(socket.AF_UNIX)
+client.connect("gdelta.sock")
+stream = client.makefile("rw")
+for request in [
+    {"op": "encode", "base": "small.txt", "new": "small_modified.txt", "output": "served1.delta"},
+    {"op": "encode", "base": "small.txt", "new": "small_modified.txt", "output": "served2.delta"},
+    {"op": "decode", "base": "small.txt", "delta": "served2.delta", "output": "served.txt"},
+    {"op": "encode", "base": "medium.json", "new": "medium_modified.json", "output": "served3.delta"},
+    {"op": "decode", "base": "medium.json", "delta": "served3.delta", "output": "served.json"},
+    {"op": "decode", "base": "small.txt", "delta": "zeros.delta", "output": "served.bin"},
+]:
+    stream.write(json.dumps(request) + "\n")
+    stream.flush()
+    print(stream.readline().strip())
+stream.write("x" * 100000 + "\n")
+stream.flush()
+print(stream.readline().strip())
+PY
+) || true
+SOCKET_MODE=$(stat -c %a gdelta.sock 2>/dev/null || stat -f %Lp gdelta.sock)
+kill $SERVER_PID
+if [ "$(echo "$SERVE_OUTPUT" | grep -c '"ok":true')" -eq 4 ] \
+    && echo "$SERVE_OUTPUT" | sed -n 2p | grep -q '"cached":true' \
+    && echo "$SERVE_OUTPUT" | sed -n 5p | grep -q 'exceeds the limit' \
+    && echo "$SERVE_OUTPUT" | sed -n 6p | grep -q 'Decompressed delta exceeds' \
+    && echo "$SERVE_OUTPUT" | sed -n 7p | grep -q 'Request exceeds' \
+    && [ "$SOCKET_MODE" = "600" ] \
+    && [ ! -e served.json ] && [ ! -e served.bin ] \
+    && diff -q small_modified.txt served.txt > /dev/null; then
+    test_pass "Encode and decode through gdelta serve"
+else
+    test_fail "Encode and decode through gdelta serve" "Unexpected responses: $SERVE_OUTPUT"
+fi
+
 echo ""
 
 # ============================================================================
 # 3. Test compression formats
 # ============================================================================
 
-echo -e "${BLUE}[3/9] Testing compression formats...${NC}"
+echo -e "${BLUE}[3/10] Testing compression formats...${NC}"
 
 if gdelta encode medium.json medium_modified.json -o test3.delta -c zstd -q; then
     if gdelta decode medium.json test3.delta -o test3_output.json -q; then
@@ -184,13 +343,57 @@ else
     test_fail "Zstd auto-detection" "Auto-detection didn't work"
 fi
 
+if gdelta encode medium.json medium_modified.json -o test_dict.delta -c zstd --zstd-dict -q; then
+    if gdelta decode medium.json test_dict.delta -o test_dict_output.json -q; then
+        if diff -q medium_modified.json test_dict_output.json > /dev/null; then
+            test_pass "Zstd with base dictionary"
+        else
+            test_fail "Zstd with base dictionary" "Output doesn't match"
+        fi
+    else
+        test_fail "Zstd with base dictionary" "Decode failed"
+    fi
+else
+    test_fail "Zstd with base dictionary" "Encode failed"
+fi
+
+if gdelta encode medium.json medium_modified.json -o test_split.delta -c zstd --split-streams -q; then
+    if gdelta decode medium.json test_split.delta -o test_split_output.json -q; then
+        if diff -q medium_modified.json test_split_output.json > /dev/null; then
+            test_pass "Zstd with split streams"
+        else
+            test_fail "Zstd with split streams" "Output doesn't match"
+        fi
+    else
+        test_fail "Zstd with split streams" "Decode failed"
+    fi
+else
+    test_fail "Zstd with split streams" "Encode failed"
+fi
+
+if gdelta encode medium.json medium_modified.json -o test_fec.delta -c zstd --fec -q; then
+    # Damage a few bytes in the middle of the protected delta
+    printf '\xff\xff\xff\xff' | dd of=test_fec.delta bs=1 seek=200 conv=notrunc 2>/dev/null
+    if gdelta decode medium.json test_fec.delta -o test_fec_output.json -q; then
+        if diff -q medium_modified.json test_fec_output.json > /dev/null; then
+            test_pass "Damaged delta repaired with --fec"
+        else
+            test_fail "Damaged delta repaired with --fec" "Output doesn't match"
+        fi
+    else
+        test_fail "Damaged delta repaired with --fec" "Decode failed"
+    fi
+else
+    test_fail "Damaged delta repaired with --fec" "Encode failed"
+fi
+
 echo ""
 
 # ============================================================================
 # 4. Test verification
 # ============================================================================
 
-echo -e "${BLUE}[4/9] Testing verification...${NC}"
+echo -e "${BLUE}[4/10] Testing verification...${NC}"
 
 if gdelta encode small.txt small_modified.txt -o test7.delta --verify -q; then
     test_pass "Encode with verification"
@@ -198,13 +401,50 @@ else
     test_fail "Encode with verification" "Verification failed"
 fi
 
+if gdelta encode small.txt small_modified.txt -o test_checksum.delta --checksum -q \
+    && gdelta decode small.txt test_checksum.delta -o test_checksum_output.txt -q; then
+    if gdelta decode small.txt test_checksum.delta -o test_checksum_output.txt --verify-checksum-only 2>&1 | grep -q "Up to date"; then
+        test_pass "Skip decoding an up-to-date output"
+    else
+        test_fail "Skip decoding an up-to-date output" "Existing output was not recognized"
+    fi
+    echo "stale" > test_checksum_output.txt
+    if ! gdelta decode small.txt test_checksum.delta -o test_checksum_output.txt --verify-checksum-only -q 2>/dev/null; then
+        test_pass "Reject a stale output without -f"
+    else
+        test_fail "Reject a stale output without -f" "Should have failed but didn't"
+    fi
+else
+    test_fail "Skip decoding an up-to-date output" "Encode or decode with checksums failed"
+fi
+
+# Deltas named against their order, so only the checksums can chain them
+mkdir -p test_set
+cp small_modified.txt test_set_v2.txt
+echo "Hello, World! Modified twice" > test_set_v3.txt
+if gdelta encode small.txt test_set_v2.txt -o test_set/b.delta --checksum -q \
+    && gdelta encode test_set_v2.txt test_set_v3.txt -o test_set/a.delta --checksum -c zstd -q \
+    && gdelta apply-set test_set small.txt -o test_set_output.txt -q \
+    && cmp -s test_set_v3.txt test_set_output.txt; then
+    test_pass "Apply a set of deltas in chain order"
+else
+    test_fail "Apply a set of deltas in chain order" "Output does not match the last version"
+fi
+rm test_set/b.delta
+if ! gdelta apply-set test_set small.txt -o test_set_broken.txt -q 2>/dev/null \
+    && [ ! -e test_set_broken.txt ]; then
+    test_pass "Reject a broken delta chain"
+else
+    test_fail "Reject a broken delta chain" "Should have failed but didn't"
+fi
+
 echo ""
 
 # ============================================================================
 # 5. Test force overwrite
 # ============================================================================
 
-echo -e "${BLUE}[5/9] Testing force overwrite...${NC}"
+echo -e "${BLUE}[5/10] Testing force overwrite...${NC}"
 
 echo "dummy" > existing.delta
 if gdelta encode small.txt small_modified.txt -o existing.delta -q 2>&1 | grep -q "already exists"; then
@@ -225,7 +465,7 @@ echo ""
 # 6. Test error handling
 # ============================================================================
 
-echo -e "${BLUE}[6/9] Testing error handling...${NC}"
+echo -e "${BLUE}[6/10] Testing error handling...${NC}"
 
 if gdelta encode nonexistent.txt small.txt -o test10.delta -q 2>&1 | grep -q "not found"; then
     test_pass "Error on non-existent base file"
@@ -251,7 +491,7 @@ echo ""
 # 7. Test memory warnings
 # ============================================================================
 
-echo -e "${BLUE}[7/9] Testing memory warnings...${NC}"
+echo -e "${BLUE}[7/10] Testing memory warnings...${NC}"
 
 TOTAL_RAM=$(free -b | awk '/^Mem:/{print $2}')
 AVAILABLE_RAM=$(free -b | awk '/^Mem:/{print $7}')
@@ -318,7 +558,7 @@ echo ""
 # 8. Test compression ratios
 # ============================================================================
 
-echo -e "${BLUE}[8/9] Testing compression ratios...${NC}"
+echo -e "${BLUE}[8/10] Testing compression ratios...${NC}"
 
 gdelta encode large_base.txt large_new.txt -o large_none.delta -c none -q
 gdelta encode large_base.txt large_new.txt -o large_lz4.delta -c lz4 -q
@@ -358,7 +598,7 @@ echo ""
 # 9. Test output formatting
 # ============================================================================
 
-echo -e "${BLUE}[9/9] Testing output formatting...${NC}"
+echo -e "${BLUE}[9/10] Testing output formatting...${NC}"
 
 OUTPUT=$(gdelta encode small.txt small_modified.txt -o quiet_test.delta -q 2>&1)
 if [ -z "$OUTPUT" ]; then
@@ -381,6 +621,47 @@ else
     test_fail "Time formatting" "Should show formatted time"
 fi
 
+OUTPUT=$(gdelta --version --json 2>&1)
+if echo "$OUTPUT" | grep -q '"version": "' \
+    && echo "$OUTPUT" | grep -q '"cli"' \
+    && echo "$OUTPUT" | grep -q '"format_versions"'; then
+    test_pass "Build info as JSON"
+else
+    test_fail "Build info as JSON" "Unexpected output: $OUTPUT"
+fi
+
+echo ""
+
+# ============================================================================
+# 10. Test benchmark comparison
+# ============================================================================
+
+echo -e "${BLUE}[10/10] Testing benchmark comparison...${NC}"
+
+write_report() {
+    cat > "$1" << EOF
+{"metrics": [{"algorithm": "gdelta", "data_format": "json", "change_pattern": "minor_edit",
+  "cache_level": "memory", "new_size": 1000000, "compression_ratio": 0.1,
+  "encode_time_ns": $2, "decode_time_ns": 1000000}]}
+EOF
+}
+write_report old_report.json 2000000
+write_report same_report.json 2000000
+write_report slow_report.json 4000000
+
+OUTPUT=$(gdelta bench-compare old_report.json same_report.json 2>&1)
+if echo "$OUTPUT" | grep -q "regressed, 1 unchanged"; then
+    test_pass "Identical reports compare as unchanged"
+else
+    test_fail "Bench compare" "Identical reports should be unchanged"
+fi
+
+if ! gdelta bench-compare old_report.json slow_report.json --fail-on-regression > /dev/null 2>&1; then
+    test_pass "Regression fails with --fail-on-regression"
+else
+    test_fail "Bench compare regression" "Should have exited with an error"
+fi
+
 echo ""
 
 # ============================================================================
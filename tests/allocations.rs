@@ -0,0 +1,140 @@
+//! Allocation-count regression tests.
+//!
+//! A counting global allocator records how many allocations encode and
+//! decode make, so that work keeping them to a fixed number of buffers is
+//! not silently undone. The counter is per thread, which keeps the test
+//! threads from disturbing each other's counts.
+
+use gdelta::{decode, decode_into_slice, decode_to_writer, encode};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+fn record() {
+    // Ignore allocations during thread teardown
+    let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+}
+
+// SAFETY: every call is forwarded unchanged to the system allocator.
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        record();
+        // SAFETY: the caller upholds the `GlobalAlloc::alloc` contract.
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        record();
+        // SAFETY: the caller upholds the `GlobalAlloc::alloc_zeroed` contract.
+        unsafe { System.alloc_zeroed(layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        record();
+        // SAFETY: the caller upholds the `GlobalAlloc::realloc` contract.
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // SAFETY: the caller upholds the `GlobalAlloc::dealloc` contract.
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// Returns the result of `f` and the number of allocations and
+/// reallocations it made on this thread.
+fn count_allocations<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    let before = ALLOCATIONS.with(Cell::get);
+    let result = f();
+    (result, ALLOCATIONS.with(Cell::get) - before)
+}
+
+/// Base data and an edited copy of `size` bytes.
+#[allow(clippy::cast_possible_truncation)]
+fn edited_pair(size: usize) -> (Vec<u8>, Vec<u8>) {
+    let base: Vec<u8> = (0..size as u32)
+        .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+        .collect();
+    let mut new = base.clone();
+    for pos in (0..size).step_by(997) {
+        new[pos] ^= 0x5A;
+    }
+    (base, new)
+}
+
+/// Input sizes from a small chunk to several times the initial buffer size.
+const SIZES: [usize; 4] = [4 * 1024, 64 * 1024, 1024 * 1024, 4 * 1024 * 1024];
+
+/// Allocations allowed for one call that only sets up fixed buffers.
+const FIXED_ALLOCATIONS: usize = 8;
+
+/// Initial capacity of the decoder's output buffer.
+const INITIAL_OUTPUT_CAPACITY: usize = 128 * 1024;
+
+#[test]
+fn test_encode_allocations_are_bounded() {
+    for size in SIZES {
+        let (base, new) = edited_pair(size);
+        let (_, allocations) = count_allocations(|| encode(&new, &base).unwrap());
+        assert!(
+            allocations <= FIXED_ALLOCATIONS,
+            "encode of {size} bytes made {allocations} allocations"
+        );
+    }
+}
+
+#[test]
+fn test_decode_into_caller_buffers_does_not_scale() {
+    let mut counts = Vec::new();
+    for size in SIZES {
+        let (base, new) = edited_pair(size);
+        let delta = encode(&new, &base).unwrap();
+
+        let mut output = vec![0u8; new.len()];
+        let (_, into_slice) =
+            count_allocations(|| decode_into_slice(&delta, &base, &mut output).unwrap());
+        assert_eq!(output, new);
+
+        let mut writer = Vec::with_capacity(new.len());
+        let (_, to_writer) =
+            count_allocations(|| decode_to_writer(&delta, &base, &mut writer).unwrap());
+        assert_eq!(writer, new);
+
+        assert!(into_slice <= FIXED_ALLOCATIONS && to_writer <= FIXED_ALLOCATIONS);
+        counts.push((into_slice, to_writer));
+    }
+    // Writing into caller-provided memory must not allocate per byte or per
+    // instruction, so the count is the same for every input size
+    assert!(
+        counts.windows(2).all(|pair| pair[0] == pair[1]),
+        "allocations changed with input size: {counts:?}"
+    );
+}
+
+#[test]
+fn test_decode_output_grows_geometrically() {
+    for size in SIZES {
+        let (base, new) = edited_pair(size);
+        let delta = encode(&new, &base).unwrap();
+        let (output, allocations) = count_allocations(|| decode(&delta, &base).unwrap());
+        assert_eq!(output, new);
+
+        // Doubling from the initial capacity takes one reallocation per step
+        let growth = size
+            .div_ceil(INITIAL_OUTPUT_CAPACITY)
+            .next_power_of_two()
+            .trailing_zeros() as usize;
+        assert!(
+            allocations <= FIXED_ALLOCATIONS + growth,
+            "decode of {size} bytes made {allocations} allocations"
+        );
+    }
+}
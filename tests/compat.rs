@@ -0,0 +1,111 @@
+//! Backward compatibility tests against stored deltas.
+//!
+//! `tests/fixtures/compat` holds deltas encoded from `base.txt` to
+//! `target.txt`, named `v<format version>-<variant>.gdelta`. They must never be
+//! regenerated: when the format changes, add fixtures for the new version next
+//! to the old ones. Every version the decoder claims to support needs at
+//! least one. The 0.2.1 release, the last one without headers, is a
+//! dev-dependency so its decoder can be checked to reject the newer ones.
+
+use gdelta::{
+    EncodeOptions, FORMAT_VERSION, MIN_FORMAT_VERSION, decode, decode_into_slice, decode_to_writer,
+    encode_with_options, format_version,
+};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+fn fixture_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/compat")
+}
+
+/// Returns every stored delta with the version taken from its file name.
+fn fixtures() -> Vec<(u8, PathBuf)> {
+    let mut fixtures: Vec<_> = fs::read_dir(fixture_dir())
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "gdelta"))
+        .map(|path| {
+            let name = path.file_stem().unwrap().to_str().unwrap();
+            let version = name
+                .strip_prefix('v')
+                .and_then(|rest| rest.split('-').next())
+                .and_then(|version| version.parse().ok())
+                .unwrap_or_else(|| panic!("fixture {name} is not named v<version>-<variant>"));
+            (version, path)
+        })
+        .collect();
+    fixtures.sort();
+    fixtures
+}
+
+#[test]
+fn test_every_supported_version_has_fixtures() {
+    let fixtures = fixtures();
+    for version in MIN_FORMAT_VERSION..=FORMAT_VERSION {
+        assert!(
+            fixtures.iter().any(|(v, _)| *v == version),
+            "no compatibility fixture for format version {version}"
+        );
+    }
+}
+
+#[test]
+fn test_decodes_stored_fixtures() {
+    let base = fs::read(fixture_dir().join("base.txt")).unwrap();
+    let target = fs::read(fixture_dir().join("target.txt")).unwrap();
+
+    for (version, path) in fixtures() {
+        let delta = fs::read(&path).unwrap();
+        let name = path.display();
+
+        assert_eq!(format_version(&delta).unwrap(), version, "{name}");
+        assert_eq!(decode(&delta, &base).unwrap(), target, "{name}");
+
+        let mut written = Vec::new();
+        decode_to_writer(&delta, &base, &mut written).unwrap();
+        assert_eq!(written, target, "{name}");
+
+        let mut output = vec![0u8; target.len()];
+        assert_eq!(
+            decode_into_slice(&delta, &base, &mut output).unwrap(),
+            target.len(),
+            "{name}"
+        );
+        assert_eq!(output, target, "{name}");
+    }
+}
+
+#[test]
+fn test_rejects_newer_versions() {
+    let mut delta = fs::read(fixture_dir().join("v1-checksum.gdelta")).unwrap();
+    delta[8] = FORMAT_VERSION + 1;
+    assert!(format_version(&delta).is_err());
+    assert!(decode(&delta, b"").is_err());
+}
+
+#[test]
+fn test_released_decoder_rejects_headers() {
+    // 0.2.1 is the last release without headers. It must fail on them
+    // instead of reading the magic as a short instruction stream.
+    let base = fs::read(fixture_dir().join("base.txt")).unwrap();
+    let target = fs::read(fixture_dir().join("target.txt")).unwrap();
+
+    for (version, path) in fixtures() {
+        let delta = fs::read(&path).unwrap();
+        let name = path.display();
+        let released = gdelta_0_2_1::decode(&delta, &base);
+        if version == 0 {
+            assert_eq!(released.unwrap(), target, "{name}");
+        } else {
+            assert!(released.is_err(), "{name}");
+        }
+    }
+
+    // Overlapping target copies expand a few bytes into a long run
+    let mut runs = base.clone();
+    runs.extend(std::iter::repeat_n(b'=', 4096));
+    let options = EncodeOptions::new().target_matches(true);
+    let delta = encode_with_options(&runs, &base, &options).unwrap();
+    assert!(delta.len() < 4096);
+    assert!(gdelta_0_2_1::decode(&delta, &base).is_err());
+}
@@ -1,6 +1,12 @@
 //! Integration tests for gdelta.
 
-use gdelta::{decode, encode};
+use gdelta::matcher::GearMatcher;
+use gdelta::{
+    ChecksumWriter, EncodeOptions, GDeltaError, Preset, base_checksum, decode, decode_into_slice,
+    decode_lossy, decode_scattered, decode_to_writer, decode_to_writer_checksummed, encode,
+    encode_with_matcher, encode_with_options, is_applied, target_checksum,
+};
+use std::io::Write;
 
 #[test]
 fn test_basic_encode_decode() {
@@ -179,3 +185,530 @@ fn test_binary_data() {
 
     assert_eq!(recovered, new);
 }
+
+#[test]
+fn test_default_matcher_matches_encode() {
+    let base: Vec<u8> = (0..=255).cycle().take(10_000).collect();
+    let mut new = base.clone();
+    new.splice(4000..4000, b"an insertion in the middle".iter().copied());
+
+    let delta = encode_with_matcher(&new, &base, &mut GearMatcher::new()).unwrap();
+
+    assert_eq!(delta, encode(&new, &base).unwrap());
+    assert_eq!(decode(&delta, &base).unwrap(), new);
+}
+
+#[test]
+fn test_checksum_detects_corruption() {
+    let base = b"The quick brown fox jumps over the lazy dog";
+    let new = b"The quick brown cat jumps over the lazy dog";
+
+    let options = EncodeOptions::new().checksum(true);
+    let delta = encode_with_options(new, base, &options).unwrap();
+    assert_eq!(decode(&delta, base).unwrap(), new);
+
+    // Every byte after the magic marker is covered by the checksum or validation
+    for i in 4..delta.len() {
+        let mut corrupted = delta.clone();
+        corrupted[i] ^= 0x01;
+        assert!(decode(&corrupted, base).is_err());
+    }
+
+    let truncated = &delta[..delta.len() - 1];
+    assert!(matches!(
+        decode(truncated, base),
+        Err(GDeltaError::ChecksumMismatch { .. })
+    ));
+}
+
+#[test]
+fn test_target_checksum_detects_wrong_output() {
+    let base = b"The quick brown fox jumps over the lazy dog".repeat(4);
+    let mut new = base.clone();
+    new[50..53].copy_from_slice(b"cat");
+
+    let options = EncodeOptions::new().target_checksum(true);
+    let delta = encode_with_options(&new, &base, &options).unwrap();
+    let target = decode_to_writer_checksummed(&delta, &base, std::io::sink()).unwrap();
+    assert_eq!(target_checksum(&delta).unwrap(), Some(target.crc32));
+    assert_eq!(
+        target_checksum(&encode(&new, &base).unwrap()).unwrap(),
+        None
+    );
+
+    // A different base of the same length yields a different target
+    let mut other = base.clone();
+    other[0] ^= 0x20;
+    let mismatch =
+        |error: Option<GDeltaError>| matches!(error, Some(GDeltaError::ChecksumMismatch { .. }));
+    assert_eq!(decode(&delta, &base).unwrap(), new);
+    assert!(mismatch(decode(&delta, &other).err()));
+    assert!(mismatch(
+        decode_into_slice(&delta, &other, &mut [0; 1024]).err()
+    ));
+    assert!(mismatch(
+        decode_to_writer(&delta, &other, std::io::sink()).err()
+    ));
+
+    let identity = encode_with_options(&base, &base, &options).unwrap();
+    assert_eq!(decode(&identity, &base).unwrap(), base);
+    assert!(mismatch(decode(&identity, &other).err()));
+}
+
+#[test]
+fn test_base_checksum_rejects_wrong_base() {
+    let base = b"The quick brown fox jumps over the lazy dog".repeat(4);
+    let mut new = base.clone();
+    new[50..53].copy_from_slice(b"cat");
+
+    let options = EncodeOptions::new().base_checksum(true);
+    let delta = encode_with_options(&new, &base, &options).unwrap();
+    let mut writer = ChecksumWriter::new(std::io::sink());
+    writer.write_all(&base).unwrap();
+    assert_eq!(base_checksum(&delta).unwrap(), Some(writer.checksum()));
+    assert_eq!(target_checksum(&delta).unwrap(), None);
+    assert_eq!(decode(&delta, &base).unwrap(), new);
+
+    // A wrong base fails even where it would decode to the same length
+    let mut wrong = base.clone();
+    wrong[0] ^= 0x20;
+    let mismatch =
+        |error: Option<GDeltaError>| matches!(error, Some(GDeltaError::ChecksumMismatch { .. }));
+    assert!(mismatch(decode(&delta, &wrong).err()));
+    assert!(mismatch(
+        decode_into_slice(&delta, &wrong, &mut [0; 1024]).err()
+    ));
+    let mut output = Vec::new();
+    assert!(mismatch(
+        decode_to_writer(&delta, &wrong, &mut output).err()
+    ));
+    assert!(output.is_empty());
+}
+
+#[test]
+fn test_is_applied() {
+    let base = b"The quick brown fox jumps over the lazy dog".repeat(4);
+    let mut new = base.clone();
+    new[50..53].copy_from_slice(b"cat");
+
+    for options in [
+        EncodeOptions::new().target_checksum(true),
+        EncodeOptions::new()
+            .target_checksum(true)
+            .store_target_size(true)
+            .checksum(true),
+        EncodeOptions::new().target_checksum(true).rsyncable(true),
+    ] {
+        let delta = encode_with_options(&new, &base, &options).unwrap();
+        assert!(is_applied(&delta, &new), "{options:?}");
+        assert!(!is_applied(&delta, &base));
+        assert!(!is_applied(&delta, &new[1..]));
+        assert!(!is_applied(&delta[..3], &new));
+
+        let identity = encode_with_options(&base, &base, &options).unwrap();
+        assert!(is_applied(&identity, &base));
+        assert!(!is_applied(&identity, &new));
+    }
+
+    // Without a target checksum nothing is known about the candidate
+    let options = EncodeOptions::new().store_target_size(true);
+    let delta = encode_with_options(&new, &base, &options).unwrap();
+    assert!(!is_applied(&delta, &new));
+}
+
+#[test]
+fn test_target_size_mismatch() {
+    let base = b"The quick brown fox jumps over the lazy dog";
+    let new = b"The quick brown cat jumps over the lazy dog";
+
+    let options = EncodeOptions::new().store_target_size(true);
+    let delta = encode_with_options(new, base, &options).unwrap();
+    assert_eq!(decode(&delta, base).unwrap(), new);
+
+    // Header is magic, version, flags, then the target size varint
+    let mut tampered = delta.clone();
+    tampered[10] += 1;
+    assert_eq!(
+        decode(&tampered, base),
+        Err(GDeltaError::SizeMismatch {
+            expected: new.len() + 1,
+            actual: new.len(),
+        })
+    );
+}
+
+#[test]
+#[allow(clippy::cast_possible_truncation)]
+fn test_rsyncable_deltas_share_segments() {
+    let mut state = 7u32;
+    let base: Vec<u8> = (0..1_000_000)
+        .map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (state >> 16) as u8
+        })
+        .collect();
+    let mut new = base.clone();
+    new[100_000..100_016].copy_from_slice(b"first edit here!");
+
+    let options = EncodeOptions::new().rsyncable(true);
+    let first = encode_with_options(&new, &base, &options).unwrap();
+    assert_eq!(decode(&first, &base).unwrap(), new);
+
+    // A second edit early in the file only changes the delta locally
+    let mut newer = new.clone();
+    newer.splice(5_000..5_000, b"inserted".iter().copied());
+    let second = encode_with_options(&newer, &base, &options).unwrap();
+    assert_eq!(decode(&second, &base).unwrap(), newer);
+
+    let common_suffix = first
+        .iter()
+        .rev()
+        .zip(second.iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    assert!(common_suffix > first.len() / 2);
+}
+
+#[test]
+fn test_deadline_exceeded() {
+    let base: Vec<u8> = (0..=255).cycle().take(10_000).collect();
+    let mut new = base.clone();
+    new.splice(4000..4000, b"an insertion in the middle".iter().copied());
+    new[0] ^= 0xFF;
+
+    let expired = EncodeOptions::new().deadline(std::time::Instant::now());
+    assert_eq!(
+        encode_with_options(&new, &base, &expired),
+        Err(GDeltaError::DeadlineExceeded)
+    );
+
+    let generous = EncodeOptions::new().timeout(std::time::Duration::from_secs(3600));
+    let delta = encode_with_options(&new, &base, &generous).unwrap();
+    assert_eq!(decode(&delta, &base).unwrap(), new);
+
+    // Degrading keeps the full search when there is time and falls back to
+    // literal data when there is none
+    let degraded = encode_with_options(&new, &base, &generous.degrade_on_deadline(true)).unwrap();
+    assert_eq!(degraded, delta);
+    let literal = encode_with_options(&new, &base, &expired.degrade_on_deadline(true)).unwrap();
+    assert_eq!(decode(&literal, &base).unwrap(), new);
+    assert!(literal.len() > delta.len());
+}
+
+#[test]
+#[allow(clippy::cast_possible_truncation)]
+fn test_memory_budget_degrades_gracefully() {
+    let mut state = 11u32;
+    let base: Vec<u8> = (0..200_000)
+        .map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (state >> 16) as u8
+        })
+        .collect();
+    let mut new = base.clone();
+    new.splice(1000..1000, b"inserted".iter().copied());
+    new[150_000] ^= 0xFF;
+
+    let options = EncodeOptions::new().memory_budget(4096);
+    let delta = encode_with_options(&new, &base, &options).unwrap();
+    assert_eq!(decode(&delta, &base).unwrap(), new);
+    assert!(delta.len() < new.len() / 10);
+}
+
+#[test]
+#[allow(clippy::cast_possible_truncation)]
+fn test_presets_roundtrip() {
+    let mut state = 3u32;
+    let base: Vec<u8> = (0..50_000)
+        .map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (state >> 16) as u8
+        })
+        .collect();
+    let mut new = base.clone();
+    new.splice(20_000..20_000, base[..3000].iter().map(|b| b ^ 0x5A));
+    new.truncate(45_000);
+
+    for preset in [
+        Preset::Default,
+        Preset::Text,
+        Preset::Structured,
+        Preset::Binary,
+        Preset::Compressed,
+    ] {
+        let options = EncodeOptions::new().preset(preset);
+        let delta = encode_with_options(&new, &base, &options).unwrap();
+        assert_eq!(decode(&delta, &base).unwrap(), new, "{preset:?}");
+        assert!(delta.len() < 5000, "{preset:?}");
+    }
+
+    let default = encode_with_options(&new, &base, &EncodeOptions::new()).unwrap();
+    assert_eq!(default, encode(&new, &base).unwrap());
+}
+
+#[test]
+fn test_max_ratio_aborts_unhelpful_delta() {
+    let base = b"The quick brown fox jumps over the lazy dog".repeat(100);
+    let unrelated: Vec<u8> = (0..=255u8).cycle().take(base.len()).collect();
+
+    let options = EncodeOptions::new().max_ratio(0.9);
+    assert_eq!(
+        encode_with_options(&unrelated, &base, &options),
+        Err(GDeltaError::NotBeneficial)
+    );
+
+    let mut similar = base.clone();
+    similar[2000] = b'!';
+    let delta = encode_with_options(&similar, &base, &options).unwrap();
+    assert!(delta.len() * 10 <= similar.len() * 9);
+    assert_eq!(decode(&delta, &base).unwrap(), similar);
+}
+
+#[test]
+#[allow(clippy::cast_possible_truncation)]
+fn test_target_matches_repeated_insertions() {
+    let mut state = 5u32;
+    let mut random = |len: usize| -> Vec<u8> {
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect()
+    };
+    let base = random(20_000);
+    let block = random(2_000);
+    let gap = random(1_000);
+    let mut new = base.clone();
+    for at in [15_000, 10_000, 5_000] {
+        new.splice(at..at, block.iter().copied());
+    }
+
+    let plain = encode(&new, &base).unwrap();
+    let options = EncodeOptions::new().target_matches(true);
+    let delta = encode_with_options(&new, &base, &options).unwrap();
+    assert!(delta.len() + 3_000 < plain.len());
+
+    assert_eq!(decode(&delta, &base).unwrap(), new);
+    assert_eq!(decode_lossy(&delta, &base).output, new);
+    assert_eq!(
+        decode_scattered(&delta, &[&base[..7_000], &base[7_000..]]).unwrap(),
+        new
+    );
+    let mut written = Vec::new();
+    decode_to_writer(&delta, &base, &mut written).unwrap();
+    assert_eq!(written, new);
+    let mut buffer = vec![0; new.len()];
+    assert_eq!(
+        decode_into_slice(&delta, &base, &mut buffer).unwrap(),
+        new.len()
+    );
+    assert_eq!(buffer, new);
+
+    // Without a base, only the repeats can be copied
+    let repeated = [&block[..], &gap[..], &block[..]].concat();
+    let delta = encode_with_options(&repeated, b"", &options).unwrap();
+    assert!(delta.len() < 3_100);
+    assert_eq!(decode(&delta, b"").unwrap(), repeated);
+}
+
+#[test]
+fn test_word_size() {
+    let base: Vec<u8> = (0..=255).cycle().take(20_000).collect();
+    let mut new = base.clone();
+    new.splice(7_000..7_000, b"a short insertion".iter().copied());
+
+    for size in [8, 16, 32, 48] {
+        let options = EncodeOptions::new().word_size(size);
+        let delta = encode_with_options(&new, &base, &options).unwrap();
+        assert_eq!(decode(&delta, &base).unwrap(), new);
+        assert!(delta.len() < 100);
+    }
+
+    for size in [4, gdelta::gear::MAX_WORD_SIZE + 1, usize::MAX] {
+        let options = EncodeOptions::new().word_size(size);
+        assert!(matches!(
+            encode_with_options(&new, &base, &options),
+            Err(GDeltaError::InvalidOptions(_))
+        ));
+    }
+}
+
+#[test]
+fn test_text_mode_matches_short_lines() {
+    let base: String = (0..2000).map(|i| format!("w{i:04}\n")).collect();
+    let new: String = (0..2000).rev().map(|i| format!("w{i:04}\n")).collect();
+
+    let plain = encode(new.as_bytes(), base.as_bytes()).unwrap();
+    let options = EncodeOptions::new().text_mode(true);
+    let delta = encode_with_options(new.as_bytes(), base.as_bytes(), &options).unwrap();
+
+    assert_eq!(decode(&delta, base.as_bytes()).unwrap(), new.as_bytes());
+    assert!(delta.len() < plain.len());
+}
+
+#[test]
+fn test_record_delimiter_inserted_row() {
+    let base: String = (0..20_000)
+        .map(|i| format!("{i},user{i},{}\n", i * 37 % 1000))
+        .collect();
+    let mut new = base.clone();
+    let row = base.match_indices('\n').nth(5_000).unwrap().0 + 1;
+    new.insert_str(row, "inserted,row,0\n");
+
+    let options = EncodeOptions::new().record_delimiter(b'\n').rsyncable(true);
+    let delta = encode_with_options(new.as_bytes(), base.as_bytes(), &options).unwrap();
+    assert_eq!(decode(&delta, base.as_bytes()).unwrap(), new.as_bytes());
+    assert!(delta.len() < 1_000);
+
+    let mut streamed = Vec::new();
+    gdelta::encode_streams(
+        new.as_bytes(),
+        std::io::Cursor::new(base.as_bytes()),
+        &mut streamed,
+        &options,
+    )
+    .unwrap();
+    assert_eq!(decode(&streamed, base.as_bytes()).unwrap(), new.as_bytes());
+}
+
+#[test]
+fn test_identity_delta() {
+    let base = b"unchanged object contents ".repeat(100);
+
+    // Plain encode stays in the raw format older releases read
+    let raw = encode(&base, &base).unwrap();
+    assert!(!gdelta::is_identity(&raw));
+    assert_eq!(gdelta::format_version(&raw).unwrap(), 0);
+    assert_eq!(decode(&raw, &base).unwrap(), base);
+
+    let headered = EncodeOptions::new().rsyncable(true);
+    let delta = encode_with_options(&base, &base, &headered).unwrap();
+    assert!(gdelta::is_identity(&delta));
+    assert_eq!(delta.len(), 10);
+    assert_eq!(decode(&delta, &base).unwrap(), base);
+
+    let mut written = Vec::new();
+    decode_to_writer(&delta, &base, &mut written).unwrap();
+    assert_eq!(written, base);
+    let mut output = vec![0u8; base.len()];
+    assert_eq!(
+        decode_into_slice(&delta, &base, &mut output).unwrap(),
+        base.len()
+    );
+    assert_eq!(output, base);
+    let (first, second) = base.split_at(1000);
+    assert_eq!(decode_scattered(&delta, &[first, second]).unwrap(), base);
+    assert_eq!(decode_lossy(&delta, &base).output, base);
+
+    let options = EncodeOptions::new()
+        .checksum(true)
+        .store_target_size(true)
+        .rsyncable(true)
+        .target_matches(true);
+    let delta = encode_with_options(&base, &base, &options).unwrap();
+    assert!(gdelta::is_identity(&delta));
+    assert_eq!(decode(&delta, &base).unwrap(), base);
+    // The stored size still guards against applying it to the wrong base
+    assert!(matches!(
+        decode(&delta, &base[1..]),
+        Err(GDeltaError::SizeMismatch { .. })
+    ));
+
+    let empty = encode_with_options(b"", b"", &headered).unwrap();
+    assert!(gdelta::is_identity(&empty));
+    assert!(decode(&empty, b"").unwrap().is_empty());
+
+    let mut changed = base.clone();
+    changed[10] ^= 1;
+    assert!(!gdelta::is_identity(&encode(&changed, &base).unwrap()));
+    assert!(!gdelta::is_identity(&encode(&base[..100], &base).unwrap()));
+}
+
+#[test]
+fn test_encode_if_similar() {
+    let base: String = (0..2000).map(|i| format!("record {i}\n")).collect();
+    let mut new = base.replace("record 1000\n", "record one thousand\n");
+
+    let delta = gdelta::encode_if_similar(new.as_bytes(), base.as_bytes(), 0.8).unwrap();
+    assert_eq!(decode(&delta, base.as_bytes()).unwrap(), new.as_bytes());
+
+    new = (0..2000).map(|i| format!("{i:x} unrelated\n")).collect();
+    assert_eq!(
+        gdelta::encode_if_similar(new.as_bytes(), base.as_bytes(), 0.8),
+        Err(GDeltaError::NotSimilar)
+    );
+}
+
+#[test]
+fn test_format_version_cap() {
+    let base: Vec<u8> = (0..5000u32)
+        .flat_map(|i| format!("row {i}\n").into_bytes())
+        .collect();
+    let new = [base.as_slice(), b"row 5000\n"].concat();
+
+    let raw = EncodeOptions::new().format_version(0);
+    for new in [&new, &base] {
+        let delta = gdelta::encode_with_options(new, &base, &raw).unwrap();
+        assert_eq!(gdelta::format_version(&delta).unwrap(), 0);
+        assert_eq!(decode(&delta, &base).unwrap(), *new);
+    }
+    let current = EncodeOptions::new()
+        .format_version(gdelta::FORMAT_VERSION)
+        .checksum(true);
+    let delta = gdelta::encode_with_options(&new, &base, &current).unwrap();
+    assert_eq!(
+        gdelta::format_version(&delta).unwrap(),
+        gdelta::FORMAT_VERSION
+    );
+
+    for options in [
+        raw.clone().checksum(true),
+        raw.clone().target_matches(true),
+        raw.clone().metadata(1, b"name"),
+        EncodeOptions::new().format_version(gdelta::FORMAT_VERSION + 1),
+    ] {
+        assert!(matches!(
+            gdelta::encode_with_options(&new, &base, &options),
+            Err(GDeltaError::InvalidOptions(_))
+        ));
+    }
+    assert!(matches!(
+        gdelta::encode_streams(
+            std::io::Cursor::new(&new),
+            std::io::Cursor::new(&base),
+            Vec::new(),
+            &raw
+        ),
+        Err(GDeltaError::InvalidOptions(_))
+    ));
+}
+
+#[test]
+fn test_choose_base() {
+    let v1: String = (0..5000).map(|i| format!("record {i}\n")).collect();
+    let v2 = v1.replace("record 2000\n", "record two thousand\n");
+    let v3 = v2.replace("record 4000\n", "record four thousand\n");
+    let new = v3.replace("record 100\n", "record one hundred\n");
+    let unrelated: String = (0..5000).map(|i| format!("{i:x} unrelated\n")).collect();
+
+    let candidates = [
+        unrelated.as_bytes(),
+        v1.as_bytes(),
+        v3.as_bytes(),
+        v2.as_bytes(),
+    ];
+    let (index, ratio) = gdelta::choose_base(new.as_bytes(), &candidates).unwrap();
+    assert_eq!(index, 2);
+    let delta = encode(new.as_bytes(), candidates[index]).unwrap();
+    assert!((ratio - delta.len() as f64 / new.len() as f64).abs() < 1e-9);
+
+    let (index, ratio) = gdelta::choose_base(new.as_bytes(), &candidates[..1]).unwrap();
+    assert_eq!(index, 0);
+    assert!(ratio > 0.5, "{ratio}");
+
+    assert!(matches!(
+        gdelta::choose_base(new.as_bytes(), &[]),
+        Err(GDeltaError::InvalidOptions(_))
+    ));
+}
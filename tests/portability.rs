@@ -0,0 +1,150 @@
+//! Byte-identical output across targets.
+//!
+//! Content-addressed stores key deltas by their hash, so the same inputs
+//! must encode to the same bytes on every platform. These tests pin the
+//! encoder output, the varint and trailer layouts and the GEAR fingerprints
+//! to values recorded on x86-64. Inputs come from a fixed xorshift generator
+//! instead of `rand` so they cannot change with a dependency update. The
+//! same values must come out with and without the `rayon` feature.
+//!
+//! Passing on the development machine only shows that nothing changed
+//! there. Run `./test_cross.sh` to run this target under QEMU on a
+//! big-endian (`s390x`) and a 32-bit (`armv7`) target as well.
+
+use gdelta::gear::{compute_fingerprint, fingerprints};
+use gdelta::{EncodeOptions, decode, encode_with_options};
+
+/// Deterministic pseudo-random bytes.
+fn xorshift_bytes(seed: u64, len: usize) -> Vec<u8> {
+    let mut state = seed;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state.to_be_bytes()[0]
+        })
+        .collect()
+}
+
+/// FNV-1a, written out so the digest does not depend on the platform.
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// A base of repeated records and a version with edits, moves and appends.
+fn sample_pair() -> (Vec<u8>, Vec<u8>) {
+    let noise = xorshift_bytes(0x9E37_79B9_7F4A_7C15, 4096);
+    let mut base = Vec::new();
+    for i in 0..2000u32 {
+        base.extend_from_slice(format!("record {i:05} value {}\n", i * 37 % 1009).as_bytes());
+        if i % 250 == 0 {
+            base.extend_from_slice(&noise[..(i as usize % 512) + 64]);
+        }
+    }
+
+    let mut new = base.clone();
+    new[1_000..1_016].copy_from_slice(b"edited in place!");
+    new.drain(20_000..21_500);
+    let moved = base[5_000..7_000].to_vec();
+    new.splice(30_000..30_000, moved);
+    new.extend_from_slice(&noise[1_000..3_000]);
+    (base, new)
+}
+
+#[test]
+fn test_encoded_bytes_match_recorded_digests() {
+    let (base, new) = sample_pair();
+    let cases = [
+        (
+            "default",
+            EncodeOptions::new(),
+            0x81f,
+            0x7b18_9d55_9671_4002,
+        ),
+        (
+            "checksum",
+            EncodeOptions::new().checksum(true).store_target_size(true),
+            0x830,
+            0x76ad_d883_c2f3_68dc,
+        ),
+        (
+            "rsyncable",
+            EncodeOptions::new().rsyncable(true),
+            0x82b,
+            0x6928_3cda_2425_327c,
+        ),
+        (
+            "target-matches",
+            EncodeOptions::new().target_matches(true),
+            0x82c,
+            0x3eb7_4efc_97e2_9a36,
+        ),
+        (
+            "parallel",
+            EncodeOptions::new().parallel(true).target_matches(true),
+            0x829,
+            0x9185_2e6c_b494_53a9,
+        ),
+    ];
+
+    for (name, options, len, digest) in cases {
+        let delta = encode_with_options(&new, &base, &options).unwrap();
+        assert_eq!(
+            (delta.len(), fnv1a(&delta)),
+            (len, digest),
+            "{name} delta differs from the recorded output"
+        );
+    }
+    assert_eq!(
+        decode(
+            &encode_with_options(&new, &base, &EncodeOptions::new()).unwrap(),
+            &base
+        )
+        .unwrap(),
+        new
+    );
+}
+
+#[test]
+fn test_wire_layout() {
+    let base = b"0123456789".repeat(30);
+    let mut new = base.clone();
+    new.extend_from_slice(b"tail");
+    let options = EncodeOptions::new().checksum(true).store_target_size(true);
+
+    #[rustfmt::skip]
+    let expected = [
+        0xc7, 0xc4, 0xff, 0xff, // magic
+        0xf7, 0xff, 0xff, 0x00,
+        0x01, 0x03,             // version, checksum and target size flags
+        0xb0, 0x02,             // target size 304 as a varint
+        0x04,                   // instruction length
+        0xec, 0x04, 0x00,       // copy 44 | 4 << 6 = 300 bytes from offset 0
+        0x04,                   // literal of 4 bytes
+        b't', b'a', b'i', b'l', // literal data
+        0x1d, 0x25, 0xb8, 0x05, // CRC-32, little-endian
+    ];
+    assert_eq!(
+        encode_with_options(&new, &base, &options).unwrap(),
+        expected
+    );
+}
+
+#[test]
+fn test_fingerprints_match_recorded_values() {
+    let data = xorshift_bytes(42, 64);
+    let rolled: Vec<u64> = fingerprints(&data).take(4).map(|(_, fp)| fp).collect();
+    assert_eq!(
+        rolled,
+        [
+            0x0378_3ea4_e78b_0613,
+            0x09bb_1068_9d30_3f91,
+            0x62f8_4880_db25_8c58,
+            0xdcf5_5079_879e_4c20,
+        ]
+    );
+    assert_eq!(compute_fingerprint(&data, 0), rolled[0]);
+}
@@ -0,0 +1,200 @@
+//! Decoding untrusted deltas must never panic.
+//!
+//! Every decode entry point is run over truncated and mutated versions of
+//! valid deltas and over the stored corpus in `tests/fixtures/robustness`.
+//! Each input may fail with an error, but a panic fails the test with the
+//! name of the input that caused it.
+//!
+//! The corpus holds malformed deltas that once reached a panic or exercise
+//! a specific check. When fuzzing finds a new crash, add the minimized input
+//! there as `<what it exercises>.gdelta` along with the fix. Corpus files
+//! must be rejected with an error against both an empty base and
+//! `tests/fixtures/compat/base.txt`. With the `unstable-format` feature, the
+//! generated `gdelta::format::seed_corpus` is checked as well.
+//!
+//! The crate contains no unsafe code, but this target is also kept small
+//! enough to run under Miri with `cargo miri test --test robustness`, which
+//! samples fewer mutations.
+
+use gdelta::{
+    BaseSource, DecodeLimits, EncodeOptions, base_checksum, decode, decode_from_source,
+    decode_into_slice, decode_lossy, decode_scattered, decode_to_writer, decode_untrusted,
+    encode_with_options, format_version, is_identity, target_checksum, validate,
+};
+use std::fs;
+use std::panic::{AssertUnwindSafe, catch_unwind};
+use std::path::Path;
+
+/// Every mutation index is tested outside Miri; under Miri only every n-th.
+const MIRI_STRIDE: usize = if cfg!(miri) { 97 } else { 1 };
+
+/// Runs every decode entry point on `delta`, returning true if all of them
+/// rejected it.
+fn decode_all(delta: &[u8], base: &[u8]) -> bool {
+    let mut rejected = true;
+    rejected &= decode(delta, base).is_err();
+    // Validation alone must predict whether decoding succeeds, except that
+    // target and base checksums can only be checked against the data
+    if target_checksum(delta).is_ok_and(|checksum| checksum.is_some())
+        || base_checksum(delta).is_ok_and(|checksum| checksum.is_some())
+    {
+        assert!(validate(delta, base.len()).is_ok() || rejected);
+    } else {
+        assert_eq!(validate(delta, base.len()).is_err(), rejected);
+    }
+    let limits = DecodeLimits::new().require_checksum(false);
+    rejected &= decode_untrusted(delta, base, &limits).is_err();
+    rejected &= decode_to_writer(delta, base, &mut Vec::new()).is_err();
+
+    let mut output = vec![0u8; 4096];
+    rejected &= decode_into_slice(delta, base, &mut output).is_err();
+
+    let (front, back) = base.split_at(base.len() / 2);
+    rejected &= decode_scattered(delta, &[front, back]).is_err();
+
+    let mut source = base;
+    assert_eq!(BaseSource::len(&source), base.len() as u64);
+    rejected &= decode_from_source(delta, &mut source, &mut Vec::new()).is_err();
+
+    let lossy = decode_lossy(delta, base);
+    rejected &= !lossy.is_complete();
+
+    let _ = format_version(delta);
+    let _ = is_identity(delta);
+    rejected
+}
+
+/// Runs [`decode_all`], turning a panic into a test failure naming `input`.
+fn check(input: &str, delta: &[u8], base: &[u8]) -> bool {
+    catch_unwind(AssertUnwindSafe(|| decode_all(delta, base)))
+        .unwrap_or_else(|_| panic!("decoding {input} panicked"))
+}
+
+/// Valid deltas covering every framing variant, with their base.
+fn seed_deltas() -> (Vec<u8>, Vec<(&'static str, Vec<u8>)>) {
+    let base: Vec<u8> = b"The quick brown fox jumps over the lazy dog. "
+        .iter()
+        .cycle()
+        .take(1500)
+        .copied()
+        .collect();
+    let mut new = base.clone();
+    new[200..210].copy_from_slice(b"0123456789");
+    new.extend_from_slice(b"a new tail a new tail a new tail");
+
+    let variants = [
+        ("raw", EncodeOptions::new()),
+        ("checksum", EncodeOptions::new().checksum(true)),
+        ("target-size", EncodeOptions::new().store_target_size(true)),
+        (
+            "target-checksum",
+            EncodeOptions::new().target_checksum(true),
+        ),
+        ("base-checksum", EncodeOptions::new().base_checksum(true)),
+        ("segmented", EncodeOptions::new().rsyncable(true)),
+        ("target-copies", EncodeOptions::new().target_matches(true)),
+    ];
+    let deltas = variants
+        .into_iter()
+        .map(|(name, options)| (name, encode_with_options(&new, &base, &options).unwrap()))
+        .collect();
+    (base, deltas)
+}
+
+#[test]
+fn test_truncated_deltas_do_not_panic() {
+    let (base, deltas) = seed_deltas();
+    for (name, delta) in &deltas {
+        for len in (0..delta.len()).step_by(MIRI_STRIDE) {
+            check(&format!("{name} truncated to {len}"), &delta[..len], &base);
+        }
+    }
+}
+
+#[test]
+fn test_mutated_deltas_do_not_panic() {
+    let (base, deltas) = seed_deltas();
+    for (name, delta) in &deltas {
+        for pos in (0..delta.len()).step_by(MIRI_STRIDE) {
+            for value in [0x00, 0x01, 0x3F, 0x40, 0x7F, 0x80, 0xBF, 0xFF] {
+                let mut mutated = delta.clone();
+                mutated[pos] = value;
+                check(
+                    &format!("{name} with byte {pos} = {value:#04x}"),
+                    &mutated,
+                    &base,
+                );
+            }
+            for bit in 0..8 {
+                let mut mutated = delta.clone();
+                mutated[pos] ^= 1 << bit;
+                check(
+                    &format!("{name} with bit {bit} of byte {pos} flipped"),
+                    &mutated,
+                    &base,
+                );
+            }
+
+            let mut removed = delta.clone();
+            removed.remove(pos);
+            check(&format!("{name} without byte {pos}"), &removed, &base);
+
+            let mut inserted = delta.clone();
+            inserted.insert(pos, 0xFF);
+            check(
+                &format!("{name} with 0xff inserted at {pos}"),
+                &inserted,
+                &base,
+            );
+        }
+    }
+}
+
+#[test]
+fn test_wrong_base_does_not_panic() {
+    let (base, deltas) = seed_deltas();
+    for (name, delta) in &deltas {
+        check(&format!("{name} against an empty base"), delta, b"");
+        check(
+            &format!("{name} against a truncated base"),
+            delta,
+            &base[..base.len() / 3],
+        );
+    }
+}
+
+#[cfg(feature = "unstable-format")]
+#[test]
+fn test_seed_corpus_does_not_panic() {
+    let base: Vec<u8> = (0..=255).collect();
+    for (name, delta) in gdelta::format::seed_corpus(base.len()) {
+        check(name, &delta, &base);
+        check(&format!("{name} against an empty base"), &delta, b"");
+    }
+}
+
+#[test]
+fn test_corpus_is_rejected() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let compat_base = fs::read(dir.join("compat/base.txt")).unwrap();
+
+    let mut corpus: Vec<_> = fs::read_dir(dir.join("robustness"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "gdelta"))
+        .collect();
+    corpus.sort();
+    assert!(!corpus.is_empty());
+
+    for path in corpus {
+        let delta = fs::read(&path).unwrap();
+        let name = path.file_name().unwrap().to_string_lossy();
+        for base in [&[][..], &compat_base] {
+            assert!(
+                check(&name, &delta, base),
+                "{name} was accepted against a {} byte base",
+                base.len()
+            );
+        }
+    }
+}